@@ -32,6 +32,10 @@ $(exit ${PIPESTATUS[0]}) # this command will exit the shell but only if the abov
 
 /usr/bin/time -f "finished in %e secs" -a -o cargo_test_out.txt ./test-release --color=always 2>/dev/null | tee cargo_test_out.txt
 
+# run the compiler tests again with a small heap, so that most of them collect garbage
+# (the options in MU_VM_OPTS are used by every VM unless a test sets them explicitly)
+MU_VM_OPTS="--gc-immixspace-size=8388608" /usr/bin/time -f "finished in %e secs" -a -o cargo_test_small_heap_out.txt cargo test --release --color=always test_compiler:: 2>/dev/null | tee cargo_test_small_heap_out.txt
+
 cd $MU_ZEBU/tests/test_jit/
 
 if [ -d "./mu-client-pypy" ]
@@ -95,6 +95,8 @@ impl Instruction {
             | CommonInst_Pin(_)
             | CommonInst_Unpin(_)
             | CommonInst_GetAddr(_)
+            | CommonInst_RegisterFinalizer(_)
+            | CommonInst_GetFinalizable
             | CommonInst_Tr64IsFp(_)
             | CommonInst_Tr64IsInt(_)
             | CommonInst_Tr64IsRef(_)
@@ -162,6 +164,8 @@ impl Instruction {
             | CommonInst_Pin(_)
             | CommonInst_Unpin(_)
             | CommonInst_GetAddr(_)
+            | CommonInst_RegisterFinalizer(_)
+            | CommonInst_GetFinalizable
             | PrintHex(_)
             | SetRetval(_)
             | KillStack(_) => true,
@@ -191,6 +195,96 @@ impl Instruction {
         }
     }
 
+    /// may the GC run while this instruction executes (is this a GC safepoint)?
+    /// Allocation, calls, and instructions that switch stacks or block the thread may trigger
+    /// a GC. Yieldpoints (in the prologue and on loop back edges) are safepoints as well,
+    /// but they are not in the IR until InjectRuntime inserts them.
+    pub fn is_gc_safepoint(&self) -> bool {
+        use inst::Instruction_::*;
+
+        match self.v {
+            ExprCall { .. }
+            | ExprCCall { .. }
+            | Call { .. }
+            | CCall { .. }
+            | TailCall(_)
+            | New(_)
+            | NewHybrid(_, _)
+            | NewStack(_)
+            | NewThread { .. }
+            | Watchpoint { .. }
+            | WPBranch { .. }
+            | SwapStackExpr { .. }
+            | SwapStackExc { .. }
+            | SwapStackKill { .. }
+            | ExnInstruction { .. }
+            | CommonInst_GetFinalizable => true,
+            BinOp(_, _, _)
+            | BinOpWithStatus(_, _, _, _)
+            | CmpOp(_, _, _)
+            | ConvOp { .. }
+            | Load { .. }
+            | Store { .. }
+            | CmpXchg { .. }
+            | AtomicRMW { .. }
+            | AllocA(_)
+            | AllocAHybrid(_, _)
+            | NewFrameCursor(_)
+            | Fence(_)
+            | Return(_)
+            | ThreadExit
+            | Throw(_)
+            | Branch1(_)
+            | Branch2 { .. }
+            | Switch { .. }
+            | GetIRef(_)
+            | GetFieldIRef { .. }
+            | GetElementIRef { .. }
+            | ShiftIRef { .. }
+            | GetVarPartIRef { .. }
+            | Select { .. }
+            | CommonInst_GetThreadLocal
+            | CommonInst_SetThreadLocal(_)
+            | CommonInst_Pin(_)
+            | CommonInst_Unpin(_)
+            | CommonInst_GetAddr(_)
+            | CommonInst_RegisterFinalizer(_)
+            | CommonInst_Tr64IsFp(_)
+            | CommonInst_Tr64IsInt(_)
+            | CommonInst_Tr64IsRef(_)
+            | CommonInst_Tr64FromFp(_)
+            | CommonInst_Tr64FromInt(_)
+            | CommonInst_Tr64FromRef(_, _)
+            | CommonInst_Tr64ToFp(_)
+            | CommonInst_Tr64ToInt(_)
+            | CommonInst_Tr64ToRef(_)
+            | CommonInst_Tr64ToTag(_)
+            | Move(_)
+            | PrintHex(_)
+            | SetRetval(_)
+            | GetVMThreadLocal
+            | KillStack(_)
+            | CurrentStack => false,
+        }
+    }
+
+    /// does this instruction compute an internal reference into a heap object (a derived
+    /// pointer)? A derived pointer is only valid while its base reference is live, so it should
+    /// not be used across a GC safepoint that its base reference does not reach.
+    /// (address computations on uptr are not derived pointers)
+    pub fn is_derived_pointer(&self) -> bool {
+        use inst::Instruction_::*;
+
+        match self.v {
+            GetIRef(_) => true,
+            GetFieldIRef { is_ptr, .. }
+            | GetElementIRef { is_ptr, .. }
+            | ShiftIRef { is_ptr, .. }
+            | GetVarPartIRef { is_ptr, .. } => !is_ptr,
+            _ => false,
+        }
+    }
+
     /// can this instruction throw exception?
     /// an instruction with an exceptional branch can throw exception
     pub fn is_potentially_excepting_instruction(&self) -> bool {
@@ -239,6 +333,8 @@ impl Instruction {
             | CommonInst_Pin(_)
             | CommonInst_Unpin(_)
             | CommonInst_GetAddr(_)
+            | CommonInst_RegisterFinalizer(_)
+            | CommonInst_GetFinalizable
             | CommonInst_Tr64IsFp(_)
             | CommonInst_Tr64IsInt(_)
             | CommonInst_Tr64IsRef(_)
@@ -312,6 +408,8 @@ impl Instruction {
             | CommonInst_Pin(_)
             | CommonInst_Unpin(_)
             | CommonInst_GetAddr(_)
+            | CommonInst_RegisterFinalizer(_)
+            | CommonInst_GetFinalizable
             | CommonInst_Tr64IsFp(_)
             | CommonInst_Tr64IsInt(_)
             | CommonInst_Tr64IsRef(_)
@@ -386,6 +484,8 @@ impl Instruction {
             CommonInst_Pin(_) |
             CommonInst_Unpin(_) |
             CommonInst_GetAddr(_) |
+            CommonInst_RegisterFinalizer(_) |
+            CommonInst_GetFinalizable |
             CommonInst_Tr64IsFp(_) |
             CommonInst_Tr64IsInt(_) |
             CommonInst_Tr64IsRef(_) |
@@ -764,6 +864,12 @@ impl Instruction {
                 ops[op].ty(),
                 ops[op]
             ),
+            &Instruction_::CommonInst_RegisterFinalizer(op) => {
+                format!("COMMINST @uvm.gc.register_finalizer({})", ops[op])
+            }
+            &Instruction_::CommonInst_GetFinalizable => {
+                format!("COMMINST @uvm.gc.get_finalizable")
+            }
             // Tagerf64
             &Instruction_::CommonInst_Tr64IsFp(op) => {
                 format!("COMMINST @uvm.tr64.is_fp({})", ops[op])
@@ -1074,6 +1180,10 @@ pub enum Instruction_ {
     CommonInst_Unpin(OpIndex),
     /// common inst: get address of a global cell or a pinned object
     CommonInst_GetAddr(OpIndex),
+    /// common inst: register an object for finalisation
+    CommonInst_RegisterFinalizer(OpIndex),
+    /// common inst: take an object that is queued for finalisation (or null if there is none)
+    CommonInst_GetFinalizable,
 
     /// common inst: is the tagref a floating point?
     CommonInst_Tr64IsFp(OpIndex),
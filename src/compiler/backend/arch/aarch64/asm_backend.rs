@@ -14,6 +14,7 @@
 
 use compiler::backend::aarch64::*;
 use compiler::backend::RegGroup;
+use compiler::backend::RegMove;
 use compiler::backend::AOT_EMIT_CONTEXT_FILE;
 use runtime::mm::*;
 use utils::Address;
@@ -21,7 +22,7 @@ use utils::ByteSize;
 use utils::POINTER_SIZE;
 
 use compiler::backend::{Mem, Reg};
-use compiler::machine_code::MachineCode;
+use compiler::machine_code::{InstClass, MachineCode, SchedInfo};
 use runtime::ValueLocation;
 use vm::VM;
 
@@ -713,6 +714,13 @@ impl MachineCode for ASMCode {
         }
     }
 
+    fn add_pseudo_use(&mut self, inst: usize, reg: MuID) {
+        let asm = &mut self.code[inst];
+        if !asm.uses.contains_key(&reg) {
+            asm.uses.insert(reg, vec![]);
+        }
+    }
+
     fn replace_branch_dest(&mut self, inst: usize, old_succ: usize, new_dest: &str, succ: usize) {
         {
             let asm = &mut self.code[inst];
@@ -809,6 +817,134 @@ impl MachineCode for ASMCode {
         }
     }
 
+    fn get_inst_sched_info(&self, index: usize) -> Option<SchedInfo> {
+        let ref inst = self.code[index];
+
+        // labels, directives, nops and any kind of branches (including calls) stay in place
+        if inst.is_symbol || inst.code.is_empty() {
+            return None;
+        }
+        match inst.branch {
+            ASMBranchTarget::None => {}
+            _ => return None,
+        }
+        // so do pushes/pops of callee saved registers (they may get removed)
+        match inst.spill_info {
+            Some(SpillMemInfo::CalleeSaved) => return None,
+            _ => {}
+        }
+        // SP is not tracked as a register use/define (e.g. frame growing), and the
+        // memory it points to is not tracked as memory either
+        if inst
+            .code
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|op| op == "SP" || op == "WSP")
+        {
+            return None;
+        }
+
+        let mnemonic = inst.code.split(' ').next().unwrap();
+        let class = match mnemonic {
+            // calls and system instructions
+            "BL" | "BLR" | "B" | "BR" | "RET" | "MSR" | "MRS" | "DMB" | "DSB" | "ISB" | "CLREX"
+            | "SEV" | "SEVL" | "WFE" | "WFI" | "YIELD" | "NOP" | "HINT" | "DRPS" | "DCPS1"
+            | "DCPS2" | "DCPS3" | "BRK" | "HLT" | "HVC" | "SMC" | "SVC" => return None,
+            "ADR" | "ADRP" => InstClass::Alu,
+            "MUL" | "MADD" | "MSUB" | "MNEG" | "SMULH" | "UMULH" | "SMULL" | "UMULL" | "SMADDL"
+            | "UMADDL" | "SMSUBL" | "UMSUBL" | "SMNEGL" | "UMNEGL" => InstClass::Mul,
+            "SDIV" | "UDIV" => InstClass::Div,
+            "FMUL" | "FNMUL" | "FMADD" | "FMSUB" | "FNMADD" | "FNMSUB" => InstClass::FpMul,
+            "FDIV" | "FSQRT" => InstClass::FpDiv,
+            _ if inst.is_mem_op_used => {
+                // exclusive and acquire/release accesses order other memory accesses
+                if mnemonic.starts_with("LDX")
+                    || mnemonic.starts_with("LDAX")
+                    || mnemonic.starts_with("LDAR")
+                    || mnemonic.starts_with("STX")
+                    || mnemonic.starts_with("STLX")
+                    || mnemonic.starts_with("STLR")
+                {
+                    return None;
+                } else if mnemonic.starts_with("LD") || mnemonic == "PRFM" {
+                    InstClass::Load
+                } else if mnemonic.starts_with("ST") {
+                    InstClass::Store
+                } else {
+                    return None;
+                }
+            }
+            _ if mnemonic.starts_with("F") || mnemonic == "SCVTF" || mnemonic == "UCVTF" => {
+                InstClass::FpAlu
+            }
+            _ => InstClass::Alu,
+        };
+
+        let reads_flags = match mnemonic {
+            "CSEL" | "CSINC" | "CSINV" | "CSNEG" | "CSET" | "CSETM" | "CINC" | "CINV" | "CNEG"
+            | "FCSEL" | "ADC" | "ADCS" | "SBC" | "SBCS" | "NGC" | "NGCS" | "CCMP" | "CCMN"
+            | "FCCMP" | "FCCMPE" => true,
+            _ => false,
+        };
+        let writes_flags = match mnemonic {
+            "CMP" | "CMN" | "TST" | "FCMP" | "FCMPE" | "CCMP" | "CCMN" | "FCCMP" | "FCCMPE"
+            | "ADDS" | "SUBS" | "ANDS" | "BICS" | "ADCS" | "SBCS" | "NEGS" | "NGCS" => true,
+            _ => false,
+        };
+
+        Some(SchedInfo {
+            class: class,
+            reads_flags: reads_flags,
+            writes_flags: writes_flags,
+        })
+    }
+
+    fn reorder_insts(&mut self, start: usize, order: Vec<usize>) {
+        debug_assert!({
+            let mut sorted = order.clone();
+            sorted.sort();
+            sorted == (start..start + order.len()).collect::<Vec<usize>>()
+        });
+
+        // old ith inst is now the (start + k)th instruction
+        let mut location_map: LinkedHashMap<usize, usize> = LinkedHashMap::new();
+        let insts: Vec<ASMInst> = order.iter().map(|i| self.code[*i].clone()).collect();
+        for (k, mut inst) in insts.into_iter().enumerate() {
+            let line = start + k;
+            location_map.insert(order[k], line);
+
+            // fix defines and uses
+            for locs in inst.defines.values_mut() {
+                for loc in locs {
+                    loc.line = line;
+                }
+            }
+            for locs in inst.uses.values_mut() {
+                for loc in locs {
+                    loc.line = line;
+                }
+            }
+            self.code[line] = inst;
+        }
+
+        // fix patchpoints
+        for patchpoint in self
+            .frame_size_lower_patchpoints
+            .iter_mut()
+            .chain(self.frame_size_upper_patchpoints.iter_mut())
+        {
+            if let Some(line) = location_map.get(&patchpoint.line) {
+                patchpoint.line = *line;
+            }
+        }
+
+        // redo control flow analysis
+        for inst in self.code.iter_mut() {
+            inst.preds.clear();
+            inst.succs.clear();
+        }
+        self.control_flow_analysis();
+    }
+
     fn emit(&self) -> Vec<u8> {
         let mut ret = vec![];
 
@@ -3992,6 +4128,84 @@ pub fn spill_rewrite(
     spilled_scratch_temps
 }
 
+/// inserts moves between the locations of temporaries (used by the linear scan allocator
+/// to connect the parts of split temporaries). The moves at an index are inserted
+/// before/after the instruction in the given order. Stores and loads are emitted as
+/// spilling code, so that they are recognised as spill stores/loads later.
+pub fn insert_moves(
+    moves_before: &LinkedHashMap<usize, Vec<RegMove>>,
+    moves_after: &LinkedHashMap<usize, Vec<RegMove>>,
+    func: &mut MuFunctionVersion,
+    cf: &mut CompiledFunction,
+    vm: &VM,
+) {
+    trace!("insert moves for aarch64 asm backend");
+
+    let mut code_before: LinkedHashMap<usize, Vec<Box<ASMCode>>> = LinkedHashMap::new();
+    let mut code_after: LinkedHashMap<usize, Vec<Box<ASMCode>>> = LinkedHashMap::new();
+    for (i, moves) in moves_before.iter() {
+        trace!("insert {:?} before inst #{}", moves, i);
+        code_before.insert(*i, vec![emit_reg_moves(moves, func, vm)]);
+    }
+    for (i, moves) in moves_after.iter() {
+        trace!("insert {:?} after inst #{}", moves, i);
+        code_after.insert(*i, vec![emit_reg_moves(moves, func, vm)]);
+    }
+
+    // copy and insert the code
+    let new_mc = {
+        let old_mc = cf.mc.take().unwrap();
+        let old_mc_ref: &ASMCode = old_mc.as_any().downcast_ref().unwrap();
+        old_mc_ref.rewrite_insert(code_before, code_after)
+    };
+
+    cf.mc = Some(new_mc);
+
+    trace!("code after inserting moves");
+    cf.mc().trace_mc();
+}
+
+/// emits a sequence of moves between temporaries and spill slots
+/// (addressing a spill slot may need a new temporary for the offset)
+fn emit_reg_moves(moves: &Vec<RegMove>, func: &mut MuFunctionVersion, vm: &VM) -> Box<ASMCode> {
+    let mut codegen = ASMCodeGen::new();
+    codegen.start_code_sequence();
+
+    for mov in moves.iter() {
+        match *mov {
+            RegMove::Copy(ref dest, ref src) => {
+                if RegGroup::get_from_value(dest) == RegGroup::FPR {
+                    codegen.emit_fmov(dest, src);
+                } else {
+                    codegen.emit_mov(dest, src);
+                }
+            }
+            RegMove::Store(ref mem, ref src) => {
+                let mem = emit_mem(
+                    &mut codegen,
+                    mem,
+                    get_type_alignment(&src.ty, vm),
+                    &mut func.context,
+                    vm,
+                );
+                codegen.emit_str_spill(&mem, src);
+            }
+            RegMove::Load(ref dest, ref mem) => {
+                let mem = emit_mem(
+                    &mut codegen,
+                    mem,
+                    get_type_alignment(&dest.ty, vm),
+                    &mut func.context,
+                    vm,
+                );
+                codegen.emit_ldr_spill(dest, &mem);
+            }
+        }
+    }
+
+    codegen.finish_code_sequence_asm()
+}
+
 #[inline(always)]
 // This function is used so that the register allocator will ignore the zero register
 // (some instructions don't use this function as they don't support the zero regester,
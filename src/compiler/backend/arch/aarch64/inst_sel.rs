@@ -1170,6 +1170,8 @@ impl<'a> InstructionSelection {
                         } else {
                             unimplemented!();
                         }
+
+                        self.emit_write_barrier(loc_op, val_op, &resolved_loc, node, f_context, vm);
                     }
 
                     Instruction_::CmpXchg {
@@ -1343,6 +1345,17 @@ impl<'a> InstructionSelection {
                         // this NOT is needed as STXR/STLXR returns sucess as '0',
                         // wheras the Mu spec says it should be 1
                         self.backend.emit_eor_imm(&res_success, &res_success, 1);
+
+                        // the barrier checks the value in the location, so we do not need to
+                        // know whether the swap succeeded
+                        self.emit_write_barrier(
+                            &ops[mem_loc],
+                            &ops[desired_value],
+                            &loc,
+                            node,
+                            f_context,
+                            vm,
+                        );
                     }
                     Instruction_::GetIRef(_)
                     | Instruction_::GetFieldIRef { .. }
@@ -1510,28 +1523,76 @@ impl<'a> InstructionSelection {
 
                     Instruction_::CommonInst_Pin(op) => {
                         trace!("instsel on PIN");
+                        let ref ops = inst.ops;
+                        let ref op = ops[op];
+                        let tmp_res = self.get_result_value(node, 0);
+
                         if !mm::GC_MOVES_OBJECT {
                             // non-moving GC: pin is a nop (move from op to result)
-                            let ref ops = inst.ops;
-                            let ref op = ops[op];
-
-                            let tmp_res = self.get_result_value(node, 0);
-
                             self.emit_move_node_to_value(&tmp_res, op, f_content, f_context, vm);
                         } else {
-                            unimplemented!()
+                            // call pin() in GC
+                            let tmp_op = self.emit_ireg(op, f_content, f_context, vm);
+                            self.emit_runtime_entry(
+                                &entrypoints::PIN_OBJECT,
+                                vec![tmp_op],
+                                Some(vec![tmp_res]),
+                                Some(node),
+                                f_context,
+                                vm,
+                            );
                         }
                     }
 
-                    Instruction_::CommonInst_Unpin(_) => {
+                    Instruction_::CommonInst_Unpin(op) => {
                         trace!("instsel on UNPIN");
                         if !mm::GC_MOVES_OBJECT {
                             // do nothing
                         } else {
-                            unimplemented!()
+                            // call unpin() in GC
+                            let ref ops = inst.ops;
+                            let ref op = ops[op];
+                            let tmp_op = self.emit_ireg(op, f_content, f_context, vm);
+                            self.emit_runtime_entry(
+                                &entrypoints::UNPIN_OBJECT,
+                                vec![tmp_op],
+                                None,
+                                Some(node),
+                                f_context,
+                                vm,
+                            );
                         }
                     }
 
+                    Instruction_::CommonInst_RegisterFinalizer(op) => {
+                        trace!("instsel on REGISTER_FINALIZER");
+                        let ref ops = inst.ops;
+                        let ref op = ops[op];
+                        let tmp_op = self.emit_ireg(op, f_content, f_context, vm);
+                        self.emit_runtime_entry(
+                            &entrypoints::REGISTER_FINALIZER,
+                            vec![tmp_op],
+                            None,
+                            Some(node),
+                            f_context,
+                            vm,
+                        );
+                    }
+
+                    Instruction_::CommonInst_GetFinalizable => {
+                        trace!("instsel on GET_FINALIZABLE");
+                        let tmp_allocator = self.emit_get_allocator(f_context, vm);
+                        let tmp_res = self.get_result_value(node, 0);
+                        self.emit_runtime_entry(
+                            &entrypoints::GET_FINALIZABLE,
+                            vec![tmp_allocator],
+                            Some(vec![tmp_res]),
+                            Some(node),
+                            f_context,
+                            vm,
+                        );
+                    }
+
                     Instruction_::Move(op) => {
                         trace!("instsel on MOVE (internal IR)");
                         let ref ops = inst.ops;
@@ -3648,6 +3709,178 @@ impl<'a> InstructionSelection {
         tmp_res
     }
 
+    /// emits the GC write barrier after a store to the memory location (if the store needs
+    /// one, i.e. it stores a reference to a heap location). The barrier is inlined: we only
+    /// call into the GC if the slot may be in an old object (the slot is in an old line, or not
+    /// in an immix space), and the GC checks if the stored reference is to a young object
+    fn emit_write_barrier(
+        &mut self,
+        loc_op: &TreeNode,
+        val_op: &TreeNode,
+        resolved_loc: &P<Value>,
+        node: &TreeNode,
+        f_context: &mut FunctionContext,
+        vm: &VM,
+    ) {
+        // constants (e.g. NULL) never refer to young objects
+        if vm.vm_options.flag_gc_disable_collection || val_op.is_const_value() {
+            return;
+        }
+
+        // stores through uptr may be to native memory or to pinned heap objects, the GC
+        // only remembers the slots in the heap
+        let (kind, entry) = match loc_op.ty().v {
+            MuType_::IRef(ref ty) => (mm::write_barrier_kind(ty), &*entrypoints::WRITE_BARRIER),
+            MuType_::UPtr(ref ty) => (
+                mm::write_barrier_kind(ty),
+                &*entrypoints::WRITE_BARRIER_UPTR,
+            ),
+            _ => (None, &*entrypoints::WRITE_BARRIER),
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return,
+        };
+
+        let blk_check_normal = make_block_name(&node.name(), "write_barrier_check_normal");
+        let blk_check_line = make_block_name(&node.name(), "write_barrier_check_line");
+        let blk_slow = make_block_name(&node.name(), "write_barrier_slow");
+        let blk_done = make_block_name(&node.name(), "write_barrier_done");
+
+        // ASM: add %tmp_slot, loc
+        let tmp_slot = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        emit_calculate_address(self.backend.as_mut(), &tmp_slot, resolved_loc, vm);
+
+        // a null reference does not need the barrier
+        // (we do not decode tagref64 in the fast path)
+        if kind != mm::WordType::TaggedRef {
+            let blk_check_tiny = make_block_name(&node.name(), "write_barrier_check_tiny");
+
+            // ASM: ldr %tmp_val, [%tmp_slot]
+            // ASM: cbz %tmp_val, %blk_done
+            let tmp_val = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+            emit_load_base_offset(self.backend.as_mut(), &tmp_val, &tmp_slot, 0, f_context, vm);
+            self.backend.emit_cbz(&tmp_val, blk_done.clone());
+            self.finish_block();
+
+            self.start_block(blk_check_tiny);
+        }
+
+        // the immix space that the slot may be in (spaces are aligned to SPACE_ALIGN)
+        // ASM: lsr %tmp_space, %tmp_slot, LOG_BYTES_PREALLOC_SPACE
+        // ASM: lsl %tmp_space, %tmp_space, LOG_BYTES_PREALLOC_SPACE
+        let tmp_space = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        self.backend
+            .emit_lsr_imm(&tmp_space, &tmp_slot, mm::LOG_BYTES_PREALLOC_SPACE as u8);
+        self.backend
+            .emit_lsl_imm(&tmp_space, &tmp_space, mm::LOG_BYTES_PREALLOC_SPACE as u8);
+
+        // ASM: adrp/ldr %tmp_bases, IMMIX_SPACE_BASES
+        let tmp_bases = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        let bases_loc = P(Value {
+            hdr: MuEntityHeader::unnamed(vm.next_id()),
+            ty: ADDRESS_TYPE.clone(),
+            v: Value_::Memory(MemoryLocation::Symbolic {
+                label: Arc::new("IMMIX_SPACE_BASES".to_string()),
+                is_global: true,
+                is_native: true,
+            }),
+        });
+        emit_addr_sym(self.backend.as_mut(), &tmp_bases, &bases_loc, vm);
+
+        // ASM: ldr %tmp_base, [%tmp_bases]
+        // ASM: cmp %tmp_base, %tmp_space
+        // ASM: b.eq %blk_check_line
+        let tmp_base = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        emit_load_base_offset(
+            self.backend.as_mut(),
+            &tmp_base,
+            &tmp_bases,
+            0,
+            f_context,
+            vm,
+        );
+        self.backend.emit_cmp(&tmp_base, &tmp_space);
+        self.backend.emit_b_cond("EQ", blk_check_line.clone());
+        self.finish_block();
+
+        // ASM: ldr %tmp_base, [%tmp_bases, #8]
+        // ASM: cmp %tmp_base, %tmp_space
+        // ASM: b.ne %blk_slow
+        self.start_block(blk_check_normal);
+        emit_load_base_offset(
+            self.backend.as_mut(),
+            &tmp_base,
+            &tmp_bases,
+            POINTER_SIZE as i64,
+            f_context,
+            vm,
+        );
+        self.backend.emit_cmp(&tmp_base, &tmp_space);
+        self.backend.emit_b_cond("NE", blk_slow.clone());
+        self.finish_block();
+
+        // the slot is in an immix space, we only need the barrier if its line may hold
+        // old objects
+        self.start_block(blk_check_line);
+
+        // ASM: lsr %tmp_line, %tmp_slot, LOG_BYTES_IN_LINE
+        // ASM: and %tmp_line, %tmp_line, LINE_INDEX_MASK
+        // ASM: add %tmp_line, %tmp_line, %tmp_space
+        // ASM: add %tmp_mark_loc, %tmp_line, LINE_MARK_DISP
+        let tmp_line = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        self.backend
+            .emit_lsr_imm(&tmp_line, &tmp_slot, mm::immix::LOG_BYTES_IN_LINE as u8);
+        self.backend
+            .emit_and_imm(&tmp_line, &tmp_line, mm::LINE_INDEX_MASK as u64);
+        self.backend.emit_add(&tmp_line, &tmp_line, &tmp_space);
+        let tmp_mark_loc = make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        emit_add_u64(
+            self.backend.as_mut(),
+            &tmp_mark_loc,
+            &tmp_line,
+            mm::LINE_MARK_DISP as u64,
+        );
+
+        // ASM: ldrb %tmp_mark, [%tmp_mark_loc]
+        // ASM: tbz %tmp_mark, LINE_MARK_OLD_BIT, %blk_done
+        let tmp_mark = make_temporary(f_context, UINT8_TYPE.clone(), vm);
+        emit_load_base_offset(
+            self.backend.as_mut(),
+            &tmp_mark,
+            &tmp_mark_loc,
+            0,
+            f_context,
+            vm,
+        );
+        self.backend.emit_tbz(
+            &tmp_mark,
+            mm::LINE_MARK_OLD_BIT.trailing_zeros() as u8,
+            blk_done.clone(),
+        );
+        self.finish_block();
+
+        // ASM: bl muentry_write_barrier(%allocator, %tmp_slot, kind)
+        // (or muentry_write_barrier_uptr for a store through uptr)
+        self.start_block(blk_slow);
+        let tmp_allocator = self.emit_get_allocator(f_context, vm);
+        self.emit_runtime_entry(
+            entry,
+            vec![
+                tmp_allocator,
+                tmp_slot,
+                make_value_int_const(kind as u64, vm),
+            ],
+            None,
+            Some(node),
+            f_context,
+            vm,
+        );
+        self.finish_block();
+
+        self.start_block(blk_done);
+    }
+
     fn emit_get_allocator(&mut self, f_context: &mut FunctionContext, vm: &VM) -> P<Value> {
         // ASM: %tl = get_thread_local()
         let tmp_tl = self.emit_get_threadlocal(f_context, vm);
@@ -3706,7 +3939,26 @@ impl<'a> InstructionSelection {
             }
         };
 
-        self.emit_c_call_internal(entry_name, sig, args, rets, cur_node, f_context, vm)
+        if entry.may_trigger_gc && !vm.vm_options.flag_gc_disable_collection {
+            // call the entry through muentry_gc_safecall, so that the GC can find a frame cursor
+            // for this frame and scan the stack precisely
+            let safecall_name = match entrypoints::GC_SAFECALL.aot {
+                ValueLocation::Relocatable(_, ref name) => name.clone(),
+                _ => panic!("expecting a relocatable value"),
+            };
+            self.emit_c_call_internal(
+                safecall_name,
+                sig,
+                args,
+                rets,
+                Some(entry_name),
+                cur_node,
+                f_context,
+                vm,
+            )
+        } else {
+            self.emit_c_call_internal(entry_name, sig, args, rets, None, cur_node, f_context, vm)
+        }
     }
 
     // Note: if tys has more than 1 element, then this will return a new struct type
@@ -4261,19 +4513,22 @@ impl<'a> InstructionSelection {
     // if ret is Some, return values will put stored in given temporaries
     // otherwise create temporaries
     // always returns result temporaries (given or created)
+    // if safecall_target is given, func_name is a safecall function, and the target is passed
+    // in X9 (the safecall function will call the target with the arguments)
     fn emit_c_call_internal(
         &mut self,
         func_name: CName,
         sig: P<CFuncSig>,
         args: Vec<P<Value>>,
         rets: Option<Vec<P<Value>>>,
+        safecall_target: Option<CName>,
         cur_node: Option<&TreeNode>,
         f_context: &mut FunctionContext,
         vm: &VM,
     ) -> Vec<P<Value>> {
         let return_type = self.combine_return_types(&sig, vm);
         let return_size = self.compute_return_allocation(&return_type, &vm);
-        let (stack_arg_size, arg_regs) = self.emit_precall_convention(
+        let (stack_arg_size, mut arg_regs) = self.emit_precall_convention(
             RegisterCallConvention::Normal,
             StackCallConvention::Push(SP.clone()),
             false,
@@ -4284,9 +4539,44 @@ impl<'a> InstructionSelection {
             vm,
         );
 
+        // put the target address in X9 for safecall
+        // (not X16/X17, as a linker veneer for the call may use them)
+        let is_safecall = safecall_target.is_some();
+        if let Some(target) = safecall_target {
+            // the safecall function does not forward arguments on stack
+            assert!(stack_arg_size == 0);
+
+            let target_loc = P(Value {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                ty: ADDRESS_TYPE.clone(),
+                v: Value_::Memory(MemoryLocation::Symbolic {
+                    label: target,
+                    is_global: true,
+                    is_native: true,
+                }),
+            });
+            emit_addr_sym(self.backend.as_mut(), &X9, &target_loc, vm);
+            arg_regs.push(X9.clone());
+        }
+
         // make call
         if vm.is_doing_jit() {
             unimplemented!()
+        } else if is_safecall {
+            // the GC and the unwinder walk through this frame, so we record the callsite
+            let callsite = self.new_callsite_label(cur_node);
+            let callsite = self
+                .backend
+                .emit_bl(
+                    Some(callsite),
+                    func_name,
+                    None,
+                    arg_regs,
+                    CALLER_SAVED_REGS.to_vec(),
+                    true,
+                )
+                .unwrap();
+            self.record_callsite(None, callsite, stack_arg_size);
         } else {
             // assume ccall wont throw exception
             self.backend.emit_bl(
@@ -4380,12 +4670,34 @@ impl<'a> InstructionSelection {
 
                     match pv.v {
                         Value_::Constant(Constant::Int(_)) => unimplemented!(),
+                        Value_::Constant(Constant::ExternSym(ref func_name))
+                            if entrypoints::is_gc_safepoint(func_name)
+                                && !vm.vm_options.flag_gc_disable_collection =>
+                        {
+                            // runtime code injected by the compiler (allocation slowpath,
+                            // yieldpoint) - call through muentry_gc_safecall
+                            let safecall_name = match entrypoints::GC_SAFECALL.aot {
+                                ValueLocation::Relocatable(_, ref name) => name.clone(),
+                                _ => panic!("expecting a relocatable value"),
+                            };
+                            self.emit_c_call_internal(
+                                safecall_name,
+                                sig,
+                                arg_values,
+                                rets,
+                                Some(func_name.clone()),
+                                Some(cur_node),
+                                f_context,
+                                vm,
+                            );
+                        }
                         Value_::Constant(Constant::ExternSym(ref func_name)) => {
                             self.emit_c_call_internal(
                                 func_name.clone(), //func_name: CName,
                                 sig,               // sig: P<CFuncSig>,
                                 arg_values,        // args: Vec<P<Value>>,
                                 rets,              // Option<Vec<P<Value>>>,
+                                None,              // safecall_target: Option<CName>,
                                 Some(cur_node),    // Option<&TreeNode>,
                                 f_context,         // &mut FunctionContext,
                                 vm,
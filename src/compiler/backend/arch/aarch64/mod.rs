@@ -36,6 +36,8 @@ pub use compiler::backend::aarch64::asm_backend::emit_context_with_reloc;
 pub use compiler::backend::aarch64::asm_backend::ASMCodeGen;
 use utils::Address;
 
+#[cfg(feature = "aot")]
+pub use compiler::backend::aarch64::asm_backend::insert_moves;
 #[cfg(feature = "aot")]
 pub use compiler::backend::aarch64::asm_backend::spill_rewrite;
 
@@ -55,6 +57,10 @@ use utils::LinkedHashMap;
 pub const CALLEE_SAVED_COUNT: usize = 18;
 pub const ARGUMENT_REG_COUNT: usize = 16;
 
+/// we are not able to collect garbage in generated code yet: stack maps and
+/// muentry_gc_safecall are not tested on aarch64, so collection is disabled
+pub const SUPPORTS_GC_COLLECTION: bool = false;
+
 macro_rules! REGISTER {
     ($id:expr, $name: expr, $ty: ident) => {{
         P(Value {
@@ -946,6 +952,7 @@ pub fn estimate_insts_for_ir(inst: &Instruction) -> usize {
         SwapStackExpr { .. } | SwapStackExc { .. } | SwapStackKill { .. } => 10,
         CommonInst_GetThreadLocal | CommonInst_SetThreadLocal(_) => 10,
         CommonInst_Pin(_) | CommonInst_Unpin(_) => 10,
+        CommonInst_RegisterFinalizer(_) | CommonInst_GetFinalizable => 10,
 
         // others
         Move(_) => 0,
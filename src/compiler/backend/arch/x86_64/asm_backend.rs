@@ -14,10 +14,12 @@
 
 #![allow(unused_variables)]
 
+use compiler::backend::peephole_opt::RegLiveness;
 use compiler::backend::x86_64;
 use compiler::backend::x86_64::check_op_len;
 use compiler::backend::x86_64::CodeGenerator;
 use compiler::backend::RegGroup;
+use compiler::backend::RegMove;
 use compiler::backend::AOT_EMIT_CONTEXT_FILE;
 use compiler::backend::{Mem, Reg};
 use compiler::machine_code::MachineCode;
@@ -629,6 +631,468 @@ impl ASMCode {
     }
 }
 
+// target specific peephole patterns (after register allocation)
+impl ASMCode {
+    /// splits an instruction into its mnemonic and its operands, returns the mnemonic, and
+    /// (column, text) of each operand (operands are separated by commas that are not in
+    /// a memory operand)
+    fn split_inst(&self, index: usize) -> (String, Vec<(usize, String)>) {
+        let ref code = self.code[index].code;
+        let mnemonic_end = match code.find(' ') {
+            Some(i) => i,
+            None => return (code.clone(), vec![]),
+        };
+
+        let bytes = code.as_bytes();
+        let mut ranges = vec![];
+        let mut depth = 0;
+        let mut start = mnemonic_end + 1;
+        for i in start..bytes.len() {
+            match bytes[i] {
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                b',' if depth == 0 => {
+                    ranges.push((start, i));
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        ranges.push((start, bytes.len()));
+
+        let operands: Vec<(usize, String)> = ranges
+            .into_iter()
+            .map(|(start, end)| {
+                let text = &code[start..end];
+                let column = start + (text.len() - text.trim_left().len());
+                (column, text.trim().to_string())
+            })
+            .collect();
+        (code[..mnemonic_end].to_string(), operands)
+    }
+
+    /// finds the location of the temporary/register that appears at the given column of
+    /// an instruction, returns (ID, location)
+    fn get_reg_at(&self, index: usize, column: usize) -> Option<(MuID, ASMLocation)> {
+        let ref inst = self.code[index];
+        for (id, locs) in inst.defines.iter().chain(inst.uses.iter()) {
+            for loc in locs.iter() {
+                if loc.index == column {
+                    return Some((*id, loc.clone()));
+                }
+            }
+        }
+        None
+    }
+
+    /// returns the next instruction in the same block (skipping nops), or None if the
+    /// instruction is the last one in its block
+    fn get_next_inst_in_block(&self, index: usize) -> Option<usize> {
+        let block_end = match self.get_block_for_inst(index) {
+            Some(block) => self.blocks.get(&block).unwrap().end_inst,
+            None => return None,
+        };
+        for i in (index + 1)..block_end {
+            if !self.code[i].is_symbol && !self.is_nop(i) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// returns the mnemonics of the instructions that read the flags after the given
+    /// instruction (before the flags are written again)
+    fn get_flags_readers_after(&self, index: usize) -> Vec<String> {
+        let mut ret = vec![];
+        let block_end = match self.get_block_for_inst(index) {
+            Some(block) => self.blocks.get(&block).unwrap().end_inst,
+            None => return ret,
+        };
+
+        for i in (index + 1)..block_end {
+            if self.code[i].is_symbol || self.is_nop(i) {
+                continue;
+            }
+            let (mnemonic, _) = self.split_inst(i);
+            if is_flags_reader(&mnemonic) {
+                ret.push(mnemonic);
+            } else if is_flags_writer(&mnemonic) {
+                return ret;
+            }
+        }
+        // the flags are never live across blocks in the code we generate
+        ret
+    }
+
+    /// folds a load into the next instruction that uses the loaded register, such as
+    /// ..
+    ///   movq MEM,%r1
+    ///   addq %r1,%r2     (%r1 is dead after this)
+    /// ..
+    /// as addq MEM,%r2
+    fn fold_load_into_op(&mut self, index: usize, liveness: &RegLiveness) -> bool {
+        let next = match self.get_next_inst_in_block(index) {
+            Some(next) => next,
+            None => return false,
+        };
+        match self.code[index].spill_info {
+            Some(SpillMemInfo::CalleeSaved) => return false,
+            _ => {}
+        }
+
+        let (mov, mov_ops) = self.split_inst(index);
+        let (op, op_ops) = self.split_inst(next);
+        if !is_int_inst(&mov, &["mov"])
+            || !is_int_inst(&op, &["add", "sub", "and", "or", "xor", "cmp", "imul"])
+            || mov.chars().last() != op.chars().last()
+            || mov_ops.len() != 2
+            || op_ops.len() != 2
+            || !is_mem_operand(&mov_ops[0].1)
+            || !is_reg_operand(&mov_ops[1].1)
+            || !is_reg_operand(&op_ops[0].1)
+            || !is_reg_operand(&op_ops[1].1)
+        {
+            return false;
+        }
+
+        let (loaded, src, (dest, dest_loc)) = match (
+            self.get_reg_at(index, mov_ops[1].0),
+            self.get_reg_at(next, op_ops[0].0),
+            self.get_reg_at(next, op_ops[1].0),
+        ) {
+            (Some((loaded, _)), Some((src, _)), Some(dest)) => (loaded, src, dest),
+            _ => return false,
+        };
+        if !liveness.is_same_reg(loaded, src)
+            || liveness.is_same_reg(loaded, dest)
+            || !liveness.is_dead_after(loaded, next)
+        {
+            return false;
+        }
+
+        // op MEM,dest
+        let (mem_column, ref mem) = mov_ops[0];
+        let ref dest_text = op_ops[1].1;
+        let new_mem_column = op.len() + 1;
+        let dest_column = new_mem_column + mem.len() + 1;
+
+        let mut uses: LinkedHashMap<MuID, Vec<ASMLocation>> = LinkedHashMap::new();
+        for (id, locs) in self.code[index].uses.iter() {
+            for loc in locs.iter() {
+                let column = loc.index - mem_column + new_mem_column;
+                add_location(
+                    &mut uses,
+                    *id,
+                    ASMLocation::new(next, column, loc.len, loc.oplen),
+                );
+            }
+        }
+        let dest_loc = ASMLocation::new(next, dest_column, dest_text.len(), dest_loc.oplen);
+        add_location(&mut uses, dest, dest_loc.clone());
+        let defines = if self.code[next].defines.is_empty() {
+            LinkedHashMap::new()
+        } else {
+            linked_hashmap! { dest => vec![dest_loc] }
+        };
+
+        {
+            let ref mut inst = self.code[next];
+            inst.code = format!("{} {},{}", op, mem, dest_text);
+            inst.uses = uses;
+            inst.defines = defines;
+            inst.is_mem_op_used = true;
+        }
+        self.set_inst_nop(index);
+        true
+    }
+
+    /// rewrites a shift and an add as lea (which does not write the flags), such as
+    /// ..
+    ///   shlq $2,%r1
+    ///   addq %r1,%r2     (%r1 and the flags are dead after this)
+    /// ..
+    /// as leaq (%r2,%r1,4),%r2
+    fn rewrite_shift_add_as_lea(&mut self, index: usize, liveness: &RegLiveness) -> bool {
+        let next = match self.get_next_inst_in_block(index) {
+            Some(next) => next,
+            None => return false,
+        };
+
+        let (shl, shl_ops) = self.split_inst(index);
+        let (add, add_ops) = self.split_inst(next);
+        if shl != "shlq"
+            || add != "addq"
+            || shl_ops.len() != 2
+            || add_ops.len() != 2
+            || !is_reg_operand(&shl_ops[1].1)
+            || !is_reg_operand(&add_ops[0].1)
+            || !is_reg_operand(&add_ops[1].1)
+        {
+            return false;
+        }
+        let scale = match shl_ops[0].1.trim_left_matches('$').parse::<usize>() {
+            Ok(shift) if shift >= 1 && shift <= 3 => 1 << shift,
+            _ => return false,
+        };
+
+        let (shifted, (index_reg, index_loc), (base, base_loc)) = match (
+            self.get_reg_at(index, shl_ops[1].0),
+            self.get_reg_at(next, add_ops[0].0),
+            self.get_reg_at(next, add_ops[1].0),
+        ) {
+            (Some((shifted, _)), Some(index_reg), Some(base)) => (shifted, index_reg, base),
+            _ => return false,
+        };
+        if !liveness.is_same_reg(shifted, index_reg)
+            || liveness.is_same_reg(shifted, base)
+            || !liveness.is_dead_after(shifted, next)
+            || !self.get_flags_readers_after(next).is_empty()
+        {
+            return false;
+        }
+
+        // leaq (base,index,scale),base
+        let ref base_text = add_ops[1].1;
+        let ref index_text = add_ops[0].1;
+        let base_column = "leaq (".len();
+        let index_column = base_column + base_text.len() + 1;
+        let dest_column = index_column + index_text.len() + 1 + scale.to_string().len() + 2;
+
+        let mut uses: LinkedHashMap<MuID, Vec<ASMLocation>> = LinkedHashMap::new();
+        add_location(
+            &mut uses,
+            base,
+            ASMLocation::new(next, base_column, base_text.len(), base_loc.oplen),
+        );
+        add_location(
+            &mut uses,
+            index_reg,
+            ASMLocation::new(next, index_column, index_text.len(), index_loc.oplen),
+        );
+        let dest_loc = ASMLocation::new(next, dest_column, base_text.len(), base_loc.oplen);
+
+        {
+            let ref mut inst = self.code[next];
+            inst.code = format!(
+                "leaq ({},{},{}),{}",
+                base_text, index_text, scale, base_text
+            );
+            inst.uses = uses;
+            inst.defines = linked_hashmap! { base => vec![dest_loc] };
+            inst.is_mem_op_used = true;
+        }
+        self.set_inst_nop(index);
+        true
+    }
+
+    /// removes a test/cmp against zero of the result of an instruction that already sets
+    /// the flags for its result, such as
+    /// ..
+    ///   andq %r1,%r2
+    ///   testq %r2,%r2
+    /// ..
+    /// and/or/xor set the flags in the same way as the test. add/sub set the carry and
+    /// overflow flags differently, so we only remove the test if they are not read.
+    fn remove_redundant_test(&mut self, index: usize, liveness: &RegLiveness) -> bool {
+        let next = match self.get_next_inst_in_block(index) {
+            Some(next) => next,
+            None => return false,
+        };
+
+        let (op, op_ops) = self.split_inst(index);
+        let (test, test_ops) = self.split_inst(next);
+        let is_logical = is_int_inst(&op, &["and", "or", "xor"]);
+        if !(is_logical || is_int_inst(&op, &["add", "sub"]))
+            || op_ops.len() != 2
+            || test_ops.len() != 2
+            || !is_reg_operand(&op_ops[1].1)
+            || op.chars().last() != test.chars().last()
+        {
+            return false;
+        }
+
+        let result = match self.get_reg_at(index, op_ops[1].0) {
+            Some((result, _)) => result,
+            None => return false,
+        };
+        let is_test_against_zero = {
+            let is_same_as_result = |column: usize| match self.get_reg_at(next, column) {
+                Some((reg, _)) => liveness.is_same_reg(reg, result),
+                None => false,
+            };
+            if is_int_inst(&test, &["test"]) {
+                is_reg_operand(&test_ops[0].1)
+                    && is_reg_operand(&test_ops[1].1)
+                    && is_same_as_result(test_ops[0].0)
+                    && is_same_as_result(test_ops[1].0)
+            } else if is_int_inst(&test, &["cmp"]) {
+                test_ops[0].1 == "$0"
+                    && is_reg_operand(&test_ops[1].1)
+                    && is_same_as_result(test_ops[1].0)
+            } else {
+                false
+            }
+        };
+        if !is_test_against_zero {
+            return false;
+        }
+
+        if !is_logical
+            && !self
+                .get_flags_readers_after(next)
+                .iter()
+                .all(|mnemonic| reads_only_zf_sf(mnemonic))
+        {
+            return false;
+        }
+
+        self.set_inst_nop(next);
+        true
+    }
+
+    /// strength-reduces a multiplication by 3, 5 or 9 as lea, such as
+    /// ..
+    ///   movq $5,%r2
+    ///   movq %r1,%rax
+    ///   mulq %r2         (%r2, %rdx and the flags are dead after this)
+    /// ..
+    /// as movq %r1,%rax; leaq (%rax,%rax,4),%rax
+    fn strength_reduce_mul(&mut self, index: usize, liveness: &RegLiveness) -> bool {
+        let (mov_op1, mul) = match self.get_next_inst_in_block(index) {
+            Some(mov_op1) => match self.get_next_inst_in_block(mov_op1) {
+                Some(mul) => (mov_op1, mul),
+                None => return false,
+            },
+            None => return false,
+        };
+
+        let (mov_imm, mov_imm_ops) = self.split_inst(index);
+        let (mov, mov_ops) = self.split_inst(mov_op1);
+        let (mul_inst, mul_ops) = self.split_inst(mul);
+        if mov_imm != "movq"
+            || mov != "movq"
+            || mul_inst != "mulq"
+            || mov_imm_ops.len() != 2
+            || mov_ops.len() != 2
+            || mul_ops.len() != 1
+            || !is_reg_operand(&mov_imm_ops[1].1)
+            || !is_reg_operand(&mov_ops[0].1)
+            || !is_reg_operand(&mov_ops[1].1)
+            || !is_reg_operand(&mul_ops[0].1)
+        {
+            return false;
+        }
+        let scale = match mov_imm_ops[0].1.trim_left_matches('$').parse::<i64>() {
+            Ok(c) if c == 3 || c == 5 || c == 9 => c - 1,
+            _ => return false,
+        };
+
+        let rax = x86_64::RAX.id();
+        let rdx = x86_64::RDX.id();
+        let (constant, op1, rax_dest, multiplier) = match (
+            self.get_reg_at(index, mov_imm_ops[1].0),
+            self.get_reg_at(mov_op1, mov_ops[0].0),
+            self.get_reg_at(mov_op1, mov_ops[1].0),
+            self.get_reg_at(mul, mul_ops[0].0),
+        ) {
+            (Some((c, _)), Some((op1, _)), Some((dest, _)), Some((m, _))) => (c, op1, dest, m),
+            _ => return false,
+        };
+        if !liveness.is_same_reg(rax_dest, rax)
+            || !liveness.is_same_reg(constant, multiplier)
+            || liveness.is_same_reg(constant, op1)
+            || liveness.is_same_reg(constant, rax)
+            || !liveness.is_dead_after(constant, mul)
+            || !liveness.is_dead_after(rdx, mul)
+            || !self.get_flags_readers_after(mul).is_empty()
+        {
+            return false;
+        }
+
+        // leaq (%rax,%rax,scale),%rax
+        let code = format!("leaq (%rax,%rax,{}),%rax", scale);
+        let dest_column = code.len() - "%rax".len();
+        {
+            let ref mut inst = self.code[mul];
+            inst.code = code;
+            inst.uses = linked_hashmap! {
+                rax => vec![
+                    ASMLocation::new(mul, 6, 4, 64),
+                    ASMLocation::new(mul, 11, 4, 64)
+                ]
+            };
+            inst.defines = linked_hashmap! {
+                rax => vec![ASMLocation::new(mul, dest_column, 4, 64)]
+            };
+            inst.is_mem_op_used = true;
+        }
+        self.set_inst_nop(index);
+        true
+    }
+}
+
+/// does the mnemonic name an integer instruction in the list (with an operand size
+/// postfix)? e.g. "addq" is "add"
+fn is_int_inst(mnemonic: &str, insts: &[&str]) -> bool {
+    match mnemonic.chars().last() {
+        Some('b') | Some('w') | Some('l') | Some('q') => {
+            insts.contains(&&mnemonic[..mnemonic.len() - 1])
+        }
+        _ => false,
+    }
+}
+
+/// does the instruction read the flags?
+fn is_flags_reader(mnemonic: &str) -> bool {
+    (mnemonic.starts_with('j') && mnemonic != "jmp")
+        || mnemonic.starts_with("set")
+        || mnemonic.starts_with("cmov")
+        || is_int_inst(mnemonic, &["adc", "sbb"])
+}
+
+/// does the instruction write all the flags?
+fn is_flags_writer(mnemonic: &str) -> bool {
+    is_int_inst(
+        mnemonic,
+        &[
+            "add", "sub", "and", "or", "xor", "cmp", "test", "neg", "imul", "mul",
+        ],
+    ) || ["call", "ucomisd", "ucomiss", "comisd", "comiss"].contains(&mnemonic)
+}
+
+/// the branches/sets that only read the zero and sign flags
+const ZF_SF_READERS: [&str; 12] = [
+    "je", "jne", "jz", "jnz", "js", "jns", "sete", "setne", "setz", "setnz", "sets", "setns",
+];
+
+/// does the instruction only read the zero and sign flags?
+fn reads_only_zf_sf(mnemonic: &str) -> bool {
+    ZF_SF_READERS.contains(&mnemonic)
+        || is_int_inst(
+            mnemonic,
+            &["cmove", "cmovne", "cmovz", "cmovnz", "cmovs", "cmovns"],
+        )
+}
+
+/// is the operand (after register allocation) a register?
+fn is_reg_operand(operand: &str) -> bool {
+    operand.starts_with('%')
+}
+
+/// is the operand a memory operand?
+fn is_mem_operand(operand: &str) -> bool {
+    !operand.is_empty() && !operand.starts_with('%') && !operand.starts_with('$')
+}
+
+/// adds a location of a temporary/register to a map of uses/defines
+fn add_location(map: &mut LinkedHashMap<MuID, Vec<ASMLocation>>, id: MuID, loc: ASMLocation) {
+    if map.contains_key(&id) {
+        map.get_mut(&id).unwrap().push(loc);
+    } else {
+        map.insert(id, vec![loc]);
+    }
+}
+
 impl MachineCode for ASMCode {
     fn as_any(&self) -> &Any {
         self
@@ -827,6 +1291,14 @@ impl MachineCode for ASMCode {
         }
     }
 
+    /// adds a use of a temp to the inst (without a location in the code)
+    fn add_pseudo_use(&mut self, inst: usize, reg: MuID) {
+        let asm = &mut self.code[inst];
+        if !asm.uses.contains_key(&reg) {
+            asm.uses.insert(reg, vec![]);
+        }
+    }
+
     /// replace destination for a jump instruction
     fn replace_branch_dest(&mut self, inst: usize, old_succ: usize, new_dest: &str, succ: MuID) {
         {
@@ -1046,6 +1518,28 @@ impl MachineCode for ASMCode {
     fn get_last_inst(&self, index: usize) -> Option<usize> {
         ASMCode::find_last_inst(index, &self.code)
     }
+
+    /// tries the target specific peephole patterns at the given index, returns the name of
+    /// the pattern that rewrote the code
+    fn try_peephole_patterns(
+        &mut self,
+        index: usize,
+        liveness: &RegLiveness,
+    ) -> Option<&'static str> {
+        if self.code[index].is_symbol {
+            None
+        } else if self.fold_load_into_op(index, liveness) {
+            Some("fold load into memory operand")
+        } else if self.rewrite_shift_add_as_lea(index, liveness) {
+            Some("rewrite shift and add as lea")
+        } else if self.remove_redundant_test(index, liveness) {
+            Some("remove redundant test")
+        } else if self.strength_reduce_mul(index, liveness) {
+            Some("strength reduce multiplication")
+        } else {
+            None
+        }
+    }
 }
 
 impl ASMInst {
@@ -4428,3 +4922,74 @@ pub fn spill_rewrite(
 
     spilled_scratch_temps
 }
+
+/// inserts moves between the locations of temporaries (used by the linear scan allocator
+/// to connect the parts of split temporaries). The moves at an index are inserted
+/// before/after the instruction in the given order. Stores and loads are emitted as
+/// spilling code, so that they are recognised as spill stores/loads later.
+pub fn insert_moves(
+    moves_before: &LinkedHashMap<usize, Vec<RegMove>>,
+    moves_after: &LinkedHashMap<usize, Vec<RegMove>>,
+    _func: &mut MuFunctionVersion,
+    cf: &mut CompiledFunction,
+    _vm: &VM,
+) {
+    trace!("insert moves for x86_64 asm backend");
+
+    let mut code_before: LinkedHashMap<usize, Vec<Box<ASMCode>>> = LinkedHashMap::new();
+    let mut code_after: LinkedHashMap<usize, Vec<Box<ASMCode>>> = LinkedHashMap::new();
+    for (i, moves) in moves_before.iter() {
+        trace!("insert {:?} before inst #{}", moves, i);
+        code_before.insert(*i, vec![emit_reg_moves(moves)]);
+    }
+    for (i, moves) in moves_after.iter() {
+        trace!("insert {:?} after inst #{}", moves, i);
+        code_after.insert(*i, vec![emit_reg_moves(moves)]);
+    }
+
+    // copy and insert the code
+    let new_mc = {
+        let old_mc = cf.mc.take().unwrap();
+        let old_mc_ref: &ASMCode = old_mc.as_any().downcast_ref().unwrap();
+        old_mc_ref.rewrite_insert(code_before, code_after)
+    };
+
+    cf.mc = Some(new_mc);
+
+    trace!("code after inserting moves");
+    cf.mc().trace_mc();
+}
+
+/// emits a sequence of moves between temporaries and spill slots
+fn emit_reg_moves(moves: &Vec<RegMove>) -> Box<ASMCode> {
+    let mut codegen = ASMCodeGen::new();
+    codegen.start_code_sequence();
+
+    for mov in moves.iter() {
+        match *mov {
+            RegMove::Copy(ref dest, ref src) => match RegGroup::get_from_value(dest) {
+                RegGroup::GPR => codegen.emit_mov_r_r(dest, src),
+                RegGroup::FPR => {
+                    if dest.ty.is_float() {
+                        codegen.emit_movss_f32_f32(dest, src)
+                    } else {
+                        codegen.emit_movsd_f64_f64(dest, src)
+                    }
+                }
+                RegGroup::GPREX => panic!("expected moving a reg or freg, found {}", dest.ty),
+            },
+            RegMove::Store(ref mem, ref src) => match RegGroup::get_from_value(src) {
+                RegGroup::GPR => codegen.emit_spill_store_gpr(mem, src),
+                RegGroup::FPR => codegen.emit_spill_store_fpr(mem, src),
+                RegGroup::GPREX => panic!("expected spilling a reg or freg, found {}", src.ty),
+            },
+            RegMove::Load(ref dest, ref mem) => match RegGroup::get_from_value(dest) {
+                RegGroup::GPR => codegen.emit_spill_load_gpr(dest, mem),
+                RegGroup::FPR => codegen.emit_spill_load_fpr(dest, mem),
+                RegGroup::GPREX => panic!("expected spilling a reg or freg, found {}", dest.ty),
+            },
+        }
+    }
+
+    codegen.finish_code_sequence_asm()
+}
@@ -1504,6 +1504,16 @@ impl<'a> InstructionSelection {
                         if need_fence {
                             self.backend.emit_mfence();
                         }
+
+                        self.emit_write_barrier(
+                            loc_op,
+                            val_op,
+                            &resolved_loc,
+                            node,
+                            f_content,
+                            f_context,
+                            vm,
+                        );
                     }
 
                     Instruction_::Fence(order) => {
@@ -1667,6 +1677,39 @@ impl<'a> InstructionSelection {
 
                         self.emit_move_value_to_value(&tmp_res, &tmp_op);
                     }
+                    Instruction_::CommonInst_RegisterFinalizer(op) => {
+                        trace!("instsel on REGISTER_FINALIZER");
+
+                        let ref op = inst.ops[op];
+                        assert!(self.match_ireg(op));
+                        let tmp_op = self.emit_ireg(op, f_content, f_context, vm);
+
+                        self.emit_runtime_entry(
+                            &entrypoints::REGISTER_FINALIZER,
+                            vec![tmp_op],
+                            None,
+                            Some(node),
+                            f_content,
+                            f_context,
+                            vm,
+                        );
+                    }
+                    Instruction_::CommonInst_GetFinalizable => {
+                        trace!("instsel on GET_FINALIZABLE");
+
+                        let tmp_allocator = self.emit_get_allocator(node, f_content, f_context, vm);
+                        let tmp_res = self.get_result_value(node);
+
+                        self.emit_runtime_entry(
+                            &entrypoints::GET_FINALIZABLE,
+                            vec![tmp_allocator],
+                            Some(vec![tmp_res]),
+                            Some(node),
+                            f_content,
+                            f_context,
+                            vm,
+                        );
+                    }
 
                     Instruction_::Move(op) => {
                         trace!("instsel on MOVE (internal IR)");
@@ -1771,27 +1814,49 @@ impl<'a> InstructionSelection {
                         }
                     }
 
-                    /*Instruction_::AllocA(ref ty) => {
-                        trace!("instsel on AllocA");
-
-                        if cfg!(debug_assertions) {
-                            match ty.v {
-                                MuType_::Hybrid(_) => panic!("cannot use ALLOCA for hybrid,\
-                                                             use ALLOCAHYBRID instead"),
-                                _ => {}
-                            }
-                        }
+                    Instruction_::AllocA(ref ty) => {
+                        trace!("instsel on ALLOCA: {}", ty.print_details());
+                        assert!(!ty.is_hybrid());
 
                         let ty_info = vm.get_backend_type_info(ty.id());
-                        let size = ty_info.size;
-                        let ty_align= ty_info.alignment;
-                        if 16 % ty_align != 0 {
-                            // It's not trivial to allign this type...
+                        if 16 % ty_info.alignment != 0 {
+                            // the stack pointer is only kept 16 bytes aligned
                             unimplemented!()
                         }
-                        // Round size up to the nearest multiple of 16
-                        let size = ((size + 16 - 1)/16)*16;
-                    }*/
+                        // keep the stack pointer 16 bytes aligned
+                        let size = math::align_up(ty_info.size, 16);
+
+                        let tmp_res = self.get_result_value(node);
+                        if size != 0 {
+                            self.backend.emit_sub_r_imm(&x86_64::RSP, size as i32);
+                        }
+                        self.backend.emit_mov_r_r(&tmp_res, &x86_64::RSP);
+
+                        // zero the slot
+                        if size <= 64 {
+                            for offset in (0..size).filter(|x| x % 8 == 0) {
+                                let mem = self.make_memory_op_base_offset(
+                                    &tmp_res,
+                                    offset as i32,
+                                    UINT64_TYPE.clone(),
+                                    vm,
+                                );
+                                self.backend.emit_mov_mem_imm(&mem, 0, 64);
+                            }
+                        } else {
+                            let tmp_size = self.make_int64_const(size as u64, vm);
+                            self.emit_runtime_entry(
+                                &entrypoints::MEM_ZERO,
+                                vec![tmp_res.clone(), tmp_size],
+                                None,
+                                Some(node),
+                                f_content,
+                                f_context,
+                                vm,
+                            );
+                        }
+                    }
+
                     Instruction_::Throw(op_index) => {
                         trace!("instsel on THROW");
 
@@ -3561,6 +3626,159 @@ impl<'a> InstructionSelection {
         tmp_allocator
     }
 
+    /// emits the GC write barrier after a store to the memory location (if the store needs
+    /// one, i.e. it stores a reference to a heap location). The barrier is inlined: we only
+    /// call into the GC if the slot may be in an old object (the slot is in an old line, or not
+    /// in an immix space), and the GC checks if the stored reference is to a young object
+    fn emit_write_barrier(
+        &mut self,
+        loc_op: &TreeNode,
+        val_op: &TreeNode,
+        resolved_loc: &P<Value>,
+        node: &TreeNode,
+        f_content: &FunctionContent,
+        f_context: &mut FunctionContext,
+        vm: &VM,
+    ) {
+        // constants (e.g. NULL) never refer to young objects
+        if vm.vm_options.flag_gc_disable_collection || val_op.is_const_value() {
+            return;
+        }
+
+        // stores through uptr may be to native memory or to pinned heap objects, the GC
+        // only remembers the slots in the heap
+        let (kind, entry) = match loc_op.ty().v {
+            MuType_::IRef(ref ty) => (mm::write_barrier_kind(ty), &*entrypoints::WRITE_BARRIER),
+            MuType_::UPtr(ref ty) => (
+                mm::write_barrier_kind(ty),
+                &*entrypoints::WRITE_BARRIER_UPTR,
+            ),
+            _ => (None, &*entrypoints::WRITE_BARRIER),
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return,
+        };
+
+        let blk_check_normal = make_block_name(&node.name(), "write_barrier_check_normal");
+        let blk_check_line = make_block_name(&node.name(), "write_barrier_check_line");
+        let blk_slow = make_block_name(&node.name(), "write_barrier_slow");
+        let blk_done = make_block_name(&node.name(), "write_barrier_done");
+
+        // ASM: lea [loc] -> %tmp_slot
+        let tmp_slot = self.make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        self.backend.emit_lea_r64(&tmp_slot, resolved_loc);
+
+        // a null reference does not need the barrier
+        // (we do not decode tagref64 in the fast path)
+        if kind != mm::WordType::TaggedRef {
+            let blk_check_tiny = make_block_name(&node.name(), "write_barrier_check_tiny");
+
+            // ASM: mov [%tmp_slot] -> %tmp_val
+            let tmp_val = self.make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+            let mem_val = self.make_memory_op_base_offset(&tmp_slot, 0, ADDRESS_TYPE.clone(), vm);
+            self.backend.emit_mov_r_mem(&tmp_val, &mem_val);
+
+            // ASM: test %tmp_val, %tmp_val
+            // ASM: je %blk_done
+            self.backend.emit_test_r_r(&tmp_val, &tmp_val);
+            self.backend.emit_je(blk_done.clone());
+            self.finish_block();
+
+            self.start_block(blk_check_tiny);
+        }
+
+        // the immix space that the slot may be in (spaces are aligned to SPACE_ALIGN)
+        // ASM: mov %tmp_slot -> %tmp_space
+        // ASM: shr %tmp_space, LOG_BYTES_PREALLOC_SPACE
+        // ASM: shl %tmp_space, LOG_BYTES_PREALLOC_SPACE
+        let tmp_space = self.make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        self.backend.emit_mov_r_r(&tmp_space, &tmp_slot);
+        self.backend
+            .emit_shr_r_imm8(&tmp_space, mm::LOG_BYTES_PREALLOC_SPACE as i8);
+        self.backend
+            .emit_shl_r_imm8(&tmp_space, mm::LOG_BYTES_PREALLOC_SPACE as i8);
+
+        // ASM: lea IMMIX_SPACE_BASES -> %tmp_bases
+        let tmp_bases = self.make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        let mem_bases = self.make_memory_symbolic(
+            Arc::new("IMMIX_SPACE_BASES".to_string()),
+            ADDRESS_TYPE.clone(),
+            true,
+            true,
+            f_context,
+            vm,
+        );
+        self.backend.emit_lea_r64(&tmp_bases, &mem_bases);
+
+        // ASM: cmp [%tmp_bases], %tmp_space
+        // ASM: je %blk_check_line
+        let mem_tiny = self.make_memory_op_base_offset(&tmp_bases, 0, ADDRESS_TYPE.clone(), vm);
+        self.backend.emit_cmp_mem_r(&mem_tiny, &tmp_space);
+        self.backend.emit_je(blk_check_line.clone());
+        self.finish_block();
+
+        // ASM: cmp [%tmp_bases + 8], %tmp_space
+        // ASM: jne %blk_slow
+        self.start_block(blk_check_normal);
+        let mem_normal = self.make_memory_op_base_offset(
+            &tmp_bases,
+            POINTER_SIZE as i32,
+            ADDRESS_TYPE.clone(),
+            vm,
+        );
+        self.backend.emit_cmp_mem_r(&mem_normal, &tmp_space);
+        self.backend.emit_jne(blk_slow.clone());
+        self.finish_block();
+
+        // the slot is in an immix space, we only need the barrier if its line may hold
+        // old objects
+        self.start_block(blk_check_line);
+
+        // ASM: mov %tmp_slot -> %tmp_line
+        // ASM: shr %tmp_line, LOG_BYTES_IN_LINE
+        // ASM: and %tmp_line, LINE_INDEX_MASK
+        // ASM: add %tmp_line, LINE_MARK_DISP
+        let tmp_line = self.make_temporary(f_context, ADDRESS_TYPE.clone(), vm);
+        self.backend.emit_mov_r_r(&tmp_line, &tmp_slot);
+        self.backend
+            .emit_shr_r_imm8(&tmp_line, mm::immix::LOG_BYTES_IN_LINE as i8);
+        self.backend
+            .emit_and_r_imm(&tmp_line, mm::LINE_INDEX_MASK as i32);
+        self.backend
+            .emit_add_r_imm(&tmp_line, mm::LINE_MARK_DISP as i32);
+
+        // ASM: mov [%tmp_space + %tmp_line] -> %tmp_mark
+        // ASM: test LINE_MARK_OLD_BIT, %tmp_mark
+        // ASM: je %blk_done
+        let tmp_mark = self.make_temporary(f_context, UINT8_TYPE.clone(), vm);
+        let mem_mark =
+            self.make_memory_op_base_index(&tmp_space, &tmp_line, 1, UINT8_TYPE.clone(), vm);
+        self.backend.emit_mov_r_mem(&tmp_mark, &mem_mark);
+        self.backend
+            .emit_test_imm_r(mm::LINE_MARK_OLD_BIT as i32, &tmp_mark);
+        self.backend.emit_je(blk_done.clone());
+        self.finish_block();
+
+        // ASM: call muentry_write_barrier(%allocator, %tmp_slot, kind)
+        // (or muentry_write_barrier_uptr for a store through uptr)
+        self.start_block(blk_slow);
+        let tmp_allocator = self.emit_get_allocator(node, f_content, f_context, vm);
+        let const_kind = self.make_int64_const(kind as u64, vm);
+        self.emit_runtime_entry(
+            entry,
+            vec![tmp_allocator, tmp_slot, const_kind],
+            None,
+            Some(node),
+            f_content,
+            f_context,
+            vm,
+        );
+        self.finish_block();
+
+        self.start_block(blk_done);
+    }
+
     /// emits code for large object allocation
     fn emit_alloc_sequence_large(
         &mut self,
@@ -3810,9 +4028,29 @@ impl<'a> InstructionSelection {
             }
         };
 
-        self.emit_c_call_internal(
-            entry_name, sig, args, rets, cur_node, f_content, f_context, vm,
-        )
+        if entry.may_trigger_gc && !vm.vm_options.flag_gc_disable_collection {
+            // call the entry through muentry_gc_safecall, so that the GC can find a frame cursor
+            // for this frame and scan the stack precisely
+            let safecall_name = match entrypoints::GC_SAFECALL.aot {
+                ValueLocation::Relocatable(_, ref name) => name.clone(),
+                _ => panic!("expecting a relocatable value"),
+            };
+            self.emit_c_call_internal(
+                safecall_name,
+                sig,
+                args,
+                rets,
+                Some(entry_name),
+                cur_node,
+                f_content,
+                f_context,
+                vm,
+            )
+        } else {
+            self.emit_c_call_internal(
+                entry_name, sig, args, rets, None, cur_node, f_content, f_context, vm,
+            )
+        }
     }
 
     /// emits calling convention before a call instruction
@@ -4125,6 +4363,8 @@ impl<'a> InstructionSelection {
     /// emits a native call
     /// Note that rets is Option<Vec<P<Value>>. If rets is Some, return values will be put
     /// in the given temporaries. Otherwise create temporaries for return results
+    /// If safecall_target is given, func_name is a safecall function, and the target is passed
+    /// in R11 (the safecall function will call the target with the arguments)
     #[allow(unused_variables)] // f_content is not used, but we keep it in case
     fn emit_c_call_internal(
         &mut self,
@@ -4132,14 +4372,38 @@ impl<'a> InstructionSelection {
         sig: P<CFuncSig>,
         args: Vec<P<Value>>,
         rets: Option<Vec<P<Value>>>,
+        safecall_target: Option<CName>,
         cur_node: Option<&TreeNode>,
         f_content: &FunctionContent,
         f_context: &mut FunctionContext,
         vm: &VM,
     ) -> Vec<P<Value>> {
-        let (stack_arg_size, args) =
+        let (stack_arg_size, mut args) =
             self.emit_precall_convention(&sig, &args, C_CALL_CONVENTION, f_context, vm);
 
+        // put the target address in R11 for safecall
+        if let Some(target) = safecall_target {
+            // the safecall function does not forward arguments on stack
+            assert!(stack_arg_size == 0);
+
+            let target_loc = self.make_memory_from_location(
+                MemoryLocation::Symbolic {
+                    base: Some(x86_64::RIP.clone()),
+                    label: target,
+                    is_global: true,
+                    is_native: true,
+                },
+                vm,
+            );
+            if cfg!(target_os = "macos") {
+                self.backend.emit_lea_r64(&x86_64::R11, &target_loc);
+            } else {
+                // for linux, this loads the address from GOT
+                self.backend.emit_mov_r_mem(&x86_64::R11, &target_loc);
+            }
+            args.push(x86_64::R11.clone());
+        }
+
         // make call
         if vm.is_doing_jit() {
             unimplemented!()
@@ -4225,12 +4489,35 @@ impl<'a> InstructionSelection {
 
                     match pv.v {
                         Value_::Constant(Constant::Int(_)) => unimplemented!(),
+                        Value_::Constant(Constant::ExternSym(ref func_name))
+                            if entrypoints::is_gc_safepoint(func_name)
+                                && !vm.vm_options.flag_gc_disable_collection =>
+                        {
+                            // runtime code injected by the compiler (allocation slowpath,
+                            // yieldpoint) - call through muentry_gc_safecall
+                            let safecall_name = match entrypoints::GC_SAFECALL.aot {
+                                ValueLocation::Relocatable(_, ref name) => name.clone(),
+                                _ => panic!("expecting a relocatable value"),
+                            };
+                            self.emit_c_call_internal(
+                                safecall_name,
+                                sig,
+                                args,
+                                rets,
+                                Some(func_name.clone()),
+                                Some(cur_node),
+                                f_content,
+                                f_context,
+                                vm,
+                            );
+                        }
                         Value_::Constant(Constant::ExternSym(ref func_name)) => {
                             self.emit_c_call_internal(
                                 func_name.clone(), //func_name: CName,
                                 sig,               // sig: P<CFuncSig>,
                                 args,              // args: Vec<P<Value>>,
                                 rets,              // Option<Vec<P<Value>>>,
+                                None,              // safecall_target: Option<CName>,
                                 Some(cur_node),    // Option<&TreeNode>,
                                 f_content,         // &FunctionContent,
                                 f_context,         // &mut FunctionContext,
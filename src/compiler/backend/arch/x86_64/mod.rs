@@ -37,6 +37,8 @@ pub use compiler::backend::x86_64::asm_backend::emit_context;
 #[cfg(feature = "aot")]
 pub use compiler::backend::x86_64::asm_backend::emit_context_with_reloc;
 #[cfg(feature = "aot")]
+pub use compiler::backend::x86_64::asm_backend::insert_moves;
+#[cfg(feature = "aot")]
 pub use compiler::backend::x86_64::asm_backend::spill_rewrite;
 
 use ast::ir::*;
@@ -54,6 +56,10 @@ use utils::LinkedHashMap;
 // number of normal callee saved registers (excluding RSP and RBP)
 pub const CALLEE_SAVED_COUNT: usize = 5;
 
+/// we are able to collect garbage in generated code (with yieldpoints, stack maps and
+/// muentry_gc_safecall)
+pub const SUPPORTS_GC_COLLECTION: bool = true;
+
 /// a macro to declare a set of general purpose registers that are aliased to the first one
 macro_rules! GPR_ALIAS {
     ($alias: ident: ($id64: expr, $r64: ident) ->
@@ -693,6 +699,7 @@ pub fn estimate_insts_for_ir(inst: &Instruction) -> usize {
         SwapStackExpr { .. } | SwapStackExc { .. } | SwapStackKill { .. } => 10,
         CommonInst_GetThreadLocal | CommonInst_SetThreadLocal(_) => 10,
         CommonInst_Pin(_) | CommonInst_Unpin(_) | CommonInst_GetAddr(_) => 10,
+        CommonInst_RegisterFinalizer(_) | CommonInst_GetFinalizable => 10,
 
         // others
         Move(_) => 0,
@@ -0,0 +1,395 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use compiler::backend;
+use compiler::machine_code::*;
+use compiler::CompilerPass;
+use utils::LinkedHashMap;
+use vm::VM;
+
+use std::any::Any;
+use std::usize;
+
+/// a pseudo register ID for the condition flags
+const FLAGS: MuID = usize::MAX;
+
+/// where the scheduling pass is in the pass list
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SchedPosition {
+    /// schedule temporaries (more freedom, but may increase register pressure)
+    PreRegAlloc,
+    /// schedule machine registers (reusing registers creates extra dependencies)
+    PostRegAlloc,
+}
+
+/// latencies (in cycles) of each instruction class for a core, and how many
+/// instructions the core issues per cycle
+pub struct LatencyTable {
+    pub issue_width: usize,
+    pub alu: usize,
+    pub mul: usize,
+    pub div: usize,
+    pub load: usize,
+    pub store: usize,
+    pub fp_alu: usize,
+    pub fp_mul: usize,
+    pub fp_div: usize,
+}
+
+impl LatencyTable {
+    /// gets the latency table for a core (as named by --inst-sched-core)
+    pub fn for_core(core: &str) -> LatencyTable {
+        match core {
+            // in-order, dual issue
+            "cortex-a53" => LatencyTable {
+                issue_width: 2,
+                alu: 1,
+                mul: 3,
+                div: 12,
+                load: 3,
+                store: 1,
+                fp_alu: 4,
+                fp_mul: 4,
+                fp_div: 15,
+            },
+            // in-order, dual issue
+            "cortex-a55" => LatencyTable {
+                issue_width: 2,
+                alu: 1,
+                mul: 3,
+                div: 12,
+                load: 4,
+                store: 1,
+                fp_alu: 4,
+                fp_mul: 4,
+                fp_div: 13,
+            },
+            // out-of-order, 3-wide decode
+            "cortex-a57" => LatencyTable {
+                issue_width: 3,
+                alu: 1,
+                mul: 3,
+                div: 20,
+                load: 4,
+                store: 1,
+                fp_alu: 5,
+                fp_mul: 5,
+                fp_div: 17,
+            },
+            _ => panic!("unknown core for instruction scheduling: {}", core),
+        }
+    }
+
+    /// gets the latency of an instruction class
+    pub fn latency(&self, class: InstClass) -> usize {
+        match class {
+            InstClass::Alu => self.alu,
+            InstClass::Mul => self.mul,
+            InstClass::Div => self.div,
+            InstClass::Load => self.load,
+            InstClass::Store => self.store,
+            InstClass::FpAlu => self.fp_alu,
+            InstClass::FpMul => self.fp_mul,
+            InstClass::FpDiv => self.fp_div,
+        }
+    }
+}
+
+/// A list scheduler for machine code. It reorders instructions within regions of a
+/// basic block (regions are separated by instructions that cannot be reordered, such as
+/// labels, branches and calls). Dependencies come from register uses/defines, the
+/// condition flags and memory ordering (loads may pass loads, other memory accesses keep
+/// their order). Instructions on the longest latency path are issued first.
+/// This pass is controlled by --inst-sched. The compiler only runs it for aarch64 (the only
+/// backend that allows reordering its instructions at the moment).
+pub struct InstructionScheduling {
+    name: &'static str,
+    position: SchedPosition,
+}
+
+impl InstructionScheduling {
+    pub fn new(position: SchedPosition) -> InstructionScheduling {
+        InstructionScheduling {
+            name: match position {
+                SchedPosition::PreRegAlloc => "Instruction Scheduling (before regalloc)",
+                SchedPosition::PostRegAlloc => "Instruction Scheduling (after regalloc)",
+            },
+            position: position,
+        }
+    }
+
+    /// should we schedule at this position with the VM options?
+    fn is_enabled(&self, vm: &VM) -> bool {
+        match vm.vm_options.flag_inst_sched.as_str() {
+            "none" => false,
+            "pre-regalloc" => self.position == SchedPosition::PreRegAlloc,
+            "post-regalloc" => self.position == SchedPosition::PostRegAlloc,
+            "both" => true,
+            other => panic!("unknown option for --inst-sched: {}", other),
+        }
+    }
+}
+
+impl CompilerPass for InstructionScheduling {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        if !self.is_enabled(vm) {
+            return;
+        }
+
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let mut cf = compiled_funcs.get(&func.id()).unwrap().write().unwrap();
+        let latencies = LatencyTable::for_core(&vm.vm_options.flag_inst_sched_core);
+
+        let blocks = cf.mc().get_all_blocks();
+        for block in blocks.iter() {
+            let range = match cf.mc().get_block_range(block) {
+                Some(range) => range,
+                None => continue,
+            };
+
+            // find regions of instructions that can be reordered
+            let mut regions = vec![];
+            let mut region_start = range.start;
+            for i in range.clone() {
+                if cf.mc().get_inst_sched_info(i).is_none() {
+                    if i - region_start > 1 {
+                        regions.push(region_start..i);
+                    }
+                    region_start = i + 1;
+                }
+            }
+            if range.end > region_start + 1 {
+                regions.push(region_start..range.end);
+            }
+
+            for region in regions {
+                let order = schedule_region(&cf, region.start, region.end, &latencies);
+                if order
+                    .iter()
+                    .enumerate()
+                    .any(|(k, i)| *i != region.start + k)
+                {
+                    trace!("block {}: reorder {:?} as {:?}", block, region, order);
+                    cf.mc_mut().reorder_insts(region.start, order);
+                }
+            }
+        }
+
+        trace!("after instruction scheduling:");
+        cf.mc().trace_mc();
+    }
+}
+
+/// a node in the dependency graph of a region
+struct SchedNode {
+    latency: usize,
+    /// (successor, latency of the edge)
+    succs: Vec<(usize, usize)>,
+    n_preds: usize,
+    /// length of the longest latency path from this node to the end of the region
+    height: usize,
+}
+
+/// adds a dependency (or raises the latency of an existing one)
+fn add_edge(nodes: &mut Vec<SchedNode>, from: usize, to: usize, latency: usize) {
+    if from == to {
+        return;
+    }
+    for edge in nodes[from].succs.iter_mut() {
+        if edge.0 == to {
+            if latency > edge.1 {
+                edge.1 = latency;
+            }
+            return;
+        }
+    }
+    nodes[from].succs.push((to, latency));
+    nodes[to].n_preds += 1;
+}
+
+/// schedules the instructions in [start, end), returns the new order of the instructions
+fn schedule_region(
+    cf: &CompiledFunction,
+    start: usize,
+    end: usize,
+    latencies: &LatencyTable,
+) -> Vec<usize> {
+    let mc = cf.mc();
+    let n = end - start;
+
+    // registers are temporaries before register allocation, and (the colors of) machine
+    // registers after it
+    let reg = |id: MuID| -> MuID {
+        let id = match cf.temps.get(&id) {
+            Some(machine_reg) => *machine_reg,
+            None => id,
+        };
+        if id < MACHINE_ID_END {
+            backend::get_color_for_precolored(id)
+        } else {
+            id
+        }
+    };
+
+    let mut nodes: Vec<SchedNode> = vec![];
+    let mut infos = vec![];
+    for i in start..end {
+        let info = mc.get_inst_sched_info(i).unwrap();
+        nodes.push(SchedNode {
+            latency: latencies.latency(info.class),
+            succs: vec![],
+            n_preds: 0,
+            height: 0,
+        });
+        infos.push(info);
+    }
+
+    // build dependencies
+    {
+        // the last define, and the uses since the last define, of each register
+        let mut last_def: LinkedHashMap<MuID, usize> = LinkedHashMap::new();
+        let mut uses_since_def: LinkedHashMap<MuID, Vec<usize>> = LinkedHashMap::new();
+        // the last store, and the loads since the last store
+        let mut last_store: Option<usize> = None;
+        let mut loads_since_store: Vec<usize> = vec![];
+
+        for k in 0..n {
+            let i = start + k;
+            let mut uses: Vec<MuID> = mc.get_inst_reg_uses(i).into_iter().map(&reg).collect();
+            let mut defines: Vec<MuID> = mc.get_inst_reg_defines(i).into_iter().map(&reg).collect();
+            if infos[k].reads_flags {
+                uses.push(FLAGS);
+            }
+            if infos[k].writes_flags {
+                defines.push(FLAGS);
+            }
+
+            // read after write
+            for r in uses.iter() {
+                if let Some(&def) = last_def.get(r) {
+                    let latency = nodes[def].latency;
+                    add_edge(&mut nodes, def, k, latency);
+                }
+            }
+            // write after read, write after write
+            for r in defines.iter() {
+                if let Some(readers) = uses_since_def.get(r) {
+                    for &reader in readers.iter() {
+                        add_edge(&mut nodes, reader, k, 0);
+                    }
+                }
+                if let Some(&def) = last_def.get(r) {
+                    add_edge(&mut nodes, def, k, 1);
+                }
+            }
+
+            // memory ordering
+            match infos[k].class {
+                InstClass::Load => {
+                    if let Some(store) = last_store {
+                        add_edge(&mut nodes, store, k, latencies.store);
+                    }
+                    loads_since_store.push(k);
+                }
+                InstClass::Store => {
+                    for &load in loads_since_store.iter() {
+                        add_edge(&mut nodes, load, k, 0);
+                    }
+                    if let Some(store) = last_store {
+                        add_edge(&mut nodes, store, k, 0);
+                    }
+                    last_store = Some(k);
+                    loads_since_store.clear();
+                }
+                _ => {}
+            }
+
+            for r in uses {
+                if !uses_since_def.contains_key(&r) {
+                    uses_since_def.insert(r, vec![]);
+                }
+                uses_since_def.get_mut(&r).unwrap().push(k);
+            }
+            for r in defines {
+                last_def.insert(r, k);
+                uses_since_def.remove(&r);
+            }
+        }
+    }
+
+    // priority: the longest latency path to the end of the region
+    // (edges always go forward, so we compute it backwards)
+    for k in (0..n).rev() {
+        let mut height = nodes[k].latency;
+        for &(succ, latency) in nodes[k].succs.iter() {
+            let path = latency + nodes[succ].height;
+            if path > height {
+                height = path;
+            }
+        }
+        nodes[k].height = height;
+    }
+
+    // list scheduling: issue the ready instructions with the highest priority
+    // (instructions from the original order first if they have the same priority)
+    let mut order = vec![];
+    let mut earliest: Vec<usize> = vec![0; n];
+    let mut n_preds: Vec<usize> = nodes.iter().map(|node| node.n_preds).collect();
+    let mut scheduled: Vec<bool> = vec![false; n];
+    let mut cycle = 0;
+    while order.len() < n {
+        let mut issued = 0;
+        while issued < latencies.issue_width {
+            let mut candidate: Option<usize> = None;
+            for k in 0..n {
+                if !scheduled[k] && n_preds[k] == 0 && earliest[k] <= cycle {
+                    let is_better = match candidate {
+                        Some(c) => nodes[k].height > nodes[c].height,
+                        None => true,
+                    };
+                    if is_better {
+                        candidate = Some(k);
+                    }
+                }
+            }
+
+            match candidate {
+                Some(k) => {
+                    scheduled[k] = true;
+                    order.push(start + k);
+                    issued += 1;
+                    for &(succ, latency) in nodes[k].succs.iter() {
+                        n_preds[succ] -= 1;
+                        if cycle + latency > earliest[succ] {
+                            earliest[succ] = cycle + latency;
+                        }
+                    }
+                }
+                None => break,
+            }
+        }
+        cycle += 1;
+    }
+
+    order
+}
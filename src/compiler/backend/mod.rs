@@ -16,6 +16,8 @@
 pub mod code_emission;
 /// A instruction selection pass. Uses simple tree pattern matching.
 pub mod inst_sel;
+/// A list instruction scheduling pass, before and/or after register allocation.
+pub mod inst_sched;
 /// A Dominator Tree pass for machine code.
 pub mod mc_loopanalysis;
 /// A peephole optimization pass after register allocation.
@@ -87,6 +89,10 @@ pub use compiler::backend::x86_64::get_return_address;
 /// initializes machine registers in the function context
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::init_machine_regs_for_func;
+/// inserts the moves between the locations of temporaries that are split by the register
+/// allocator
+#[cfg(target_arch = "x86_64")]
+pub use compiler::backend::x86_64::insert_moves;
 /// checks if two machine registers are alias (the same register)
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::is_aliased;
@@ -119,6 +125,9 @@ pub use compiler::backend::x86_64::ARGUMENT_GPRS;
 /// number of callee saved registers
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::CALLEE_SAVED_COUNT;
+/// can we collect garbage in generated code on this architecture?
+#[cfg(target_arch = "x86_64")]
+pub use compiler::backend::x86_64::SUPPORTS_GC_COLLECTION;
 
 /// --- aarch64 backend ---
 #[cfg(target_arch = "aarch64")]
@@ -160,6 +169,10 @@ pub use compiler::backend::aarch64::get_return_address;
 /// initializes machine registers in the function context
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::init_machine_regs_for_func;
+/// inserts the moves between the locations of temporaries that are split by the register
+/// allocator
+#[cfg(target_arch = "aarch64")]
+pub use compiler::backend::aarch64::insert_moves;
 /// checks if two machine registers are alias (the same register)
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::is_aliased;
@@ -189,6 +202,8 @@ pub use compiler::backend::aarch64::ARGUMENT_FPRS;
 pub use compiler::backend::aarch64::ARGUMENT_GPRS;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::CALLEE_SAVED_COUNT;
+#[cfg(target_arch = "aarch64")]
+pub use compiler::backend::aarch64::SUPPORTS_GC_COLLECTION;
 
 use ast::ir::*;
 use ast::ptr::*;
@@ -700,6 +715,18 @@ impl RegGroup {
     }
 }
 
+/// RegMove describes a move that the register allocator inserts between the locations of
+/// a temporary that lives in different places (registers or its spill slot) over its lifetime
+#[derive(Clone, Debug)]
+pub enum RegMove {
+    /// copies a temporary to another temporary (dest, src)
+    Copy(P<Value>, P<Value>),
+    /// stores a temporary to a spill slot (mem, src)
+    Store(P<Value>, P<Value>),
+    /// loads a temporary from a spill slot (dest, mem)
+    Load(P<Value>, P<Value>),
+}
+
 fn make_block_name(inst: &MuName, label: &str) -> MuName {
     Arc::new(format!("{}:{}", inst, label))
 }
@@ -16,12 +16,25 @@ use ast::ir::*;
 use compiler::backend;
 use compiler::machine_code::CompiledFunction;
 use compiler::CompilerPass;
+use utils::LinkedHashSet;
 use vm::VM;
 
 use std::any::Any;
+use std::collections::HashMap;
 
+/// A peephole pattern looks at an instruction (and the instructions around it in the same
+/// block), and rewrites the code if they match. It returns true if the code is rewritten.
+/// Target specific patterns are implemented by the machine code
+/// (see MachineCode::try_peephole_patterns())
+type PeepholePattern = fn(&mut CompiledFunction, &RegLiveness, usize) -> bool;
+
+/// A peephole optimization pass after register allocation. It first rewrites instructions
+/// with the target independent patterns and the target specific patterns, then removes
+/// unnecessary jumps.
 pub struct PeepholeOptimization {
     name: &'static str,
+    /// target independent patterns (name, pattern), in the order that we try them
+    patterns: Vec<(&'static str, PeepholePattern)>,
 }
 
 impl CompilerPass for PeepholeOptimization {
@@ -37,14 +50,26 @@ impl CompilerPass for PeepholeOptimization {
         let compiled_funcs = vm.compiled_funcs().read().unwrap();
         let mut cf = compiled_funcs.get(&func.id()).unwrap().write().unwrap();
 
-        // remove redundant move first
+        // rewrite instructions with patterns first (this includes removing redundant moves)
+        let mut liveness = RegLiveness::new(&cf);
         for i in 0..cf.mc().number_of_insts() {
             cf.mc().trace_inst(i);
 
-            // if two sides of a move instruction are the same,
-            // it is redundant, and can be eliminated
-            trace!("trying to remove redundant move");
-            self.remove_redundant_move(i, &mut cf);
+            // a rewrite may expose another pattern at the same instruction, so we keep
+            // trying until no pattern applies
+            loop {
+                if cf.mc().is_nop(i) {
+                    break;
+                }
+                match self.try_patterns(i, &mut cf, &liveness) {
+                    Some(pattern) => {
+                        info!("inst {} is rewritten by pattern: {}", i, pattern);
+                        // registers may be live in different places after the rewrite
+                        liveness = RegLiveness::new(&cf);
+                    }
+                    None => break,
+                }
+            }
         }
 
         // then remove jumps (because removing movs will affect this)
@@ -79,51 +104,38 @@ impl PeepholeOptimization {
     pub fn new() -> PeepholeOptimization {
         PeepholeOptimization {
             name: "Peephole Optimization",
+            patterns: vec![
+                (
+                    "remove redundant move",
+                    remove_redundant_move as PeepholePattern,
+                ),
+                (
+                    "fold spill store and reload",
+                    fold_spill_store_reload as PeepholePattern,
+                ),
+                (
+                    "fold spill reload and store",
+                    fold_spill_reload_store as PeepholePattern,
+                ),
+            ],
         }
     }
 
-    fn remove_redundant_move(&mut self, inst: usize, cf: &mut CompiledFunction) {
-        // if this instruction is a move, and move from register to register (no memory operands)
-        if cf.mc().is_move(inst) && !cf.mc().is_using_mem_op(inst) {
-            // get source reg/temp ID
-            let src: MuID = {
-                let uses = cf.mc().get_inst_reg_uses(inst);
-                if uses.len() == 0 {
-                    // moving immediate to register, its not redundant
-                    return;
-                }
-                uses[0]
-            };
-
-            // get dest reg/temp ID
-            let dst: MuID = cf.mc().get_inst_reg_defines(inst)[0];
-
-            // turning temp into machine reg
-            let src_machine_reg: MuID = {
-                match cf.temps.get(&src) {
-                    Some(reg) => *reg,
-                    None => src,
-                }
-            };
-            let dst_machine_reg: MuID = {
-                match cf.temps.get(&dst) {
-                    Some(reg) => *reg,
-                    None => dst,
-                }
-            };
-
-            // check if two registers are aliased
-            if backend::is_aliased(src_machine_reg, dst_machine_reg) {
-                info!(
-                    "move between {} and {} is redundant! removed",
-                    src_machine_reg, dst_machine_reg
-                );
-                // redundant, remove this move
-                cf.mc_mut().set_inst_nop(inst);
-            } else {
-
+    /// tries the target independent patterns, then the target specific patterns at the
+    /// instruction, returns the name of the pattern that rewrote the code
+    fn try_patterns(
+        &self,
+        inst: usize,
+        cf: &mut CompiledFunction,
+        liveness: &RegLiveness,
+    ) -> Option<&'static str> {
+        for &(name, pattern) in self.patterns.iter() {
+            trace!("trying pattern: {}", name);
+            if pattern(cf, liveness, inst) {
+                return Some(name);
             }
         }
+        cf.mc_mut().try_peephole_patterns(inst, liveness)
     }
 
     fn remove_unnecessary_jump(&mut self, inst: usize, cf: &mut CompiledFunction) {
@@ -238,3 +250,251 @@ impl PeepholeOptimization {
         }
     }
 }
+
+/// RegLiveness tells which machine registers (by their colors) are live after each
+/// instruction. It is computed from the block live-outs after register allocation
+/// (nops are ignored, as patterns remove instructions by setting them as nops).
+pub struct RegLiveness {
+    /// a map between temporaries and their assigned machine registers
+    temps: HashMap<MuID, MuID>,
+    /// live registers after each instruction (None if the instruction is not in a block)
+    live_after: Vec<Option<LinkedHashSet<MuID>>>,
+}
+
+impl RegLiveness {
+    pub fn new(cf: &CompiledFunction) -> RegLiveness {
+        let mut ret = RegLiveness {
+            temps: cf.temps.clone(),
+            live_after: vec![None; cf.mc().number_of_insts()],
+        };
+
+        let mc = cf.mc();
+        for block in mc.get_all_blocks() {
+            let range = match mc.get_block_range(&block) {
+                Some(range) => range,
+                None => continue,
+            };
+
+            let mut live: LinkedHashSet<MuID> = LinkedHashSet::new();
+            match mc.get_ir_block_liveout(&block) {
+                Some(liveout) => {
+                    for reg in liveout.iter() {
+                        live.insert(ret.color(*reg));
+                    }
+                }
+                // we do not know what is live in this block
+                None => continue,
+            }
+
+            for i in range.rev() {
+                ret.live_after[i] = Some(live.clone());
+                if mc.is_nop(i) {
+                    continue;
+                }
+                for reg in mc.get_inst_reg_defines(i) {
+                    live.remove(&ret.color(reg));
+                }
+                for reg in mc.get_inst_reg_uses(i) {
+                    live.insert(ret.color(reg));
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// returns the machine register (as its color) of a temporary or a machine register
+    pub fn color(&self, reg: MuID) -> MuID {
+        let reg = match self.temps.get(&reg) {
+            Some(machine_reg) => *machine_reg,
+            None => reg,
+        };
+        if reg < MACHINE_ID_END {
+            backend::get_color_for_precolored(reg)
+        } else {
+            reg
+        }
+    }
+
+    /// are the two temporaries/registers in the same machine register?
+    pub fn is_same_reg(&self, reg1: MuID, reg2: MuID) -> bool {
+        self.color(reg1) == self.color(reg2)
+    }
+
+    /// is the temporary/register dead after the instruction?
+    /// (we assume it is live if we do not know)
+    pub fn is_dead_after(&self, reg: MuID, inst: usize) -> bool {
+        match self.live_after[inst] {
+            Some(ref live) => !live.contains(&self.color(reg)),
+            None => false,
+        }
+    }
+}
+
+/// if two sides of a move instruction are the same, it is redundant, and can be eliminated
+fn remove_redundant_move(cf: &mut CompiledFunction, liveness: &RegLiveness, inst: usize) -> bool {
+    // if this instruction is a move, and move from register to register (no memory operands)
+    if cf.mc().is_move(inst) && !cf.mc().is_using_mem_op(inst) {
+        // get source reg/temp ID
+        let src: MuID = {
+            let uses = cf.mc().get_inst_reg_uses(inst);
+            if uses.len() == 0 {
+                // moving immediate to register, its not redundant
+                return false;
+            }
+            uses[0]
+        };
+
+        // get dest reg/temp ID
+        let dst: MuID = cf.mc().get_inst_reg_defines(inst)[0];
+
+        // turning temp into machine reg
+        let src_machine_reg: MuID = liveness.color(src);
+        let dst_machine_reg: MuID = liveness.color(dst);
+
+        // check if two registers are aliased
+        if backend::is_aliased(src_machine_reg, dst_machine_reg) {
+            info!(
+                "move between {} and {} is redundant! removed",
+                src_machine_reg, dst_machine_reg
+            );
+            // redundant, remove this move
+            cf.mc_mut().set_inst_nop(inst);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// returns the end of the block that the instruction is in
+fn get_block_end(cf: &CompiledFunction, inst: usize) -> Option<usize> {
+    match cf.mc().get_block_for_inst(inst) {
+        Some(block) => cf.mc().get_block_range(&block).map(|range| range.end),
+        None => None,
+    }
+}
+
+/// if a spilled register is stored, and then reloaded into the same register, such as
+/// ..
+///   spill store R -> slot
+///   ..  (R is not redefined)
+///   spill reload slot -> R
+/// ..
+/// the reload is redundant, and can be eliminated
+fn fold_spill_store_reload(cf: &mut CompiledFunction, liveness: &RegLiveness, inst: usize) -> bool {
+    let slot = match cf.mc().is_spill_store(inst) {
+        Some(slot) => slot,
+        None => return false,
+    };
+    let block_end = match get_block_end(cf, inst) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    // the registers that still hold the stored value (the store also uses the base
+    // register of the slot, but that is the frame pointer, which is never reloaded)
+    let mut candidates: Vec<MuID> = cf
+        .mc()
+        .get_inst_reg_uses(inst)
+        .into_iter()
+        .map(|reg| liveness.color(reg))
+        .collect();
+
+    let mut reload = None;
+    for i in (inst + 1)..block_end {
+        let mc = cf.mc();
+        if mc.is_nop(i) {
+            continue;
+        }
+        if let Some(other_slot) = mc.is_spill_store(i) {
+            if other_slot.id() == slot.id() {
+                // the slot is overwritten
+                break;
+            }
+        }
+        if let Some(other_slot) = mc.is_spill_load(i) {
+            if other_slot.id() == slot.id() {
+                let defines = mc.get_inst_reg_defines(i);
+                if defines.len() == 1 && candidates.contains(&liveness.color(defines[0])) {
+                    reload = Some(i);
+                }
+                break;
+            }
+        }
+        for reg in mc.get_inst_reg_defines(i) {
+            let color = liveness.color(reg);
+            candidates.retain(|x| *x != color);
+        }
+        if candidates.is_empty() {
+            break;
+        }
+    }
+
+    match reload {
+        Some(i) => {
+            info!("spill reload at inst {} is redundant! removed", i);
+            cf.mc_mut().set_inst_nop(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// if a spilled register is reloaded, and then stored back to the same slot, such as
+/// ..
+///   spill reload slot -> R
+///   ..  (R is not redefined, the slot is not stored)
+///   spill store R -> slot
+/// ..
+/// the store is redundant (the slot already holds the value), and can be eliminated
+fn fold_spill_reload_store(cf: &mut CompiledFunction, liveness: &RegLiveness, inst: usize) -> bool {
+    let slot = match cf.mc().is_spill_load(inst) {
+        Some(slot) => slot,
+        None => return false,
+    };
+    let block_end = match get_block_end(cf, inst) {
+        Some(end) => end,
+        None => return false,
+    };
+    let reg = {
+        let defines = cf.mc().get_inst_reg_defines(inst);
+        if defines.len() != 1 {
+            return false;
+        }
+        liveness.color(defines[0])
+    };
+
+    let mut store = None;
+    for i in (inst + 1)..block_end {
+        let mc = cf.mc();
+        if mc.is_nop(i) {
+            continue;
+        }
+        if let Some(other_slot) = mc.is_spill_store(i) {
+            if other_slot.id() == slot.id() {
+                let uses = mc.get_inst_reg_uses(i);
+                if uses.iter().any(|x| liveness.color(*x) == reg) {
+                    store = Some(i);
+                }
+                break;
+            }
+        }
+        if mc
+            .get_inst_reg_defines(i)
+            .iter()
+            .any(|x| liveness.color(*x) == reg)
+        {
+            break;
+        }
+    }
+
+    match store {
+        Some(i) => {
+            info!("spill store at inst {} is redundant! removed", i);
+            cf.mc_mut().set_inst_nop(i);
+            true
+        }
+        None => false,
+    }
+}
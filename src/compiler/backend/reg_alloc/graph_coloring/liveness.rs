@@ -569,7 +569,7 @@ pub fn build_interference_graph_chaitin_briggs(
 }
 
 /// builds global liveness for a compiled function
-fn build_global_liveness(cf: &mut CompiledFunction, func: &MuFunctionVersion) {
+pub fn build_global_liveness(cf: &mut CompiledFunction, func: &MuFunctionVersion) {
     info!("---start building live set---");
 
     // build control flow graphs, treat a whole block as one node in the graph
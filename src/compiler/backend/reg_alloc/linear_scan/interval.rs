@@ -0,0 +1,315 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use compiler::backend;
+use compiler::machine_code::CompiledFunction;
+use std::fmt;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+
+/// Interval represents where a temporary (or a machine register) is live, as a sorted list of
+/// disjoint ranges [start, end). The gaps between the ranges are lifetime holes, where the
+/// register of the temporary can be used by other temporaries.
+///
+/// Positions are numbered from the instruction indices: instruction i reads its operands
+/// at 2i, and writes its results at 2i + 1. So a temporary that dies at an instruction can
+/// share a register with a temporary that the instruction defines. A value can only be moved
+/// between instructions, i.e. at an even position.
+///
+/// An interval can be split into several intervals (each of which gets its own location).
+#[derive(Clone)]
+pub struct Interval {
+    /// temp ID (could be register). The intervals split from an interval have the same temp
+    pub temp: MuID,
+    /// temp register group (which machine register class we should assign)
+    pub group: backend::RegGroup,
+    ranges: Vec<(usize, usize)>,
+    /// sorted positions where the temp is used or defined (where it needs a register)
+    uses: Vec<usize>,
+}
+
+impl Interval {
+    fn new(temp: MuID, group: backend::RegGroup) -> Interval {
+        Interval {
+            temp: temp,
+            group: group,
+            ranges: vec![],
+            uses: vec![],
+        }
+    }
+
+    /// the first position where the temp is live
+    pub fn start(&self) -> usize {
+        self.ranges[0].0
+    }
+
+    /// the position after the last position where the temp is live
+    pub fn end(&self) -> usize {
+        self.ranges[self.ranges.len() - 1].1
+    }
+
+    /// is the temp live at the position?
+    pub fn covers(&self, pos: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= pos && pos < end)
+    }
+
+    /// returns the first position where both intervals are live
+    pub fn next_intersection(&self, other: &Interval) -> Option<usize> {
+        let mut i = 0;
+        let mut j = 0;
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a_start, a_end) = self.ranges[i];
+            let (b_start, b_end) = other.ranges[j];
+            if a_end <= b_start {
+                i += 1;
+            } else if b_end <= a_start {
+                j += 1;
+            } else {
+                return Some(if a_start > b_start { a_start } else { b_start });
+            }
+        }
+        None
+    }
+
+    /// do the intervals have a position where both are live?
+    pub fn intersects(&self, other: &Interval) -> bool {
+        self.next_intersection(other).is_some()
+    }
+
+    /// returns the first position at or after pos where the temp needs a register
+    pub fn next_use(&self, pos: usize) -> Option<usize> {
+        self.uses.iter().find(|x| **x >= pos).map(|x| *x)
+    }
+
+    /// returns the latest position (at or before pos) where we can split the interval, so that
+    /// the rest starts at or before pos. We cannot move the value in the middle of an
+    /// instruction, so if the temp is live across pos - 1 and pos, we split before the
+    /// instruction instead
+    pub fn split_pos_before(&self, pos: usize) -> usize {
+        if pos % 2 == 1 && self.covers(pos - 1) && self.covers(pos) {
+            pos - 1
+        } else {
+            pos
+        }
+    }
+
+    /// splits the interval at pos: this interval keeps the part before pos, and the part
+    /// from pos is returned as a new interval
+    pub fn split_at(&mut self, pos: usize) -> Interval {
+        debug_assert!(self.start() < pos && pos < self.end());
+
+        let mut ranges = vec![];
+        let mut rest = vec![];
+        for &(start, end) in self.ranges.iter() {
+            if end <= pos {
+                ranges.push((start, end));
+            } else if pos <= start {
+                rest.push((start, end));
+            } else {
+                ranges.push((start, pos));
+                rest.push((pos, end));
+            }
+        }
+        self.ranges = ranges;
+
+        let rest_uses = self
+            .uses
+            .iter()
+            .filter(|x| **x >= pos)
+            .map(|x| *x)
+            .collect();
+        self.uses.retain(|x| *x < pos);
+
+        Interval {
+            temp: self.temp,
+            group: self.group,
+            ranges: rest,
+            uses: rest_uses,
+        }
+    }
+
+    /// adds a range, and merges it with the ranges it overlaps
+    /// (adjacent ranges are not merged, as set_from() relies on ranges starting at block starts)
+    fn add_range(&mut self, from: usize, to: usize) {
+        debug_assert!(from < to);
+        let mut from = from;
+        let mut to = to;
+        let mut new_ranges = vec![];
+        let mut inserted = false;
+        for &(start, end) in self.ranges.iter() {
+            if end <= from {
+                new_ranges.push((start, end));
+            } else if to <= start {
+                if !inserted {
+                    new_ranges.push((from, to));
+                    inserted = true;
+                }
+                new_ranges.push((start, end));
+            } else {
+                // overlapping
+                if start < from {
+                    from = start;
+                }
+                if end > to {
+                    to = end;
+                }
+            }
+        }
+        if !inserted {
+            new_ranges.push((from, to));
+        }
+        self.ranges = new_ranges;
+    }
+
+    /// the temp needs a register at pos
+    fn add_use(&mut self, pos: usize) {
+        if let Err(index) = self.uses.binary_search(&pos) {
+            self.uses.insert(index, pos);
+        }
+    }
+
+    /// the temp is defined at pos: shortens the range that starts at the block start
+    fn set_from(&mut self, block_start: usize, pos: usize) {
+        for range in self.ranges.iter_mut() {
+            if range.0 == block_start && pos < range.1 {
+                range.0 = pos;
+                return;
+            }
+        }
+        unreachable!()
+    }
+}
+
+impl fmt::Debug for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Interval({}): group={:?}, ranges={:?}, uses={:?}",
+            self.temp, self.group, self.ranges, self.uses
+        )
+    }
+}
+
+/// Move represents a move between two temps, which we try to assign the same register
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move {
+    pub from: MuID,
+    pub to: MuID,
+}
+
+/// checks if a reg is machine register. If so, return its color
+/// otherwise return the reg
+#[inline(always)]
+pub fn c(u: MuID) -> MuID {
+    if u < MACHINE_ID_END {
+        backend::get_color_for_precolored(u)
+    } else {
+        u
+    }
+}
+
+/// gets the interval of a temp, creates an empty one if it does not exist yet
+fn get_interval<'a>(
+    intervals: &'a mut LinkedHashMap<MuID, Interval>,
+    func: &MuFunctionVersion,
+    temp: MuID,
+) -> &'a mut Interval {
+    if !intervals.contains_key(&temp) {
+        let entry = match func.context.get_value(temp) {
+            Some(entry) => entry,
+            None => panic!("The temp {} is not in func context", temp),
+        };
+        let group = backend::RegGroup::get_from_ty(entry.ty());
+        intervals.insert(temp, Interval::new(temp, group));
+    }
+    intervals.get_mut(&temp).unwrap()
+}
+
+/// builds the intervals of all temps and machine registers (by color) from the global
+/// liveness (block live-outs), and collects the moves between registers
+pub fn build_intervals(
+    cf: &CompiledFunction,
+    func: &MuFunctionVersion,
+) -> (LinkedHashMap<MuID, Interval>, LinkedHashSet<Move>) {
+    let mut intervals: LinkedHashMap<MuID, Interval> = LinkedHashMap::new();
+    let mut moves: LinkedHashSet<Move> = LinkedHashSet::new();
+
+    let mc = cf.mc();
+    for block in mc.get_all_blocks() {
+        let range = match mc.get_block_range(&block) {
+            Some(range) => range,
+            None => continue,
+        };
+        let block_start = range.start * 2;
+        let block_end = range.end * 2;
+
+        // the temps that are live out are live in the whole block
+        // (unless we find their definition)
+        let mut live: LinkedHashSet<MuID> = LinkedHashSet::new();
+        match mc.get_ir_block_liveout(&block) {
+            Some(liveout) => {
+                for &temp in liveout.iter() {
+                    let temp = c(temp);
+                    live.insert(temp);
+                    get_interval(&mut intervals, func, temp).add_range(block_start, block_end);
+                }
+            }
+            None => panic!("cannot find liveout for block {}", block),
+        }
+
+        for i in range.rev() {
+            let is_move = mc.is_move(i);
+            let defines = mc.get_inst_reg_defines(i);
+            let uses = mc.get_inst_reg_uses(i);
+
+            if is_move && !mc.is_using_mem_op(i) && defines.len() == 1 && uses.len() == 1 {
+                moves.insert(Move {
+                    from: c(uses[0]),
+                    to: c(defines[0]),
+                });
+            }
+
+            for d in defines {
+                let d = c(d);
+                let def_pos = i * 2 + 1;
+                {
+                    let interval = get_interval(&mut intervals, func, d);
+                    if live.contains(&d) {
+                        interval.set_from(block_start, def_pos);
+                    } else {
+                        // the result is never used, it still occupies a register
+                        interval.add_range(def_pos, def_pos + 1);
+                    }
+                    interval.add_use(def_pos);
+                }
+                live.remove(&d);
+            }
+
+            for u in uses {
+                let u = c(u);
+                {
+                    let interval = get_interval(&mut intervals, func, u);
+                    interval.add_range(block_start, i * 2 + 1);
+                    interval.add_use(i * 2);
+                }
+                live.insert(u);
+            }
+        }
+    }
+
+    (intervals, moves)
+}
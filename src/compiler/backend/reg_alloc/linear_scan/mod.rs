@@ -0,0 +1,996 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate hprof;
+
+/// live intervals (with lifetime holes) built from the global liveness
+mod interval;
+
+use ast::ir::*;
+use ast::ptr::*;
+use compiler::backend;
+use compiler::backend::reg_alloc::graph_coloring::build_global_liveness;
+use compiler::backend::reg_alloc::linear_scan::interval::*;
+use compiler::backend::RegMove;
+use compiler::machine_code::CompiledFunction;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::usize;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use vm::VM;
+
+/// intervals to allocate, ordered by their start positions (start, temp, interval index)
+type Unhandled = BinaryHeap<Reverse<(usize, MuID, usize)>>;
+
+/// where an interval (a part of a temporary) lives
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Location {
+    /// in a machine register
+    Reg(MuID),
+    /// in the spill slot of the temporary
+    Stack,
+}
+
+/// LinearScan algorithm
+/// based on Wimmer and Mössenböck, Optimized Interval Splitting in a Linear Scan Register
+/// Allocator (VEE'05), and Traub et al., Quality and Speed in Linear-scan Register Allocation
+/// (PLDI'98, second-chance binpacking).
+///
+/// Intervals are visited in the order of their start positions. A temporary gets a register
+/// that is free for its whole interval (the lifetime holes of the intervals are used for
+/// binpacking), or if no register is free that long, the register that is free the longest:
+/// the interval is split before the register is needed by others, and the rest is allocated
+/// later. If no register is free at all, we take the register whose next use is the furthest,
+/// and split the intervals that hold it: they live in their spill slots until their next uses
+/// (if the current interval has the furthest next use, it lives in its spill slot instead).
+///
+/// After the scan, the parts of a split temporary become separate temporaries. Moves are
+/// inserted where the parts meet in a block, and at the edges between blocks where the
+/// temporary is in different locations at the end of the predecessor and at the start of the
+/// successor (data flow resolution).
+pub struct LinearScan<'a> {
+    // context
+    pub func: &'a mut MuFunctionVersion,
+    pub cf: &'a mut CompiledFunction,
+    pub vm: &'a VM,
+
+    /// live intervals of temporaries, and the parts they are split into
+    intervals: Vec<Interval>,
+    /// where each interval lives (None if it is not allocated yet)
+    locations: Vec<Option<Location>>,
+    /// the intervals that a temporary is split into
+    parts: LinkedHashMap<MuID, Vec<usize>>,
+    /// live intervals of machine registers (by color) that the code uses directly
+    fixed: LinkedHashMap<MuID, Interval>,
+    /// moves between temporaries (which we try to assign the same register)
+    moves: LinkedHashSet<Move>,
+
+    /// assigned register for temporaries
+    assignments: LinkedHashMap<MuID, MuID>,
+
+    // for validation use
+    /// spill slots of temporaries that (partly) live in memory
+    spill_slots: LinkedHashMap<MuID, P<Value>>,
+    /// we need to know the mapping between the temporary of a part -> original temp
+    spill_scratch_temps: LinkedHashMap<MuID, MuID>,
+}
+
+impl<'a> LinearScan<'a> {
+    /// starts linear scan allocation
+    pub fn start(
+        func: &'a mut MuFunctionVersion,
+        cf: &'a mut CompiledFunction,
+        vm: &'a VM,
+    ) -> LinearScan<'a> {
+        trace!("Initializing linear scan allocator...");
+        cf.mc().trace_mc();
+
+        let _p = hprof::enter("regalloc: build global liveness");
+        build_global_liveness(cf, func);
+        drop(_p);
+
+        let _p = hprof::enter("regalloc: build intervals");
+        let (all_intervals, moves) = build_intervals(cf, func);
+        drop(_p);
+
+        let mut intervals = vec![];
+        let mut parts = LinkedHashMap::new();
+        let mut fixed = LinkedHashMap::new();
+        for (temp, interval) in all_intervals {
+            if temp < MACHINE_ID_END {
+                fixed.insert(temp, interval);
+            } else {
+                parts.insert(temp, vec![intervals.len()]);
+                intervals.push(interval);
+            }
+        }
+        let n_intervals = intervals.len();
+
+        let mut linear_scan = LinearScan {
+            func: func,
+            cf: cf,
+            vm: vm,
+            intervals: intervals,
+            locations: vec![None; n_intervals],
+            parts: parts,
+            fixed: fixed,
+            moves: moves,
+            assignments: LinkedHashMap::new(),
+            spill_slots: LinkedHashMap::new(),
+            spill_scratch_temps: LinkedHashMap::new(),
+        };
+
+        linear_scan.regalloc();
+        linear_scan
+    }
+
+    /// does linear scan register allocation
+    fn regalloc(&mut self) {
+        if cfg!(debug_assertions) {
+            trace!("---Intervals---");
+            for interval in self.intervals.iter() {
+                trace!("{:?}", interval);
+            }
+        }
+
+        // start timing for linear scan
+        let _p = hprof::enter("regalloc: linear scan");
+
+        self.scan();
+
+        // finish
+        drop(_p);
+
+        let _p = hprof::enter("regalloc: resolve data flow");
+        self.resolve();
+        drop(_p);
+    }
+
+    /// walks the intervals in order of their start positions, and assigns registers
+    fn scan(&mut self) {
+        let usable_regs = usable_regs_by_group();
+
+        // temporaries related by moves: we prefer to assign them the same register
+        let mut hints: LinkedHashMap<MuID, Vec<MuID>> = LinkedHashMap::new();
+        for mov in self.moves.iter() {
+            if mov.from != mov.to {
+                for &(a, b) in [(mov.from, mov.to), (mov.to, mov.from)].iter() {
+                    if !hints.contains_key(&a) {
+                        hints.insert(a, vec![]);
+                    }
+                    hints.get_mut(&a).unwrap().push(b);
+                }
+            }
+        }
+
+        let mut unhandled: Unhandled = BinaryHeap::new();
+        for i in 0..self.intervals.len() {
+            self.add_unhandled(&mut unhandled, i);
+        }
+
+        // intervals that are assigned a register, and cover the current position
+        let mut active: Vec<usize> = vec![];
+        // intervals that are assigned a register, but are in a lifetime hole
+        let mut inactive: Vec<usize> = vec![];
+
+        while let Some(Reverse((position, _, cur))) = unhandled.pop() {
+            trace!(
+                "allocating {:?} at position {}",
+                self.intervals[cur],
+                position
+            );
+
+            // expire/update active and inactive intervals
+            {
+                let intervals = &self.intervals;
+                let mut new_active = vec![];
+                let mut new_inactive = vec![];
+                for &i in active.iter().chain(inactive.iter()) {
+                    let interval = &intervals[i];
+                    if interval.end() <= position {
+                        // handled
+                    } else if interval.covers(position) {
+                        new_active.push(i);
+                    } else {
+                        new_inactive.push(i);
+                    }
+                }
+                active = new_active;
+                inactive = new_inactive;
+            }
+
+            let regs = usable_regs.get(&self.intervals[cur].group).unwrap();
+            if !self.try_allocate_free_reg(cur, regs, &active, &inactive, &hints, &mut unhandled) {
+                self.allocate_blocked_reg(cur, regs, &mut active, &mut inactive, &mut unhandled);
+            }
+
+            if let Some(Location::Reg(reg)) = self.locations[cur] {
+                trace!("assign {} to {:?}", reg, self.intervals[cur]);
+                active.push(cur);
+            }
+        }
+    }
+
+    /// tries to find a register that is free at the start of cur. If the register is not free
+    /// for the whole interval, cur is split before the register is needed by others, and the
+    /// rest is allocated later. Returns false if no register is free
+    fn try_allocate_free_reg(
+        &mut self,
+        cur: usize,
+        regs: &[MuID],
+        active: &[usize],
+        inactive: &[usize],
+        hints: &LinkedHashMap<MuID, Vec<MuID>>,
+        unhandled: &mut Unhandled,
+    ) -> bool {
+        let position = self.intervals[cur].start();
+
+        let (reg, free_until) = {
+            let cur_interval = &self.intervals[cur];
+
+            let mut free_until: LinkedHashMap<MuID, usize> = LinkedHashMap::new();
+            for &reg in regs.iter() {
+                let pos = match self.fixed.get(&reg) {
+                    // the register is used by the code (as precolored)
+                    Some(fixed) => match fixed.next_intersection(cur_interval) {
+                        Some(pos) => pos,
+                        None => usize::MAX,
+                    },
+                    None => usize::MAX,
+                };
+                free_until.insert(reg, pos);
+            }
+            for &i in active.iter() {
+                let reg = self.reg_of(i);
+                if free_until.contains_key(&reg) {
+                    free_until.insert(reg, 0);
+                }
+            }
+            for &i in inactive.iter() {
+                let reg = self.reg_of(i);
+                if let Some(pos) = self.intervals[i].next_intersection(cur_interval) {
+                    if free_until.contains_key(&reg) && pos < *free_until.get(&reg).unwrap() {
+                        free_until.insert(reg, pos);
+                    }
+                }
+            }
+
+            // prefer the register of the part before this one (so we do not need a move),
+            // or a register that the temporary is moved from/to
+            let mut hinted = vec![];
+            if let Some(reg) = self.reg_of_temp_before(cur_interval.temp, position) {
+                hinted.push(reg);
+            }
+            if let Some(related) = hints.get(&cur_interval.temp) {
+                for &other in related.iter() {
+                    if other < MACHINE_ID_END {
+                        hinted.push(other);
+                    } else if let Some(reg) = self.reg_of_temp_before(other, position + 1) {
+                        hinted.push(reg);
+                    }
+                }
+            }
+            let end = cur_interval.end();
+            let hint = hinted.into_iter().find(|reg| match free_until.get(reg) {
+                Some(pos) => *pos >= end,
+                None => false,
+            });
+
+            let reg = match hint {
+                Some(reg) => {
+                    trace!("  pick hinted register {}", reg);
+                    reg
+                }
+                None => {
+                    // the register that is free for the longest
+                    let mut best = regs[0];
+                    for &reg in regs.iter() {
+                        if free_until.get(&reg).unwrap() > free_until.get(&best).unwrap() {
+                            best = reg;
+                        }
+                    }
+                    best
+                }
+            };
+            (reg, *free_until.get(&reg).unwrap())
+        };
+
+        if free_until <= position {
+            // no register is free
+            return false;
+        }
+
+        if free_until < self.intervals[cur].end() {
+            // the register is only free for the first part of cur
+            let split_pos = self.intervals[cur].split_pos_before(free_until);
+            if split_pos <= position {
+                return false;
+            }
+            trace!("  {} is free until {}", reg, free_until);
+            let rest = self.split(cur, split_pos);
+            self.add_unhandled(unhandled, rest);
+        }
+
+        self.locations[cur] = Some(Location::Reg(reg));
+        true
+    }
+
+    /// no register is free at the start of cur: takes the register whose next use is the
+    /// furthest. The intervals that hold it are split, and live in their spill slots until
+    /// their next uses. If cur's first use is further than the next uses of all registers,
+    /// cur lives in its spill slot until its first use instead
+    fn allocate_blocked_reg(
+        &mut self,
+        cur: usize,
+        regs: &[MuID],
+        active: &mut Vec<usize>,
+        inactive: &mut Vec<usize>,
+        unhandled: &mut Unhandled,
+    ) {
+        let position = self.intervals[cur].start();
+
+        // the next position where each register is needed by others,
+        // and where it is used by the code (and cannot be taken)
+        let mut use_pos: LinkedHashMap<MuID, usize> = LinkedHashMap::new();
+        let mut block_pos: LinkedHashMap<MuID, usize> = LinkedHashMap::new();
+        for &reg in regs.iter() {
+            use_pos.insert(reg, usize::MAX);
+            block_pos.insert(reg, usize::MAX);
+        }
+        {
+            let cur_interval = &self.intervals[cur];
+            let update = |map: &mut LinkedHashMap<MuID, usize>, reg: MuID, pos: usize| {
+                if let Some(old) = map.get_mut(&reg) {
+                    if pos < *old {
+                        *old = pos;
+                    }
+                }
+            };
+
+            for &i in active.iter() {
+                let interval = &self.intervals[i];
+                // the interval will be split where it can move out of the register
+                let split_pos = interval.split_pos_before(position);
+                let pos = if split_pos <= interval.start() && interval.start() < position {
+                    // it cannot be split before cur needs the register
+                    position
+                } else {
+                    match interval.next_use(split_pos) {
+                        Some(pos) => pos,
+                        None => usize::MAX,
+                    }
+                };
+                update(&mut use_pos, self.reg_of(i), pos);
+            }
+            for &i in inactive.iter() {
+                let interval = &self.intervals[i];
+                if interval.intersects(cur_interval) {
+                    if let Some(pos) = interval.next_use(position) {
+                        update(&mut use_pos, self.reg_of(i), pos);
+                    }
+                }
+            }
+            for &reg in regs.iter() {
+                if let Some(fixed) = self.fixed.get(&reg) {
+                    if let Some(pos) = fixed.next_intersection(cur_interval) {
+                        // cur has to move out of the register before
+                        let pos = cur_interval.split_pos_before(pos);
+                        update(&mut use_pos, reg, pos);
+                        update(&mut block_pos, reg, pos);
+                    }
+                }
+            }
+        }
+
+        // the register whose next use is the furthest
+        let mut reg = regs[0];
+        for &r in regs.iter() {
+            if use_pos.get(&r).unwrap() > use_pos.get(&reg).unwrap() {
+                reg = r;
+            }
+        }
+        let reg_use = *use_pos.get(&reg).unwrap();
+
+        let spill_cur = match self.intervals[cur].next_use(position) {
+            None => true,
+            Some(first_use) => {
+                reg_use < first_use && self.intervals[cur].split_pos_before(first_use) > position
+            }
+        };
+        if spill_cur {
+            trace!("  spill {:?} until its first use", self.intervals[cur]);
+            self.spill_until_next_use(cur, unhandled);
+            return;
+        }
+
+        if reg_use <= position {
+            panic!(
+                "cannot find a register for {:?} at position {}",
+                self.intervals[cur], position
+            )
+        }
+
+        trace!("  take {} (next used at {})", reg, reg_use);
+        let block = *block_pos.get(&reg).unwrap();
+        if block < self.intervals[cur].end() {
+            // the register is used by the code later, cur only gets it until then
+            let rest = self.split(cur, block);
+            self.add_unhandled(unhandled, rest);
+        }
+        self.locations[cur] = Some(Location::Reg(reg));
+
+        // the intervals that hold the register live in their spill slots until their next uses
+        let holders: Vec<usize> = active
+            .iter()
+            .filter(|i| self.reg_of(**i) == reg)
+            .map(|i| *i)
+            .collect();
+        for i in holders {
+            active.retain(|x| *x != i);
+            let split_pos = self.intervals[i].split_pos_before(position);
+            if split_pos <= self.intervals[i].start() {
+                // it starts here as well
+                self.locations[i] = None;
+                self.spill_until_next_use(i, unhandled);
+            } else {
+                let rest = self.split(i, split_pos);
+                self.spill_until_next_use(rest, unhandled);
+            }
+        }
+        let holders: Vec<usize> = inactive
+            .iter()
+            .filter(|i| self.reg_of(**i) == reg)
+            .map(|i| *i)
+            .collect();
+        for i in holders {
+            let split_pos = match self.intervals[i].next_intersection(&self.intervals[cur]) {
+                Some(pos) => self.intervals[i].split_pos_before(pos),
+                None => continue,
+            };
+            // the part before stays in the register
+            let rest = self.split(i, split_pos);
+            self.spill_until_next_use(rest, unhandled);
+        }
+    }
+
+    /// the interval does not get a register at its start: it lives in the spill slot until
+    /// its next use, and the rest is allocated later
+    fn spill_until_next_use(&mut self, i: usize, unhandled: &mut Unhandled) {
+        let start = self.intervals[i].start();
+        match self.intervals[i].next_use(start) {
+            Some(use_pos) => {
+                let split_pos = self.intervals[i].split_pos_before(use_pos);
+                if split_pos > start {
+                    let rest = self.split(i, split_pos);
+                    self.add_unhandled(unhandled, rest);
+                    self.locations[i] = Some(Location::Stack);
+                } else {
+                    // it needs a register right away
+                    self.add_unhandled(unhandled, i);
+                }
+            }
+            None => {
+                self.locations[i] = Some(Location::Stack);
+            }
+        }
+    }
+
+    /// splits the interval at pos, and returns the (unallocated) part from pos
+    fn split(&mut self, i: usize, pos: usize) -> usize {
+        let rest = self.intervals[i].split_at(pos);
+        trace!("  split at {}: {:?}, {:?}", pos, self.intervals[i], rest);
+
+        let index = self.intervals.len();
+        self.parts.get_mut(&rest.temp).unwrap().push(index);
+        self.intervals.push(rest);
+        self.locations.push(None);
+        index
+    }
+
+    fn add_unhandled(&self, unhandled: &mut Unhandled, i: usize) {
+        let interval = &self.intervals[i];
+        unhandled.push(Reverse((interval.start(), interval.temp, i)));
+    }
+
+    /// the register of an interval that is assigned a register
+    fn reg_of(&self, i: usize) -> MuID {
+        match self.locations[i] {
+            Some(Location::Reg(reg)) => reg,
+            _ => panic!("{:?} is not assigned a register", self.intervals[i]),
+        }
+    }
+
+    /// the register of the latest part of a temporary that starts before pos (and is assigned
+    /// a register)
+    fn reg_of_temp_before(&self, temp: MuID, pos: usize) -> Option<MuID> {
+        let mut ret = None;
+        let mut ret_start = 0;
+        if let Some(parts) = self.parts.get(&temp) {
+            for &i in parts.iter() {
+                if let Some(Location::Reg(reg)) = self.locations[i] {
+                    let start = self.intervals[i].start();
+                    if start < pos && (ret.is_none() || start > ret_start) {
+                        ret = Some(reg);
+                        ret_start = start;
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// the part of a temporary that is live at pos
+    fn part_at(&self, temp: MuID, pos: usize) -> usize {
+        let parts = self.parts.get(&temp).unwrap();
+        match parts.iter().find(|i| self.intervals[**i].covers(pos)) {
+            Some(i) => *i,
+            None => panic!("temp {} is not live at position {}", temp, pos),
+        }
+    }
+
+    /// returns the spill slot of a temporary (allocates one if it does not have one yet)
+    fn spill_slot(&mut self, temp: MuID) -> P<Value> {
+        if let Some(mem) = self.spill_slots.get(&temp) {
+            return mem.clone();
+        }
+
+        let value = match self.func.context.get_value(temp) {
+            Some(entry) => entry.value().clone(),
+            None => panic!("The spilled register {} is not in func context", temp),
+        };
+        let mem = self.cf.frame.alloc_slot_for_spilling(value, self.vm);
+        self.spill_slots.insert(temp, mem.clone());
+        mem
+    }
+
+    /// the parts of split temporaries become separate temporaries, and moves are inserted
+    /// where the parts meet (in a block, or at the edges between blocks)
+    fn resolve(&mut self) {
+        {
+            let intervals = &self.intervals;
+            for parts in self.parts.values_mut() {
+                parts.sort_by_key(|i| intervals[*i].start());
+            }
+        }
+
+        // each part in a register gets its own temporary
+        let mut part_temps: Vec<Option<P<Value>>> = vec![None; self.intervals.len()];
+        let split_temps: Vec<(MuID, Vec<usize>)> = self
+            .parts
+            .iter()
+            .map(|(temp, parts)| (*temp, parts.clone()))
+            .collect();
+        for &(temp, ref parts) in split_temps.iter() {
+            if parts.len() == 1 {
+                let reg = self.reg_of(parts[0]);
+                self.assignments.insert(temp, reg);
+                continue;
+            }
+
+            let ty = self.func.context.get_value(temp).unwrap().ty().clone();
+            for &i in parts.iter() {
+                if let Some(Location::Reg(reg)) = self.locations[i] {
+                    let part_temp = self
+                        .func
+                        .new_ssa(MuEntityHeader::unnamed(self.vm.next_id()), ty.clone())
+                        .clone_value();
+                    trace!("part {:?} of {} is {}", self.intervals[i], temp, part_temp);
+                    self.assignments.insert(part_temp.id(), reg);
+                    self.spill_scratch_temps.insert(part_temp.id(), temp);
+                    part_temps[i] = Some(part_temp);
+                }
+            }
+        }
+
+        // rewrite the uses and defines of split temporaries
+        let n_insts = self.cf.mc().number_of_insts();
+        for i in 0..n_insts {
+            let mut uses: Vec<MuID> = self.cf.mc().get_inst_reg_uses(i);
+            uses.sort();
+            uses.dedup();
+            for temp in uses {
+                if self.parts.get(&temp).map_or(false, |parts| parts.len() > 1) {
+                    let part = self.part_at(temp, i * 2);
+                    let part_temp = part_temps[part].as_ref().unwrap().id();
+                    self.cf
+                        .mc_mut()
+                        .replace_use_tmp_for_inst(temp, part_temp, i);
+                }
+            }
+            let mut defines: Vec<MuID> = self.cf.mc().get_inst_reg_defines(i);
+            defines.sort();
+            defines.dedup();
+            for temp in defines {
+                if self.parts.get(&temp).map_or(false, |parts| parts.len() > 1) {
+                    let part = self.part_at(temp, i * 2 + 1);
+                    let part_temp = part_temps[part].as_ref().unwrap().id();
+                    self.cf
+                        .mc_mut()
+                        .replace_define_tmp_for_inst(temp, part_temp, i);
+                }
+            }
+        }
+
+        // moves to insert before/after an instruction: (temp, from part, to part)
+        // the moves in one group happen at the same time
+        let mut groups_before: LinkedHashMap<usize, Vec<Vec<(MuID, usize, usize)>>> =
+            LinkedHashMap::new();
+        let mut groups_after: LinkedHashMap<usize, Vec<Vec<(MuID, usize, usize)>>> =
+            LinkedHashMap::new();
+        let add_group = |groups: &mut LinkedHashMap<usize, Vec<Vec<(MuID, usize, usize)>>>,
+                         index: usize,
+                         group: Vec<(MuID, usize, usize)>| {
+            if !group.is_empty() {
+                if !groups.contains_key(&index) {
+                    groups.insert(index, vec![]);
+                }
+                groups.get_mut(&index).unwrap().push(group);
+            }
+        };
+
+        // the blocks of the code
+        let mut block_starts: LinkedHashMap<usize, MuName> = LinkedHashMap::new();
+        let mut block_ranges: LinkedHashMap<MuName, (usize, usize)> = LinkedHashMap::new();
+        for block in self.cf.mc().get_all_blocks() {
+            if let Some(range) = self.cf.mc().get_block_range(&block) {
+                block_starts.insert(range.start, block.clone());
+                block_ranges.insert(block, (range.start, range.end));
+            }
+        }
+
+        // a split in the middle of a block: moves at the split position
+        // (at a block start, the moves are at the edges)
+        {
+            let mut in_block: LinkedHashMap<usize, Vec<(MuID, usize, usize)>> =
+                LinkedHashMap::new();
+            for &(temp, ref parts) in split_temps.iter() {
+                for pair in parts.windows(2) {
+                    let pos = self.intervals[pair[1]].start();
+                    if self.intervals[pair[0]].covers(pos - 1)
+                        && !block_starts.contains_key(&(pos / 2))
+                    {
+                        debug_assert!(pos % 2 == 0);
+                        if !in_block.contains_key(&(pos / 2)) {
+                            in_block.insert(pos / 2, vec![]);
+                        }
+                        in_block
+                            .get_mut(&(pos / 2))
+                            .unwrap()
+                            .push((temp, pair[0], pair[1]));
+                    }
+                }
+            }
+            for (index, group) in in_block {
+                add_group(&mut groups_before, index, group);
+            }
+        }
+
+        // data flow resolution at the edges between blocks
+        let mut preds: LinkedHashMap<MuName, Vec<MuName>> = LinkedHashMap::new();
+        for block in block_ranges.keys() {
+            preds.insert(block.clone(), vec![]);
+        }
+        for (block, &(_, end)) in block_ranges.iter() {
+            let last = self.cf.mc().get_last_inst(end).unwrap();
+            for succ in self.cf.mc().get_succs(last).iter() {
+                if let Some(succ_block) = block_starts.get(succ) {
+                    let succ_preds = preds.get_mut(succ_block).unwrap();
+                    if !succ_preds.contains(block) {
+                        succ_preds.push(block.clone());
+                    }
+                }
+            }
+        }
+        // the moves at the end of a predecessor go before its last instruction if it is a
+        // branch (or may have several successors), otherwise after the block
+        let end_of_block = |cf: &CompiledFunction, end: usize| -> (bool, usize, usize) {
+            let mc = cf.mc();
+            let last = mc.get_last_inst(end).unwrap();
+            if mc.get_succs(last).len() == 1 && mc.is_jmp(last).is_none() {
+                (false, end - 1, end * 2 - 1)
+            } else {
+                (true, last, last * 2)
+            }
+        };
+
+        let mut slot_stores: LinkedHashMap<(bool, usize), Vec<(MuID, usize, usize)>> =
+            LinkedHashMap::new();
+        for (block, &(start, _)) in block_ranges.iter() {
+            let block_preds = preds.get(block).unwrap();
+            if block_preds.is_empty() {
+                continue;
+            }
+            let livein: Vec<MuID> = match self.cf.mc().get_ir_block_livein(block) {
+                Some(livein) => livein
+                    .iter()
+                    .map(|x| *x)
+                    .filter(|x| *x >= MACHINE_ID_END)
+                    .collect(),
+                None => panic!("cannot find livein for block {}", block),
+            };
+            // the moves at the start of a block go after its label
+            let start_index = if self.cf.mc().is_label(start).is_some() {
+                (false, start)
+            } else {
+                (true, start)
+            };
+
+            if block_preds.len() == 1 {
+                // moves at the start of the block
+                let (_, pred_end) = *block_ranges.get(&block_preds[0]).unwrap();
+                let mut group = vec![];
+                for &temp in livein.iter() {
+                    let from = self.part_at(temp, pred_end * 2 - 1);
+                    let to = self.part_at(temp, start * 2);
+                    group.push((temp, from, to));
+                }
+                if start_index.0 {
+                    add_group(&mut groups_before, start_index.1, group);
+                } else {
+                    add_group(&mut groups_after, start_index.1, group);
+                }
+            } else if block_preds.iter().all(|pred| {
+                let (_, pred_end) = *block_ranges.get(pred).unwrap();
+                self.cf
+                    .mc()
+                    .get_succs(self.cf.mc().get_last_inst(pred_end).unwrap())
+                    .len()
+                    == 1
+            }) {
+                // moves at the end of each predecessor
+                for pred in block_preds.iter() {
+                    let (_, pred_end) = *block_ranges.get(pred).unwrap();
+                    let (before, index, pos) = end_of_block(&*self.cf, pred_end);
+                    let mut group = vec![];
+                    for &temp in livein.iter() {
+                        let from = self.part_at(temp, pos);
+                        let to = self.part_at(temp, start * 2);
+                        group.push((temp, from, to));
+                    }
+                    if before {
+                        add_group(&mut groups_before, index, group);
+                    } else {
+                        add_group(&mut groups_after, index, group);
+                    }
+                }
+            } else {
+                // critical edges: we cannot insert moves that only happen on the edge. Each
+                // predecessor stores the temporary to its spill slot, and the block loads it
+                let mut loads = vec![];
+                for &temp in livein.iter() {
+                    let to = self.part_at(temp, start * 2);
+                    let froms: Vec<((bool, usize), usize)> = block_preds
+                        .iter()
+                        .map(|pred| {
+                            let (_, pred_end) = *block_ranges.get(pred).unwrap();
+                            let (before, index, pos) = end_of_block(&*self.cf, pred_end);
+                            ((before, index), self.part_at(temp, pos))
+                        })
+                        .collect();
+                    if froms
+                        .iter()
+                        .all(|&(_, from)| self.locations[from] == self.locations[to])
+                    {
+                        continue;
+                    }
+
+                    for (index, from) in froms {
+                        if self.locations[from] != Some(Location::Stack) {
+                            if !slot_stores.contains_key(&index) {
+                                slot_stores.insert(index, vec![]);
+                            }
+                            let stores = slot_stores.get_mut(&index).unwrap();
+                            if !stores.iter().any(|x| x.0 == temp) {
+                                stores.push((temp, from, usize::MAX));
+                            }
+                        }
+                    }
+                    if self.locations[to] != Some(Location::Stack) {
+                        loads.push((temp, usize::MAX, to));
+                    }
+                }
+                if start_index.0 {
+                    add_group(&mut groups_before, start_index.1, loads);
+                } else {
+                    add_group(&mut groups_after, start_index.1, loads);
+                }
+            }
+        }
+        for ((before, index), stores) in slot_stores {
+            if before {
+                add_group(&mut groups_before, index, stores);
+            } else {
+                add_group(&mut groups_after, index, stores);
+            }
+        }
+
+        // turn the groups into sequences of moves
+        let mut moves_before: LinkedHashMap<usize, Vec<RegMove>> = LinkedHashMap::new();
+        let mut moves_after: LinkedHashMap<usize, Vec<RegMove>> = LinkedHashMap::new();
+        for (index, groups) in groups_before {
+            let mut moves = vec![];
+            for group in groups {
+                moves.extend(self.sequentialize(group, &part_temps));
+            }
+            if !moves.is_empty() {
+                moves_before.insert(index, moves);
+            }
+        }
+        for (index, groups) in groups_after {
+            let mut moves = vec![];
+            for group in groups {
+                moves.extend(self.sequentialize(group, &part_temps));
+            }
+            if !moves.is_empty() {
+                moves_after.insert(index, moves);
+            }
+        }
+
+        if moves_before.is_empty() && moves_after.is_empty() {
+            return;
+        }
+        backend::insert_moves(&moves_before, &moves_after, self.func, self.cf, self.vm);
+
+        // the liveness is outdated after the insertion
+        build_global_liveness(self.cf, self.func);
+        self.allocate_new_temps();
+    }
+
+    /// turns a group of moves that happen at the same time into a sequence: stores first,
+    /// then moves between registers (a cycle goes through a spill slot), then loads.
+    /// A move from/to part usize::MAX is a move from/to the spill slot
+    fn sequentialize(
+        &mut self,
+        group: Vec<(MuID, usize, usize)>,
+        part_temps: &Vec<Option<P<Value>>>,
+    ) -> Vec<RegMove> {
+        let location = |locations: &Vec<Option<Location>>, i: usize| {
+            if i == usize::MAX {
+                Location::Stack
+            } else {
+                locations[i].unwrap()
+            }
+        };
+        let part_temp = |i: usize| part_temps[i].as_ref().unwrap().clone();
+
+        let mut stores = vec![];
+        let mut loads = vec![];
+        // (temp, from register, to register, from part, to part)
+        let mut copies: Vec<(MuID, MuID, MuID, usize, usize)> = vec![];
+        for (temp, from, to) in group {
+            match (
+                location(&self.locations, from),
+                location(&self.locations, to),
+            ) {
+                (Location::Reg(from_reg), Location::Reg(to_reg)) => {
+                    if from_reg != to_reg {
+                        copies.push((temp, from_reg, to_reg, from, to));
+                    }
+                }
+                (Location::Reg(_), Location::Stack) => {
+                    let mem = self.spill_slot(temp);
+                    stores.push(RegMove::Store(mem, part_temp(from)));
+                }
+                (Location::Stack, Location::Reg(_)) => {
+                    let mem = self.spill_slot(temp);
+                    loads.push(RegMove::Load(part_temp(to), mem));
+                }
+                (Location::Stack, Location::Stack) => {}
+            }
+        }
+
+        let mut ret = stores;
+        while !copies.is_empty() {
+            // a copy can be done if no other copy reads the register it writes
+            let ready = (0..copies.len()).find(|&i| !copies.iter().any(|c| c.1 == copies[i].2));
+            match ready {
+                Some(i) => {
+                    let (_, _, _, from, to) = copies.remove(i);
+                    ret.push(RegMove::Copy(part_temp(to), part_temp(from)));
+                }
+                None => {
+                    // the copies form a cycle, we break it with the spill slot
+                    let (temp, _, _, from, to) = copies.remove(0);
+                    let mem = self.spill_slot(temp);
+                    ret.push(RegMove::Store(mem.clone(), part_temp(from)));
+                    loads.push(RegMove::Load(part_temp(to), mem));
+                }
+            }
+        }
+        ret.extend(loads);
+        ret
+    }
+
+    /// assigns registers to the temporaries that the inserted moves need (e.g. to address
+    /// a spill slot with a large offset): they get registers that are free where they live
+    fn allocate_new_temps(&mut self) {
+        let mut new_temps = LinkedHashSet::new();
+        {
+            let mc = self.cf.mc();
+            for i in 0..mc.number_of_insts() {
+                for temp in mc
+                    .get_inst_reg_uses(i)
+                    .into_iter()
+                    .chain(mc.get_inst_reg_defines(i).into_iter())
+                {
+                    if temp >= MACHINE_ID_END && !self.assignments.contains_key(&temp) {
+                        new_temps.insert(temp);
+                    }
+                }
+            }
+        }
+        if new_temps.is_empty() {
+            return;
+        }
+
+        let usable_regs = usable_regs_by_group();
+        let (intervals, _) = build_intervals(self.cf, self.func);
+        for temp in new_temps.iter() {
+            let interval = intervals.get(temp).unwrap();
+            let reg = usable_regs
+                .get(&interval.group)
+                .unwrap()
+                .iter()
+                .find(|&&reg| {
+                    let used_by_code = match intervals.get(&reg) {
+                        Some(fixed) => fixed.intersects(interval),
+                        None => false,
+                    };
+                    !used_by_code
+                        && !intervals.iter().any(|(other, other_interval)| {
+                            self.assignments.get(other) == Some(&reg)
+                                && other_interval.intersects(interval)
+                        })
+                })
+                .map(|x| *x);
+            match reg {
+                Some(reg) => {
+                    trace!("assign {} to {:?}", reg, interval);
+                    self.assignments.insert(*temp, reg);
+                }
+                None => panic!("cannot find a register for {:?}", interval),
+            }
+        }
+    }
+
+    pub fn spills(&self) -> Vec<MuID> {
+        self.spill_slots.keys().map(|x| *x).collect()
+    }
+
+    pub fn get_assignments(&self) -> LinkedHashMap<MuID, MuID> {
+        self.assignments.clone()
+    }
+
+    pub fn get_spill_scratch_temps(&self) -> LinkedHashMap<MuID, MuID> {
+        self.spill_scratch_temps.clone()
+    }
+}
+
+/// usable registers of each group, in the order that we prefer them
+fn usable_regs_by_group() -> LinkedHashMap<backend::RegGroup, Vec<MuID>> {
+    let mut ret: LinkedHashMap<backend::RegGroup, Vec<MuID>> = LinkedHashMap::new();
+    ret.insert(backend::RegGroup::GPR, vec![]);
+    ret.insert(backend::RegGroup::FPR, vec![]);
+    for reg in backend::all_usable_regs().iter() {
+        let reg_id = reg.extract_ssa_id().unwrap();
+        let group = backend::pick_group_for_reg(reg_id);
+        ret.get_mut(&group).unwrap().push(reg_id);
+    }
+    // temporaries of GPREX are assigned general purpose registers
+    // (as number_of_usable_regs_in_group() counts them)
+    let gprs = ret.get(&backend::RegGroup::GPR).unwrap().clone();
+    ret.insert(backend::RegGroup::GPREX, gprs);
+    ret
+}
@@ -17,15 +17,204 @@
 //! and expose RegisterAllocation (which implements CompilerPass) to the compiler.
 //! Outside of this module, the compiler should not assume a specific register
 //! allocation algorithm.
-//! We currently implemented graph coloring. We may have other algorithms in
-//! the future.
+//! We currently implemented graph coloring and linear scan. Graph coloring is used
+//! by default, linear scan is faster to run (which suits JIT and baseline compilation).
 
 /// a graph coloring implementation (mostly based on Appel's compiler book).
 pub mod graph_coloring;
+/// a linear scan implementation (interval bin packing with lifetime holes, and interval splitting).
+pub mod linear_scan;
+/// computes stack maps for callsites, which the GC uses to find references in frames.
+mod stack_map;
 /// a register allocation validation pass. Design is discussed in Issue #19.
 /// This pass is controlled by --disable-regalloc-validate option
 /// (currently disabled for all cases due to bugs)
 mod validate;
 
-/// exposing graph coloring register allocation pass.
-pub use compiler::backend::reg_alloc::graph_coloring::RegisterAllocation;
+use ast::ir::*;
+use compiler::backend::init_machine_regs_for_func;
+use compiler::backend::is_callee_saved;
+use compiler::backend::reg_alloc::graph_coloring::GraphColoring;
+use compiler::backend::reg_alloc::linear_scan::LinearScan;
+use compiler::machine_code::CompiledFunction;
+use compiler::CompilerPass;
+use std::any::Any;
+use std::collections::HashSet;
+use utils::LinkedHashMap;
+use vm::VM;
+
+/// register allocation algorithms
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegAllocAlgorithm {
+    GraphColoring,
+    LinearScan,
+}
+
+/// the register allocation pass
+pub struct RegisterAllocation {
+    name: &'static str,
+    /// the algorithm to use (None: as the VM options say)
+    algorithm: Option<RegAllocAlgorithm>,
+}
+
+impl CompilerPass for RegisterAllocation {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let algorithm = match self.algorithm {
+            Some(algorithm) => algorithm,
+            None => {
+                if vm.vm_options.flag_regalloc_linear_scan {
+                    RegAllocAlgorithm::LinearScan
+                } else {
+                    RegAllocAlgorithm::GraphColoring
+                }
+            }
+        };
+        self.regalloc(algorithm, vm, func);
+    }
+}
+
+impl RegisterAllocation {
+    /// creates a register allocation pass that uses the algorithm in the VM options
+    pub fn new() -> RegisterAllocation {
+        RegisterAllocation {
+            name: "Register Allocation",
+            algorithm: None,
+        }
+    }
+
+    /// creates a register allocation pass that uses the given algorithm
+    pub fn new_with_algorithm(algorithm: RegAllocAlgorithm) -> RegisterAllocation {
+        RegisterAllocation {
+            name: "Register Allocation",
+            algorithm: Some(algorithm),
+        }
+    }
+
+    fn regalloc(&mut self, algorithm: RegAllocAlgorithm, vm: &VM, func: &mut MuFunctionVersion) {
+        // get compiled function
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let mut cf = compiled_funcs.get(&func.id()).unwrap().write().unwrap();
+
+        // initialize machine registers for the function context (we are gonna use them)
+        init_machine_regs_for_func(&mut func.context);
+
+        // if the GC may run, bases of derived pointers need to be live as long as the
+        // derived pointers, so they are in the stack maps
+        if !vm.vm_options.flag_gc_disable_collection {
+            let callsites = get_callsites(vm, func);
+            stack_map::keep_derived_bases_alive(&mut cf, func, &callsites);
+        }
+
+        // a map of register assignment (from temp to machine register), and
+        // a map of spilled temporaries (from scratch temp to spilled temp)
+        let (reg_assignment, spill_scratch_temps) = match algorithm {
+            RegAllocAlgorithm::GraphColoring => {
+                let coloring = GraphColoring::start(func, &mut cf, vm);
+                (
+                    coloring.get_assignments(),
+                    coloring.get_spill_scratch_temps(),
+                )
+            }
+            RegAllocAlgorithm::LinearScan => {
+                let linear_scan = LinearScan::start(func, &mut cf, vm);
+                (
+                    linear_scan.get_assignments(),
+                    linear_scan.get_spill_scratch_temps(),
+                )
+            }
+        };
+
+        finish_regalloc(vm, func, &mut cf, reg_assignment, spill_scratch_temps);
+    }
+}
+
+/// validates the register assignment, computes stack maps, and rewrites the code
+/// with the assigned machine registers
+fn finish_regalloc(
+    vm: &VM,
+    func: &MuFunctionVersion,
+    cf: &mut CompiledFunction,
+    reg_assignment: LinkedHashMap<MuID, MuID>,
+    spill_scratch_temps: LinkedHashMap<MuID, MuID>,
+) {
+    // if we need to validate the results
+    if !vm.vm_options.flag_disable_regalloc_validate {
+        // we use spill scratch temps to validate spilling correctness
+        validate::validate_regalloc(cf, reg_assignment.clone(), spill_scratch_temps.clone());
+    }
+
+    // compute stack maps for callsites, so the GC knows where live references are
+    // (this needs to be done before we replace temporaries with machine registers)
+    {
+        let callsites = get_callsites(vm, func);
+        let stack_maps = stack_map::compute_stack_maps(
+            cf,
+            func,
+            &callsites,
+            &reg_assignment,
+            &spill_scratch_temps,
+        );
+        cf.frame.stack_maps = stack_maps;
+    }
+
+    // use the result to replace temporaries with assigned regs
+    trace!("Replacing Registers...");
+    for (temp, machine_reg) in reg_assignment {
+        trace!("replacing {} with {}", temp, machine_reg);
+        cf.mc_mut().replace_reg(temp, machine_reg);
+        cf.temps.insert(temp, machine_reg);
+    }
+
+    // find out what callee saved registers are used, so we can delete unnecessary savings.
+    // Currently I am only deleting those unnecessary push/pops of callee saved regs, but
+    // I am not deleting the frame slots for them (so the frame size is still larger than
+    // it needs to be).
+    // FIXME: should fix offsets of frame slots, and patch the code. See Issue #47
+    {
+        // all the used callee saved registers
+        let used_callee_saved: Vec<MuID> = {
+            let used_callee_saved: HashSet<MuID> = cf
+                .temps
+                .values()
+                .map(|x| *x)
+                .filter(|x| is_callee_saved(*x))
+                .collect();
+            used_callee_saved.into_iter().collect()
+        };
+
+        // remove unused callee saved registers
+        let removed_callee_saved = cf
+            .mc_mut()
+            .remove_unnecessary_callee_saved(used_callee_saved);
+        for reg in removed_callee_saved {
+            cf.frame.remove_record_for_callee_saved_reg(reg);
+        }
+
+        // patch frame size
+        let frame_size = cf.frame.cur_size();
+        trace!(
+            "patching the code to grow/shrink size of {} bytes",
+            frame_size
+        );
+        cf.mc_mut().patch_frame_size(frame_size);
+    }
+
+    cf.mc().trace_mc();
+}
+
+/// returns the names of all the callsites in the function
+fn get_callsites(vm: &VM, func: &MuFunctionVersion) -> HashSet<MuName> {
+    let callsite_table = vm.callsite_table().read().unwrap();
+    match callsite_table.get(&func.id()) {
+        Some(callsites) => callsites.iter().map(|c| c.name.clone()).collect(),
+        None => HashSet::new(),
+    }
+}
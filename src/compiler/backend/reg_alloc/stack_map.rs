@@ -0,0 +1,337 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module computes stack maps for callsites after register allocation.
+//! A stack map tells the GC which frame slots and callee saved registers hold live
+//! references when the frame is suspended at the callsite.
+//! Values that are live across a call are either in callee saved registers (as the call
+//! destroys all caller saved registers), or spilled to frame slots. For spilled values, we
+//! treat a spill load as a use of the spilled temporary, and a spill store as a define, so
+//! that liveness tells us whether the frame slot holds a live value at a callsite.
+//! Internal references (derived from a reference) are not in stack maps. Before register
+//! allocation, we make the base reference of a derived pointer live wherever the derived
+//! pointer is used (and across a call that the derived pointer is passed to), so the base is
+//! in the stack map whenever the derived pointer is live. Objects that stack roots refer to
+//! are not moved by the GC, so the derived pointers stay valid.
+
+use ast::inst::*;
+use ast::ir::*;
+use compiler::backend::get_callee_saved_offset;
+use compiler::backend::get_color_for_precolored;
+use compiler::backend::is_callee_saved;
+use compiler::frame::StackMap;
+use compiler::machine_code::CompiledFunction;
+use std::collections::{HashMap, HashSet};
+use utils::LinkedHashMap;
+
+/// computes stack maps for the given callsites
+/// (this needs to be done before we replace temporaries with machine registers)
+pub fn compute_stack_maps(
+    cf: &CompiledFunction,
+    func: &MuFunctionVersion,
+    callsites: &HashSet<MuName>,
+    reg_assigned: &LinkedHashMap<MuID, MuID>,
+    spill_scratch_temps: &LinkedHashMap<MuID, MuID>,
+) -> HashMap<MuName, StackMap> {
+    let mut ret = HashMap::new();
+    if callsites.is_empty() {
+        return ret;
+    }
+
+    let liveout = liveness_with_spills(cf, spill_scratch_temps);
+
+    let mc = cf.mc();
+    for i in 0..mc.number_of_insts() {
+        let callsite = match mc.is_label(i) {
+            Some(label) => {
+                if callsites.contains(&label) {
+                    label
+                } else {
+                    continue;
+                }
+            }
+            None => continue,
+        };
+        // the callsite label is right after the call instruction
+        let call = match mc.get_last_inst(i) {
+            Some(call) => call,
+            None => panic!("cannot find call instruction for callsite {}", callsite),
+        };
+        let call_defines = mc.get_inst_reg_defines(call);
+
+        let mut stack_map = StackMap::default();
+        for temp in liveout[call].iter() {
+            if *temp < MACHINE_ID_END || call_defines.contains(temp) {
+                continue;
+            }
+            // internal references are kept alive through their bases
+            let (is_ref, is_tagref) = match func.context.get_value(*temp) {
+                Some(entry) => {
+                    let ty = entry.ty();
+                    (ty.is_heap_reference() && !ty.is_iref(), ty.is_tagref64())
+                }
+                None => (false, false),
+            };
+            if !is_ref && !is_tagref {
+                continue;
+            }
+
+            if let Some(reg) = reg_assigned.get(temp) {
+                let reg = get_color_for_precolored(*reg);
+                assert!(
+                    is_callee_saved(reg),
+                    "reference {} is live across callsite {}, but is in a caller saved register",
+                    temp,
+                    callsite
+                );
+                let offset = get_callee_saved_offset(reg);
+                if is_ref {
+                    stack_map.ref_callee_saved.push(offset);
+                } else {
+                    stack_map.tagref_callee_saved.push(offset);
+                }
+            } else if let Some(slot) = cf.frame.allocated.get(temp) {
+                if is_ref {
+                    stack_map.ref_slots.push(slot.offset);
+                } else {
+                    stack_map.tagref_slots.push(slot.offset);
+                }
+            } else {
+                panic!(
+                    "reference {} is live across callsite {}, but it is neither in a register \
+                     nor in a frame slot",
+                    temp, callsite
+                );
+            }
+        }
+        stack_map.ref_slots.sort();
+        stack_map.ref_slots.dedup();
+        stack_map.ref_callee_saved.sort();
+        stack_map.ref_callee_saved.dedup();
+        stack_map.tagref_slots.sort();
+        stack_map.tagref_slots.dedup();
+        stack_map.tagref_callee_saved.sort();
+        stack_map.tagref_callee_saved.dedup();
+
+        trace!("stack map for {}: {}", callsite, stack_map);
+        ret.insert(callsite, stack_map);
+    }
+
+    ret
+}
+
+/// makes the base reference of every derived pointer live wherever the derived pointer is
+/// used, and across the calls it is passed to (called before register allocation)
+pub fn keep_derived_bases_alive(
+    cf: &mut CompiledFunction,
+    func: &MuFunctionVersion,
+    callsites: &HashSet<MuName>,
+) {
+    let bases = find_derived_bases(func);
+    if bases.is_empty() {
+        return;
+    }
+
+    let n_insts = cf.mc().number_of_insts();
+    // the derived pointers used since the last call in current block
+    let mut used_derived: Vec<MuID> = vec![];
+    let mut cur_block = None;
+    for i in 0..n_insts {
+        let block = cf.mc().get_block_for_inst(i);
+        if block != cur_block {
+            used_derived.clear();
+            cur_block = block;
+        }
+
+        let label = cf.mc().is_label(i);
+        if let Some(label) = label {
+            if callsites.contains(&label) {
+                // the callee may use the derived pointers that we passed as arguments, so
+                // their bases need to be live after the call
+                let next = cf.mc().get_next_inst(i);
+                if let Some(next) = next {
+                    if cf.mc().get_block_for_inst(next) == cur_block {
+                        for derived in used_derived.iter() {
+                            add_pseudo_use(cf, next, bases[derived]);
+                        }
+                    }
+                }
+                used_derived.clear();
+            }
+            continue;
+        }
+
+        let uses = cf.mc().get_inst_reg_uses(i);
+        for temp in uses {
+            if let Some(base) = bases.get(&temp) {
+                add_pseudo_use(cf, i, *base);
+                used_derived.push(temp);
+            }
+        }
+    }
+}
+
+fn add_pseudo_use(cf: &mut CompiledFunction, inst: usize, reg: MuID) {
+    let uses = cf.mc().get_inst_reg_uses(inst);
+    if !uses.contains(&reg) {
+        trace!("keep base {} alive at inst {}", reg, inst);
+        cf.mc_mut().add_pseudo_use(inst, reg);
+    }
+}
+
+/// finds derived pointers (SSA variables) in the function, and the references (SSA variables)
+/// they are derived from
+fn find_derived_bases(func: &MuFunctionVersion) -> HashMap<MuID, MuID> {
+    let mut derived = vec![];
+    for block in func.content.as_ref().unwrap().blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            if let TreeNode_::Instruction(ref inst) = node.v {
+                if inst.is_derived_pointer() {
+                    if let Some(ref values) = inst.value {
+                        derived.push((values[0].id(), node.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    // a derived pointer may be derived from another derived pointer, so we iterate until
+    // we know all the bases
+    let mut bases = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(id, ref node) in derived.iter() {
+            if bases.contains_key(&id) {
+                continue;
+            }
+            if let Some(base) = find_base(node, &bases) {
+                bases.insert(id, base);
+                changed = true;
+            }
+        }
+    }
+    bases
+}
+
+/// returns the reference that the derived pointer (or the reference) node comes from
+fn find_base(node: &TreeNode, bases: &HashMap<MuID, MuID>) -> Option<MuID> {
+    match node.v {
+        TreeNode_::Value(ref pv) => {
+            if !pv.is_reg() {
+                None
+            } else if pv.ty.is_ref() {
+                Some(pv.id())
+            } else {
+                bases.get(&pv.id()).cloned()
+            }
+        }
+        TreeNode_::Instruction(ref inst) => {
+            let ref ops = inst.ops;
+            match inst.v {
+                Instruction_::GetIRef(op) => find_base(&ops[op], bases),
+                Instruction_::GetFieldIRef {
+                    is_ptr: false,
+                    base,
+                    ..
+                }
+                | Instruction_::GetElementIRef {
+                    is_ptr: false,
+                    base,
+                    ..
+                }
+                | Instruction_::ShiftIRef {
+                    is_ptr: false,
+                    base,
+                    ..
+                }
+                | Instruction_::GetVarPartIRef {
+                    is_ptr: false,
+                    base,
+                } => find_base(&ops[base], bases),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// computes liveout for every instruction. Spilled temporaries are live from
+/// their spill stores to their spill loads.
+fn liveness_with_spills(
+    cf: &CompiledFunction,
+    spill_scratch_temps: &LinkedHashMap<MuID, MuID>,
+) -> Vec<HashSet<MuID>> {
+    let mc = cf.mc();
+    let n_insts = mc.number_of_insts();
+
+    let mut uses: Vec<Vec<MuID>> = Vec::with_capacity(n_insts);
+    let mut defines: Vec<Vec<MuID>> = Vec::with_capacity(n_insts);
+    for i in 0..n_insts {
+        let mut inst_uses = mc.get_inst_reg_uses(i);
+        let mut inst_defines = mc.get_inst_reg_defines(i);
+
+        // a spill load defines a scratch temporary, and a spill store uses one (the spill
+        // info is the frame slot, the scratch temporaries tell us which temporary it holds)
+        if mc.is_spill_load(i).is_some() {
+            for scratch in inst_defines.iter() {
+                if let Some(spilled) = spill_scratch_temps.get(scratch) {
+                    inst_uses.push(*spilled);
+                }
+            }
+        }
+        if mc.is_spill_store(i).is_some() {
+            for scratch in inst_uses.iter() {
+                if let Some(spilled) = spill_scratch_temps.get(scratch) {
+                    inst_defines.push(*spilled);
+                }
+            }
+        }
+
+        uses.push(inst_uses);
+        defines.push(inst_defines);
+    }
+
+    let mut livein: Vec<HashSet<MuID>> = vec![HashSet::new(); n_insts];
+    let mut liveout: Vec<HashSet<MuID>> = vec![HashSet::new(); n_insts];
+
+    // iterate until we reach a fixed point
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for i in (0..n_insts).rev() {
+            // out[n] <- union of in[s] for every successor s
+            let mut out = HashSet::new();
+            for succ in mc.get_succs(i).iter() {
+                out.extend(livein[*succ].iter().cloned());
+            }
+
+            // in[n] <- use[n] + (out[n] - def[n])
+            let mut inn: HashSet<MuID> = out
+                .iter()
+                .filter(|x| !defines[i].contains(x))
+                .cloned()
+                .collect();
+            inn.extend(uses[i].iter().cloned());
+
+            if inn != livein[i] || out != liveout[i] {
+                changed = true;
+                livein[i] = inn;
+                liveout[i] = out;
+            }
+        }
+    }
+
+    liveout
+}
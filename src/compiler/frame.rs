@@ -54,6 +54,8 @@ pub struct Frame {
     /// mapping from callee saved id (i.e. the position in the list of callee saved registers)
     /// and offset from the frame pointer
     pub callee_saved: HashMap<isize, ByteOffset>,
+    /// stack maps for each callsite in this function (computed after register allocation)
+    pub stack_maps: HashMap<MuName, StackMap>,
 }
 
 rodal_struct!(Frame {
@@ -62,7 +64,8 @@ rodal_struct!(Frame {
     argument_by_reg,
     argument_by_stack,
     allocated,
-    callee_saved
+    callee_saved,
+    stack_maps
 });
 
 impl fmt::Display for Frame {
@@ -73,6 +76,10 @@ impl fmt::Display for Frame {
             writeln!(f, "    {}", slot).unwrap();
         }
         writeln!(f, "  exception callsites:").unwrap();
+        writeln!(f, "  stack maps:").unwrap();
+        for (callsite, stack_map) in self.stack_maps.iter() {
+            writeln!(f, "    {}: {}", callsite, stack_map).unwrap();
+        }
         writeln!(f, "  cur offset: {}", self.cur_offset).unwrap();
         writeln!(f, "}}")
     }
@@ -88,6 +95,7 @@ impl Frame {
             argument_by_stack: HashMap::new(),
             callee_saved: HashMap::new(),
             allocated: HashMap::new(),
+            stack_maps: HashMap::new(),
         }
     }
 
@@ -241,3 +249,48 @@ impl FrameSlot {
         })
     }
 }
+
+/// StackMap records where live references are kept at a callsite, so that the GC can find
+/// them precisely when the frame is on the stack.
+/// Internal references are not recorded: the compiler keeps their base references alive
+/// as long as they are (and objects that stack roots refer to do not move)
+#[derive(Clone, Debug, Default)]
+pub struct StackMap {
+    /// offsets (from the frame pointer) of frame slots that hold live references
+    pub ref_slots: Vec<ByteOffset>,
+    /// callee saved registers that hold live references
+    /// (identified in the same way as Frame.callee_saved, i.e. by get_callee_saved_offset())
+    pub ref_callee_saved: Vec<isize>,
+    /// offsets of frame slots that hold live tagref64 values (which may hold a reference)
+    pub tagref_slots: Vec<ByteOffset>,
+    /// callee saved registers that hold live tagref64 values
+    pub tagref_callee_saved: Vec<isize>,
+}
+
+rodal_struct!(StackMap {
+    ref_slots,
+    ref_callee_saved,
+    tagref_slots,
+    tagref_callee_saved
+});
+
+impl StackMap {
+    /// returns true if there is no live reference at this callsite
+    pub fn is_empty(&self) -> bool {
+        self.ref_slots.is_empty()
+            && self.ref_callee_saved.is_empty()
+            && self.tagref_slots.is_empty()
+            && self.tagref_callee_saved.is_empty()
+    }
+}
+
+impl fmt::Display for StackMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ref slots: {:?}, ref callee saved: {:?}, tagref slots: {:?}, \
+             tagref callee saved: {:?}",
+            self.ref_slots, self.ref_callee_saved, self.tagref_slots, self.tagref_callee_saved
+        )
+    }
+}
@@ -16,6 +16,7 @@ use ast::ir::*;
 use ast::ptr::*;
 use compiler;
 use compiler::backend::mc_loopanalysis::MCLoopAnalysisResult;
+use compiler::backend::peephole_opt::RegLiveness;
 use compiler::frame::*;
 use rodal;
 use runtime::resolve_symbol;
@@ -121,19 +122,21 @@ impl CompiledFunction {
     }
 }
 
-// Contains information about a callsite (needed for exception handling)
+// Contains information about a callsite (needed for exception handling and stack scanning)
 rodal_named!(CompiledCallsite);
 pub struct CompiledCallsite {
     pub exceptional_destination: Option<Address>,
     pub stack_args_size: usize,
     pub callee_saved_registers: Arc<HashMap<isize, isize>>,
     pub function_version: MuID,
+    pub stack_map: StackMap,
 }
 impl CompiledCallsite {
     pub fn new(
         callsite: &Callsite,
         fv: MuID,
         callee_saved_registers: Arc<HashMap<isize, isize>>,
+        stack_map: StackMap,
     ) -> CompiledCallsite {
         CompiledCallsite {
             exceptional_destination: match &callsite.exception_destination {
@@ -143,6 +146,7 @@ impl CompiledCallsite {
             stack_args_size: callsite.stack_arg_size,
             callee_saved_registers: callee_saved_registers,
             function_version: fv,
+            stack_map: stack_map,
         }
     }
 }
@@ -241,6 +245,9 @@ pub trait MachineCode {
     fn replace_define_tmp_for_inst(&mut self, from: MuID, to: MuID, inst: usize);
     /// replace a temp that is used in the inst with another temp
     fn replace_use_tmp_for_inst(&mut self, from: MuID, to: MuID, inst: usize);
+    /// adds a use of a temp to the inst that does not appear in the code, so that the temp
+    /// is live at the inst (e.g. to keep the base of a derived pointer alive)
+    fn add_pseudo_use(&mut self, inst: usize, reg: MuID);
     /// replace destination for an unconditional branch instruction
     fn replace_branch_dest(&mut self, inst: usize, old_succ: usize, new_dest: &str, succ: usize);
     /// set an instruction as nop
@@ -252,6 +259,33 @@ pub trait MachineCode {
     /// patch frame size
     fn patch_frame_size(&mut self, size: usize);
 
+    // functions for instruction scheduling
+
+    /// gets the scheduling info of the instruction at the given index, returns None if the
+    /// instruction cannot be reordered (labels, branches, calls, barriers, etc.)
+    /// By default, no instruction can be reordered
+    fn get_inst_sched_info(&self, _index: usize) -> Option<SchedInfo> {
+        None
+    }
+    /// reorders instructions: the instructions at [start, start + order.len()) are replaced
+    /// by the instructions at the given indices (order is a permutation of the range)
+    /// By default, the instructions stay in place (as no instruction can be reordered)
+    fn reorder_insts(&mut self, _start: usize, _order: Vec<usize>) {}
+
+    // functions for peephole optimization
+
+    /// tries the target specific peephole patterns at the given index (the liveness tells
+    /// which registers are live after register allocation), returns the name of the pattern
+    /// that rewrote the code, or None if no pattern matches
+    /// By default, there is no target specific pattern
+    fn try_peephole_patterns(
+        &mut self,
+        _index: usize,
+        _liveness: &RegLiveness,
+    ) -> Option<&'static str> {
+        None
+    }
+
     fn as_any(&self) -> &Any;
 
     fn build_cfg(&self) -> MachineCFG {
@@ -340,6 +374,38 @@ pub trait MachineCode {
     }
 }
 
+/// the kind of work a machine instruction does (which determines its latency)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstClass {
+    /// integer arithmetic/logical operations and moves
+    Alu,
+    /// integer multiplication
+    Mul,
+    /// integer division
+    Div,
+    /// memory load
+    Load,
+    /// memory store
+    Store,
+    /// floating point arithmetic, conversions and moves
+    FpAlu,
+    /// floating point multiplication (and fused multiply-add)
+    FpMul,
+    /// floating point division and square root
+    FpDiv,
+}
+
+/// what the instruction scheduler needs to know about an instruction
+/// (besides its register uses and defines)
+#[derive(Clone, Copy, Debug)]
+pub struct SchedInfo {
+    pub class: InstClass,
+    /// does the instruction read the condition flags?
+    pub reads_flags: bool,
+    /// does the instruction write the condition flags?
+    pub writes_flags: bool,
+}
+
 pub struct MachineCFG {
     inner: LinkedHashMap<MuName, MachineCFGNode>,
 }
@@ -93,17 +93,25 @@ impl CompilerPolicy {
     pub fn new(passes: Vec<Box<CompilerPass>>) -> CompilerPolicy {
         CompilerPolicy { passes: passes }
     }
-}
 
-impl Default for CompilerPolicy {
-    fn default() -> Self {
+    /// the default passes, with the given register allocation pass
+    /// (e.g. to use linear scan for JIT and baseline compilation)
+    pub fn with_register_allocation(
+        regalloc: backend::reg_alloc::RegisterAllocation,
+    ) -> CompilerPolicy {
         let mut passes: Vec<Box<CompilerPass>> = vec![];
         passes.push(Box::new(passes::UIRGen::new("")));
         passes.push(Box::new(passes::DotGen::new(".orig")));
 
         // ir level passes
+        passes.push(Box::new(passes::Profiling::new()));
         passes.push(Box::new(passes::RetSink::new()));
         passes.push(Box::new(passes::Inlining::new()));
+        passes.push(Box::new(passes::EscapeAnalysis::new()));
+        passes.push(Box::new(passes::SCCP::new()));
+        passes.push(Box::new(passes::GVN::new()));
+        passes.push(Box::new(passes::LICM::new()));
+        passes.push(Box::new(passes::DCE::new()));
         passes.push(Box::new(passes::InjectRuntime::new()));
         passes.push(Box::new(passes::DefUse::new()));
         passes.push(Box::new(passes::TreeGen::new()));
@@ -115,7 +123,16 @@ impl Default for CompilerPolicy {
         // compilation
         passes.push(Box::new(backend::inst_sel::InstructionSelection::new()));
         passes.push(Box::new(backend::mc_loopanalysis::MCLoopAnalysis::new()));
-        passes.push(Box::new(backend::reg_alloc::RegisterAllocation::new()));
+        // only the aarch64 backend allows reordering its instructions
+        #[cfg(target_arch = "aarch64")]
+        passes.push(Box::new(backend::inst_sched::InstructionScheduling::new(
+            backend::inst_sched::SchedPosition::PreRegAlloc,
+        )));
+        passes.push(Box::new(regalloc));
+        #[cfg(target_arch = "aarch64")]
+        passes.push(Box::new(backend::inst_sched::InstructionScheduling::new(
+            backend::inst_sched::SchedPosition::PostRegAlloc,
+        )));
 
         // machine code level passes
         passes.push(Box::new(backend::peephole_opt::PeepholeOptimization::new()));
@@ -125,6 +142,12 @@ impl Default for CompilerPolicy {
     }
 }
 
+impl Default for CompilerPolicy {
+    fn default() -> Self {
+        CompilerPolicy::with_register_allocation(backend::reg_alloc::RegisterAllocation::new())
+    }
+}
+
 // rewrite parts of the hprof crates to print via log (instead of print!())
 use self::hprof::ProfileNode;
 use std::rc::Rc;
@@ -19,6 +19,8 @@ use std::any::Any;
 use utils::vec_utils::as_str as vector_as_str;
 use utils::LinkedHashMap;
 use utils::LinkedHashSet;
+use vm::profile;
+use vm::profile::Profile;
 use vm::VM;
 
 pub struct ControlFlowAnalysis {
@@ -54,6 +56,10 @@ impl CompilerPass for ControlFlowAnalysis {
             &mut visited,
             func,
         );
+
+        if let Some(profile) = profile::get_profile(vm) {
+            use_switch_profile(&profile, func);
+        }
     }
 
     #[allow(unused_variables)]
@@ -85,6 +91,57 @@ impl CompilerPass for ControlFlowAnalysis {
     }
 }
 
+/// clears the result of control flow analysis. Passes that run control flow analysis for
+/// their own use and then change the CFG call this, control flow analysis will run again
+pub fn clear_control_flow(f_content: &mut FunctionContent) {
+    for block in f_content.blocks.values_mut() {
+        block.control_flow = ControlFlow::default();
+    }
+    f_content.exception_blocks.clear();
+}
+
+/// SWITCH does not carry branch probabilities, so we use the counts of its edges in the
+/// profile (BRANCH2 gets its probability in the Profiling pass). Blocks created after
+/// profiling have no counts, and their edges keep the default probabilities
+fn use_switch_profile(profile: &Profile, func: &mut MuFunctionVersion) {
+    let fv = func.name();
+    let f_content = func.content.as_mut().unwrap();
+
+    for (id, block) in f_content.blocks.iter_mut() {
+        let last_inst = block.content.as_ref().unwrap().body.last().unwrap().clone();
+        let is_switch = match last_inst.as_inst().v {
+            Switch { .. } => true,
+            _ => false,
+        };
+        if !is_switch {
+            continue;
+        }
+
+        let counts: Vec<Option<u64>> = block
+            .control_flow
+            .succs
+            .iter()
+            .map(|edge| profile.get(&profile::edge_key(&fv, *id, edge.target)))
+            .collect();
+        if counts.iter().any(|count| count.is_none()) {
+            continue;
+        }
+        let total: u64 = counts.iter().map(|count| count.unwrap()).sum();
+        if total == 0 {
+            continue;
+        }
+
+        for (edge, count) in block.control_flow.succs.iter_mut().zip(counts) {
+            edge.probability = count.unwrap() as f32 / total as f32;
+        }
+        trace!(
+            "use profile for switch in {}: {:?}",
+            id,
+            block.control_flow.succs
+        );
+    }
+}
+
 /// if an edge target already appears in the stack, it is a backedge, otherwise forward edge
 fn check_edge_kind(target: MuID, stack: &Vec<MuID>) -> EdgeKind {
     if stack.contains(&target) {
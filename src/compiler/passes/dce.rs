@@ -0,0 +1,466 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::ptr::*;
+use compiler::passes::control_flow::clear_control_flow;
+use compiler::passes::ControlFlowAnalysis;
+use compiler::CompilerPass;
+use std::any::Any;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use vm::VM;
+
+/// Dead code elimination. This pass
+/// * removes blocks that are not reachable from the entry block,
+/// * removes instructions without side effects whose results are never used, and
+/// * removes block parameters that are never used (along with the arguments passed to them).
+///
+/// Liveness is computed optimistically: an SSA variable is live only if it is used by an
+/// instruction with side effects, by a keepalive, or by something live. So dead values that
+/// are only passed around a loop are removed as well.
+///
+/// We use the CFG from control flow analysis to find reachable blocks and predecessors.
+/// Control flow analysis runs again later in the pipeline, so we clear the CFG when we finish.
+///
+/// We only remove parameters of a block if all its predecessors reach it with BRANCH, BRANCH2
+/// or SWITCH. Parameters of the entry block, and of blocks that are reached from other
+/// terminators (such as the resumption of a CALL) are kept.
+pub struct DCE {
+    name: &'static str,
+}
+
+impl DCE {
+    pub fn new() -> DCE {
+        DCE {
+            name: "Dead Code Elimination",
+        }
+    }
+}
+
+impl CompilerPass for DCE {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn start_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // build the CFG
+        ControlFlowAnalysis::new().execute(vm, func);
+    }
+
+    #[allow(unused_variables)]
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let mut f_content = func.content.take().unwrap();
+
+        remove_unreachable_blocks(&mut f_content);
+
+        let preds = get_preds(&f_content);
+        let live = compute_liveness(&f_content, &preds);
+
+        remove_dead_insts(&mut f_content, &live);
+        remove_dead_params(&mut f_content, &preds, &live);
+
+        func.content = Some(f_content);
+    }
+
+    #[allow(unused_variables)]
+    fn finish_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // the CFG is out of date, control flow analysis will rebuild it
+        let f_content = func.content.as_mut().unwrap();
+        clear_control_flow(f_content);
+
+        debug!("after DCE: {:?}", f_content);
+    }
+}
+
+/// removes blocks without predecessors (other than the entry block)
+fn remove_unreachable_blocks(f_content: &mut FunctionContent) {
+    let entry = f_content.entry;
+    let unreachable: Vec<MuID> = f_content
+        .blocks
+        .iter()
+        .filter(|&(id, block)| *id != entry && block.control_flow.preds.is_empty())
+        .map(|(id, _)| *id)
+        .collect();
+
+    for id in unreachable {
+        trace!("remove unreachable block {}", id);
+        f_content.blocks.remove(&id);
+    }
+}
+
+/// returns the predecessors of each block (without duplicates)
+fn get_preds(f_content: &FunctionContent) -> LinkedHashMap<MuID, Vec<MuID>> {
+    let mut ret = LinkedHashMap::new();
+    for (id, block) in f_content.blocks.iter() {
+        let mut preds = vec![];
+        for pred in block.control_flow.preds.iter() {
+            if !preds.contains(pred) {
+                preds.push(*pred);
+            }
+        }
+        ret.insert(*id, preds);
+    }
+    ret
+}
+
+/// returns the destinations of a BRANCH, BRANCH2 or SWITCH
+/// (None if the instruction is another terminator)
+fn get_branch_dests(inst: &Instruction) -> Option<Vec<&Destination>> {
+    match inst.v {
+        Instruction_::Branch1(ref dest) => Some(vec![dest]),
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => Some(vec![true_dest, false_dest]),
+        Instruction_::Switch {
+            ref default,
+            ref branches,
+            ..
+        } => {
+            let mut ret = vec![default];
+            for &(_, ref dest) in branches.iter() {
+                ret.push(dest);
+            }
+            Some(ret)
+        }
+        _ => None,
+    }
+}
+
+/// returns the last instruction of a block
+fn last_inst(block: &Block) -> &Instruction {
+    block
+        .content
+        .as_ref()
+        .unwrap()
+        .body
+        .last()
+        .unwrap()
+        .as_inst()
+}
+
+/// returns blocks whose parameters we may remove
+fn get_removable_param_blocks(
+    f_content: &FunctionContent,
+    preds: &LinkedHashMap<MuID, Vec<MuID>>,
+) -> LinkedHashSet<MuID> {
+    let mut ret = LinkedHashSet::new();
+    for (id, block_preds) in preds.iter() {
+        if *id == f_content.entry {
+            continue;
+        }
+        let all_branches = block_preds
+            .iter()
+            .all(|pred| get_branch_dests(last_inst(f_content.get_block(*pred))).is_some());
+        if all_branches {
+            ret.insert(*id);
+        }
+    }
+    ret
+}
+
+/// computes the set of live SSA variables
+fn compute_liveness(
+    f_content: &FunctionContent,
+    preds: &LinkedHashMap<MuID, Vec<MuID>>,
+) -> LinkedHashSet<MuID> {
+    let removable_param_blocks = get_removable_param_blocks(f_content, preds);
+
+    // SSA variables that each SSA variable depends on
+    let mut deps: LinkedHashMap<MuID, Vec<MuID>> = LinkedHashMap::new();
+    // SSA variables that are live regardless of their uses
+    let mut roots: Vec<MuID> = vec![];
+
+    {
+        let mut add_dep = |value: MuID, op: &P<TreeNode>| {
+            if let Some(id) = op.extract_ssa_id() {
+                if !deps.contains_key(&value) {
+                    deps.insert(value, vec![]);
+                }
+                deps.get_mut(&value).unwrap().push(id);
+            }
+        };
+
+        for (id, block) in f_content.blocks.iter() {
+            let block_content = block.content.as_ref().unwrap();
+
+            if !removable_param_blocks.contains(id) {
+                for arg in block_content.args.iter() {
+                    roots.push(arg.id());
+                }
+            }
+
+            for node in block_content.body.iter() {
+                let inst = node.as_inst();
+
+                if !inst.has_side_effect() {
+                    // results depend on all the operands
+                    if let Some(ref values) = inst.value {
+                        for value in values.iter() {
+                            for op in inst.ops.iter() {
+                                add_dep(value.id(), op);
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                match get_branch_dests(inst) {
+                    Some(dests) => {
+                        // the operands passed to a block parameter are live if the parameter
+                        // is live. Other operands are always live
+                        let mut passed = vec![];
+                        for dest in dests {
+                            let target = f_content.get_block(dest.target.id());
+                            let params = &target.content.as_ref().unwrap().args;
+                            for (param, arg) in params.iter().zip(dest.args.iter()) {
+                                if let &DestArg::Normal(i) = arg {
+                                    add_dep(param.id(), &inst.ops[i]);
+                                    passed.push(i);
+                                }
+                            }
+                        }
+
+                        let mut used = vec![];
+                        match inst.v {
+                            Instruction_::Branch2 { cond, .. } => used.push(cond),
+                            Instruction_::Switch {
+                                cond, ref branches, ..
+                            } => {
+                                used.push(cond);
+                                for &(value, _) in branches.iter() {
+                                    used.push(value);
+                                }
+                            }
+                            _ => {}
+                        }
+
+                        for (i, op) in inst.ops.iter().enumerate() {
+                            if used.contains(&i) || !passed.contains(&i) {
+                                if let Some(id) = op.extract_ssa_id() {
+                                    roots.push(id);
+                                }
+                            }
+                        }
+                    }
+                    None => {
+                        for op in inst.ops.iter() {
+                            if let Some(id) = op.extract_ssa_id() {
+                                roots.push(id);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref keepalives) = block_content.keepalives {
+                for value in keepalives.iter() {
+                    roots.push(value.id());
+                }
+            }
+        }
+    }
+
+    let mut live = LinkedHashSet::new();
+    let mut work_list = roots;
+    while let Some(id) = work_list.pop() {
+        if live.contains(&id) {
+            continue;
+        }
+        live.insert(id);
+        if let Some(ids) = deps.get(&id) {
+            work_list.extend(ids.iter().cloned());
+        }
+    }
+
+    live
+}
+
+/// removes instructions without side effects whose results are not live
+fn remove_dead_insts(f_content: &mut FunctionContent, live: &LinkedHashSet<MuID>) {
+    for block in f_content.blocks.values_mut() {
+        let block_content = block.content.as_mut().unwrap();
+        block_content.body.retain(|node| {
+            let inst = node.as_inst();
+            if inst.has_side_effect() {
+                return true;
+            }
+            let is_live = match inst.value {
+                Some(ref values) => values.iter().any(|v| live.contains(&v.id())),
+                None => false,
+            };
+            if !is_live {
+                trace!("remove dead instruction {}", node);
+            }
+            is_live
+        });
+    }
+}
+
+/// removes block parameters that are not live, and the arguments passed to them
+fn remove_dead_params(
+    f_content: &mut FunctionContent,
+    preds: &LinkedHashMap<MuID, Vec<MuID>>,
+    live: &LinkedHashSet<MuID>,
+) {
+    let removable_param_blocks = get_removable_param_blocks(f_content, preds);
+
+    // indices of the parameters that we remove for each block
+    let mut dead_params: LinkedHashMap<MuID, Vec<usize>> = LinkedHashMap::new();
+    for id in removable_param_blocks.iter() {
+        let block_content = f_content.get_block_mut(*id).content.as_mut().unwrap();
+        let dead: Vec<usize> = block_content
+            .args
+            .iter()
+            .enumerate()
+            .filter(|&(_, arg)| !live.contains(&arg.id()))
+            .map(|(i, _)| i)
+            .collect();
+        if dead.is_empty() {
+            continue;
+        }
+
+        let args = block_content.args.clone();
+        block_content.args = args
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, ref arg)| {
+                if dead.contains(&i) {
+                    trace!("remove dead block parameter {}", arg);
+                    false
+                } else {
+                    true
+                }
+            })
+            .map(|(_, arg)| arg)
+            .collect();
+        dead_params.insert(*id, dead);
+    }
+
+    if dead_params.is_empty() {
+        return;
+    }
+
+    // rewrite the branches to those blocks
+    let mut branch_blocks = LinkedHashSet::new();
+    for id in dead_params.keys() {
+        for pred in preds.get(id).unwrap().iter() {
+            branch_blocks.insert(*pred);
+        }
+    }
+
+    for id in branch_blocks.iter() {
+        let block_content = f_content.get_block_mut(*id).content.as_mut().unwrap();
+        let new_node = {
+            let node = block_content.body.last().unwrap();
+            let new_inst = remove_dest_args(node.as_inst(), &dead_params);
+            let new_node = TreeNode::new_inst(new_inst);
+            trace!("rewrite {} to {}", node, new_node);
+            new_node
+        };
+        block_content.body.pop();
+        block_content.body.push(new_node);
+    }
+}
+
+/// returns a copy of a BRANCH, BRANCH2 or SWITCH without the arguments to removed parameters.
+/// Operands that are no longer used are removed as well.
+fn remove_dest_args(
+    inst: &Instruction,
+    dead_params: &LinkedHashMap<MuID, Vec<usize>>,
+) -> Instruction {
+    let mut new_ops = vec![];
+    // maps the old operand index to the new one
+    let mut new_index: LinkedHashMap<OpIndex, OpIndex> = LinkedHashMap::new();
+    let v = {
+        let mut use_op = |i: OpIndex| -> OpIndex {
+            if let Some(j) = new_index.get(&i) {
+                return *j;
+            }
+            new_ops.push(inst.ops[i].clone());
+            new_index.insert(i, new_ops.len() - 1);
+            new_ops.len() - 1
+        };
+
+        let rewrite_dest = |dest: &Destination, use_op: &mut FnMut(OpIndex) -> OpIndex| {
+            let dead = dead_params.get(&dest.target.id());
+            let args = dest
+                .args
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| match dead {
+                    Some(dead) => !dead.contains(&i),
+                    None => true,
+                })
+                .map(|(_, arg)| match arg {
+                    &DestArg::Normal(i) => DestArg::Normal(use_op(i)),
+                    &DestArg::Freshbound(n) => DestArg::Freshbound(n),
+                })
+                .collect();
+            Destination {
+                target: dest.target.clone(),
+                args: args,
+            }
+        };
+
+        match inst.v {
+            Instruction_::Branch1(ref dest) => {
+                Instruction_::Branch1(rewrite_dest(dest, &mut use_op))
+            }
+            Instruction_::Branch2 {
+                cond,
+                ref true_dest,
+                ref false_dest,
+                true_prob,
+            } => Instruction_::Branch2 {
+                cond: use_op(cond),
+                true_dest: rewrite_dest(true_dest, &mut use_op),
+                false_dest: rewrite_dest(false_dest, &mut use_op),
+                true_prob: true_prob,
+            },
+            Instruction_::Switch {
+                cond,
+                ref default,
+                ref branches,
+            } => {
+                let cond = use_op(cond);
+                let default = rewrite_dest(default, &mut use_op);
+                let branches = branches
+                    .iter()
+                    .map(|&(value, ref dest)| (use_op(value), rewrite_dest(dest, &mut use_op)))
+                    .collect();
+                Instruction_::Switch {
+                    cond: cond,
+                    default: default,
+                    branches: branches,
+                }
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    Instruction {
+        hdr: inst.hdr.clone(),
+        value: None,
+        ops: new_ops,
+        v: v,
+    }
+}
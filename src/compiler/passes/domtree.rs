@@ -0,0 +1,201 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use utils::Tree;
+
+/// dominator tree of Mu IR blocks
+/// (the machine code has its own in mc_loopanalysis, which works on machine code blocks)
+pub struct DomTree {
+    tree: Tree<MuID>,
+    idoms: LinkedHashMap<MuID, MuID>,
+    /// reachable blocks in reverse postorder
+    rpo: Vec<MuID>,
+}
+
+impl DomTree {
+    /// computes the dominator tree of a function.
+    /// This uses the CFG from control flow analysis, so it needs to run after
+    /// ControlFlowAnalysis. Blocks that are not reachable from the entry are not in the tree.
+    pub fn new(f_content: &FunctionContent) -> DomTree {
+        let rpo = compute_rpo(f_content);
+        let idoms = compute_immediate_dominators(f_content, &rpo);
+
+        let mut tree = Tree::new(f_content.entry);
+        for block in rpo.iter() {
+            if let Some(idom) = idoms.get(block) {
+                tree.insert(*idom, *block);
+            }
+        }
+
+        trace!("---IR domtree---");
+        trace!("{:?}", tree);
+
+        DomTree {
+            tree: tree,
+            idoms: idoms,
+            rpo: rpo,
+        }
+    }
+
+    /// returns the entry block
+    pub fn root(&self) -> MuID {
+        *self.tree.root()
+    }
+
+    /// returns the immediate dominator of a block (None for the entry block)
+    pub fn idom(&self, block: MuID) -> Option<MuID> {
+        self.idoms.get(&block).cloned()
+    }
+
+    /// returns the blocks that a block immediately dominates
+    pub fn children(&self, block: MuID) -> Vec<MuID> {
+        if self.tree.has_children(&block) {
+            self.tree.get_children(&block).iter().cloned().collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// does block a dominate block b?
+    pub fn dominates(&self, a: MuID, b: MuID) -> bool {
+        let mut cur = b;
+        loop {
+            if cur == a {
+                return true;
+            }
+            match self.idoms.get(&cur) {
+                Some(idom) => cur = *idom,
+                None => return false,
+            }
+        }
+    }
+
+    /// is the block reachable from the entry?
+    pub fn is_reachable(&self, block: MuID) -> bool {
+        self.rpo.contains(&block)
+    }
+
+    /// returns reachable blocks in reverse postorder
+    pub fn rpo(&self) -> &Vec<MuID> {
+        &self.rpo
+    }
+
+    /// returns reachable blocks in dominator tree preorder
+    pub fn preorder(&self) -> Vec<MuID> {
+        let mut ret = vec![];
+        let mut work_list = vec![self.root()];
+        while let Some(block) = work_list.pop() {
+            ret.push(block);
+            let mut children = self.children(block);
+            children.reverse();
+            work_list.extend(children);
+        }
+        ret
+    }
+}
+
+/// returns reachable blocks in reverse postorder
+fn compute_rpo(f_content: &FunctionContent) -> Vec<MuID> {
+    let mut postorder = vec![];
+    let mut visited = LinkedHashSet::new();
+    // (block, the index of the next successor to visit)
+    let mut stack: Vec<(MuID, usize)> = vec![(f_content.entry, 0)];
+    visited.insert(f_content.entry);
+
+    while let Some((block, i)) = stack.pop() {
+        let ref succs = f_content.get_block(block).control_flow.succs;
+        if i < succs.len() {
+            stack.push((block, i + 1));
+            let succ = succs[i].target;
+            if !visited.contains(&succ) {
+                visited.insert(succ);
+                stack.push((succ, 0));
+            }
+        } else {
+            postorder.push(block);
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+/// computes immediate dominators with the iterative algorithm in
+/// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy
+fn compute_immediate_dominators(
+    f_content: &FunctionContent,
+    rpo: &Vec<MuID>,
+) -> LinkedHashMap<MuID, MuID> {
+    let entry = f_content.entry;
+    let mut rpo_index: LinkedHashMap<MuID, usize> = LinkedHashMap::new();
+    for (i, block) in rpo.iter().enumerate() {
+        rpo_index.insert(*block, i);
+    }
+
+    // the entry is its own dominator while we compute
+    let mut doms: LinkedHashMap<MuID, MuID> = LinkedHashMap::new();
+    doms.insert(entry, entry);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in rpo.iter().skip(1) {
+            let mut new_idom: Option<MuID> = None;
+            for pred in f_content.get_block(*block).control_flow.preds.iter() {
+                if !doms.contains_key(pred) {
+                    // not processed yet
+                    continue;
+                }
+                new_idom = match new_idom {
+                    None => Some(*pred),
+                    Some(idom) => Some(intersect(*pred, idom, &doms, &rpo_index)),
+                };
+            }
+
+            let new_idom = new_idom.unwrap();
+            if doms.get(block) != Some(&new_idom) {
+                doms.insert(*block, new_idom);
+                changed = true;
+            }
+        }
+    }
+
+    doms.remove(&entry);
+    doms
+}
+
+/// finds the nearest common dominator of two blocks
+fn intersect(
+    a: MuID,
+    b: MuID,
+    doms: &LinkedHashMap<MuID, MuID>,
+    rpo_index: &LinkedHashMap<MuID, usize>,
+) -> MuID {
+    let index = |block: MuID| *rpo_index.get(&block).unwrap();
+
+    let mut a = a;
+    let mut b = b;
+    while a != b {
+        while index(a) > index(b) {
+            a = *doms.get(&a).unwrap();
+        }
+        while index(b) > index(a) {
+            b = *doms.get(&b).unwrap();
+        }
+    }
+    a
+}
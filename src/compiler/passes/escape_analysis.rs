@@ -0,0 +1,836 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::ptr::*;
+use ast::types::*;
+use compiler::passes::control_flow::clear_control_flow;
+use compiler::passes::ControlFlowAnalysis;
+use compiler::passes::DomTree;
+use compiler::passes::IRLoopAnalysis;
+use compiler::CompilerPass;
+use std::any::Any;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use vm::VM;
+
+/// Escape analysis. This pass finds the objects allocated by NEW that never leave the
+/// function: the object reference is only used to get internal references, and those are
+/// only used to compute the address of fields/elements, or as the location of LOAD/STORE.
+/// Any other use (e.g. storing the reference, passing it to a call, or returning it) lets
+/// the object escape.
+///
+/// A non-escaping object is replaced in one of two ways:
+/// * scalar replacement: if every access is a constant path to a scalar field, each field
+///   becomes an SSA variable (with block parameters at the joins), and the allocation, the
+///   address computations, and the loads and stores are removed.
+/// * stack allocation: otherwise, if the object has no traced fields, is small, and is not
+///   allocated in a loop, the NEW is replaced with an ALLOCA. The slot has no GC header.
+pub struct EscapeAnalysis {
+    name: &'static str,
+}
+
+impl EscapeAnalysis {
+    pub fn new() -> EscapeAnalysis {
+        EscapeAnalysis {
+            name: "Escape Analysis",
+        }
+    }
+}
+
+/// objects larger than this (in bytes) are not allocated on the stack
+const MAX_STACK_ALLOC_SIZE: usize = 256;
+
+impl CompilerPass for EscapeAnalysis {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn start_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // build the CFG for the dominator tree
+        ControlFlowAnalysis::new().execute(vm, func);
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        if vm.vm_options.flag_disable_escape_analysis {
+            info!("escape analysis is disabled");
+            return;
+        }
+
+        let mut f_content = func.content.take().unwrap();
+
+        let rewrite = {
+            let domtree = DomTree::new(&f_content);
+            let loop_analysis = IRLoopAnalysis::new(&f_content, &domtree);
+            let usage = analyse_usage(&f_content, &domtree);
+            let frontiers = dominance_frontiers(&f_content, &domtree);
+
+            let mut rewrite = Rewrite::new();
+            let mut renaming = Renaming {
+                f_content: &f_content,
+                domtree: &domtree,
+                usage: &usage,
+                alloc_vars: LinkedHashMap::new(),
+                vars: LinkedHashMap::new(),
+                var_zeros: vec![],
+                var_types: vec![],
+                block_params: LinkedHashMap::new(),
+            };
+
+            for (&id, alloc) in usage.allocs.iter() {
+                if alloc.escapes {
+                    trace!("EA: {} escapes", id);
+                    continue;
+                }
+
+                if renaming.add_allocation(id, &frontiers, vm) {
+                    debug!("EA: replace {} with scalars", id);
+                    continue;
+                }
+
+                let ty_info = vm.get_backend_type_info(alloc.ty.id());
+                if !alloc.ty.is_traced()
+                    && ty_info.size <= MAX_STACK_ALLOC_SIZE
+                    && 16 % ty_info.alignment == 0
+                    && loop_analysis.loop_depth(alloc.block) == 0
+                {
+                    debug!("EA: allocate {} on the stack", id);
+                    rewrite.add_stack_slot(id, alloc, &usage);
+                }
+            }
+
+            if !renaming.alloc_vars.is_empty() {
+                let init = renaming.var_zeros.clone();
+                renaming.visit_block(domtree.root(), init, &mut rewrite, &mut func.context, vm);
+            }
+            rewrite
+        };
+
+        if !rewrite.is_empty() {
+            rewrite.apply(&mut f_content);
+        }
+
+        func.content = Some(f_content);
+    }
+
+    #[allow(unused_variables)]
+    fn finish_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        clear_control_flow(func.content.as_mut().unwrap());
+
+        debug!("after EA: {:?}", func.content.as_ref().unwrap());
+    }
+}
+
+/// an object allocated by NEW
+struct Allocation {
+    /// the NEW instruction
+    hdr: MuEntityHeader,
+    /// the block of the NEW instruction
+    block: MuID,
+    /// the allocated type
+    ty: P<MuType>,
+    /// whether the object (or a reference into it) leaves the function
+    escapes: bool,
+}
+
+/// a LOAD or STORE into an object
+struct Access {
+    /// the NEW instruction of the object
+    alloc: MuID,
+    /// the field/element indices from the object to the accessed location
+    /// (None if an element index is not constant)
+    path: Option<Vec<usize>>,
+    /// the block of the access
+    block: MuID,
+    is_store: bool,
+}
+
+/// an address computation into an object
+struct Address {
+    /// the NEW instruction of the object
+    alloc: MuID,
+    /// the result, if it is a GETIREF of the object reference
+    obj_iref: Option<P<Value>>,
+}
+
+/// how the objects allocated in a function are used
+struct Usage {
+    allocs: LinkedHashMap<MuID, Allocation>,
+    /// LOAD/STORE instructions into the objects
+    accesses: LinkedHashMap<MuID, Access>,
+    /// GETIREF/GETFIELDIREF/GETELEMIREF instructions into the objects
+    addresses: LinkedHashMap<MuID, Address>,
+}
+
+/// how an instruction uses a reference into an object
+enum UseKind {
+    /// computes a reference to a location with the given path
+    Derive(Option<Vec<usize>>),
+    /// loads from or stores into the location
+    Access,
+    Escape,
+}
+
+fn analyse_usage(f_content: &FunctionContent, domtree: &DomTree) -> Usage {
+    let mut usage = Usage {
+        allocs: LinkedHashMap::new(),
+        accesses: LinkedHashMap::new(),
+        addresses: LinkedHashMap::new(),
+    };
+    // SSA variables that are references into the objects: (NEW instruction, path)
+    let mut derived: LinkedHashMap<MuID, (MuID, Option<Vec<usize>>)> = LinkedHashMap::new();
+
+    // visit definitions before uses, then the unreachable blocks (any use in them escapes)
+    let mut blocks = domtree.preorder();
+    for &id in f_content.blocks.keys() {
+        if !domtree.is_reachable(id) {
+            blocks.push(id);
+        }
+    }
+
+    for block_id in blocks {
+        let reachable = domtree.is_reachable(block_id);
+        let block_content = f_content.get_block(block_id).content.as_ref().unwrap();
+
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+
+            if let Instruction_::New(ref ty) = inst.v {
+                let result = inst.value.as_ref().unwrap()[0].id();
+                usage.allocs.insert(
+                    inst.id(),
+                    Allocation {
+                        hdr: inst.hdr.clone(),
+                        block: block_id,
+                        ty: ty.clone(),
+                        escapes: !reachable,
+                    },
+                );
+                derived.insert(result, (inst.id(), Some(vec![])));
+                continue;
+            }
+
+            for (i, op) in inst.ops.iter().enumerate() {
+                let (alloc, path) = match op.extract_ssa_id() {
+                    Some(id) => match derived.get(&id) {
+                        Some(d) => d.clone(),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+
+                let kind = if reachable {
+                    get_use_kind(inst, i, &path)
+                } else {
+                    UseKind::Escape
+                };
+                match kind {
+                    UseKind::Derive(new_path) => {
+                        let result = inst.value.as_ref().unwrap()[0].clone();
+                        let obj_iref = match inst.v {
+                            Instruction_::GetIRef(_) => Some(result.clone()),
+                            _ => None,
+                        };
+                        derived.insert(result.id(), (alloc, new_path));
+                        usage.addresses.insert(
+                            inst.id(),
+                            Address {
+                                alloc: alloc,
+                                obj_iref: obj_iref,
+                            },
+                        );
+                    }
+                    UseKind::Access => {
+                        let is_store = match inst.v {
+                            Instruction_::Store { .. } => true,
+                            _ => false,
+                        };
+                        usage.accesses.insert(
+                            inst.id(),
+                            Access {
+                                alloc: alloc,
+                                path: path,
+                                block: block_id,
+                                is_store: is_store,
+                            },
+                        );
+                    }
+                    UseKind::Escape => {
+                        trace!("EA: {} escapes at {}", alloc, inst);
+                        usage.allocs.get_mut(&alloc).unwrap().escapes = true;
+                    }
+                }
+            }
+        }
+
+        if let Some(ref keepalives) = block_content.keepalives {
+            for value in keepalives.iter() {
+                if let Some(&(alloc, _)) = derived.get(&value.id()) {
+                    usage.allocs.get_mut(&alloc).unwrap().escapes = true;
+                }
+            }
+        }
+    }
+
+    usage
+}
+
+/// returns how the instruction uses its i-th operand, which refers to a location of an
+/// object with the given path
+fn get_use_kind(inst: &Instruction, i: usize, path: &Option<Vec<usize>>) -> UseKind {
+    match inst.v {
+        Instruction_::GetIRef(op) if op == i => UseKind::Derive(path.clone()),
+        Instruction_::GetFieldIRef {
+            is_ptr: false,
+            base,
+            index,
+        } if base == i => UseKind::Derive(path.clone().map(|mut p| {
+            p.push(index);
+            p
+        })),
+        Instruction_::GetElementIRef {
+            is_ptr: false,
+            base,
+            index,
+        } if base == i => {
+            let index = match inst.ops[index].v {
+                TreeNode_::Value(ref pv) => match pv.v {
+                    Value_::Constant(Constant::Int(c)) => Some(c as usize),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match (path.clone(), index) {
+                (Some(mut p), Some(index)) => {
+                    p.push(index);
+                    UseKind::Derive(Some(p))
+                }
+                _ => UseKind::Derive(None),
+            }
+        }
+        Instruction_::Load {
+            is_ptr: false,
+            mem_loc,
+            ..
+        } if mem_loc == i => UseKind::Access,
+        Instruction_::Store {
+            is_ptr: false,
+            mem_loc,
+            value,
+            ..
+        } if mem_loc == i && value != i => UseKind::Access,
+        _ => UseKind::Escape,
+    }
+}
+
+/// returns the type of the location at the path in the type
+fn get_path_type(ty: &P<MuType>, path: &[usize]) -> Option<P<MuType>> {
+    let mut ty = ty.clone();
+    for &index in path {
+        let next = match ty.v {
+            MuType_::Struct(_) => ty.get_field_ty(index),
+            MuType_::Array(ref elem_ty, len) if index < len => Some(elem_ty.clone()),
+            _ => None,
+        };
+        match next {
+            Some(next) => ty = next,
+            None => return None,
+        }
+    }
+    Some(ty)
+}
+
+/// returns the initial (zero) value of a freshly allocated location of the type, if it can
+/// be kept in an SSA variable
+fn get_zero_value(ty: &P<MuType>, vm: &VM) -> Option<P<Value>> {
+    let c = match ty.v {
+        MuType_::Int(len) if len <= 64 => Constant::Int(0),
+        MuType_::UPtr(_) => Constant::Int(0),
+        MuType_::Float => Constant::Float(0f32),
+        MuType_::Double => Constant::Double(0f64),
+        MuType_::Ref(_) | MuType_::IRef(_) => Constant::NullRef,
+        _ => return None,
+    };
+    Some(P(Value {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        ty: ty.clone(),
+        v: Value_::Constant(c),
+    }))
+}
+
+/// returns the targets of a branch instruction (None for other instructions)
+fn get_branch_targets(inst: &Instruction) -> Option<Vec<MuID>> {
+    match inst.v {
+        Instruction_::Branch1(ref dest) => Some(vec![dest.target.id()]),
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => Some(vec![true_dest.target.id(), false_dest.target.id()]),
+        Instruction_::Switch {
+            ref default,
+            ref branches,
+            ..
+        } => {
+            let mut ret = vec![default.target.id()];
+            for &(_, ref dest) in branches.iter() {
+                ret.push(dest.target.id());
+            }
+            Some(ret)
+        }
+        _ => None,
+    }
+}
+
+/// computes the dominance frontier of every reachable block
+/// (Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm")
+fn dominance_frontiers(
+    f_content: &FunctionContent,
+    domtree: &DomTree,
+) -> LinkedHashMap<MuID, LinkedHashSet<MuID>> {
+    let mut ret = LinkedHashMap::new();
+    for &block in domtree.rpo().iter() {
+        ret.insert(block, LinkedHashSet::new());
+    }
+
+    for &block in domtree.rpo().iter() {
+        let mut preds = LinkedHashSet::new();
+        for &pred in f_content.get_block(block).control_flow.preds.iter() {
+            if domtree.is_reachable(pred) {
+                preds.insert(pred);
+            }
+        }
+        if preds.len() < 2 {
+            continue;
+        }
+
+        let idom = domtree.idom(block);
+        for &pred in preds.iter() {
+            let mut runner = Some(pred);
+            while runner.is_some() && runner != idom {
+                let r = runner.unwrap();
+                ret.get_mut(&r).unwrap().insert(block);
+                runner = domtree.idom(r);
+            }
+        }
+    }
+
+    ret
+}
+
+/// computes the iterated dominance frontier of a set of blocks
+fn iterated_frontier(
+    frontiers: &LinkedHashMap<MuID, LinkedHashSet<MuID>>,
+    blocks: &LinkedHashSet<MuID>,
+) -> LinkedHashSet<MuID> {
+    let mut ret = LinkedHashSet::new();
+    let mut work: Vec<MuID> = blocks.iter().cloned().collect();
+    while let Some(block) = work.pop() {
+        if let Some(frontier) = frontiers.get(&block) {
+            for &b in frontier.iter() {
+                if !ret.contains(&b) {
+                    ret.insert(b);
+                    work.push(b);
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// SSA construction for the fields of the objects that are replaced with scalars
+struct Renaming<'a> {
+    f_content: &'a FunctionContent,
+    domtree: &'a DomTree,
+    usage: &'a Usage,
+    /// the objects to replace, and the variables of their fields
+    alloc_vars: LinkedHashMap<MuID, Vec<usize>>,
+    /// (object, path) -> variable
+    vars: LinkedHashMap<(MuID, Vec<usize>), usize>,
+    var_zeros: Vec<P<TreeNode>>,
+    var_types: Vec<P<MuType>>,
+    /// the variables that each block takes as new parameters
+    block_params: LinkedHashMap<MuID, Vec<usize>>,
+}
+
+impl<'a> Renaming<'a> {
+    /// adds the fields of an object as variables, returns false (and adds nothing) if the
+    /// object cannot be replaced with scalars
+    fn add_allocation(
+        &mut self,
+        alloc_id: MuID,
+        frontiers: &LinkedHashMap<MuID, LinkedHashSet<MuID>>,
+        vm: &VM,
+    ) -> bool {
+        let usage = self.usage;
+        let alloc = usage.allocs.get(&alloc_id).unwrap();
+
+        // the accessed fields, and the blocks that define them
+        let mut fields: LinkedHashMap<Vec<usize>, LinkedHashSet<MuID>> = LinkedHashMap::new();
+        for access in usage.accesses.values() {
+            if access.alloc != alloc_id {
+                continue;
+            }
+            let path = match access.path {
+                Some(ref path) => path.clone(),
+                None => return false,
+            };
+            if !fields.contains_key(&path) {
+                let mut defs = LinkedHashSet::new();
+                defs.insert(alloc.block);
+                fields.insert(path.clone(), defs);
+            }
+            if access.is_store {
+                fields.get_mut(&path).unwrap().insert(access.block);
+            }
+        }
+
+        let mut new_vars = vec![];
+        for (path, defs) in fields.iter() {
+            let ty = match get_path_type(&alloc.ty, path) {
+                Some(ty) => ty,
+                None => return false,
+            };
+            let zero = match get_zero_value(&ty, vm) {
+                Some(zero) => zero,
+                None => return false,
+            };
+
+            let params = iterated_frontier(frontiers, defs);
+            for &block in params.iter() {
+                if !self.can_add_params(block) {
+                    trace!("EA: cannot add parameters to block {}", block);
+                    return false;
+                }
+            }
+
+            new_vars.push((path.clone(), ty, zero, params));
+        }
+
+        let mut vars = vec![];
+        for (path, ty, zero, params) in new_vars {
+            let var = self.var_types.len();
+            self.var_types.push(ty);
+            self.var_zeros.push(TreeNode::new_value(zero));
+            self.vars.insert((alloc_id, path), var);
+            for &block in params.iter() {
+                if !self.block_params.contains_key(&block) {
+                    self.block_params.insert(block, vec![]);
+                }
+                self.block_params.get_mut(&block).unwrap().push(var);
+            }
+            vars.push(var);
+        }
+        self.alloc_vars.insert(alloc_id, vars);
+        true
+    }
+
+    /// returns true if we can add parameters to the block, i.e. it is not the entry, and all
+    /// its predecessors are reachable and jump to it with branch instructions
+    fn can_add_params(&self, block: MuID) -> bool {
+        if block == self.domtree.root() {
+            return false;
+        }
+        let f_content = self.f_content;
+        for &pred in f_content.get_block(block).control_flow.preds.iter() {
+            if !self.domtree.is_reachable(pred) {
+                return false;
+            }
+            let pred_content = f_content.get_block(pred).content.as_ref().unwrap();
+            let last_inst = pred_content.body.last().unwrap().as_inst();
+            if get_branch_targets(last_inst).is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// renames the variables in the block and the blocks it dominates. `current` is the
+    /// current value of every variable
+    fn visit_block(
+        &mut self,
+        block_id: MuID,
+        mut current: Vec<P<TreeNode>>,
+        rewrite: &mut Rewrite,
+        f_context: &mut FunctionContext,
+        vm: &VM,
+    ) {
+        trace!("EA: rename in block {}", block_id);
+
+        if let Some(params) = self.block_params.get(&block_id) {
+            let mut new_params = vec![];
+            for &var in params.iter() {
+                let value = new_ssa(f_context, self.var_types[var].clone(), vm);
+                current[var] = TreeNode::new_value(value.clone());
+                new_params.push(value);
+            }
+            rewrite.new_params.insert(block_id, new_params);
+        }
+
+        let f_content = self.f_content;
+        let usage = self.usage;
+        let block_content = f_content.get_block(block_id).content.as_ref().unwrap();
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+            let id = inst.id();
+
+            if let Some(vars) = self.alloc_vars.get(&id) {
+                // the fields of a new object are zero
+                for &var in vars.iter() {
+                    current[var] = self.var_zeros[var].clone();
+                }
+                rewrite.removed.insert(id);
+                continue;
+            }
+
+            if let Some(address) = usage.addresses.get(&id) {
+                if self.alloc_vars.contains_key(&address.alloc) {
+                    rewrite.removed.insert(id);
+                }
+                continue;
+            }
+
+            if let Some(access) = usage.accesses.get(&id) {
+                if !self.alloc_vars.contains_key(&access.alloc) {
+                    continue;
+                }
+                let path = access.path.as_ref().unwrap().clone();
+                let var = *self.vars.get(&(access.alloc, path)).unwrap();
+                match inst.v {
+                    Instruction_::Load { .. } => {
+                        let result = inst.value.as_ref().unwrap()[0].id();
+                        rewrite.replacement.insert(result, current[var].clone());
+                    }
+                    Instruction_::Store { value, .. } => {
+                        current[var] = get_replaced_op(&inst.ops[value], &rewrite.replacement);
+                    }
+                    _ => unreachable!(),
+                }
+                rewrite.removed.insert(id);
+            }
+        }
+
+        // pass the current values to the successors that take them
+        let last_inst = block_content.body.last().unwrap().as_inst();
+        if let Some(targets) = get_branch_targets(last_inst) {
+            for target in targets {
+                if let Some(params) = self.block_params.get(&target) {
+                    let args = params.iter().map(|&var| current[var].clone()).collect();
+                    rewrite.new_args.insert((block_id, target), args);
+                }
+            }
+        }
+
+        for child in self.domtree.children(block_id) {
+            self.visit_block(child, current.clone(), rewrite, f_context, vm);
+        }
+    }
+}
+
+/// the changes to a function
+struct Rewrite {
+    /// instructions to remove
+    removed: LinkedHashSet<MuID>,
+    /// instructions to replace with another instruction
+    replaced_insts: LinkedHashMap<MuID, P<TreeNode>>,
+    /// SSA variables to replace, with the operands that replace them
+    replacement: LinkedHashMap<MuID, P<TreeNode>>,
+    /// new parameters of blocks
+    new_params: LinkedHashMap<MuID, Vec<P<Value>>>,
+    /// new arguments of the edges (from, to)
+    new_args: LinkedHashMap<(MuID, MuID), Vec<P<TreeNode>>>,
+}
+
+impl Rewrite {
+    fn new() -> Rewrite {
+        Rewrite {
+            removed: LinkedHashSet::new(),
+            replaced_insts: LinkedHashMap::new(),
+            replacement: LinkedHashMap::new(),
+            new_params: LinkedHashMap::new(),
+            new_args: LinkedHashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.replaced_insts.is_empty()
+    }
+
+    /// replaces the NEW with an ALLOCA, and the GETIREFs of the object with its result
+    fn add_stack_slot(&mut self, alloc_id: MuID, alloc: &Allocation, usage: &Usage) {
+        let mut slot: Option<P<Value>> = None;
+        for (&id, address) in usage.addresses.iter() {
+            if address.alloc != alloc_id || address.obj_iref.is_none() {
+                continue;
+            }
+            let obj_iref = address.obj_iref.as_ref().unwrap();
+            if slot.is_none() {
+                slot = Some(obj_iref.clone());
+            } else {
+                let slot_node = TreeNode::new_value(slot.as_ref().unwrap().clone());
+                self.replacement.insert(obj_iref.id(), slot_node);
+            }
+            self.removed.insert(id);
+        }
+
+        // the ALLOCA (in place of the NEW) defines the result of the first GETIREF
+        let inst = match slot {
+            Some(slot) => Instruction {
+                hdr: alloc.hdr.clone(),
+                value: Some(vec![slot]),
+                ops: vec![],
+                v: Instruction_::AllocA(alloc.ty.clone()),
+            },
+            None => {
+                // the object is never used
+                self.removed.insert(alloc_id);
+                return;
+            }
+        };
+        self.replaced_insts
+            .insert(alloc_id, TreeNode::new_inst(inst));
+    }
+
+    fn apply(&self, f_content: &mut FunctionContent) {
+        for block in f_content.blocks.values_mut() {
+            let block_id = block.id();
+            let block_content = block.content.as_mut().unwrap();
+
+            if let Some(params) = self.new_params.get(&block_id) {
+                block_content.args.extend(params.iter().cloned());
+            }
+
+            let mut new_body = vec![];
+            let n_insts = block_content.body.len();
+            for (i, node) in block_content.body.iter().enumerate() {
+                let inst = node.as_inst();
+                if self.removed.contains(&inst.id()) {
+                    trace!("EA: remove {}", node);
+                    continue;
+                }
+                if let Some(new_node) = self.replaced_insts.get(&inst.id()) {
+                    trace!("EA: replace {} with {}", node, new_node);
+                    new_body.push(new_node.clone());
+                    continue;
+                }
+
+                let mut new_inst = inst.clone();
+                new_inst.ops = inst
+                    .ops
+                    .iter()
+                    .map(|op| get_replaced_op(op, &self.replacement))
+                    .collect();
+                if i == n_insts - 1 {
+                    if let Some(targets) = get_branch_targets(inst) {
+                        let targets = LinkedHashSet::from_vec(targets);
+                        for &target in targets.iter() {
+                            if let Some(args) = self.new_args.get(&(block_id, target)) {
+                                append_dest_args(&mut new_inst, target, args);
+                            }
+                        }
+                    }
+                }
+                new_body.push(TreeNode::new_inst(new_inst));
+            }
+            block_content.body = new_body;
+
+            if let Some(ref mut keepalives) = block_content.keepalives {
+                let mut new_keepalives: Vec<P<Value>> = vec![];
+                for value in keepalives.iter() {
+                    let value = match self.replacement.get(&value.id()) {
+                        Some(node) => node.as_value().clone(),
+                        None => value.clone(),
+                    };
+                    // the fields may be replaced with constants
+                    if value.is_const() || new_keepalives.iter().any(|v| v.id() == value.id()) {
+                        continue;
+                    }
+                    new_keepalives.push(value);
+                }
+                *keepalives = new_keepalives;
+            }
+        }
+    }
+}
+
+/// appends arguments to the destinations of a branch instruction that go to the target
+/// (if there are several such destinations, the arguments are appended to each of them)
+fn append_dest_args(inst: &mut Instruction, target: MuID, args: &Vec<P<TreeNode>>) {
+    let Instruction {
+        ref mut ops,
+        ref mut v,
+        ..
+    } = *inst;
+    let mut append = |dest: &mut Destination| {
+        if dest.target.id() == target {
+            for arg in args.iter() {
+                ops.push(arg.clone());
+                dest.args.push(DestArg::Normal(ops.len() - 1));
+            }
+        }
+    };
+    match *v {
+        Instruction_::Branch1(ref mut dest) => append(dest),
+        Instruction_::Branch2 {
+            ref mut true_dest,
+            ref mut false_dest,
+            ..
+        } => {
+            append(true_dest);
+            append(false_dest);
+        }
+        Instruction_::Switch {
+            ref mut default,
+            ref mut branches,
+            ..
+        } => {
+            append(default);
+            for &mut (_, ref mut dest) in branches.iter_mut() {
+                append(dest);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// returns the operand to use in place of an operand
+fn get_replaced_op(
+    op: &P<TreeNode>,
+    replacement: &LinkedHashMap<MuID, P<TreeNode>>,
+) -> P<TreeNode> {
+    match op.extract_ssa_id() {
+        Some(id) => match replacement.get(&id) {
+            // the replacement is never replaced itself
+            Some(new_op) => new_op.clone(),
+            None => op.clone(),
+        },
+        None => op.clone(),
+    }
+}
+
+fn new_ssa(f_context: &mut FunctionContext, ty: P<MuType>, vm: &VM) -> P<Value> {
+    let id = vm.next_id();
+    let val = P(Value {
+        hdr: MuEntityHeader::unnamed(id),
+        ty: ty,
+        v: Value_::SSAVar(id),
+    });
+    f_context.values.insert(id, SSAVarEntry::new(val.clone()));
+    val
+}
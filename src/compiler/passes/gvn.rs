@@ -0,0 +1,339 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::op::*;
+use ast::ptr::*;
+use compiler::passes::control_flow::clear_control_flow;
+use compiler::passes::ControlFlowAnalysis;
+use compiler::passes::DomTree;
+use compiler::CompilerPass;
+use std::any::Any;
+use utils::LinkedHashMap;
+use vm::VM;
+
+/// Global value numbering (dominator-based). This pass walks the dominator tree, and
+/// keeps a scoped table of the expressions computed in the dominating blocks. If an
+/// instruction computes an expression that is already in the table, its result is replaced
+/// with the earlier one, and the instruction is removed.
+///
+/// Candidates are instructions without side effects that compute one result: arithmetics,
+/// comparisons, conversions, SELECT, and address computations (GETIREF, GETFIELDIREF, etc).
+///
+/// Non-atomic LOADs are candidates within a block. An earlier load from the same location
+/// is available until an instruction that may write memory (e.g. a STORE or a CALL). We do
+/// not have alias analysis, so any such instruction makes all the earlier loads unavailable.
+pub struct GVN {
+    name: &'static str,
+}
+
+impl GVN {
+    pub fn new() -> GVN {
+        GVN {
+            name: "Global Value Numbering",
+        }
+    }
+}
+
+impl CompilerPass for GVN {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn start_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // build the CFG for the dominator tree
+        ControlFlowAnalysis::new().execute(vm, func);
+    }
+
+    #[allow(unused_variables)]
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let mut f_content = func.content.take().unwrap();
+
+        let replacement = {
+            let domtree = DomTree::new(&f_content);
+            let mut numbering = ValueNumbering {
+                f_content: &f_content,
+                domtree: &domtree,
+                replacement: LinkedHashMap::new(),
+                scopes: vec![],
+            };
+            numbering.visit_block(domtree.root());
+            numbering.replacement
+        };
+
+        if !replacement.is_empty() {
+            rewrite_blocks(&mut f_content, &replacement);
+        }
+
+        func.content = Some(f_content);
+    }
+
+    #[allow(unused_variables)]
+    fn finish_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        clear_control_flow(func.content.as_mut().unwrap());
+
+        debug!("after GVN: {:?}", func.content.as_ref().unwrap());
+    }
+}
+
+struct ValueNumbering<'a> {
+    f_content: &'a FunctionContent,
+    domtree: &'a DomTree,
+    /// SSA variables that are redundant, with the operands that replace them
+    replacement: LinkedHashMap<MuID, P<TreeNode>>,
+    /// available expressions, one scope for each block on the dominator tree path
+    scopes: Vec<LinkedHashMap<String, P<TreeNode>>>,
+}
+
+impl<'a> ValueNumbering<'a> {
+    fn visit_block(&mut self, block_id: MuID) {
+        trace!("GVN: visit block {}", block_id);
+        self.scopes.push(LinkedHashMap::new());
+
+        // loads available in this block
+        let mut loads: LinkedHashMap<String, P<TreeNode>> = LinkedHashMap::new();
+
+        let f_content = self.f_content;
+        let block_content = f_content.get_block(block_id).content.as_ref().unwrap();
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+
+            if is_load_candidate(inst) {
+                if let Some(key) = self.get_key(inst) {
+                    let result = inst.value.as_ref().unwrap()[0].clone();
+                    match loads.get(&key) {
+                        Some(available) => {
+                            trace!("GVN: replace {} with {}", result, available);
+                            self.replacement.insert(result.id(), available.clone());
+                            continue;
+                        }
+                        None => {}
+                    }
+                    loads.insert(key, TreeNode::new_value(result));
+                }
+                continue;
+            }
+
+            if may_write_memory(inst) {
+                loads.clear();
+                continue;
+            }
+
+            if !is_candidate(inst) {
+                continue;
+            }
+
+            if let Some(key) = self.get_key(inst) {
+                let result = inst.value.as_ref().unwrap()[0].clone();
+                match self.lookup(&key) {
+                    Some(available) => {
+                        trace!("GVN: replace {} with {}", result, available);
+                        self.replacement.insert(result.id(), available);
+                    }
+                    None => {
+                        self.scopes
+                            .last_mut()
+                            .unwrap()
+                            .insert(key, TreeNode::new_value(result));
+                    }
+                }
+            }
+        }
+
+        for child in self.domtree.children(block_id) {
+            self.visit_block(child);
+        }
+
+        self.scopes.pop();
+    }
+
+    /// finds an available expression in the scopes
+    fn lookup(&self, key: &String) -> Option<P<TreeNode>> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(node) = scope.get(key) {
+                return Some(node.clone());
+            }
+        }
+        None
+    }
+
+    /// returns the key of the expression that the instruction computes. Two instructions with
+    /// the same key compute the same value
+    fn get_key(&self, inst: &Instruction) -> Option<String> {
+        let mut op_keys = vec![];
+        for op in inst.ops.iter() {
+            match get_op_key(&get_replaced_op(op, &self.replacement)) {
+                Some(key) => op_keys.push(key),
+                None => return None,
+            }
+        }
+
+        let result_ty = &inst.value.as_ref().unwrap()[0].ty;
+        let key = match inst.v {
+            Instruction_::BinOp(op, op1, op2) if is_commutative(op) => {
+                let mut operands = vec![op_keys[op1].clone(), op_keys[op2].clone()];
+                operands.sort();
+                format!("{:?} {} ({})", op, result_ty, operands.join(", "))
+            }
+            _ => format!("{:?} {} ({})", inst.v, result_ty, op_keys.join(", ")),
+        };
+        Some(key)
+    }
+}
+
+/// returns the key of an operand (None if it is not a simple value)
+fn get_op_key(op: &P<TreeNode>) -> Option<String> {
+    match op.v {
+        TreeNode_::Value(ref pv) => match pv.v {
+            Value_::SSAVar(id) => Some(format!("%{}", id)),
+            Value_::Constant(ref c) => Some(format!("{}:{:?}", pv.ty, c)),
+            Value_::Global(_) => Some(format!("@{}", pv.id())),
+            Value_::Memory(_) => None,
+        },
+        TreeNode_::Instruction(_) => None,
+    }
+}
+
+/// returns the operand to use in place of an operand
+fn get_replaced_op(
+    op: &P<TreeNode>,
+    replacement: &LinkedHashMap<MuID, P<TreeNode>>,
+) -> P<TreeNode> {
+    match op.extract_ssa_id() {
+        Some(id) => match replacement.get(&id) {
+            // the replacement is never replaced, it is the first computation of the value
+            Some(new_op) => new_op.clone(),
+            None => op.clone(),
+        },
+        None => op.clone(),
+    }
+}
+
+fn is_commutative(op: BinOp) -> bool {
+    match op {
+        BinOp::Add | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor => true,
+        _ => false,
+    }
+}
+
+/// is the instruction a candidate for value numbering?
+fn is_candidate(inst: &Instruction) -> bool {
+    let has_single_result = match inst.value {
+        Some(ref values) => values.len() == 1,
+        None => false,
+    };
+    if !has_single_result || inst.has_side_effect() {
+        return false;
+    }
+
+    match inst.v {
+        Instruction_::BinOp(_, _, _)
+        | Instruction_::CmpOp(_, _, _)
+        | Instruction_::ConvOp { .. }
+        | Instruction_::Select { .. }
+        | Instruction_::GetIRef(_)
+        | Instruction_::GetFieldIRef { .. }
+        | Instruction_::GetElementIRef { .. }
+        | Instruction_::ShiftIRef { .. }
+        | Instruction_::GetVarPartIRef { .. } => true,
+        _ => false,
+    }
+}
+
+/// is the instruction a load that we may replace with an earlier load?
+fn is_load_candidate(inst: &Instruction) -> bool {
+    match inst.v {
+        Instruction_::Load {
+            order: MemoryOrder::NotAtomic,
+            ..
+        } => inst
+            .value
+            .as_ref()
+            .map_or(false, |values| values.len() == 1),
+        _ => false,
+    }
+}
+
+/// may the instruction write memory that an earlier load reads from?
+fn may_write_memory(inst: &Instruction) -> bool {
+    match inst.v {
+        // allocation only writes to new memory
+        Instruction_::New(_)
+        | Instruction_::NewHybrid(_, _)
+        | Instruction_::AllocA(_)
+        | Instruction_::AllocAHybrid(_, _) => false,
+        // atomic loads order memory accesses
+        Instruction_::Load { .. } => !is_load_candidate(inst),
+        _ => inst.has_side_effect(),
+    }
+}
+
+/// removes the redundant instructions, and replaces the uses of their results
+fn rewrite_blocks(f_content: &mut FunctionContent, replacement: &LinkedHashMap<MuID, P<TreeNode>>) {
+    for block in f_content.blocks.values_mut() {
+        let block_content = block.content.as_mut().unwrap();
+
+        let mut new_body = vec![];
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+
+            let is_redundant = match inst.value {
+                Some(ref values) => values.iter().all(|v| replacement.contains_key(&v.id())),
+                None => false,
+            };
+            if is_redundant {
+                trace!("GVN: remove {}", node);
+                continue;
+            }
+
+            let ops: Vec<P<TreeNode>> = inst
+                .ops
+                .iter()
+                .map(|op| get_replaced_op(op, replacement))
+                .collect();
+            if ops
+                .iter()
+                .zip(inst.ops.iter())
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+            {
+                new_body.push(node.clone());
+            } else {
+                let mut new_inst = inst.clone();
+                new_inst.ops = ops;
+                new_body.push(TreeNode::new_inst(new_inst));
+            }
+        }
+        block_content.body = new_body;
+
+        if let Some(ref mut keepalives) = block_content.keepalives {
+            // the replacements are always SSA variables
+            let mut new_keepalives: Vec<P<Value>> = vec![];
+            for value in keepalives.iter() {
+                let value = match replacement.get(&value.id()) {
+                    Some(node) => node.as_value().clone(),
+                    None => value.clone(),
+                };
+                if !new_keepalives.iter().any(|v| v.id() == value.id()) {
+                    new_keepalives.push(value);
+                }
+            }
+            *keepalives = new_keepalives;
+        }
+    }
+}
@@ -23,6 +23,7 @@ use runtime::mm;
 use runtime::mm::*;
 use runtime::thread;
 use std::any::Any;
+use std::collections::HashSet;
 use utils::math;
 use utils::*;
 use vm::VM;
@@ -56,6 +57,17 @@ impl CompilerPass for InjectRuntime {
     }
 
     fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // yieldpoints are only needed if the GC may collect
+        // we insert yieldpoints in the prologue (if the function may call other functions),
+        // and before the terminators of the blocks that have a back edge
+        let (prologue_yieldpoint, backedge_blocks) = if vm.vm_options.flag_gc_disable_collection {
+            (false, HashSet::new())
+        } else {
+            let f_content = func.content.as_ref().unwrap();
+            (has_mu_calls(f_content), find_backedge_sources(f_content))
+        };
+        let entry = func.content.as_ref().unwrap().entry;
+
         // make a clone of the blocks
         let blocks = func.content.as_mut().unwrap().blocks.clone();
         let func_context = &mut func.context;
@@ -65,6 +77,8 @@ impl CompilerPass for InjectRuntime {
         for (_, block) in blocks.into_iter() {
             // get all the instructions of this block, so we can iterate through them
             let body_copy = block.content.as_ref().unwrap().body.clone();
+            let n_insts = body_copy.len();
+            let is_backedge_source = backedge_blocks.contains(&block.id());
 
             // set cur block as current block
             // we may change cur block to some newly generated block, so cur block is mutable
@@ -73,7 +87,21 @@ impl CompilerPass for InjectRuntime {
             // clear the body of current block
             cur_block.clear_insts();
 
-            for node in body_copy {
+            if prologue_yieldpoint && cur_block.id() == entry {
+                let block_after = gen_yieldpoint(&mut cur_block, &mut new_blocks, func_context, vm);
+                new_blocks.push(cur_block);
+                cur_block = block_after;
+            }
+
+            for (i, node) in body_copy.into_iter().enumerate() {
+                if is_backedge_source && i == n_insts - 1 {
+                    // yieldpoint before the terminator (which branches back)
+                    let block_after =
+                        gen_yieldpoint(&mut cur_block, &mut new_blocks, func_context, vm);
+                    new_blocks.push(cur_block);
+                    cur_block = block_after;
+                }
+
                 let inst: &Instruction = node.as_inst();
                 trace!("check instruction: {:?}", inst);
                 match inst.v {
@@ -481,3 +509,403 @@ fn gen_allocation_sequence(
 
     alloc_end
 }
+
+/// does this function call other Mu functions?
+fn has_mu_calls(f_content: &FunctionContent) -> bool {
+    f_content.blocks.values().any(|block| {
+        block
+            .content
+            .as_ref()
+            .unwrap()
+            .body
+            .iter()
+            .any(|node| match node.v {
+                TreeNode_::Instruction(ref inst) => match inst.v {
+                    Instruction_::Call { .. }
+                    | Instruction_::ExprCall { .. }
+                    | Instruction_::TailCall(_) => true,
+                    _ => false,
+                },
+                _ => false,
+            })
+    })
+}
+
+/// returns the targets of the terminator of a block
+fn block_succs(block: &Block) -> Vec<MuID> {
+    let last_inst = block.content.as_ref().unwrap().body.last().unwrap();
+    match last_inst.as_inst().v {
+        Instruction_::Branch1(ref dest) => vec![dest.target.id()],
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => vec![true_dest.target.id(), false_dest.target.id()],
+        Instruction_::Switch {
+            ref default,
+            ref branches,
+            ..
+        } => {
+            let mut ret: Vec<MuID> = branches.iter().map(|&(_, ref d)| d.target.id()).collect();
+            ret.push(default.target.id());
+            ret
+        }
+        Instruction_::Watchpoint {
+            ref disable_dest,
+            ref resume,
+            ..
+        } => {
+            let mut ret = vec![resume.normal_dest.target.id(), resume.exn_dest.target.id()];
+            if let Some(ref dest) = *disable_dest {
+                ret.push(dest.target.id());
+            }
+            ret
+        }
+        Instruction_::WPBranch {
+            ref disable_dest,
+            ref enable_dest,
+            ..
+        } => vec![disable_dest.target.id(), enable_dest.target.id()],
+        Instruction_::Call { ref resume, .. }
+        | Instruction_::CCall { ref resume, .. }
+        | Instruction_::SwapStackExc { ref resume, .. }
+        | Instruction_::ExnInstruction { ref resume, .. } => {
+            vec![resume.normal_dest.target.id(), resume.exn_dest.target.id()]
+        }
+        _ => vec![],
+    }
+}
+
+/// finds the blocks that are sources of back edges (a depth first traversal from the entry,
+/// an edge to a block that is on the traversal stack is a back edge)
+fn find_backedge_sources(f_content: &FunctionContent) -> HashSet<MuID> {
+    fn dfs(
+        cur: MuID,
+        f_content: &FunctionContent,
+        stack: &mut Vec<MuID>,
+        visited: &mut HashSet<MuID>,
+        ret: &mut HashSet<MuID>,
+    ) {
+        stack.push(cur);
+        visited.insert(cur);
+
+        for succ in block_succs(f_content.get_block(cur)) {
+            if stack.contains(&succ) {
+                ret.insert(cur);
+            } else if !visited.contains(&succ) {
+                dfs(succ, f_content, stack, visited, ret);
+            }
+        }
+
+        stack.pop();
+    }
+
+    let mut ret = HashSet::new();
+    dfs(
+        f_content.entry,
+        f_content,
+        &mut vec![],
+        &mut HashSet::new(),
+        &mut ret,
+    );
+    ret
+}
+
+// returns new block after the yieldpoint
+fn gen_yieldpoint(
+    cur_block: &mut Block,
+    new_blocks: &mut Vec<Block>,
+    func_context: &mut FunctionContext,
+    vm: &VM,
+) -> Block {
+    let yieldpoint_id = vm.next_id();
+
+    // tl = GETVMTHREADLOCAL
+    let tmp_tl = func_context.make_temporary(vm.next_id(), UPTR_U8_TYPE.clone());
+    cur_block.append_inst(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_tl.clone_value()]),
+        ops: vec![],
+        v: Instruction_::GetVMThreadLocal,
+    }));
+
+    // tl_u64 = PTRCAST tl
+    let tmp_tl_u64 = func_context.make_temporary(vm.next_id(), UINT64_TYPE.clone());
+    cur_block.append_inst(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_tl_u64.clone_value()]),
+        ops: vec![tmp_tl.clone()],
+        v: Instruction_::ConvOp {
+            operation: ConvOp::PTRCAST,
+            from_ty: UPTR_U8_TYPE.clone(),
+            to_ty: UINT64_TYPE.clone(),
+            operand: 0,
+        },
+    }));
+
+    // is_mu_thread = NE tl_u64 0
+    // (native threads may call Mu functions, they do not have a mutator, and never yield)
+    let tmp_is_mu_thread = func_context.make_temporary(vm.next_id(), UINT1_TYPE.clone());
+    cur_block.append_inst(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_is_mu_thread.clone_value()]),
+        ops: vec![
+            tmp_tl_u64,
+            TreeNode::new_value(Value::make_int64_const(vm.next_id(), 0)),
+        ],
+        v: Instruction_::CmpOp(CmpOp::NE, 0, 1),
+    }));
+
+    // instructions to check if the mutator should yield
+    let mut check_body = vec![];
+
+    // global_ptr_loc = SHIFTIREF tl GLOBAL_PTR_OFFSET
+    let global_ptr_offset = *thread::ALLOCATOR_OFFSET + *GLOBAL_PTR_OFFSET;
+    let tmp_global_ptr_loc = func_context.make_temporary(vm.next_id(), UPTR_U8_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_global_ptr_loc.clone_value()]),
+        ops: vec![
+            tmp_tl.clone(),
+            TreeNode::new_value(Value::make_int64_const(
+                vm.next_id(),
+                global_ptr_offset as u64,
+            )),
+        ],
+        v: Instruction_::ShiftIRef {
+            is_ptr: true,
+            base: 0,
+            offset: 1,
+        },
+    }));
+
+    // global_ptr_loc_u64 = PTRCAST global_ptr_loc
+    let tmp_global_ptr_loc_u64 = func_context.make_temporary(vm.next_id(), UPTR_U64_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_global_ptr_loc_u64.clone_value()]),
+        ops: vec![tmp_global_ptr_loc],
+        v: Instruction_::ConvOp {
+            operation: ConvOp::PTRCAST,
+            from_ty: UPTR_U8_TYPE.clone(),
+            to_ty: UPTR_U64_TYPE.clone(),
+            operand: 0,
+        },
+    }));
+
+    // global_ptr = LOAD global_ptr_loc_u64
+    let tmp_global_ptr = func_context.make_temporary(vm.next_id(), UINT64_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_global_ptr.clone_value()]),
+        ops: vec![tmp_global_ptr_loc_u64],
+        v: Instruction_::Load {
+            is_ptr: true,
+            order: MemoryOrder::NotAtomic,
+            mem_loc: 0,
+        },
+    }));
+
+    // global = PTRCAST global_ptr
+    let tmp_global = func_context.make_temporary(vm.next_id(), UPTR_U8_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_global.clone_value()]),
+        ops: vec![tmp_global_ptr],
+        v: Instruction_::ConvOp {
+            operation: ConvOp::PTRCAST,
+            from_ty: UINT64_TYPE.clone(),
+            to_ty: UPTR_U8_TYPE.clone(),
+            operand: 0,
+        },
+    }));
+
+    // take_yield_loc = SHIFTIREF global TAKE_YIELD_OFFSET
+    let tmp_take_yield_loc = func_context.make_temporary(vm.next_id(), UPTR_U8_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_take_yield_loc.clone_value()]),
+        ops: vec![
+            tmp_global,
+            TreeNode::new_value(Value::make_int64_const(
+                vm.next_id(),
+                *TAKE_YIELD_OFFSET as u64,
+            )),
+        ],
+        v: Instruction_::ShiftIRef {
+            is_ptr: true,
+            base: 0,
+            offset: 1,
+        },
+    }));
+
+    // take_yield = LOAD take_yield_loc
+    let tmp_take_yield = func_context.make_temporary(vm.next_id(), UINT8_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_take_yield.clone_value()]),
+        ops: vec![tmp_take_yield_loc],
+        v: Instruction_::Load {
+            is_ptr: true,
+            order: MemoryOrder::NotAtomic,
+            mem_loc: 0,
+        },
+    }));
+
+    // should_yield = NE take_yield 0
+    let tmp_should_yield = func_context.make_temporary(vm.next_id(), UINT1_TYPE.clone());
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![tmp_should_yield.clone_value()]),
+        ops: vec![
+            tmp_take_yield,
+            TreeNode::new_value(Value::make_int_const_ty(
+                vm.next_id(),
+                UINT8_TYPE.clone(),
+                0,
+            )),
+        ],
+        v: Instruction_::CmpOp(CmpOp::NE, 0, 1),
+    }));
+
+    // yieldpoint_end
+    let yieldpoint_end = {
+        let block_name = Arc::new(format!("yieldpoint:{}:end", yieldpoint_id));
+        let mut block = Block::new(MuEntityHeader::named(vm.next_id(), block_name));
+        block.trace_hint = TraceHint::None;
+        block.content = Some(BlockContent {
+            args: vec![],
+            exn_arg: None,
+            body: vec![],
+            keepalives: None,
+        });
+        block
+    };
+
+    // slowpath - CCALL yieldpoint_slow(mutator), then jumps to yieldpoint_end
+    let slowpath = {
+        let block_name = Arc::new(format!("yieldpoint:{}:slowpath", yieldpoint_id));
+        let mut block = Block::new(MuEntityHeader::named(vm.next_id(), block_name));
+        block.trace_hint = TraceHint::SlowPath;
+        block.content = Some(BlockContent {
+            args: vec![],
+            exn_arg: None,
+            body: {
+                let mutator_offset = *thread::ALLOCATOR_OFFSET;
+                let tmp_mutator_loc =
+                    func_context.make_temporary(vm.next_id(), UPTR_U8_TYPE.clone());
+
+                let func: &entrypoints::RuntimeEntrypoint = &entrypoints::YIELDPOINT_SLOW;
+                let tmp_yieldpoint_slow = TreeNode::new_value(P(Value {
+                    hdr: MuEntityHeader::unnamed(vm.next_id()),
+                    ty: P(MuType::new(
+                        vm.next_id(),
+                        MuType_::UFuncPtr(func.sig.clone()),
+                    )),
+                    v: Value_::Constant(Constant::ExternSym(func.aot.to_relocatable())),
+                }));
+                vec![
+                    // tmp_mutator_loc = SHIFTIREF tmp_tl MUTATOR_OFFSET
+                    TreeNode::new_inst(Instruction {
+                        hdr: MuEntityHeader::unnamed(vm.next_id()),
+                        value: Some(vec![tmp_mutator_loc.clone_value()]),
+                        ops: vec![
+                            tmp_tl.clone(),
+                            TreeNode::new_value(Value::make_int64_const(
+                                vm.next_id(),
+                                mutator_offset as u64,
+                            )),
+                        ],
+                        v: Instruction_::ShiftIRef {
+                            is_ptr: true,
+                            base: 0,
+                            offset: 1,
+                        },
+                    }),
+                    // CCALL yieldpoint_slow(mutator)
+                    TreeNode::new_inst(Instruction {
+                        hdr: MuEntityHeader::unnamed(vm.next_id()),
+                        value: Some(vec![]),
+                        ops: vec![tmp_yieldpoint_slow, tmp_mutator_loc.clone()],
+                        v: Instruction_::ExprCCall {
+                            data: CallData {
+                                func: 0,
+                                args: vec![1],
+                                convention: C_CALL_CONVENTION,
+                            },
+                            is_abort: false,
+                        },
+                    }),
+                    // BRANCH yieldpoint_end
+                    TreeNode::new_inst(Instruction {
+                        hdr: MuEntityHeader::unnamed(vm.next_id()),
+                        value: None,
+                        ops: vec![],
+                        v: Instruction_::Branch1(Destination {
+                            target: yieldpoint_end.hdr.clone(),
+                            args: vec![],
+                        }),
+                    }),
+                ]
+            },
+            keepalives: None,
+        });
+        block
+    };
+
+    // BRANCH2 should_yield slowpath yieldpoint_end
+    check_body.push(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: vec![tmp_should_yield],
+        v: Instruction_::Branch2 {
+            cond: 0,
+            true_dest: Destination {
+                target: slowpath.hdr.clone(),
+                args: vec![],
+            },
+            false_dest: Destination {
+                target: yieldpoint_end.hdr.clone(),
+                args: vec![],
+            },
+            true_prob: 0.01f32,
+        },
+    }));
+
+    let check = {
+        let block_name = Arc::new(format!("yieldpoint:{}:check", yieldpoint_id));
+        let mut block = Block::new(MuEntityHeader::named(vm.next_id(), block_name));
+        block.trace_hint = TraceHint::None;
+        block.content = Some(BlockContent {
+            args: vec![],
+            exn_arg: None,
+            body: check_body,
+            keepalives: None,
+        });
+        block
+    };
+
+    // BRANCH2 is_mu_thread check yieldpoint_end
+    cur_block.append_inst(TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: vec![tmp_is_mu_thread],
+        v: Instruction_::Branch2 {
+            cond: 0,
+            true_dest: Destination {
+                target: check.hdr.clone(),
+                args: vec![],
+            },
+            false_dest: Destination {
+                target: yieldpoint_end.hdr.clone(),
+                args: vec![],
+            },
+            true_prob: 0.99f32,
+        },
+    }));
+
+    new_blocks.push(check);
+    new_blocks.push(slowpath);
+
+    yieldpoint_end
+}
@@ -15,19 +15,86 @@
 use ast::inst::*;
 use ast::ir::*;
 use ast::ptr::*;
+use ast::types::*;
+use compiler::passes::control_flow::clear_control_flow;
+use compiler::passes::ControlFlowAnalysis;
+use compiler::passes::DomTree;
+use compiler::passes::IRLoopAnalysis;
+use vm::profile;
+use vm::profile::Profile;
 use vm::VM;
 
 use compiler::CompilerPass;
 use std::any::Any;
+use std::cmp;
 use std::collections::HashMap;
-
+use std::mem;
+use utils::LinkedHashMap;
+
+/// Function inlining with a cost model.
+///
+/// Every callsite of a known function gets a frequency relative to the entry of the caller:
+/// from the profile (`--profile-use`) if it has counts for the callsite, otherwise from the
+/// loop depth of the callsite. We consider callsites in the order of frequency per
+/// (estimated) instruction of the callee, and inline a callee if it is small enough (hot
+/// callsites allow bigger callees) and the growth of the caller stays within its budget.
+/// Callsites that the profile shows are never executed are only inlined if that does not
+/// grow the caller.
+///
+/// Callsites in inlined code are considered in turn, so we inline through several levels
+/// of calls (up to `MAX_INLINE_DEPTH`), but never inline a function into itself.
+///
+/// If the callsite has an exception clause, exceptions from the inlined code go to its
+/// exceptional destination: `THROW` becomes a branch to the destination, and
+/// non-terminating calls become `CALL`s with the exceptional destination. We do not inline
+/// such a callsite if the callee has other instructions that may throw.
 pub struct Inlining {
     name: &'static str,
 
-    // whether a function version should be inlined
-    should_inline: HashMap<MuID, bool>,
+    // callsites that we have not decided whether to inline
+    callsites: HashMap<MuID, CallsiteInfo>,
+
+    // exceptional destinations that THROW from inlined code may branch to: the block with
+    // the body of the destination, and whether it takes the exception as an argument
+    // (see insert_exn_landing())
+    exn_landings: HashMap<MuID, (MuEntityHeader, bool)>,
+}
+
+/// a callsite in the function that we are compiling
+#[derive(Clone)]
+struct CallsiteInfo {
+    /// the caller, followed by the functions that were inlined to get this callsite
+    inlined_from: Vec<MuID>,
+    /// execution count relative to the caller entry, if we know it from the profile
+    profiled_freq: Option<f32>,
+}
+
+/// where exceptions thrown in inlined code go, if the callsite has an exception clause
+struct ExnRedirect {
+    /// the exceptional destination of the callsite
+    handler: MuEntityHeader,
+    /// arguments to the exceptional destination
+    args: Vec<P<TreeNode>>,
+    /// the landing block for THROW, and whether it takes the exception as an argument
+    /// (None if the callee does not throw)
+    landing: Option<(MuEntityHeader, bool)>,
 }
 
+/// callees with more (estimated) machine instructions are not inlined
+const INLINE_SIZE_LIMIT: usize = 25;
+/// size limit for hot callsites (executed more times than the caller, as the profile
+/// shows or as we expect of callsites in loops)
+const HOT_CALLSITE_INLINE_SIZE_LIMIT: usize = 100;
+/// a caller may grow by its own size with inlining, or by this many (estimated) machine
+/// instructions if it is smaller
+const MIN_GROWTH_BUDGET: usize = 100;
+/// (estimated) machine instructions that we save by not making a call
+const CALL_COST: usize = 5;
+/// we do not inline callsites that come from this many levels of inlining
+const MAX_INLINE_DEPTH: usize = 4;
+/// without a profile, we expect a loop to run this many iterations
+const LOOP_TRIP_COUNT: f32 = 10f32;
+
 impl CompilerPass for Inlining {
     fn name(&self) -> &'static str {
         self.name
@@ -43,8 +110,34 @@ impl CompilerPass for Inlining {
             return;
         }
 
-        if self.check(vm, func) {
-            self.inline(vm, func);
+        if vm.vm_options.flag_profile_instrument {
+            // the profile describes the functions as the client wrote them
+            info!("inlining is disabled when instrumenting");
+            return;
+        }
+
+        self.callsites.clear();
+        self.exn_landings.clear();
+        self.find_callsites(vm, func);
+
+        let mut budget = cmp::max(
+            estimate_insts(func.content.as_ref().unwrap()),
+            MIN_GROWTH_BUDGET,
+        );
+        trace!("growth budget of {}: {} insts", func, budget);
+
+        // inline callsites, and then the callsites that come from inlined code
+        let mut inlined = false;
+        loop {
+            let callsites = self.check(vm, func, &mut budget);
+            if callsites.is_empty() {
+                break;
+            }
+            self.inline(vm, func, callsites);
+            inlined = true;
+        }
+
+        if inlined {
             debug!("after inlining: {:?}", func);
         }
     }
@@ -54,113 +147,130 @@ impl Inlining {
     pub fn new() -> Inlining {
         Inlining {
             name: "Inlining",
-            should_inline: HashMap::new(),
+            callsites: HashMap::new(),
+            exn_landings: HashMap::new(),
         }
     }
 
-    /// checks whether we need to rewrite the function because of inlining
-    fn check(&mut self, vm: &VM, func: &mut MuFunctionVersion) -> bool {
-        debug!("check inline");
-
-        // should_inline will store all the calls from this function,
-        // and whether they should be inlined
-        self.should_inline.clear();
-
-        let mut inline_something = false;
-
-        // check each call from this function
-        for (_, (func_id, has_exc)) in func.get_static_call_edges() {
-            // check a single callsite, whether it should be inlined
-            // the result is returned as boolean, and also written into 'should_inline'
-            let should_inline_this =
-                self.check_should_inline_func(has_exc, func_id, func.func_id, vm);
-            inline_something = inline_something || should_inline_this;
+    /// finds the callsites in the function that we may inline
+    fn find_callsites(&mut self, vm: &VM, func: &MuFunctionVersion) {
+        let profile = profile::get_profile(vm);
+        let fv = func.name();
+
+        for block in func.content.as_ref().unwrap().blocks.values() {
+            for inst in block.content.as_ref().unwrap().body.iter() {
+                let inst = inst.as_inst();
+                if mu_callee(inst).is_some() {
+                    let callsite = inst.id();
+                    let info = CallsiteInfo {
+                        inlined_from: vec![func.func_id],
+                        profiled_freq: profile
+                            .as_ref()
+                            .and_then(|profile| profiled_freq(profile, &fv, callsite)),
+                    };
+                    self.callsites.insert(callsite, info);
+                }
+            }
         }
-
-        inline_something
     }
 
-    /// checks whether we should inline the caller into the callee
-    fn check_should_inline_func(
+    /// decides which of the callsites to inline, and returns them
+    fn check(
         &mut self,
-        has_exc: bool,
-        callee: MuID,
-        caller: MuID,
         vm: &VM,
-    ) -> bool {
-        // recursive call, do not inline
-        if callee == caller {
-            return false;
-        }
-
-        let funcs_guard = vm.funcs().read().unwrap();
-        let func = match funcs_guard.get(&callee) {
-            Some(func) => func.read().unwrap(),
-            None => panic!("callee {} is undeclared", callee),
-        };
-        let fv_id = match func.cur_ver {
-            Some(fv_id) => fv_id,
-            None => {
-                // the funtion is not defined
-                info!("the function is undefined, we cannot inline it. ");
-                return false;
-            }
-        };
+        func: &mut MuFunctionVersion,
+        budget: &mut usize,
+    ) -> HashMap<MuID, CallsiteInfo> {
+        debug!("check inline");
 
-        // if we have checked this callee before, we use the same result
-        // (this is not optimal though. The inline criteria we use at the moment
-        // do not take caller size growth into consideration, so we will
-        // get the same result anyway. )
-        match self.should_inline.get(&fv_id) {
-            Some(flag) => {
-                trace!("func {} should be inlined (checked before)", callee);
-                return *flag;
-            }
-            None => {}
+        if self.callsites.is_empty() {
+            return HashMap::new();
         }
 
-        let fv_guard = vm.func_vers().read().unwrap();
-        let fv = fv_guard.get(&fv_id).unwrap().read().unwrap();
+        let call_edges = func.get_static_call_edges();
+        let loop_depths = inst_loop_depths(vm, func);
+
+        // (callsite, info, frequency, estimated size of the callee)
+        let mut candidates: Vec<(MuID, CallsiteInfo, f32, usize)> = vec![];
+        for (callsite, &(callee, has_exc)) in call_edges.iter() {
+            // each callsite is checked once
+            let info = match self.callsites.remove(callsite) {
+                Some(info) => info,
+                None => continue,
+            };
 
-        // if the function is forced inline, we inline it
-        if fv.force_inline {
-            trace!("func {} is forced as inline function", callee);
-            return true;
+            let size = match check_callee(callee, has_exc, &info, vm) {
+                Some(size) => size,
+                None => continue,
+            };
+            let freq = match info.profiled_freq {
+                Some(freq) => freq,
+                None => {
+                    let depth = loop_depths.get(callsite).cloned().unwrap_or(0);
+                    LOOP_TRIP_COUNT.powi(depth as i32)
+                }
+            };
+            candidates.push((*callsite, info, freq, size));
         }
 
-        // some heuristics here to decide if we should inline the function
-        let n_insts = estimate_insts(&fv);
-        let could_throw = fv.could_throw();
-        let has_tailcall = fv.has_tailcall();
-
-        // simple heuristic here:
-        // * estimated machine insts are fewer than 10 insts
-        // * leaf in call graph (no out calls)
-        // * no throw (otherwise we will need to rearrange catch)
-        let should_inline = n_insts <= 25 && !(has_exc && could_throw) && !has_tailcall;
-
-        trace!("func {} has {} insts (estimated)", callee, n_insts);
-        trace!(
-            "     has exception clause? {}, could throw? {}",
-            has_exc,
-            could_throw
-        );
-        trace!("SO func should be inlined? {}", should_inline);
+        // frequent callsites of small callees first
+        candidates.sort_by(|a, b| {
+            let priority_a = a.2 / (a.3 + 1) as f32;
+            let priority_b = b.2 / (b.3 + 1) as f32;
+            priority_b.partial_cmp(&priority_a).unwrap()
+        });
+
+        let mut ret = HashMap::new();
+        for (callsite, info, freq, size) in candidates {
+            let growth = size.saturating_sub(CALL_COST);
+
+            let should_inline = if freq == 0f32 {
+                trace!("callsite {} is never executed", callsite);
+                growth == 0
+            } else {
+                let size_limit = if freq > 1f32 {
+                    HOT_CALLSITE_INLINE_SIZE_LIMIT
+                } else {
+                    INLINE_SIZE_LIMIT
+                };
+                size <= size_limit && growth <= *budget
+            };
 
-        self.should_inline.insert(callee, should_inline);
+            trace!(
+                "callsite {}: frequency {}, callee has {} insts (estimated)",
+                callsite,
+                freq,
+                size
+            );
+            trace!("SO callsite should be inlined? {}", should_inline);
+
+            if should_inline {
+                *budget -= growth;
+                ret.insert(callsite, info);
+            }
+        }
 
-        should_inline
+        ret
     }
 
-    /// inlines the callee that are marked as 'should inline'
-    fn inline(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+    /// inlines the callees at the given callsites
+    fn inline(
+        &mut self,
+        vm: &VM,
+        func: &mut MuFunctionVersion,
+        callsites: HashMap<MuID, CallsiteInfo>,
+    ) {
         debug!("inlining for Function {}", func);
 
         let call_edges = func.get_static_call_edges();
+        let profile = profile::get_profile(vm);
 
         let f_content = func.content.as_mut().unwrap();
         let ref mut f_context = func.context;
 
+        // THROW in inlined code needs a landing block in the exceptional destination
+        self.insert_exn_landings(vm, f_content, f_context, &callsites, &call_edges);
+
         let mut new_blocks: Vec<Block> = vec![];
 
         for (_, block) in f_content.blocks.iter() {
@@ -174,207 +284,187 @@ impl Inlining {
             for inst in block.content.unwrap().body {
                 trace!("check inst: {}", inst);
                 let inst_id = inst.id();
-                if call_edges.contains_key(&inst_id) {
-                    let call_target = &call_edges.get(&inst_id).unwrap().0;
-                    if self.should_inline.contains_key(call_target)
-                        && *self.should_inline.get(call_target).unwrap()
-                    {
-                        trace!("inserting inlined function at {}", inst);
-
-                        // from TreeNode into Inst (we do not need old TreeNode)
-                        let inst = inst.as_inst();
-
-                        // inline expansion starts here
-
-                        // getting the function being inlined
-                        let inlined_func = call_edges.get(&inst.id()).unwrap().0;
-                        trace!("function being inlined is {}", inlined_func);
-                        let inlined_fvid = match vm.get_cur_version_for_func(inlined_func) {
-                            Some(fvid) => fvid,
-                            None => panic!(
-                                "cannot resolve current version of Func {}, \
-                                 which is supposed to be inlined",
-                                inlined_func
-                            ),
-                        };
-                        let inlined_fvs_guard = vm.func_vers().read().unwrap();
-                        let inlined_fv_lock = inlined_fvs_guard.get(&inlined_fvid).unwrap();
-                        let inlined_fv_guard = inlined_fv_lock.read().unwrap();
-                        let inlined_fv_content = inlined_fv_guard.get_orig_ir().unwrap();
-
-                        trace!(
-                            "orig_content: {:?}",
-                            inlined_fv_guard.get_orig_ir().unwrap()
-                        );
-                        trace!(
-                            "content     : {:?}",
-                            inlined_fv_guard.content.as_ref().unwrap()
-                        );
-                        // creates a new block ID
-                        // which will be the entry block for the inlined function
-                        let new_inlined_entry_hdr =
-                            new_inlined_block_name(inlined_fv_content.get_entry_block().name(), vm);
-                        // change current call instruction to a branch
-                        trace!("turning CALL instruction into a branch");
-                        let ref ops = inst.ops;
-                        match inst.v {
-                            Instruction_::ExprCall { ref data, .. } => {
-                                let arg_nodes: Vec<P<TreeNode>> =
-                                    data.args.iter().map(|x| ops[*x].clone()).collect();
-                                let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
-
-                                let branch = TreeNode::new_inst(Instruction {
-                                    hdr: inst.hdr.clone(),
-                                    value: None,
-                                    ops: arg_nodes.clone(),
-                                    v: Instruction_::Branch1(Destination {
-                                        // this block doesnt exist yet, we will create it later
-                                        target: new_inlined_entry_hdr.clone(),
-                                        args: arg_indices
-                                            .iter()
-                                            .map(|x| DestArg::Normal(*x))
-                                            .collect(),
-                                    }),
-                                });
-                                trace!("branch inst: {}", branch);
-
-                                // add branch to current block
-                                cur_block.content.as_mut().unwrap().body.push(branch);
-
-                                // finish current block
-                                new_blocks.push(cur_block.clone());
-
-                                // creates a new block after inlined part,
-                                // which will receive results from inlined function
-                                let old_name = cur_block.name();
-                                let new_name =
-                                    Arc::new(format!("{}_cont_after_inline_{}", old_name, inst_id));
-                                trace!("create continue block for EXPRCALL/CCALL: {}", &new_name);
-                                cur_block =
-                                    Block::new(MuEntityHeader::named(vm.next_id(), new_name));
-                                cur_block.content = Some(BlockContent {
-                                    args: {
-                                        if inst.value.is_none() {
-                                            vec![]
-                                        } else {
-                                            inst.value.as_ref().unwrap().clone()
-                                        }
-                                    },
-                                    exn_arg: None,
-                                    body: vec![],
-                                    keepalives: None,
-                                });
-
-                                // deal with the inlined function
-                                copy_inline_blocks(
-                                    &mut new_blocks,
-                                    cur_block.hdr.clone(),
-                                    inlined_fv_content,
-                                    new_inlined_entry_hdr,
-                                    vm,
-                                );
-                                copy_inline_context(f_context, &inlined_fv_guard.context);
-                            }
-
-                            Instruction_::Call {
-                                ref data,
-                                ref resume,
-                            } => {
-                                let arg_nodes: Vec<P<TreeNode>> =
-                                    data.args.iter().map(|x| ops[*x].clone()).collect();
-                                let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
-
-                                let branch = Instruction {
-                                    hdr: inst.hdr.clone(),
-                                    value: None,
-                                    ops: arg_nodes,
-                                    v: Instruction_::Branch1(Destination {
-                                        target: new_inlined_entry_hdr.clone(),
-                                        args: arg_indices
-                                            .iter()
-                                            .map(|x| DestArg::Normal(*x))
-                                            .collect(),
-                                    }),
-                                };
-
-                                // add branch to current block
-                                cur_block
-                                    .content
-                                    .as_mut()
-                                    .unwrap()
-                                    .body
-                                    .push(TreeNode::new_inst(branch));
-
-                                // next block
-                                let mut next_block = resume.normal_dest.target.clone();
-
-                                // if normal_dest expects different number of arguments
-                                // other than the inlined function returns, we need
-                                // an intermediate block to pass extra arguments
-                                if resume.normal_dest.args.len()
-                                    != inlined_fv_guard.sig.ret_tys.len()
-                                {
-                                    debug!("need an extra block for passing normal dest arguments");
-                                    let int_block_name =
-                                        Arc::new(format!("inline_{}_arg_pass", inst_id));
-                                    let mut intermediate_block = Block::new(MuEntityHeader::named(
-                                        vm.next_id(),
-                                        int_block_name,
-                                    ));
-
-                                    // branch to normal_dest with normal_dest arguments
-                                    let normal_dest_args =
-                                        resume.normal_dest.get_arguments_as_node(&ops);
-                                    let normal_dest_args_len = normal_dest_args.len();
-
-                                    let branch = Instruction {
-                                        hdr: MuEntityHeader::unnamed(vm.next_id()),
-                                        value: None,
-                                        ops: normal_dest_args,
-                                        v: Instruction_::Branch1(Destination {
-                                            target: resume.normal_dest.target.clone(),
-                                            args: (0..normal_dest_args_len)
-                                                .map(|x| DestArg::Normal(x))
-                                                .collect(),
-                                        }),
-                                    };
-
-                                    intermediate_block.content = Some(BlockContent {
-                                        args: {
-                                            match inst.value {
-                                                Some(ref vec) => vec.clone(),
-                                                None => vec![],
-                                            }
-                                        },
-                                        exn_arg: None,
-                                        body: vec![TreeNode::new_inst(branch)],
-                                        keepalives: None,
-                                    });
-
-                                    trace!("extra block: {:?}", intermediate_block);
-
-                                    next_block = intermediate_block.hdr.clone();
-                                    new_blocks.push(intermediate_block);
-                                }
-
-                                // deal with inlined function
-                                copy_inline_blocks(
-                                    &mut new_blocks,
-                                    next_block.clone(),
-                                    inlined_fv_guard.get_orig_ir().unwrap(),
-                                    new_inlined_entry_hdr.clone(),
-                                    vm,
-                                );
-                                copy_inline_context(f_context, &inlined_fv_guard.context);
-                            }
-
-                            _ => panic!("unexpected callsite: {}", inst),
-                        }
-                    } else {
+                let info = match callsites.get(&inst_id) {
+                    Some(info) => info,
+                    None => {
                         cur_block.content.as_mut().unwrap().body.push(inst.clone());
+                        continue;
                     }
-                } else {
-                    cur_block.content.as_mut().unwrap().body.push(inst.clone());
+                };
+
+                trace!("inserting inlined function at {}", inst);
+
+                // from TreeNode into Inst (we do not need old TreeNode)
+                let inst = inst.as_inst();
+
+                // inline expansion starts here
+
+                // getting the function being inlined
+                let inlined_func = call_edges.get(&inst.id()).unwrap().0;
+                trace!("function being inlined is {}", inlined_func);
+                let inlined_fvid = match vm.get_cur_version_for_func(inlined_func) {
+                    Some(fvid) => fvid,
+                    None => panic!(
+                        "cannot resolve current version of Func {}, \
+                         which is supposed to be inlined",
+                        inlined_func
+                    ),
+                };
+                let inlined_fvs_guard = vm.func_vers().read().unwrap();
+                let inlined_fv_lock = inlined_fvs_guard.get(&inlined_fvid).unwrap();
+                let inlined_fv_guard = inlined_fv_lock.read().unwrap();
+                let inlined_fv_content = inlined_fv_guard.get_orig_ir().unwrap();
+
+                trace!("orig_content: {:?}", inlined_fv_content);
+                // creates a new block ID
+                // which will be the entry block for the inlined function
+                let new_inlined_entry_hdr =
+                    new_inlined_block_name(inlined_fv_content.get_entry_block().name(), vm);
+
+                // change current call instruction to a branch
+                trace!("turning CALL instruction into a branch");
+                let ref ops = inst.ops;
+                let (data, resume) = match inst.v {
+                    Instruction_::ExprCall { ref data, .. } => (data, None),
+                    Instruction_::Call {
+                        ref data,
+                        ref resume,
+                    } => (data, Some(resume)),
+                    _ => panic!("unexpected callsite: {}", inst),
+                };
+
+                let arg_nodes: Vec<P<TreeNode>> =
+                    data.args.iter().map(|x| ops[*x].clone()).collect();
+                let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
+                let branch = TreeNode::new_inst(Instruction {
+                    hdr: inst.hdr.clone(),
+                    value: None,
+                    ops: arg_nodes,
+                    v: Instruction_::Branch1(Destination {
+                        // this block doesnt exist yet, we will create it later
+                        target: new_inlined_entry_hdr.clone(),
+                        args: arg_indices.iter().map(|x| DestArg::Normal(*x)).collect(),
+                    }),
+                });
+                trace!("branch inst: {}", branch);
+
+                // add branch to current block
+                cur_block.content.as_mut().unwrap().body.push(branch);
+
+                let results = match inst.value {
+                    Some(ref vec) => vec.clone(),
+                    None => vec![],
+                };
+
+                // the block that RET in the inlined function branches to, and
+                // where exceptions go
+                let (ret_block, exn) = match resume {
+                    None => {
+                        // creates a new block after inlined part,
+                        // which will receive results from inlined function
+                        let new_name = Arc::new(format!(
+                            "{}_cont_after_inline_{}",
+                            cur_block.name(),
+                            inst_id
+                        ));
+                        trace!("create continue block for EXPRCALL: {}", &new_name);
+                        let mut cont_block =
+                            Block::new(MuEntityHeader::named(vm.next_id(), new_name));
+                        cont_block.content = Some(BlockContent {
+                            args: results,
+                            exn_arg: None,
+                            body: vec![],
+                            keepalives: None,
+                        });
+
+                        // finish current block
+                        new_blocks.push(mem::replace(&mut cur_block, cont_block));
+
+                        (cur_block.hdr.clone(), None)
+                    }
+                    Some(resume) => {
+                        let normal_dest_args = resume.normal_dest.get_arguments_as_node(&ops);
+
+                        // results of the call may be used in normal_dest, so they are
+                        // defined as the arguments of an intermediate block, which then
+                        // passes normal_dest arguments
+                        let ret_block = if results.is_empty() && normal_dest_args.is_empty() {
+                            resume.normal_dest.target.clone()
+                        } else {
+                            debug!("need an extra block for passing normal dest arguments");
+                            let int_block_name = Arc::new(format!("inline_{}_arg_pass", inst_id));
+                            let mut intermediate_block =
+                                Block::new(MuEntityHeader::named(vm.next_id(), int_block_name));
+
+                            let normal_dest_args_len = normal_dest_args.len();
+                            let branch = Instruction {
+                                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                                value: None,
+                                ops: normal_dest_args,
+                                v: Instruction_::Branch1(Destination {
+                                    target: resume.normal_dest.target.clone(),
+                                    args: (0..normal_dest_args_len)
+                                        .map(|x| DestArg::Normal(x))
+                                        .collect(),
+                                }),
+                            };
+
+                            intermediate_block.content = Some(BlockContent {
+                                args: results,
+                                exn_arg: None,
+                                body: vec![TreeNode::new_inst(branch)],
+                                keepalives: None,
+                            });
+
+                            trace!("extra block: {:?}", intermediate_block);
+
+                            let hdr = intermediate_block.hdr.clone();
+                            new_blocks.push(intermediate_block);
+                            hdr
+                        };
+
+                        let exn = ExnRedirect {
+                            handler: resume.exn_dest.target.clone(),
+                            args: resume.exn_dest.get_arguments_as_node(&ops),
+                            landing: self.exn_landings.get(&resume.exn_dest.target.id()).cloned(),
+                        };
+
+                        (ret_block, Some(exn))
+                    }
+                };
+
+                // deal with the inlined function
+                let new_callsites = copy_inline_blocks(
+                    &mut new_blocks,
+                    f_context,
+                    ret_block,
+                    inlined_fv_content,
+                    new_inlined_entry_hdr,
+                    exn.as_ref(),
+                    vm,
+                );
+
+                // callsites in the inlined code will be checked later
+                let inlined_fv = inlined_fv_guard.name();
+                for (new_callsite, old_callsite) in new_callsites {
+                    let mut inlined_from = info.inlined_from.clone();
+                    inlined_from.push(inlined_func);
+
+                    let callee_freq = profile
+                        .as_ref()
+                        .and_then(|profile| profiled_freq(profile, &inlined_fv, old_callsite));
+                    let freq = match (info.profiled_freq, callee_freq) {
+                        (Some(freq), Some(callee_freq)) => Some(freq * callee_freq),
+                        _ => None,
+                    };
+
+                    self.callsites.insert(
+                        new_callsite,
+                        CallsiteInfo {
+                            inlined_from: inlined_from,
+                            profiled_freq: freq,
+                        },
+                    );
                 }
             }
 
@@ -386,6 +476,264 @@ impl Inlining {
             f_content.blocks.insert(blk.id(), blk);
         }
     }
+
+    /// inserts landing blocks in the exceptional destinations of the callsites whose callees
+    /// throw
+    fn insert_exn_landings(
+        &mut self,
+        vm: &VM,
+        f_content: &mut FunctionContent,
+        f_context: &mut FunctionContext,
+        callsites: &HashMap<MuID, CallsiteInfo>,
+        call_edges: &LinkedHashMap<MuID, (MuID, bool)>,
+    ) {
+        let mut handlers = vec![];
+        for block in f_content.blocks.values() {
+            for inst in block.content.as_ref().unwrap().body.iter() {
+                let inst = inst.as_inst();
+                if !callsites.contains_key(&inst.id()) {
+                    continue;
+                }
+                let handler = match inst.get_exception_target() {
+                    Some(handler) => handler,
+                    None => continue,
+                };
+                if self.exn_landings.contains_key(&handler) || handlers.contains(&handler) {
+                    continue;
+                }
+
+                let callee = call_edges.get(&inst.id()).unwrap().0;
+                let fv_id = vm.get_cur_version_for_func(callee).unwrap();
+                let fvs = vm.func_vers().read().unwrap();
+                let fv = fvs.get(&fv_id).unwrap().read().unwrap();
+                if has_inst(
+                    fv.get_orig_ir().unwrap(),
+                    &|inst: &Instruction| match inst.v {
+                        Instruction_::Throw(_) => true,
+                        _ => false,
+                    },
+                ) {
+                    handlers.push(handler);
+                }
+            }
+        }
+
+        for handler in handlers {
+            let landing = insert_exn_landing(vm, f_content, f_context, handler);
+            self.exn_landings.insert(handler, landing);
+        }
+    }
+}
+
+/// checks whether the callee can be inlined at the callsite, and returns its estimated
+/// size in machine instructions
+fn check_callee(callee: MuID, has_exc: bool, info: &CallsiteInfo, vm: &VM) -> Option<usize> {
+    // recursive call, do not inline (this also keeps us from locking the caller)
+    if info.inlined_from.contains(&callee) {
+        trace!("func {} is recursive", callee);
+        return None;
+    }
+    if info.inlined_from.len() > MAX_INLINE_DEPTH {
+        trace!(
+            "callsite is inlined from {} levels of calls",
+            MAX_INLINE_DEPTH
+        );
+        return None;
+    }
+
+    let fv_id = match vm.get_cur_version_for_func(callee) {
+        Some(fv_id) => fv_id,
+        None => {
+            // the funtion is not defined
+            info!("the function is undefined, we cannot inline it. ");
+            return None;
+        }
+    };
+    let fv_guard = vm.func_vers().read().unwrap();
+    let fv = fv_guard.get(&fv_id).unwrap().read().unwrap();
+    let f_content = match fv.get_orig_ir() {
+        Some(f_content) => f_content,
+        None => return None,
+    };
+
+    let has_tailcall = has_inst(f_content, &|inst: &Instruction| match inst.v {
+        Instruction_::TailCall(_) => true,
+        _ => false,
+    });
+    let exn_ok = !has_exc || can_redirect_exceptions(f_content);
+    trace!(
+        "func {}: has tailcall? {}, has exception clause? {}, exceptions can be redirected? {}",
+        callee,
+        has_tailcall,
+        has_exc,
+        exn_ok
+    );
+    if has_tailcall || !exn_ok {
+        return None;
+    }
+
+    // if the function is forced inline, we inline it
+    if fv.force_inline {
+        trace!("func {} is forced as inline function", callee);
+        return Some(0);
+    }
+
+    Some(estimate_insts(f_content))
+}
+
+/// checks whether all exceptions thrown in the function can go to the exceptional
+/// destination of a callsite where it is inlined
+fn can_redirect_exceptions(f_content: &FunctionContent) -> bool {
+    !has_inst(f_content, &|inst: &Instruction| match inst.v {
+        // rewritten when inlined
+        Instruction_::Throw(_) | Instruction_::ExprCall { .. } => false,
+        // allocation throws when out of memory
+        Instruction_::New(_) | Instruction_::NewHybrid(_, _) => true,
+        _ => inst.is_potentially_throwing() && !inst.has_exception_clause(),
+    })
+}
+
+/// returns the function that the instruction calls, if it is a Mu call to a known function
+fn mu_callee(inst: &Instruction) -> Option<MuID> {
+    match inst.v {
+        Instruction_::ExprCall { ref data, .. } | Instruction_::Call { ref data, .. } => {
+            match inst.ops[data.func].v {
+                TreeNode_::Value(ref pv) => match pv.v {
+                    Value_::Constant(Constant::FuncRef(ref func)) => Some(func.id()),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// execution count of a callsite relative to the entry of its function in the profile
+fn profiled_freq(profile: &Profile, fv: &str, callsite: MuID) -> Option<f32> {
+    let entry_count = profile.get(&profile::entry_key(fv));
+    let count = profile.get(&profile::call_key(fv, callsite));
+    match (entry_count, count) {
+        (Some(entry_count), Some(count)) if entry_count > 0 => {
+            Some(count as f32 / entry_count as f32)
+        }
+        _ => None,
+    }
+}
+
+/// returns the loop depth of the block of each instruction
+fn inst_loop_depths(vm: &VM, func: &mut MuFunctionVersion) -> HashMap<MuID, usize> {
+    ControlFlowAnalysis::new().execute(vm, func);
+
+    let mut ret = HashMap::new();
+    {
+        let f_content = func.content.as_ref().unwrap();
+        let domtree = DomTree::new(f_content);
+        let loop_analysis = IRLoopAnalysis::new(f_content, &domtree);
+
+        for (id, block) in f_content.blocks.iter() {
+            let depth = loop_analysis.loop_depth(*id);
+            for inst in block.content.as_ref().unwrap().body.iter() {
+                ret.insert(inst.id(), depth);
+            }
+        }
+    }
+
+    clear_control_flow(func.content.as_mut().unwrap());
+    ret
+}
+
+/// splits an exceptional destination into a block that receives the exception and branches
+/// to a new landing block, which has the original body and takes the exception as a normal
+/// argument. THROW in inlined code branches to the landing block. Returns the landing
+/// block, and whether it takes the exception
+fn insert_exn_landing(
+    vm: &VM,
+    f_content: &mut FunctionContent,
+    f_context: &mut FunctionContext,
+    handler: MuID,
+) -> (MuEntityHeader, bool) {
+    let (landing, takes_exn) = {
+        let block = f_content.get_block_mut(handler);
+        let landing_id = vm.next_id();
+        let landing_hdr = MuEntityHeader::named(
+            landing_id,
+            Arc::new(format!("{}:exn_landing.#{}", block.name(), landing_id)),
+        );
+        let trace_hint = block.trace_hint.clone();
+
+        let content = block.content.as_mut().unwrap();
+        let takes_exn = content.exn_arg.is_some();
+
+        // the destination receives its arguments and the exception as new SSA variables
+        let new_args: Vec<P<Value>> = content
+            .args
+            .iter()
+            .map(|arg| new_ssa(f_context, arg.ty.clone(), vm))
+            .collect();
+        let new_exn_arg = content
+            .exn_arg
+            .as_ref()
+            .map(|arg| new_ssa(f_context, arg.ty.clone(), vm));
+
+        let mut landing_args = content.args.clone();
+        let mut branch_args: Vec<P<TreeNode>> = new_args
+            .iter()
+            .map(|arg| TreeNode::new_value(arg.clone()))
+            .collect();
+        if let Some(ref exn_arg) = content.exn_arg {
+            landing_args.push(exn_arg.clone());
+        }
+        if let Some(ref exn_arg) = new_exn_arg {
+            branch_args.push(TreeNode::new_value(exn_arg.clone()));
+        }
+
+        let n_branch_args = branch_args.len();
+        let branch = Instruction {
+            hdr: MuEntityHeader::unnamed(vm.next_id()),
+            value: None,
+            ops: branch_args,
+            v: Instruction_::Branch1(Destination {
+                target: landing_hdr.clone(),
+                args: (0..n_branch_args).map(|x| DestArg::Normal(x)).collect(),
+            }),
+        };
+
+        let landing = Block {
+            hdr: landing_hdr,
+            content: Some(BlockContent {
+                args: landing_args,
+                exn_arg: None,
+                body: mem::replace(&mut content.body, vec![TreeNode::new_inst(branch)]),
+                keepalives: content.keepalives.take(),
+            }),
+            trace_hint: trace_hint,
+            control_flow: ControlFlow::default(),
+        };
+
+        content.args = new_args;
+        content.exn_arg = new_exn_arg;
+
+        (landing, takes_exn)
+    };
+
+    trace!("insert exception landing block: {:?}", landing);
+    let landing_hdr = landing.hdr.clone();
+    f_content.blocks.insert(landing.id(), landing);
+
+    (landing_hdr, takes_exn)
+}
+
+/// creates a new SSA variable of the given type
+fn new_ssa(f_context: &mut FunctionContext, ty: P<MuType>, vm: &VM) -> P<Value> {
+    let id = vm.next_id();
+    let val = P(Value {
+        hdr: MuEntityHeader::unnamed(id),
+        ty: ty,
+        v: Value_::SSAVar(id),
+    });
+    f_context.values.insert(id, SSAVarEntry::new(val.clone()));
+    val
 }
 
 fn new_inlined_block_name(old_block_name: MuName, vm: &VM) -> MuEntityHeader {
@@ -395,14 +743,19 @@ fn new_inlined_block_name(old_block_name: MuName, vm: &VM) -> MuEntityHeader {
         Arc::new(format!("{}:inlinedblock.#{}", old_block_name, new_id)),
     )
 }
-/// copies blocks from callee to caller, with specified entry block and return block
+
+/// copies blocks from callee to caller, with specified entry block and return block.
+/// SSA variables of the callee are renamed, so a callee can be inlined more than once.
+/// Returns the callsites in the copied blocks (with the ID of the callsite in the callee)
 fn copy_inline_blocks(
     caller: &mut Vec<Block>,
+    caller_context: &mut FunctionContext,
     ret_block: MuEntityHeader,
     callee: &FunctionContent,
     entry_block: MuEntityHeader,
+    exn: Option<&ExnRedirect>,
     vm: &VM,
-) {
+) -> Vec<(MuID, MuID)> {
     trace!("trying to copy inlined function blocks to caller");
 
     // old id -> new id
@@ -416,227 +769,255 @@ fn copy_inline_blocks(
         }
     }
 
-    let fix_dest = |dest: Destination| Destination {
-        target: (*block_map.get(&dest.target.id()).unwrap()).clone(),
-        args: dest.args,
-    };
+    // old SSA variable -> new SSA variable
+    let mut value_map: HashMap<MuID, P<Value>> = HashMap::new();
 
-    let fix_resume = |resume: ResumptionData| ResumptionData {
-        normal_dest: fix_dest(resume.normal_dest),
-        exn_dest: fix_dest(resume.exn_dest),
+    for block in callee.blocks.values() {
+        let content = block.content.as_ref().unwrap();
+        let mut defs: Vec<&P<Value>> = content.args.iter().chain(content.exn_arg.iter()).collect();
+        for inst in content.body.iter() {
+            if let Some(ref values) = inst.as_inst().value {
+                defs.extend(values.iter());
+            }
+        }
+
+        for def in defs {
+            let new_def = new_ssa(caller_context, def.ty.clone(), vm);
+            value_map.insert(def.id(), new_def);
+        }
+    }
+
+    let rename_value = |val: &P<Value>| match value_map.get(&val.id()) {
+        Some(new_val) => new_val.clone(),
+        None => val.clone(),
+    };
+    let rename_op = |op: &P<TreeNode>| match op.v {
+        TreeNode_::Value(ref val) => match value_map.get(&val.id()) {
+            Some(new_val) => TreeNode::new_value(new_val.clone()),
+            None => op.clone(),
+        },
+        TreeNode_::Instruction(_) => panic!("expect flat IR in the callee"),
     };
 
+    let mut callsites = vec![];
+
     for old_block in callee.blocks.values() {
         let old_id = old_block.id();
         let new_hdr = (*block_map.get(&old_block.id()).unwrap()).clone();
+        let old_block_content = old_block.content.as_ref().unwrap();
         let mut block = Block {
             hdr: new_hdr.clone(),
-            content: Some(old_block.content.as_ref().unwrap().clone_empty()),
+            content: Some(BlockContent {
+                args: old_block_content.args.iter().map(&rename_value).collect(),
+                exn_arg: old_block_content.exn_arg.as_ref().map(&rename_value),
+                body: vec![],
+                keepalives: old_block_content
+                    .keepalives
+                    .as_ref()
+                    .map(|vals| vals.iter().map(&rename_value).collect()),
+            }),
             trace_hint: TraceHint::None,
             control_flow: ControlFlow::default(),
         };
 
         trace!("starts copying instruction from {} to {}", old_id, new_hdr);
 
-        // Create the new blocks contents
-        {
-            let old_block_content = old_block.content.as_ref().unwrap();
-            let block_content = block.content.as_mut().unwrap();
+        for old_inst in old_block_content.body.iter() {
+            let old_inst = old_inst.as_inst();
 
-            // Copy the old_block contents (minus the last one)
-            for i in 0..old_block_content.body.len() - 1 {
-                block_content.body.push(match old_block_content.body[i].v {
-                    TreeNode_::Instruction(ref inst) => {
-                        TreeNode::new_inst(inst.clone_with_id(vm.next_id()))
-                    }
-                    _ => panic!("expect instruction as block body"),
-                });
+            // every inst should have a unique ID
+            let mut inst = old_inst.clone_with_id(vm.next_id());
+            inst.value = old_inst
+                .value
+                .as_ref()
+                .map(|vals| vals.iter().map(&rename_value).collect());
+            inst.ops = old_inst.ops.iter().map(&rename_op).collect();
+            fix_dests(&mut inst.v, &block_map);
+
+            if mu_callee(&inst).is_some() {
+                callsites.push((inst.id(), old_inst.id()));
             }
 
-            // check its last instruction
-            let last_inst = old_block_content.body.last().unwrap();
-            // every inst should have a unique ID
-            let inst_new_id = vm.next_id();
-            let last_inst_clone = match last_inst.v {
-                TreeNode_::Instruction(ref inst) => {
-                    TreeNode::new_inst(inst.clone_with_id(inst_new_id))
+            let rewrite = match inst.v {
+                // change RET to a branch
+                Instruction_::Return(ref vec) => Some(Instruction {
+                    hdr: inst.hdr.clone(),
+                    value: inst.value.clone(),
+                    ops: inst.ops.clone(),
+                    v: Instruction_::Branch1(Destination {
+                        target: ret_block.clone(),
+                        args: vec.iter().map(|x| DestArg::Normal(*x)).collect(),
+                    }),
+                }),
+
+                // change THROW to a branch to the exceptional destination
+                Instruction_::Throw(exc) if exn.is_some() => {
+                    let exn = exn.unwrap();
+                    let (ref landing, takes_exn) = *exn.landing.as_ref().unwrap();
+
+                    let mut ops = exn.args.clone();
+                    if takes_exn {
+                        ops.push(inst.ops[exc].clone());
+                    }
+                    let n_ops = ops.len();
+
+                    Some(Instruction {
+                        hdr: inst.hdr.clone(),
+                        value: None,
+                        ops: ops,
+                        v: Instruction_::Branch1(Destination {
+                            target: landing.clone(),
+                            args: (0..n_ops).map(|x| DestArg::Normal(x)).collect(),
+                        }),
+                    })
                 }
-                _ => panic!("expect instruction as block body"),
-            };
-
-            match &last_inst.v {
-                &TreeNode_::Instruction(ref inst) => {
-                    trace!("last instruction: {}", inst);
-
-                    let hdr = inst.hdr.clone_with_id(inst_new_id);
-                    let ref value = inst.value;
-                    let ref ops = inst.ops;
-                    let ref v = inst.v;
-
-                    match v {
-                        &Instruction_::Return(ref vec) => {
-                            // change RET to a branch
-                            let branch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch1(Destination {
-                                    target: ret_block.clone(),
-                                    args: vec.iter().map(|x| DestArg::Normal(*x)).collect(),
-                                }),
-                            };
-
-                            trace!("rewrite to: {}", branch);
-                            block_content.body.push(TreeNode::new_inst(branch));
-                        }
-
-                        // fix destination
-                        &Instruction_::Branch1(ref dest) => {
-                            let branch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch1(fix_dest(dest.clone())),
-                            };
-
-                            trace!("rewrite to: {}", branch);
-                            block_content.body.push(TreeNode::new_inst(branch));
-                        }
-                        &Instruction_::Branch2 {
-                            ref cond,
-                            ref true_dest,
-                            ref false_dest,
-                            ref true_prob,
-                        } => {
-                            let branch2 = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch2 {
-                                    cond: *cond,
-                                    true_dest: fix_dest(true_dest.clone()),
-                                    false_dest: fix_dest(false_dest.clone()),
-                                    true_prob: *true_prob,
-                                },
-                            };
-
-                            trace!("rewrite to: {}", branch2);
-                            block_content.body.push(TreeNode::new_inst(branch2));
-                        }
-                        &Instruction_::Call {
-                            ref data,
-                            ref resume,
-                        } => {
-                            let call = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Call {
-                                    data: data.clone(),
-                                    resume: fix_resume(resume.clone()),
-                                },
-                            };
-
-                            trace!("rewrite to: {}", call);
-                            block_content.body.push(TreeNode::new_inst(call));
-                        }
-                        &Instruction_::CCall {
-                            ref data,
-                            ref resume,
-                        } => {
-                            let call = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::CCall {
-                                    data: data.clone(),
-                                    resume: fix_resume(resume.clone()),
-                                },
-                            };
 
-                            trace!("rewrite to: {}", call);
-                            block_content.body.push(TreeNode::new_inst(call));
-                        }
-                        &Instruction_::Switch {
-                            ref cond,
-                            ref default,
-                            ref branches,
-                        } => {
-                            let switch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Switch {
-                                    cond: *cond,
-                                    default: fix_dest(default.clone()),
-                                    branches: branches
-                                        .iter()
-                                        .map(|&(ref op, ref dest)| {
-                                            (op.clone(), fix_dest(dest.clone()))
-                                        })
-                                        .collect(),
+                // change EXPRCALL to a CALL to the exceptional destination, the rest of
+                // the block continues in a new block
+                Instruction_::ExprCall {
+                    ref data,
+                    is_abort: false,
+                } if exn.is_some() => {
+                    let exn = exn.unwrap();
+                    let cont_hdr = new_inlined_block_name(old_block.name(), vm);
+
+                    let mut ops = inst.ops.clone();
+                    let n_ops = ops.len();
+                    ops.extend(exn.args.iter().cloned());
+
+                    Some(Instruction {
+                        hdr: inst.hdr.clone(),
+                        value: inst.value.clone(),
+                        v: Instruction_::Call {
+                            data: data.clone(),
+                            resume: ResumptionData {
+                                normal_dest: Destination {
+                                    target: cont_hdr,
+                                    args: vec![],
                                 },
-                            };
-
-                            trace!("rewrite to: {}", switch);
-                            block_content.body.push(TreeNode::new_inst(switch));
-                        }
-
-                        &Instruction_::SwapStackExc {
-                            stack,
-                            is_exception,
-                            ref args,
-                            ref resume,
-                        } => {
-                            let swapstack = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::SwapStackExc {
-                                    stack: stack,
-                                    is_exception: is_exception,
-                                    args: args.clone(),
-                                    resume: fix_resume(resume.clone()),
+                                exn_dest: Destination {
+                                    target: exn.handler.clone(),
+                                    args: (n_ops..ops.len()).map(|x| DestArg::Normal(x)).collect(),
                                 },
-                            };
+                            },
+                        },
+                        ops: ops,
+                    })
+                }
 
-                            trace!("rewrite to: {}", swapstack);
-                            block_content.body.push(TreeNode::new_inst(swapstack));
-                        }
-                        &Instruction_::Watchpoint { .. }
-                        | &Instruction_::WPBranch { .. }
-                        | &Instruction_::ExnInstruction { .. } => unimplemented!(),
+                _ => None,
+            };
+            let inst = rewrite.unwrap_or(inst);
+            trace!("copied inst: {}", inst);
 
-                        _ => {
-                            block_content.body.push(last_inst_clone);
-                        }
-                    }
-                }
-                _ => {
-                    // do nothing, and directly push the instruction back
-                    block_content.body.push(last_inst_clone)
+            // a CALL in the middle of the block ends the block
+            let cont_hdr = match inst.v {
+                Instruction_::Call { ref resume, .. } if old_inst.is_non_terminal_inst() => {
+                    Some(resume.normal_dest.target.clone())
                 }
+                _ => None,
+            };
+
+            block
+                .content
+                .as_mut()
+                .unwrap()
+                .body
+                .push(TreeNode::new_inst(inst));
+
+            if let Some(cont_hdr) = cont_hdr {
+                let cont_block = Block {
+                    hdr: cont_hdr,
+                    content: Some(BlockContent {
+                        args: vec![],
+                        exn_arg: None,
+                        body: vec![],
+                        keepalives: None,
+                    }),
+                    trace_hint: TraceHint::None,
+                    control_flow: ControlFlow::default(),
+                };
+                caller.push(mem::replace(&mut block, cont_block));
             }
         }
 
         caller.push(block);
     }
+
+    callsites
 }
 
-/// copies inlined function context into caller
-fn copy_inline_context(caller: &mut FunctionContext, callee: &FunctionContext) {
-    trace!("trying to copy inlined function context to caller");
-    for (id, entry) in callee.values.iter() {
-        caller
-            .values
-            .insert(*id, SSAVarEntry::new(entry.value().clone()));
+/// changes the destinations of a copied instruction to the copied blocks
+fn fix_dests(v: &mut Instruction_, block_map: &HashMap<MuID, MuEntityHeader>) {
+    let fix_dest = |dest: &mut Destination| {
+        dest.target = (*block_map.get(&dest.target.id()).unwrap()).clone();
+    };
+
+    match *v {
+        Instruction_::Branch1(ref mut dest) => fix_dest(dest),
+        Instruction_::Branch2 {
+            ref mut true_dest,
+            ref mut false_dest,
+            ..
+        } => {
+            fix_dest(true_dest);
+            fix_dest(false_dest);
+        }
+        Instruction_::Switch {
+            ref mut default,
+            ref mut branches,
+            ..
+        } => {
+            fix_dest(default);
+            for &mut (_, ref mut dest) in branches.iter_mut() {
+                fix_dest(dest);
+            }
+        }
+        Instruction_::Call { ref mut resume, .. }
+        | Instruction_::CCall { ref mut resume, .. }
+        | Instruction_::SwapStackExc { ref mut resume, .. }
+        | Instruction_::ExnInstruction { ref mut resume, .. } => {
+            fix_dest(&mut resume.normal_dest);
+            fix_dest(&mut resume.exn_dest);
+        }
+        Instruction_::Watchpoint {
+            ref mut disable_dest,
+            ref mut resume,
+            ..
+        } => {
+            if let Some(ref mut dest) = *disable_dest {
+                fix_dest(dest);
+            }
+            fix_dest(&mut resume.normal_dest);
+            fix_dest(&mut resume.exn_dest);
+        }
+        Instruction_::WPBranch {
+            ref mut disable_dest,
+            ref mut enable_dest,
+            ..
+        } => {
+            fix_dest(disable_dest);
+            fix_dest(enable_dest);
+        }
+        _ => {}
     }
 }
 
-/// calculate estimate machine instruction for a Mu function
-fn estimate_insts(fv: &MuFunctionVersion) -> usize {
-    let f_content = fv.content.as_ref().unwrap();
+/// checks whether the function has an instruction that satisfies the predicate
+fn has_inst(f_content: &FunctionContent, pred: &Fn(&Instruction) -> bool) -> bool {
+    f_content.blocks.values().any(|block| {
+        block
+            .content
+            .as_ref()
+            .unwrap()
+            .body
+            .iter()
+            .any(|inst| pred(inst.as_inst()))
+    })
+}
 
+/// calculate estimate machine instruction for a Mu function
+fn estimate_insts(f_content: &FunctionContent) -> usize {
     let mut insts = 0;
 
     for block in f_content.blocks.values() {
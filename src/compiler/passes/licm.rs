@@ -0,0 +1,194 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::op::*;
+use compiler::passes::control_flow::clear_control_flow;
+use compiler::passes::loopanalysis::insert_preheaders;
+use compiler::passes::ControlFlowAnalysis;
+use compiler::passes::DomTree;
+use compiler::passes::IRLoopAnalysis;
+use compiler::CompilerPass;
+use std::any::Any;
+use utils::LinkedHashSet;
+use vm::VM;
+
+/// Loop-invariant code motion. This pass inserts a preheader for every loop, and hoists
+/// loop-invariant instructions to the preheader.
+///
+/// An instruction is loop-invariant if its operands are constants, or are defined outside
+/// the loop, or are the results of other loop-invariant instructions. We only hoist
+/// instructions that have no side effects and cannot trap (arithmetics except integer
+/// division, comparisons, conversions, SELECT, and address computations), as they are
+/// executed in the preheader even if the loop body does not execute them. Derived pointers
+/// are hoisted as well, the register allocator keeps their bases alive across safepoints.
+///
+/// Inner loops are processed first, so an instruction that is invariant in several loops
+/// moves out of all of them.
+pub struct LICM {
+    name: &'static str,
+}
+
+impl LICM {
+    pub fn new() -> LICM {
+        LICM {
+            name: "Loop-Invariant Code Motion",
+        }
+    }
+}
+
+impl CompilerPass for LICM {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        ControlFlowAnalysis::new().execute(vm, func);
+
+        // insert preheaders, and analyse the loops again with the new CFG
+        let changed = {
+            let loop_analysis = {
+                let f_content = func.content.as_ref().unwrap();
+                let domtree = DomTree::new(f_content);
+                IRLoopAnalysis::new(f_content, &domtree)
+            };
+            if loop_analysis.loops().is_empty() {
+                clear_control_flow(func.content.as_mut().unwrap());
+                return;
+            }
+            insert_preheaders(vm, func, &loop_analysis)
+        };
+        if changed {
+            clear_control_flow(func.content.as_mut().unwrap());
+            ControlFlowAnalysis::new().execute(vm, func);
+        }
+
+        let mut f_content = func.content.take().unwrap();
+        {
+            let domtree = DomTree::new(&f_content);
+            let loop_analysis = IRLoopAnalysis::new(&f_content, &domtree);
+
+            for header in loop_analysis.inner_to_outer() {
+                let l = loop_analysis.get_loop(header).unwrap();
+                match l.preheader {
+                    Some(preheader) => hoist_loop(&mut f_content, &domtree, &l.blocks, preheader),
+                    None => trace!("loop {} has no preheader", header),
+                }
+            }
+        }
+        clear_control_flow(&mut f_content);
+        func.content = Some(f_content);
+    }
+}
+
+/// hoists invariant instructions in the loop to the preheader
+fn hoist_loop(
+    f_content: &mut FunctionContent,
+    domtree: &DomTree,
+    blocks: &LinkedHashSet<MuID>,
+    preheader: MuID,
+) {
+    // SSA variables defined in the loop (that are not hoisted)
+    let mut defined: LinkedHashSet<MuID> = LinkedHashSet::new();
+    for block in blocks.iter() {
+        let block_content = f_content.get_block(*block).content.as_ref().unwrap();
+        for arg in block_content.args.iter() {
+            defined.insert(arg.id());
+        }
+        if let Some(ref exn_arg) = block_content.exn_arg {
+            defined.insert(exn_arg.id());
+        }
+        for node in block_content.body.iter() {
+            if let Some(ref values) = node.as_inst().value {
+                for value in values.iter() {
+                    defined.insert(value.id());
+                }
+            }
+        }
+    }
+
+    // visit blocks in reverse postorder, so definitions are visited before uses
+    let mut hoisted = vec![];
+    for block in domtree.rpo().iter() {
+        if !blocks.contains(block) {
+            continue;
+        }
+
+        let block_content = f_content.get_block_mut(*block).content.as_mut().unwrap();
+        let mut new_body = vec![];
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+            let is_invariant = can_hoist(inst)
+                && inst.ops.iter().all(|op| match op.extract_ssa_id() {
+                    Some(id) => !defined.contains(&id),
+                    None => true,
+                });
+
+            if is_invariant {
+                trace!("hoist {} from block {} to {}", node, block, preheader);
+                for value in inst.value.as_ref().unwrap().iter() {
+                    defined.remove(&value.id());
+                }
+                hoisted.push(node.clone());
+            } else {
+                new_body.push(node.clone());
+            }
+        }
+        block_content.body = new_body;
+    }
+
+    if hoisted.is_empty() {
+        return;
+    }
+
+    // put the instructions before the BRANCH of the preheader
+    let block_content = f_content.get_block_mut(preheader).content.as_mut().unwrap();
+    let branch = block_content.body.pop().unwrap();
+    block_content.body.extend(hoisted);
+    block_content.body.push(branch);
+}
+
+/// can we hoist the instruction (if it is loop-invariant)?
+fn can_hoist(inst: &Instruction) -> bool {
+    let has_result = match inst.value {
+        Some(ref values) => !values.is_empty(),
+        None => false,
+    };
+    if !has_result || inst.has_side_effect() {
+        return false;
+    }
+
+    match inst.v {
+        // integer division may trap
+        Instruction_::BinOp(BinOp::Sdiv, _, _)
+        | Instruction_::BinOp(BinOp::Srem, _, _)
+        | Instruction_::BinOp(BinOp::Udiv, _, _)
+        | Instruction_::BinOp(BinOp::Urem, _, _) => false,
+        Instruction_::BinOp(_, _, _)
+        | Instruction_::CmpOp(_, _, _)
+        | Instruction_::ConvOp { .. }
+        | Instruction_::Select { .. }
+        | Instruction_::GetIRef(_)
+        | Instruction_::GetFieldIRef { .. }
+        | Instruction_::GetElementIRef { .. }
+        | Instruction_::ShiftIRef { .. }
+        | Instruction_::GetVarPartIRef { .. } => true,
+        _ => false,
+    }
+}
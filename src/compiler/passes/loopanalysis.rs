@@ -0,0 +1,334 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::ptr::*;
+use compiler::passes::DomTree;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use utils::Tree;
+use vm::VM;
+
+/// a natural loop in Mu IR
+#[derive(Debug)]
+pub struct IRNaturalLoop {
+    pub header: MuID,
+    /// blocks that branch back to the header
+    pub backedges: Vec<MuID>,
+    /// blocks in the loop (including the header)
+    pub blocks: LinkedHashSet<MuID>,
+    /// the only block outside the loop that branches to the header, if it only branches to
+    /// the header. Instructions can be hoisted to the end of the preheader
+    pub preheader: Option<MuID>,
+}
+
+/// the loop-nest tree. The root is the entry block, and other nodes are loop headers
+pub type IRLoopNestTree = Tree<MuID>;
+
+/// loop analysis of Mu IR (the machine code has its own in mc_loopanalysis).
+/// Loops with the same header are merged into one loop.
+pub struct IRLoopAnalysis {
+    loops: LinkedHashMap<MuID, IRNaturalLoop>,
+    loop_nest_tree: IRLoopNestTree,
+    loop_depth: LinkedHashMap<MuID, usize>,
+}
+
+impl IRLoopAnalysis {
+    /// finds the loops of a function. This uses the CFG from control flow analysis, so it
+    /// needs to run after ControlFlowAnalysis
+    pub fn new(f_content: &FunctionContent, domtree: &DomTree) -> IRLoopAnalysis {
+        let loops = compute_loops(f_content, domtree);
+        trace!("---IR loops---");
+        trace!("{:?}", loops);
+
+        let loop_nest_tree = compute_loop_nest_tree(f_content.entry, &loops);
+        trace!("---IR loop-nest tree---");
+        trace!("{:?}", loop_nest_tree);
+
+        let mut loop_depth = LinkedHashMap::new();
+        for block in domtree.rpo().iter() {
+            let depth = loops.values().filter(|l| l.blocks.contains(block)).count();
+            loop_depth.insert(*block, depth);
+        }
+        trace!("---IR loop depth---");
+        trace!("{:?}", loop_depth);
+
+        IRLoopAnalysis {
+            loops: loops,
+            loop_nest_tree: loop_nest_tree,
+            loop_depth: loop_depth,
+        }
+    }
+
+    /// returns all the loops, keyed by their headers
+    pub fn loops(&self) -> &LinkedHashMap<MuID, IRNaturalLoop> {
+        &self.loops
+    }
+
+    /// returns the loop with the header
+    pub fn get_loop(&self, header: MuID) -> Option<&IRNaturalLoop> {
+        self.loops.get(&header)
+    }
+
+    pub fn loop_nest_tree(&self) -> &IRLoopNestTree {
+        &self.loop_nest_tree
+    }
+
+    /// returns the number of loops that contain the block (0 for unreachable blocks)
+    pub fn loop_depth(&self, block: MuID) -> usize {
+        match self.loop_depth.get(&block) {
+            Some(depth) => *depth,
+            None => 0,
+        }
+    }
+
+    /// returns loop headers, inner loops before the loops that contain them
+    pub fn inner_to_outer(&self) -> Vec<MuID> {
+        let mut ret: Vec<MuID> = self.loops.keys().cloned().collect();
+        ret.sort_by_key(|header| self.loops.get(header).unwrap().blocks.len());
+        ret
+    }
+}
+
+/// finds natural loops: for every backedge n -> h (where h dominates n), the loop contains
+/// h and the blocks that reach n without going through h
+fn compute_loops(
+    f_content: &FunctionContent,
+    domtree: &DomTree,
+) -> LinkedHashMap<MuID, IRNaturalLoop> {
+    let mut loops: LinkedHashMap<MuID, IRNaturalLoop> = LinkedHashMap::new();
+
+    for block in domtree.rpo().iter() {
+        for edge in f_content.get_block(*block).control_flow.succs.iter() {
+            let header = edge.target;
+            if !domtree.dominates(header, *block) {
+                continue;
+            }
+
+            let mut blocks = LinkedHashSet::new();
+            blocks.insert(header);
+            let mut work_list = vec![*block];
+            while let Some(cur) = work_list.pop() {
+                if blocks.contains(&cur) {
+                    continue;
+                }
+                blocks.insert(cur);
+                for pred in f_content.get_block(cur).control_flow.preds.iter() {
+                    work_list.push(*pred);
+                }
+            }
+
+            if loops.contains_key(&header) {
+                let l = loops.get_mut(&header).unwrap();
+                if !l.backedges.contains(block) {
+                    l.backedges.push(*block);
+                }
+                l.blocks.add_all(blocks);
+            } else {
+                loops.insert(
+                    header,
+                    IRNaturalLoop {
+                        header: header,
+                        backedges: vec![*block],
+                        blocks: blocks,
+                        preheader: None,
+                    },
+                );
+            }
+        }
+    }
+
+    for l in loops.values_mut() {
+        l.preheader = find_preheader(f_content, l);
+    }
+
+    loops
+}
+
+/// returns the preheader of a loop, if it has one
+fn find_preheader(f_content: &FunctionContent, l: &IRNaturalLoop) -> Option<MuID> {
+    let outside_preds = get_outside_preds(f_content, l);
+    if outside_preds.len() != 1 {
+        return None;
+    }
+
+    let pred = outside_preds[0];
+    let body = &f_content.get_block(pred).content.as_ref().unwrap().body;
+    match body.last().unwrap().as_inst().v {
+        Instruction_::Branch1(_) => Some(pred),
+        _ => None,
+    }
+}
+
+/// returns the blocks outside the loop that branch to the header (without duplicates)
+fn get_outside_preds(f_content: &FunctionContent, l: &IRNaturalLoop) -> Vec<MuID> {
+    let mut ret = vec![];
+    for pred in f_content.get_block(l.header).control_flow.preds.iter() {
+        if !l.blocks.contains(pred) && !ret.contains(pred) {
+            ret.push(*pred);
+        }
+    }
+    ret
+}
+
+/// the parent of a loop is the smallest loop that contains its header
+fn compute_loop_nest_tree(
+    entry: MuID,
+    loops: &LinkedHashMap<MuID, IRNaturalLoop>,
+) -> IRLoopNestTree {
+    let mut tree = IRLoopNestTree::new(entry);
+    for (header, _) in loops.iter() {
+        if *header == entry {
+            // the root already stands for this loop
+            continue;
+        }
+
+        let parent = loops
+            .values()
+            .filter(|l| l.header != *header && l.blocks.contains(header))
+            .min_by_key(|l| l.blocks.len())
+            .map(|l| l.header)
+            .unwrap_or(entry);
+        tree.insert(parent, *header);
+    }
+    tree
+}
+
+/// inserts a preheader for every loop that does not have one: a block that takes the same
+/// parameters as the loop header, and branches to the header. Branches from outside the loop
+/// to the header go to the preheader instead.
+///
+/// We only insert preheaders if all the branches from outside the loop are BRANCH, BRANCH2 or
+/// SWITCH, and the header is not the entry block or an exception block.
+/// Returns true if we inserted any block (the CFG needs to be computed again).
+pub fn insert_preheaders(
+    vm: &VM,
+    func: &mut MuFunctionVersion,
+    loop_analysis: &IRLoopAnalysis,
+) -> bool {
+    let mut f_content = func.content.take().unwrap();
+    let mut changed = false;
+
+    for l in loop_analysis.loops().values() {
+        if l.preheader.is_some() || l.header == f_content.entry {
+            continue;
+        }
+
+        let outside_preds = get_outside_preds(&f_content, l);
+        let can_redirect = outside_preds.iter().all(|pred| {
+            let body = &f_content.get_block(*pred).content.as_ref().unwrap().body;
+            match body.last().unwrap().as_inst().v {
+                Instruction_::Branch1(_)
+                | Instruction_::Branch2 { .. }
+                | Instruction_::Switch { .. } => true,
+                _ => false,
+            }
+        });
+        let (header_hdr, header_args) = {
+            let header = f_content.get_block(l.header);
+            let header_content = header.content.as_ref().unwrap();
+            if header_content.exn_arg.is_some() || !can_redirect || outside_preds.is_empty() {
+                continue;
+            }
+            (header.hdr.clone(), header_content.args.clone())
+        };
+
+        // create the preheader
+        let preheader = {
+            let name = Arc::new(format!("{}_preheader", header_hdr.name()));
+            trace!("create preheader {} for loop {}", name, l.header);
+
+            let mut block = Block::new(MuEntityHeader::named(vm.next_id(), name));
+            let args: Vec<P<Value>> = header_args
+                .iter()
+                .map(|arg| {
+                    func.new_ssa(MuEntityHeader::unnamed(vm.next_id()), arg.ty.clone())
+                        .clone_value()
+                })
+                .collect();
+            block.content = Some(BlockContent {
+                args: args.clone(),
+                exn_arg: None,
+                body: vec![func.new_inst(Instruction {
+                    hdr: MuEntityHeader::unnamed(vm.next_id()),
+                    value: None,
+                    ops: args
+                        .iter()
+                        .map(|val| TreeNode::new_value(val.clone()))
+                        .collect(),
+                    v: Instruction_::Branch1(Destination {
+                        target: header_hdr.clone(),
+                        args: (0..args.len()).map(|i| DestArg::Normal(i)).collect(),
+                    }),
+                })],
+                keepalives: None,
+            });
+            block
+        };
+
+        // branches from outside the loop go to the preheader
+        for pred in outside_preds.iter() {
+            let block_content = f_content.get_block_mut(*pred).content.as_mut().unwrap();
+            let new_node = {
+                let node = block_content.body.last().unwrap();
+                let mut new_inst = node.as_inst().clone();
+                retarget_branch(&mut new_inst, l.header, &preheader.hdr);
+                let new_node = func.new_inst(new_inst);
+                trace!("rewrite {} to {}", node, new_node);
+                new_node
+            };
+            block_content.body.pop();
+            block_content.body.push(new_node);
+        }
+
+        f_content.blocks.insert(preheader.id(), preheader);
+        changed = true;
+    }
+
+    func.content = Some(f_content);
+    changed
+}
+
+/// makes a BRANCH, BRANCH2 or SWITCH go to another block instead of the target
+fn retarget_branch(inst: &mut Instruction, target: MuID, new_target: &MuEntityHeader) {
+    let retarget = |dest: &mut Destination| {
+        if dest.target.id() == target {
+            dest.target = new_target.clone();
+        }
+    };
+
+    match inst.v {
+        Instruction_::Branch1(ref mut dest) => retarget(dest),
+        Instruction_::Branch2 {
+            ref mut true_dest,
+            ref mut false_dest,
+            ..
+        } => {
+            retarget(true_dest);
+            retarget(false_dest);
+        }
+        Instruction_::Switch {
+            ref mut default,
+            ref mut branches,
+            ..
+        } => {
+            retarget(default);
+            for &mut (_, ref mut dest) in branches.iter_mut() {
+                retarget(dest);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
@@ -17,16 +17,56 @@ use ast::ptr::*;
 use std::any::Any;
 use vm::VM;
 
+/// A profiling pass. It instruments functions with counters, or uses the counts of a profile
+/// for branch probabilities and block layout
+mod profiling;
+pub use compiler::passes::profiling::Profiling;
+
 /// An inlining pass. Based on a certain criteria, the compiler chooses certain functions to be
 /// inlined in their callsite by rewriting the call into a branch with several copied blocks from
 /// the inlined function
 mod inlining;
 pub use compiler::passes::inlining::Inlining;
 
+/// An escape analysis pass. It replaces the objects that never leave the function with SSA
+/// variables for their fields, or with stack allocation
+mod escape_analysis;
+pub use compiler::passes::escape_analysis::EscapeAnalysis;
+
 /// A pass to check and rewrite RET instructions to ensure a single return sink for every function
 mod ret_sink;
 pub use compiler::passes::ret_sink::RetSink;
 
+/// A sparse conditional constant propagation pass. It folds instructions on constants,
+/// resolves branches on known conditions, and removes unreachable blocks
+mod sccp;
+pub use compiler::passes::sccp::SCCP;
+
+/// A dead code elimination pass. It removes unreachable blocks, and instructions and
+/// block parameters whose results are never used
+mod dce;
+pub use compiler::passes::dce::DCE;
+
+/// A global value numbering pass. It removes instructions that compute the same value as
+/// an instruction in a dominating block
+mod gvn;
+pub use compiler::passes::gvn::GVN;
+
+/// Dominator tree of Mu IR
+mod domtree;
+pub use compiler::passes::domtree::DomTree;
+
+/// Loop analysis of Mu IR (natural loops, the loop-nest tree and loop depth)
+mod loopanalysis;
+pub use compiler::passes::loopanalysis::IRLoopAnalysis;
+pub use compiler::passes::loopanalysis::IRLoopNestTree;
+pub use compiler::passes::loopanalysis::IRNaturalLoop;
+
+/// A loop-invariant code motion pass. It inserts loop preheaders, and hoists loop-invariant
+/// instructions to them
+mod licm;
+pub use compiler::passes::licm::LICM;
+
 /// A pass to inject runtime fastpath into Mu IR
 mod inject_runtime;
 pub use compiler::passes::inject_runtime::InjectRuntime;
@@ -0,0 +1,534 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::types::*;
+use compiler::CompilerPass;
+use std::any::Any;
+use std::collections::HashMap;
+use utils::LinkedHashMap;
+use vm::profile;
+use vm::profile::Profile;
+use vm::VM;
+
+/// Profile-guided optimisation. This pass does nothing unless `--profile-instrument` or
+/// `--profile-use` is set (see `vm::profile`).
+///
+/// With `--profile-use`, it sets the probability of BRANCH2 from the counts of its two
+/// edges, and marks blocks that were never executed (while the function was) as slow path,
+/// so trace generation lays them out of the hot trace. SWITCH does not carry probabilities,
+/// control flow analysis reads the profile for its edges. The inliner reads the counts of
+/// callsites.
+///
+/// With `--profile-instrument`, it allocates a global cell with a uint64 counter for
+/// the function entry, every branch edge and every callsite, and inserts code to increment
+/// the counters. Edges of BRANCH2 and SWITCH are split with a new block that increments
+/// the counter. The counters are not atomic, the counts are approximate with multiple
+/// threads.
+///
+/// This pass runs before other IR passes, so the keys of the counters refer to the blocks
+/// and instructions that the client built.
+pub struct Profiling {
+    name: &'static str,
+}
+
+impl Profiling {
+    pub fn new() -> Profiling {
+        Profiling { name: "Profiling" }
+    }
+}
+
+impl CompilerPass for Profiling {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        if let Some(profile) = profile::get_profile(vm) {
+            use_profile(&profile, func);
+        }
+
+        if vm.vm_options.flag_profile_instrument {
+            instrument(vm, func);
+            debug!("after instrumentation: {:?}", func);
+        }
+    }
+}
+
+/// returns the count of an edge in the profile
+fn get_edge_count(profile: &Profile, fv: &str, from: MuID, to: MuID) -> Option<u64> {
+    profile.get(&profile::edge_key(fv, from, to))
+}
+
+/// sets branch probabilities and trace hints from the profile
+fn use_profile(profile: &Profile, func: &mut MuFunctionVersion) {
+    let fv = func.name();
+    let entry_count = match profile.get(&profile::entry_key(&fv)) {
+        Some(count) => count,
+        None => {
+            info!("no profile for {}", fv);
+            return;
+        }
+    };
+
+    let f_content = func.content.as_mut().unwrap();
+
+    // counts of blocks (None if some edges to the block are not profiled)
+    let mut block_counts: LinkedHashMap<MuID, Option<u64>> = LinkedHashMap::new();
+    block_counts.insert(f_content.entry, Some(entry_count));
+    // edges from each block, with their counts
+    let mut edges: LinkedHashMap<MuID, Vec<(MuID, Option<u64>)>> = LinkedHashMap::new();
+
+    for (id, block) in f_content.blocks.iter_mut() {
+        let block_content = block.content.as_mut().unwrap();
+        let last = block_content.body.pop().unwrap();
+
+        let mut out_edges = vec![];
+        let new_last = {
+            let inst = last.as_inst();
+            match inst.v {
+                Instruction_::Branch1(ref dest) => {
+                    let target = dest.target.id();
+                    out_edges.push((target, get_edge_count(profile, &fv, *id, target)));
+                    None
+                }
+                Instruction_::Branch2 {
+                    ref true_dest,
+                    ref false_dest,
+                    ..
+                } => {
+                    let (true_target, false_target) =
+                        (true_dest.target.id(), false_dest.target.id());
+                    let true_count = get_edge_count(profile, &fv, *id, true_target);
+                    let false_count = get_edge_count(profile, &fv, *id, false_target);
+                    out_edges.push((true_target, true_count));
+                    out_edges.push((false_target, false_count));
+
+                    match (true_count, false_count) {
+                        (Some(t), Some(f)) if t + f > 0 && true_target != false_target => {
+                            let mut new_inst = inst.clone();
+                            if let Instruction_::Branch2 {
+                                ref mut true_prob, ..
+                            } = new_inst.v
+                            {
+                                *true_prob = t as f32 / (t + f) as f32;
+                                trace!("set probability of {} to {}", inst, true_prob);
+                            }
+                            Some(TreeNode::new_inst(new_inst))
+                        }
+                        _ => None,
+                    }
+                }
+                Instruction_::Switch {
+                    ref default,
+                    ref branches,
+                    ..
+                } => {
+                    for &(_, ref dest) in branches.iter() {
+                        let target = dest.target.id();
+                        out_edges.push((target, get_edge_count(profile, &fv, *id, target)));
+                    }
+                    let target = default.target.id();
+                    out_edges.push((target, get_edge_count(profile, &fv, *id, target)));
+                    None
+                }
+                _ => {
+                    // edges of other instructions are not profiled
+                    for target in block_successors(inst) {
+                        out_edges.push((target, None));
+                    }
+                    None
+                }
+            }
+        };
+        block_content.body.push(new_last.unwrap_or(last));
+
+        // edges to the same target share a counter
+        let mut unique_edges: Vec<(MuID, Option<u64>)> = vec![];
+        for edge in out_edges {
+            if !unique_edges.iter().any(|e| e.0 == edge.0) {
+                unique_edges.push(edge);
+            }
+        }
+        let out_edges = unique_edges;
+
+        for &(target, count) in out_edges.iter() {
+            let new_count = match block_counts.get(&target) {
+                Some(&old_count) => match (old_count, count) {
+                    (Some(a), Some(b)) => Some(a + b),
+                    _ => None,
+                },
+                None => count,
+            };
+            block_counts.insert(target, new_count);
+        }
+        edges.insert(*id, out_edges);
+    }
+
+    if entry_count == 0 {
+        return;
+    }
+
+    // a block is cold if it was never executed. We only mark the first block of a cold
+    // path, i.e. a cold block that a hot block branches to
+    for (id, block) in f_content.blocks.iter_mut() {
+        if block_counts.get(id) != Some(&Some(0)) {
+            continue;
+        }
+        let from_hot = edges.iter().any(|(pred, out_edges)| {
+            out_edges.iter().any(|&(target, _)| target == *id)
+                && match block_counts.get(pred) {
+                    Some(&Some(count)) => count > 0,
+                    _ => false,
+                }
+        });
+        if from_hot {
+            trace!("block {} is cold", id);
+            block.trace_hint = TraceHint::SlowPath;
+        }
+    }
+}
+
+/// returns the blocks that an instruction may branch to
+fn block_successors(inst: &Instruction) -> Vec<MuID> {
+    let mut ret = vec![];
+    match inst.v {
+        Instruction_::Watchpoint {
+            ref disable_dest,
+            ref resume,
+            ..
+        } => {
+            if let Some(ref dest) = *disable_dest {
+                ret.push(dest.target.id());
+            }
+            ret.push(resume.normal_dest.target.id());
+            ret.push(resume.exn_dest.target.id());
+        }
+        Instruction_::WPBranch {
+            ref disable_dest,
+            ref enable_dest,
+            ..
+        } => {
+            ret.push(disable_dest.target.id());
+            ret.push(enable_dest.target.id());
+        }
+        Instruction_::Call { ref resume, .. }
+        | Instruction_::CCall { ref resume, .. }
+        | Instruction_::SwapStackExc { ref resume, .. }
+        | Instruction_::ExnInstruction { ref resume, .. } => {
+            ret.push(resume.normal_dest.target.id());
+            ret.push(resume.exn_dest.target.id());
+        }
+        _ => {}
+    }
+    ret
+}
+
+/// is the instruction a callsite that we count?
+fn is_call(inst: &Instruction) -> bool {
+    match inst.v {
+        Instruction_::Call { .. }
+        | Instruction_::CCall { .. }
+        | Instruction_::ExprCall { .. }
+        | Instruction_::ExprCCall { .. } => true,
+        _ => false,
+    }
+}
+
+/// returns the keys of all the counters for the function, without duplicates
+fn collect_keys(fv: &str, f_content: &FunctionContent) -> Vec<String> {
+    let mut ret = vec![profile::entry_key(fv)];
+    {
+        let mut add_key = |key: String| {
+            if !ret.contains(&key) {
+                ret.push(key);
+            }
+        };
+
+        for (id, block) in f_content.blocks.iter() {
+            let body = &block.content.as_ref().unwrap().body;
+            for node in body.iter() {
+                let inst = node.as_inst();
+                if is_call(inst) {
+                    add_key(profile::call_key(fv, inst.id()));
+                }
+                for target in profiled_targets(inst) {
+                    add_key(profile::edge_key(fv, *id, target));
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// returns the targets of the edges that we count for an instruction
+fn profiled_targets(inst: &Instruction) -> Vec<MuID> {
+    match inst.v {
+        Instruction_::Branch1(ref dest) => vec![dest.target.id()],
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => vec![true_dest.target.id(), false_dest.target.id()],
+        Instruction_::Switch {
+            ref default,
+            ref branches,
+            ..
+        } => {
+            let mut ret: Vec<MuID> = branches
+                .iter()
+                .map(|&(_, ref dest)| dest.target.id())
+                .collect();
+            ret.push(default.target.id());
+            ret
+        }
+        _ => vec![],
+    }
+}
+
+/// inserts counters into the function
+fn instrument(vm: &VM, func: &mut MuFunctionVersion) {
+    let fv = func.name();
+    let mut f_content = func.content.take().unwrap();
+
+    // allocate the counters
+    let keys = collect_keys(&fv, &f_content);
+    let counters = {
+        let ty = vm.declare_type(
+            MuEntityHeader::unnamed(vm.next_id()),
+            MuType_::array(UINT64_TYPE.clone(), keys.len()),
+        );
+        let name = format!("{}:profile_counters", fv);
+        vm.declare_global(MuEntityHeader::named(vm.next_id(), Arc::new(name)), ty)
+    };
+    let iref_counter_ty = vm.declare_type(
+        MuEntityHeader::unnamed(vm.next_id()),
+        MuType_::iref(UINT64_TYPE.clone()),
+    );
+    let index: HashMap<String, usize> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.clone(), i))
+        .collect();
+    trace!("profile counters of {}: {:?}", fv, keys);
+
+    let mut counter = CounterGen {
+        vm: vm,
+        counters: counters.clone(),
+        iref_counter_ty: iref_counter_ty,
+        index: index,
+    };
+
+    let mut new_blocks = vec![];
+    let entry = f_content.entry;
+    for (id, block) in f_content.blocks.iter_mut() {
+        let block_name = block.name();
+        let block_content = block.content.as_mut().unwrap();
+
+        let mut new_body = vec![];
+        if *id == entry {
+            new_body.extend(counter.increment(func, &profile::entry_key(&fv)));
+        }
+
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+            if is_call(inst) {
+                new_body.extend(counter.increment(func, &profile::call_key(&fv, inst.id())));
+            }
+
+            match inst.v {
+                Instruction_::Branch1(ref dest) => {
+                    let key = profile::edge_key(&fv, *id, dest.target.id());
+                    new_body.extend(counter.increment(func, &key));
+                    new_body.push(node.clone());
+                }
+                Instruction_::Branch2 { .. } | Instruction_::Switch { .. } => {
+                    // branch to a new block that increments the counter of the edge
+                    let mut new_inst = inst.clone();
+                    {
+                        let mut split_edge = |dest: &mut Destination| {
+                            let key = profile::edge_key(&fv, *id, dest.target.id());
+                            let args = dest.get_arguments_as_node(&inst.ops);
+                            let new_block =
+                                counter.new_edge_block(func, &block_name, &key, dest, args);
+                            *dest = Destination {
+                                target: new_block.hdr.clone(),
+                                args: vec![],
+                            };
+                            new_blocks.push(new_block);
+                        };
+
+                        match new_inst.v {
+                            Instruction_::Branch2 {
+                                ref mut true_dest,
+                                ref mut false_dest,
+                                ..
+                            } => {
+                                split_edge(true_dest);
+                                split_edge(false_dest);
+                            }
+                            Instruction_::Switch {
+                                ref mut default,
+                                ref mut branches,
+                                ..
+                            } => {
+                                for &mut (_, ref mut dest) in branches.iter_mut() {
+                                    split_edge(dest);
+                                }
+                                split_edge(default);
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                    new_body.push(func.new_inst(new_inst));
+                }
+                _ => new_body.push(node.clone()),
+            }
+        }
+
+        block_content.body = new_body;
+    }
+
+    for block in new_blocks {
+        f_content.blocks.insert(block.id(), block);
+    }
+
+    vm.profile_counters()
+        .write()
+        .unwrap()
+        .insert(counters.id(), keys);
+    func.content = Some(f_content);
+}
+
+/// generates code to increment counters
+struct CounterGen<'a> {
+    vm: &'a VM,
+    /// the global cell of counters
+    counters: P<Value>,
+    iref_counter_ty: P<MuType>,
+    /// index of the counter for each key
+    index: HashMap<String, usize>,
+}
+
+impl<'a> CounterGen<'a> {
+    /// returns instructions that increment the counter of the key:
+    ///   %p = GETELEMIREF @counters index
+    ///   %c = LOAD %p
+    ///   %c2 = ADD %c 1
+    ///   STORE %p %c2
+    fn increment(&mut self, func: &mut MuFunctionVersion, key: &String) -> Vec<P<TreeNode>> {
+        let vm = self.vm;
+        let i = *self.index.get(key).unwrap();
+
+        let constant = |v: u64| {
+            TreeNode::new_value(P(Value {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                ty: UINT64_TYPE.clone(),
+                v: Value_::Constant(Constant::Int(v)),
+            }))
+        };
+
+        let p = func.new_ssa(
+            MuEntityHeader::unnamed(vm.next_id()),
+            self.iref_counter_ty.clone(),
+        );
+        let c = func.new_ssa(MuEntityHeader::unnamed(vm.next_id()), UINT64_TYPE.clone());
+        let c2 = func.new_ssa(MuEntityHeader::unnamed(vm.next_id()), UINT64_TYPE.clone());
+
+        vec![
+            func.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![p.clone_value()]),
+                ops: vec![
+                    TreeNode::new_value(self.counters.clone()),
+                    constant(i as u64),
+                ],
+                v: Instruction_::GetElementIRef {
+                    is_ptr: false,
+                    base: 0,
+                    index: 1,
+                },
+            }),
+            func.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![c.clone_value()]),
+                ops: vec![p.clone()],
+                v: Instruction_::Load {
+                    is_ptr: false,
+                    order: MemoryOrder::NotAtomic,
+                    mem_loc: 0,
+                },
+            }),
+            func.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![c2.clone_value()]),
+                ops: vec![c.clone(), constant(1)],
+                v: Instruction_::BinOp(BinOp::Add, 0, 1),
+            }),
+            func.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: None,
+                ops: vec![p.clone(), c2.clone()],
+                v: Instruction_::Store {
+                    is_ptr: false,
+                    order: MemoryOrder::NotAtomic,
+                    mem_loc: 0,
+                    value: 1,
+                },
+            }),
+        ]
+    }
+
+    /// creates a block that increments the counter of the key, and branches to the
+    /// destination with the arguments
+    fn new_edge_block(
+        &mut self,
+        func: &mut MuFunctionVersion,
+        from_name: &MuName,
+        key: &String,
+        dest: &Destination,
+        args: Vec<P<TreeNode>>,
+    ) -> Block {
+        let vm = self.vm;
+        let id = vm.next_id();
+        let name = format!("{}:profile_edge.#{}", from_name, id);
+
+        let mut body = self.increment(func, key);
+        body.push(func.new_inst(Instruction {
+            hdr: MuEntityHeader::unnamed(vm.next_id()),
+            value: None,
+            v: Instruction_::Branch1(Destination {
+                target: dest.target.clone(),
+                args: (0..args.len()).map(|i| DestArg::Normal(i)).collect(),
+            }),
+            ops: args,
+        }));
+
+        let mut block = Block::new(MuEntityHeader::named(id, Arc::new(name)));
+        block.content = Some(BlockContent {
+            args: vec![],
+            exn_arg: None,
+            body: body,
+            keepalives: None,
+        });
+        block
+    }
+}
@@ -0,0 +1,978 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::inst::*;
+use ast::ir::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::types::*;
+use compiler::CompilerPass;
+use std::any::Any;
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+use vm::VM;
+
+/// Sparse conditional constant propagation (Wegman and Zadeck).
+///
+/// Every SSA variable starts as Undefined, and may be lowered to a constant, then to
+/// Overdefined. The analysis keeps two worklists: blocks that are found executable, and
+/// SSA variables whose values are lowered. An executable block is visited once, after that
+/// an instruction is only visited again when the value of one of its operands is lowered.
+/// A conditional branch on a known condition only makes one of its edges executable, and
+/// a block argument only meets the values passed on executable edges.
+/// After the analysis reaches a fixed point, we
+/// * replace uses of constant variables with the constants and remove the (pure)
+///   instructions that defined them,
+/// * rewrite BRANCH2/SWITCH on known conditions into BRANCH, and SELECT on known
+///   conditions into the chosen operand,
+/// * remove blocks that are never executed.
+///
+/// Folding follows Mu semantics (e.g. the shift amount is taken modulo the width). We do not
+/// fold operations whose result the machine decides at run time (division by zero, signed
+/// division overflow, and converting out-of-range floating point values to integers).
+pub struct SCCP {
+    name: &'static str,
+}
+
+impl SCCP {
+    pub fn new() -> SCCP {
+        SCCP {
+            name: "Sparse Conditional Constant Propagation",
+        }
+    }
+}
+
+impl CompilerPass for SCCP {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let analysis = {
+            let f_content = func.content.as_ref().unwrap();
+            let mut analysis = Analysis::new(f_content);
+            analysis.solve(f_content);
+            analysis
+        };
+
+        let mut f_content = func.content.take().unwrap();
+        remove_unreachable_blocks(&mut f_content, &analysis);
+        rewrite_blocks(vm, &mut f_content, &analysis);
+        func.content = Some(f_content);
+    }
+}
+
+/// lattice value of an SSA variable
+#[derive(Clone, Debug)]
+enum Lattice {
+    /// we have not seen a definition yet (optimistically, it can be any constant)
+    Undefined,
+    /// the variable always holds this constant
+    Const(Constant),
+    /// the variable may hold different values
+    Overdefined,
+}
+
+impl Lattice {
+    fn meet(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (&Lattice::Undefined, _) => other.clone(),
+            (_, &Lattice::Undefined) => self.clone(),
+            (&Lattice::Const(ref a), &Lattice::Const(ref b)) if is_same_constant(a, b) => {
+                self.clone()
+            }
+            _ => Lattice::Overdefined,
+        }
+    }
+
+    fn is_same(&self, other: &Lattice) -> bool {
+        match (self, other) {
+            (&Lattice::Undefined, &Lattice::Undefined) => true,
+            (&Lattice::Overdefined, &Lattice::Overdefined) => true,
+            (&Lattice::Const(ref a), &Lattice::Const(ref b)) => is_same_constant(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// compares floating point constants by their bits (so NaN is the same as itself)
+fn is_same_constant(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (&Constant::Float(x), &Constant::Float(y)) => x.to_bits() == y.to_bits(),
+        (&Constant::Double(x), &Constant::Double(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+struct Analysis {
+    /// lattice values of SSA variables
+    values: LinkedHashMap<MuID, Lattice>,
+    /// all the SSA variables defined in the function (by blocks or instructions)
+    defined: LinkedHashMap<MuID, P<Value>>,
+    /// blocks that can be executed
+    reachable: LinkedHashSet<MuID>,
+    /// the instructions that use each SSA variable (as block ID and index in the block)
+    uses: LinkedHashMap<MuID, Vec<(MuID, usize)>>,
+    /// executable blocks that are not visited yet
+    block_worklist: Vec<MuID>,
+    /// SSA variables whose values are lowered, and whose uses are not visited yet
+    ssa_worklist: Vec<MuID>,
+}
+
+impl Analysis {
+    fn new(f_content: &FunctionContent) -> Analysis {
+        let mut defined = LinkedHashMap::new();
+        let mut uses: LinkedHashMap<MuID, Vec<(MuID, usize)>> = LinkedHashMap::new();
+        for (block_id, block) in f_content.blocks.iter() {
+            let block_content = block.content.as_ref().unwrap();
+            for arg in block_content.args.iter() {
+                defined.insert(arg.id(), arg.clone());
+            }
+            if let Some(ref exn_arg) = block_content.exn_arg {
+                defined.insert(exn_arg.id(), exn_arg.clone());
+            }
+            for (i, node) in block_content.body.iter().enumerate() {
+                let inst = node.as_inst();
+                if let Some(ref values) = inst.value {
+                    for value in values.iter() {
+                        defined.insert(value.id(), value.clone());
+                    }
+                }
+                for op in inst.ops.iter() {
+                    if let Some(id) = op.extract_ssa_id() {
+                        let users = uses.entry(id).or_insert(vec![]);
+                        if !users.contains(&(*block_id, i)) {
+                            users.push((*block_id, i));
+                        }
+                    }
+                }
+            }
+        }
+
+        Analysis {
+            values: LinkedHashMap::new(),
+            defined: defined,
+            reachable: LinkedHashSet::new(),
+            uses: uses,
+            block_worklist: vec![],
+            ssa_worklist: vec![],
+        }
+    }
+
+    /// visits executable blocks, and the uses of lowered SSA variables, until both
+    /// worklists are empty
+    fn solve(&mut self, f_content: &FunctionContent) {
+        // we know nothing about function arguments
+        let entry = f_content.entry;
+        self.reachable.insert(entry);
+        self.block_worklist.push(entry);
+        for arg in f_content
+            .get_entry_block()
+            .content
+            .as_ref()
+            .unwrap()
+            .args
+            .iter()
+        {
+            self.lower(arg.id(), Lattice::Overdefined);
+        }
+
+        loop {
+            if let Some(block) = self.block_worklist.pop() {
+                let block_content = f_content.get_block(block).content.as_ref().unwrap();
+                if let Some(ref exn_arg) = block_content.exn_arg {
+                    self.lower(exn_arg.id(), Lattice::Overdefined);
+                }
+                for node in block_content.body.iter() {
+                    self.visit_inst(node.as_inst(), f_content);
+                }
+            } else if let Some(id) = self.ssa_worklist.pop() {
+                let users = match self.uses.get(&id) {
+                    Some(users) => users.clone(),
+                    None => continue,
+                };
+                for (block, i) in users {
+                    // the uses in a block that is not executable yet are visited with the block
+                    if !self.reachable.contains(&block) {
+                        continue;
+                    }
+                    let block_content = f_content.get_block(block).content.as_ref().unwrap();
+                    self.visit_inst(block_content.body[i].as_inst(), f_content);
+                }
+            } else {
+                break;
+            }
+        }
+
+        trace!(
+            "reachable blocks: {:?}",
+            self.reachable.iter().collect::<Vec<_>>()
+        );
+    }
+
+    /// lowers the lattice value of an SSA variable
+    fn lower(&mut self, id: MuID, value: Lattice) {
+        let old = self.get_value(id);
+        let new = old.meet(&value);
+        if !new.is_same(&old) {
+            trace!("{} = {:?}", id, new);
+            self.values.insert(id, new);
+            self.ssa_worklist.push(id);
+        }
+    }
+
+    fn get_value(&self, id: MuID) -> Lattice {
+        match self.values.get(&id) {
+            Some(value) => value.clone(),
+            None => {
+                if self.defined.contains_key(&id) {
+                    Lattice::Undefined
+                } else {
+                    // not defined in this function (e.g. a machine register)
+                    Lattice::Overdefined
+                }
+            }
+        }
+    }
+
+    /// returns the lattice value of an operand
+    fn get(&self, op: &TreeNode) -> Lattice {
+        match op.v {
+            TreeNode_::Value(ref pv) => match pv.v {
+                Value_::SSAVar(id) => self.get_value(id),
+                Value_::Constant(ref c) => match normalize_constant(c, &pv.ty) {
+                    Some(c) => Lattice::Const(c),
+                    None => Lattice::Overdefined,
+                },
+                _ => Lattice::Overdefined,
+            },
+            TreeNode_::Instruction(_) => Lattice::Overdefined,
+        }
+    }
+
+    /// returns the constants of the operands, or the lattice value of the result if any
+    /// operand is not a constant
+    fn get_constants(&self, ops: &[&P<TreeNode>]) -> Result<Vec<Constant>, Lattice> {
+        let mut ret = vec![];
+        let mut undefined = false;
+        for op in ops.iter() {
+            match self.get(op) {
+                Lattice::Const(c) => ret.push(c),
+                Lattice::Undefined => undefined = true,
+                Lattice::Overdefined => return Err(Lattice::Overdefined),
+            }
+        }
+        if undefined {
+            Err(Lattice::Undefined)
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// makes a block reachable through a destination, and passes the arguments to it
+    fn visit_dest(&mut self, dest: &Destination, ops: &Vec<P<TreeNode>>, f: &FunctionContent) {
+        let target = dest.target.id();
+        if !self.reachable.contains(&target) {
+            trace!("block {} is reachable", target);
+            self.reachable.insert(target);
+            self.block_worklist.push(target);
+        }
+
+        let block_args = &f.get_block(target).content.as_ref().unwrap().args;
+        for (arg, dest_arg) in block_args.iter().zip(dest.args.iter()) {
+            let value = match dest_arg {
+                &DestArg::Normal(i) => self.get(&ops[i]),
+                &DestArg::Freshbound(_) => Lattice::Overdefined,
+            };
+            self.lower(arg.id(), value);
+        }
+    }
+
+    fn visit_inst(&mut self, inst: &Instruction, f_content: &FunctionContent) {
+        let ref ops = inst.ops;
+        match inst.v {
+            Instruction_::Branch2 {
+                cond,
+                ref true_dest,
+                ref false_dest,
+                ..
+            } => match self.get(&ops[cond]) {
+                Lattice::Undefined => {}
+                Lattice::Const(ref c) => {
+                    if is_true(c) {
+                        self.visit_dest(true_dest, ops, f_content);
+                    } else {
+                        self.visit_dest(false_dest, ops, f_content);
+                    }
+                }
+                Lattice::Overdefined => {
+                    self.visit_dest(true_dest, ops, f_content);
+                    self.visit_dest(false_dest, ops, f_content);
+                }
+            },
+            Instruction_::Switch { cond, .. } => {
+                let dests = match self.get(&ops[cond]) {
+                    Lattice::Undefined => vec![],
+                    Lattice::Const(_) => match self.get_switch_dest(inst) {
+                        Some(dest) => vec![dest],
+                        None => get_destinations(inst),
+                    },
+                    Lattice::Overdefined => get_destinations(inst),
+                };
+                for dest in dests {
+                    self.visit_dest(dest, ops, f_content);
+                }
+            }
+            _ => {
+                if let Some(ref values) = inst.value {
+                    let result = if values.len() == 1 {
+                        self.eval(inst)
+                    } else {
+                        Lattice::Overdefined
+                    };
+                    for value in values.iter() {
+                        self.lower(value.id(), result.clone());
+                    }
+                }
+                for dest in get_destinations(inst) {
+                    self.visit_dest(dest, ops, f_content);
+                }
+            }
+        }
+    }
+
+    /// returns the destination that a SWITCH on a constant takes
+    /// (None if the condition or any case is not a constant)
+    fn get_switch_dest<'a>(&self, inst: &'a Instruction) -> Option<&'a Destination> {
+        let ref ops = inst.ops;
+        match inst.v {
+            Instruction_::Switch {
+                cond,
+                ref default,
+                ref branches,
+            } => {
+                let width = match ops[cond].ty().get_int_length() {
+                    Some(width) => width,
+                    None => return None,
+                };
+                let cond_value = match self.get(&ops[cond]) {
+                    Lattice::Const(ref c) => int_of(c, width),
+                    _ => None,
+                };
+                let cond_value = match cond_value {
+                    Some(v) => v,
+                    None => return None,
+                };
+
+                for &(case, ref dest) in branches.iter() {
+                    let case_value = match self.get(&ops[case]) {
+                        Lattice::Const(ref c) => int_of(c, width),
+                        _ => None,
+                    };
+                    match case_value {
+                        Some(v) if v == cond_value => return Some(dest),
+                        Some(_) => {}
+                        None => return None,
+                    }
+                }
+                Some(default)
+            }
+            _ => None,
+        }
+    }
+
+    /// evaluates an instruction that yields one value
+    fn eval(&self, inst: &Instruction) -> Lattice {
+        let ref ops = inst.ops;
+        let ref result_ty = inst.value.as_ref().unwrap()[0].ty;
+
+        let folded = match inst.v {
+            Instruction_::BinOp(op, op1, op2) => {
+                match self.get_constants(&[&ops[op1], &ops[op2]]) {
+                    Ok(c) => fold_binop(op, result_ty, &ops[op2].ty(), &c[0], &c[1]),
+                    Err(lattice) => return lattice,
+                }
+            }
+            Instruction_::CmpOp(op, op1, op2) => {
+                match self.get_constants(&[&ops[op1], &ops[op2]]) {
+                    Ok(c) => fold_cmpop(op, result_ty, &ops[op1].ty(), &c[0], &c[1]),
+                    Err(lattice) => return lattice,
+                }
+            }
+            Instruction_::ConvOp {
+                operation,
+                ref from_ty,
+                ref to_ty,
+                operand,
+            } => match self.get_constants(&[&ops[operand]]) {
+                Ok(c) => fold_convop(operation, from_ty, to_ty, &c[0]),
+                Err(lattice) => return lattice,
+            },
+            Instruction_::Select {
+                cond,
+                true_val,
+                false_val,
+            } => {
+                return match self.get(&ops[cond]) {
+                    Lattice::Undefined => Lattice::Undefined,
+                    Lattice::Const(ref c) => {
+                        if is_true(c) {
+                            self.get(&ops[true_val])
+                        } else {
+                            self.get(&ops[false_val])
+                        }
+                    }
+                    Lattice::Overdefined => {
+                        self.get(&ops[true_val]).meet(&self.get(&ops[false_val]))
+                    }
+                };
+            }
+            Instruction_::Move(op) => return self.get(&ops[op]),
+            _ => None,
+        };
+
+        match folded {
+            Some(c) => Lattice::Const(c),
+            None => Lattice::Overdefined,
+        }
+    }
+
+    /// returns the constant of an SSA variable that we can use in place of the variable
+    fn get_replacement(&self, id: MuID) -> Option<Constant> {
+        match self.values.get(&id) {
+            Some(&Lattice::Const(ref c)) => {
+                let ty = &self.defined.get(&id).unwrap().ty;
+                // other constants (such as NULL) may not be supported in every operand
+                if ty.is_int() || ty.is_fp() {
+                    Some(c.clone())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// returns all the destinations of an instruction
+fn get_destinations(inst: &Instruction) -> Vec<&Destination> {
+    match inst.v {
+        Instruction_::Branch1(ref dest) => vec![dest],
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => vec![true_dest, false_dest],
+        Instruction_::Switch {
+            ref default,
+            ref branches,
+            ..
+        } => {
+            let mut ret = vec![default];
+            for &(_, ref dest) in branches.iter() {
+                ret.push(dest);
+            }
+            ret
+        }
+        Instruction_::Watchpoint {
+            ref disable_dest,
+            ref resume,
+            ..
+        } => {
+            let mut ret = vec![];
+            if let Some(ref dest) = *disable_dest {
+                ret.push(dest);
+            }
+            ret.push(&resume.normal_dest);
+            ret.push(&resume.exn_dest);
+            ret
+        }
+        Instruction_::WPBranch {
+            ref disable_dest,
+            ref enable_dest,
+            ..
+        } => vec![disable_dest, enable_dest],
+        Instruction_::Call { ref resume, .. }
+        | Instruction_::CCall { ref resume, .. }
+        | Instruction_::SwapStackExc { ref resume, .. }
+        | Instruction_::ExnInstruction { ref resume, .. } => {
+            vec![&resume.normal_dest, &resume.exn_dest]
+        }
+        _ => vec![],
+    }
+}
+
+fn remove_unreachable_blocks(f_content: &mut FunctionContent, analysis: &Analysis) {
+    let unreachable: Vec<MuID> = f_content
+        .blocks
+        .keys()
+        .filter(|id| !analysis.reachable.contains(*id))
+        .cloned()
+        .collect();
+
+    for id in unreachable {
+        trace!("remove unreachable block {}", id);
+        f_content.blocks.remove(&id);
+    }
+}
+
+fn rewrite_blocks(vm: &VM, f_content: &mut FunctionContent, analysis: &Analysis) {
+    // SSA variables that we replace, with the operands to replace them
+    let mut replacement: LinkedHashMap<MuID, P<TreeNode>> = LinkedHashMap::new();
+    for (id, value) in analysis.defined.iter() {
+        if let Some(c) = analysis.get_replacement(*id) {
+            let constant = P(Value {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                ty: value.ty.clone(),
+                v: Value_::Constant(c),
+            });
+            trace!("replace {} with {}", value, constant);
+            replacement.insert(*id, TreeNode::new_value(constant));
+        }
+    }
+
+    // a SELECT on a known condition is its chosen operand
+    for block in f_content.blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            if let Instruction_::Select {
+                cond,
+                true_val,
+                false_val,
+            } = inst.v
+            {
+                let result = inst.value.as_ref().unwrap()[0].id();
+                if replacement.contains_key(&result) {
+                    continue;
+                }
+                if let Lattice::Const(ref c) = analysis.get(&inst.ops[cond]) {
+                    let chosen = if is_true(c) { true_val } else { false_val };
+                    trace!("replace {} with {}", result, inst.ops[chosen]);
+                    replacement.insert(result, inst.ops[chosen].clone());
+                }
+            }
+        }
+    }
+
+    for (_, block) in f_content.blocks.iter_mut() {
+        let block_content = block.content.as_mut().unwrap();
+
+        let mut new_body = vec![];
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+
+            // the uses of this instruction's results are replaced, and it does nothing else
+            let is_replaced = match inst.value {
+                Some(ref values) => {
+                    !inst.has_side_effect()
+                        && values.iter().all(|v| replacement.contains_key(&v.id()))
+                }
+                None => false,
+            };
+            if is_replaced {
+                trace!("remove {}", node);
+                continue;
+            }
+
+            let ops: Vec<P<TreeNode>> = inst
+                .ops
+                .iter()
+                .map(|op| get_replaced_op(op, &replacement))
+                .collect();
+
+            let new_inst = match inst.v {
+                Instruction_::Branch2 {
+                    cond,
+                    ref true_dest,
+                    ref false_dest,
+                    ..
+                } => match analysis.get(&inst.ops[cond]) {
+                    Lattice::Const(ref c) => {
+                        let dest = if is_true(c) { true_dest } else { false_dest };
+                        Some(branch_to(inst, dest, &ops))
+                    }
+                    _ => None,
+                },
+                Instruction_::Switch { .. } => match analysis.get_switch_dest(inst) {
+                    Some(dest) => Some(branch_to(inst, dest, &ops)),
+                    None => None,
+                },
+                _ => None,
+            };
+
+            let new_node = match new_inst {
+                Some(new_inst) => {
+                    let new_node = TreeNode::new_inst(new_inst);
+                    trace!("rewrite {} to {}", node, new_node);
+                    new_node
+                }
+                None => {
+                    if ops
+                        .iter()
+                        .zip(inst.ops.iter())
+                        .all(|(a, b)| Arc::ptr_eq(a, b))
+                    {
+                        node.clone()
+                    } else {
+                        let mut new_inst = inst.clone();
+                        new_inst.ops = ops;
+                        TreeNode::new_inst(new_inst)
+                    }
+                }
+            };
+            new_body.push(new_node);
+        }
+        block_content.body = new_body;
+
+        // constants do not need to be kept alive
+        if let Some(ref mut keepalives) = block_content.keepalives {
+            keepalives.retain(|v| !replacement.contains_key(&v.id()));
+        }
+    }
+}
+
+/// returns the operand to use in place of an operand
+fn get_replaced_op(
+    op: &P<TreeNode>,
+    replacement: &LinkedHashMap<MuID, P<TreeNode>>,
+) -> P<TreeNode> {
+    let mut ret = op.clone();
+    // a SELECT may choose the result of another SELECT
+    while let Some(id) = ret.extract_ssa_id() {
+        match replacement.get(&id) {
+            Some(new_op) => ret = new_op.clone(),
+            None => break,
+        }
+    }
+    ret
+}
+
+/// creates a BRANCH to the destination (that replaces the instruction)
+fn branch_to(inst: &Instruction, dest: &Destination, ops: &Vec<P<TreeNode>>) -> Instruction {
+    let mut new_ops = vec![];
+    let args = dest
+        .args
+        .iter()
+        .map(|arg| match arg {
+            &DestArg::Normal(i) => {
+                new_ops.push(ops[i].clone());
+                DestArg::Normal(new_ops.len() - 1)
+            }
+            &DestArg::Freshbound(n) => DestArg::Freshbound(n),
+        })
+        .collect();
+
+    Instruction {
+        hdr: inst.hdr.clone(),
+        value: None,
+        ops: new_ops,
+        v: Instruction_::Branch1(Destination {
+            target: dest.target.clone(),
+            args: args,
+        }),
+    }
+}
+
+/// returns a constant in the form we fold: integers are masked to their width, and
+/// integers wider than 64 bits are IntEx. Returns None for constants we do not fold
+fn normalize_constant(c: &Constant, ty: &P<MuType>) -> Option<Constant> {
+    match c {
+        &Constant::Int(_) | &Constant::IntEx(_) => match ty.get_int_length() {
+            Some(width) => int_of(c, width).map(|v| int_constant(v, width)),
+            None => None,
+        },
+        &Constant::Float(_) | &Constant::Double(_) | &Constant::NullRef => Some(c.clone()),
+        _ => None,
+    }
+}
+
+/// returns the integer value of a constant (masked to the width)
+fn int_of(c: &Constant, width: usize) -> Option<u128> {
+    if width == 0 || width > 128 {
+        return None;
+    }
+    let v = match c {
+        &Constant::Int(v) => v as u128,
+        &Constant::IntEx(ref v) if v.len() == 1 => v[0] as u128,
+        &Constant::IntEx(ref v) if v.len() == 2 => (v[0] as u128) | ((v[1] as u128) << 64),
+        &Constant::NullRef => 0,
+        _ => return None,
+    };
+    Some(mask(v, width))
+}
+
+/// creates an integer constant of the width
+fn int_constant(v: u128, width: usize) -> Constant {
+    let v = mask(v, width);
+    if width > 64 {
+        Constant::IntEx(vec![v as u64, (v >> 64) as u64])
+    } else {
+        Constant::Int(v as u64)
+    }
+}
+
+fn mask(v: u128, width: usize) -> u128 {
+    if width >= 128 {
+        v
+    } else {
+        v & ((1u128 << width) - 1)
+    }
+}
+
+/// interprets an integer of the width as signed
+fn signed(v: u128, width: usize) -> i128 {
+    if width >= 128 {
+        v as i128
+    } else {
+        ((v << (128 - width)) as i128) >> (128 - width)
+    }
+}
+
+fn is_true(c: &Constant) -> bool {
+    int_of(c, 1) == Some(1)
+}
+
+fn fp_of(c: &Constant) -> Option<f64> {
+    match c {
+        &Constant::Float(v) => Some(v as f64),
+        &Constant::Double(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn fold_binop(
+    op: BinOp,
+    ty: &P<MuType>,
+    op2_ty: &P<MuType>,
+    a: &Constant,
+    b: &Constant,
+) -> Option<Constant> {
+    if op.is_fp() {
+        return match (a, b) {
+            (&Constant::Float(x), &Constant::Float(y)) => Some(Constant::Float(match op {
+                BinOp::FAdd => x + y,
+                BinOp::FSub => x - y,
+                BinOp::FMul => x * y,
+                BinOp::FDiv => x / y,
+                BinOp::FRem => x % y,
+                _ => unreachable!(),
+            })),
+            (&Constant::Double(x), &Constant::Double(y)) => Some(Constant::Double(match op {
+                BinOp::FAdd => x + y,
+                BinOp::FSub => x - y,
+                BinOp::FMul => x * y,
+                BinOp::FDiv => x / y,
+                BinOp::FRem => x % y,
+                _ => unreachable!(),
+            })),
+            _ => None,
+        };
+    }
+
+    if !ty.is_int() {
+        return None;
+    }
+    let width = ty.get_int_length().unwrap();
+    let x = int_of(a, width)?;
+    // shift amounts may be of a different type
+    let y = int_of(b, op2_ty.get_int_length()?)?;
+
+    let min_signed = signed(1u128 << (width - 1), width);
+    let res = match op {
+        BinOp::Add => x.wrapping_add(y),
+        BinOp::Sub => x.wrapping_sub(y),
+        BinOp::Mul => x.wrapping_mul(y),
+        BinOp::Udiv | BinOp::Urem if y == 0 => return None,
+        BinOp::Udiv => x / y,
+        BinOp::Urem => x % y,
+        BinOp::Sdiv | BinOp::Srem => {
+            let (sx, sy) = (signed(x, width), signed(y, width));
+            if sy == 0 || (sx == min_signed && sy == -1) {
+                return None;
+            }
+            if op == BinOp::Sdiv {
+                (sx / sy) as u128
+            } else {
+                (sx % sy) as u128
+            }
+        }
+        BinOp::And => x & y,
+        BinOp::Or => x | y,
+        BinOp::Xor => x ^ y,
+        BinOp::Shl => x << (y % width as u128),
+        BinOp::Lshr => x >> (y % width as u128),
+        BinOp::Ashr => (signed(x, width) >> (y % width as u128)) as u128,
+        _ => return None,
+    };
+
+    Some(int_constant(res, width))
+}
+
+fn fold_cmpop(
+    op: CmpOp,
+    ty: &P<MuType>,
+    op_ty: &P<MuType>,
+    a: &Constant,
+    b: &Constant,
+) -> Option<Constant> {
+    if !ty.is_int_n(1) {
+        return None;
+    }
+
+    let res = if op.is_int_cmp() {
+        let width = op_ty.get_int_length()?;
+        let x = int_of(a, width)?;
+        let y = int_of(b, width)?;
+        let (sx, sy) = (signed(x, width), signed(y, width));
+        match op {
+            CmpOp::EQ => x == y,
+            CmpOp::NE => x != y,
+            CmpOp::SGE => sx >= sy,
+            CmpOp::SGT => sx > sy,
+            CmpOp::SLE => sx <= sy,
+            CmpOp::SLT => sx < sy,
+            CmpOp::UGE => x >= y,
+            CmpOp::UGT => x > y,
+            CmpOp::ULE => x <= y,
+            CmpOp::ULT => x < y,
+            _ => unreachable!(),
+        }
+    } else {
+        let x = fp_of(a)?;
+        let y = fp_of(b)?;
+        let unordered = x.is_nan() || y.is_nan();
+        match op {
+            CmpOp::FFALSE => false,
+            CmpOp::FTRUE => true,
+            CmpOp::FOEQ => !unordered && x == y,
+            CmpOp::FOGT => !unordered && x > y,
+            CmpOp::FOGE => !unordered && x >= y,
+            CmpOp::FOLT => !unordered && x < y,
+            CmpOp::FOLE => !unordered && x <= y,
+            CmpOp::FONE => !unordered && x != y,
+            CmpOp::FORD => !unordered,
+            CmpOp::FUEQ => unordered || x == y,
+            CmpOp::FUGT => unordered || x > y,
+            CmpOp::FUGE => unordered || x >= y,
+            CmpOp::FULT => unordered || x < y,
+            CmpOp::FULE => unordered || x <= y,
+            CmpOp::FUNE => unordered || x != y,
+            CmpOp::FUNO => unordered,
+            _ => unreachable!(),
+        }
+    };
+
+    Some(Constant::Int(res as u64))
+}
+
+fn fold_convop(
+    operation: ConvOp,
+    from_ty: &P<MuType>,
+    to_ty: &P<MuType>,
+    c: &Constant,
+) -> Option<Constant> {
+    match operation {
+        ConvOp::TRUNC | ConvOp::ZEXT | ConvOp::SEXT => {
+            if !from_ty.is_int() || !to_ty.is_int() {
+                return None;
+            }
+            let from_width = from_ty.get_int_length().unwrap();
+            let to_width = to_ty.get_int_length().unwrap();
+            let v = int_of(c, from_width)?;
+            let v = if operation == ConvOp::SEXT {
+                signed(v, from_width) as u128
+            } else {
+                v
+            };
+            Some(int_constant(v, to_width))
+        }
+        ConvOp::FPTRUNC => match c {
+            &Constant::Double(v) if to_ty.is_float() => Some(Constant::Float(v as f32)),
+            _ => None,
+        },
+        ConvOp::FPEXT => match c {
+            &Constant::Float(v) if to_ty.is_double() => Some(Constant::Double(v as f64)),
+            _ => None,
+        },
+        ConvOp::FPTOUI | ConvOp::FPTOSI => {
+            let width = match to_ty.v {
+                MuType_::Int(width) if width <= 64 => width,
+                _ => return None,
+            };
+            let v = fp_of(c)?;
+            if v.is_nan() {
+                return None;
+            }
+            let v = v.trunc();
+            let limit = 2f64.powi(width as i32);
+            if operation == ConvOp::FPTOUI {
+                if v < 0f64 || v >= limit {
+                    return None;
+                }
+                Some(int_constant(v as u64 as u128, width))
+            } else {
+                if v < -limit / 2f64 || v >= limit / 2f64 {
+                    return None;
+                }
+                Some(int_constant(v as i64 as u128, width))
+            }
+        }
+        ConvOp::UITOFP | ConvOp::SITOFP => {
+            let width = match from_ty.v {
+                MuType_::Int(width) if width <= 64 => width,
+                _ => return None,
+            };
+            let v = int_of(c, width)?;
+            if operation == ConvOp::UITOFP {
+                let v = v as u64;
+                if to_ty.is_float() {
+                    Some(Constant::Float(v as f32))
+                } else if to_ty.is_double() {
+                    Some(Constant::Double(v as f64))
+                } else {
+                    None
+                }
+            } else {
+                let v = signed(v, width) as i64;
+                if to_ty.is_float() {
+                    Some(Constant::Float(v as f32))
+                } else if to_ty.is_double() {
+                    Some(Constant::Double(v as f64))
+                } else {
+                    None
+                }
+            }
+        }
+        ConvOp::BITCAST => match c {
+            &Constant::Float(v) if to_ty.is_int() && to_ty.is_int_n(32) => {
+                Some(Constant::Int(v.to_bits() as u64))
+            }
+            &Constant::Double(v) if to_ty.is_int() && to_ty.is_int_n(64) => {
+                Some(Constant::Int(v.to_bits()))
+            }
+            &Constant::Int(v) if from_ty.is_int() && from_ty.is_int_n(32) && to_ty.is_float() => {
+                Some(Constant::Float(f32::from_bits(v as u32)))
+            }
+            &Constant::Int(v) if from_ty.is_int() && from_ty.is_int_n(64) && to_ty.is_double() => {
+                Some(Constant::Double(f64::from_bits(v)))
+            }
+            _ => None,
+        },
+        ConvOp::REFCAST | ConvOp::PTRCAST => None,
+    }
+}
@@ -86,6 +86,8 @@ fn is_suitable_child(inst: &Instruction) -> bool {
         | CommonInst_Pin(_)
         | CommonInst_Unpin(_)
         | CommonInst_GetAddr(_)
+        | CommonInst_RegisterFinalizer(_)
+        | CommonInst_GetFinalizable
         | CmpXchg { .. }
         | AtomicRMW { .. }
         | Store { .. }
@@ -136,6 +138,11 @@ impl CompilerPass for TreeGen {
         // because of SSA form,  it is guaranteed to see 1 before 2 for SSA variables.
         debug!("---CompilerPass {} for {}---", self.name(), func);
 
+        // if the GC may run, a reference that a derived pointer comes from needs to stay in
+        // an SSA variable, so that the register allocator can keep it alive with the
+        // derived pointer (see reg_alloc::stack_map)
+        let keep_bases = !vm.vm_options.flag_gc_disable_collection;
+
         {
             let ref mut func_content = func.content;
             let ref mut context = func.context;
@@ -152,7 +159,7 @@ impl CompilerPass for TreeGen {
                 for i in 0..body.len() {
                     let node = body[i].clone();
                     let new_node = insert_inst_as_child(node, context);
-                    if !is_child_inst(&new_node, i, &body, context) {
+                    if !is_child_inst(&new_node, i, &body, context, keep_bases) {
                         new_body.push(new_node);
                     }
                     trace!("");
@@ -191,6 +198,7 @@ fn is_child_inst(
     cur_index: usize,
     body: &Vec<P<TreeNode>>,
     context: &mut FunctionContext,
+    keep_bases: bool,
 ) -> bool {
     trace!("is child inst: {}", node);
     match node.v {
@@ -201,6 +209,7 @@ fn is_child_inst(
             // * the value is used only once
             // * the instruction is movable
             // * the value is used in the next instruction
+            // * the value is not the base of a derived pointer (if we keep bases)
             if inst.value.is_some() {
                 let left = inst.value.as_ref().unwrap();
 
@@ -216,6 +225,9 @@ fn is_child_inst(
                             if cur_index != body.len() - 1 {
                                 let ref next_inst = body[cur_index + 1].as_inst();
                                 next_inst.ops.iter().any(|x| x.as_value() == val_lhs)
+                                    && !(keep_bases
+                                        && val_lhs.ty.is_ref()
+                                        && next_inst.is_derived_pointer())
                             } else {
                                 false
                             }
@@ -48,7 +48,7 @@ impl HeapDump {
 
             if !heap.objects.contains_key(&obj) {
                 // add this object to heap dump
-                let obj_dump = heap.persist_object(obj);
+                let obj_dump = ObjectDump::new(obj);
                 heap.objects.insert(obj, obj_dump);
 
                 heap.keep_tracing(heap.objects.get(&obj).unwrap(), &mut work_queue);
@@ -60,8 +60,42 @@ impl HeapDump {
         heap
     }
 
-    #[allow(unused_variables)]
-    fn persist_object(&self, obj: Address) -> ObjectDump {
+    fn keep_tracing(&self, obj_dump: &ObjectDump, work_queue: &mut Vec<Address>) {
+        let base = obj_dump.addr;
+
+        for offset in obj_dump.reference_offsets.iter() {
+            let field_addr = base + *offset;
+            let edge = unsafe { field_addr.load::<Address>() };
+
+            trace!(
+                "object reference from {} -> {} at +[{}]",
+                base,
+                edge,
+                offset
+            );
+
+            if !edge.is_zero() && !self.objects.contains_key(&edge) {
+                work_queue.push(edge);
+            }
+        }
+    }
+
+    fn label_relocatable_refs(&mut self) {
+        let mut count = 0;
+
+        for addr in self.objects.keys() {
+            let label = format!("GCDUMP_{}_{}", count, addr);
+            self.relocatable_refs.insert(*addr, label);
+
+            count += 1;
+        }
+    }
+}
+
+impl ObjectDump {
+    /// dumps an object (its size, alignment, encode and where its references are).
+    /// The object needs to have a valid encode
+    pub fn new(obj: Address) -> ObjectDump {
         trace!("dump object: {}", obj);
 
         match SpaceDescriptor::get(unsafe { obj.to_object_reference() }) {
@@ -167,37 +201,6 @@ impl HeapDump {
             SpaceDescriptor::Immortal => unimplemented!(),
         }
     }
-
-    fn keep_tracing(&self, obj_dump: &ObjectDump, work_queue: &mut Vec<Address>) {
-        let base = obj_dump.addr;
-
-        for offset in obj_dump.reference_offsets.iter() {
-            let field_addr = base + *offset;
-            let edge = unsafe { field_addr.load::<Address>() };
-
-            trace!(
-                "object reference from {} -> {} at +[{}]",
-                base,
-                edge,
-                offset
-            );
-
-            if !edge.is_zero() && !self.objects.contains_key(&edge) {
-                work_queue.push(edge);
-            }
-        }
-    }
-
-    fn label_relocatable_refs(&mut self) {
-        let mut count = 0;
-
-        for addr in self.objects.keys() {
-            let label = format!("GCDUMP_{}_{}", count, addr);
-            self.relocatable_refs.insert(*addr, label);
-
-            count += 1;
-        }
-    }
 }
 
 use std::fmt;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use common::ptr::*;
+use heap::gc::policy::{self, AllocRetry};
 use heap::*;
 use objectmodel::sidemap::*;
 use std::ptr;
@@ -33,12 +34,18 @@ impl Allocator for FreelistAllocator {
     }
 
     fn alloc(&mut self, size: ByteSize, align: ByteSize) -> Address {
+        let mut retry = AllocRetry::new();
+
         loop {
             unsafe { &mut *self.mutator }.yieldpoint();
 
             let ret = self.space.alloc(size, align);
 
             if ret.is_zero() {
+                if retry.is_out_of_memory() {
+                    policy::out_of_memory();
+                    return unsafe { Address::zero() };
+                }
                 gc::trigger_gc();
             } else {
                 return ret;
@@ -55,6 +62,12 @@ impl FreelistAllocator {
         }
     }
 
+    /// returns the space this allocator allocates from
+    #[inline(always)]
+    pub fn space(&self) -> &FreelistSpace {
+        &self.space
+    }
+
     pub fn init_object(&mut self, addr: Address, encode: LargeObjectEncode) {
         let slot = self.space.get_type_encode_slot(addr);
         unsafe {
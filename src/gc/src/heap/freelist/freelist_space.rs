@@ -13,16 +13,18 @@
 // limitations under the License.
 
 use common::ptr::*;
+use heap::gc::policy;
 use heap::*;
 use objectmodel::sidemap::*;
 use utils::mem::memsec::memzero;
 use utils::mem::*;
 
 use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 
 const LOG_BYTES_IN_PAGE: usize = 12;
-const BYTES_IN_PAGE: ByteSize = 1 << LOG_BYTES_IN_PAGE; // 4KB
+pub const BYTES_IN_PAGE: ByteSize = 1 << LOG_BYTES_IN_PAGE; // 4KB
 
 // 4M pages
 const PAGES_IN_SPACE: usize = 1 << (LOG_BYTES_PREALLOC_SPACE - LOG_BYTES_IN_PAGE);
@@ -49,6 +51,8 @@ pub struct FreelistSpace {
     // 32 bytes
     pub last_gc_free_pages: usize,
     pub last_gc_used_pages: usize,
+    // bytes allocated (in pages)
+    bytes_allocated: AtomicUsize,
 
     // 16 bytes
     mmap_start: Address,
@@ -145,12 +149,6 @@ impl Space for FreelistSpace {
         self.last_gc_free_pages = free_pages;
         self.last_gc_used_pages = used_pages;
 
-        if self.n_used_pages() == self.total_pages && self.total_pages != 0 {
-            use std::process;
-            println!("Out of memory in Freelist Space");
-            process::exit(1);
-        }
-
         debug_assert_eq!(self.n_used_pages() + self.n_usable_pages(), self.cur_pages);
 
         trace!("=======================");
@@ -218,6 +216,10 @@ impl FreelistSpace {
         space.mmap_size = mmap_size;
         trace!("    store mmap");
 
+        space.last_gc_free_pages = 0;
+        space.last_gc_used_pages = 0;
+        space.bytes_allocated = AtomicUsize::new(0);
+
         debug_assert_eq!(Address::from_ptr(&space.mem as *const [u8; 0]), mem_start);
 
         space.trace_details();
@@ -250,7 +252,7 @@ impl FreelistSpace {
         } else {
             // we will need to allocate new memory
             let pages_required = size >> LOG_BYTES_IN_PAGE;
-            if self.cur_pages + pages_required <= self.total_pages {
+            if self.cur_pages + pages_required <= self.total_pages && policy::commit(size) {
                 // we have enough pages
                 let start = self.cur_end;
 
@@ -267,6 +269,7 @@ impl FreelistSpace {
         if let Some(node) = opt_node {
             let res = node.addr;
             self.used_nodes.lock().unwrap().push(node);
+            self.bytes_allocated.fetch_add(size, Ordering::Relaxed);
 
             // zero the pages
             unsafe {
@@ -279,6 +282,49 @@ impl FreelistSpace {
         }
     }
 
+    /// gives free nodes at the end of the space back to the OS (up to 'bytes'), after a sweep.
+    /// Returns the bytes released
+    pub fn release_free_pages(&mut self, bytes: ByteSize) -> ByteSize {
+        let mut released = 0;
+        {
+            let mut usable_nodes = self.usable_nodes.lock().unwrap();
+            loop {
+                // the last node of the space, it needs to be free
+                let cur_end = self.cur_end;
+                let index = match usable_nodes
+                    .iter()
+                    .position(|node| node.addr + node.size == cur_end)
+                {
+                    Some(index) => index,
+                    None => break,
+                };
+                if released + usable_nodes[index].size > bytes {
+                    break;
+                }
+                let node = usable_nodes.swap_remove(index);
+
+                // the memory will read as zero when we grow into it again
+                decommit(node.addr, node.size);
+
+                self.cur_end = node.addr;
+                self.cur_size -= node.size;
+                self.cur_pages -= node.size >> LOG_BYTES_IN_PAGE;
+                released += node.size;
+            }
+        }
+        if released != 0 {
+            trace!("{:?} released {} bytes", self.desc, released);
+        }
+
+        policy::uncommit(released);
+        released
+    }
+
+    /// returns bytes allocated in the space (in pages)
+    pub fn bytes_allocated(&self) -> ByteSize {
+        self.bytes_allocated.load(Ordering::Relaxed)
+    }
+
     pub fn n_used_pages(&self) -> usize {
         let lock = self.used_nodes.lock().unwrap();
         let mut ret = 0;
@@ -309,6 +355,96 @@ impl FreelistSpace {
         Address::from_ptr(&self.page_encode_table[index] as *const LargeObjectEncode)
     }
 
+    /// checks if the address is the start of a used node (where a large object starts)
+    pub fn is_used_node_start(&self, addr: Address) -> bool {
+        self.used_nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|node| node.addr == addr)
+    }
+
+    /// checks the node lists and the page tables for the heap verifier. Nodes should not
+    /// overlap, and every used node should hold a large object with a valid encode
+    /// (and should be marked after a sweep). Returns the problems found
+    pub fn verify_metadata(&self, after_sweep: bool) -> Vec<String> {
+        let mut problems = vec![];
+
+        let used_nodes = self.used_nodes.lock().unwrap();
+        let usable_nodes = self.usable_nodes.lock().unwrap();
+
+        let mut all_nodes: Vec<(Address, ByteSize)> = vec![];
+        let mut pages = 0;
+        for node in used_nodes.iter().chain(usable_nodes.iter()) {
+            if node.addr < self.start
+                || node.addr + node.size > self.cur_end
+                || !node.addr.is_aligned_to(BYTES_IN_PAGE)
+                || node.size == 0
+                || node.size % BYTES_IN_PAGE != 0
+            {
+                problems.push(format!(
+                    "{:?}: node {} ({} bytes) is not in the space",
+                    self.desc, node.addr, node.size
+                ));
+                continue;
+            }
+            pages += node.size >> LOG_BYTES_IN_PAGE;
+            all_nodes.push((node.addr, node.size));
+        }
+        if pages != self.cur_pages {
+            problems.push(format!(
+                "{:?}: nodes have {} pages, but the space has {} pages",
+                self.desc, pages, self.cur_pages
+            ));
+        }
+
+        all_nodes.sort_by_key(|&(addr, _)| addr);
+        for pair in all_nodes.windows(2) {
+            let (addr, size) = pair[0];
+            if addr + size > pair[1].0 {
+                problems.push(format!(
+                    "{:?}: node {} ({} bytes) overlaps with node {}",
+                    self.desc, addr, size, pair[1].0
+                ));
+            }
+        }
+
+        for node in used_nodes.iter() {
+            if node.addr < self.start || node.addr >= self.cur_end {
+                continue;
+            }
+            let index = self.get_page_index(node.addr);
+            let encode = self.page_encode_table[index];
+            if !GlobalTypeTable::has_full_entry(encode.type_id()) {
+                problems.push(format!(
+                    "{:?}: object {} has an invalid type id ({:?})",
+                    self.desc, node.addr, encode
+                ));
+            } else if encode.size() > node.size {
+                problems.push(format!(
+                    "{:?}: object {} ({} bytes) exceeds its node ({} bytes)",
+                    self.desc,
+                    node.addr,
+                    encode.size(),
+                    node.size
+                ));
+            }
+
+            // read the mark as a byte, it may not be a valid enum value
+            let page_mark = unsafe {
+                Address::from_ptr(&self.page_mark_table[index] as *const PageMark).load::<u8>()
+            };
+            if after_sweep && page_mark != PageMark::Live as u8 {
+                problems.push(format!(
+                    "{:?}: used node {} is not marked after sweep (page mark {})",
+                    self.desc, node.addr, page_mark
+                ));
+            }
+        }
+
+        problems
+    }
+
     fn trace_details(&self) {
         trace!("=== {:?} ===", self.desc);
         trace!(
@@ -16,3 +16,4 @@ mod freelist_space;
 
 pub use self::freelist_mutator::FreelistAllocator;
 pub use self::freelist_space::FreelistSpace;
+pub use self::freelist_space::BYTES_IN_PAGE;
@@ -0,0 +1,155 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finalisation.
+//!
+//! An object registered for finalisation is watched by the GC. When a GC finds it
+//! unreachable, the GC resurrects it (and everything it refers to), and puts it in the
+//! finalisation queue. A finaliser thread takes objects from the queue with
+//! get_finalizable(), and runs whatever clean-up code the client wants for them.
+//!
+//! In Zebu, objects are registered with the common instruction
+//! @uvm.gc.register_finalizer (or register_finalizer() in the client API). The client
+//! starts a dedicated Mu thread that loops on @uvm.gc.get_finalizable: it returns null
+//! after a short wait if nothing is queued, so the thread can check whether it should quit.
+//!
+//! Ordering guarantees:
+//!
+//! * weak references to an object are cleared before the object is queued (in the same GC
+//!   that finds the object unreachable). Weak references met while resurrecting objects are
+//!   processed as usual.
+//! * an object is queued at most once. It is no longer registered once it is queued, so if
+//!   it becomes unreachable again after finalisation, it is simply reclaimed (unless it is
+//!   registered again).
+//! * objects found unreachable in one GC are queued together, but in no specified order.
+//!   A finaliser should not assume that objects it refers to have or have not been
+//!   finalised.
+//! * queued objects are roots until the finaliser thread takes them.
+//! * an old registered object (one that survived a GC) is only found unreachable by a
+//!   major GC.
+
+use super::{get_forwarded_object, is_object_alive};
+use heap::Mutator;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use utils::*;
+
+lazy_static! {
+    /// objects registered for finalisation (that are still reachable)
+    static ref FINALIZABLE: Mutex<Vec<ObjectReference>> = Mutex::new(vec![]);
+    /// unreachable objects waiting for their finalisers
+    static ref FINALIZE_QUEUE: (Mutex<VecDeque<ObjectReference>>, Condvar) =
+        (Mutex::new(VecDeque::new()), Condvar::new());
+}
+
+/// how long get_finalizable() waits for the queue before it returns null (in ms)
+pub const FINALIZER_WAIT_MS: u64 = 10;
+/// how long get_finalizable() waits on the queue before it checks for a GC (in ms)
+const FINALIZER_POLL_MS: u64 = 1;
+
+pub fn init() {
+    FINALIZABLE.lock().unwrap().clear();
+    FINALIZE_QUEUE.0.lock().unwrap().clear();
+}
+
+/// registers an object for finalisation
+pub fn register_finalizer(obj: ObjectReference) {
+    trace!("register {} for finalisation", obj);
+    FINALIZABLE.lock().unwrap().push(obj);
+}
+
+/// takes an object from the finalisation queue if there is one
+pub fn try_get_finalizable() -> Option<ObjectReference> {
+    FINALIZE_QUEUE.0.lock().unwrap().pop_front()
+}
+
+/// takes an object from the finalisation queue. If the queue is empty, it waits for
+/// FINALIZER_WAIT_MS, and returns a null reference if still nothing is queued.
+/// The mutator keeps taking yieldpoints while it waits, so it does not hold up a GC
+pub fn get_finalizable(mutator: &mut Mutator) -> ObjectReference {
+    let &(ref lock, ref cvar) = &*FINALIZE_QUEUE;
+
+    let mut waited = 0;
+    loop {
+        {
+            let queue = lock.lock().unwrap();
+            let mut queue = if queue.is_empty() {
+                cvar.wait_timeout(queue, Duration::from_millis(FINALIZER_POLL_MS))
+                    .unwrap()
+                    .0
+            } else {
+                queue
+            };
+            if let Some(obj) = queue.pop_front() {
+                trace!("Mutator{}: get {} for finalisation", mutator.id(), obj);
+                return obj;
+            }
+        }
+
+        waited += FINALIZER_POLL_MS;
+        if waited >= FINALIZER_WAIT_MS {
+            return unsafe { Address::zero().to_object_reference() };
+        }
+        // the GC may be waiting for us
+        mutator.yieldpoint();
+    }
+}
+
+/// queued objects are roots (they need to stay alive until they are finalised)
+pub fn add_finalizer_roots(roots: &mut Vec<ObjectReference>) {
+    roots.extend(FINALIZE_QUEUE.0.lock().unwrap().iter());
+}
+
+/// finds registered objects that are not reachable after tracing. They are removed from
+/// registration and returned. Registered objects that survive and get evacuated are updated
+/// to their new locations (must be called after processing weak references)
+pub fn find_unreachable_finalizable(major: bool) -> Vec<ObjectReference> {
+    let mut registered = FINALIZABLE.lock().unwrap();
+    let mut unreachable = vec![];
+
+    let mut i = 0;
+    while i < registered.len() {
+        let obj = registered[i];
+
+        // only a major GC evacuates objects (a minor GC may see stale forwarded bits)
+        let forwarded = if major {
+            get_forwarded_object(obj)
+        } else {
+            None
+        };
+
+        if let Some(new) = forwarded {
+            registered[i] = new;
+            i += 1;
+        } else if is_object_alive(obj) {
+            i += 1;
+        } else {
+            unreachable.push(registered.swap_remove(i));
+        }
+    }
+
+    trace!(
+        "{} objects registered for finalisation are unreachable",
+        unreachable.len()
+    );
+    unreachable
+}
+
+/// puts resurrected objects into the finalisation queue, and wakes the finaliser thread
+pub fn queue_finalizable(objs: Vec<ObjectReference>) {
+    let &(ref lock, ref cvar) = &*FINALIZE_QUEUE;
+    lock.lock().unwrap().extend(objs);
+    cvar.notify_all();
+}
@@ -16,23 +16,62 @@ use heap::*;
 use objectmodel::*;
 use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex, RwLock};
+use GC;
+use GC_MOVES_OBJECT;
 use MY_GC;
 
 use crossbeam::sync::chase_lev::*;
+use std::cell::RefCell;
 use std::mem::transmute;
 use std::sync::atomic;
 use std::sync::mpsc;
 use std::sync::mpsc::channel;
 use std::thread;
+use std::time::Instant;
+
+pub mod finalizer;
+pub mod policy;
+pub mod stats;
+pub mod verify;
+pub use self::finalizer::{
+    get_finalizable, register_finalizer, try_get_finalizable, FINALIZER_WAIT_MS,
+};
+pub use self::policy::{set_heap_policy, set_oom_handler, HeapPolicy};
+pub use self::verify::{set_verify_heap, VerifyPhase};
 
 lazy_static! {
     static ref STW_COND: Arc<(Mutex<usize>, Condvar)> =
         { Arc::new((Mutex::new(0), Condvar::new())) };
     static ref ROOTS: RwLock<Vec<ObjectReference>> = RwLock::new(vec![]);
+    /// weak reference fields met during tracing (collected from all gc threads)
+    static ref WEAK_REF_FIELDS: Mutex<Vec<Address>> = Mutex::new(vec![]);
+    /// slots that may hold references from old objects to young objects (logged by the
+    /// write barrier, and flushed from mutators before a GC)
+    static ref REMEMBERED_SET: Mutex<Vec<(Address, WordType)>> = Mutex::new(vec![]);
+    /// precise stack scanner for the current thread (set by the VM)
+    static ref STACK_SCANNER: RwLock<Option<fn() -> Option<Vec<ObjectReference>>>> =
+        RwLock::new(None);
+    /// scanner for stacks that are not running on any thread (set by the VM)
+    static ref INACTIVE_STACK_SCANNER: RwLock<Option<fn() -> Vec<ObjectReference>>> =
+        RwLock::new(None);
 }
 
 pub static ENABLE_GC: AtomicBool = AtomicBool::new(false);
 
+/// is the GC generational (otherwise every GC is a major GC)?
+static GENERATIONAL: AtomicBool = AtomicBool::new(true);
+/// the next GC will be a major GC
+static MAJOR_GC_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// current (or last) GC is a major GC
+static MAJOR_GC: AtomicBool = AtomicBool::new(true);
+/// the next stop-the-world pause verifies the heap instead of collecting
+static VERIFY_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// some stacks are scanned conservatively in current pause (their roots may not be objects)
+static CONSERVATIVE_STACK_ROOTS: AtomicBool = AtomicBool::new(false);
+/// if a minor GC leaves less free lines than this ratio in an immix space,
+/// the next GC will be a major GC
+const MAJOR_GC_FREE_RATIO: f64 = 0.25;
+
 static CONTROLLER: AtomicIsize = AtomicIsize::new(0);
 const NO_CONTROLLER: isize = -1;
 
@@ -40,6 +79,15 @@ pub fn init(n_gcthreads: usize) {
     CONTROLLER.store(NO_CONTROLLER, Ordering::SeqCst);
     GC_THREADS.store(n_gcthreads, Ordering::SeqCst);
     GC_COUNT.store(0, Ordering::SeqCst);
+    GENERATIONAL.store(true, Ordering::SeqCst);
+    MAJOR_GC_REQUESTED.store(false, Ordering::SeqCst);
+    MAJOR_GC.store(true, Ordering::SeqCst);
+    VERIFY_REQUESTED.store(false, Ordering::SeqCst);
+    CONSERVATIVE_STACK_ROOTS.store(false, Ordering::SeqCst);
+    REMEMBERED_SET.lock().unwrap().clear();
+    finalizer::init();
+    stats::init();
+    verify::init();
 }
 
 pub fn trigger_gc() {
@@ -52,6 +100,34 @@ pub fn trigger_gc() {
     }
 }
 
+/// turns on/off generational collection. This needs to be set before any reference is stored
+/// to the heap (the write barrier does nothing if the GC is not generational)
+pub fn set_generational(enabled: bool) {
+    GENERATIONAL.store(enabled, Ordering::SeqCst);
+}
+
+/// is the GC generational?
+pub fn is_generational() -> bool {
+    GENERATIONAL.load(Ordering::SeqCst)
+}
+
+/// makes the next GC a major GC (which collects the whole heap)
+pub fn request_major_gc() {
+    MAJOR_GC_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// is current GC a major GC?
+pub fn is_major_gc() -> bool {
+    MAJOR_GC.load(Ordering::SeqCst)
+}
+
+/// stops the world to verify the heap (without collecting). The current mutator needs to
+/// reach a yieldpoint after this
+pub fn request_heap_verification() {
+    VERIFY_REQUESTED.store(true, Ordering::SeqCst);
+    trigger_gc();
+}
+
 #[cfg(target_arch = "x86_64")]
 #[link(name = "gc_clib_x64")]
 extern "C" {
@@ -72,6 +148,72 @@ extern "C" {
     fn get_registers_count() -> i32;
 }
 
+/// sets a precise stack scanner. The scanner is called by every mutator thread in sync_barrier()
+/// to scan its own stack. If the scanner returns None, we fall back to conservative stack_scan()
+pub fn set_stack_scanner(scanner: fn() -> Option<Vec<ObjectReference>>) {
+    *STACK_SCANNER.write().unwrap() = Some(scanner);
+}
+
+/// sets a scanner for inactive stacks (stacks that are not running on any thread).
+/// The scanner is called by the controller after all mutators stopped
+pub fn set_inactive_stack_scanner(scanner: fn() -> Vec<ObjectReference>) {
+    *INACTIVE_STACK_SCANNER.write().unwrap() = Some(scanner);
+}
+
+/// scans the stack of current thread, precisely if we have a stack scanner that is able to
+fn scan_current_stack() -> Vec<ObjectReference> {
+    let (roots, conservative) = snapshot_current_stack();
+    if conservative {
+        CONSERVATIVE_STACK_ROOTS.store(true, Ordering::SeqCst);
+    }
+    roots
+}
+
+/// scans the stack of current thread (without marking current pause as conservative),
+/// returns the roots and whether they are found by conservative scanning.
+/// A mutator entering native uses this to record its roots for later collections
+pub fn snapshot_current_stack() -> (Vec<ObjectReference>, bool) {
+    let scanner = *STACK_SCANNER.read().unwrap();
+    let precise = match scanner {
+        Some(scanner) => scanner(),
+        None => None,
+    };
+    match precise {
+        Some(roots) => {
+            trace!("roots: {} from precise stack scanning", roots.len());
+            (roots, false)
+        }
+        None => (stack_scan(), true),
+    }
+}
+
+/// conservatively scans the memory in [start, end) for roots. A precise stack scanner uses
+/// this for a stack that it cannot walk (e.g. it has native frames)
+pub fn scan_range_conservatively(start: Address, end: Address) -> Vec<ObjectReference> {
+    CONSERVATIVE_STACK_ROOTS.store(true, Ordering::SeqCst);
+
+    let gccontext_guard = MY_GC.read().unwrap();
+    let gccontext = gccontext_guard.as_ref().unwrap();
+
+    let mut ret = vec![];
+    let mut cursor = start.align_up(POINTER_SIZE);
+    while cursor + POINTER_SIZE <= end {
+        let value: Address = unsafe { cursor.load::<Address>() };
+        if gccontext.is_heap_object(value) {
+            ret.push(unsafe { value.to_object_reference() });
+        }
+        cursor = cursor + POINTER_SIZE;
+    }
+
+    trace!(
+        "roots: {} from conservatively scanning 0x{:x} - 0x{:x}",
+        ret.len(),
+        start,
+        end
+    );
+    ret
+}
+
 pub fn stack_scan() -> Vec<ObjectReference> {
     trace!("stack scanning...");
     let stack_ptr: Address = unsafe { immmix_get_stack_ptr() };
@@ -147,8 +289,8 @@ pub fn sync_barrier(mutator: &mut Mutator) {
 
     if controller_id != NO_CONTROLLER {
         // scan its stack
-        {
-            let mut thread_roots = stack_scan();
+        if mutator.has_stack_roots() {
+            let mut thread_roots = scan_current_stack();
             ROOTS.write().unwrap().append(&mut thread_roots);
         }
 
@@ -160,11 +302,12 @@ pub fn sync_barrier(mutator: &mut Mutator) {
     } else {
         // this thread is controller
         // other threads should block
+        let pause_start = Instant::now();
 
         // init roots
-        {
+        if mutator.has_stack_roots() {
             // scan its stack
-            let mut thread_roots = stack_scan();
+            let mut thread_roots = scan_current_stack();
             ROOTS.write().unwrap().append(&mut thread_roots);
         }
 
@@ -176,7 +319,8 @@ pub fn sync_barrier(mutator: &mut Mutator) {
             "expect {} mutators to park",
             *N_MUTATORS.read().unwrap() - 1
         );
-        while count < *N_MUTATORS.read().unwrap() - 1 {
+        // mutators in native will not reach a yieldpoint, we treat them as stopped
+        while count + n_mutators_in_native() < *N_MUTATORS.read().unwrap() - 1 {
             let new_count = { *lock.lock().unwrap() };
             if new_count != count {
                 count = new_count;
@@ -186,8 +330,40 @@ pub fn sync_barrier(mutator: &mut Mutator) {
 
         trace!("everyone stopped, gc will start");
 
-        // roots->trace->sweep
-        gc();
+        // scan the stacks of mutators in native (from the snapshots they took)
+        for m in MUTATORS.read().unwrap().iter() {
+            if let &Some(ref m) = m {
+                if !m.is_in_native() {
+                    continue;
+                }
+                if let Some((mut native_roots, conservative)) = m.native_roots() {
+                    trace!("roots: {} from a mutator in native", native_roots.len());
+                    if conservative {
+                        CONSERVATIVE_STACK_ROOTS.store(true, Ordering::SeqCst);
+                    }
+                    ROOTS.write().unwrap().append(&mut native_roots);
+                }
+            }
+        }
+
+        // scan stacks that are not running on any thread
+        {
+            let scanner = *INACTIVE_STACK_SCANNER.read().unwrap();
+            if let Some(scanner) = scanner {
+                let mut stack_roots = scanner();
+                trace!("roots: {} from inactive stacks", stack_roots.len());
+                ROOTS.write().unwrap().append(&mut stack_roots);
+            }
+        }
+
+        // roots->trace->sweep (or only verify the heap if it is requested)
+        let collected = if VERIFY_REQUESTED.swap(false, Ordering::SeqCst) {
+            verify_heap_only();
+            false
+        } else {
+            gc(pause_start);
+            true
+        };
 
         // mutators will resume
         CONTROLLER.store(NO_CONTROLLER, Ordering::SeqCst);
@@ -207,9 +383,26 @@ pub fn sync_barrier(mutator: &mut Mutator) {
             *count = 0;
             cvar.notify_all();
         }
+
+        if collected {
+            stats::record_collection(is_major_gc(), pause_start.elapsed());
+        }
     }
 }
 
+/// returns the number of mutators that are in native (see Mutator::enter_native())
+fn n_mutators_in_native() -> usize {
+    MUTATORS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|m| match *m {
+            &Some(ref m) => m.is_in_native(),
+            &None => false,
+        })
+        .count()
+}
+
 fn block_current_thread(mutator: &mut Mutator) {
     trace!("Mutator{} blocked", mutator.id());
 
@@ -228,7 +421,7 @@ fn block_current_thread(mutator: &mut Mutator) {
 
 pub static GC_COUNT: atomic::AtomicUsize = AtomicUsize::new(0);
 
-fn gc() {
+fn gc(pause_start: Instant) {
     if !ENABLE_GC.load(Ordering::SeqCst) {
         panic!("Triggering GC when GC is disabled");
     }
@@ -238,16 +431,36 @@ fn gc() {
         atomic::Ordering::SeqCst,
     );
 
+    let major = !is_generational() || MAJOR_GC_REQUESTED.swap(false, Ordering::SeqCst);
+    MAJOR_GC.store(major, Ordering::SeqCst);
+
+    // roots are not updated during GC (objects they refer to do not move), so we use the
+    // same roots to verify the heap after GC
+    let verify = verify::is_enabled();
+    let verify_roots = if verify {
+        let roots = verification_roots();
+        verify::check_heap(&roots, VerifyPhase::BeforeGC);
+        roots
+    } else {
+        vec![]
+    };
+
     // each space prepares for GC
-    {
+    // A minor GC keeps mark bits and line marks from earlier GCs (objects that survived
+    // a GC are old, and they stay alive until next major GC), so it only traces young objects
+    if major {
         let mut gccontext_guard = MY_GC.write().unwrap();
         let gccontext = gccontext_guard.as_mut().unwrap();
+        // choose blocks to defrag (this uses line marks, so it goes before prepare_for_gc())
+        if GC_MOVES_OBJECT {
+            prepare_defrag(&mut gccontext.immix_tiny, &mut gccontext.immix_normal);
+        }
         gccontext.immix_tiny.prepare_for_gc();
         gccontext.immix_normal.prepare_for_gc();
         gccontext.lo.prepare_for_gc();
     }
 
-    trace!("GC starts");
+    trace!("{} GC starts", if major { "major" } else { "minor" });
 
     // mark & trace
     {
@@ -256,17 +469,51 @@ fn gc() {
 
         let gccontext_guard = MY_GC.read().unwrap();
         let gccontext = gccontext_guard.as_ref().unwrap();
-        for obj in gccontext.roots.iter() {
+        for obj in gccontext.roots.keys() {
             roots.push(*obj);
         }
 
+        // a major GC traces all the old objects, so it does not need the remembered set
+        let remset: Vec<(Address, WordType)> = REMEMBERED_SET.lock().unwrap().drain(..).collect();
+        if !major {
+            scan_remembered_set(remset, gccontext, &mut roots);
+        }
+
+        // objects waiting for finalisation
+        finalizer::add_finalizer_roots(&mut roots);
+
         trace!("total roots: {}", roots.len());
 
+        // we do not update roots, so objects referred by roots cannot move
+        if major {
+            pin_roots(roots);
+        }
+
         start_trace(&mut roots);
     }
 
     trace!("trace done");
 
+    // clear weak references whose referents are dead
+    process_weak_refs(major);
+
+    // resurrect unreachable objects that are registered for finalisation, and queue them
+    // (weak references to them are already cleared)
+    let resurrected = finalizer::find_unreachable_finalizable(major);
+    if !resurrected.is_empty() {
+        // they are roots for this trace, so they cannot move
+        if major {
+            pin_roots(&resurrected);
+        }
+        start_trace(&mut resurrected.clone());
+        process_weak_refs(major);
+
+        finalizer::queue_finalizable(resurrected);
+    }
+
+    // evacuation finishes, returns the blocks that we reserved to evacuate objects into
+    finish_defrag();
+
     // sweep
     {
         let mut gccontext_guard = MY_GC.write().unwrap();
@@ -275,14 +522,103 @@ fn gc() {
         gccontext.immix_tiny.sweep();
         gccontext.immix_normal.sweep();
         gccontext.lo.sweep();
+
+        // old objects are not reclaimed until a major GC. If we are short of free memory,
+        // the next GC will be a major GC
+        if is_generational()
+            && (is_short_of_free_lines(&gccontext.immix_tiny)
+                || is_short_of_free_lines(&gccontext.immix_normal))
+        {
+            trace!("short of free lines after GC, next GC will be a major GC");
+            request_major_gc();
+        }
+
+        if major {
+            resize_heap(gccontext, pause_start);
+        }
+    }
+
+    if verify {
+        // objects that are queued for finalisation in this GC are roots as well
+        let mut roots = verify_roots;
+        finalizer::add_finalizer_roots(&mut roots);
+        verify::check_heap(&roots, VerifyPhase::AfterGC);
     }
 
     // clear existing roots (roots from last gc)
     ROOTS.write().unwrap().clear();
+    CONSERVATIVE_STACK_ROOTS.store(false, Ordering::SeqCst);
 
     trace!("GC finishes");
 }
 
+/// verifies the heap in a stop-the-world pause instead of collecting
+fn verify_heap_only() {
+    let roots = verification_roots();
+    verify::check_heap(&roots, VerifyPhase::OnDemand);
+
+    ROOTS.write().unwrap().clear();
+    CONSERVATIVE_STACK_ROOTS.store(false, Ordering::SeqCst);
+}
+
+/// returns roots for the heap verifier: explicit roots, objects waiting for finalisation,
+/// and stack roots (unless some stacks are scanned conservatively)
+fn verification_roots() -> Vec<ObjectReference> {
+    let mut roots = vec![];
+    if !CONSERVATIVE_STACK_ROOTS.load(Ordering::SeqCst) {
+        roots.extend(ROOTS.read().unwrap().iter());
+    }
+    {
+        let gccontext_guard = MY_GC.read().unwrap();
+        let gccontext = gccontext_guard.as_ref().unwrap();
+        roots.extend(gccontext.roots.keys());
+    }
+    finalizer::add_finalizer_roots(&mut roots);
+    roots
+}
+
+/// resizes the heap with the live data after a major GC. If the heap shrinks below the memory
+/// that the spaces commit, the spaces release free memory at their end
+fn resize_heap(gccontext: &mut GC, pause_start: Instant) {
+    let live = (gccontext.immix_tiny.last_gc_used_lines << LOG_BYTES_IN_LINE)
+        + (gccontext.immix_normal.last_gc_used_lines << LOG_BYTES_IN_LINE)
+        + gccontext.lo.last_gc_used_pages * BYTES_IN_PAGE;
+    let heap_size = policy::resize_heap(live, pause_start.elapsed());
+
+    let committed = policy::committed();
+    if committed > heap_size {
+        let mut excess = committed - heap_size;
+        excess -= gccontext.immix_tiny.release_free_blocks(excess);
+        excess -= gccontext.immix_normal.release_free_blocks(excess);
+        gccontext.lo.release_free_pages(excess);
+        trace!(
+            "spaces released {} bytes (heap size: {} bytes)",
+            committed - policy::committed(),
+            heap_size
+        );
+    }
+}
+
+/// checks if an immix space has less free lines than MAJOR_GC_FREE_RATIO after a sweep
+fn is_short_of_free_lines(space: &ImmixSpace) -> bool {
+    let total_lines = space.last_gc_free_lines + space.last_gc_used_lines;
+    total_lines != 0
+        && (space.last_gc_free_lines as f64) < (total_lines as f64) * MAJOR_GC_FREE_RATIO
+}
+
+/// pins objects that roots refer to for current GC (so they will not be evacuated)
+fn pin_roots(roots: &Vec<ObjectReference>) {
+    for obj in roots.iter() {
+        match SpaceDescriptor::get(*obj) {
+            SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => {
+                let space = ImmixSpace::get::<ImmixSpace>(obj.to_address());
+                space.pin_object_for_gc(*obj);
+            }
+            _ => {}
+        }
+    }
+}
+
 pub const PUSH_BACK_THRESHOLD: usize = 50;
 pub static GC_THREADS: atomic::AtomicUsize = AtomicUsize::new(0);
 
@@ -344,7 +680,10 @@ fn start_steal_trace(stealer: Stealer<ObjectReference>, job_sender: mpsc::Sender
             } else {
                 let work = stealer.steal();
                 let ret = match work {
-                    Steal::Empty => return,
+                    Steal::Empty => {
+                        flush_local_weak_refs();
+                        return;
+                    }
                     Steal::Abort => continue,
                     Steal::Data(obj) => obj,
                 };
@@ -558,37 +897,306 @@ fn trace_word(
             let field_addr = obj.to_address() + offset;
             let edge = unsafe { field_addr.load::<ObjectReference>() };
 
+            trace_edge(edge, EdgeField::Ref(field_addr), local_queue, job_sender);
+        }
+        WordType::WeakRef => {
+            // weak fields are not traced, we record them and fix them up after tracing
+            let field_addr = obj.to_address() + offset;
+            let edge = unsafe { field_addr.load::<ObjectReference>() };
+
             if edge.to_address().is_zero() {
                 return;
             }
 
-            match SpaceDescriptor::get(edge) {
-                SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => {
-                    let space = ImmixSpace::get::<ImmixSpace>(edge.to_address());
-                    if !space.is_object_traced(edge) {
-                        steal_process_edge(edge, local_queue, job_sender);
+            LOCAL_WEAK_REFS.with(|refs| refs.borrow_mut().push(field_addr));
+        }
+        WordType::TaggedRef => {
+            let field_addr = obj.to_address() + offset;
+            let tagref = unsafe { field_addr.load::<u64>() };
+
+            // only follow the tagref if it holds a reference
+            // (if the referent moves, we rewrite the field with tagref64_set_ref() to keep
+            // the tag)
+            if tagref64_is_ref(tagref) {
+                let edge = unsafe { tagref64_get_ref(tagref).to_object_reference() };
+                trace_edge(
+                    edge,
+                    EdgeField::TaggedRef(field_addr),
+                    local_queue,
+                    job_sender,
+                );
+            }
+        }
+    }
+}
+
+/// the heap field that an edge is loaded from (we update the field if the referent moves)
+#[derive(Copy, Clone)]
+enum EdgeField {
+    Ref(Address),
+    TaggedRef(Address),
+}
+
+impl EdgeField {
+    #[inline(always)]
+    fn update(self, new: ObjectReference) {
+        match self {
+            EdgeField::Ref(field_addr) => unsafe { field_addr.store(new) },
+            EdgeField::TaggedRef(field_addr) => unsafe {
+                let tagref = field_addr.load::<u64>();
+                field_addr.store(tagref64_set_ref(tagref, new.to_address()))
+            },
+        }
+    }
+}
+
+#[inline(always)]
+fn trace_edge(
+    edge: ObjectReference,
+    field: EdgeField,
+    local_queue: &mut Vec<ObjectReference>,
+    job_sender: &mpsc::Sender<ObjectReference>,
+) {
+    if edge.to_address().is_zero() {
+        return;
+    }
+
+    match SpaceDescriptor::get(edge) {
+        SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => {
+            let space = ImmixSpace::get::<ImmixSpace>(edge.to_address());
+            if space.is_defrag_block(edge.to_address()) {
+                match evacuate(edge) {
+                    Evacuation::Moved(new) => {
+                        field.update(new);
+                        steal_process_edge(new, local_queue, job_sender);
                     }
-                }
-                SpaceDescriptor::Freelist => {
-                    let space = FreelistSpace::get::<FreelistSpace>(edge.to_address());
-                    if !space.is_object_traced(edge) {
-                        debug!("edge {} is not traced, trace it", edge);
-                        steal_process_edge(edge, local_queue, job_sender);
-                    } else {
-                        debug!("edge {} is traced, skip", edge);
+                    Evacuation::Forwarded(new) => field.update(new),
+                    Evacuation::Stay => {
+                        if !space.is_object_traced(edge) {
+                            steal_process_edge(edge, local_queue, job_sender);
+                        }
                     }
                 }
-                SpaceDescriptor::Immortal => unimplemented!(),
+            } else if !space.is_object_traced(edge) {
+                steal_process_edge(edge, local_queue, job_sender);
             }
         }
-        WordType::WeakRef | WordType::TaggedRef => {
-            use std::process;
-            error!("unimplemented");
-            process::exit(1);
+        SpaceDescriptor::Freelist => {
+            let space = FreelistSpace::get::<FreelistSpace>(edge.to_address());
+            if !space.is_object_traced(edge) {
+                debug!("edge {} is not traced, trace it", edge);
+                steal_process_edge(edge, local_queue, job_sender);
+            } else {
+                debug!("edge {} is traced, skip", edge);
+            }
         }
+        SpaceDescriptor::Immortal => unimplemented!(),
     }
 }
 
+thread_local! {
+    /// weak reference fields found by current gc thread
+    static LOCAL_WEAK_REFS: RefCell<Vec<Address>> = RefCell::new(vec![]);
+}
+
+/// hands weak reference fields found by current gc thread to the global list
+fn flush_local_weak_refs() {
+    LOCAL_WEAK_REFS.with(|refs| {
+        let mut refs = refs.borrow_mut();
+        if !refs.is_empty() {
+            WEAK_REF_FIELDS.lock().unwrap().append(&mut refs);
+        }
+    });
+}
+
+/// clears weak reference fields whose referents are not traced
+/// (must be called after tracing finishes and before sweeping)
+fn process_weak_refs(major: bool) {
+    let mut fields = WEAK_REF_FIELDS.lock().unwrap();
+    trace!("processing {} weak references", fields.len());
+
+    for field_addr in fields.drain(..) {
+        let referent = unsafe { field_addr.load::<ObjectReference>() };
+        if referent.to_address().is_zero() {
+            continue;
+        }
+
+        // only a major GC evacuates objects (a minor GC may see stale forwarded bits)
+        let forwarded = if major {
+            get_forwarded_object(referent)
+        } else {
+            None
+        };
+
+        if let Some(new) = forwarded {
+            trace_if!(
+                TRACE_GC,
+                "  weak ref at {} to {} is forwarded to {}",
+                field_addr,
+                referent,
+                new
+            );
+            unsafe { field_addr.store(new) };
+        } else if !is_object_alive(referent) {
+            trace_if!(
+                TRACE_GC,
+                "  weak ref at {} to {} is dead, clear it",
+                field_addr,
+                referent
+            );
+            unsafe { field_addr.store(Address::zero()) };
+        }
+    }
+}
+
+/// returns the new location of an object if it is evacuated in current gc
+fn get_forwarded_object(obj: ObjectReference) -> Option<ObjectReference> {
+    match SpaceDescriptor::get(obj) {
+        SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => get_forwarded(obj),
+        _ => None,
+    }
+}
+
+/// checks if an object survives current gc (only valid after tracing)
+fn is_object_alive(obj: ObjectReference) -> bool {
+    match SpaceDescriptor::get(obj) {
+        SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => {
+            let space = ImmixSpace::get::<ImmixSpace>(obj.to_address());
+            space.is_object_traced(obj)
+        }
+        SpaceDescriptor::Freelist => {
+            let space = FreelistSpace::get::<FreelistSpace>(obj.to_address());
+            space.is_object_traced(obj)
+        }
+        // immortal objects never die
+        SpaceDescriptor::Immortal => true,
+    }
+}
+
+/// checks if an object in the given spaces is young (allocated after last GC). Objects
+/// outside the spaces are never young
+#[inline(always)]
+fn is_young_object(
+    obj: ObjectReference,
+    tiny: &ImmixSpace,
+    normal: &ImmixSpace,
+    lo: &FreelistSpace,
+) -> bool {
+    // old objects keep their mark bits from the GC that they survived
+    let addr = obj.to_address();
+    if tiny.addr_in_space(addr) {
+        !tiny.is_object_traced(obj)
+    } else if normal.addr_in_space(addr) {
+        !normal.is_object_traced(obj)
+    } else if lo.addr_in_space(addr) {
+        !lo.is_object_traced(obj)
+    } else {
+        false
+    }
+}
+
+/// checks if a slot that a reference is just stored to needs to be in the remembered set:
+/// the reference is to a young object, and the slot is not in a young object
+/// (we do not know which object the slot is in, so a slot that is not in an immix space
+/// is always remembered)
+#[inline(always)]
+pub fn needs_remembering(
+    slot: Address,
+    kind: WordType,
+    tiny: &ImmixSpace,
+    normal: &ImmixSpace,
+    lo: &FreelistSpace,
+) -> bool {
+    let referent = match kind {
+        WordType::NonRef => return false,
+        WordType::Ref | WordType::WeakRef => unsafe { slot.load::<ObjectReference>() },
+        WordType::TaggedRef => {
+            let tagref = unsafe { slot.load::<u64>() };
+            if !tagref64_is_ref(tagref) {
+                return false;
+            }
+            unsafe { tagref64_get_ref(tagref).to_object_reference() }
+        }
+    };
+
+    if referent.to_address().is_zero() || !is_young_object(referent, tiny, normal, lo) {
+        return false;
+    }
+
+    if tiny.addr_in_space(slot) {
+        tiny.is_in_old_line(slot)
+    } else if normal.addr_in_space(slot) {
+        normal.is_in_old_line(slot)
+    } else {
+        true
+    }
+}
+
+/// the write barrier for stores that are not done by a mutator (e.g. by the client)
+pub fn write_barrier_global(slot: Address, kind: WordType) {
+    if !is_generational() {
+        return;
+    }
+
+    let remember = {
+        let gccontext_guard = MY_GC.read().unwrap();
+        let gccontext = gccontext_guard.as_ref().unwrap();
+        needs_remembering(
+            slot,
+            kind,
+            &gccontext.immix_tiny,
+            &gccontext.immix_normal,
+            &gccontext.lo,
+        )
+    };
+    if remember {
+        REMEMBERED_SET.lock().unwrap().push((slot, kind));
+    }
+}
+
+/// hands slots logged by a mutator to the global remembered set
+pub fn flush_remembered_set(remset: &mut Vec<(Address, WordType)>) {
+    if !remset.is_empty() {
+        REMEMBERED_SET.lock().unwrap().append(remset);
+    }
+}
+
+/// finds young objects that the remembered set refers to, and adds them to roots for a
+/// minor GC (weak slots are processed with other weak references after tracing)
+fn scan_remembered_set(
+    remset: Vec<(Address, WordType)>,
+    gccontext: &GC,
+    roots: &mut Vec<ObjectReference>,
+) {
+    trace!("scanning {} slots in remembered set", remset.len());
+
+    let mut weak_refs = vec![];
+
+    for (slot, kind) in remset {
+        // the slot may have been overwritten after it is logged, so we check it again
+        if !needs_remembering(
+            slot,
+            kind,
+            &gccontext.immix_tiny,
+            &gccontext.immix_normal,
+            &gccontext.lo,
+        ) {
+            continue;
+        }
+
+        match kind {
+            WordType::Ref => roots.push(unsafe { slot.load::<ObjectReference>() }),
+            WordType::TaggedRef => {
+                roots.push(unsafe { tagref64_get_ref(slot.load::<u64>()).to_object_reference() })
+            }
+            WordType::WeakRef => weak_refs.push(slot),
+            WordType::NonRef => unreachable!(),
+        }
+    }
+
+    WEAK_REF_FIELDS.lock().unwrap().append(&mut weak_refs);
+}
+
 #[inline(always)]
 fn steal_process_edge(
     edge: ObjectReference,
@@ -0,0 +1,254 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Heap sizing and out-of-memory handling.
+//!
+//! Every space reserves address space for its maximum size, but it only uses (commits)
+//! the part it has grown into. The heap size limits the memory that all the spaces commit
+//! together: a space that cannot grow within the heap size triggers a GC instead.
+//!
+//! The heap starts at the target size of the heap policy, and is resized after every major
+//! GC (within the minimum and the maximum size):
+//!
+//! * if live data takes more than GROW_LIVE_RATIO of the heap, the heap grows so that live
+//!   data takes TARGET_LIVE_RATIO of it.
+//! * if live data takes less than SHRINK_LIVE_RATIO of the heap, the heap shrinks to the same
+//!   ratio (but not below the target size).
+//! * if the pause took longer than the pause target, the heap shrinks by PAUSE_SHRINK_RATIO
+//!   (as long as live data does not take more than GROW_LIVE_RATIO of it), since a smaller
+//!   heap is quicker to sweep. This may go below the target size.
+//!
+//! When the heap shrinks below the committed memory, spaces give free memory at their end
+//! back to the OS. Without a heap policy (set_heap_policy()), the heap size is fixed to the
+//! sum of the space sizes.
+//!
+//! If an allocation still fails after a major GC, the heap is out of memory, and we call
+//! the handler set by set_oom_handler(). If the handler takes care of the failure, the
+//! allocation returns null to its caller (the VM throws an exception to the Mu code once it
+//! is out of the runtime). If there is no handler or the handler does not, we exit.
+
+use heap::gc;
+use std::cmp;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use utils::*;
+
+/// the heap grows if live data takes more than this ratio of the heap after a major GC
+const GROW_LIVE_RATIO: f64 = 0.7;
+/// the ratio of live data to the heap that we resize the heap for
+const TARGET_LIVE_RATIO: f64 = 0.5;
+/// the heap shrinks if live data takes less than this ratio of the heap after a major GC
+const SHRINK_LIVE_RATIO: f64 = 0.25;
+/// the heap shrinks to this ratio if a pause takes longer than the pause target
+const PAUSE_SHRINK_RATIO: f64 = 0.75;
+
+/// sizes of the heap (in bytes), and the pause time target
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct HeapPolicy {
+    pub min_size: ByteSize,
+    pub target_size: ByteSize,
+    pub max_size: ByteSize,
+    /// pause time target in milliseconds (0 for no target)
+    pub pause_target_ms: usize,
+}
+
+static MIN_SIZE: AtomicUsize = AtomicUsize::new(0);
+static TARGET_SIZE: AtomicUsize = AtomicUsize::new(0);
+static MAX_SIZE: AtomicUsize = AtomicUsize::new(0);
+static PAUSE_TARGET_MS: AtomicUsize = AtomicUsize::new(0);
+/// current heap size
+static HEAP_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// memory that the spaces have committed
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// called when the heap is out of memory (set by the VM)
+    static ref OOM_HANDLER: RwLock<Option<fn() -> bool>> = RwLock::new(None);
+}
+
+/// initializes a fixed heap size (before the spaces are created)
+pub fn init(heap_size: ByteSize) {
+    MIN_SIZE.store(heap_size, Ordering::SeqCst);
+    TARGET_SIZE.store(heap_size, Ordering::SeqCst);
+    MAX_SIZE.store(heap_size, Ordering::SeqCst);
+    PAUSE_TARGET_MS.store(0, Ordering::SeqCst);
+    HEAP_SIZE.store(heap_size, Ordering::SeqCst);
+    COMMITTED.store(0, Ordering::SeqCst);
+    *OOM_HANDLER.write().unwrap() = None;
+}
+
+/// sets the heap policy (after gc_init()). The heap is resized to the target size, and
+/// may shrink at the next major GC if the spaces already use more than that
+pub fn set_heap_policy(policy: HeapPolicy) {
+    assert!(
+        policy.min_size <= policy.target_size && policy.target_size <= policy.max_size,
+        "heap sizes should be min <= target <= max: {:?}",
+        policy
+    );
+    info!("heap policy: {:?}", policy);
+
+    MIN_SIZE.store(policy.min_size, Ordering::SeqCst);
+    TARGET_SIZE.store(policy.target_size, Ordering::SeqCst);
+    MAX_SIZE.store(policy.max_size, Ordering::SeqCst);
+    PAUSE_TARGET_MS.store(policy.pause_target_ms, Ordering::SeqCst);
+    HEAP_SIZE.store(policy.target_size, Ordering::SeqCst);
+}
+
+/// returns current heap size
+pub fn heap_size() -> ByteSize {
+    HEAP_SIZE.load(Ordering::SeqCst)
+}
+
+/// returns the memory that the spaces have committed
+pub fn committed() -> ByteSize {
+    COMMITTED.load(Ordering::SeqCst)
+}
+
+/// commits memory for a space to grow if the heap has room for all of it
+pub fn commit(bytes: ByteSize) -> bool {
+    commit_up_to(bytes, bytes) == bytes
+}
+
+/// commits memory for a space to grow, as much as the heap has room for (up to 'bytes', in
+/// multiples of 'unit'). Returns the bytes committed
+pub fn commit_up_to(bytes: ByteSize, unit: ByteSize) -> ByteSize {
+    debug_assert!(unit != 0);
+    loop {
+        let committed = COMMITTED.load(Ordering::SeqCst);
+        let available = heap_size().saturating_sub(committed);
+        let n = cmp::min(bytes, available) / unit * unit;
+        if n == 0 {
+            return 0;
+        }
+        if COMMITTED.compare_and_swap(committed, committed + n, Ordering::SeqCst) == committed {
+            return n;
+        }
+    }
+}
+
+/// commits memory regardless of the heap size (for the initial memory of a space)
+pub fn force_commit(bytes: ByteSize) {
+    COMMITTED.fetch_add(bytes, Ordering::SeqCst);
+}
+
+/// a space gives memory back
+pub fn uncommit(bytes: ByteSize) {
+    debug_assert!(committed() >= bytes);
+    COMMITTED.fetch_sub(bytes, Ordering::SeqCst);
+}
+
+/// resizes the heap after a major GC with the bytes of live data and the pause time so far.
+/// Returns the new heap size
+pub fn resize_heap(live: ByteSize, pause: Duration) -> ByteSize {
+    let old_size = heap_size();
+    let min_size = MIN_SIZE.load(Ordering::SeqCst);
+    let target_size = TARGET_SIZE.load(Ordering::SeqCst);
+    let max_size = MAX_SIZE.load(Ordering::SeqCst);
+    let pause_target_ms = PAUSE_TARGET_MS.load(Ordering::SeqCst) as u64;
+
+    let live_ratio = if old_size == 0 {
+        1f64
+    } else {
+        live as f64 / old_size as f64
+    };
+
+    let mut new_size = if live_ratio > GROW_LIVE_RATIO {
+        (live as f64 / TARGET_LIVE_RATIO) as ByteSize
+    } else if live_ratio < SHRINK_LIVE_RATIO {
+        // do not grow back to the target size if we shrunk for pause time
+        cmp::max(
+            (live as f64 / TARGET_LIVE_RATIO) as ByteSize,
+            cmp::min(target_size, old_size),
+        )
+    } else {
+        old_size
+    };
+
+    let pause_ms = pause.as_secs() * 1000 + (pause.subsec_nanos() / 1_000_000) as u64;
+    if pause_target_ms != 0 && pause_ms > pause_target_ms && new_size <= old_size {
+        new_size = cmp::max(
+            (new_size as f64 * PAUSE_SHRINK_RATIO) as ByteSize,
+            (live as f64 / GROW_LIVE_RATIO) as ByteSize,
+        );
+    }
+
+    let new_size = cmp::max(min_size, cmp::min(new_size, max_size));
+    if new_size != old_size {
+        debug!(
+            "heap resized: {} -> {} bytes (live: {} bytes, pause: {} ms)",
+            old_size, new_size, live, pause_ms
+        );
+    }
+    HEAP_SIZE.store(new_size, Ordering::SeqCst);
+    new_size
+}
+
+/// sets a handler for out of memory. The handler is called on the thread whose allocation
+/// fails. It returns true if the caller of the allocation will deal with the failure (the
+/// allocation returns null), otherwise the GC exits
+pub fn set_oom_handler(handler: fn() -> bool) {
+    *OOM_HANDLER.write().unwrap() = Some(handler);
+}
+
+/// the heap is out of memory: returns if the handler takes care of it (and the allocation
+/// should return null), otherwise exits
+pub fn out_of_memory() {
+    let handler = *OOM_HANDLER.read().unwrap();
+    if let Some(handler) = handler {
+        if handler() {
+            return;
+        }
+    }
+    error!(
+        "Out of memory (heap size: {} bytes, committed: {} bytes)",
+        heap_size(),
+        committed()
+    );
+    process::exit(1);
+}
+
+/// an allocator that keeps failing to get memory uses this to see if the heap is out of memory
+pub struct AllocRetry {
+    /// GC count when the allocation last failed
+    gc_count: Option<usize>,
+}
+
+impl AllocRetry {
+    pub fn new() -> AllocRetry {
+        AllocRetry { gc_count: None }
+    }
+
+    /// called every time the allocation fails. Returns true if a major GC has happened since
+    /// the first failure. If only a minor GC happened, the next GC will be a major GC
+    pub fn is_out_of_memory(&mut self) -> bool {
+        let gc_count = gc::GC_COUNT.load(Ordering::SeqCst);
+        let last_gc_count = self.gc_count;
+        self.gc_count = Some(gc_count);
+
+        match last_gc_count {
+            Some(last_gc_count) if last_gc_count != gc_count => {
+                if gc::is_major_gc() {
+                    true
+                } else {
+                    gc::request_major_gc();
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+}
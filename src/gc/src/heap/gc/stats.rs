@@ -0,0 +1,141 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GC statistics (collections, pause times and space usage).
+
+use heap::freelist::{FreelistSpace, BYTES_IN_PAGE};
+use heap::gc::policy;
+use heap::gc::GC_COUNT;
+use heap::immix::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use utils::*;
+use MY_GC;
+
+static N_MAJOR_GC: AtomicUsize = AtomicUsize::new(0);
+static LAST_PAUSE_NS: AtomicUsize = AtomicUsize::new(0);
+static MAX_PAUSE_NS: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_PAUSE_NS: AtomicUsize = AtomicUsize::new(0);
+
+/// usage of a space
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SpaceStats {
+    /// bytes allocated since the GC starts. For immix spaces, this counts the lines
+    /// that mutators take to allocate into (so it includes unused bytes at the end of lines)
+    pub allocated_bytes: ByteSize,
+    /// bytes in use after last GC (immix spaces count live lines, the large object space
+    /// counts live pages)
+    pub live_bytes: ByteSize,
+    /// free bytes after last GC
+    pub free_bytes: ByteSize,
+    /// free bytes after last GC that are in blocks with live objects (these can only be
+    /// used as holes). Always 0 for the large object space
+    pub fragmented_bytes: ByteSize,
+}
+
+impl SpaceStats {
+    fn from_immix(space: &ImmixSpace) -> SpaceStats {
+        SpaceStats {
+            allocated_bytes: space.bytes_allocated(),
+            live_bytes: space.last_gc_used_lines << LOG_BYTES_IN_LINE,
+            free_bytes: space.last_gc_free_lines << LOG_BYTES_IN_LINE,
+            fragmented_bytes: space.last_gc_fragmented_lines << LOG_BYTES_IN_LINE,
+        }
+    }
+
+    fn from_freelist(space: &FreelistSpace) -> SpaceStats {
+        SpaceStats {
+            allocated_bytes: space.bytes_allocated(),
+            live_bytes: space.last_gc_used_pages * BYTES_IN_PAGE,
+            free_bytes: space.last_gc_free_pages * BYTES_IN_PAGE,
+            fragmented_bytes: 0,
+        }
+    }
+
+    /// the ratio of fragmented free bytes to all free bytes (0 if there are no free bytes)
+    pub fn fragmentation(&self) -> f64 {
+        if self.free_bytes == 0 {
+            0f64
+        } else {
+            self.fragmented_bytes as f64 / self.free_bytes as f64
+        }
+    }
+}
+
+/// statistics of the GC. Pause times are from when the GC asks mutators to stop, until
+/// the mutators are resumed
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GCStats {
+    /// number of collections so far
+    pub n_collections: usize,
+    /// number of major collections so far
+    pub n_major_collections: usize,
+    /// pause time of last collection (in nanoseconds)
+    pub last_pause_ns: u64,
+    /// the longest pause time (in nanoseconds)
+    pub max_pause_ns: u64,
+    /// total pause time of all collections (in nanoseconds)
+    pub total_pause_ns: u64,
+    /// current heap size (see heap::gc::policy)
+    pub heap_size: ByteSize,
+    /// memory that the spaces have committed
+    pub committed_bytes: ByteSize,
+    pub immix_tiny: SpaceStats,
+    pub immix_normal: SpaceStats,
+    pub lo: SpaceStats,
+}
+
+pub fn init() {
+    N_MAJOR_GC.store(0, Ordering::SeqCst);
+    LAST_PAUSE_NS.store(0, Ordering::SeqCst);
+    MAX_PAUSE_NS.store(0, Ordering::SeqCst);
+    TOTAL_PAUSE_NS.store(0, Ordering::SeqCst);
+}
+
+/// records a finished collection (called by the GC controller after mutators resume)
+pub fn record_collection(major: bool, pause: Duration) {
+    let pause_ns = (pause.as_secs() * 1_000_000_000 + pause.subsec_nanos() as u64) as usize;
+
+    if major {
+        N_MAJOR_GC.fetch_add(1, Ordering::SeqCst);
+    }
+    LAST_PAUSE_NS.store(pause_ns, Ordering::SeqCst);
+    TOTAL_PAUSE_NS.fetch_add(pause_ns, Ordering::SeqCst);
+    if pause_ns > MAX_PAUSE_NS.load(Ordering::SeqCst) {
+        MAX_PAUSE_NS.store(pause_ns, Ordering::SeqCst);
+    }
+
+    trace!("GC paused for {} ns", pause_ns);
+}
+
+/// returns current statistics of the GC
+pub fn get_stats() -> GCStats {
+    let gccontext_guard = MY_GC.read().unwrap();
+    let gccontext = gccontext_guard.as_ref().unwrap();
+
+    GCStats {
+        n_collections: GC_COUNT.load(Ordering::SeqCst),
+        n_major_collections: N_MAJOR_GC.load(Ordering::SeqCst),
+        last_pause_ns: LAST_PAUSE_NS.load(Ordering::SeqCst) as u64,
+        max_pause_ns: MAX_PAUSE_NS.load(Ordering::SeqCst) as u64,
+        total_pause_ns: TOTAL_PAUSE_NS.load(Ordering::SeqCst) as u64,
+        heap_size: policy::heap_size(),
+        committed_bytes: policy::committed(),
+        immix_tiny: SpaceStats::from_immix(&gccontext.immix_tiny),
+        immix_normal: SpaceStats::from_immix(&gccontext.immix_normal),
+        lo: SpaceStats::from_freelist(&gccontext.lo),
+    }
+}
@@ -0,0 +1,421 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Heap verifier.
+//!
+//! Heap corruption usually shows up much later as a crash in tracing. The verifier finds it
+//! earlier by checking:
+//!
+//! * space metadata: every immix block is in the usable or the used list with valid block
+//!   and line marks, and every used freelist node holds a large object with a valid encode
+//! * reachable objects: starting from the roots, every object must be a valid object start
+//!   (in a line/page that is in use), with an encode that matches the GlobalTypeTable.
+//!   After a GC, it must also be marked (and not be a stale copy after evacuation)
+//! * references: every non-null reference field (including weak references and references
+//!   in tagged references) must point to a valid object. References to objects outside the
+//!   GC spaces (immortal objects) are not checked
+//!
+//! Problems are printed along with the offending objects (with objectdump).
+//!
+//! Stack roots found by conservative stack scanning may not be objects, so the verifier
+//! does not use stack roots if any stack is scanned conservatively. Objects that are only
+//! reachable from those roots are not verified.
+
+use super::is_major_gc;
+use common::objectdump::ObjectDump;
+use heap::freelist::*;
+use heap::immix::*;
+use heap::*;
+use objectmodel::*;
+use utils::*;
+use GC;
+use MY_GC;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// verify the heap before and after each collection (only in debug builds)
+static VERIFY_HEAP: AtomicBool = AtomicBool::new(false);
+
+/// when the heap is verified
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VerifyPhase {
+    /// after all mutators stopped, before a collection starts
+    BeforeGC,
+    /// after a collection sweeps the spaces
+    AfterGC,
+    /// requested by verify_heap() (mutators are stopped, but there is no collection)
+    OnDemand,
+}
+
+pub fn init() {
+    VERIFY_HEAP.store(false, Ordering::SeqCst);
+}
+
+/// turns on/off verifying the heap before and after each collection. This has no effect
+/// in release builds
+pub fn set_verify_heap(enabled: bool) {
+    VERIFY_HEAP.store(enabled, Ordering::SeqCst);
+}
+
+/// does the GC verify the heap before and after each collection?
+pub fn is_enabled() -> bool {
+    cfg!(debug_assertions) && VERIFY_HEAP.load(Ordering::SeqCst)
+}
+
+/// verifies the heap, and panics if any problem is found
+pub fn check_heap(roots: &Vec<ObjectReference>, phase: VerifyPhase) {
+    let problems = verify_heap(roots, phase);
+    if problems != 0 {
+        panic!(
+            "heap verification ({:?}) found {} problems",
+            phase, problems
+        );
+    }
+}
+
+/// verifies the space metadata, and the objects that are reachable from the roots.
+/// Problems are printed, and the number of problems is returned.
+/// Mutators should be stopped (or should not touch the heap)
+pub fn verify_heap(roots: &Vec<ObjectReference>, phase: VerifyPhase) -> usize {
+    trace!("verify heap ({:?}) from {} roots", phase, roots.len());
+
+    let gccontext_guard = MY_GC.read().unwrap();
+    let gccontext = gccontext_guard.as_ref().unwrap();
+
+    let mut verifier = HeapVerifier {
+        gc: gccontext,
+        phase,
+        after_major_gc: phase == VerifyPhase::AfterGC && is_major_gc(),
+        problems: 0,
+        visited: HashSet::new(),
+        work_queue: vec![],
+    };
+    verifier.verify_spaces();
+    verifier.verify_from_roots(roots);
+
+    trace!(
+        "verified {} objects, {} problems",
+        verifier.visited.len(),
+        verifier.problems
+    );
+    verifier.problems
+}
+
+struct HeapVerifier<'a> {
+    gc: &'a GC,
+    phase: VerifyPhase,
+    /// a major GC may evacuate objects, we check that no reachable object is a stale copy
+    after_major_gc: bool,
+    problems: usize,
+    /// objects that we checked
+    visited: HashSet<ObjectReference>,
+    /// valid objects whose fields are not checked yet
+    work_queue: Vec<ObjectReference>,
+}
+
+impl<'a> HeapVerifier<'a> {
+    fn verify_spaces(&mut self) {
+        let after_sweep = self.phase == VerifyPhase::AfterGC;
+        let mut problems = self.gc.immix_tiny.verify_metadata(after_sweep);
+        problems.extend(self.gc.immix_normal.verify_metadata(after_sweep));
+        problems.extend(self.gc.lo.verify_metadata(after_sweep));
+
+        for problem in problems {
+            self.report(problem, &[]);
+        }
+    }
+
+    fn verify_from_roots(&mut self, roots: &Vec<ObjectReference>) {
+        for root in roots.iter() {
+            if !root.to_address().is_zero() {
+                self.verify_edge(*root, None, true);
+            }
+        }
+
+        while let Some(obj) = self.work_queue.pop() {
+            for (offset, word_ty) in object_fields(obj) {
+                self.verify_field(obj, offset, word_ty);
+            }
+        }
+    }
+
+    fn verify_field(&mut self, obj: ObjectReference, offset: ByteSize, word_ty: WordType) {
+        let field_addr = obj.to_address() + offset;
+        match word_ty {
+            WordType::NonRef => {}
+            WordType::Ref | WordType::WeakRef => {
+                let edge = unsafe { field_addr.load::<ObjectReference>() };
+                if !edge.to_address().is_zero() {
+                    // we check the referent of a weak reference, but do not follow it
+                    self.verify_edge(edge, Some((obj, offset)), word_ty == WordType::Ref);
+                }
+            }
+            WordType::TaggedRef => {
+                let tagref = unsafe { field_addr.load::<u64>() };
+                if tagref64_is_ref(tagref) {
+                    let edge = unsafe { tagref64_get_ref(tagref).to_object_reference() };
+                    if !edge.to_address().is_zero() {
+                        self.verify_edge(edge, Some((obj, offset)), true);
+                    }
+                }
+            }
+        }
+    }
+
+    /// checks the referent of an edge (from a field, or from roots if 'from' is None), and
+    /// queues it so its fields will be checked
+    fn verify_edge(
+        &mut self,
+        edge: ObjectReference,
+        from: Option<(ObjectReference, ByteSize)>,
+        follow: bool,
+    ) {
+        if self.visited.contains(&edge) {
+            return;
+        }
+        // objects outside the GC spaces are immortal, we cannot check them
+        if !self.gc.is_heap_object(edge.to_address()) {
+            return;
+        }
+
+        match self.check_object(edge) {
+            Ok(()) => {
+                if follow {
+                    self.visited.insert(edge);
+                    self.work_queue.push(edge);
+                }
+            }
+            Err(msg) => {
+                // only report an invalid object once
+                self.visited.insert(edge);
+                match from {
+                    Some((src, offset)) => self.report(
+                        format!("{} (referred by {} at +{})", msg, src, offset),
+                        &[edge, src],
+                    ),
+                    None => self.report(format!("{} (referred by a root)", msg), &[edge]),
+                }
+            }
+        }
+    }
+
+    /// checks if an object in the heap is a valid object at this point
+    fn check_object(&self, obj: ObjectReference) -> Result<(), String> {
+        let addr = obj.to_address();
+        if !addr.is_aligned_to(POINTER_SIZE) {
+            return Err(format!("{} is not aligned", obj));
+        }
+        let size = check_encode(obj)?;
+
+        match SpaceDescriptor::get(obj) {
+            SpaceDescriptor::ImmixTiny | SpaceDescriptor::ImmixNormal => {
+                let space = ImmixSpace::get::<ImmixSpace>(addr);
+                if addr + size > space.end() {
+                    return Err(format!("{} ({} bytes) exceeds its space", obj, size));
+                }
+
+                let line_mark = space.get_line_mark(space.get_line_mark_index(addr));
+                if line_mark == LineMark::Free {
+                    return Err(format!("{} is in a free line", obj));
+                }
+
+                if self.phase == VerifyPhase::AfterGC {
+                    let gc_byte = unsafe {
+                        space
+                            .get_gc_byte_slot(space.get_word_index(addr))
+                            .load::<u8>()
+                    };
+                    if !bit_utils::test_bit_u8(gc_byte, GC_MARK_BIT) {
+                        return Err(format!("{} is reachable, but is not marked", obj));
+                    }
+                    if line_mark != LineMark::Live && line_mark != LineMark::ConservLive {
+                        return Err(format!(
+                            "{} is reachable, but its line is {:?}",
+                            obj, line_mark
+                        ));
+                    }
+                    // (a minor GC does not clear forwarded bits, so only check after a major GC)
+                    if self.after_major_gc && bit_utils::test_bit_u8(gc_byte, GC_FORWARDED_BIT) {
+                        return Err(format!("{} is reachable, but it is forwarded", obj));
+                    }
+                }
+            }
+            SpaceDescriptor::Freelist => {
+                let space = FreelistSpace::get::<FreelistSpace>(addr);
+                if !space.is_used_node_start(addr) {
+                    return Err(format!("{} is not the start of a used node", obj));
+                }
+                if self.phase == VerifyPhase::AfterGC && !space.is_object_traced(obj) {
+                    return Err(format!("{} is reachable, but is not marked", obj));
+                }
+            }
+            SpaceDescriptor::Immortal => {}
+        }
+
+        Ok(())
+    }
+
+    /// prints a problem and the objects involved
+    fn report(&mut self, msg: String, objs: &[ObjectReference]) {
+        println!("heap verification ({:?}): {}", self.phase, msg);
+        for obj in objs.iter() {
+            // we cannot dump an object without a valid encode
+            if check_encode(*obj).is_ok() {
+                println!("    {:?}", ObjectDump::new(obj.to_address()));
+            }
+        }
+        self.problems += 1;
+    }
+}
+
+/// checks the encode of an object against the GlobalTypeTable, and returns the object size
+fn check_encode(obj: ObjectReference) -> Result<ByteSize, String> {
+    let addr = obj.to_address();
+    match SpaceDescriptor::get(obj) {
+        // every tiny object encode is valid
+        SpaceDescriptor::ImmixTiny => {
+            let space = ImmixSpace::get::<ImmixSpace>(addr);
+            let encode = unsafe {
+                space
+                    .get_type_byte_slot(space.get_word_index(addr))
+                    .load::<TinyObjectEncode>()
+            };
+            Ok(encode.size())
+        }
+        SpaceDescriptor::ImmixNormal => {
+            let space = ImmixSpace::get::<ImmixSpace>(addr);
+            let slot = space.get_type_byte_slot(space.get_word_index(addr));
+            let small_encode = unsafe { slot.load::<SmallObjectEncode>() };
+            let (type_id, size) = if small_encode.is_small() {
+                (small_encode.type_id(), small_encode.size())
+            } else {
+                let medium_encode = unsafe { slot.load::<MediumObjectEncode>() };
+                (medium_encode.type_id(), medium_encode.size())
+            };
+
+            if !GlobalTypeTable::has_short_entry(type_id) {
+                return Err(format!(
+                    "{} has an unknown type id {} (encode {:?})",
+                    obj, type_id, small_encode
+                ));
+            }
+            let type_encode = &GlobalTypeTable::table()[type_id];
+            if type_encode.fix_len() as usize * POINTER_SIZE > size {
+                return Err(format!(
+                    "{} ({} bytes) is too small for the fixed part of type {}",
+                    obj, size, type_id
+                ));
+            }
+            if !addr.is_aligned_to(type_encode.align()) {
+                return Err(format!(
+                    "{} is not aligned to {} bytes (type {})",
+                    obj,
+                    type_encode.align(),
+                    type_id
+                ));
+            }
+            Ok(size)
+        }
+        SpaceDescriptor::Freelist => {
+            let space = FreelistSpace::get::<FreelistSpace>(addr);
+            let encode = space.get_type_encode(obj);
+            if !GlobalTypeTable::has_full_entry(encode.type_id()) {
+                return Err(format!("{} has an unknown type ({:?})", obj, encode));
+            }
+            let type_encode = GlobalTypeTable::get_full_type(encode.type_id());
+            if type_encode.fix.len() * POINTER_SIZE > encode.size() {
+                return Err(format!(
+                    "{} ({} bytes) is too small for the fixed part of type {}",
+                    obj,
+                    encode.size(),
+                    encode.type_id()
+                ));
+            }
+            Ok(encode.size())
+        }
+        SpaceDescriptor::Immortal => Err(format!("{} is an immortal object", obj)),
+    }
+}
+
+/// returns the offset and type of every word of an object (that has a valid encode)
+fn object_fields(obj: ObjectReference) -> Vec<(ByteSize, WordType)> {
+    let addr = obj.to_address();
+    let mut fields = vec![];
+
+    match SpaceDescriptor::get(obj) {
+        SpaceDescriptor::ImmixTiny => {
+            let space = ImmixSpace::get::<ImmixSpace>(addr);
+            let encode = unsafe {
+                space
+                    .get_type_byte_slot(space.get_word_index(addr))
+                    .load::<TinyObjectEncode>()
+            };
+            for i in 0..encode.n_fields() {
+                fields.push((i << LOG_POINTER_SIZE, encode.field(i)));
+            }
+        }
+        SpaceDescriptor::ImmixNormal => {
+            let space = ImmixSpace::get::<ImmixSpace>(addr);
+            let slot = space.get_type_byte_slot(space.get_word_index(addr));
+            let small_encode = unsafe { slot.load::<SmallObjectEncode>() };
+            let (type_id, size) = if small_encode.is_small() {
+                (small_encode.type_id(), small_encode.size())
+            } else {
+                let medium_encode = unsafe { slot.load::<MediumObjectEncode>() };
+                (medium_encode.type_id(), medium_encode.size())
+            };
+
+            let type_encode = &GlobalTypeTable::table()[type_id];
+            let mut offset = 0;
+            for i in 0..type_encode.fix_len() {
+                fields.push((offset, type_encode.fix_ty(i)));
+                offset += POINTER_SIZE;
+            }
+            if type_encode.var_len() != 0 {
+                while offset < size {
+                    for i in 0..type_encode.var_len() {
+                        fields.push((offset, type_encode.var_ty(i)));
+                        offset += POINTER_SIZE;
+                    }
+                }
+            }
+        }
+        SpaceDescriptor::Freelist => {
+            let space = FreelistSpace::get::<FreelistSpace>(addr);
+            let encode = space.get_type_encode(obj);
+            let type_encode = GlobalTypeTable::get_full_type(encode.type_id());
+
+            let mut offset = 0;
+            for &word_ty in type_encode.fix.iter() {
+                fields.push((offset, word_ty));
+                offset += POINTER_SIZE;
+            }
+            if !type_encode.var.is_empty() {
+                while offset < encode.size() {
+                    for &word_ty in type_encode.var.iter() {
+                        fields.push((offset, word_ty));
+                        offset += POINTER_SIZE;
+                    }
+                }
+            }
+        }
+        SpaceDescriptor::Immortal => {}
+    }
+
+    // do not read beyond the object (the var part may not fit the object exactly)
+    let size = check_encode(obj).unwrap_or(0);
+    fields.retain(|&(offset, _)| offset < size);
+    fields
+}
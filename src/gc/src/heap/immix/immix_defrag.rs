@@ -0,0 +1,343 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Opportunistic defragmentation for immix spaces (as in the original Immix design).
+//!
+//! Before a GC, each immix space picks its most fragmented blocks as defrag candidates
+//! (from line marks of last GC), and reserves some free blocks as evacuation targets.
+//! During tracing, an object in a candidate block that we reach through a heap field gets
+//! copied to the reserved blocks. We leave a forwarding pointer in the first word of the
+//! old object (and set GC_FORWARDED_BIT in its gc byte), and update the field.
+//! Objects referred by roots (pinned objects, stacks and registers) are pinned for the GC,
+//! and stay where they are, as we cannot update the locations that refer to them.
+//! Evacuation is opportunistic: when the reserved blocks run out, objects stay in place.
+//!
+//! GC threads evacuate in parallel. A thread claims an object by setting GC_FORWARDING_BIT in
+//! its gc byte with an atomic compare-and-swap, and other threads that reach the object wait
+//! until the forwarding pointer is installed. Each thread bump allocates into its own block,
+//! and only takes the lock on the reserved blocks when it needs a new block.
+
+use common::ptr::*;
+use heap::immix::*;
+use heap::*;
+use objectmodel::sidemap::*;
+use utils::*;
+
+use std::cell::RefCell;
+use std::mem::transmute;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// the result of trying to evacuate an object
+pub enum Evacuation {
+    /// the object is copied to a new location by this call (the new object needs tracing)
+    Moved(ObjectReference),
+    /// the object was copied by an earlier call
+    Forwarded(ObjectReference),
+    /// the object stays in place
+    Stay,
+}
+
+/// blocks reserved for evacuation in current GC
+struct ReservedBlocks {
+    tiny: Vec<Raw<ImmixBlock>>,
+    normal: Vec<Raw<ImmixBlock>>,
+    /// blocks that gc threads have taken to evacuate objects into
+    used: Vec<Raw<ImmixBlock>>,
+}
+
+/// the block that a gc thread is evacuating objects into
+#[derive(Copy, Clone)]
+struct BumpRegion {
+    cursor: Address,
+    limit: Address,
+}
+
+impl BumpRegion {
+    fn empty() -> BumpRegion {
+        BumpRegion {
+            cursor: unsafe { Address::zero() },
+            limit: unsafe { Address::zero() },
+        }
+    }
+
+    fn alloc(&mut self, size: ByteSize, align: ByteSize) -> Option<Address> {
+        let start = self.cursor.align_up(align);
+        if !self.cursor.is_zero() && start + size <= self.limit {
+            self.cursor = start + size;
+            Some(start)
+        } else {
+            None
+        }
+    }
+}
+
+/// evacuation targets of a gc thread (only valid in the GC that they are taken in)
+struct LocalTargets {
+    epoch: usize,
+    tiny: BumpRegion,
+    normal: BumpRegion,
+}
+
+lazy_static! {
+    /// evacuation targets for current GC (the lock is only taken when a gc thread needs
+    /// a new block)
+    static ref RESERVED: Mutex<Option<ReservedBlocks>> = Mutex::new(None);
+}
+
+/// is there any block to evacuate objects into in current GC?
+static DEFRAG_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// increases for every GC that defrags (so gc threads know their local targets are stale)
+static DEFRAG_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static LOCAL_TARGETS: RefCell<LocalTargets> = RefCell::new(LocalTargets {
+        epoch: 0,
+        tiny: BumpRegion::empty(),
+        normal: BumpRegion::empty(),
+    });
+}
+
+/// selects defrag candidates and reserves evacuation targets for both immix spaces
+/// (called before the spaces prepare for GC)
+pub fn prepare_defrag(tiny: &mut ImmixSpace, normal: &mut ImmixSpace) {
+    let tiny_blocks = tiny.select_defrag_blocks();
+    let normal_blocks = normal.select_defrag_blocks();
+
+    let mut reserved = RESERVED.lock().unwrap();
+    debug_assert!(reserved.is_none());
+    if tiny_blocks.is_empty() && normal_blocks.is_empty() {
+        return;
+    }
+    *reserved = Some(ReservedBlocks {
+        tiny: tiny_blocks,
+        normal: normal_blocks,
+        used: vec![],
+    });
+    DEFRAG_EPOCH.fetch_add(1, Ordering::SeqCst);
+    DEFRAG_ACTIVE.store(true, Ordering::SeqCst);
+}
+
+/// returns reserved blocks to the spaces (called after tracing and before sweeping).
+/// The blocks are returned as used blocks, and sweeping will find out whether they are usable
+pub fn finish_defrag() {
+    DEFRAG_ACTIVE.store(false, Ordering::SeqCst);
+    if let Some(reserved) = RESERVED.lock().unwrap().take() {
+        let blocks = reserved
+            .used
+            .into_iter()
+            .chain(reserved.tiny.into_iter())
+            .chain(reserved.normal.into_iter());
+        for block in blocks {
+            let space = ImmixSpace::get::<ImmixSpace>(block.mem_start());
+            space.return_used_block(block);
+        }
+    }
+}
+
+/// allocates space for an evacuated object from the blocks of current gc thread
+fn alloc_evacuation_target(desc: SpaceDescriptor, size: ByteSize) -> Option<Address> {
+    LOCAL_TARGETS.with(|targets| {
+        let mut targets = targets.borrow_mut();
+        let epoch = DEFRAG_EPOCH.load(Ordering::SeqCst);
+        if targets.epoch != epoch {
+            targets.epoch = epoch;
+            targets.tiny = BumpRegion::empty();
+            targets.normal = BumpRegion::empty();
+        }
+
+        let region = match desc {
+            SpaceDescriptor::ImmixTiny => &mut targets.tiny,
+            _ => &mut targets.normal,
+        };
+        if let Some(addr) = region.alloc(size, MINIMAL_ALIGNMENT) {
+            return Some(addr);
+        }
+
+        // take a new block
+        let block = {
+            let mut reserved = RESERVED.lock().unwrap();
+            let reserved = match reserved.as_mut() {
+                Some(reserved) => reserved,
+                None => return None,
+            };
+            let block = match desc {
+                SpaceDescriptor::ImmixTiny => reserved.tiny.pop(),
+                _ => reserved.normal.pop(),
+            };
+            match block {
+                Some(block) => {
+                    reserved.used.push(block.clone());
+                    block
+                }
+                None => return None,
+            }
+        };
+        *region = BumpRegion {
+            cursor: block.mem_start(),
+            limit: block.mem_start() + BYTES_IN_BLOCK,
+        };
+        region.alloc(size, MINIMAL_ALIGNMENT)
+    })
+}
+
+/// the word that a gc byte is in (so we can update the byte with atomic operations on words)
+#[inline(always)]
+fn gc_byte_word(slot: Address) -> (&'static AtomicUsize, usize) {
+    let word = unsafe { Address::from_usize(slot.as_usize() & !(POINTER_SIZE - 1)) };
+    let shift = (slot - word) << 3;
+    (unsafe { word.to_ref::<AtomicUsize>() }, shift)
+}
+
+/// loads a gc byte (with acquire ordering)
+#[inline(always)]
+fn load_gc_byte(slot: Address) -> u8 {
+    let (word, shift) = gc_byte_word(slot);
+    (word.load(Ordering::Acquire) >> shift) as u8
+}
+
+/// sets a gc byte if it is still the old value, returns whether the byte is set
+#[inline(always)]
+fn cas_gc_byte(slot: Address, old: u8, new: u8) -> bool {
+    let (word, shift) = gc_byte_word(slot);
+    let mask = 0xffusize << shift;
+    loop {
+        let cur = word.load(Ordering::Acquire);
+        if ((cur & mask) >> shift) as u8 != old {
+            return false;
+        }
+        let new_word = (cur & !mask) | ((new as usize) << shift);
+        // the other bytes in the word may change, in which case we retry
+        if word.compare_and_swap(cur, new_word, Ordering::AcqRel) == cur {
+            return true;
+        }
+    }
+}
+
+/// tries to evacuate an object in a defrag block
+pub fn evacuate(obj: ObjectReference) -> Evacuation {
+    if !DEFRAG_ACTIVE.load(Ordering::Relaxed) {
+        return Evacuation::Stay;
+    }
+
+    let old = obj.to_address();
+    let mut space = ImmixSpace::get::<ImmixSpace>(old);
+    let index = space.get_word_index(old);
+    let gc_slot = space.get_gc_byte_slot(index);
+
+    // claim the object, or find out what other gc threads have done to it
+    let gc_byte = loop {
+        let gc_byte = load_gc_byte(gc_slot);
+        if gc_byte & GC_FORWARDED_BIT != 0 {
+            return Evacuation::Forwarded(unsafe { old.load::<ObjectReference>() });
+        }
+        if gc_byte & (GC_MARK_BIT | GC_PINNED_BIT) != 0 {
+            return Evacuation::Stay;
+        }
+        if gc_byte & GC_FORWARDING_BIT != 0 {
+            // another gc thread is copying the object
+            continue;
+        }
+        if cas_gc_byte(gc_slot, gc_byte, gc_byte | GC_FORWARDING_BIT) {
+            break gc_byte;
+        }
+    };
+
+    let type_slot = space.get_type_byte_slot(index);
+    let desc = SpaceDescriptor::get(obj);
+    let size = match desc {
+        SpaceDescriptor::ImmixTiny => {
+            let encode = unsafe { type_slot.load::<TinyObjectEncode>() };
+            encode.size()
+        }
+        SpaceDescriptor::ImmixNormal => {
+            let encode = unsafe { type_slot.load::<MediumObjectEncode>() };
+            let small_encode: &SmallObjectEncode = unsafe { transmute(&encode) };
+            if small_encode.is_small() {
+                small_encode.size()
+            } else {
+                encode.size()
+            }
+        }
+        _ => unreachable!(),
+    };
+
+    let new = match alloc_evacuation_target(desc, size) {
+        Some(new) => new,
+        None => {
+            // pin the object, so we will not try to evacuate it again
+            let claimed = cas_gc_byte(
+                gc_slot,
+                gc_byte | GC_FORWARDING_BIT,
+                gc_byte | GC_PINNED_BIT,
+            );
+            debug_assert!(claimed);
+            return Evacuation::Stay;
+        }
+    };
+    unsafe {
+        ptr::copy_nonoverlapping(old.to_ptr::<u8>(), new.to_ptr_mut::<u8>(), size);
+        // type bytes (for every word of the object, so we do not need to know its encoding)
+        ptr::copy_nonoverlapping(
+            type_slot.to_ptr::<u8>(),
+            space
+                .get_type_byte_slot(space.get_word_index(new))
+                .to_ptr_mut::<u8>(),
+            size >> LOG_POINTER_SIZE,
+        );
+        // gc byte (the slot may have stale bits from objects that were there)
+        let gc_byte = if size > BYTES_IN_LINE {
+            GC_STRADDLE_BIT
+        } else {
+            0
+        };
+        space
+            .get_gc_byte_slot(space.get_word_index(new))
+            .store(gc_byte);
+    }
+
+    let new = unsafe { new.to_object_reference() };
+    // the new object is marked here, so other gc threads will not trace it again
+    space.mark_object_traced(new);
+
+    // leave a forwarding pointer, and publish it with the forwarded bit
+    unsafe { old.store(new) };
+    let claimed = cas_gc_byte(
+        gc_slot,
+        gc_byte | GC_FORWARDING_BIT,
+        gc_byte | GC_FORWARDED_BIT,
+    );
+    debug_assert!(claimed);
+    trace!("evacuated {} to {}", obj, new);
+
+    Evacuation::Moved(new)
+}
+
+/// returns the new location of an object if it has been evacuated in current GC
+/// (only valid after tracing)
+pub fn get_forwarded(obj: ObjectReference) -> Option<ObjectReference> {
+    let addr = obj.to_address();
+    let space = ImmixSpace::get::<ImmixSpace>(addr);
+    let gc_byte = unsafe {
+        space
+            .get_gc_byte_slot(space.get_word_index(addr))
+            .load::<u8>()
+    };
+    if gc_byte & GC_FORWARDED_BIT != 0 {
+        Some(unsafe { addr.load::<ObjectReference>() })
+    } else {
+        None
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use heap::gc::policy::{self, AllocRetry};
 use heap::immix::immix_space::ImmixBlock;
 use heap::immix::ImmixSpace;
 use heap::*;
@@ -124,6 +125,12 @@ impl ImmixAllocator {
         }
     }
 
+    /// returns the space this allocator allocates from
+    #[inline(always)]
+    pub fn space(&self) -> &ImmixSpace {
+        &self.space
+    }
+
     #[inline(never)]
     pub fn alloc_slow(&mut self, size: usize, align: usize) -> Address {
         if size > BYTES_IN_LINE {
@@ -200,6 +207,7 @@ impl ImmixAllocator {
                     unsafe {
                         self.cursor.memset(0, self.limit - self.cursor);
                     }
+                    self.space.add_allocated_bytes(self.limit - self.cursor);
 
                     for line in next_available_line..end_line {
                         self.block().set_line_mark(line, LineMark::FreshAlloc);
@@ -218,6 +226,7 @@ impl ImmixAllocator {
     fn alloc_from_global(&mut self, size: usize, align: usize, request_large: bool) -> Address {
         trace!("Mutator: slowpath: alloc_from_global()");
         self.return_block(request_large);
+        let mut retry = AllocRetry::new();
 
         loop {
             // check if yield
@@ -235,6 +244,7 @@ impl ImmixAllocator {
                         self.large_cursor = b.mem_start();
                         self.large_limit = b.mem_start() + BYTES_IN_BLOCK;
                         self.large_block = Some(b);
+                        self.space.add_allocated_bytes(BYTES_IN_BLOCK);
 
                         trace!(
                             "Mutator: slowpath: new large_block starting from 0x{:x}",
@@ -257,6 +267,11 @@ impl ImmixAllocator {
                     }
                 }
                 None => {
+                    // a GC resets this allocator, so it has no block when we give up
+                    if retry.is_out_of_memory() {
+                        policy::out_of_memory();
+                        return unsafe { Address::zero() };
+                    }
                     continue;
                 }
             }
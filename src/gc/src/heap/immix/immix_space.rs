@@ -14,6 +14,7 @@
 
 use common::ptr::*;
 use heap::gc;
+use heap::gc::policy;
 use heap::immix::*;
 use heap::*;
 use objectmodel::*;
@@ -22,6 +23,7 @@ use utils::mem::memsec;
 use utils::mem::*;
 
 use std::collections::LinkedList;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::*;
 
@@ -73,6 +75,10 @@ pub struct ImmixSpace {
     // 32 bytes
     pub last_gc_free_lines: usize,
     pub last_gc_used_lines: usize,
+    // free lines in blocks that still have live lines (after last GC)
+    pub last_gc_fragmented_lines: usize,
+    // bytes handed to mutators (counted by lines/blocks that mutators take)
+    bytes_allocated: AtomicUsize,
 
     // 16 bytes
     mmap_start: Address,
@@ -136,10 +142,13 @@ impl Space for ImmixSpace {
             memsec::memzero(&mut self.line_mark_table[0] as *mut LineMark, lines);
         }
 
-        // erase gc bytes
+        // erase gc bytes (mark bits, and forwarded/pinned bits from last GC)
         let words = self.cur_size >> LOG_POINTER_SIZE;
         for i in 0..words {
-            self.gc_byte_table[i] = bit_utils::clear_bit_u8(self.gc_byte_table[i], GC_MARK_BIT);
+            self.gc_byte_table[i] = bit_utils::clear_bit_u8(
+                self.gc_byte_table[i],
+                GC_MARK_BIT | GC_FORWARDED_BIT | GC_FORWARDING_BIT | GC_PINNED_BIT,
+            );
         }
     }
 
@@ -155,6 +164,7 @@ impl Space for ImmixSpace {
         // some statistics
         let mut free_lines = 0;
         let mut used_lines = 0;
+        let mut fragmented_lines = 0;
 
         {
             let mut used_blocks_lock = self.used_blocks.lock().unwrap();
@@ -174,6 +184,7 @@ impl Space for ImmixSpace {
                 let block_index = self.get_block_mark_index(block.mem_start());
 
                 let mut has_free_lines = false;
+                let mut block_free_lines = 0;
                 // find free lines in the block, and set their line mark as free
                 // (not zeroing the memory yet)
 
@@ -183,11 +194,16 @@ impl Space for ImmixSpace {
                     {
                         has_free_lines = true;
                         self.line_mark_table[i] = LineMark::Free;
-                        free_lines += 1;
+                        block_free_lines += 1;
                     } else {
                         used_lines += 1;
                     }
                 }
+                free_lines += block_free_lines;
+                // free lines in a block with live lines can only be used as holes
+                if block_free_lines != LINES_IN_BLOCK {
+                    fragmented_lines += block_free_lines;
+                }
 
                 if has_free_lines {
                     trace!("Block {} is usable", block.addr());
@@ -220,11 +236,7 @@ impl Space for ImmixSpace {
 
         self.last_gc_free_lines = free_lines;
         self.last_gc_used_lines = used_lines;
-
-        if self.n_used_blocks() == self.total_blocks && self.total_blocks != 0 {
-            println!("Out of memory in Immix Space");
-            process::exit(1);
-        }
+        self.last_gc_fragmented_lines = fragmented_lines;
 
         debug_assert_eq!(
             self.n_used_blocks() + self.n_usable_blocks(),
@@ -260,8 +272,9 @@ impl Space for ImmixSpace {
                     med_encode.size()
                 }
             };
+            // the object may not start at a line boundary, we mark every line it touches
             let start_line = self.get_line_mark_index(obj_addr);
-            let end_line = start_line + (size >> LOG_BYTES_IN_LINE);
+            let end_line = self.get_line_mark_index(obj_addr + (size - 1)) + 1;
             for i in start_line..end_line {
                 self.set_line_mark(i, LineMark::Live);
             }
@@ -349,6 +362,8 @@ impl ImmixSpace {
 
         space.last_gc_used_lines = 0;
         space.last_gc_free_lines = 0;
+        space.last_gc_fragmented_lines = 0;
+        space.bytes_allocated = AtomicUsize::new(0);
 
         trace!("    initializing blocks...");
         space.init_blocks();
@@ -365,6 +380,8 @@ impl ImmixSpace {
         } else {
             self.total_blocks
         };
+        // initial blocks do not count against the heap size
+        policy::force_commit(n_blocks << LOG_BYTES_IN_BLOCK);
         self.grow_blocks(n_blocks);
     }
 
@@ -480,6 +497,131 @@ impl ImmixSpace {
         space.get_type_byte_slot(index)
     }
 
+    // defragmentation
+
+    /// picks fragmented blocks as defrag candidates (marks them as BlockMark::Defrag),
+    /// and reserves free blocks as evacuation targets for their objects. Returns the
+    /// reserved blocks (empty if we do not defrag in this GC).
+    /// This uses line marks from last GC (and lines allocated since then), so it needs
+    /// to be called before prepare_for_gc() erases line marks.
+    pub fn select_defrag_blocks(&mut self) -> Vec<Raw<ImmixBlock>> {
+        // (holes, live lines, block) of fragmented blocks
+        let mut candidates: Vec<(usize, usize, Raw<ImmixBlock>)> = vec![];
+        let mut free_blocks: Vec<Address> = vec![];
+        {
+            let used_blocks = self.used_blocks.lock().unwrap();
+            let usable_blocks = self.usable_blocks.lock().unwrap();
+            for block in used_blocks.iter().chain(usable_blocks.iter()) {
+                let (free_lines, holes) = self.count_free_lines_and_holes(block.mem_start());
+                if free_lines == LINES_IN_BLOCK {
+                    free_blocks.push(block.addr());
+                } else if holes >= DEFRAG_HOLES_THRESHOLD
+                    && free_lines >= DEFRAG_FREE_LINES_THRESHOLD
+                {
+                    candidates.push((holes, LINES_IN_BLOCK - free_lines, block.clone()));
+                }
+            }
+            // we only take free blocks from usable blocks as evacuation targets
+            free_blocks.retain(|b| usable_blocks.iter().any(|x| x.addr() == *b));
+        }
+
+        // we reserve at most half of the free blocks, so mutators still have free blocks
+        // to allocate after this GC
+        let n_reserved = free_blocks.len() >> 1;
+        if n_reserved == 0 || candidates.is_empty() {
+            return vec![];
+        }
+
+        // the most fragmented blocks first, as long as their live lines fit in the reserved
+        // blocks (live lines are an estimate, evacuation stops once we run out of space)
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let mut budget = n_reserved * LINES_IN_BLOCK;
+        let mut n_defrag = 0;
+        for &(_, live_lines, ref block) in candidates.iter() {
+            if live_lines > budget {
+                break;
+            }
+            budget -= live_lines;
+            let block_index = self.get_block_mark_index(block.mem_start());
+            self.block_mark_table[block_index] = BlockMark::Defrag;
+            n_defrag += 1;
+        }
+        if n_defrag == 0 {
+            return vec![];
+        }
+
+        // take the reserved blocks out of usable blocks
+        let reserved: Vec<Address> = free_blocks.into_iter().take(n_reserved).collect();
+        let mut ret = vec![];
+        {
+            let mut usable_blocks = self.usable_blocks.lock().unwrap();
+            let mut remaining = LinkedList::new();
+            while let Some(block) = usable_blocks.pop_front() {
+                if reserved.contains(&block.addr()) {
+                    ret.push(block);
+                } else {
+                    remaining.push_back(block);
+                }
+            }
+            usable_blocks.append(&mut remaining);
+        }
+        for block in ret.iter() {
+            let block_index = self.get_block_mark_index(block.mem_start());
+            self.block_mark_table[block_index] = BlockMark::Usable;
+        }
+
+        debug!(
+            "{:?}: {} defrag blocks, {} blocks reserved for evacuation",
+            self.desc,
+            n_defrag,
+            ret.len()
+        );
+        ret
+    }
+
+    /// returns the number of free lines, and how many holes they form for a block
+    fn count_free_lines_and_holes(&self, block_start: Address) -> (usize, usize) {
+        let line_index = self.get_line_mark_index(block_start);
+        let mut free_lines = 0;
+        let mut holes = 0;
+        let mut in_hole = false;
+        for i in line_index..(line_index + LINES_IN_BLOCK) {
+            if self.line_mark_table[i] == LineMark::Free {
+                free_lines += 1;
+                if !in_hole {
+                    holes += 1;
+                    in_hole = true;
+                }
+            } else {
+                in_hole = false;
+            }
+        }
+        (free_lines, holes)
+    }
+
+    /// is the address in a block whose objects are being evacuated?
+    #[inline(always)]
+    pub fn is_defrag_block(&self, addr: Address) -> bool {
+        self.block_mark_table[self.get_block_mark_index(addr)] == BlockMark::Defrag
+    }
+
+    /// checks if the line of the address may hold objects that survived earlier GCs
+    /// (allocators only take free lines, so young objects never live in these lines)
+    #[inline(always)]
+    pub fn is_in_old_line(&self, addr: Address) -> bool {
+        match self.line_mark_table[self.get_line_mark_index(addr)] {
+            LineMark::Live | LineMark::ConservLive => true,
+            _ => false,
+        }
+    }
+
+    /// pins an object for current GC (the object will not be evacuated)
+    #[inline(always)]
+    pub fn pin_object_for_gc(&self, obj: ObjectReference) {
+        let slot = self.get_gc_byte_slot(self.get_word_index(obj.to_address()));
+        unsafe { slot.store(slot.load::<u8>() | GC_PINNED_BIT) }
+    }
+
     pub fn return_used_block(&self, old: Raw<ImmixBlock>) {
         self.used_blocks.lock().unwrap().push_front(old);
     }
@@ -506,22 +648,75 @@ impl ImmixSpace {
             None => {
                 // check if we can grow more
                 if self.cur_blocks < self.total_blocks {
-                    let next_growth = self.cur_growth_rate << 1;
+                    let next_growth = cmp::max(self.cur_growth_rate << 1, 1);
                     let n_blocks = if self.cur_blocks + next_growth < self.total_blocks {
                         next_growth
                     } else {
                         self.total_blocks - self.cur_blocks
                     };
-                    self.grow_blocks(n_blocks);
-                    self.get_next_usable_block()
-                } else {
-                    gc::trigger_gc();
-                    None
+                    // the heap size may not allow all of them
+                    let n_blocks =
+                        policy::commit_up_to(n_blocks << LOG_BYTES_IN_BLOCK, BYTES_IN_BLOCK)
+                            >> LOG_BYTES_IN_BLOCK;
+                    if n_blocks != 0 {
+                        self.grow_blocks(n_blocks);
+                        return self.get_next_usable_block();
+                    }
                 }
+                gc::trigger_gc();
+                None
             }
         }
     }
 
+    /// gives free blocks at the end of the space back to the OS (up to 'bytes'), after a sweep.
+    /// Returns the bytes released
+    pub fn release_free_blocks(&mut self, bytes: ByteSize) -> ByteSize {
+        let max_blocks = bytes >> LOG_BYTES_IN_BLOCK;
+        let mut n_blocks = 0;
+        {
+            let mut usable_blocks = self.usable_blocks.lock().unwrap();
+            while n_blocks < max_blocks && self.cur_blocks > 0 {
+                // the last block of the space, it needs to be free
+                let block_start = self.cur_end - BYTES_IN_BLOCK;
+                let line_index = self.get_line_mark_index(block_start);
+                let has_used_lines = (line_index..(line_index + LINES_IN_BLOCK))
+                    .any(|i| self.line_mark_table[i] != LineMark::Free);
+                if has_used_lines {
+                    break;
+                }
+                match usable_blocks
+                    .iter()
+                    .position(|block| block.mem_start() == block_start)
+                {
+                    Some(i) => {
+                        let mut rest = usable_blocks.split_off(i);
+                        rest.pop_front();
+                        usable_blocks.append(&mut rest);
+                    }
+                    None => break,
+                }
+
+                // the memory will read as zero when we grow into it again
+                decommit(block_start, BYTES_IN_BLOCK);
+                let block_index = self.get_block_mark_index(block_start);
+                self.block_mark_table[block_index] = BlockMark::Uninitialized;
+
+                self.cur_end = block_start;
+                self.cur_size -= BYTES_IN_BLOCK;
+                self.cur_blocks -= 1;
+                n_blocks += 1;
+            }
+        }
+        if n_blocks != 0 {
+            trace!("{:?} released {} blocks", self.desc, n_blocks);
+        }
+
+        let released = n_blocks << LOG_BYTES_IN_BLOCK;
+        policy::uncommit(released);
+        released
+    }
+
     fn trace_details(&self) {
         trace!("=== {:?} ===", self.desc);
         trace!(
@@ -564,6 +759,17 @@ impl ImmixSpace {
     }
 
     // for debug use
+    /// records bytes that a mutator takes from the space to allocate into
+    #[inline(always)]
+    pub fn add_allocated_bytes(&self, bytes: ByteSize) {
+        self.bytes_allocated.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// returns bytes that mutators have taken from the space to allocate into
+    pub fn bytes_allocated(&self) -> ByteSize {
+        self.bytes_allocated.load(Ordering::Relaxed)
+    }
+
     pub fn n_used_blocks(&self) -> usize {
         self.used_blocks.lock().unwrap().len()
     }
@@ -571,6 +777,109 @@ impl ImmixSpace {
     pub fn n_usable_blocks(&self) -> usize {
         self.usable_blocks.lock().unwrap().len()
     }
+
+    /// checks the block lists and the block/line mark tables for the heap verifier (mutators
+    /// need to have returned their blocks). After a sweep, full blocks should be in the used
+    /// list, and blocks with free lines in the usable list. Returns the problems found
+    pub fn verify_metadata(&self, after_sweep: bool) -> Vec<String> {
+        let mut problems = vec![];
+
+        let used_blocks = self.used_blocks.lock().unwrap();
+        let usable_blocks = self.usable_blocks.lock().unwrap();
+        if used_blocks.len() + usable_blocks.len() != self.cur_blocks {
+            problems.push(format!(
+                "{:?}: {} used and {} usable blocks, but the space has {} blocks",
+                self.desc,
+                used_blocks.len(),
+                usable_blocks.len(),
+                self.cur_blocks
+            ));
+        }
+
+        let mut seen = vec![false; self.cur_blocks];
+        let all_blocks = used_blocks
+            .iter()
+            .map(|block| (block, true))
+            .chain(usable_blocks.iter().map(|block| (block, false)));
+        for (block, is_used) in all_blocks {
+            let start = block.mem_start();
+            if start < self.start || start >= self.cur_end || !start.is_aligned_to(BYTES_IN_BLOCK) {
+                problems.push(format!(
+                    "{:?}: block {} is not in the space",
+                    self.desc, start
+                ));
+                continue;
+            }
+            let block_index = self.get_block_mark_index(start);
+            if seen[block_index] {
+                problems.push(format!("{:?}: block {} is listed twice", self.desc, start));
+                continue;
+            }
+            seen[block_index] = true;
+
+            // read marks as bytes, they may not be valid enum values
+            let block_mark = unsafe {
+                Address::from_ptr(&self.block_mark_table[block_index] as *const BlockMark)
+                    .load::<u8>()
+            };
+            if block_mark > BlockMark::Defrag as u8 {
+                problems.push(format!(
+                    "{:?}: block {} has an invalid block mark {}",
+                    self.desc, start, block_mark
+                ));
+            }
+
+            let line_start = self.get_line_mark_index(start);
+            let mut free_lines = 0;
+            for i in line_start..(line_start + LINES_IN_BLOCK) {
+                let line_mark = unsafe {
+                    Address::from_ptr(&self.line_mark_table[i] as *const LineMark).load::<u8>()
+                };
+                if line_mark > LineMark::PrevLive as u8 {
+                    problems.push(format!(
+                        "{:?}: line {} of block {} has an invalid line mark {}",
+                        self.desc,
+                        i - line_start,
+                        start,
+                        line_mark
+                    ));
+                } else if line_mark == LineMark::Free as u8 {
+                    free_lines += 1;
+                }
+            }
+
+            if after_sweep {
+                let expected = if is_used {
+                    BlockMark::Full
+                } else {
+                    BlockMark::Usable
+                };
+                if block_mark != expected as u8 {
+                    problems.push(format!(
+                        "{:?}: block {} is in the {} list, but its block mark is {}",
+                        self.desc,
+                        start,
+                        if is_used { "used" } else { "usable" },
+                        block_mark
+                    ));
+                }
+                if is_used && free_lines != 0 {
+                    problems.push(format!(
+                        "{:?}: full block {} has {} free lines",
+                        self.desc, start, free_lines
+                    ));
+                }
+                if !is_used && free_lines == 0 {
+                    problems.push(format!(
+                        "{:?}: usable block {} has no free line",
+                        self.desc, start
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
 }
 
 impl ImmixBlock {
@@ -15,9 +15,16 @@
 use heap::*;
 use utils::*;
 
+mod immix_defrag;
 mod immix_mutator;
 mod immix_space;
 
+pub use self::immix_defrag::evacuate;
+pub use self::immix_defrag::finish_defrag;
+pub use self::immix_defrag::get_forwarded;
+pub use self::immix_defrag::prepare_defrag;
+pub use self::immix_defrag::Evacuation;
+
 pub use self::immix_mutator::ImmixAllocator;
 pub use self::immix_mutator::CURSOR_OFFSET;
 pub use self::immix_mutator::LIMIT_OFFSET;
@@ -83,10 +90,23 @@ pub const OFFSET_MEM_START: ByteOffset =
 
 pub const GC_STRADDLE_BIT: u8 = 0b1000_0000u8;
 pub const GC_MARK_BIT: u8 = 0b0000_0001u8;
+// the object is evacuated in current GC, its first word is the forwarding pointer
+pub const GC_FORWARDED_BIT: u8 = 0b0000_0010u8;
+// a gc thread is evacuating the object in current GC (its forwarding pointer is not ready yet)
+pub const GC_FORWARDING_BIT: u8 = 0b0000_1000u8;
+// the object is referred by roots in current GC, and it cannot be evacuated
+pub const GC_PINNED_BIT: u8 = 0b0000_0100u8;
+
+// a block is a defrag candidate if its free lines form at least this many holes
+pub const DEFRAG_HOLES_THRESHOLD: usize = 2;
+// a block is a defrag candidate only if at least this many lines are free
+pub const DEFRAG_FREE_LINES_THRESHOLD: usize = LINES_IN_BLOCK >> 2;
 
 #[repr(u8)]
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum LineMark {
+    // the lines that may hold old objects (Live and ConservLive, see
+    // ImmixSpace::is_in_old_line()) have LINE_MARK_OLD_BIT (in heap/mod.rs) set
     Free = 0,
     Live,
     FreshAlloc,
@@ -100,4 +120,6 @@ pub enum BlockMark {
     Uninitialized = 0,
     Usable,
     Full,
+    // objects in this block will be evacuated in current GC
+    Defrag,
 }
@@ -15,11 +15,14 @@
 use common::ptr::*;
 use heap::freelist::*;
 use heap::immix::*;
+use objectmodel::sidemap::WordType;
 use utils::*;
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
+use std::thread;
 
 pub mod freelist;
 pub mod gc;
@@ -91,6 +94,20 @@ pub fn fill_alignment_gap(start: Address, end: Address) -> () {
     }
 }
 
+/// the addresses of the tiny and normal immix spaces (aligned to SPACE_ALIGN), so that
+/// generated code can check if a slot is in an immix space without calling into the GC
+/// (set by gc_init())
+#[no_mangle]
+pub static mut IMMIX_SPACE_BASES: [usize; 2] = [0, 0];
+
+/// for an inlined write barrier: the line mark of an address in an immix space is at
+/// (space + ((addr >> LOG_BYTES_IN_LINE) & LINE_INDEX_MASK) + LINE_MARK_DISP), and the lines
+/// that may hold old objects have LINE_MARK_OLD_BIT set in their marks
+pub const LINE_INDEX_MASK: usize = LINES_IN_SPACE - 1;
+pub const LINE_MARK_DISP: ByteOffset =
+    OFFSET_META_LINE_MARK_TABLE - (OFFSET_MEM_START >> LOG_BYTES_IN_LINE);
+pub const LINE_MARK_OLD_BIT: u8 = 0b1;
+
 const MAX_MUTATORS: usize = 1024;
 lazy_static! {
     pub static ref MUTATORS: RwLock<Vec<Option<Arc<MutatorGlobal>>>> = {
@@ -110,12 +127,21 @@ pub struct Mutator {
     pub normal: ImmixAllocator,
     pub lo: FreelistAllocator,
     global: Arc<MutatorGlobal>,
+    /// raw address of the MutatorGlobal (so that generated code can inline the yieldpoint check)
+    global_ptr: Address,
+    /// slots logged by the write barrier since last GC (handed to the GC in prepare_for_gc())
+    remset: Vec<(Address, WordType)>,
+    /// does the stack of the mutator thread keep references (that the GC needs to scan)?
+    has_stack_roots: bool,
 }
 
 lazy_static! {
     pub static ref TINY_ALLOCATOR_OFFSET: ByteSize = offset_of!(Mutator=>tiny).get_byte_offset();
     pub static ref NORMAL_ALLOCATOR_OFFSET: ByteSize =
         offset_of!(Mutator=>normal).get_byte_offset();
+    pub static ref GLOBAL_PTR_OFFSET: ByteSize = offset_of!(Mutator=>global_ptr).get_byte_offset();
+    pub static ref TAKE_YIELD_OFFSET: ByteSize =
+        offset_of!(MutatorGlobal=>take_yield).get_byte_offset();
 }
 
 impl Mutator {
@@ -125,21 +151,31 @@ impl Mutator {
         lo: FreelistAllocator,
         global: Arc<MutatorGlobal>,
     ) -> Mutator {
-        let mut id_lock = N_MUTATORS.write().unwrap();
-        {
+        let mut count_lock = N_MUTATORS.write().unwrap();
+        // use the first free slot (mutators may have been destroyed, so the number of
+        // live mutators is not necessarily a free slot)
+        let id = {
             let mut mutators_lock = MUTATORS.write().unwrap();
-            mutators_lock.remove(*id_lock);
-            mutators_lock.insert(*id_lock, Some(global.clone()));
-        }
+            let id = match mutators_lock.iter().position(|m| m.is_none()) {
+                Some(id) => id,
+                None => panic!("too many mutators (max {})", MAX_MUTATORS),
+            };
+            mutators_lock[id] = Some(global.clone());
+            id
+        };
 
+        let global_ptr = Address::from_ptr(&*global as *const MutatorGlobal);
         let ret = Mutator {
-            id: *id_lock,
+            id,
             tiny,
             normal,
             lo,
             global,
+            global_ptr,
+            remset: vec![],
+            has_stack_roots: true,
         };
-        *id_lock += 1;
+        *count_lock += 1;
 
         ret
     }
@@ -154,6 +190,16 @@ impl Mutator {
         self.id
     }
 
+    /// the mutator thread does not keep references on its stack (e.g. a client thread that
+    /// refers to objects through roots), so the GC does not scan its stack
+    pub fn set_no_stack_roots(&mut self) {
+        self.has_stack_roots = false;
+    }
+
+    pub fn has_stack_roots(&self) -> bool {
+        self.has_stack_roots
+    }
+
     pub fn reset_after_gc(&mut self) {
         self.tiny.reset_after_gc();
         self.normal.reset_after_gc();
@@ -164,9 +210,12 @@ impl Mutator {
         self.tiny.prepare_for_gc();
         self.normal.prepare_for_gc();
         self.lo.prepare_for_gc();
+        gc::flush_remembered_set(&mut self.remset);
     }
 
     pub fn destroy(&mut self) {
+        gc::flush_remembered_set(&mut self.remset);
+
         let mut mutator_count_lock = N_MUTATORS.write().unwrap();
 
         let mut mutators_lock = MUTATORS.write().unwrap();
@@ -183,6 +232,33 @@ impl Mutator {
         }
     }
 
+    /// the write barrier, called after a reference (of the given kind) is stored to the slot.
+    /// We log the slot if it may hold a reference from an old object to a young object
+    #[inline(always)]
+    pub fn write_barrier(&mut self, slot: Address, kind: WordType) {
+        if gc::needs_remembering(
+            slot,
+            kind,
+            self.tiny.space(),
+            self.normal.space(),
+            self.lo.space(),
+        ) {
+            self.remset.push((slot, kind));
+        }
+    }
+
+    /// the write barrier for a store through an untraced pointer. The slot may be in native
+    /// memory, so we only log it if it is in the heap (e.g. a field of a pinned object)
+    #[inline(always)]
+    pub fn write_barrier_uptr(&mut self, slot: Address, kind: WordType) {
+        if self.tiny.space().addr_in_space(slot)
+            || self.normal.space().addr_in_space(slot)
+            || self.lo.space().addr_in_space(slot)
+        {
+            self.write_barrier(slot, kind);
+        }
+    }
+
     #[inline(always)]
     pub fn yieldpoint(&mut self) {
         if self.global.take_yield() {
@@ -195,6 +271,40 @@ impl Mutator {
         trace!("Mutator{}: yieldpoint triggered, slow path", self.id);
         gc::sync_barrier(self);
     }
+
+    /// the mutator is about to block in native code (and will not reach any yieldpoint).
+    /// The GC treats a mutator in native as stopped, and will not wait for it.
+    /// The mutator should not touch the heap until leave_native()
+    pub fn enter_native(&mut self) {
+        trace!("Mutator{}: enter native", self.id);
+        // return current blocks, as a GC may happen while we are in native
+        self.prepare_for_gc();
+        self.reset_after_gc();
+        // the frames below the native code do not change until we leave native, and the objects
+        // that stack roots refer to are not moved, so the GC can use a snapshot of our stack
+        if self.has_stack_roots {
+            *self.global.native_roots.lock().unwrap() = Some(gc::snapshot_current_stack());
+        }
+        self.global.set_in_native(true);
+    }
+
+    /// the mutator comes back from native code. If a GC is happening, we wait until it is done
+    pub fn leave_native(&mut self) {
+        loop {
+            self.global.set_in_native(false);
+            if !self.global.take_yield() {
+                break;
+            }
+            // a GC is happening (the controller may have counted us as stopped), so we stay
+            // in native until the GC finishes
+            self.global.set_in_native(true);
+            while self.global.take_yield() {
+                thread::yield_now();
+            }
+        }
+        *self.global.native_roots.lock().unwrap() = None;
+        trace!("Mutator{}: leave native", self.id);
+    }
 }
 
 pub trait Allocator {
@@ -206,9 +316,14 @@ pub trait Allocator {
     fn alloc(&mut self, size: ByteSize, align: ByteSize) -> Address;
 }
 
+#[repr(C)]
 pub struct MutatorGlobal {
     take_yield: AtomicBool,
     still_blocked: AtomicBool,
+    in_native: AtomicBool,
+    /// roots from the stack of the mutator while it is in native (and whether they are found
+    /// by conservative scanning)
+    native_roots: Mutex<Option<(Vec<ObjectReference>, bool)>>,
 }
 
 impl MutatorGlobal {
@@ -216,6 +331,8 @@ impl MutatorGlobal {
         MutatorGlobal {
             take_yield: AtomicBool::new(false),
             still_blocked: AtomicBool::new(false),
+            in_native: AtomicBool::new(false),
+            native_roots: Mutex::new(None),
         }
     }
 
@@ -234,4 +351,18 @@ impl MutatorGlobal {
     pub fn take_yield(&self) -> bool {
         self.take_yield.load(Ordering::SeqCst)
     }
+
+    #[inline(always)]
+    pub fn is_in_native(&self) -> bool {
+        self.in_native.load(Ordering::SeqCst)
+    }
+    pub fn set_in_native(&self, b: bool) {
+        self.in_native.store(b, Ordering::SeqCst);
+    }
+
+    /// returns the stack roots that the mutator recorded when it entered native
+    /// (the mutator may stay in native for several collections)
+    pub fn native_roots(&self) -> Option<(Vec<ObjectReference>, bool)> {
+        self.native_roots.lock().unwrap().clone()
+    }
 }
@@ -18,8 +18,11 @@
 //! the interface so the garbage collector is a standalone crate from the VM,
 //! and it should be able to reuse easily outside Zebu project.
 //!
-//! The GC implements immix for small object allocation/reclamation, and
-//! treadmill for large objects. It uses an object model with 64-bits object header
+//! The GC implements immix for small object allocation/reclamation (with opportunistic
+//! defragmentation that evacuates objects from fragmented blocks), and
+//! treadmill for large objects. It is generational (unless set_generational(false)): objects that survive a GC keep their
+//! mark bits (sticky mark bits) and are old, and a minor GC only traces young objects from
+//! roots and the remembered set. A major GC traces the whole heap. It uses an object model with 64-bits object header
 //! before the start of the object. Allocation always returns an ObjectReference
 //! pointing to the start of the object.
 //!
@@ -40,7 +43,11 @@
 //!    if user decides to implement an inlined fastpath
 //! 5. the allocation may trigger a GC, and it is guaranteed to return a valid address
 //! 6. call init_object() or init_hybrid() to initialize the object
-//! 7. when the thread quits, call drop_mutator() to properly destroy a mutator.
+//! 7. after storing a reference to the heap, call muentry_write_barrier() (or
+//!    write_barrier() if the store is not done by a mutator, or muentry_write_barrier_uptr()
+//!    if the slot may be native memory). A user may inline a fast path that skips the call
+//!    for slots in young lines of immix spaces (see IMMIX_SPACE_BASES)
+//! 8. when the thread quits, call drop_mutator() to properly destroy a mutator.
 //!
 //! Other utility functions provided by the GC:
 //!
@@ -53,16 +60,33 @@
 //!   the GC will traverse the heap from given roots, and dump all reachable objects
 //!   in a structured way so that the user can use the data structure to access every
 //!   object and persist them in their own approach
+//! * finalisation - muentry_register_finalizer()/muentry_get_finalizable():
+//!   the GC resurrects a registered object when it becomes unreachable, and queues it for
+//!   a finaliser thread (see heap::gc::finalizer for the ordering guarantees)
+//! * statistics - gc_stats(): the number of collections, pause times, and allocated/live/free
+//!   bytes of each space. force_gc() triggers a major collection
+//! * heap verification - verify_heap(), or set_verify_heap() to verify the heap before and
+//!   after each collection in debug builds: the GC checks space metadata, and every object
+//!   and reference reachable from roots (see heap::gc::verify)
+//! * heap sizing - set_heap_policy(): the heap starts at a target size, and grows or shrinks
+//!   after major collections with the ratio of live data and the pause time target
+//!   (see heap::gc::policy). Spaces reserve their maximum size, but only commit what the heap
+//!   size allows
+//! * out of memory - set_oom_handler(): the handler is called when an allocation fails after
+//!   a major collection. If the handler takes care of the failure, the allocation returns
+//!   null to its caller (otherwise the GC prints a message and exits)
 //!
 //! Issues (going to be fixed in a major GC rewrite):
 //!
-//! * currently collection is disabled: due to bugs (and the fact that we are going to
-//!   majorly change the GC)
+//! * a mutator that blocks in native code (and does not reach yieldpoints) needs to
+//!   call enter_native()/leave_native() around the blocking code, otherwise the GC may
+//!   wait for it forever
 //! * we are using a 64-bits header for each object, we will switch to sidemap object
 //!   model (Issue #12)
-//! * we are allocating the whole heap, and initialize it all at once during startup. We
-//!   should allow dynamic growth of heap (Issue #56)
 //! * pin/unpin operations is different from Mu spec (Issue #33)
+//! * defragmentation only moves objects that are reachable through heap fields. Objects that
+//!   roots refer to are not moved, and internal references (irefs) held in registers or on
+//!   stacks need their base reference to be kept alive alongside them
 //! * we are using some utility C functions (heap/gc/clib_(architecture).c/.S) to help acquire
 //!   some information for GC. And those C functions are not returning accurate results
 //!   (Issue #21)
@@ -111,7 +135,8 @@ pub mod heap;
 
 /// whether this GC will move objects?
 /// (does an object have a fixed address once allocated before it is reclaimed)
-pub const GC_MOVES_OBJECT: bool = false;
+/// Immix defragmentation may move objects, unless they are pinned
+pub const GC_MOVES_OBJECT: bool = true;
 
 /// threshold for small objects. Use small object allocator (immix) for objects that
 /// are smaller than this threshold. Otherwise use large object allocator (freelist)
@@ -134,7 +159,8 @@ struct GC {
     immix_tiny: Raw<ImmixSpace>,
     immix_normal: Raw<ImmixSpace>,
     lo: Raw<FreelistSpace>,
-    roots: LinkedHashSet<ObjectReference>,
+    /// explicit roots (with the number of times each object is added)
+    roots: LinkedHashMap<ObjectReference, usize>,
 }
 
 lazy_static! {
@@ -168,6 +194,9 @@ pub extern "C" fn gc_init(config: GCConfig) {
     // init object model - init this first, since spaces may use it
     objectmodel::init();
 
+    // init heap size - spaces commit their initial memory against it
+    heap::gc::policy::init(config.immix_tiny_size + config.immix_normal_size + config.lo_size);
+
     // init spaces
     trace!("  initializing tiny immix space...");
     let immix_tiny = ImmixSpace::new(SpaceDescriptor::ImmixTiny, config.immix_tiny_size);
@@ -175,6 +204,9 @@ pub extern "C" fn gc_init(config: GCConfig) {
     let immix_normal = ImmixSpace::new(SpaceDescriptor::ImmixNormal, config.immix_normal_size);
     trace!("  initializing large object space...");
     let lo = FreelistSpace::new(SpaceDescriptor::Freelist, config.lo_size);
+    unsafe {
+        IMMIX_SPACE_BASES = [immix_tiny.addr().as_usize(), immix_normal.addr().as_usize()];
+    }
 
     // init GC
     heap::gc::init(config.n_gcthreads);
@@ -182,7 +214,7 @@ pub extern "C" fn gc_init(config: GCConfig) {
         immix_tiny,
         immix_normal,
         lo,
-        roots: LinkedHashSet::new(),
+        roots: LinkedHashMap::new(),
     });
     heap::gc::ENABLE_GC.store(config.enable_gc, Ordering::Relaxed);
 
@@ -270,26 +302,58 @@ pub extern "C" fn drop_mutator(mutator: *mut Mutator) {
     // rust will reclaim the boxed mutator
 }
 
+/// conservatively scans a memory range for roots (for a stack that cannot be scanned precisely)
+pub use heap::gc::scan_range_conservatively;
 /// sets low water mark for current thread
 /// When the GC conservatively scans stack for root, it will not scan beyond the low
 /// water mark
 pub use heap::gc::set_low_water_mark;
+/// sets precise stack scanners (otherwise we scan the stack conservatively)
+pub use heap::gc::{set_inactive_stack_scanner, set_stack_scanner};
 
-/// adds an object reference to the root set
+/// adds an object reference to the root set. The root set counts how many times an object
+/// is added, and the object stays a root until it is removed as many times
 #[no_mangle]
 pub extern "C" fn add_to_root(obj: ObjectReference) {
     let mut gc = MY_GC.write().unwrap();
-    gc.as_mut().unwrap().roots.insert(obj);
+    let roots = &mut gc.as_mut().unwrap().roots;
+    let count = match roots.get(&obj) {
+        Some(count) => *count,
+        None => 0,
+    };
+    roots.insert(obj, count + 1);
 }
 
-/// removes an object reference from the root set
+/// removes an object reference from the root set (once)
 #[no_mangle]
 pub extern "C" fn remove_root(obj: ObjectReference) {
     let mut gc = MY_GC.write().unwrap();
-    gc.as_mut().unwrap().roots.remove(&obj);
+    // the GC may be destroyed before the last references to its objects go away
+    let roots = match gc.as_mut() {
+        Some(gc) => &mut gc.roots,
+        None => return,
+    };
+    let count = match roots.get(&obj) {
+        Some(count) => *count,
+        None => return,
+    };
+    if count > 1 {
+        roots.insert(obj, count - 1);
+    } else {
+        roots.remove(&obj);
+    }
+}
+
+/// checks if an address is in one of the spaces that the GC manages
+pub fn is_heap_address(addr: Address) -> bool {
+    let gc = MY_GC.read().unwrap();
+    match gc.as_ref() {
+        Some(gc) => gc.is_heap_object(addr),
+        None => false,
+    }
 }
 
-/// pins an object so that it will be moved or reclaimed
+/// pins an object so that it will not be moved or reclaimed
 #[no_mangle]
 pub extern "C" fn muentry_pin_object(obj: ObjectReference) -> Address {
     add_to_root(obj);
@@ -317,12 +381,36 @@ pub extern "C" fn yieldpoint_slow(mutator: *mut Mutator) {
     unsafe { mutator.as_mut().unwrap() }.yieldpoint_slow()
 }
 
+/// the slowpath for yieldpoint (called from compiler generated code)
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn muentry_yieldpoint_slow(mutator: *mut Mutator) {
+    yieldpoint_slow(mutator)
+}
+
+/// informs the GC that the mutator is going to block in native code (and will not reach
+/// any yieldpoint). The GC will not wait for the mutator, and uses the roots on its stack
+/// that the mutator records here. The mutator should not access the heap until it calls
+/// leave_native()
+#[no_mangle]
+pub extern "C" fn enter_native(mutator: *mut Mutator) {
+    unsafe { mutator.as_mut().unwrap() }.enter_native();
+}
+
+/// informs the GC that the mutator returns from native code. This call blocks if
+/// a collection is in progress
+#[no_mangle]
+pub extern "C" fn leave_native(mutator: *mut Mutator) {
+    unsafe { mutator.as_mut().unwrap() }.leave_native();
+}
+
 #[inline(always)]
 fn mutator_ref(m: *mut Mutator) -> &'static mut Mutator {
     unsafe { &mut *m }
 }
 
 /// allocates an object in the immix space
+/// (all the allocation functions return null if the heap is out of memory, see set_oom_handler())
 #[no_mangle]
 pub extern "C" fn muentry_alloc_tiny(
     mutator: *mut Mutator,
@@ -341,7 +429,9 @@ pub extern "C" fn muentry_alloc_normal(
 ) -> ObjectReference {
     let m = mutator_ref(mutator);
     let res = m.normal.alloc(size, align);
-    m.normal.post_alloc(res, size);
+    if !res.is_zero() {
+        m.normal.post_alloc(res, size);
+    }
     unsafe { res.to_object_reference() }
 }
 
@@ -367,7 +457,9 @@ pub extern "C" fn muentry_alloc_normal_slow(
 ) -> Address {
     let m = mutator_ref(mutator);
     let res = m.normal.alloc_slow(size, align);
-    m.normal.post_alloc(res, size);
+    if !res.is_zero() {
+        m.normal.post_alloc(res, size);
+    }
     res
 }
 
@@ -431,14 +523,111 @@ pub extern "C" fn muentry_init_large_object(
         .init_object(obj.to_address(), encode);
 }
 
+/// the write barrier. It needs to be called after a reference is stored to memory
+/// (kind is the WordType of the slot: Ref, WeakRef or TaggedRef)
+#[no_mangle]
+pub extern "C" fn muentry_write_barrier(mutator: *mut Mutator, slot: Address, kind: WordType) {
+    mutator_ref(mutator).write_barrier(slot, kind);
+}
+
+/// the write barrier for a store through an untraced pointer (the slot may be native memory,
+/// it is only logged if it is in the heap)
+#[no_mangle]
+pub extern "C" fn muentry_write_barrier_uptr(mutator: *mut Mutator, slot: Address, kind: WordType) {
+    mutator_ref(mutator).write_barrier_uptr(slot, kind);
+}
+
+/// the write barrier for stores that are not done by a mutator thread
+pub use heap::gc::write_barrier_global as write_barrier;
+
+/// registers an object for finalisation. When the object becomes unreachable, the GC
+/// keeps it alive, and queues it for muentry_get_finalizable()
+#[no_mangle]
+pub extern "C" fn muentry_register_finalizer(obj: ObjectReference) {
+    heap::gc::register_finalizer(obj);
+}
+
+/// takes an object that is queued for finalisation (called by a finaliser thread).
+/// It waits a short while (FINALIZER_WAIT_MS) for the queue, and returns null if nothing
+/// is queued. The mutator may stop for a GC in this call
+#[no_mangle]
+pub extern "C" fn muentry_get_finalizable(mutator: *mut Mutator) -> ObjectReference {
+    heap::gc::get_finalizable(mutator_ref(mutator))
+}
+
+/// takes an object that is queued for finalisation, does not wait if the queue is empty
+pub use heap::gc::try_get_finalizable;
+
+/// takes an object that is queued for finalisation and adds it to the root set (for a thread
+/// that is not a mutator, e.g. the client). It holds the GC lock, so no GC can find the object
+/// neither queued nor rooted. The caller removes the root with remove_root()
+pub fn try_get_finalizable_as_root() -> Option<ObjectReference> {
+    let mut gc = MY_GC.write().unwrap();
+    let ret = heap::gc::try_get_finalizable();
+    if let Some(obj) = ret {
+        let roots = &mut gc.as_mut().unwrap().roots;
+        let count = match roots.get(&obj) {
+            Some(count) => *count,
+            None => 0,
+        };
+        roots.insert(obj, count + 1);
+    }
+    ret
+}
+
 /// forces gc to happen
-/// (this is not a 'hint' - world will be stopped, and heap traversal will start)
+/// (this is not a 'hint' - world will be stopped, and a major GC will traverse the heap)
 #[no_mangle]
 pub extern "C" fn force_gc(mutator: *mut Mutator) {
+    heap::gc::request_major_gc();
     heap::gc::trigger_gc();
     yieldpoint(mutator);
 }
 
+/// forces gc to happen from a thread that is not a mutator (e.g. a client of the VM).
+/// The thread joins the GC with a temporary mutator whose stack is not scanned, so it should
+/// only refer to heap objects through roots (which do not move)
+#[no_mangle]
+pub extern "C" fn force_gc_from_client() {
+    let mutator = new_mutator_ptr();
+    unsafe { mutator.as_mut().unwrap() }.set_no_stack_roots();
+    force_gc(mutator);
+    drop_mutator(mutator);
+    unsafe { drop(Box::from_raw(mutator)) };
+}
+
+pub use heap::gc::stats::{GCStats, SpaceStats};
+
+/// returns statistics of the GC (collections so far, pause times and usage of each space)
+#[no_mangle]
+pub extern "C" fn gc_stats() -> GCStats {
+    heap::gc::stats::get_stats()
+}
+
+/// verifies the heap before and after each collection if enabled (only in debug builds)
+pub use heap::gc::set_verify_heap;
+
+/// turns on/off generational collection (after gc_init(), it is on by default).
+/// If the GC is generational, the user needs to call the write barrier after storing
+/// references to memory
+pub use heap::gc::{is_generational, set_generational};
+
+/// sets the minimum, target and maximum heap size, and the pause time target (after gc_init(),
+/// otherwise the heap size is fixed to the sum of the space sizes)
+pub use heap::gc::{set_heap_policy, HeapPolicy};
+
+/// sets a handler for out of memory (it is called on the thread whose allocation fails,
+/// and should not return)
+pub use heap::gc::set_oom_handler;
+
+/// verifies the heap now. The world is stopped (but there is no collection), problems are
+/// printed along with the offending objects, and it panics if there is any
+#[no_mangle]
+pub extern "C" fn verify_heap(mutator: *mut Mutator) {
+    heap::gc::request_heap_verification();
+    yieldpoint(mutator);
+}
+
 /// traces reachable objects and record them as a data structure
 /// so that the user can inspect the reachable heap and persist it in their way
 #[no_mangle]
@@ -20,6 +20,9 @@ pub use self::sidemap::*;
 pub mod immortal;
 pub use self::immortal::*;
 
+pub mod tagref;
+pub use self::tagref::*;
+
 pub fn init() {
     use objectmodel::sidemap::*;
     GlobalTypeTable::init();
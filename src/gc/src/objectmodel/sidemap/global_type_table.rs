@@ -189,6 +189,18 @@ impl GlobalTypeTable {
         debug_assert!(lock.contains_key(&id));
         lock.get(&id).unwrap().clone()
     }
+
+    /// checks if a short entry is set for the id
+    /// (the table is zeroed, and a set entry always has an alignment)
+    pub fn has_short_entry(id: TypeID) -> bool {
+        id < N_TYPES && GlobalTypeTable::table()[id].align() != 0
+    }
+
+    /// checks if a full entry is set for the id
+    pub fn has_full_entry(id: TypeID) -> bool {
+        let meta = GlobalTypeTable::table_meta();
+        meta.full_entries.read().unwrap().contains_key(&id)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,89 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding and encoding of tagref64 values (NaN-boxed doubles, 52-bit ints, or
+//! references with a 6-bit tag). This follows the same scheme as the VM
+//! (`VM::handle_tr64_*`).
+
+use utils::bit_utils::u64_asr;
+use utils::Address;
+
+const TR64_REF_MASK: u64 = 0x7ff0000000000003u64;
+const TR64_REF_BITS: u64 = 0x7ff0000000000002u64;
+/// bits that hold the reference (bit 47 of the address is stored at bit 63)
+const TR64_ADDR_MASK: u64 = 0x7ffffffffff8u64;
+const TR64_ADDR_SIGN_BIT: u64 = 0x8000000000000000u64;
+/// bits that hold the tag
+const TR64_TAG_MASK: u64 = 0x000f800000000004u64;
+
+/// checks if a tagref64 value holds a reference
+#[inline(always)]
+pub fn tagref64_is_ref(v: u64) -> bool {
+    (v & TR64_REF_MASK) == TR64_REF_BITS
+}
+
+/// gets the reference from a tagref64 value (the value needs to hold a reference)
+#[inline(always)]
+pub fn tagref64_get_ref(v: u64) -> Address {
+    debug_assert!(tagref64_is_ref(v));
+    unsafe {
+        Address::from_usize(((v & TR64_ADDR_MASK) | u64_asr(v & TR64_ADDR_SIGN_BIT, 16)) as usize)
+    }
+}
+
+/// replaces the reference in a tagref64 value, and keeps its tag
+/// (used when the referent is moved)
+#[inline(always)]
+pub fn tagref64_set_ref(v: u64, addr: Address) -> u64 {
+    debug_assert!(tagref64_is_ref(v));
+    let addr = addr.as_usize() as u64;
+    TR64_REF_BITS
+        | (v & TR64_TAG_MASK)
+        | (addr & TR64_ADDR_MASK)
+        | ((addr & 0x800000000000u64) << 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reference 0x7f00_1234_5678 with tag 0b101011
+    const TR64_REF: u64 = 0x7ff0000000000002u64
+        | 0x7f0012345678u64
+        | ((0b101011u64 & 0x3e) << 46)
+        | ((0b101011u64 & 0x1) << 2);
+
+    #[test]
+    fn test_is_ref() {
+        assert!(tagref64_is_ref(TR64_REF));
+        // int
+        assert!(!tagref64_is_ref(0x7ff0000000000001u64 | (42 << 1)));
+        // double
+        assert!(!tagref64_is_ref(unsafe { ::std::mem::transmute(1.5f64) }));
+    }
+
+    #[test]
+    fn test_get_ref() {
+        assert_eq!(tagref64_get_ref(TR64_REF).as_usize(), 0x7f0012345678usize);
+    }
+
+    #[test]
+    fn test_set_ref_keeps_tag() {
+        let moved = unsafe { Address::from_usize(0x7f00abcdef00usize) };
+        let v = tagref64_set_ref(TR64_REF, moved);
+        assert!(tagref64_is_ref(v));
+        assert_eq!(tagref64_get_ref(v), moved);
+        assert_eq!(v & TR64_TAG_MASK, TR64_REF & TR64_TAG_MASK);
+    }
+}
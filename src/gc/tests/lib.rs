@@ -17,5 +17,11 @@ extern crate log;
 
 mod test_immix_tiny;
 mod test_immix_normal;
+mod test_immix_defrag;
+mod test_generational;
+mod test_finalizer;
+mod test_gc_stats;
+mod test_heap_verify;
+mod test_heap_policy;
 //mod test_gcbench;
 //mod test_gc_linked_list;
@@ -0,0 +1,99 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::heap::*;
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+use self::mu_utils::*;
+
+const SMALL_SPACE_SIZE: usize = 1 << 19; // 512kb
+
+#[test]
+pub fn test_finalizer_resurrection() {
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: SMALL_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+
+    // insert type (32 bytes, 1st field is a reference)
+    let small_header = {
+        let fix_ty = {
+            let mut ret = [0u8; 63];
+            ret[0] = 0b00000001u8;
+            ret
+        };
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            fix_ty,
+            0,
+            [0; 63]
+        ));
+        let raw_encode = 0b1000_0000_0000_0000u16 | ((id & 0b0001_1111_1111_1111usize) as u16);
+        SmallObjectEncode::new(raw_encode)
+    };
+
+    let normal_space = get_space_immix_normal();
+    let mutator = new_mutator_ptr();
+
+    // a registered object (that refers to another object), nothing else refers to it
+    let obj = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, obj, small_header);
+    let child = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, child, small_header);
+    unsafe {
+        obj.to_address().store(child);
+        (child.to_address() + 8usize).store(42usize);
+    }
+    muentry_register_finalizer(obj);
+
+    // a registered object that is still reachable
+    let live = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, live, small_header);
+    add_to_root(live);
+    muentry_register_finalizer(live);
+
+    // the unreachable object is resurrected (with its child) and queued
+    force_gc(mutator);
+    assert!(normal_space.is_object_traced(obj));
+    assert!(normal_space.is_object_traced(child));
+    assert_eq!(try_get_finalizable(), Some(obj));
+    assert_eq!(unsafe { obj.to_address().load::<ObjectReference>() }, child);
+    assert_eq!(unsafe { (child.to_address() + 8usize).load::<usize>() }, 42);
+
+    // it is only queued once, and the reachable object is not queued
+    assert_eq!(try_get_finalizable(), None);
+    assert!(muentry_get_finalizable(mutator).to_address().is_zero());
+
+    // once it is taken from the queue, it is reclaimed as usual
+    remove_root(live);
+    force_gc(mutator);
+    assert!(!normal_space.is_object_traced(obj));
+    assert!(!normal_space.is_object_traced(child));
+    assert_eq!(try_get_finalizable(), Some(live));
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
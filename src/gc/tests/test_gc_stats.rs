@@ -0,0 +1,91 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::heap::immix::*;
+use self::mu_gc::heap::*;
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+
+const SMALL_SPACE_SIZE: usize = 1 << 19; // 512kb
+
+#[test]
+pub fn test_gc_stats() {
+    const OBJECT_SIZE: usize = 16;
+    const OBJECT_ALIGN: usize = 8;
+    const WORK_LOAD: usize = 1000;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: SMALL_SPACE_SIZE,
+        immix_normal_size: 0,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+
+    let mutator = new_mutator_ptr();
+    let tiny_header = TinyObjectEncode::new(0b0u8);
+
+    let stats = gc_stats();
+    assert_eq!(stats.n_collections, 0);
+    assert_eq!(stats.immix_tiny.allocated_bytes, 0);
+
+    let mut obj = None;
+    for _ in 0..WORK_LOAD {
+        let res = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+        muentry_init_tiny_object(mutator, res, tiny_header);
+        obj = Some(res);
+    }
+    let obj = obj.unwrap();
+
+    // immix spaces count the lines that the mutator takes
+    let stats = gc_stats();
+    assert!(stats.immix_tiny.allocated_bytes >= OBJECT_SIZE * WORK_LOAD);
+    assert_eq!(stats.immix_tiny.allocated_bytes % BYTES_IN_LINE, 0);
+    assert_eq!(stats.immix_normal.allocated_bytes, 0);
+    assert_eq!(stats.lo.allocated_bytes, 0);
+
+    // one object survives: its block is fragmented
+    add_to_root(obj);
+    force_gc(mutator);
+    let stats = gc_stats();
+    assert_eq!(stats.n_collections, 1);
+    assert_eq!(stats.n_major_collections, 1);
+    assert!(stats.last_pause_ns > 0);
+    assert_eq!(stats.max_pause_ns, stats.last_pause_ns);
+    assert_eq!(stats.total_pause_ns, stats.last_pause_ns);
+    assert!(stats.immix_tiny.live_bytes > 0);
+    assert!(stats.immix_tiny.fragmented_bytes > 0);
+    assert!(stats.immix_tiny.fragmented_bytes <= stats.immix_tiny.free_bytes);
+    assert!(stats.immix_tiny.fragmentation() > 0f64);
+
+    // nothing survives
+    remove_root(obj);
+    force_gc(mutator);
+    let stats = gc_stats();
+    assert_eq!(stats.n_collections, 2);
+    assert_eq!(stats.n_major_collections, 2);
+    assert!(stats.total_pause_ns >= stats.max_pause_ns);
+    assert!(stats.max_pause_ns >= stats.last_pause_ns);
+    assert_eq!(stats.immix_tiny.live_bytes, 0);
+    assert_eq!(stats.immix_tiny.fragmented_bytes, 0);
+    assert_eq!(stats.immix_tiny.fragmentation(), 0f64);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
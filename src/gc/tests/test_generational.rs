@@ -0,0 +1,227 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::heap::gc::*;
+use self::mu_gc::heap::*;
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+use self::mu_utils::*;
+
+const SMALL_SPACE_SIZE: usize = 1 << 19; // 512kb
+
+/// triggers a GC without requesting a major GC (as allocation does)
+fn minor_gc(mutator: *mut Mutator) {
+    trigger_gc();
+    yieldpoint(mutator);
+}
+
+#[test]
+pub fn test_minor_gc_remembered_set() {
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: SMALL_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+
+    // insert type (32 bytes, 1st field is a reference)
+    let small_header = {
+        let fix_ty = {
+            let mut ret = [0u8; 63];
+            ret[0] = 0b00000001u8;
+            ret
+        };
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            fix_ty,
+            0,
+            [0; 63]
+        ));
+        let raw_encode = 0b1000_0000_0000_0000u16 | ((id & 0b0001_1111_1111_1111usize) as u16);
+        SmallObjectEncode::new(raw_encode)
+    };
+
+    let normal_space = get_space_immix_normal();
+    let mutator = new_mutator_ptr();
+
+    // an object that survives a GC becomes old
+    let old = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, old, small_header);
+    add_to_root(old);
+    minor_gc(mutator);
+    assert!(!is_major_gc());
+    assert!(normal_space.is_object_traced(old));
+
+    // store a young object to the old object (with the write barrier)
+    let young = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, young, small_header);
+    unsafe {
+        (young.to_address() + 8usize).store(42usize);
+        old.to_address().store(young);
+    }
+    muentry_write_barrier(mutator, old.to_address(), WordType::Ref);
+
+    // a young object that nothing refers to
+    let garbage = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, garbage, small_header);
+
+    // the minor GC finds the young object from the remembered set
+    minor_gc(mutator);
+    assert!(!is_major_gc());
+    assert!(normal_space.is_object_traced(young));
+    assert!(!normal_space.is_object_traced(garbage));
+    assert_eq!(unsafe { old.to_address().load::<ObjectReference>() }, young);
+    assert_eq!(unsafe { (young.to_address() + 8usize).load::<usize>() }, 42);
+
+    // old objects are not reclaimed by a minor GC
+    remove_root(old);
+    minor_gc(mutator);
+    assert!(!is_major_gc());
+    assert!(normal_space.last_gc_used_lines > 0);
+
+    // but they are reclaimed by a major GC
+    force_gc(mutator);
+    assert!(is_major_gc());
+    assert_eq!(normal_space.last_gc_used_lines, 0);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
+
+#[test]
+pub fn test_minor_gc_write_barrier_uptr() {
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: SMALL_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+
+    // insert type (32 bytes, 1st field is a reference)
+    let small_header = {
+        let fix_ty = {
+            let mut ret = [0u8; 63];
+            ret[0] = 0b00000001u8;
+            ret
+        };
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            fix_ty,
+            0,
+            [0; 63]
+        ));
+        let raw_encode = 0b1000_0000_0000_0000u16 | ((id & 0b0001_1111_1111_1111usize) as u16);
+        SmallObjectEncode::new(raw_encode)
+    };
+
+    let normal_space = get_space_immix_normal();
+    let mutator = new_mutator_ptr();
+
+    let old = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, old, small_header);
+    add_to_root(old);
+    minor_gc(mutator);
+    assert!(!is_major_gc());
+
+    // a store through a pointer (uptr) to the old object needs the barrier as well
+    let young = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, young, small_header);
+    unsafe {
+        old.to_address().store(young);
+    }
+    muentry_write_barrier_uptr(mutator, old.to_address(), WordType::Ref);
+
+    // a store through a pointer to native memory is ignored by the barrier
+    let mut native_slot = young;
+    muentry_write_barrier_uptr(
+        mutator,
+        Address::from_mut_ptr(&mut native_slot as *mut ObjectReference),
+        WordType::Ref
+    );
+
+    minor_gc(mutator);
+    assert!(!is_major_gc());
+    assert!(normal_space.is_object_traced(young));
+    assert_eq!(unsafe { old.to_address().load::<ObjectReference>() }, young);
+
+    remove_root(old);
+    drop_mutator(mutator);
+    gc_destroy();
+}
+
+#[test]
+pub fn test_non_generational_gc() {
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: SMALL_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+    set_generational(false);
+    assert!(!is_generational());
+
+    // insert type (32 bytes, no reference)
+    let small_header = {
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            [0; 63],
+            0,
+            [0; 63]
+        ));
+        let raw_encode = 0b1000_0000_0000_0000u16 | ((id & 0b0001_1111_1111_1111usize) as u16);
+        SmallObjectEncode::new(raw_encode)
+    };
+
+    let normal_space = get_space_immix_normal();
+    let mutator = new_mutator_ptr();
+
+    let obj = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, obj, small_header);
+    add_to_root(obj);
+
+    // every collection is a major GC
+    minor_gc(mutator);
+    assert!(is_major_gc());
+    assert!(normal_space.is_object_traced(obj));
+
+    remove_root(obj);
+    minor_gc(mutator);
+    assert!(is_major_gc());
+    assert_eq!(normal_space.last_gc_used_lines, 0);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
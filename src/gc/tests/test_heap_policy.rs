@@ -0,0 +1,93 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::heap::*;
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+
+const LO_SPACE_SIZE: usize = 32 << 20; // 32mb
+const HEAP_SIZE: usize = 1 << 20; // 1mb
+
+#[test]
+pub fn test_heap_grow_and_shrink() {
+    const OBJECT_SIZE: usize = 4096;
+    const OBJECT_ALIGN: usize = 8;
+    const WORK_LOAD: usize = 640;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: 0,
+        lo_size: LO_SPACE_SIZE,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+    set_heap_policy(HeapPolicy {
+        min_size: HEAP_SIZE,
+        target_size: HEAP_SIZE,
+        max_size: LO_SPACE_SIZE,
+        pause_target_ms: 0
+    });
+
+    let header = {
+        let id = GlobalTypeTable::insert_full_entry(FullTypeEncode {
+            align: 8,
+            fix: vec![WordType::NonRef; OBJECT_SIZE >> 3],
+            var: vec![]
+        });
+        LargeObjectEncode::new(OBJECT_SIZE, id)
+    };
+
+    let mutator = new_mutator_ptr();
+
+    // all the objects are live: the heap grows beyond its target size
+    let mut objs = vec![];
+    for _ in 0..WORK_LOAD {
+        let res = muentry_alloc_large(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+        muentry_init_large_object(mutator, res, header);
+        add_to_root(res);
+        objs.push(res);
+    }
+    let stats = gc_stats();
+    assert!(stats.n_major_collections > 0);
+    assert!(stats.heap_size > HEAP_SIZE);
+    assert!(stats.committed_bytes >= OBJECT_SIZE * WORK_LOAD);
+    assert!(stats.committed_bytes <= stats.heap_size);
+
+    // nothing survives: the heap shrinks back, and the space gives its memory back
+    for obj in objs {
+        remove_root(obj);
+    }
+    force_gc(mutator);
+    let stats = gc_stats();
+    assert_eq!(stats.lo.live_bytes, 0);
+    assert_eq!(stats.heap_size, HEAP_SIZE);
+    assert!(stats.committed_bytes <= HEAP_SIZE);
+
+    // the released memory can be used again
+    let res = muentry_alloc_large(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_large_object(mutator, res, header);
+    add_to_root(res);
+    force_gc(mutator);
+    verify_heap(mutator);
+    let stats = gc_stats();
+    assert_eq!(stats.lo.live_bytes, OBJECT_SIZE);
+    remove_root(res);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
@@ -0,0 +1,105 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::heap::gc::verify;
+use self::mu_gc::heap::gc::*;
+use self::mu_gc::heap::*;
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+use self::mu_utils::*;
+
+const SMALL_SPACE_SIZE: usize = 1 << 19; // 512kb
+
+#[test]
+pub fn test_heap_verify() {
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+    const LIST_LENGTH: usize = 100;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: SMALL_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+    set_verify_heap(true);
+
+    // insert type (32 bytes, 1st field is a reference)
+    let small_header = {
+        let fix_ty = {
+            let mut ret = [0u8; 63];
+            ret[0] = 0b00000001u8;
+            ret
+        };
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            fix_ty,
+            0,
+            [0; 63]
+        ));
+        SmallObjectEncode::create(OBJECT_SIZE, id)
+    };
+
+    let mutator = new_mutator_ptr();
+
+    // a linked list
+    let head = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_small_object(mutator, head, small_header);
+    let mut last = head;
+    for _ in 1..LIST_LENGTH {
+        let obj = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+        muentry_init_small_object(mutator, obj, small_header);
+        unsafe { last.to_address().store(obj) };
+        last = obj;
+    }
+    add_to_root(head);
+
+    // a valid heap passes verification before and after GC, and on demand
+    force_gc(mutator);
+    verify_heap(mutator);
+    let roots = vec![head];
+    assert_eq!(verify::verify_heap(&roots, VerifyPhase::OnDemand), 0);
+
+    // a reference that is not an object start
+    let second = unsafe { head.to_address().load::<ObjectReference>() };
+    unsafe { head.to_address().store(second.to_address() + 4usize) };
+    assert_eq!(verify::verify_heap(&roots, VerifyPhase::OnDemand), 1);
+    unsafe { head.to_address().store(second) };
+
+    // an object with an unknown type (at the end of the list, objects may have moved)
+    let mut last = head;
+    loop {
+        let next = unsafe { last.to_address().load::<ObjectReference>() };
+        if next.to_address().is_zero() {
+            break;
+        }
+        last = next;
+    }
+    let bad_header = SmallObjectEncode::create(OBJECT_SIZE, (1 << SMALL_ID_WIDTH) - 1);
+    muentry_init_small_object(mutator, last, bad_header);
+    assert_eq!(verify::verify_heap(&roots, VerifyPhase::OnDemand), 1);
+    muentry_init_small_object(mutator, last, small_header);
+
+    assert_eq!(verify::verify_heap(&roots, VerifyPhase::OnDemand), 0);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
@@ -0,0 +1,123 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate log;
+extern crate mu_gc;
+extern crate mu_utils;
+
+use self::mu_gc::objectmodel::sidemap::*;
+use self::mu_gc::*;
+use self::mu_utils::*;
+
+const SMALL_SPACE_SIZE: usize = 1 << 19; // 512kb
+
+/// walks the linked list from head, checks the values in the nodes, and returns node addresses
+fn check_linked_list(head: Address, len: usize) -> Vec<Address> {
+    let mut ret = vec![];
+    let mut cur = head;
+    while !cur.is_zero() {
+        let value = unsafe { (cur + 8usize).load::<usize>() };
+        assert_eq!(value, len - 1 - ret.len());
+        ret.push(cur);
+        cur = unsafe { cur.load::<Address>() };
+    }
+    assert_eq!(ret.len(), len);
+    ret
+}
+
+#[test]
+pub fn test_immix_defrag_linkedlist() {
+    const IMMIX_SPACE_SIZE: usize = SMALL_SPACE_SIZE;
+    const OBJECT_SIZE: usize = 32; // small object
+    const OBJECT_ALIGN: usize = 8;
+    const LIST_LEN: usize = 32;
+    // garbage objects we allocate after each list node (so that live lines are
+    // separated by free lines after a GC)
+    const GARBAGE_PER_NODE: usize = 31;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: 0,
+        immix_normal_size: IMMIX_SPACE_SIZE,
+        lo_size: 0,
+        n_gcthreads: 1,
+        enable_gc: true
+    });
+
+    // insert type (32 bytes, 1st field is a reference)
+    let small_header = {
+        let fix_ty = {
+            let mut ret = [0u8; 63];
+            ret[0] = 0b00000001u8;
+            ret
+        };
+        let id = GlobalTypeTable::insert_small_entry(ShortTypeEncode::new(
+            OBJECT_ALIGN,
+            4,
+            fix_ty,
+            0,
+            [0; 63]
+        ));
+        let raw_encode = 0b1000_0000_0000_0000u16 | ((id & 0b0001_1111_1111_1111usize) as u16);
+        SmallObjectEncode::new(raw_encode)
+    };
+
+    let normal_space = get_space_immix_normal();
+    let mutator = new_mutator_ptr();
+
+    // the 1st field of a node points to the last node, and the 2nd field is its index
+    let mut last_obj: Address = unsafe { Address::zero() };
+    for i in 0..LIST_LEN {
+        yieldpoint(mutator);
+        let res = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+        muentry_init_small_object(mutator, res, small_header);
+        unsafe {
+            res.to_address().store(last_obj);
+            (res.to_address() + 8usize).store(i);
+        }
+        last_obj = res.to_address();
+
+        for _ in 0..GARBAGE_PER_NODE {
+            let garbage = muentry_alloc_normal(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+            muentry_init_small_object(mutator, garbage, small_header);
+        }
+    }
+
+    // only the head is a root (so it is pinned)
+    let head = unsafe { last_obj.to_object_reference() };
+    add_to_root(head);
+
+    // the 1st gc reclaims garbage, and leaves holes in the block
+    force_gc(mutator);
+    let before = check_linked_list(head.to_address(), LIST_LEN);
+    let used_lines_before = normal_space.last_gc_used_lines;
+
+    // the 2nd gc finds the block fragmented, and evacuates the nodes
+    force_gc(mutator);
+    let after = check_linked_list(head.to_address(), LIST_LEN);
+    assert_eq!(before[0], after[0]);
+    assert!(before.iter().zip(after.iter()).skip(1).all(|(a, b)| a != b));
+    assert!(normal_space.last_gc_used_lines < used_lines_before);
+
+    // the list is still intact after another gc
+    force_gc(mutator);
+    check_linked_list(head.to_address(), LIST_LEN);
+
+    remove_root(head);
+    force_gc(mutator);
+    assert_eq!(normal_space.last_gc_used_lines, 0);
+
+    drop_mutator(mutator);
+    gc_destroy();
+}
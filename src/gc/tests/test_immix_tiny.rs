@@ -195,3 +195,131 @@ pub fn test_tiny_immix_linkedlist() {
     drop_mutator(mutator);
     gc_destroy();
 }
+
+#[test]
+pub fn test_tiny_immix_weakref() {
+    const IMMIX_SPACE_SIZE: usize = SMALL_SPACE_SIZE;
+    const OBJECT_SIZE: usize = 16;
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: IMMIX_SPACE_SIZE,
+        immix_normal_size: 0,
+        lo_size: 0,
+        n_gcthreads: 8,
+        enable_gc: true
+    });
+
+    let mutator = new_mutator_ptr();
+    let leaf_header = TinyObjectEncode::new(0b0u8);
+    // first field is a weak reference, second field is a reference
+    let weak_header = TinyObjectEncode::create(
+        OBJECT_SIZE,
+        WordType::WeakRef,
+        WordType::Ref,
+        WordType::NonRef,
+    );
+
+    let holder = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_tiny_object(mutator, holder, weak_header);
+    let weak_target = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_tiny_object(mutator, weak_target, leaf_header);
+    let strong_target = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_tiny_object(mutator, strong_target, leaf_header);
+
+    unsafe {
+        holder.to_address().store(weak_target.to_address());
+        (holder.to_address() + POINTER_SIZE).store(strong_target.to_address());
+    }
+
+    // the weak referent is kept alive by the root set, the weak ref stays
+    add_to_root(holder);
+    add_to_root(weak_target);
+    force_gc(mutator);
+    assert_eq!(
+        unsafe { holder.to_address().load::<Address>() },
+        weak_target.to_address()
+    );
+
+    // only reachable via the weak ref, it gets cleared
+    remove_root(weak_target);
+    force_gc(mutator);
+    assert!(unsafe { holder.to_address().load::<Address>() }.is_zero());
+    // strong ref is untouched
+    assert_eq!(
+        unsafe { (holder.to_address() + POINTER_SIZE).load::<Address>() },
+        strong_target.to_address()
+    );
+
+    remove_root(holder);
+    drop_mutator(mutator);
+    gc_destroy();
+}
+
+#[test]
+pub fn test_tiny_immix_tagref() {
+    const IMMIX_SPACE_SIZE: usize = SMALL_SPACE_SIZE;
+    const OBJECT_SIZE: usize = 16;
+    const OBJECT_ALIGN: usize = 8;
+
+    start_logging_trace();
+    gc_init(GCConfig {
+        immix_tiny_size: IMMIX_SPACE_SIZE,
+        immix_normal_size: 0,
+        lo_size: 0,
+        n_gcthreads: 8,
+        enable_gc: true
+    });
+
+    let mutator = new_mutator_ptr();
+    let leaf_header = TinyObjectEncode::new(0b0u8);
+    // first field is a tagref64, second field is a weak reference
+    // (the weak reference tells us whether the target survives)
+    let header = TinyObjectEncode::create(
+        OBJECT_SIZE,
+        WordType::TaggedRef,
+        WordType::WeakRef,
+        WordType::NonRef,
+    );
+
+    let holder = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_tiny_object(mutator, holder, header);
+    let target = muentry_alloc_tiny(mutator, OBJECT_SIZE, OBJECT_ALIGN);
+    muentry_init_tiny_object(mutator, target, leaf_header);
+
+    // tagref64 holding target with tag 0b101011
+    let addr = target.to_address().as_usize() as u64;
+    let tagref = 0x7ff0000000000002u64
+        | (addr & 0x7ffffffffff8u64)
+        | ((addr & 0x800000000000u64) << 16)
+        | ((0b101011u64 & 0x3e) << 46)
+        | ((0b101011u64 & 0x1) << 2);
+
+    unsafe {
+        holder.to_address().store(tagref);
+        (holder.to_address() + POINTER_SIZE).store(target.to_address());
+    }
+
+    // target is kept alive via the tagref
+    add_to_root(holder);
+    force_gc(mutator);
+    assert_eq!(unsafe { holder.to_address().load::<u64>() }, tagref);
+    assert_eq!(
+        unsafe { (holder.to_address() + POINTER_SIZE).load::<Address>() },
+        target.to_address()
+    );
+
+    // the tagref holds an int now, target dies
+    let tagref_int = 0x7ff0000000000001u64 | (42u64 << 1);
+    unsafe {
+        holder.to_address().store(tagref_int);
+    }
+    force_gc(mutator);
+    assert_eq!(unsafe { holder.to_address().load::<u64>() }, tagref_int);
+    assert!(unsafe { (holder.to_address() + POINTER_SIZE).load::<Address>() }.is_zero());
+
+    remove_root(holder);
+    drop_mutator(mutator);
+    gc_destroy();
+}
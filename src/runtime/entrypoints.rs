@@ -20,13 +20,23 @@ use ast::ptr::*;
 use ast::types::*;
 use compiler::backend::RegGroup;
 
-use std::sync::{Arc, RwLock};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
 pub type EntryFuncSig = MuFuncSig;
 
+lazy_static! {
+    /// symbol names of the entrypoints that may trigger GC. An entrypoint is added when it is
+    /// created (the compiler gets the entrypoint before it emits a call to its symbol)
+    static ref GC_SAFEPOINTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
 pub struct RuntimeEntrypoint {
     pub sig: P<MuFuncSig>,
     pub aot: ValueLocation,
     pub jit: RwLock<Option<ValueLocation>>,
+    /// may this entrypoint trigger GC? If so, the compiler calls it through
+    /// muentry_gc_safecall so that the GC can scan Mu frames precisely
+    pub may_trigger_gc: bool,
 }
 
 impl RuntimeEntrypoint {
@@ -39,6 +49,20 @@ impl RuntimeEntrypoint {
             }),
             aot: ValueLocation::Relocatable(RegGroup::GPR, Arc::new(c_name.to_string())),
             jit: RwLock::new(None),
+            may_trigger_gc: false,
+        }
+    }
+
+    /// creates an entrypoint that may trigger GC
+    fn new_gc_safepoint(
+        c_name: &str,
+        arg_tys: Vec<P<MuType>>,
+        ret_tys: Vec<P<MuType>>,
+    ) -> RuntimeEntrypoint {
+        GC_SAFEPOINTS.lock().unwrap().insert(c_name.to_string());
+        RuntimeEntrypoint {
+            may_trigger_gc: true,
+            ..RuntimeEntrypoint::new(c_name, arg_tys, ret_tys)
         }
     }
 }
@@ -72,6 +96,12 @@ lazy_static! {
         "muentry_safecall_kill_stack",
         vec![STACKREF_TYPE.clone()],
         vec![]);
+    // impl: runtime_ARCH_OS.S
+    // (takes the actual target in a scratch register, and arguments for the target)
+    pub static ref GC_SAFECALL: RuntimeEntrypoint = RuntimeEntrypoint::new(
+        "muentry_gc_safecall",
+        vec![],
+        vec![]);
     pub static ref NEW_THREAD_NORMAL: RuntimeEntrypoint = RuntimeEntrypoint::new(
         "muentry_new_thread_normal",
         vec![STACKREF_TYPE.clone(), REF_VOID_TYPE.clone()],
@@ -84,7 +114,7 @@ lazy_static! {
 
 // impl/decl: gc/lib.rs
 lazy_static! {
-    pub static ref ALLOC_TINY: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_TINY: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_tiny",
         vec![
             ADDRESS_TYPE.clone(),
@@ -93,7 +123,7 @@ lazy_static! {
         ],
         vec![ADDRESS_TYPE.clone()]
     );
-    pub static ref ALLOC_TINY_SLOW: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_TINY_SLOW: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_tiny_slow",
         vec![
             ADDRESS_TYPE.clone(),
@@ -102,7 +132,7 @@ lazy_static! {
         ],
         vec![ADDRESS_TYPE.clone()]
     );
-    pub static ref ALLOC_NORMAL: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_NORMAL: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_normal",
         vec![
             ADDRESS_TYPE.clone(),
@@ -111,7 +141,7 @@ lazy_static! {
         ],
         vec![ADDRESS_TYPE.clone()]
     );
-    pub static ref ALLOC_NORMAL_SLOW: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_NORMAL_SLOW: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_normal_slow",
         vec![
             ADDRESS_TYPE.clone(),
@@ -120,7 +150,7 @@ lazy_static! {
         ],
         vec![ADDRESS_TYPE.clone()]
     );
-    pub static ref ALLOC_LARGE: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_LARGE: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_large",
         vec![
             ADDRESS_TYPE.clone(),
@@ -129,7 +159,7 @@ lazy_static! {
         ],
         vec![ADDRESS_TYPE.clone()]
     );
-    pub static ref ALLOC_VAR_SIZE: RuntimeEntrypoint = RuntimeEntrypoint::new(
+    pub static ref ALLOC_VAR_SIZE: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
         "muentry_alloc_var_size",
         vec![
             UINT64_TYPE.clone(),
@@ -185,6 +215,43 @@ lazy_static! {
     );
     pub static ref UNPIN_OBJECT: RuntimeEntrypoint =
         RuntimeEntrypoint::new("muentry_unpin_object", vec![ADDRESS_TYPE.clone()], vec![]);
+    pub static ref WRITE_BARRIER: RuntimeEntrypoint = RuntimeEntrypoint::new(
+        "muentry_write_barrier",
+        vec![
+            ADDRESS_TYPE.clone(),
+            ADDRESS_TYPE.clone(),
+            UINT8_TYPE.clone()
+        ],
+        vec![]
+    );
+    pub static ref WRITE_BARRIER_UPTR: RuntimeEntrypoint = RuntimeEntrypoint::new(
+        "muentry_write_barrier_uptr",
+        vec![
+            ADDRESS_TYPE.clone(),
+            ADDRESS_TYPE.clone(),
+            UINT8_TYPE.clone()
+        ],
+        vec![]
+    );
+    pub static ref YIELDPOINT_SLOW: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
+        "muentry_yieldpoint_slow",
+        vec![ADDRESS_TYPE.clone()],
+        vec![]
+    );
+    pub static ref REGISTER_FINALIZER: RuntimeEntrypoint =
+        RuntimeEntrypoint::new("muentry_register_finalizer", vec![ADDRESS_TYPE.clone()], vec![]);
+    // the finaliser thread may stop for a GC while it waits for the queue
+    pub static ref GET_FINALIZABLE: RuntimeEntrypoint = RuntimeEntrypoint::new_gc_safepoint(
+        "muentry_get_finalizable",
+        vec![ADDRESS_TYPE.clone()],
+        vec![ADDRESS_TYPE.clone()]
+    );
+}
+
+/// checks if a native function (by its symbol name) is a runtime entrypoint that may trigger GC
+/// (the compiler calls them through muentry_gc_safecall)
+pub fn is_gc_safepoint(c_name: &str) -> bool {
+    GC_SAFEPOINTS.lock().unwrap().contains(c_name)
 }
 
 // decl: exception.rs
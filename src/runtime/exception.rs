@@ -15,7 +15,9 @@
 use compiler::backend::*;
 use compiler::machine_code::CompiledCallsite;
 use log;
+use runtime::mm;
 use runtime::*;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::ops::Deref;
 use utils::Address;
@@ -136,6 +138,42 @@ pub extern "C" fn throw_exception_internal(exception_obj: Address, frame_cursor:
     }
 }
 
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+#[link(name = "runtime_c")]
+extern "C" {
+    fn get_gc_frame_cursor() -> Address;
+}
+
+thread_local! {
+    /// set when an allocation called through muentry_gc_safecall runs out of memory
+    static PENDING_OOM: Cell<bool> = Cell::new(false);
+}
+
+/// handles out of memory for the GC (installed by mm::init_oom_exception()). It always
+/// takes care of the failure, so the allocation returns null to its caller.
+/// Runtime entries that allocate are called through muentry_gc_safecall, so for allocation
+/// from Mu code we leave the out-of-memory exception pending, and muentry_gc_safecall throws
+/// it once the runtime entry has returned. Other callers (e.g. the API) check for null.
+pub fn out_of_memory() -> bool {
+    let cursor = unsafe { get_gc_frame_cursor() };
+    if !cursor.is_zero() && thread::MuThread::has_current() {
+        PENDING_OOM.with(|pending| pending.set(true));
+    }
+    true
+}
+
+/// called by muentry_gc_safecall after the runtime entry returns. Returns the exception to
+/// throw to the Mu code (or null if there is none)
+#[no_mangle]
+pub extern "C" fn take_gc_safecall_exception() -> Address {
+    if PENDING_OOM.with(|pending| pending.replace(false)) {
+        mm::get_oom_exception().to_address()
+    } else {
+        unsafe { Address::zero() }
+    }
+}
+
 /// prints current frame cursor
 fn print_frame(cursor: Address) {
     let top = 2;
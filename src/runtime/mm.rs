@@ -24,6 +24,7 @@ use ast::ptr::*;
 use ast::types::*;
 use compiler::backend::BackendType;
 use compiler::backend::RegGroup;
+use runtime::exception;
 use runtime::thread::MuThread;
 use runtime::ValueLocation;
 use utils::math;
@@ -32,6 +33,12 @@ use utils::ObjectReference;
 use utils::*;
 use vm::VM;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// the exception object that Mu code gets when the heap is out of memory (we allocate it
+/// when the runtime starts, as we cannot allocate it once the heap is out of memory)
+static OOM_EXCEPTION: AtomicUsize = AtomicUsize::new(0);
+
 /// we do not allocate hybrid into tiny object space (hybrid should be at least 32 bytes)
 pub fn check_hybrid_size(size: ByteSize) -> ByteSize {
     if size < 32 {
@@ -41,6 +48,21 @@ pub fn check_hybrid_size(size: ByteSize) -> ByteSize {
     }
 }
 
+/// returns the kind of write barrier (as a GC word type) that a store of the given type
+/// to the heap needs, or None if the store does not need a write barrier
+pub fn write_barrier_kind(ty: &MuType) -> Option<WordType> {
+    if !is_generational() {
+        return None;
+    }
+
+    match ty.v {
+        MuType_::Ref(_) => Some(WordType::Ref),
+        MuType_::WeakRef(_) => Some(WordType::WeakRef),
+        MuType_::Tagref64 => Some(WordType::TaggedRef),
+        _ => None,
+    }
+}
+
 pub fn gen_object_encode(backend_ty: &BackendType, size: ByteSize, vm: &VM) -> ObjectEncode {
     let is_hybrid = backend_ty.ty.is_hybrid();
     let gc_tyid = {
@@ -135,6 +157,10 @@ pub extern "C" fn muentry_alloc_var_size(
     } else {
         muentry_alloc_large(mutator, size, align)
     };
+    // out of memory: muentry_gc_safecall throws the exception
+    if res.to_address().is_zero() {
+        return res;
+    }
 
     // get encoding
     let ref vm = cur_thread.vm;
@@ -154,22 +180,70 @@ pub extern "C" fn muentry_alloc_var_size(
 
 /// finds an allocator and allocates memory
 /// if current thread has an allocator, use the allocator. Otherwise creates a new allocator,
-/// allocates objects and drops the allocator
+/// allocates objects and drops the allocator.
+/// The object is added to the roots before the allocator is dropped (a GC cannot start while
+/// the allocator is alive), so the caller needs to remove the root once the object is kept
+/// alive in another way. Returns null (and adds no root) if the heap is out of memory.
 fn check_allocator(size: ByteSize, align: ByteSize, encode: ObjectEncode) -> ObjectReference {
     if MuThread::has_current() {
         // we have an allocator
         let allocator = (&mut MuThread::current_mut().allocator) as *mut Mutator;
-        allocate(allocator, size, align, encode)
+        let ret = allocate(allocator, size, align, encode);
+        if !ret.to_address().is_zero() {
+            add_to_root(ret);
+        }
+
+        ret
     } else {
         let allocator = new_mutator_ptr();
+        // the client refers to objects through handles (roots), the GC does not scan our stack
+        unsafe { &mut *allocator }.set_no_stack_roots();
         let ret = allocate(allocator, size, align, encode);
+        if !ret.to_address().is_zero() {
+            add_to_root(ret);
+        }
         drop_mutator(allocator);
 
         ret
     }
 }
 
-/// allocates and initiates an object (hybrid or other types, large or small)
+/// allocates the out-of-memory exception (a tiny object without references), and installs
+/// the handler that throws it (called after gc_init())
+pub fn init_oom_exception() {
+    let allocator = new_mutator_ptr();
+    let size = 16;
+    let obj = muentry_alloc_tiny(allocator, size, POINTER_SIZE);
+    let encode =
+        TinyObjectEncode::create(size, WordType::NonRef, WordType::NonRef, WordType::NonRef);
+    muentry_init_tiny_object(allocator, obj, encode);
+    drop_mutator(allocator);
+
+    // the object is a root, so it stays alive and in place
+    add_to_root(obj);
+    OOM_EXCEPTION.store(obj.to_address().as_usize(), Ordering::SeqCst);
+    set_oom_handler(exception::out_of_memory);
+}
+
+/// returns the exception object that is thrown when the heap is out of memory
+pub fn get_oom_exception() -> ObjectReference {
+    unsafe { Address::from_usize(OOM_EXCEPTION.load(Ordering::SeqCst)).to_object_reference() }
+}
+
+/// forces a major collection for the client (through API). It uses the allocator of
+/// current thread if it has one. Otherwise the client joins the GC without a stack to scan:
+/// it refers to objects through handles, which keep their objects as roots (and in place)
+pub fn force_collection() {
+    if MuThread::has_current() {
+        let allocator = (&mut MuThread::current_mut().allocator) as *mut Mutator;
+        force_gc(allocator);
+    } else {
+        force_gc_from_client();
+    }
+}
+
+/// allocates and initiates an object (hybrid or other types, large or small).
+/// Returns null if the heap is out of memory
 #[inline(always)]
 fn allocate(
     allocator: *mut Mutator,
@@ -181,24 +255,36 @@ fn allocate(
     // allocate
     if size <= MAX_TINY_OBJECT {
         let res = muentry_alloc_tiny(allocator, size, align);
+        if res.to_address().is_zero() {
+            return res;
+        }
         muentry_init_tiny_object(allocator, res, encode.tiny());
         res
     } else if size <= MAX_SMALL_OBJECT {
         let res = muentry_alloc_normal(allocator, size, align);
+        if res.to_address().is_zero() {
+            return res;
+        }
         muentry_init_small_object(allocator, res, encode.small());
         res
     } else if size <= MAX_MEDIUM_OBJECT {
         let res = muentry_alloc_normal(allocator, size, align);
+        if res.to_address().is_zero() {
+            return res;
+        }
         muentry_init_medium_object(allocator, res, encode.medium());
         res
     } else {
         let res = muentry_alloc_large(allocator, size, align);
+        if res.to_address().is_zero() {
+            return res;
+        }
         muentry_init_large_object(allocator, res, encode.large());
         res
     }
 }
 
-/// allocates an object of fixed types
+/// allocates an object of fixed types (the object is a root until remove_root() is called)
 pub fn allocate_fixed(ty: P<MuType>, backendtype: Box<BackendType>, vm: &VM) -> Address {
     let encode = gen_object_encode(&backendtype, backendtype.size, vm);
 
@@ -206,7 +292,7 @@ pub fn allocate_fixed(ty: P<MuType>, backendtype: Box<BackendType>, vm: &VM) ->
     check_allocator(backendtype.size, backendtype.alignment, encode).to_address()
 }
 
-/// allocates an object of hybrid types
+/// allocates an object of hybrid types (the object is a root until remove_root() is called)
 pub fn allocate_hybrid(
     ty: P<MuType>,
     len: usize,
@@ -220,7 +306,7 @@ pub fn allocate_hybrid(
     check_allocator(size, backendtype.alignment, encode).to_address()
 }
 
-/// allocates a global cell
+/// allocates a global cell (global cells stay roots, so they are never moved or reclaimed)
 pub fn allocate_global(
     iref_global: P<Value>,
     backendtype: Box<BackendType>,
@@ -240,5 +326,8 @@ pub fn allocate_global(
     );
 
     let addr = allocate_fixed(referenced_type, backendtype, vm);
+    if addr.is_zero() {
+        panic!("out of memory when allocating global {}", iref_global);
+    }
     ValueLocation::Direct(RegGroup::GPR, addr)
 }
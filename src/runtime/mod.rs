@@ -17,6 +17,7 @@ use compiler::backend::RegGroup;
 use utils;
 use utils::Address;
 use utils::Word;
+use vm::profile;
 use vm::VM;
 
 use libc::*;
@@ -39,6 +40,8 @@ pub mod math;
 /// memory management: allocation, reclamation
 /// (the actual code is in src/gc, which gets re-exported in mm module)
 pub mod mm;
+/// precise stack scanning with stack maps
+pub mod stack_scan;
 /// thread management: stack, thread
 pub mod thread;
 
@@ -295,13 +298,10 @@ pub extern "C" fn mu_main(
             .unwrap_or(unsafe { Address::zero() });
         thread::MuThread::new_thread_normal(stack, threadlocal, args, vm.clone());
 
-        loop {
-            let thread = vm.pop_join_handle();
-            if thread.is_none() {
-                break;
-            }
-            thread.unwrap().join().unwrap();
-        }
+        vm.join_mu_threads();
+
+        // write the counts if the boot image is instrumented
+        profile::write_profile(&vm);
 
         trace!("All threads have exited, quiting...");
     }
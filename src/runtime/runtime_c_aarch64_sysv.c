@@ -42,6 +42,10 @@ mu_tls:
  * */
 __thread void* mu_tls;
 
+// the frame cursor of the last Mu frame when the thread enters the runtime through
+// muentry_gc_safecall (zero if the thread is not in such a call)
+__thread void* mu_gc_frame_cursor;
+
 
 
 void set_thread_local(void* thread) {
@@ -67,6 +71,14 @@ void* muentry_get_thread_local() {
     return mu_tls;
 }
 
+void set_gc_frame_cursor(void* cursor) {
+    mu_gc_frame_cursor = cursor;
+}
+
+void* get_gc_frame_cursor() {
+    return mu_gc_frame_cursor;
+}
+
 void* resolve_symbol(const char* sym) {
     // MOV X1, X0
     // MOV X0, XZR
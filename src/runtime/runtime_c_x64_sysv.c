@@ -28,6 +28,10 @@ uint32_t mu_retval;
 
 __thread void* mu_tls;
 
+// the frame cursor of the last Mu frame when the thread enters the runtime through
+// muentry_gc_safecall (zero if the thread is not in such a call)
+__thread void* mu_gc_frame_cursor;
+
 void muentry_set_retval(uint32_t x) {
     mu_retval = x;
 }
@@ -50,6 +54,14 @@ void* muentry_get_thread_local() {
     return mu_tls;
 }
 
+void set_gc_frame_cursor(void* cursor) {
+    mu_gc_frame_cursor = cursor;
+}
+
+void* get_gc_frame_cursor() {
+    return mu_gc_frame_cursor;
+}
+
 void* resolve_symbol(const char* sym) {
     // printf("%s\n", sym);
     return dlsym(RTLD_DEFAULT, sym);
@@ -0,0 +1,253 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements precise stack scanning with the stack maps emitted by the compiler.
+//! We walk Mu frames (in the same way as the exception unwinder) from a frame cursor,
+//! and use the stack map at each callsite to find references in frame slots and callee saved
+//! registers.
+//! The current stack of a thread is scanned by the thread itself (if it entered the runtime
+//! through muentry_gc_safecall, which records a frame cursor, otherwise the GC falls back to
+//! conservative scanning). Stacks that are not bound to any thread are scanned by the GC
+//! controller from their saved stack pointer.
+//! We cannot walk native frames, so if we find a native frame above the bottom of a stack,
+//! we scan the stack conservatively instead.
+
+use ast::ir::*;
+use compiler::backend::*;
+use runtime::mm;
+use runtime::thread::MuStack;
+use runtime::thread::MuThread;
+use utils::Address;
+use utils::ObjectReference;
+use utils::POINTER_SIZE;
+use vm::VM;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+#[link(name = "runtime_c")]
+extern "C" {
+    /// gets the frame cursor recorded by muentry_gc_safecall for current thread
+    /// (zero if current thread is not in such a call)
+    fn get_gc_frame_cursor() -> Address;
+}
+
+lazy_static! {
+    /// all the stacks that are alive (from stack ID to the addresses of its MuStack and of
+    /// the VM that created it)
+    static ref STACKS: Mutex<HashMap<MuID, (Address, Address)>> = Mutex::new(HashMap::new());
+}
+
+/// installs precise stack scanners to the GC
+pub fn init() {
+    mm::set_stack_scanner(scan_current_stack);
+    mm::set_inactive_stack_scanner(scan_inactive_stacks);
+}
+
+/// registers a stack so that the GC scans it when it is not bound to any thread
+/// (the stack needs to be boxed, as we keep its address)
+pub fn register_stack(stack: &MuStack, vm: &VM) {
+    let addr = Address::from_ptr(stack as *const MuStack);
+    let vm = Address::from_ptr(vm as *const VM);
+    STACKS.lock().unwrap().insert(stack.id(), (addr, vm));
+}
+
+/// unregisters a stack (when the stack is destroyed)
+pub fn unregister_stack(stack: &MuStack) {
+    STACKS.lock().unwrap().remove(&stack.id());
+}
+
+/// takes the stack at the given address out of the registry, and returns its ownership.
+/// The stack needs to be registered and not bound to any thread (otherwise returns why not).
+/// We check and remove the stack under one lock, so two clients cannot take the same stack.
+/// A thread registers its stack again when it starts on the stack
+pub fn take_unbound_stack(addr: Address) -> Result<Box<MuStack>, &'static str> {
+    let mut stacks = STACKS.lock().unwrap();
+    let id = match stacks.iter().find(|&(_, &(stack, _))| stack == addr) {
+        Some((id, _)) => *id,
+        None => return Err("has been killed"),
+    };
+    if MuThread::is_stack_bound(addr) {
+        return Err("is bound to a thread");
+    }
+    stacks.remove(&id);
+
+    Ok(unsafe { Box::from_raw(addr.to_ptr_mut::<MuStack>()) })
+}
+
+/// scans the current stack of current thread (called by every mutator during a GC).
+/// Returns None if we cannot scan precisely.
+fn scan_current_stack() -> Option<Vec<ObjectReference>> {
+    if !MuThread::has_current() {
+        return None;
+    }
+    let thread = MuThread::current();
+
+    let cursor = unsafe { get_gc_frame_cursor() };
+    if cursor.is_zero() {
+        return None;
+    }
+
+    // muentry_gc_safecall saved every callee saved register below the cursor
+    let mut reg_locs = HashMap::new();
+    for i in 0..CALLEE_SAVED_COUNT {
+        let offset = -(((i + 1) * POINTER_SIZE) as isize);
+        reg_locs.insert(offset, cursor + offset);
+    }
+
+    match scan_frames(&thread.vm, cursor, reg_locs) {
+        Some(roots) => Some(roots),
+        None => {
+            // a native stack (with native frames below Mu frames) is scanned conservatively
+            // by the GC. For a Mu stack, we scan from the saved callee saved registers to the
+            // bottom of the stack (the frames below the cursor belong to the runtime)
+            let stack = unsafe { &*thread.stack };
+            stack.upper_bound().map(|upper_bound| {
+                let start = cursor - CALLEE_SAVED_COUNT * POINTER_SIZE;
+                mm::scan_range_conservatively(start, upper_bound)
+            })
+        }
+    }
+}
+
+/// scans all the registered stacks that are not bound to any thread
+/// (called by the GC controller after all mutators stopped)
+fn scan_inactive_stacks() -> Vec<ObjectReference> {
+    let mut ret = vec![];
+
+    let stacks = STACKS.lock().unwrap();
+    for (id, &(addr, vm)) in stacks.iter() {
+        // a bound stack is scanned by its thread (its saved stack pointer is stale).
+        // This takes LIVE_THREADS while we hold STACKS, nothing takes them in the other order
+        if MuThread::is_stack_bound(addr) {
+            continue;
+        }
+        let stack: &MuStack = unsafe { addr.to_ref::<MuStack>() };
+        let vm: &VM = unsafe { vm.to_ref::<VM>() };
+        trace!("scanning inactive stack {}", id);
+
+        // SWAPSTACK saved the frame pointer and the return address at the stack pointer,
+        // and it does not keep any value in registers
+        let mut roots = match scan_frames(vm, stack.sp(), HashMap::new()) {
+            Some(roots) => roots,
+            None => match stack.upper_bound() {
+                Some(upper_bound) => mm::scan_range_conservatively(stack.sp(), upper_bound),
+                None => panic!("inactive stack {} has no bound", id),
+            },
+        };
+        ret.append(&mut roots);
+    }
+
+    ret
+}
+
+/// walks Mu frames from the frame cursor, and returns all the references in the frames.
+/// reg_locs maps callee saved registers (identified by their offsets to the frame cursor) to
+/// the locations where their values are saved.
+/// Returns None if we find a native frame before the bottom of the stack.
+fn scan_frames(
+    vm: &VM,
+    cursor: Address,
+    mut reg_locs: HashMap<isize, Address>,
+) -> Option<Vec<ObjectReference>> {
+    let mut ret = vec![];
+
+    let compiled_callsite_table = vm.compiled_callsite_table().read().unwrap();
+
+    let mut frame_cursor = cursor;
+    loop {
+        let callsite = get_return_address(frame_cursor);
+        // frame pointer for the frame that contains the callsite
+        let frame_pointer = get_previous_frame_pointer(frame_cursor);
+
+        // the bottom frame of a Mu stack has a null frame pointer
+        if frame_pointer.is_zero() {
+            break;
+        }
+        let callsite_info = match compiled_callsite_table.get(&callsite) {
+            Some(info) => info,
+            None => {
+                trace!(
+                    "found a native frame 0x{:x} at 0x{:x}",
+                    frame_pointer,
+                    callsite
+                );
+                return None;
+            }
+        };
+        trace!(
+            "scanning frame 0x{:x} at callsite 0x{:x}",
+            frame_pointer,
+            callsite
+        );
+
+        let ref stack_map = callsite_info.stack_map;
+        for offset in stack_map.ref_slots.iter() {
+            push_root(&mut ret, frame_pointer + *offset);
+        }
+        for offset in stack_map.tagref_slots.iter() {
+            push_tagref_root(&mut ret, frame_pointer + *offset);
+        }
+        for reg in stack_map.ref_callee_saved.iter() {
+            push_root(&mut ret, get_reg_loc(&reg_locs, *reg, callsite));
+        }
+        for reg in stack_map.tagref_callee_saved.iter() {
+            push_tagref_root(&mut ret, get_reg_loc(&reg_locs, *reg, callsite));
+        }
+
+        // the callee saved registers that this frame saved for its caller
+        for (target_offset, source_offset) in callsite_info.callee_saved_registers.iter() {
+            reg_locs.insert(*target_offset, frame_pointer + *source_offset);
+        }
+
+        // move up to the previous frame
+        frame_cursor = frame_pointer;
+    }
+
+    Some(ret)
+}
+
+/// returns where the value of the callee saved register is saved
+fn get_reg_loc(reg_locs: &HashMap<isize, Address>, reg: isize, callsite: Address) -> Address {
+    match reg_locs.get(&reg) {
+        Some(loc) => *loc,
+        None => panic!(
+            "callsite 0x{:x} keeps a reference in callee saved register (offset {}), \
+             but we do not know where its value is saved",
+            callsite, reg
+        ),
+    }
+}
+
+#[inline(always)]
+fn push_root(roots: &mut Vec<ObjectReference>, loc: Address) {
+    let value = unsafe { loc.load::<Address>() };
+    if !value.is_zero() {
+        roots.push(unsafe { value.to_object_reference() });
+    }
+}
+
+/// a tagref64 is a root if it holds a (non-null) reference
+#[inline(always)]
+fn push_tagref_root(roots: &mut Vec<ObjectReference>, loc: Address) {
+    let value = unsafe { loc.load::<u64>() };
+    if mm::tagref64_is_ref(value) {
+        let addr = mm::tagref64_get_ref(value);
+        if !addr.is_zero() {
+            roots.push(unsafe { addr.to_object_reference() });
+        }
+    }
+}
@@ -16,6 +16,7 @@ use ast::ir::*;
 use ast::ptr::*;
 use ast::types::*;
 use runtime::mm;
+use runtime::stack_scan;
 use runtime::ValueLocation;
 use vm::VM;
 
@@ -27,9 +28,12 @@ use utils::Word;
 use utils::POINTER_SIZE;
 
 use std;
+use std::collections::HashSet;
 use std::fmt;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 use std::thread::JoinHandle;
 
@@ -179,6 +183,21 @@ impl MuStack {
         }
     }
 
+    /// returns the stack pointer saved when the stack became inactive
+    pub fn sp(&self) -> Address {
+        self.sp
+    }
+
+    /// returns the upper bound (the bottom) of the stack, if we allocated the stack
+    /// (we do not know the bound of a native stack)
+    pub fn upper_bound(&self) -> Option<Address> {
+        if self.mmap.is_some() {
+            Some(self.upper_bound)
+        } else {
+            None
+        }
+    }
+
     /// sets up arguments for the stack's entry function, so it is ready to be executed.
     /// We use a special calling convention for the entry function: we push all the argument
     /// registers for the platform to the stack. If the argument register is used, we get
@@ -289,6 +308,12 @@ pub enum MuStackState {
     Unknown,
 }
 
+impl Drop for MuStack {
+    fn drop(&mut self) {
+        stack_scan::unregister_stack(self);
+    }
+}
+
 /// MuThread represents metadata for a Mu thread.
 /// A Mu thread in Zebu is basically an OS thread (pthread). However, we need to maintain our own
 /// thread local info, such as allocator, stack, user-level thread local pointer, exception object,
@@ -366,6 +391,13 @@ impl fmt::Display for MuThread {
     }
 }
 
+lazy_static! {
+    /// the MuThreads of all the Mu threads that have not exited. A thread removes itself
+    /// (with the lock held) before its MuThread is released, so a threadref from the client
+    /// can be checked against this
+    static ref LIVE_THREADS: Mutex<HashSet<Address>> = Mutex::new(HashSet::new());
+}
+
 use std::os::raw::c_int;
 
 #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
@@ -419,18 +451,39 @@ pub fn check_result() -> c_int {
 }
 
 impl MuThread {
-    /// creates a new Mu thread with normal execution
+    /// creates a new Mu thread with normal execution, returns a pointer to its MuThread
+    /// (which is only valid until the thread exits)
     pub fn new_thread_normal(
         mut stack: Box<MuStack>,
         threadlocal: Address,
         vals: Vec<ValueLocation>,
         vm: Arc<VM>,
-    ) {
+    ) -> *mut MuThread {
         // set up arguments on stack
         stack.setup_args(vals);
-        let (join_handle, _) =
+        let (join_handle, muthread) =
             MuThread::mu_thread_launch(vm.next_id(), stack, threadlocal, None, vm.clone());
         vm.push_join_handle(join_handle);
+        muthread
+    }
+
+    /// creates a new Mu thread that starts by throwing the given exception to the top frame
+    /// of the stack, returns a pointer to its MuThread (which is only valid until the thread exits)
+    pub fn new_thread_exceptional(
+        stack: Box<MuStack>,
+        threadlocal: Address,
+        exception: Address,
+        vm: Arc<VM>,
+    ) -> *mut MuThread {
+        let (join_handle, muthread) = MuThread::mu_thread_launch(
+            vm.next_id(),
+            stack,
+            threadlocal,
+            Some(exception),
+            vm.clone(),
+        );
+        vm.push_join_handle(join_handle);
+        muthread
     }
 
     /// creates and launches a mu thread, returns a JoinHandle and address to its MuThread structure
@@ -452,6 +505,13 @@ impl MuThread {
         // we need to return the pointer, but we cannot send it to other thread
         let muthread_ptr = Box::into_raw(thread);
         let muthread = unsafe { Box::from_raw(muthread_ptr) };
+        // the thread is alive from now (it may exit before we return)
+        LIVE_THREADS
+            .lock()
+            .unwrap()
+            .insert(Address::from_mut_ptr(muthread_ptr));
+        // the stack is bound, it may be unbound again (and scanned by the GC) after SWAPSTACK
+        stack_scan::register_stack(unsafe { &*muthread.stack }, &muthread.vm);
 
         (
             match thread::Builder::new()
@@ -472,8 +532,11 @@ impl MuThread {
                             None => muthread_start_normal(new_sp, sp_threadlocal_loc),
                         }
 
-                        // Thread finished, delete it's data
-                        Box::from_raw(muthread);
+                        // Thread finished, destroy its mutator (so the GC will not wait for
+                        // this thread), and delete its data
+                        mm::drop_mutator(&mut (*muthread).allocator as *mut mm::Mutator);
+                        set_thread_local(ptr::null_mut());
+                        MuThread::release(muthread);
                     }
                 }) {
                 Ok(handle) => handle,
@@ -502,6 +565,61 @@ impl MuThread {
         }
     }
 
+    /// releases the MuThread of a thread that has exited, and the stack it was running on
+    /// (we are on the native stack now)
+    //  The GC holds the lock of the stack registry while it checks which stacks are bound,
+    //  so we must not hold LIVE_THREADS when the stack is unregistered. We unregister the
+    //  stack while the thread is still live, so the GC never scans it as an inactive stack.
+    unsafe fn release(muthread: *mut MuThread) {
+        let addr = Address::from_mut_ptr(muthread);
+        let muthread = Box::from_raw(muthread);
+        let stack = Box::from_raw(muthread.stack);
+        stack_scan::unregister_stack(&stack);
+
+        LIVE_THREADS.lock().unwrap().remove(&addr);
+
+        drop(stack);
+        drop(muthread);
+    }
+
+    /// calls f with the MuThread at the given address, or returns None if the thread has
+    /// exited. The thread cannot release its MuThread while f runs.
+    //  We only know the thread by the address of its MuThread. If the thread has exited,
+    //  a new thread may take the same address, and we will find that thread instead.
+    pub fn with_live_thread<T, F>(addr: Address, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut MuThread) -> T,
+    {
+        let live_threads = LIVE_THREADS.lock().unwrap();
+        if live_threads.contains(&addr) {
+            Some(f(unsafe { addr.to_ref_mut::<MuThread>() }))
+        } else {
+            None
+        }
+    }
+
+    /// is the given stack the current stack of any Mu thread that has not exited?
+    pub fn is_stack_bound(stack: Address) -> bool {
+        let live_threads = LIVE_THREADS.lock().unwrap();
+        live_threads
+            .iter()
+            .any(|thread| unsafe { thread.to_ref::<MuThread>() }.stack as usize == stack.as_usize())
+    }
+
+    /// gets the user thread local of this thread
+    /// (the thread itself or the client may set it at any time)
+    pub fn get_user_tls(&self) -> Address {
+        let tls = &self.user_tls as *const Address as *const AtomicUsize;
+        unsafe { Address::from_usize((*tls).load(Ordering::SeqCst)) }
+    }
+
+    /// sets the user thread local of this thread
+    /// (the thread sees the new value the next time it reads its thread local)
+    pub fn set_user_tls(&mut self, user_tls: Address) {
+        let tls = &mut self.user_tls as *mut Address as *const AtomicUsize;
+        unsafe { (*tls).store(user_tls.as_usize(), Ordering::SeqCst) };
+    }
+
     /// is current thread a Mu thread?
     #[inline(always)]
     pub fn has_current() -> bool {
@@ -586,6 +704,10 @@ impl MuThread {
         // set thread local
         let ptr_fake_mu_thread: *mut MuThread = Box::into_raw(fake_mu_thread);
         set_thread_local(ptr_fake_mu_thread);
+        LIVE_THREADS
+            .lock()
+            .unwrap()
+            .insert(Address::from_mut_ptr(ptr_fake_mu_thread));
 
         true
     }
@@ -604,7 +726,9 @@ impl MuThread {
             set_thread_local(ptr::null_mut());
 
             // get mu thread back to Box (and will get dropped)
-            Box::from_raw(mu_thread);
+            let mut live_threads = LIVE_THREADS.lock().unwrap();
+            live_threads.remove(&mu_thread_addr);
+            drop(Box::from_raw(mu_thread));
         }
     }
 }
@@ -630,14 +754,14 @@ rodal_struct!(PrimordialThreadInfo {
 pub unsafe extern "C" fn muentry_new_stack(entry: Address, stack_size: usize) -> *mut MuStack {
     let ref vm = MuThread::current_mut().vm;
     let stack = Box::new(MuStack::new(vm.next_id(), entry, stack_size));
+    stack_scan::register_stack(&stack, vm);
     Box::into_raw(stack)
 }
 
 // Kills the given stack. WARNING! do not call this whilst on the given stack
 #[no_mangle]
 pub unsafe extern "C" fn muentry_kill_stack(stack: *mut MuStack) {
-    // This new box will be destroyed upon returning
-    Box::from_raw(stack);
+    drop(Box::from_raw(stack));
 }
 
 // Creates a new thread
@@ -648,15 +772,7 @@ pub unsafe extern "C" fn muentry_new_thread_exceptional(
     exception: Address,
 ) -> *mut MuThread {
     let vm = MuThread::current_mut().vm.clone();
-    let (join_handle, muthread) = MuThread::mu_thread_launch(
-        vm.next_id(),
-        Box::from_raw(stack),
-        thread_local,
-        Some(exception),
-        vm.clone(),
-    );
-    vm.push_join_handle(join_handle);
-    muthread
+    MuThread::new_thread_exceptional(Box::from_raw(stack), thread_local, exception, vm)
 }
 
 // Creates a new thread
@@ -63,6 +63,15 @@ pub fn munmap(addr: Address, size: ByteSize) {
     }
 }
 
+/// gives the physical memory of a mapped range back to the OS (the range stays mapped,
+/// and should be zeroed by the user before it is used again)
+pub fn decommit(addr: Address, size: ByteSize) {
+    use self::libc::*;
+    unsafe {
+        madvise(addr.to_ptr_mut() as *mut c_void, size as size_t, MADV_DONTNEED);
+    }
+}
+
 /// malloc's and zeroes the memory
 pub unsafe fn malloc_zero(size: usize) -> *mut u8 {
     use self::memsec;
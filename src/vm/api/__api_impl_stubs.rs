@@ -411,6 +411,26 @@ impl MuCtx {
         panic!("Not implemented")
     }
 
+    pub fn register_finalizer(&mut self, obj: &APIHandle) {
+        panic!("Not implemented")
+    }
+
+    pub fn get_finalizable(&mut self) -> *const APIHandle {
+        panic!("Not implemented")
+    }
+
+    pub fn get_gc_stat(&mut self, stat: CMuGCStat) -> u64 {
+        panic!("Not implemented")
+    }
+
+    pub fn force_gc(&mut self) {
+        panic!("Not implemented")
+    }
+
+    pub fn get_oom_exception(&mut self) -> *const APIHandle {
+        panic!("Not implemented")
+    }
+
     pub fn expose(&mut self, func: &APIHandle, call_conv: CMuCallConv, cookie: &APIHandle) -> *const APIHandle {
         panic!("Not implemented")
     }
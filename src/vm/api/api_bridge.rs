@@ -1150,6 +1150,39 @@ extern "C" fn _forwarder__MuCtx__get_addr(ctx: *mut CMuCtx, loc: CMuValue) -> CM
     _rv_prep
 }
 
+extern "C" fn _forwarder__MuCtx__register_finalizer(ctx: *mut CMuCtx, obj: CMuRefValue) {
+    let mut _arg_ctx = from_MuCtx_ptr(ctx);
+    let mut _arg_obj = from_handle(obj);
+    unsafe { (*_arg_ctx).register_finalizer(_arg_obj) };
+}
+
+extern "C" fn _forwarder__MuCtx__get_finalizable(ctx: *mut CMuCtx) -> CMuRefValue {
+    let mut _arg_ctx = from_MuCtx_ptr(ctx);
+    let _rv = unsafe { (*_arg_ctx).get_finalizable() };
+    let _rv_prep = to_handle(_rv);
+    _rv_prep
+}
+
+extern "C" fn _forwarder__MuCtx__get_gc_stat(ctx: *mut CMuCtx, stat: CMuGCStat) -> u64 {
+    let mut _arg_ctx = from_MuCtx_ptr(ctx);
+    let mut _arg_stat = stat;
+    let _rv = unsafe { (*_arg_ctx).get_gc_stat(_arg_stat) };
+    let _rv_prep = _rv;
+    _rv_prep
+}
+
+extern "C" fn _forwarder__MuCtx__force_gc(ctx: *mut CMuCtx) {
+    let mut _arg_ctx = from_MuCtx_ptr(ctx);
+    unsafe { (*_arg_ctx).force_gc() };
+}
+
+extern "C" fn _forwarder__MuCtx__get_oom_exception(ctx: *mut CMuCtx) -> CMuRefValue {
+    let mut _arg_ctx = from_MuCtx_ptr(ctx);
+    let _rv = unsafe { (*_arg_ctx).get_oom_exception() };
+    let _rv_prep = to_handle(_rv);
+    _rv_prep
+}
+
 extern "C" fn _forwarder__MuCtx__expose(
     ctx: *mut CMuCtx,
     func: CMuFuncRefValue,
@@ -2779,6 +2812,11 @@ pub fn make_new_MuCtx(header: *mut c_void) -> *mut CMuCtx {
         pin: _forwarder__MuCtx__pin,
         unpin: _forwarder__MuCtx__unpin,
         get_addr: _forwarder__MuCtx__get_addr,
+        register_finalizer: _forwarder__MuCtx__register_finalizer,
+        get_finalizable: _forwarder__MuCtx__get_finalizable,
+        get_gc_stat: _forwarder__MuCtx__get_gc_stat,
+        force_gc: _forwarder__MuCtx__force_gc,
+        get_oom_exception: _forwarder__MuCtx__get_oom_exception,
         expose: _forwarder__MuCtx__expose,
         unexpose: _forwarder__MuCtx__unexpose,
         new_ir_builder: _forwarder__MuCtx__new_ir_builder,
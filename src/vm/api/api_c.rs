@@ -89,6 +89,7 @@ pub type CMuArraySize = usize;
 pub type CMuWPID = u32;
 pub type CMuFlag = u32;
 pub type CMuTrapHandlerResult = CMuFlag;
+pub type CMuGCStat = CMuFlag;
 pub type CMuBinOpStatus = CMuFlag;
 pub type CMuBinOptr = CMuFlag;
 pub type CMuCmpOptr = CMuFlag;
@@ -233,6 +234,11 @@ pub struct CMuCtx {
     pub pin: extern "C" fn(*mut CMuCtx, CMuValue) -> CMuUPtrValue,
     pub unpin: extern "C" fn(*mut CMuCtx, CMuValue),
     pub get_addr: extern "C" fn(*mut CMuCtx, CMuValue) -> CMuUPtrValue,
+    pub register_finalizer: extern "C" fn(*mut CMuCtx, CMuRefValue),
+    pub get_finalizable: extern "C" fn(*mut CMuCtx) -> CMuRefValue,
+    pub get_gc_stat: extern "C" fn(*mut CMuCtx, CMuGCStat) -> u64,
+    pub force_gc: extern "C" fn(*mut CMuCtx),
+    pub get_oom_exception: extern "C" fn(*mut CMuCtx) -> CMuRefValue,
     pub expose: extern "C" fn(*mut CMuCtx, CMuFuncRefValue, CMuCallConv, CMuIntValue) -> CMuValue,
     pub unexpose: extern "C" fn(*mut CMuCtx, CMuCallConv, CMuValue),
     pub new_ir_builder: extern "C" fn(*mut CMuCtx) -> *mut CMuIRBuilder,
@@ -615,6 +621,25 @@ pub struct CMuIRBuilder {
 pub const CMU_THREAD_EXIT: CMuTrapHandlerResult = 0x00;
 pub const CMU_REBIND_PASS_VALUES: CMuTrapHandlerResult = 0x01;
 pub const CMU_REBIND_THROW_EXC: CMuTrapHandlerResult = 0x02;
+pub const CMU_GC_STAT_COLLECTIONS: CMuGCStat = 0x01;
+pub const CMU_GC_STAT_MAJOR_COLLECTIONS: CMuGCStat = 0x02;
+pub const CMU_GC_STAT_LAST_PAUSE_NS: CMuGCStat = 0x03;
+pub const CMU_GC_STAT_MAX_PAUSE_NS: CMuGCStat = 0x04;
+pub const CMU_GC_STAT_TOTAL_PAUSE_NS: CMuGCStat = 0x05;
+pub const CMU_GC_STAT_HEAP_SIZE: CMuGCStat = 0x06;
+pub const CMU_GC_STAT_COMMITTED_BYTES: CMuGCStat = 0x07;
+pub const CMU_GC_STAT_TINY_ALLOCATED_BYTES: CMuGCStat = 0x10;
+pub const CMU_GC_STAT_TINY_LIVE_BYTES: CMuGCStat = 0x11;
+pub const CMU_GC_STAT_TINY_FREE_BYTES: CMuGCStat = 0x12;
+pub const CMU_GC_STAT_TINY_FRAGMENTED_BYTES: CMuGCStat = 0x13;
+pub const CMU_GC_STAT_NORMAL_ALLOCATED_BYTES: CMuGCStat = 0x20;
+pub const CMU_GC_STAT_NORMAL_LIVE_BYTES: CMuGCStat = 0x21;
+pub const CMU_GC_STAT_NORMAL_FREE_BYTES: CMuGCStat = 0x22;
+pub const CMU_GC_STAT_NORMAL_FRAGMENTED_BYTES: CMuGCStat = 0x23;
+pub const CMU_GC_STAT_LO_ALLOCATED_BYTES: CMuGCStat = 0x30;
+pub const CMU_GC_STAT_LO_LIVE_BYTES: CMuGCStat = 0x31;
+pub const CMU_GC_STAT_LO_FREE_BYTES: CMuGCStat = 0x32;
+pub const CMU_GC_STAT_LO_FRAGMENTED_BYTES: CMuGCStat = 0x33;
 pub const CMU_BOS_N: CMuBinOpStatus = 0x01;
 pub const CMU_BOS_Z: CMuBinOpStatus = 0x02;
 pub const CMU_BOS_C: CMuBinOpStatus = 0x04;
@@ -721,6 +746,8 @@ pub const CMU_CI_UVM_NATIVE_GET_ADDR: CMuCommInst = 0x242;
 pub const CMU_CI_UVM_NATIVE_EXPOSE: CMuCommInst = 0x243;
 pub const CMU_CI_UVM_NATIVE_UNEXPOSE: CMuCommInst = 0x244;
 pub const CMU_CI_UVM_NATIVE_GET_COOKIE: CMuCommInst = 0x245;
+pub const CMU_CI_UVM_GC_REGISTER_FINALIZER: CMuCommInst = 0x248;
+pub const CMU_CI_UVM_GC_GET_FINALIZABLE: CMuCommInst = 0x249;
 pub const CMU_CI_UVM_META_ID_OF: CMuCommInst = 0x250;
 pub const CMU_CI_UVM_META_NAME_OF: CMuCommInst = 0x251;
 pub const CMU_CI_UVM_META_LOAD_BUNDLE: CMuCommInst = 0x252;
@@ -226,15 +226,18 @@ impl MuCtx {
     }
 
     pub fn ref_eq(&mut self, lhs: &APIHandle, rhs: &APIHandle) -> bool {
-        panic!("Not implemented")
+        trace!("ref_eq: {} {}", lhs, rhs);
+        self.get_mvm().vm.handle_ref_eq(lhs, rhs)
     }
 
     pub fn ref_ult(&mut self, lhs: &APIHandle, rhs: &APIHandle) -> bool {
-        panic!("Not implemented")
+        trace!("ref_ult: {} {}", lhs, rhs);
+        self.get_mvm().vm.handle_ref_ult(lhs, rhs)
     }
 
     pub fn extract_value(&mut self, str: &APIHandle, index: c_int) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("extract_value: {}, index {}", str, index);
+        prepare_handle(self.get_mvm().vm.handle_extract_value(str, index as usize))
     }
 
     pub fn insert_value(
@@ -243,11 +246,17 @@ impl MuCtx {
         index: c_int,
         newval: &APIHandle,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("insert_value: {}, index {}, newval {}", str, index, newval);
+        prepare_handle(
+            self.get_mvm()
+                .vm
+                .handle_insert_value(str, index as usize, newval),
+        )
     }
 
     pub fn extract_element(&mut self, str: &APIHandle, index: &APIHandle) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("extract_element: {}, index {}", str, index);
+        prepare_handle(self.get_mvm().vm.handle_extract_element(str, index))
     }
 
     pub fn insert_element(
@@ -256,7 +265,13 @@ impl MuCtx {
         index: &APIHandle,
         newval: &APIHandle,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!(
+            "insert_element: {}, index {}, newval {}",
+            str,
+            index,
+            newval
+        );
+        prepare_handle(self.get_mvm().vm.handle_insert_element(str, index, newval))
     }
 
     pub fn new_fixed(&mut self, mu_type: MuID) -> *const APIHandle {
@@ -325,7 +340,21 @@ impl MuCtx {
         desired: &APIHandle,
         is_succ: *mut CMuBool,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("cmpxchg: {} expected {} desired {}", loc, expected, desired);
+        let (old, succ) = self.get_mvm().vm.handle_cmpxchg(
+            impl_memorder(ord_succ),
+            impl_memorder(ord_fail),
+            weak,
+            loc,
+            expected,
+            desired,
+        );
+
+        unsafe {
+            *is_succ = if succ { 1 } else { 0 };
+        }
+
+        prepare_handle(old)
     }
 
     pub fn atomicrmw(
@@ -335,15 +364,23 @@ impl MuCtx {
         loc: &APIHandle,
         opnd: &APIHandle,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("atomicrmw: {} opnd {}", loc, opnd);
+        prepare_handle(self.get_mvm().vm.handle_atomicrmw(
+            impl_memorder(ord),
+            impl_atomicrmw_op(op),
+            loc,
+            opnd,
+        ))
     }
 
     pub fn fence(&mut self, ord: CMuMemOrd) {
-        panic!("Not implemented")
+        trace!("fence");
+        self.get_mvm().vm.handle_fence(impl_memorder(ord))
     }
 
     pub fn new_stack(&mut self, func: &APIHandle) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("new_stack: {}", func);
+        prepare_handle(self.get_mvm().vm.handle_new_stack(func))
     }
 
     pub fn new_thread_nor(
@@ -352,7 +389,9 @@ impl MuCtx {
         threadlocal: Option<&APIHandle>,
         vals: Vec<&APIHandle>,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("new_thread_nor: {}", stack);
+        let vm = self.get_mvm().vm.clone();
+        prepare_handle(vm.handle_new_thread_normal(stack, threadlocal, vals, vm.clone()))
     }
 
     pub fn new_thread_exc(
@@ -361,19 +400,26 @@ impl MuCtx {
         threadlocal: Option<&APIHandle>,
         exc: &APIHandle,
     ) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("new_thread_exc: {}, exc {}", stack, exc);
+        let vm = self.get_mvm().vm.clone();
+        prepare_handle(vm.handle_new_thread_exceptional(stack, threadlocal, exc, vm.clone()))
     }
 
     pub fn kill_stack(&mut self, stack: &APIHandle) {
-        panic!("Not implemented")
+        trace!("kill_stack: {}", stack);
+        self.get_mvm().vm.handle_kill_stack(stack)
     }
 
     pub fn set_threadlocal(&mut self, thread: &APIHandle, threadlocal: &APIHandle) {
-        panic!("Not implemented")
+        trace!("set_threadlocal: {}, threadlocal {}", thread, threadlocal);
+        self.get_mvm()
+            .vm
+            .handle_set_threadlocal(thread, threadlocal)
     }
 
     pub fn get_threadlocal(&mut self, thread: &APIHandle) -> *const APIHandle {
-        panic!("Not implemented")
+        trace!("get_threadlocal: {}", thread);
+        prepare_handle(self.get_mvm().vm.handle_get_threadlocal(thread))
     }
 
     pub fn new_cursor(&mut self, stack: &APIHandle) -> *const APIHandle {
@@ -476,6 +522,48 @@ impl MuCtx {
         prepare_handle(self.get_mvm().vm.handle_get_addr(loc))
     }
 
+    pub fn register_finalizer(&mut self, obj: &APIHandle) {
+        self.get_mvm().vm.handle_register_finalizer(obj)
+    }
+
+    pub fn get_finalizable(&mut self) -> *const APIHandle {
+        prepare_handle(self.get_mvm().vm.handle_get_finalizable())
+    }
+
+    pub fn get_gc_stat(&mut self, stat: CMuGCStat) -> u64 {
+        let stats = self.get_mvm().vm.get_gc_stats();
+        match stat {
+            CMU_GC_STAT_COLLECTIONS => stats.n_collections as u64,
+            CMU_GC_STAT_MAJOR_COLLECTIONS => stats.n_major_collections as u64,
+            CMU_GC_STAT_LAST_PAUSE_NS => stats.last_pause_ns,
+            CMU_GC_STAT_MAX_PAUSE_NS => stats.max_pause_ns,
+            CMU_GC_STAT_TOTAL_PAUSE_NS => stats.total_pause_ns,
+            CMU_GC_STAT_HEAP_SIZE => stats.heap_size as u64,
+            CMU_GC_STAT_COMMITTED_BYTES => stats.committed_bytes as u64,
+            CMU_GC_STAT_TINY_ALLOCATED_BYTES => stats.immix_tiny.allocated_bytes as u64,
+            CMU_GC_STAT_TINY_LIVE_BYTES => stats.immix_tiny.live_bytes as u64,
+            CMU_GC_STAT_TINY_FREE_BYTES => stats.immix_tiny.free_bytes as u64,
+            CMU_GC_STAT_TINY_FRAGMENTED_BYTES => stats.immix_tiny.fragmented_bytes as u64,
+            CMU_GC_STAT_NORMAL_ALLOCATED_BYTES => stats.immix_normal.allocated_bytes as u64,
+            CMU_GC_STAT_NORMAL_LIVE_BYTES => stats.immix_normal.live_bytes as u64,
+            CMU_GC_STAT_NORMAL_FREE_BYTES => stats.immix_normal.free_bytes as u64,
+            CMU_GC_STAT_NORMAL_FRAGMENTED_BYTES => stats.immix_normal.fragmented_bytes as u64,
+            CMU_GC_STAT_LO_ALLOCATED_BYTES => stats.lo.allocated_bytes as u64,
+            CMU_GC_STAT_LO_LIVE_BYTES => stats.lo.live_bytes as u64,
+            CMU_GC_STAT_LO_FREE_BYTES => stats.lo.free_bytes as u64,
+            CMU_GC_STAT_LO_FRAGMENTED_BYTES => stats.lo.fragmented_bytes as u64,
+            _ => panic!("invalid CMuGCStat: {}", stat),
+        }
+    }
+
+    pub fn force_gc(&mut self) {
+        self.get_mvm().vm.force_gc()
+    }
+
+    pub fn get_oom_exception(&mut self) -> *const APIHandle {
+        prepare_handle(self.get_mvm().vm.handle_get_oom_exception())
+    }
+
     pub fn expose(
         &mut self,
         func: &APIHandle,
@@ -560,3 +648,21 @@ fn impl_memorder(order: CMuMemOrd) -> MemoryOrder {
         _ => panic!("invalid CMuMemOrd flag: {}", order),
     }
 }
+
+use ast::op::AtomicRMWOp;
+fn impl_atomicrmw_op(op: CMuAtomicRMWOptr) -> AtomicRMWOp {
+    match op {
+        CMU_ARMW_XCHG => AtomicRMWOp::XCHG,
+        CMU_ARMW_ADD => AtomicRMWOp::ADD,
+        CMU_ARMW_SUB => AtomicRMWOp::SUB,
+        CMU_ARMW_AND => AtomicRMWOp::AND,
+        CMU_ARMW_NAND => AtomicRMWOp::NAND,
+        CMU_ARMW_OR => AtomicRMWOp::OR,
+        CMU_ARMW_XOR => AtomicRMWOp::XOR,
+        CMU_ARMW_MAX => AtomicRMWOp::MAX,
+        CMU_ARMW_MIN => AtomicRMWOp::MIN,
+        CMU_ARMW_UMAX => AtomicRMWOp::UMAX,
+        CMU_ARMW_UMIN => AtomicRMWOp::UMIN,
+        _ => panic!("invalid CMuAtomicRMWOptr flag: {}", op),
+    }
+}
@@ -3951,6 +3951,48 @@ impl<'lb, 'lvm> BundleLoader<'lb, 'lvm> {
                     v: Instruction_::CommonInst_Unpin(0),
                 }
             }
+            CMU_CI_UVM_GC_REGISTER_FINALIZER => {
+                assert_ir!(
+                    tys.is_empty()
+                        && sigs.is_empty()
+                        && flags.is_empty()
+                        && result_ids.is_empty()
+                        && exc_clause.is_none()
+                        && keepalives.is_none()
+                );
+                assert!(args.len() == 1);
+
+                let op = self.get_treenode(fcb, args[0]);
+                assert_ir!(op.ty().is_ref());
+
+                Instruction {
+                    hdr: hdr,
+                    value: None,
+                    ops: vec![op],
+                    v: Instruction_::CommonInst_RegisterFinalizer(0),
+                }
+            }
+            CMU_CI_UVM_GC_GET_FINALIZABLE => {
+                assert_ir!(
+                    tys.is_empty()
+                        && args.is_empty()
+                        && sigs.is_empty()
+                        && flags.is_empty()
+                        && exc_clause.is_none()
+                        && keepalives.is_none()
+                );
+                assert!(result_ids.len() == 1);
+
+                let rv_ty = self.ensure_refvoid();
+                let rv = self.new_ssa(fcb, result_ids[0], rv_ty).clone_value();
+
+                Instruction {
+                    hdr: hdr,
+                    value: Some(vec![rv]),
+                    ops: vec![],
+                    v: Instruction_::CommonInst_GetFinalizable,
+                }
+            }
             CMU_CI_UVM_THREAD_EXIT => {
                 assert_ir!(
                     tys.is_empty()
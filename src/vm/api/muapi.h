@@ -133,6 +133,30 @@ typedef MuFlag MuTrapHandlerResult;
 #define MU_REBIND_PASS_VALUES   ((MuTrapHandlerResult)0x01)
 #define MU_REBIND_THROW_EXC     ((MuTrapHandlerResult)0x02)
 
+// Statistics reported by get_gc_stat (pause times are in nanoseconds, the heap size
+// and committed bytes are for the whole heap, and the other numbers of bytes are for
+// the tiny object, normal object and large object spaces)
+typedef MuFlag MuGCStat;
+#define MU_GC_STAT_COLLECTIONS          ((MuGCStat)0x01)
+#define MU_GC_STAT_MAJOR_COLLECTIONS    ((MuGCStat)0x02)
+#define MU_GC_STAT_LAST_PAUSE_NS        ((MuGCStat)0x03)
+#define MU_GC_STAT_MAX_PAUSE_NS         ((MuGCStat)0x04)
+#define MU_GC_STAT_TOTAL_PAUSE_NS       ((MuGCStat)0x05)
+#define MU_GC_STAT_HEAP_SIZE            ((MuGCStat)0x06)
+#define MU_GC_STAT_COMMITTED_BYTES      ((MuGCStat)0x07)
+#define MU_GC_STAT_TINY_ALLOCATED_BYTES ((MuGCStat)0x10)
+#define MU_GC_STAT_TINY_LIVE_BYTES      ((MuGCStat)0x11)
+#define MU_GC_STAT_TINY_FREE_BYTES      ((MuGCStat)0x12)
+#define MU_GC_STAT_TINY_FRAGMENTED_BYTES((MuGCStat)0x13)
+#define MU_GC_STAT_NORMAL_ALLOCATED_BYTES((MuGCStat)0x20)
+#define MU_GC_STAT_NORMAL_LIVE_BYTES    ((MuGCStat)0x21)
+#define MU_GC_STAT_NORMAL_FREE_BYTES    ((MuGCStat)0x22)
+#define MU_GC_STAT_NORMAL_FRAGMENTED_BYTES((MuGCStat)0x23)
+#define MU_GC_STAT_LO_ALLOCATED_BYTES   ((MuGCStat)0x30)
+#define MU_GC_STAT_LO_LIVE_BYTES        ((MuGCStat)0x31)
+#define MU_GC_STAT_LO_FREE_BYTES        ((MuGCStat)0x32)
+#define MU_GC_STAT_LO_FRAGMENTED_BYTES  ((MuGCStat)0x33)
+
 // Used by MuTrapHandler
 typedef void _MuValuesFreer_Func(MuValue *values, MuCPtr freerdata);
 typedef _MuValuesFreer_Func* MuValuesFreer;
@@ -438,6 +462,15 @@ struct MuCtx {
     void        (*unpin   )(MuCtx *ctx, MuValue loc);      // loc is either MuRefValue or MuIRefValue
     MuUPtrValue (*get_addr)(MuCtx *ctx, MuValue loc);      // loc is either MuRefValue or MuIRefValue
 
+    // Finalisation
+    void        (*register_finalizer)(MuCtx *ctx, MuRefValue obj);
+    MuRefValue  (*get_finalizable   )(MuCtx *ctx);   // NULL ref if no object is queued
+
+    // GC statistics and control
+    uint64_t    (*get_gc_stat)(MuCtx *ctx, MuGCStat stat);
+    void        (*force_gc   )(MuCtx *ctx);                // a major collection
+    MuRefValue  (*get_oom_exception)(MuCtx *ctx);          // thrown when the heap is out of memory
+
     // Expose Mu functions as native callable things, usually function pointers
     MuValue     (*expose  )(MuCtx *ctx, MuFuncRefValue func, MuCallConv call_conv, MuIntValue cookie);
     void        (*unexpose)(MuCtx *ctx, MuCallConv call_conv, MuValue value);
@@ -788,6 +821,8 @@ struct MuIRBuilder {
 #define MU_CI_UVM_NATIVE_EXPOSE                     ((MuCommInst)0x243) /// MUAPIPARSER muname:@uvm.native.expose
 #define MU_CI_UVM_NATIVE_UNEXPOSE                   ((MuCommInst)0x244) /// MUAPIPARSER muname:@uvm.native.unexpose
 #define MU_CI_UVM_NATIVE_GET_COOKIE                 ((MuCommInst)0x245) /// MUAPIPARSER muname:@uvm.native.get_cookie
+#define MU_CI_UVM_GC_REGISTER_FINALIZER             ((MuCommInst)0x248) /// MUAPIPARSER muname:@uvm.gc.register_finalizer
+#define MU_CI_UVM_GC_GET_FINALIZABLE                ((MuCommInst)0x249) /// MUAPIPARSER muname:@uvm.gc.get_finalizable
 #define MU_CI_UVM_META_ID_OF                        ((MuCommInst)0x250) /// MUAPIPARSER muname:@uvm.meta.id_of
 #define MU_CI_UVM_META_NAME_OF                      ((MuCommInst)0x251) /// MUAPIPARSER muname:@uvm.meta.name_of
 #define MU_CI_UVM_META_LOAD_BUNDLE                  ((MuCommInst)0x252) /// MUAPIPARSER muname:@uvm.meta.load_bundle
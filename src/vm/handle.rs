@@ -15,19 +15,92 @@
 use ast::ir::*;
 use ast::ptr::*;
 use ast::types::*;
+use runtime::mm;
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::Mutex;
 use utils::Address;
 use utils::BitSize;
+use utils::ObjectReference;
 
 /// APIHandle represents the opaque handle type that the client uses to
 /// communicate with Mu. Handles can refer to values, functions, signatures,
 /// etc that client can inspect/query from the VM.
-#[derive(Clone)]
+/// A handle that the VM creates keeps the heap objects it refers to alive and in place
+/// (they are GC roots) until the handle and all its copies are dropped.
 pub struct APIHandle {
     pub id: MuID,
     pub v: APIHandleValue,
 }
 
+lazy_static! {
+    /// handles that keep heap objects as roots (from handle ID to the number of live copies
+    /// of the handle, and the objects that each copy keeps)
+    static ref ROOTED_HANDLES: Mutex<HashMap<MuID, (usize, Vec<ObjectReference>)>> =
+        Mutex::new(HashMap::new());
+}
+
+impl APIHandle {
+    /// makes this handle (and its copies) keep the given objects as GC roots.
+    /// This is done once for every handle that the VM creates
+    pub fn root_objects(&self, objs: Vec<ObjectReference>) {
+        if objs.is_empty() {
+            return;
+        }
+        for obj in objs.iter() {
+            mm::add_to_root(*obj);
+        }
+        let mut handles = ROOTED_HANDLES.lock().unwrap();
+        debug_assert!(!handles.contains_key(&self.id));
+        handles.insert(self.id, (1, objs));
+    }
+
+    /// returns the objects that this handle keeps as roots
+    pub fn rooted_objects(&self) -> Vec<ObjectReference> {
+        match ROOTED_HANDLES.lock().unwrap().get(&self.id) {
+            Some(&(_, ref objs)) => objs.clone(),
+            None => vec![],
+        }
+    }
+}
+
+impl Clone for APIHandle {
+    fn clone(&self) -> APIHandle {
+        if let Some(&mut (ref mut count, ref objs)) =
+            ROOTED_HANDLES.lock().unwrap().get_mut(&self.id)
+        {
+            *count += 1;
+            for obj in objs.iter() {
+                mm::add_to_root(*obj);
+            }
+        }
+
+        APIHandle {
+            id: self.id,
+            v: self.v.clone(),
+        }
+    }
+}
+
+impl Drop for APIHandle {
+    fn drop(&mut self) {
+        let mut handles = ROOTED_HANDLES.lock().unwrap();
+        let last = match handles.get_mut(&self.id) {
+            Some(&mut (ref mut count, ref objs)) => {
+                for obj in objs.iter() {
+                    mm::remove_root(*obj);
+                }
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if last {
+            handles.remove(&self.id);
+        }
+    }
+}
+
 /// when we returning an API handle to the client, we create a Box<APIHandle>,
 /// then api_impl will turn it into a raw pointer, and pass the pointer the the client.
 /// Thus Rust allocates the handle, but will not reclaim it. When the client explicitly
@@ -79,10 +152,10 @@ pub enum APIHandleValue {
     TagRef64(u64),
     /// function reference (as ID)
     FuncRef(MuID),
-    /// Mu thread reference
-    ThreadRef,
-    /// Mu stack reference
-    StackRef,
+    /// Mu thread reference (address of the MuThread)
+    ThreadRef(Address),
+    /// Mu stack reference (address of the MuStack)
+    StackRef(Address),
     /// frame cursor reference
     FCRef,
 
@@ -146,8 +219,8 @@ impl fmt::Debug for APIHandleValue {
             &IRef(ref ty, addr) => write!(f, "iref<{}> to {}", ty, addr),
             &TagRef64(val) => write!(f, "tagref64 0x{:x}", val),
             &FuncRef(id) => write!(f, "funcref to #{}", id),
-            &ThreadRef => write!(f, "threadref"),
-            &StackRef => write!(f, "stackref"),
+            &ThreadRef(addr) => write!(f, "threadref to {}", addr),
+            &StackRef(addr) => write!(f, "stackref to {}", addr),
             &FCRef => write!(f, "framecursorref"),
             &Bundle => write!(f, "IR.bundle"),
             &Type(id) => write!(f, "IR.type to #{}", id),
@@ -165,6 +238,33 @@ impl fmt::Debug for APIHandleValue {
 }
 
 impl APIHandleValue {
+    /// returns the heap objects that this value refers to with refs (including refs in
+    /// tagref64 and aggregate values). Irefs are not included, as we do not know their base
+    pub fn heap_objects(&self) -> Vec<ObjectReference> {
+        let mut ret = vec![];
+        self.collect_heap_objects(&mut ret);
+        ret
+    }
+
+    fn collect_heap_objects(&self, objs: &mut Vec<ObjectReference>) {
+        let addr = match self {
+            &APIHandleValue::Ref(_, addr) => addr,
+            &APIHandleValue::TagRef64(val) if mm::tagref64_is_ref(val) => mm::tagref64_get_ref(val),
+            &APIHandleValue::Struct(ref vals)
+            | &APIHandleValue::Array(ref vals)
+            | &APIHandleValue::Vector(ref vals) => {
+                for val in vals.iter() {
+                    val.collect_heap_objects(objs);
+                }
+                return;
+            }
+            _ => return,
+        };
+        if !addr.is_zero() && mm::is_heap_address(addr) {
+            objs.push(unsafe { addr.to_object_reference() });
+        }
+    }
+
     /// matches the handle as ref or iref
     pub fn as_ref_or_iref(&self) -> (P<MuType>, Address) {
         match self {
@@ -253,6 +353,22 @@ impl APIHandleValue {
         }
     }
 
+    /// matches the handle as thread reference
+    pub fn as_threadref(&self) -> Address {
+        match self {
+            &APIHandleValue::ThreadRef(addr) => addr,
+            _ => panic!("expected ThreadRef handle"),
+        }
+    }
+
+    /// matches the handle as stack reference
+    pub fn as_stackref(&self) -> Address {
+        match self {
+            &APIHandleValue::StackRef(addr) => addr,
+            _ => panic!("expected StackRef handle"),
+        }
+    }
+
     /// matches the handle as tag reference's value)
     pub fn as_tr64(&self) -> u64 {
         match self {
@@ -21,6 +21,8 @@ pub use vm::vm::VM;
 pub mod uir_output;
 /// vm_options defines commandline flags to create a new Zebu instance
 mod vm_options;
+/// profiles for profile-guided optimisation
+pub mod profile;
 
 /// built info from cargo
 pub mod built_info;
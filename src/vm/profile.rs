@@ -0,0 +1,188 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Profiles for profile-guided optimisation.
+//!
+//! With `--profile-instrument`, the compiler allocates a global cell of counters for each
+//! function version, and inserts code that increments the counters at the function entry,
+//! on branch edges and before calls (see `compiler::passes::Profiling`). The VM keeps the
+//! key of each counter, and writes the counts to `--profile-output` at exit.
+//!
+//! With `--profile-use`, the compiler reads the profile, and uses the counts for branch
+//! probabilities, block layout and inlining.
+//!
+//! A profile is a text file with one counter per line: `<count> <key>`. Counts of the same
+//! key are summed, so profiles of several runs can be concatenated.
+
+use ast::ir::*;
+use runtime::resolve_symbol;
+use utils::Address;
+use vm::VM;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// key of the counter for entries of a function version
+pub fn entry_key(fv: &str) -> String {
+    format!("entry {}", fv)
+}
+
+/// key of the counter for the edge from one block to another
+pub fn edge_key(fv: &str, from: MuID, to: MuID) -> String {
+    format!("edge {} {} {}", fv, from, to)
+}
+
+/// key of the counter for a call instruction
+pub fn call_key(fv: &str, call: MuID) -> String {
+    format!("call {} {}", fv, call)
+}
+
+/// counts read from (or to be written to) a profile file
+#[derive(Debug, Default)]
+pub struct Profile {
+    counts: HashMap<String, u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// reads a profile file
+    pub fn load(path: &str) -> io::Result<Profile> {
+        let mut ret = Profile::new();
+
+        let file = File::open(path)?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut split = line.splitn(2, ' ');
+            let count = split.next().unwrap().parse::<u64>();
+            let key = split.next();
+            match (count, key) {
+                (Ok(count), Some(key)) => ret.add(key, count),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed profile line: {}", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// writes the profile to a file
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut keys: Vec<&String> = self.counts.keys().collect();
+        keys.sort();
+
+        let mut file = File::create(path)?;
+        for key in keys {
+            writeln!(file, "{} {}", self.counts.get(key).unwrap(), key)?;
+        }
+        Ok(())
+    }
+
+    /// adds the count to the counter of the key
+    pub fn add(&mut self, key: &str, count: u64) {
+        *self.counts.entry(key.to_string()).or_insert(0) += count;
+    }
+
+    /// returns the count of the key (None if the profile does not have the counter)
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.counts.get(key).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+lazy_static! {
+    /// profiles that have been loaded, keyed by their paths
+    static ref LOADED_PROFILES: Mutex<HashMap<String, Arc<Profile>>> = Mutex::new(HashMap::new());
+}
+
+/// returns the profile given by `--profile-use` (None if there is no profile to use).
+/// The profile is loaded once, and shared by later compilations
+pub fn get_profile(vm: &VM) -> Option<Arc<Profile>> {
+    let ref path = vm.vm_options.flag_profile_use;
+    if path.is_empty() {
+        return None;
+    }
+
+    let mut loaded = LOADED_PROFILES.lock().unwrap();
+    if !loaded.contains_key(path) {
+        let profile = match Profile::load(path) {
+            Ok(profile) => profile,
+            Err(e) => panic!("failed to load profile {}: {}", path, e),
+        };
+        info!(
+            "loaded profile {} ({} counters)",
+            path,
+            profile.counts.len()
+        );
+        loaded.insert(path.clone(), Arc::new(profile));
+    }
+    loaded.get(path).cloned()
+}
+
+/// collects the counts from the counters of instrumented functions
+pub fn collect_profile(vm: &VM) -> Profile {
+    let mut ret = Profile::new();
+
+    let profile_counters = vm.profile_counters().read().unwrap();
+    let globals = vm.globals().read().unwrap();
+    let global_locs = vm.global_locations().read().unwrap();
+
+    for (global_id, keys) in profile_counters.iter() {
+        // in a boot image, globals are symbols. Otherwise we allocated them
+        let addr: Address = match global_locs.get(global_id) {
+            Some(loc) => loc.to_address(),
+            None => resolve_symbol(globals.get(global_id).unwrap().name()),
+        };
+
+        for (i, key) in keys.iter().enumerate() {
+            let count = unsafe { (addr + (i << 3)).load::<u64>() };
+            ret.add(key, count);
+        }
+    }
+
+    ret
+}
+
+/// writes the counts of instrumented functions to `--profile-output`. This is called when
+/// the boot image exits
+pub fn write_profile(vm: &VM) {
+    if vm.profile_counters().read().unwrap().is_empty() {
+        return;
+    }
+
+    let ref path = vm.vm_options.flag_profile_output;
+    let profile = collect_profile(vm);
+    match profile.save(path) {
+        Ok(_) => info!("profile written to {}", path),
+        Err(e) => error!("failed to write profile {}: {}", path, e),
+    }
+}
@@ -16,11 +16,13 @@ use std::collections::HashMap;
 
 use ast::inst::*;
 use ast::ir::*;
+use ast::op::AtomicRMWOp;
 use ast::ptr::*;
 use ast::types;
 use ast::types::*;
 use compiler::backend;
 use compiler::backend::BackendType;
+use compiler::frame::StackMap;
 use compiler::machine_code::{CompiledCallsite, CompiledFunction};
 use compiler::{Compiler, CompilerPolicy};
 use rodal;
@@ -32,6 +34,8 @@ use runtime::*;
 use utils::Address;
 use utils::BitSize;
 use utils::ByteSize;
+use utils::ObjectReference;
+use utils::Word;
 use vm::handle::*;
 use vm::vm_options::MuLogLevel;
 use vm::vm_options::VMOptions;
@@ -93,6 +97,9 @@ pub struct VM {
     /// gc types declared
     gc_type_map: RwLock<HashMap<TypeEncode, TypeID>>,
     gc_id_map: RwLock<HashMap<TypeID, TypeEncode>>,
+    /// profile counters of instrumented functions (a map from the global cell that holds
+    /// the counters to the profile key of each counter)
+    profile_counters: RwLock<HashMap<MuID, Vec<String>>>,
 
     /// current options for this VM
     pub vm_options: VMOptions, // +624
@@ -148,6 +155,7 @@ unsafe impl rodal::Dump for VM {
         dumper.dump_object(&self.primordial);
         dumper.dump_object(&self.gc_type_map);
         dumper.dump_object(&self.gc_id_map);
+        dumper.dump_object(&self.profile_counters);
         dumper.dump_object(&self.vm_options);
         dumper.dump_object(&self.compiled_funcs);
         dumper.dump_object(&self.callsite_table);
@@ -245,6 +253,7 @@ impl<'a> VM {
             primordial: RwLock::new(None),
             gc_type_map: RwLock::new(HashMap::new()),
             gc_id_map: RwLock::new(HashMap::new()),
+            profile_counters: RwLock::new(HashMap::new()),
             aot_pending_funcref_store: RwLock::new(HashMap::new()),
             compiled_callsite_table: RwLock::new(HashMap::new()),
             primordial_threadlocal: RwLock::new(None),
@@ -284,6 +293,18 @@ impl<'a> VM {
             n_gcthreads: options.flag_gc_nthreads,
             enable_gc: !options.flag_gc_disable_collection,
         });
+        gc::set_heap_policy(gc::HeapPolicy {
+            min_size: options.flag_gc_heap_min_size,
+            target_size: options.flag_gc_heap_target_size,
+            max_size: options.flag_gc_heap_max_size,
+            pause_target_ms: options.flag_gc_pause_target,
+        });
+        gc::set_verify_heap(options.flag_gc_verify_heap);
+        gc::set_generational(!options.flag_gc_disable_generational);
+        // out of memory throws an exception to Mu code
+        gc::init_oom_exception();
+        // scan Mu stacks precisely
+        stack_scan::init();
     }
 
     /// starts logging based on MuLogLevel flag
@@ -401,12 +422,17 @@ impl<'a> VM {
             let compiled_func = compiled_funcs.get(fv).unwrap().read().unwrap();
             let callee_saved_table = Arc::new(compiled_func.frame.callee_saved.clone());
             for callsite in callsite_list.iter() {
+                let stack_map = match compiled_func.frame.stack_maps.get(&callsite.name) {
+                    Some(stack_map) => stack_map.clone(),
+                    None => StackMap::default(),
+                };
                 compiled_callsite_table.insert(
                     resolve_symbol(callsite.name.clone()),
                     CompiledCallsite::new(
                         &callsite,
                         compiled_func.func_ver_id,
                         callee_saved_table.clone(),
+                        stack_map,
                     ),
                 );
             }
@@ -977,11 +1003,21 @@ impl<'a> VM {
         &self.global_locations
     }
 
+    /// returns the lock for profile counters
+    pub fn profile_counters(&self) -> &RwLock<HashMap<MuID, Vec<String>>> {
+        &self.profile_counters
+    }
+
     /// returns the lock for primordial thread info
     pub fn primordial(&self) -> &RwLock<Option<PrimordialThreadInfo>> {
         &self.primordial
     }
 
+    /// returns the lock for callsite table
+    pub fn callsite_table(&self) -> &RwLock<HashMap<MuID, Vec<Callsite>>> {
+        &self.callsite_table
+    }
+
     /// returns the lock for compiled callsite table
     pub fn compiled_callsite_table(&self) -> &RwLock<HashMap<Address, CompiledCallsite>> {
         &self.compiled_callsite_table
@@ -1231,17 +1267,198 @@ impl<'a> VM {
         let func_addr = resolve_symbol(self.get_name_for_func(func_id));
         let stack_arg_size = backend::call_stack_size(func.sig.clone(), self);
 
-        Box::new(MuStack::new(self.next_id(), func_addr, stack_arg_size))
+        let stack = Box::new(MuStack::new(self.next_id(), func_addr, stack_arg_size));
+        stack_scan::register_stack(&stack, self);
+        stack
+    }
+
+    /// performs NEWSTACK, returns a stackref handle
+    pub fn handle_new_stack(&self, func: APIHandleArg) -> APIHandleResult {
+        let func_id = func.v.as_funcref();
+        let stack = Box::into_raw(self.new_stack(func_id));
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::StackRef(Address::from_mut_ptr(stack)),
+        });
+
+        trace!("API: new stack for {:?}", func);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// takes the MuStack of a stackref handle, which needs to be a stack that is alive and
+    /// not bound to any thread
+    fn take_unbound_stack(&self, stack: APIHandleArg) -> Box<MuStack> {
+        match stack_scan::take_unbound_stack(stack.v.as_stackref()) {
+            Ok(mu_stack) => mu_stack,
+            Err(reason) => panic!("{} refers to a stack that {}", stack, reason),
+        }
+    }
+
+    /// performs NEWTHREAD that passes values to the stack, returns a threadref handle.
+    /// The stack is bound to the new thread (and is released when the thread exits).
+    /// The threadref is valid until the thread exits.
+    pub fn handle_new_thread_normal(
+        &self,
+        stack: APIHandleArg,
+        threadlocal: Option<APIHandleArg>,
+        vals: Vec<APIHandleArg>,
+        arc_vm: Arc<VM>,
+    ) -> APIHandleResult {
+        let mu_stack = self.take_unbound_stack(stack);
+        let user_tls = self.threadlocal_from_handle(threadlocal);
+        let args: Vec<ValueLocation> = vals
+            .iter()
+            .map(|val| self.handle_to_value_location(val))
+            .collect();
+
+        // the new thread may throw exceptions
+        self.build_callsite_table();
+        let thread = MuThread::new_thread_normal(mu_stack, user_tls, args, arc_vm);
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::ThreadRef(Address::from_mut_ptr(thread)),
+        });
+
+        trace!("API: new thread on {:?} with args {:?}", stack, vals);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// performs NEWTHREAD that throws an exception to the stack, returns a threadref handle.
+    /// The stack is bound to the new thread (and is released when the thread exits).
+    /// The threadref is valid until the thread exits.
+    pub fn handle_new_thread_exceptional(
+        &self,
+        stack: APIHandleArg,
+        threadlocal: Option<APIHandleArg>,
+        exception: APIHandleArg,
+        arc_vm: Arc<VM>,
+    ) -> APIHandleResult {
+        let mu_stack = self.take_unbound_stack(stack);
+        let user_tls = self.threadlocal_from_handle(threadlocal);
+        let (_, exception_obj) = exception.v.as_ref();
+
+        self.build_callsite_table();
+        let thread = MuThread::new_thread_exceptional(mu_stack, user_tls, exception_obj, arc_vm);
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::ThreadRef(Address::from_mut_ptr(thread)),
+        });
+
+        trace!("API: new thread on {:?} throwing {:?}", stack, exception);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// kills a stack that is not bound to any thread, and releases its memory
+    pub fn handle_kill_stack(&self, stack: APIHandleArg) {
+        trace!("API: kill stack {:?}", stack);
+
+        drop(self.take_unbound_stack(stack));
+    }
+
+    /// gets the user thread local of a Mu thread, returns a ref<void> handle
+    /// (the thread needs to be alive, as its MuThread is released when it exits)
+    pub fn handle_get_threadlocal(&self, thread: APIHandleArg) -> APIHandleResult {
+        let mu_thread = thread.v.as_threadref();
+        let user_tls = match MuThread::with_live_thread(mu_thread, |t| t.get_user_tls()) {
+            Some(user_tls) => user_tls,
+            None => panic!("{} refers to a thread that has exited", thread),
+        };
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::Ref(types::VOID_TYPE.clone(), user_tls),
+        });
+
+        trace!("API: get threadlocal of {:?}", thread);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// sets the user thread local of a Mu thread. The thread sees the new value the next
+    /// time it reads its thread local.
+    pub fn handle_set_threadlocal(&self, thread: APIHandleArg, threadlocal: APIHandleArg) {
+        let user_tls = threadlocal.v.as_ref().1;
+        let mu_thread = thread.v.as_threadref();
+        if MuThread::with_live_thread(mu_thread, |t| t.set_user_tls(user_tls)).is_none() {
+            panic!("{} refers to a thread that has exited", thread);
+        }
+
+        trace!("API: set threadlocal of {:?} to {:?}", thread, threadlocal);
+    }
+
+    /// gets the user thread local address from an optional ref handle (zero if none)
+    fn threadlocal_from_handle(&self, threadlocal: Option<APIHandleArg>) -> Address {
+        match threadlocal {
+            Some(tl) => tl.v.as_ref().1,
+            None => unsafe { Address::zero() },
+        }
+    }
+
+    /// converts a handle to a ValueLocation, so it can be passed to a new stack as an argument
+    fn handle_to_value_location(&self, handle: APIHandleArg) -> ValueLocation {
+        use compiler::backend::RegGroup;
+        use utils::mem::{f32_to_raw, f64_to_raw};
+
+        match handle.v {
+            APIHandleValue::Int(val, len) => {
+                ValueLocation::Constant(RegGroup::GPR, (val & bits_ones(len)) as Word)
+            }
+            APIHandleValue::Float(val) => {
+                ValueLocation::Constant(RegGroup::FPR, f32_to_raw(val) as Word)
+            }
+            APIHandleValue::Double(val) => {
+                ValueLocation::Constant(RegGroup::FPR, f64_to_raw(val) as Word)
+            }
+            APIHandleValue::TagRef64(val) => ValueLocation::Constant(RegGroup::GPR, val as Word),
+            APIHandleValue::Ref(_, addr)
+            | APIHandleValue::IRef(_, addr)
+            | APIHandleValue::UPtr(_, addr)
+            | APIHandleValue::UFP(_, addr)
+            | APIHandleValue::ThreadRef(addr)
+            | APIHandleValue::StackRef(addr) => {
+                ValueLocation::Constant(RegGroup::GPR, addr.as_usize() as Word)
+            }
+            APIHandleValue::FuncRef(id) => {
+                let func_addr = resolve_symbol(self.get_name_for_func(id));
+                ValueLocation::Constant(RegGroup::GPR, func_addr.as_usize() as Word)
+            }
+            _ => panic!("cannot pass {} as an argument to a new thread", handle),
+        }
     }
 
-    /// creates a handle that we can return to the client
+    /// creates a handle that we can return to the client. Heap objects that the handle
+    /// refers to stay alive and in place while the handle is alive
     fn new_handle(&self, handle: APIHandle) -> APIHandleResult {
         let ret = Box::new(handle);
+        ret.root_objects(ret.v.heap_objects());
+
+        ret
+    }
+
+    /// creates a handle with a value derived from another handle (e.g. an iref into the
+    /// object of a ref). The new handle keeps the objects of the other handle as well
+    fn new_derived_handle(&self, from: APIHandleArg, handle: APIHandle) -> APIHandleResult {
+        let ret = Box::new(handle);
+        let mut objs = ret.v.heap_objects();
+        objs.extend(from.v.heap_objects());
+        objs.extend(from.rooted_objects());
+        ret.root_objects(objs);
 
         ret
     }
 
     /// creates a fix sized object in the heap, and returns a reference handle
+    /// (the reference is null if the heap is out of memory)
     pub fn new_fixed(&self, tyid: MuID) -> APIHandleResult {
         let ty = self.get_type(tyid);
         assert!(!ty.is_hybrid());
@@ -1249,14 +1466,24 @@ impl<'a> VM {
         let backend_ty = self.get_backend_type_info(tyid);
         let addr = gc::allocate_fixed(ty.clone(), backend_ty, self);
         trace!("API: allocated fixed type {} at {}", ty, addr);
+        if addr.is_zero() {
+            error!("API: out of memory when allocating {}", ty);
+        }
 
-        self.new_handle(APIHandle {
+        let ret = self.new_handle(APIHandle {
             id: self.next_id(),
             v: APIHandleValue::Ref(ty, addr),
-        })
+        });
+        // the handle keeps the object alive now
+        if !addr.is_zero() {
+            gc::remove_root(unsafe { addr.to_object_reference() });
+        }
+
+        ret
     }
 
     /// creates a hybrid type object in the heap, and returns a reference handle
+    /// (the reference is null if the heap is out of memory)
     pub fn new_hybrid(&self, tyid: MuID, length: APIHandleArg) -> APIHandleResult {
         let ty = self.get_type(tyid);
         assert!(ty.is_hybrid());
@@ -1271,11 +1498,23 @@ impl<'a> VM {
             len,
             addr
         );
+        if addr.is_zero() {
+            error!(
+                "API: out of memory when allocating {} of length {}",
+                ty, len
+            );
+        }
 
-        self.new_handle(APIHandle {
+        let ret = self.new_handle(APIHandle {
             id: self.next_id(),
             v: APIHandleValue::Ref(ty, addr),
-        })
+        });
+        // the handle keeps the object alive now
+        if !addr.is_zero() {
+            gc::remove_root(unsafe { addr.to_object_reference() });
+        }
+
+        ret
     }
 
     /// performs REFCAST
@@ -1299,10 +1538,13 @@ impl<'a> VM {
                 assert!(to_ty.is_iref());
                 let inner_ty = to_ty.get_referent_ty().unwrap();
 
-                self.new_handle(APIHandle {
-                    id: handle_id,
-                    v: APIHandleValue::IRef(inner_ty, addr),
-                })
+                self.new_derived_handle(
+                    from_op,
+                    APIHandle {
+                        id: handle_id,
+                        v: APIHandleValue::IRef(inner_ty, addr),
+                    },
+                )
             }
             APIHandleValue::FuncRef(_) => unimplemented!(),
 
@@ -1315,10 +1557,13 @@ impl<'a> VM {
         let (ty, addr) = handle_ref.v.as_ref();
 
         // assume iref has the same address as ref
-        let ret = self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::IRef(ty, addr),
-        });
+        let ret = self.new_derived_handle(
+            handle_ref,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::IRef(ty, addr),
+            },
+        );
 
         trace!("API: get iref from {:?}", handle_ref);
         trace!("API: result {:?}", ret);
@@ -1343,10 +1588,13 @@ impl<'a> VM {
             addr + (aligned_size * (offset as usize))
         };
 
-        let ret = self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::IRef(ty, offset_addr),
-        });
+        let ret = self.new_derived_handle(
+            handle_iref,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::IRef(ty, offset_addr),
+            },
+        );
 
         trace!("API: shift iref from {:?}", handle_iref);
         trace!("API: result {:?}", ret);
@@ -1375,10 +1623,13 @@ impl<'a> VM {
             addr + (aligned_size * (index as usize))
         };
 
-        let ret = self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::IRef(ele_ty, elem_addr),
-        });
+        let ret = self.new_derived_handle(
+            handle_iref,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::IRef(ele_ty, elem_addr),
+            },
+        );
 
         trace!(
             "API: get element iref from {:?} at index {:?}",
@@ -1404,10 +1655,13 @@ impl<'a> VM {
             None => panic!("cannot get varpart ty from {}", ty),
         };
 
-        let ret = self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::IRef(varpart_ty, varpart_addr),
-        });
+        let ret = self.new_derived_handle(
+            handle_iref,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::IRef(varpart_ty, varpart_addr),
+            },
+        );
 
         trace!("API: get var part iref from {:?}", handle_iref);
         trace!("API: result {:?}", ret);
@@ -1434,10 +1688,13 @@ impl<'a> VM {
             addr + field_offset
         };
 
-        let ret = self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::IRef(field_ty, field_addr),
-        });
+        let ret = self.new_derived_handle(
+            handle_iref,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::IRef(field_ty, field_addr),
+            },
+        );
 
         trace!(
             "API: get field iref from {:?}, field: {}",
@@ -1465,19 +1722,7 @@ impl<'a> VM {
         };
 
         let handle_id = self.next_id();
-        let handle_value = unsafe {
-            match ty.v {
-                MuType_::Int(len) => APIHandleValue::Int(addr.load::<u64>(), len),
-                MuType_::Float => APIHandleValue::Float(addr.load::<f32>()),
-                MuType_::Double => APIHandleValue::Double(addr.load::<f64>()),
-                MuType_::Ref(ref ty) => APIHandleValue::Ref(ty.clone(), addr.load::<Address>()),
-                MuType_::IRef(ref ty) => APIHandleValue::IRef(ty.clone(), addr.load::<Address>()),
-                MuType_::UPtr(ref ty) => APIHandleValue::UPtr(ty.clone(), addr.load::<Address>()),
-                MuType_::Tagref64 => APIHandleValue::TagRef64(addr.load::<u64>()),
-
-                _ => unimplemented!(),
-            }
-        };
+        let handle_value = unsafe { self.load_value(&ty, addr) };
 
         let ret = self.new_handle(APIHandle {
             id: handle_id,
@@ -1494,7 +1739,7 @@ impl<'a> VM {
     #[allow(unused_variables)]
     pub fn handle_store(&self, ord: MemoryOrder, loc: APIHandleArg, val: APIHandleArg) {
         // get address
-        let (_, addr) = loc.v.as_iref();
+        let (ty, addr) = loc.v.as_iref();
 
         // FIXME: not using memory order for store at the moment - See Issue #51
         let rust_memord = match ord {
@@ -1507,48 +1752,410 @@ impl<'a> VM {
 
         // get value and store
         // we will store here (its unsafe)
-        unsafe {
-            match val.v {
-                APIHandleValue::Int(ival, bits) => {
-                    let trunc: u64 = ival & bits_ones(bits);
-                    match bits {
-                        1...8 => addr.store::<u8>(trunc as u8),
-                        9...16 => addr.store::<u16>(trunc as u16),
-                        17...32 => addr.store::<u32>(trunc as u32),
-                        33...64 => addr.store::<u64>(trunc as u64),
-                        _ => panic!("unimplemented int length"),
-                    }
-                }
-                APIHandleValue::TagRef64(val) => addr.store::<u64>(val),
-                APIHandleValue::Float(fval) => addr.store::<f32>(fval),
-                APIHandleValue::Double(fval) => addr.store::<f64>(fval),
-                APIHandleValue::UPtr(_, aval) => addr.store::<Address>(aval),
-                APIHandleValue::UFP(_, aval) => addr.store::<Address>(aval),
-
-                APIHandleValue::Struct(_)
-                | APIHandleValue::Array(_)
-                | APIHandleValue::Vector(_) => unimplemented!(),
-
-                APIHandleValue::Ref(_, aval) | APIHandleValue::IRef(_, aval) => {
-                    addr.store::<Address>(aval)
+        unsafe { self.store_value(&ty, addr, &val.v) }
+
+        trace!("API: store value {:?} to location {:?}", val, loc);
+    }
+
+    /// loads a value of the given type from memory. Aggregate types are loaded field by field
+    /// (or element by element) into a struct/array/vector value.
+    unsafe fn load_value(&self, ty: &P<MuType>, addr: Address) -> APIHandleValue {
+        match ty.v {
+            MuType_::Int(len) => {
+                let val = match len {
+                    1...8 => addr.load::<u8>() as u64,
+                    9...16 => addr.load::<u16>() as u64,
+                    17...32 => addr.load::<u32>() as u64,
+                    33...64 => addr.load::<u64>(),
+                    _ => panic!("unimplemented int length"),
+                };
+                APIHandleValue::Int(val, len)
+            }
+            MuType_::Float => APIHandleValue::Float(addr.load::<f32>()),
+            MuType_::Double => APIHandleValue::Double(addr.load::<f64>()),
+            MuType_::Ref(ref ty) => APIHandleValue::Ref(ty.clone(), addr.load::<Address>()),
+            MuType_::IRef(ref ty) => APIHandleValue::IRef(ty.clone(), addr.load::<Address>()),
+            MuType_::UPtr(ref ty) => APIHandleValue::UPtr(ty.clone(), addr.load::<Address>()),
+            MuType_::UFuncPtr(_) => APIHandleValue::UFP(ty.clone(), addr.load::<Address>()),
+            MuType_::Tagref64 => APIHandleValue::TagRef64(addr.load::<u64>()),
+            // loading a weakref gives a strong ref
+            MuType_::WeakRef(ref ty) => APIHandleValue::Ref(ty.clone(), addr.load::<Address>()),
+            MuType_::FuncRef(_) => APIHandleValue::FuncRef(self.func_id_at(addr)),
+            MuType_::ThreadRef => APIHandleValue::ThreadRef(addr.load::<Address>()),
+            MuType_::StackRef => APIHandleValue::StackRef(addr.load::<Address>()),
+
+            MuType_::Struct(_) => {
+                let backend_ty = self.get_backend_type_info(ty.id());
+                let n_fields = backend_ty.struct_layout.as_ref().unwrap().len();
+
+                let fields = (0..n_fields)
+                    .map(|i| {
+                        let field_ty = ty.get_field_ty(i).unwrap();
+                        self.load_value(&field_ty, addr + backend_ty.get_field_offset(i))
+                    })
+                    .collect();
+                APIHandleValue::Struct(fields)
+            }
+            MuType_::Array(ref elem_ty, len) => {
+                let elems = (0..len)
+                    .map(|i| self.load_value(elem_ty, addr + self.get_elem_offset(elem_ty, i)))
+                    .collect();
+                APIHandleValue::Array(elems)
+            }
+            MuType_::Vector(ref elem_ty, len) => {
+                let elems = (0..len)
+                    .map(|i| self.load_value(elem_ty, addr + self.get_elem_offset(elem_ty, i)))
+                    .collect();
+                APIHandleValue::Vector(elems)
+            }
+
+            MuType_::Hybrid(_) | MuType_::Void => panic!("cannot load a value of type {}", ty),
+        }
+    }
+
+    /// finds the function whose funcref is stored at the given address
+    fn func_id_at(&self, addr: Address) -> MuID {
+        let pending_funcref_guard = self.aot_pending_funcref_store.read().unwrap();
+        self.func_id_at_with_pending(addr, &pending_funcref_guard)
+    }
+
+    /// finds the function whose funcref is stored at the given address, with the pending
+    /// funcref stores (that the caller has locked)
+    fn func_id_at_with_pending(
+        &self,
+        addr: Address,
+        pending_funcrefs: &HashMap<Address, ValueLocation>,
+    ) -> MuID {
+        let func_addr = unsafe { addr.load::<Address>() };
+
+        // for AOT, the funcref may be a pending store that is only resolved in the boot image
+        if func_addr.as_usize() as u64 == PENDING_FUNCREF {
+            let symbol = match pending_funcrefs.get(&addr) {
+                Some(&ValueLocation::Relocatable(_, ref symbol)) => mangle_name(symbol.clone()),
+                _ => panic!("no pending funcref is stored at {}", addr),
+            };
+            self.func_id_of_symbol(&symbol, func_addr)
+        } else {
+            self.func_id_of_addr(func_addr)
+        }
+    }
+
+    /// finds the function that starts at the given address
+    fn func_id_of_addr(&self, func_addr: Address) -> MuID {
+        let (symbol, start) = get_function_info(func_addr);
+        assert!(
+            start == func_addr,
+            "{} is not the start of a function",
+            func_addr
+        );
+        self.func_id_of_symbol(&symbol, func_addr)
+    }
+
+    /// finds the function of the given (mangled) symbol
+    fn func_id_of_symbol(&self, symbol: &str, func_addr: Address) -> MuID {
+        let funcs = self.funcs.read().unwrap();
+        for (&id, func) in funcs.iter() {
+            if mangle_name(func.read().unwrap().name()) == symbol {
+                return id;
+            }
+        }
+        panic!(
+            "cannot find Mu function for funcref {} ({})",
+            func_addr, symbol
+        )
+    }
+
+    /// atomically replaces the funcref at `addr` with `f(old function)` (if it returns a
+    /// function) when we are doing AOT. Returns the old function.
+    //  The location holds a pending funcref that is only resolved in the boot image, so
+    //  we cannot access its bits atomically. Instead we update it under the lock of the
+    //  pending stores (as no Mu code runs on the locations of the boot image before it is
+    //  made, this is atomic against other API accesses).
+    fn atomic_update_pending_funcref<F>(&self, addr: Address, f: F) -> MuID
+    where
+        F: FnOnce(MuID) -> Option<MuID>,
+    {
+        let mut pending_funcref_guard = self.aot_pending_funcref_store.write().unwrap();
+        let old = self.func_id_at_with_pending(addr, &pending_funcref_guard);
+
+        if let Some(new) = f(old) {
+            let symbol = self.get_name_for_func(new);
+            unsafe { addr.store::<u64>(PENDING_FUNCREF) };
+            pending_funcref_guard.insert(
+                addr,
+                ValueLocation::Relocatable(backend::RegGroup::GPR, symbol),
+            );
+        }
+        old
+    }
+
+    /// extracts the raw bits from a handle value as the operand of an atomic operation
+    fn atomic_bits_from_handle_value(&self, v: &APIHandleValue) -> u64 {
+        match v {
+            &APIHandleValue::Int(val, len) => val & bits_ones(len),
+            &APIHandleValue::TagRef64(val) => val,
+            &APIHandleValue::Ref(_, addr)
+            | &APIHandleValue::IRef(_, addr)
+            | &APIHandleValue::UPtr(_, addr)
+            | &APIHandleValue::UFP(_, addr)
+            | &APIHandleValue::ThreadRef(addr)
+            | &APIHandleValue::StackRef(addr) => addr.as_usize() as u64,
+            // only for JIT, AOT uses atomic_update_pending_funcref()
+            &APIHandleValue::FuncRef(id) => {
+                resolve_symbol(self.get_name_for_func(id)).as_usize() as u64
+            }
+            _ => panic!("unsupported operand {} for atomic operations", v),
+        }
+    }
+
+    /// creates a handle value from the raw bits read from a location of the given type
+    fn atomic_bits_to_handle_value(&self, ty: &P<MuType>, bits: u64) -> APIHandleValue {
+        let addr = unsafe { Address::from_usize(bits as usize) };
+        match ty.v {
+            MuType_::Int(len) => APIHandleValue::Int(bits & bits_ones(len), len),
+            MuType_::Ref(ref referent) => APIHandleValue::Ref(referent.clone(), addr),
+            // as LOAD, a weakref gives a strong ref
+            MuType_::WeakRef(ref referent) => APIHandleValue::Ref(referent.clone(), addr),
+            MuType_::IRef(ref referent) => APIHandleValue::IRef(referent.clone(), addr),
+            MuType_::UPtr(ref referent) => APIHandleValue::UPtr(referent.clone(), addr),
+            MuType_::UFuncPtr(_) => APIHandleValue::UFP(ty.clone(), addr),
+            MuType_::Tagref64 => APIHandleValue::TagRef64(bits),
+            MuType_::ThreadRef => APIHandleValue::ThreadRef(addr),
+            MuType_::StackRef => APIHandleValue::StackRef(addr),
+            // only for JIT, AOT uses atomic_update_pending_funcref()
+            MuType_::FuncRef(_) => APIHandleValue::FuncRef(self.func_id_of_addr(addr)),
+            _ => panic!("atomic access is not supported on type {}", ty),
+        }
+    }
+
+    /// stores a value of the given type to memory. Aggregate values are stored
+    /// field by field (or element by element).
+    unsafe fn store_value(&self, ty: &P<MuType>, addr: Address, val: &APIHandleValue) {
+        match val {
+            &APIHandleValue::Int(ival, bits) => {
+                let trunc: u64 = ival & bits_ones(bits);
+                match bits {
+                    1...8 => addr.store::<u8>(trunc as u8),
+                    9...16 => addr.store::<u16>(trunc as u16),
+                    17...32 => addr.store::<u32>(trunc as u32),
+                    33...64 => addr.store::<u64>(trunc as u64),
+                    _ => panic!("unimplemented int length"),
                 }
+            }
+            &APIHandleValue::TagRef64(val) => {
+                addr.store::<u64>(val);
+                call_write_barrier(ty, addr);
+            }
+            &APIHandleValue::Float(fval) => addr.store::<f32>(fval),
+            &APIHandleValue::Double(fval) => addr.store::<f64>(fval),
+            &APIHandleValue::UPtr(_, aval) => addr.store::<Address>(aval),
+            &APIHandleValue::UFP(_, aval) => addr.store::<Address>(aval),
+
+            &APIHandleValue::Struct(ref fields) => {
+                let backend_ty = self.get_backend_type_info(ty.id());
+                assert_eq!(
+                    fields.len(),
+                    backend_ty.struct_layout.as_ref().unwrap().len(),
+                    "storing a struct value with wrong number of fields to {}",
+                    ty
+                );
 
-                // if we are JITing, we can store the address of the function
-                // but if we are doing AOT, we pend the store, and resolve the store
-                // when making boot image
-                APIHandleValue::FuncRef(id) => {
-                    if self.is_doing_jit() {
-                        unimplemented!()
-                    } else {
-                        self.store_funcref(addr, id)
+                for (i, field) in fields.iter().enumerate() {
+                    let field_ty = ty.get_field_ty(i).unwrap();
+                    self.store_value(&field_ty, addr + backend_ty.get_field_offset(i), field);
+                }
+            }
+            &APIHandleValue::Array(ref elems) | &APIHandleValue::Vector(ref elems) => {
+                let (elem_ty, len) = match ty.v {
+                    MuType_::Array(ref elem_ty, len) | MuType_::Vector(ref elem_ty, len) => {
+                        (elem_ty, len)
                     }
+                    _ => panic!("storing a sequence value {} to {}", val, ty),
+                };
+                assert_eq!(
+                    elems.len(),
+                    len,
+                    "storing a sequence value with wrong length to {}",
+                    ty
+                );
+
+                for (i, elem) in elems.iter().enumerate() {
+                    self.store_value(elem_ty, addr + self.get_elem_offset(elem_ty, i), elem);
                 }
+            }
 
-                _ => unimplemented!(),
+            // a ref value is also stored to a weakref location
+            &APIHandleValue::Ref(_, aval) | &APIHandleValue::IRef(_, aval) => {
+                addr.store::<Address>(aval);
+                call_write_barrier(ty, addr);
+            }
+            &APIHandleValue::ThreadRef(aval) | &APIHandleValue::StackRef(aval) => {
+                addr.store::<Address>(aval)
             }
+
+            // if we are JITing, we can store the address of the function
+            // but if we are doing AOT, we pend the store, and resolve the store
+            // when making boot image
+            &APIHandleValue::FuncRef(id) => {
+                if self.is_doing_jit() {
+                    let func_addr = resolve_symbol(self.get_name_for_func(id));
+                    addr.store::<Address>(func_addr)
+                } else {
+                    self.store_funcref(addr, id)
+                }
+            }
+
+            _ => panic!("cannot store {} to a location of type {}", val, ty),
         }
+    }
 
-        trace!("API: store value {:?} to location {:?}", val, loc);
+    /// returns the offset of the index-th element in an array/vector of the given element type
+    fn get_elem_offset(&self, elem_ty: &P<MuType>, index: usize) -> ByteSize {
+        use utils::math::align_up;
+
+        let backend_ty = self.get_backend_type_info(elem_ty.id());
+        align_up(backend_ty.size, backend_ty.alignment) * index
+    }
+
+    /// performs EXTRACTVALUE on a struct value
+    pub fn handle_extract_value(&self, str: APIHandleArg, index: usize) -> APIHandleResult {
+        let field = match str.v {
+            APIHandleValue::Struct(ref fields) => match fields.get(index) {
+                Some(field) => field.clone(),
+                None => panic!("field index {} out of bound for {}", index, str),
+            },
+            _ => panic!("expected Struct handle, found {}", str),
+        };
+
+        let ret = self.new_derived_handle(
+            str,
+            APIHandle {
+                id: self.next_id(),
+                v: field,
+            },
+        );
+
+        trace!("API: extract value from {:?}, field: {}", str, index);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// performs INSERTVALUE on a struct value, returns a new struct value
+    pub fn handle_insert_value(
+        &self,
+        str: APIHandleArg,
+        index: usize,
+        newval: APIHandleArg,
+    ) -> APIHandleResult {
+        let new_fields = match str.v {
+            APIHandleValue::Struct(ref fields) => {
+                assert!(
+                    index < fields.len(),
+                    "field index {} out of bound for {}",
+                    index,
+                    str
+                );
+                let mut new_fields = fields.clone();
+                new_fields[index] = newval.v.clone();
+                new_fields
+            }
+            _ => panic!("expected Struct handle, found {}", str),
+        };
+
+        let ret = self.new_derived_handle(
+            str,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::Struct(new_fields),
+            },
+        );
+
+        trace!(
+            "API: insert value {:?} into {:?}, field: {}",
+            newval,
+            str,
+            index
+        );
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// performs EXTRACTELEMENT on an array or vector value
+    pub fn handle_extract_element(
+        &self,
+        seq: APIHandleArg,
+        index: APIHandleArg,
+    ) -> APIHandleResult {
+        let index = self.handle_to_uint64(index) as usize;
+        let elem = match seq.v {
+            APIHandleValue::Array(ref elems) | APIHandleValue::Vector(ref elems) => {
+                match elems.get(index) {
+                    Some(elem) => elem.clone(),
+                    None => panic!("element index {} out of bound for {}", index, seq),
+                }
+            }
+            _ => panic!("expected Array or Vector handle, found {}", seq),
+        };
+
+        let ret = self.new_derived_handle(
+            seq,
+            APIHandle {
+                id: self.next_id(),
+                v: elem,
+            },
+        );
+
+        trace!("API: extract element from {:?}, index: {}", seq, index);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// performs INSERTELEMENT on an array or vector value, returns a new array/vector value
+    pub fn handle_insert_element(
+        &self,
+        seq: APIHandleArg,
+        index: APIHandleArg,
+        newval: APIHandleArg,
+    ) -> APIHandleResult {
+        let index = self.handle_to_uint64(index) as usize;
+        let update = |elems: &Vec<APIHandleValue>| {
+            assert!(
+                index < elems.len(),
+                "element index {} out of bound for {}",
+                index,
+                seq
+            );
+            let mut new_elems = elems.clone();
+            new_elems[index] = newval.v.clone();
+            new_elems
+        };
+
+        let new_seq = match seq.v {
+            APIHandleValue::Array(ref elems) => APIHandleValue::Array(update(elems)),
+            APIHandleValue::Vector(ref elems) => APIHandleValue::Vector(update(elems)),
+            _ => panic!("expected Array or Vector handle, found {}", seq),
+        };
+
+        let ret = self.new_derived_handle(
+            seq,
+            APIHandle {
+                id: self.next_id(),
+                v: new_seq,
+            },
+        );
+
+        trace!(
+            "API: insert element {:?} into {:?}, index: {}",
+            newval,
+            seq,
+            index
+        );
+        trace!("API: result {:?}", ret);
+
+        ret
     }
 
     #[cfg(feature = "aot")]
@@ -1566,39 +2173,267 @@ impl<'a> VM {
         );
     }
 
+    /// performs CMPXCHG, returns the old value at the location and whether the swap succeeded
+    pub fn handle_cmpxchg(
+        &self,
+        ord_succ: MemoryOrder,
+        ord_fail: MemoryOrder,
+        weak: bool,
+        loc: APIHandleArg,
+        expected: APIHandleArg,
+        desired: APIHandleArg,
+    ) -> (APIHandleResult, bool) {
+        let (ty, addr) = loc.v.as_iref();
+        let len = atomic_access_bits(&ty);
+
+        let rust_ord_succ = atomic_ordering(ord_succ);
+        let rust_ord_fail = match ord_fail {
+            MemoryOrder::Relaxed => Ordering::Relaxed,
+            MemoryOrder::Acquire => Ordering::Acquire,
+            MemoryOrder::SeqCst => Ordering::SeqCst,
+            MemoryOrder::NotAtomic => Ordering::Relaxed, // use relaxed for not atomic
+            MemoryOrder::Consume => Ordering::Acquire,   // use acquire for consume
+            _ => panic!("unsupported failure order {:?} for CMPXCHG", ord_fail),
+        };
+        assert!(
+            is_valid_failure_ordering(rust_ord_succ, rust_ord_fail),
+            "failure order {:?} is stronger than success order {:?} for CMPXCHG",
+            ord_fail,
+            ord_succ
+        );
+
+        let (old, succ) = if ty.is_funcref() && !self.is_doing_jit() {
+            let expected_id = expected.v.as_funcref();
+            let desired_id = desired.v.as_funcref();
+            let old_id = self.atomic_update_pending_funcref(addr, |old_id| {
+                if old_id == expected_id {
+                    Some(desired_id)
+                } else {
+                    None
+                }
+            });
+            (APIHandleValue::FuncRef(old_id), old_id == expected_id)
+        } else {
+            let expected_bits = self.atomic_bits_from_handle_value(&expected.v);
+            let desired_bits = self.atomic_bits_from_handle_value(&desired.v);
+
+            let (old_bits, succ) = unsafe {
+                atomic_cmpxchg_bits(
+                    addr,
+                    len,
+                    expected_bits,
+                    desired_bits,
+                    rust_ord_succ,
+                    rust_ord_fail,
+                    weak,
+                )
+            };
+            (self.atomic_bits_to_handle_value(&ty, old_bits), succ)
+        };
+        if succ {
+            call_write_barrier(&ty, addr);
+        }
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: old,
+        });
+
+        trace!(
+            "API: cmpxchg at {:?}, expected {:?}, desired {:?}",
+            loc,
+            expected,
+            desired
+        );
+        trace!("API: result {:?}, succeeded: {}", ret, succ);
+
+        (ret, succ)
+    }
+
+    /// performs ATOMICRMW, returns the old value at the location
+    pub fn handle_atomicrmw(
+        &self,
+        ord: MemoryOrder,
+        op: AtomicRMWOp,
+        loc: APIHandleArg,
+        opnd: APIHandleArg,
+    ) -> APIHandleResult {
+        let (ty, addr) = loc.v.as_iref();
+        let len = atomic_access_bits(&ty);
+
+        // only integers support arithmetic, other types can only be exchanged
+        if !ty.is_int() && op != AtomicRMWOp::XCHG {
+            panic!("ATOMICRMW {} is not supported on type {}", op, ty);
+        }
+
+        let old = if ty.is_funcref() && !self.is_doing_jit() {
+            let opnd_id = opnd.v.as_funcref();
+            APIHandleValue::FuncRef(self.atomic_update_pending_funcref(addr, |_| Some(opnd_id)))
+        } else {
+            let rust_memord = atomic_ordering(ord);
+            let opnd_bits = self.atomic_bits_from_handle_value(&opnd.v);
+
+            let old_bits = unsafe {
+                atomic_update_bits(addr, len, rust_memord, |old| {
+                    atomic_rmw_compute(op, len, old, opnd_bits)
+                })
+            };
+            self.atomic_bits_to_handle_value(&ty, old_bits)
+        };
+        call_write_barrier(&ty, addr);
+
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: old,
+        });
+
+        trace!("API: atomicrmw {} at {:?} with {:?}", op, loc, opnd);
+        trace!("API: result {:?}", ret);
+
+        ret
+    }
+
+    /// performs FENCE
+    pub fn handle_fence(&self, ord: MemoryOrder) {
+        trace!("API: fence {:?}", ord);
+
+        match ord {
+            // a relaxed fence has no effect
+            MemoryOrder::NotAtomic | MemoryOrder::Relaxed => {}
+            _ => std::sync::atomic::fence(atomic_ordering(ord)),
+        }
+    }
+
+    /// compares two reference handles (ref, iref, funcref, threadref, stackref) for equality
+    //  This compares the addresses held by the handles. The objects of live handles are
+    //  roots, and roots are never moved, so the addresses stay valid while the handles live.
+    pub fn handle_ref_eq(&self, lhs: APIHandleArg, rhs: APIHandleArg) -> bool {
+        let ret = match (&lhs.v, &rhs.v) {
+            (&APIHandleValue::Ref(_, a), &APIHandleValue::Ref(_, b))
+            | (&APIHandleValue::IRef(_, a), &APIHandleValue::IRef(_, b))
+            | (&APIHandleValue::ThreadRef(a), &APIHandleValue::ThreadRef(b))
+            | (&APIHandleValue::StackRef(a), &APIHandleValue::StackRef(b)) => a == b,
+            (&APIHandleValue::FuncRef(a), &APIHandleValue::FuncRef(b)) => a == b,
+            _ => panic!("unexpected operands for ref_eq: {} and {}", lhs, rhs),
+        };
+
+        trace!("API: ref_eq {:?} and {:?}: {}", lhs, rhs, ret);
+        ret
+    }
+
+    /// checks if an internal reference is below another one. The result is only
+    /// meaningful if both irefs refer to the same object.
+    pub fn handle_ref_ult(&self, lhs: APIHandleArg, rhs: APIHandleArg) -> bool {
+        let (_, a) = lhs.v.as_iref();
+        let (_, b) = rhs.v.as_iref();
+        let ret = a < b;
+
+        trace!("API: ref_ult {:?} and {:?}: {}", lhs, rhs, ret);
+        ret
+    }
+
     /// performs CommonInst_Pin
-    //  This function and the following two make assumption that GC will not move object.
-    //  They need to be reimplemented if we have a moving GC
     //  FIXME: The pin/unpin semantic (here and in instruction selection) is different from Mu spec
     //  See Issue #33
     pub fn handle_pin_object(&self, loc: APIHandleArg) -> APIHandleResult {
-        debug_assert!(!gc::GC_MOVES_OBJECT);
-        // gc will not move, so we just put ref into uptr
-
         let (ty, addr) = loc.v.as_ref_or_iref();
-        self.new_handle(APIHandle {
-            id: self.next_id(),
-            v: APIHandleValue::UPtr(ty, addr),
-        })
+        if gc::GC_MOVES_OBJECT {
+            for obj in self.objects_to_pin(loc) {
+                gc::muentry_pin_object(obj);
+            }
+        }
+
+        // the pointer handle keeps the object in place as well
+        self.new_derived_handle(
+            loc,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::UPtr(ty, addr),
+            },
+        )
     }
 
     /// performs CommonInst_Unpin
-    #[allow(unused_variables)]
     pub fn handle_unpin_object(&self, loc: APIHandleArg) {
-        debug_assert!(!gc::GC_MOVES_OBJECT);
-        // gc will not move, do no need to unpin
-        // do nothing
+        if gc::GC_MOVES_OBJECT {
+            for obj in self.objects_to_pin(loc) {
+                gc::muentry_unpin_object(obj.to_address());
+            }
+        }
+    }
+
+    /// returns the object that a ref handle refers to, or that an iref handle points into.
+    /// We know the object of an iref if the VM derived the iref from a ref. Otherwise the iref
+    /// does not point into the heap (e.g. it refers to a global cell), and there is nothing
+    /// to pin
+    fn objects_to_pin(&self, loc: APIHandleArg) -> Vec<ObjectReference> {
+        match loc.v {
+            APIHandleValue::Ref(_, _) => loc.v.heap_objects(),
+            APIHandleValue::IRef(_, _) => loc.rooted_objects(),
+            _ => panic!("expected Ref or IRef handle"),
+        }
     }
 
     /// performs CommonInst_GetAddr
     pub fn handle_get_addr(&self, loc: APIHandleArg) -> APIHandleResult {
-        assert!(!gc::GC_MOVES_OBJECT);
-        // loc needs to be already pinned - we don't check since we don't pin
-
+        // loc needs to be already pinned. Besides, the pointer handle keeps the object
+        // alive and in place while it is alive
         let (ty, addr) = loc.v.as_ref_or_iref();
+        self.new_derived_handle(
+            loc,
+            APIHandle {
+                id: self.next_id(),
+                v: APIHandleValue::UPtr(ty, addr),
+            },
+        )
+    }
+
+    /// performs CommonInst_RegisterFinalizer
+    /// (the object is not finalizable while the client holds a handle to it, as the objects
+    /// of live handles are roots)
+    pub fn handle_register_finalizer(&self, obj: APIHandleArg) {
+        let (_, addr) = obj.v.as_ref();
+        gc::muentry_register_finalizer(unsafe { addr.to_object_reference() });
+    }
+
+    /// takes an object that is queued for finalisation (returns a null ref if there is none).
+    /// Unlike CommonInst_GetFinalizable, this does not wait for the queue
+    pub fn handle_get_finalizable(&self) -> APIHandleResult {
+        // the object is rooted when it leaves the queue, until the handle keeps it alive
+        let obj = gc::try_get_finalizable_as_root();
+        let addr = match obj {
+            Some(obj) => obj.to_address(),
+            None => Address::zero(),
+        };
+        let ret = self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::Ref(types::REF_VOID_TYPE.clone(), addr),
+        });
+        if let Some(obj) = obj {
+            gc::remove_root(obj);
+        }
+
+        ret
+    }
+
+    /// returns current statistics of the GC
+    pub fn get_gc_stats(&self) -> gc::GCStats {
+        gc::gc_stats()
+    }
+
+    /// forces a major collection
+    pub fn force_gc(&self) {
+        gc::force_collection()
+    }
+
+    /// returns a handle to the exception that is thrown when the heap is out of memory
+    pub fn handle_get_oom_exception(&self) -> APIHandleResult {
         self.new_handle(APIHandle {
             id: self.next_id(),
-            v: APIHandleValue::UPtr(ty, addr),
+            v: APIHandleValue::Ref(
+                types::REF_VOID_TYPE.clone(),
+                gc::get_oom_exception().to_address(),
+            ),
         })
     }
 
@@ -1708,6 +2543,30 @@ impl<'a> VM {
     pub fn pop_join_handle(&self) -> Option<JoinHandle<()>> {
         self.pending_joins.lock().unwrap().pop_front()
     }
+    /// waits until all the Mu threads (including the threads they create) have exited
+    pub fn join_mu_threads(&self) {
+        // if current thread is a Mu thread, it will not reach any yieldpoint while waiting,
+        // so we tell the GC not to wait for it
+        let mutator = if MuThread::has_current() {
+            let mutator = &mut MuThread::current_mut().allocator as *mut gc::Mutator;
+            gc::enter_native(mutator);
+            Some(mutator)
+        } else {
+            None
+        };
+
+        loop {
+            let thread = self.pop_join_handle();
+            if thread.is_none() {
+                break;
+            }
+            thread.unwrap().join().unwrap();
+        }
+
+        if let Some(mutator) = mutator {
+            gc::leave_native(mutator);
+        }
+    }
     /// unwraps a handle to float
     pub fn handle_to_float(&self, handle: APIHandleArg) -> f32 {
         handle.v.as_float()
@@ -1879,3 +2738,192 @@ impl<'a> VM {
         })
     }
 }
+
+/// calls the GC write barrier after a value of the given type is stored to the address
+fn call_write_barrier(ty: &MuType, addr: Address) {
+    if let Some(kind) = gc::write_barrier_kind(ty) {
+        gc::write_barrier(addr, kind);
+    }
+}
+
+/// returns the number of bits that an atomic access to a location of the given type works on
+fn atomic_access_bits(ty: &P<MuType>) -> BitSize {
+    match ty.v {
+        MuType_::Int(len) if len >= 1 && len <= 64 => len,
+        MuType_::Int(len) if len > 64 => panic!(
+            "Zebu doesnt implement atomic access on int with length larger than 64 bits ({})",
+            ty
+        ),
+        MuType_::Ref(_)
+        | MuType_::WeakRef(_)
+        | MuType_::IRef(_)
+        | MuType_::UPtr(_)
+        | MuType_::UFuncPtr(_)
+        | MuType_::FuncRef(_)
+        | MuType_::ThreadRef
+        | MuType_::StackRef
+        | MuType_::Tagref64 => 64,
+        _ => panic!("atomic access is not supported on type {}", ty),
+    }
+}
+
+/// converts a Mu memory order to the Rust ordering of an atomic access
+fn atomic_ordering(ord: MemoryOrder) -> Ordering {
+    match ord {
+        MemoryOrder::NotAtomic => Ordering::Relaxed, // use relaxed for not atomic
+        MemoryOrder::Relaxed => Ordering::Relaxed,
+        MemoryOrder::Consume => Ordering::Acquire, // use acquire for consume
+        MemoryOrder::Acquire => Ordering::Acquire,
+        MemoryOrder::Release => Ordering::Release,
+        MemoryOrder::AcqRel => Ordering::AcqRel,
+        MemoryOrder::SeqCst => Ordering::SeqCst,
+    }
+}
+
+/// returns the strongest ordering that is allowed for the failure case of a
+/// compare-and-swap whose success ordering is `ord`
+fn atomic_failure_ordering(ord: Ordering) -> Ordering {
+    match ord {
+        Ordering::Release | Ordering::Relaxed => Ordering::Relaxed,
+        Ordering::AcqRel | Ordering::Acquire => Ordering::Acquire,
+        _ => Ordering::SeqCst,
+    }
+}
+
+/// checks if `fail` is a legal failure ordering for a compare-and-swap with success ordering `succ`
+fn is_valid_failure_ordering(succ: Ordering, fail: Ordering) -> bool {
+    match (atomic_failure_ordering(succ), fail) {
+        (_, Ordering::Relaxed) => true,
+        (Ordering::Acquire, Ordering::Acquire) | (Ordering::SeqCst, Ordering::Acquire) => true,
+        (Ordering::SeqCst, Ordering::SeqCst) => true,
+        _ => false,
+    }
+}
+
+/// returns the number of bytes that a value of `len` bits occupies in memory
+fn storage_bytes_for_bits(len: BitSize) -> ByteSize {
+    match len {
+        1...8 => 1,
+        9...16 => 2,
+        17...32 => 4,
+        33...64 => 8,
+        _ => panic!("unimplemented int length {}", len),
+    }
+}
+
+/// locates an atomic access of `len` bits at `addr` within its enclosing machine word.
+/// Returns the address of the word, the bit offset of the value in the word, and the
+/// mask that selects the value.
+//  Rust 1.30 only gives us word-sized integer atomics, so narrower accesses are done
+//  with compare-and-swap on the aligned word that contains them. This assumes the
+//  location is naturally aligned and the target is little endian (x86-64 and aarch64).
+fn atomic_word_location(addr: Address, len: BitSize) -> (Address, usize, u64) {
+    let bytes = storage_bytes_for_bits(len);
+    assert!(
+        addr.is_aligned_to(bytes),
+        "misaligned atomic access of {} bits at {}",
+        len,
+        addr
+    );
+
+    let word_size = std::mem::size_of::<usize>();
+    let word_addr = addr.mask(!(word_size - 1));
+    let shift = (addr - word_addr) << 3;
+
+    (word_addr, shift, bits_ones(bytes << 3) << shift)
+}
+
+/// atomically compares the `len`-bit value at `addr` with `expected`, and replaces it with
+/// `desired` if they are equal. Returns the old value and whether the swap happened.
+unsafe fn atomic_cmpxchg_bits(
+    addr: Address,
+    len: BitSize,
+    expected: u64,
+    desired: u64,
+    succ: Ordering,
+    fail: Ordering,
+    weak: bool,
+) -> (u64, bool) {
+    let (word_addr, shift, mask) = atomic_word_location(addr, len);
+    let word = word_addr.to_ref::<AtomicUsize>();
+    let expected = expected & bits_ones(len);
+    let desired = desired & bits_ones(len);
+
+    let mut cur = word.load(fail) as u64;
+    loop {
+        let old = (cur & mask) >> shift;
+        if old != expected {
+            return (old, false);
+        }
+
+        let new = (cur & !mask) | (desired << shift);
+        let res = if weak {
+            word.compare_exchange_weak(cur as usize, new as usize, succ, fail)
+        } else {
+            word.compare_exchange(cur as usize, new as usize, succ, fail)
+        };
+
+        match res {
+            Ok(_) => return (old, true),
+            // a weak cmpxchg is allowed to fail spuriously
+            Err(actual) if weak => return (((actual as u64) & mask) >> shift, false),
+            // otherwise the word changed (maybe only its neighbouring bytes), try again
+            Err(actual) => cur = actual as u64,
+        }
+    }
+}
+
+/// atomically replaces the `len`-bit value at `addr` with `f(old)`. Returns the old value.
+unsafe fn atomic_update_bits<F>(addr: Address, len: BitSize, ord: Ordering, f: F) -> u64
+where
+    F: Fn(u64) -> u64,
+{
+    let (word_addr, shift, mask) = atomic_word_location(addr, len);
+    let word = word_addr.to_ref::<AtomicUsize>();
+    let fail_ord = atomic_failure_ordering(ord);
+
+    let mut cur = word.load(fail_ord) as u64;
+    loop {
+        let old = (cur & mask) >> shift;
+        let new = (cur & !mask) | ((f(old) & bits_ones(len)) << shift);
+
+        match word.compare_exchange_weak(cur as usize, new as usize, ord, fail_ord) {
+            Ok(_) => return old,
+            Err(actual) => cur = actual as u64,
+        }
+    }
+}
+
+/// computes the new value of ATOMICRMW on `len`-bit integers
+fn atomic_rmw_compute(op: AtomicRMWOp, len: BitSize, old: u64, opnd: u64) -> u64 {
+    let old = old & bits_ones(len);
+    let opnd = opnd & bits_ones(len);
+    // sign extends a len-bit value (for signed MAX/MIN)
+    let sext = |v: u64| ((v << (64 - len)) as i64) >> (64 - len);
+
+    match op {
+        AtomicRMWOp::XCHG => opnd,
+        AtomicRMWOp::ADD => old.wrapping_add(opnd),
+        AtomicRMWOp::SUB => old.wrapping_sub(opnd),
+        AtomicRMWOp::AND => old & opnd,
+        AtomicRMWOp::NAND => !(old & opnd),
+        AtomicRMWOp::OR => old | opnd,
+        AtomicRMWOp::XOR => old ^ opnd,
+        AtomicRMWOp::MAX => {
+            if sext(old) >= sext(opnd) {
+                old
+            } else {
+                opnd
+            }
+        }
+        AtomicRMWOp::MIN => {
+            if sext(old) <= sext(opnd) {
+                old
+            } else {
+                opnd
+            }
+        }
+        AtomicRMWOp::UMAX => std::cmp::max(old, opnd),
+        AtomicRMWOp::UMIN => std::cmp::min(old, opnd),
+    }
+}
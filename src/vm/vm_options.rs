@@ -17,8 +17,10 @@
 extern crate docopt;
 
 use self::docopt::Docopt;
+use compiler::backend;
 use std;
 use std::default::Default;
+use std::env;
 
 const USAGE: &'static str = "
 zebu (mu implementation). Pass arguments as a strings to init it.
@@ -32,10 +34,27 @@ VM:
 
 Compiler:
   --disable-inline                      disable compiler function inlining
+  --disable-escape-analysis             disable replacing non-escaping allocations with
+                                        scalars or stack slots
   --disable-regalloc-validate           disable register allocation validation
+  --regalloc-linear-scan                use linear scan register allocation (faster to compile)
+                                        instead of graph coloring
   --disable-ir-validate                 disable IR validation
+  --inst-sched=<when>                   schedule machine instructions (aarch64 only): none,
+                                        pre-regalloc, post-regalloc or both [default: none]
+  --inst-sched-core=<core>              the core whose latencies the instruction scheduler uses:
+                                        cortex-a53, cortex-a55 or cortex-a57
+                                        [default: cortex-a53]
   --emit-debug-info                     emit debugging information
 
+Profile-Guided Optimisation:
+  --profile-instrument                  instrument generated code with counters for function
+                                        entries, branch edges and callsites
+  --profile-output=<file>               the file that instrumented code writes the counts to at
+                                        exit [default: mu.profile]
+  --profile-use=<file>                  use the counts in the profile for branch probabilities,
+                                        block layout and inlining [default: ]
+
 AOT Compiler:
   --aot-emit-dir=<dir>                  the emit directory for ahead-of-time compiling
                                         [default: emit]
@@ -46,12 +65,24 @@ AOT Compiler:
                                            [default: ]
 
 Garbage Collection:
-  --gc-disable-collection               disable collection
+  --gc-disable-collection               disable collection (and yieldpoints in generated code)
   --gc-immixspace-size=<kb>             immix space size (default 65536kb = 64mb)
                                         [default: 67108864]
   --gc-lospace-size=<kb>                large object space size (default 65536kb = 64mb)
                                         [default: 67108864]
+  --gc-heap-min-size=<size>             the heap does not shrink below this size (in bytes)
+                                        [default: 8388608]
+  --gc-heap-target-size=<size>          initial heap size (in bytes), the heap grows or shrinks
+                                        from it with the ratio of live data [default: 33554432]
+  --gc-heap-max-size=<size>             the heap does not grow beyond this size (in bytes),
+                                        0 for the sum of space sizes [default: 0]
+  --gc-pause-target=<ms>                the heap shrinks if a collection pauses longer than this
+                                        (0 for no target) [default: 0]
   --gc-nthreads=<n>                     number of threads for parallel gc [default: 8]
+  --gc-verify-heap                      verify the heap before and after each collection
+                                        (debug builds only)
+  --gc-disable-generational             collect the whole heap in every collection
+                                        (and no write barriers in generated code)
 ";
 
 #[derive(Debug, Deserialize)]
@@ -62,10 +93,19 @@ pub struct VMOptions {
 
     // Compiler
     pub flag_disable_inline: bool,
+    pub flag_disable_escape_analysis: bool,
     pub flag_disable_regalloc_validate: bool,
+    pub flag_regalloc_linear_scan: bool,
     pub flag_disable_ir_validate: bool,
+    pub flag_inst_sched: String,
+    pub flag_inst_sched_core: String,
     pub flag_emit_debug_info: bool,
 
+    // Profile-guided optimisation
+    pub flag_profile_instrument: bool,
+    pub flag_profile_output: String,
+    pub flag_profile_use: String,
+
     // AOT compiler
     pub flag_aot_emit_dir: String,
     pub flag_aot_link_static: bool,
@@ -76,24 +116,43 @@ pub struct VMOptions {
     pub flag_gc_disable_collection: bool,
     pub flag_gc_immixspace_size: usize,
     pub flag_gc_lospace_size: usize,
-    pub flag_gc_nthreads: usize
+    pub flag_gc_heap_min_size: usize,
+    pub flag_gc_heap_target_size: usize,
+    pub flag_gc_heap_max_size: usize,
+    pub flag_gc_pause_target: usize,
+    pub flag_gc_nthreads: usize,
+    pub flag_gc_verify_heap: bool,
+    pub flag_gc_disable_generational: bool
 }
 
 // The fields need to be listed here in the order rust stores them in
 rodal_struct!(VMOptions {
+    flag_inst_sched,
+    flag_inst_sched_core,
+    flag_profile_output,
+    flag_profile_use,
     flag_aot_emit_dir,
     flag_bootimage_external_lib,
     flag_bootimage_external_libpath,
     flag_gc_immixspace_size,
     flag_gc_lospace_size,
+    flag_gc_heap_min_size,
+    flag_gc_heap_target_size,
+    flag_gc_heap_max_size,
+    flag_gc_pause_target,
     flag_gc_nthreads,
     flag_log_level,
     flag_disable_inline,
+    flag_disable_escape_analysis,
     flag_disable_regalloc_validate,
+    flag_regalloc_linear_scan,
     flag_disable_ir_validate,
     flag_emit_debug_info,
+    flag_profile_instrument,
     flag_aot_link_static,
-    flag_gc_disable_collection
+    flag_gc_disable_collection,
+    flag_gc_verify_heap,
+    flag_gc_disable_generational
 });
 
 #[derive(Debug, Clone, Copy, Deserialize)]
@@ -127,19 +186,32 @@ impl VMOptions {
     pub fn init(str: &str) -> VMOptions {
         info!("init vm options with: {:?}", str);
 
+        let mut args: Vec<String> = str.split_whitespace().map(|s| s.to_string()).collect();
+        // options in MU_VM_OPTS are appended unless the user sets them explicitly
+        // (e.g. we run the test suites with a small heap and collection enabled)
+        if let Ok(env_opts) = env::var("MU_VM_OPTS") {
+            if args.is_empty() {
+                args.push("init_mu".to_string());
+            }
+            for opt in env_opts.split_whitespace() {
+                let opt_name = opt.split('=').next().unwrap();
+                if !args.iter().any(|arg| arg.split('=').next().unwrap() == opt_name) {
+                    args.push(opt.to_string());
+                }
+            }
+        }
+
         let mut ret: VMOptions = Docopt::new(USAGE)
-            .and_then(|d| d.argv(str.split_whitespace().into_iter()).parse())
+            .and_then(|d| d.argv(args.into_iter()).parse())
             .unwrap_or_else(|e| e.exit())
             .deserialize()
             .unwrap();
 
         info!("parsed as {:?}", ret);
 
-        // at the moment disable collection for debugging
-        // also because currently GC is buggy, and we are going to rewrite the GC
-        // See Issue #12
-        if !ret.flag_gc_disable_collection {
-            warn!("gc-disabled-collection is forced to true (opposite to user setting)");
+        // collection is only enabled on the architectures where generated code supports it
+        if !backend::SUPPORTS_GC_COLLECTION && !ret.flag_gc_disable_collection {
+            warn!("gc-disable-collection is forced to true (collection is not supported)");
             ret.flag_gc_disable_collection = true;
         }
 
@@ -156,6 +228,38 @@ impl VMOptions {
             ret.flag_disable_regalloc_validate = true;
         }
 
+        // the heap cannot grow beyond the spaces, and its sizes need to be min <= target <= max
+        let space_size = ret.flag_gc_immixspace_size + ret.flag_gc_lospace_size;
+        if ret.flag_gc_heap_max_size == 0 {
+            ret.flag_gc_heap_max_size = space_size;
+        } else if ret.flag_gc_heap_max_size > space_size {
+            warn!(
+                "gc-heap-max-size is forced to {} (the sum of space sizes)",
+                space_size
+            );
+            ret.flag_gc_heap_max_size = space_size;
+        }
+        if ret.flag_gc_heap_min_size > ret.flag_gc_heap_max_size {
+            warn!(
+                "gc-heap-min-size is forced to {} (gc-heap-max-size)",
+                ret.flag_gc_heap_max_size
+            );
+            ret.flag_gc_heap_min_size = ret.flag_gc_heap_max_size;
+        }
+        if ret.flag_gc_heap_target_size < ret.flag_gc_heap_min_size {
+            warn!(
+                "gc-heap-target-size is forced to {} (gc-heap-min-size)",
+                ret.flag_gc_heap_min_size
+            );
+            ret.flag_gc_heap_target_size = ret.flag_gc_heap_min_size;
+        } else if ret.flag_gc_heap_target_size > ret.flag_gc_heap_max_size {
+            warn!(
+                "gc-heap-target-size is forced to {} (gc-heap-max-size)",
+                ret.flag_gc_heap_max_size
+            );
+            ret.flag_gc_heap_target_size = ret.flag_gc_heap_max_size;
+        }
+
         if cfg!(target_os = "macos") {
             if !ret.flag_aot_link_static {
                 warn!("link-statically is forced to true (opposite to user setting)");
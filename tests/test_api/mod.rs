@@ -12,5 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod test_aggregate;
+mod test_atomic;
+mod test_gc_api;
+mod test_ref_cmp;
+mod test_thread_stack;
 mod test_tr64;
 mod test_vm_version;
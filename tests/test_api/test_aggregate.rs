@@ -0,0 +1,179 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mu::ast::inst::MemoryOrder;
+use mu::ast::ir::*;
+use mu::ast::ptr::*;
+use mu::ast::types::*;
+use mu::utils::Address;
+use mu::vm::handle::*;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+fn handle(v: APIHandleValue) -> APIHandle {
+    APIHandle {
+        id: 0, // arbitrary
+        v: v,
+    }
+}
+
+fn iref_to<T>(ty: &P<MuType>, loc: &mut T) -> APIHandle {
+    handle(APIHandleValue::IRef(
+        ty.clone(),
+        Address::from_mut_ptr(loc as *mut T),
+    ))
+}
+
+#[test]
+fn test_extract_insert_value() {
+    let vm = VM::new();
+
+    let s = handle(APIHandleValue::Struct(vec![
+        APIHandleValue::Int(1, 64),
+        APIHandleValue::Double(2.5f64),
+    ]));
+
+    assert_eq!(vm.handle_extract_value(&s, 0).v.as_int(), 1);
+    assert_eq!(vm.handle_extract_value(&s, 1).v.as_double(), 2.5f64);
+
+    let s2 = vm.handle_insert_value(&s, 0, &handle(APIHandleValue::Int(42, 64)));
+    assert_eq!(vm.handle_extract_value(&s2, 0).v.as_int(), 42);
+    assert_eq!(vm.handle_extract_value(&s2, 1).v.as_double(), 2.5f64);
+
+    // the original value is unchanged
+    assert_eq!(vm.handle_extract_value(&s, 0).v.as_int(), 1);
+}
+
+#[test]
+fn test_extract_insert_element() {
+    let vm = VM::new();
+
+    let a = handle(APIHandleValue::Array(vec![
+        APIHandleValue::Int(10, 32),
+        APIHandleValue::Int(20, 32),
+        APIHandleValue::Int(30, 32),
+    ]));
+    let idx = handle(APIHandleValue::Int(2, 64));
+
+    assert_eq!(vm.handle_extract_element(&a, &idx).v.as_int(), 30);
+
+    let a2 = vm.handle_insert_element(&a, &idx, &handle(APIHandleValue::Int(99, 32)));
+    assert_eq!(vm.handle_extract_element(&a2, &idx).v.as_int(), 99);
+    assert_eq!(vm.handle_extract_element(&a, &idx).v.as_int(), 30);
+
+    let v = handle(APIHandleValue::Vector(vec![
+        APIHandleValue::Float(1f32),
+        APIHandleValue::Float(2f32),
+    ]));
+    let idx = handle(APIHandleValue::Int(1, 64));
+    let v2 = vm.handle_insert_element(&v, &idx, &handle(APIHandleValue::Float(5f32)));
+    assert_eq!(vm.handle_extract_element(&v2, &idx).v.as_float(), 5f32);
+}
+
+#[test]
+fn test_load_store_struct() {
+    let vm = Arc::new(VM::new());
+
+    typedef!    ((vm) int8  = mu_int(8));
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) struct1 = mu_struct(int64, int8, int64));
+
+    let mut mem: [u64; 3] = [0; 3];
+    let loc = iref_to(&struct1, &mut mem);
+
+    let val = handle(APIHandleValue::Struct(vec![
+        APIHandleValue::Int(0x1111, 64),
+        APIHandleValue::Int(0x22, 8),
+        APIHandleValue::Int(0x3333, 64),
+    ]));
+    vm.handle_store(MemoryOrder::NotAtomic, &loc, &val);
+    assert_eq!(mem, [0x1111, 0x22, 0x3333]);
+
+    let loaded = vm.handle_load(MemoryOrder::NotAtomic, &loc);
+    assert_eq!(vm.handle_extract_value(&loaded, 0).v.as_int(), 0x1111);
+    assert_eq!(vm.handle_extract_value(&loaded, 1).v.as_int(), 0x22);
+    assert_eq!(vm.handle_extract_value(&loaded, 2).v.as_int(), 0x3333);
+}
+
+#[test]
+fn test_load_store_array_of_structs() {
+    let vm = Arc::new(VM::new());
+
+    typedef!    ((vm) int8  = mu_int(8));
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) struct1 = mu_struct(int64, int8));
+    typedef!    ((vm) array1 = mu_array(struct1, 2));
+
+    let mut mem: [u64; 4] = [0; 4];
+    let loc = iref_to(&array1, &mut mem);
+
+    let elem =
+        |a, b| APIHandleValue::Struct(vec![APIHandleValue::Int(a, 64), APIHandleValue::Int(b, 8)]);
+    let val = handle(APIHandleValue::Array(vec![elem(1, 2), elem(3, 4)]));
+    vm.handle_store(MemoryOrder::NotAtomic, &loc, &val);
+    assert_eq!(mem, [1, 2, 3, 4]);
+
+    let loaded = vm.handle_load(MemoryOrder::NotAtomic, &loc);
+    let idx = handle(APIHandleValue::Int(1, 64));
+    let second = vm.handle_extract_element(&loaded, &idx);
+    assert_eq!(vm.handle_extract_value(&second, 0).v.as_int(), 3);
+    assert_eq!(vm.handle_extract_value(&second, 1).v.as_int(), 4);
+}
+
+#[test]
+fn test_load_store_struct_of_refs() {
+    let vm = Arc::new(VM::new());
+
+    typedef!    ((vm) int64 = mu_int(64));
+    funcsig!    ((vm) sig = () -> ());
+    funcdecl!   ((vm) <sig> func);
+    typedef!    ((vm) funcref_sig = mu_funcref(sig));
+    let weakref_int64 = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("weakref_int64")),
+        MuType_::WeakRef(int64.clone()),
+    );
+    let stackref = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("stackref")),
+        MuType_::StackRef,
+    );
+    let threadref = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("threadref")),
+        MuType_::ThreadRef,
+    );
+    typedef!    ((vm) struct1 = mu_struct(weakref_int64, stackref, threadref, funcref_sig));
+
+    let mut mem: [u64; 4] = [0; 4];
+    let loc = iref_to(&struct1, &mut mem);
+
+    let val = handle(APIHandleValue::Struct(vec![
+        APIHandleValue::Ref(int64.clone(), unsafe { Address::from_usize(0x1000) }),
+        APIHandleValue::StackRef(unsafe { Address::from_usize(0x2000) }),
+        APIHandleValue::ThreadRef(unsafe { Address::from_usize(0x3000) }),
+        APIHandleValue::FuncRef(func.id()),
+    ]));
+    vm.handle_store(MemoryOrder::NotAtomic, &loc, &val);
+    assert_eq!(&mem[0..3], &[0x1000, 0x2000, 0x3000]);
+
+    let loaded = vm.handle_load(MemoryOrder::NotAtomic, &loc);
+    // a weakref is loaded as a strong ref
+    let (_, weak) = vm.handle_extract_value(&loaded, 0).v.as_ref();
+    assert_eq!(weak.as_usize(), 0x1000);
+    let stack = vm.handle_extract_value(&loaded, 1).v.as_stackref();
+    assert_eq!(stack.as_usize(), 0x2000);
+    let thread = vm.handle_extract_value(&loaded, 2).v.as_threadref();
+    assert_eq!(thread.as_usize(), 0x3000);
+    let funcref = vm.handle_extract_value(&loaded, 3).v.as_funcref();
+    assert_eq!(funcref, func.id());
+}
@@ -0,0 +1,412 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mu::ast::inst::MemoryOrder;
+use mu::ast::ir::*;
+use mu::ast::op::AtomicRMWOp;
+use mu::ast::ptr::*;
+use mu::ast::types::*;
+use mu::utils::Address;
+use mu::vm::handle::*;
+use mu::vm::*;
+
+use std::sync::Arc;
+use std::thread;
+
+/**
+ * Atomic operations through handles. The locations are plain Rust
+ * variables, so no heap is needed.
+ */
+
+fn iref_to<T>(ty: &P<MuType>, loc: &mut T) -> APIHandle {
+    APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::IRef(ty.clone(), Address::from_mut_ptr(loc as *mut T)),
+    }
+}
+
+fn iref_at(ty: &P<MuType>, addr: Address) -> APIHandle {
+    APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::IRef(ty.clone(), addr),
+    }
+}
+
+fn int(val: u64, len: usize) -> APIHandle {
+    APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::Int(val, len),
+    }
+}
+
+#[test]
+fn test_cmpxchg_int64() {
+    let vm = VM::new();
+    let mut x: u64 = 42;
+    let loc = iref_to(&UINT64_TYPE, &mut x);
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &int(42, 64),
+        &int(100, 64),
+    );
+    assert!(succ);
+    assert_eq!(old.v.as_int(), 42);
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::Relaxed,
+        false,
+        &loc,
+        &int(42, 64),
+        &int(7, 64),
+    );
+    assert!(!succ);
+    assert_eq!(old.v.as_int(), 100);
+
+    assert_eq!(x, 100);
+}
+
+#[test]
+fn test_cmpxchg_int8_keeps_neighbours() {
+    let vm = VM::new();
+    let mut x: u64 = 0x0807060504030201;
+    let loc = iref_at(
+        &UINT8_TYPE,
+        Address::from_mut_ptr(&mut x as *mut u64) + 3usize,
+    );
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::AcqRel,
+        MemoryOrder::Acquire,
+        false,
+        &loc,
+        &int(4, 8),
+        &int(0xff, 8),
+    );
+    assert!(succ);
+    assert_eq!(old.v.as_int(), 4);
+
+    assert_eq!(x, 0x08070605ff030201);
+}
+
+#[test]
+fn test_cmpxchg_ref() {
+    let vm = VM::new();
+    let mut x: usize = 0;
+    let ty = P(MuType::new(vm.next_id(), MuType_::Ref(UINT64_TYPE.clone())));
+    let loc = iref_to(&ty, &mut x);
+
+    let null = APIHandle {
+        id: 0,
+        v: APIHandleValue::Ref(UINT64_TYPE.clone(), unsafe { Address::zero() }),
+    };
+    let some_ref = APIHandle {
+        id: 0,
+        v: APIHandleValue::Ref(UINT64_TYPE.clone(), unsafe { Address::from_usize(0x1000) }),
+    };
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &null,
+        &some_ref,
+    );
+    assert!(succ);
+    assert!(old.v.as_ref().1.is_zero());
+    assert_eq!(x, 0x1000);
+
+    // a weak cmpxchg may fail spuriously, so retry it until it succeeds
+    loop {
+        let (old, succ) = vm.handle_cmpxchg(
+            MemoryOrder::SeqCst,
+            MemoryOrder::SeqCst,
+            true,
+            &loc,
+            &some_ref,
+            &null,
+        );
+        assert_eq!(old.v.as_ref().1.as_usize(), 0x1000);
+        if succ {
+            break;
+        }
+    }
+    assert_eq!(x, 0);
+}
+
+#[test]
+fn test_atomicrmw_int32() {
+    let vm = VM::new();
+    let mut x: u64 = 0xdeadbeef0000000a;
+    let loc = iref_to(&UINT32_TYPE, &mut x);
+    // the lower half of x
+    let lo = |x: u64| x & 0xffffffff;
+
+    let old = vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::ADD, &loc, &int(5, 32));
+    assert_eq!(old.v.as_int(), 10);
+    assert_eq!(lo(x), 15);
+
+    let old = vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::SUB, &loc, &int(20, 32));
+    assert_eq!(old.v.as_int(), 15);
+    assert_eq!(lo(x), 0xfffffffb);
+
+    let old = vm.handle_atomicrmw(MemoryOrder::Relaxed, AtomicRMWOp::XCHG, &loc, &int(3, 32));
+    assert_eq!(old.v.as_int(), 0xfffffffb);
+    assert_eq!(lo(x), 3);
+
+    vm.handle_atomicrmw(MemoryOrder::Relaxed, AtomicRMWOp::NAND, &loc, &int(1, 32));
+    assert_eq!(lo(x), 0xfffffffe);
+
+    assert_eq!(x >> 32, 0xdeadbeef);
+}
+
+#[test]
+fn test_atomicrmw_min_max() {
+    let vm = VM::new();
+    let mut x: u16 = 0xfffe; // -2 as a signed int<16>
+    let loc = iref_to(&UINT16_TYPE, &mut x);
+
+    vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::MAX, &loc, &int(1, 16));
+    assert_eq!(x, 1);
+
+    vm.handle_atomicrmw(
+        MemoryOrder::SeqCst,
+        AtomicRMWOp::MIN,
+        &loc,
+        &int(0xffff, 16),
+    );
+    assert_eq!(x, 0xffff);
+
+    vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::UMIN, &loc, &int(2, 16));
+    assert_eq!(x, 2);
+
+    vm.handle_atomicrmw(
+        MemoryOrder::SeqCst,
+        AtomicRMWOp::UMAX,
+        &loc,
+        &int(0x8000, 16),
+    );
+    assert_eq!(x, 0x8000);
+}
+
+#[test]
+fn test_cmpxchg_weakref() {
+    let vm = VM::new();
+    let mut x: usize = 0;
+    let ty = P(MuType::new(
+        vm.next_id(),
+        MuType_::weakref(UINT64_TYPE.clone()),
+    ));
+    let loc = iref_to(&ty, &mut x);
+
+    let null = APIHandle {
+        id: 0,
+        v: APIHandleValue::Ref(UINT64_TYPE.clone(), unsafe { Address::zero() }),
+    };
+    let some_ref = APIHandle {
+        id: 0,
+        v: APIHandleValue::Ref(UINT64_TYPE.clone(), unsafe { Address::from_usize(0x1000) }),
+    };
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &null,
+        &some_ref,
+    );
+    assert!(succ);
+    assert!(old.v.as_ref().1.is_zero());
+    assert_eq!(x, 0x1000);
+
+    // as LOAD, the old value of a weakref is a strong ref
+    let old = vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::XCHG, &loc, &null);
+    assert_eq!(old.v.as_ref().1.as_usize(), 0x1000);
+    assert_eq!(x, 0);
+}
+
+#[test]
+fn test_cmpxchg_funcref() {
+    let vm = VM::new();
+    funcsig!    ((vm) sig = () -> ());
+    funcdecl!   ((vm) <sig> f1);
+    funcdecl!   ((vm) <sig> f2);
+
+    let mut x: u64 = 0;
+    let ty = P(MuType::new(vm.next_id(), MuType_::funcref(sig.clone())));
+    let loc = iref_to(&ty, &mut x);
+
+    let f1 = vm.handle_from_func(f1.id());
+    let f2 = vm.handle_from_func(f2.id());
+    vm.handle_store(MemoryOrder::NotAtomic, &loc, &f1);
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &f2,
+        &f1,
+    );
+    assert!(!succ);
+    assert_eq!(old.v.as_funcref(), f1.v.as_funcref());
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &f1,
+        &f2,
+    );
+    assert!(succ);
+    assert_eq!(old.v.as_funcref(), f1.v.as_funcref());
+
+    let old = vm.handle_atomicrmw(MemoryOrder::SeqCst, AtomicRMWOp::XCHG, &loc, &f1);
+    assert_eq!(old.v.as_funcref(), f2.v.as_funcref());
+    assert_eq!(
+        vm.handle_load(MemoryOrder::SeqCst, &loc).v.as_funcref(),
+        f1.v.as_funcref()
+    );
+}
+
+#[test]
+fn test_cmpxchg_threadref() {
+    let vm = VM::new();
+    let mut x: usize = 0x1000;
+    let ty = P(MuType::new(vm.next_id(), MuType_::ThreadRef));
+    let loc = iref_to(&ty, &mut x);
+
+    let threadref = |addr: usize| APIHandle {
+        id: 0,
+        v: APIHandleValue::ThreadRef(unsafe { Address::from_usize(addr) }),
+    };
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &threadref(0x1000),
+        &threadref(0x2000),
+    );
+    assert!(succ);
+    assert_eq!(old.v.as_threadref().as_usize(), 0x1000);
+    assert_eq!(x, 0x2000);
+
+    let old = vm.handle_atomicrmw(
+        MemoryOrder::SeqCst,
+        AtomicRMWOp::XCHG,
+        &loc,
+        &threadref(0x3000),
+    );
+    assert_eq!(old.v.as_threadref().as_usize(), 0x2000);
+    assert_eq!(x, 0x3000);
+}
+
+#[test]
+fn test_cmpxchg_stackref() {
+    let vm = VM::new();
+    let mut x: usize = 0x1000;
+    let ty = P(MuType::new(vm.next_id(), MuType_::StackRef));
+    let loc = iref_to(&ty, &mut x);
+
+    let stackref = |addr: usize| APIHandle {
+        id: 0,
+        v: APIHandleValue::StackRef(unsafe { Address::from_usize(addr) }),
+    };
+
+    let (old, succ) = vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &stackref(0x2000),
+        &stackref(0x3000),
+    );
+    assert!(!succ);
+    assert_eq!(old.v.as_stackref().as_usize(), 0x1000);
+    assert_eq!(x, 0x1000);
+
+    let old = vm.handle_atomicrmw(
+        MemoryOrder::SeqCst,
+        AtomicRMWOp::XCHG,
+        &loc,
+        &stackref(0x3000),
+    );
+    assert_eq!(old.v.as_stackref().as_usize(), 0x1000);
+    assert_eq!(x, 0x3000);
+}
+
+#[test]
+#[should_panic(expected = "larger than 64 bits")]
+fn test_cmpxchg_int128() {
+    let vm = VM::new();
+    let mut x: [u64; 2] = [0, 0];
+    let ty = P(MuType::new(vm.next_id(), MuType_::Int(128)));
+    let loc = iref_to(&ty, &mut x);
+
+    vm.handle_cmpxchg(
+        MemoryOrder::SeqCst,
+        MemoryOrder::SeqCst,
+        false,
+        &loc,
+        &int(0, 64),
+        &int(1, 64),
+    );
+}
+
+#[test]
+fn test_fence() {
+    // message passing: the release fence orders the data store before the flag store,
+    // and the acquire fence orders the flag load before the data load
+    let vm = Arc::new(VM::new());
+    let cells: Box<[u64; 2]> = Box::new([0, 0]);
+    let data = Address::from_ptr(&cells[0] as *const u64).as_usize();
+    let flag = Address::from_ptr(&cells[1] as *const u64).as_usize();
+
+    let writer = {
+        let vm = vm.clone();
+        thread::spawn(move || {
+            let data = iref_at(&UINT64_TYPE, unsafe { Address::from_usize(data) });
+            let flag = iref_at(&UINT64_TYPE, unsafe { Address::from_usize(flag) });
+
+            vm.handle_store(MemoryOrder::NotAtomic, &data, &int(42, 64));
+            vm.handle_fence(MemoryOrder::Release);
+            vm.handle_store(MemoryOrder::Relaxed, &flag, &int(1, 64));
+        })
+    };
+
+    let data = iref_at(&UINT64_TYPE, unsafe { Address::from_usize(data) });
+    let flag = iref_at(&UINT64_TYPE, unsafe { Address::from_usize(flag) });
+    while vm.handle_load(MemoryOrder::Relaxed, &flag).v.as_int() == 0 {
+        thread::yield_now();
+    }
+    vm.handle_fence(MemoryOrder::Acquire);
+    assert_eq!(vm.handle_load(MemoryOrder::NotAtomic, &data).v.as_int(), 42);
+
+    writer.join().unwrap();
+
+    // the other orders are accepted (a relaxed fence has no effect)
+    vm.handle_fence(MemoryOrder::Relaxed);
+    vm.handle_fence(MemoryOrder::SeqCst);
+    assert_eq!(cells[0], 42);
+    assert_eq!(cells[1], 1);
+}
@@ -0,0 +1,107 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mu::vm::api::api_c::*;
+use mu::vm::api::mu_fastimpl_new_with_opts;
+
+use std::ffi::CString;
+use std::ptr;
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_force_gc_through_muctx() {
+    let opts = CString::new("init_mu --gc-immixspace-size=4194304").unwrap();
+    let mvm = mu_fastimpl_new_with_opts(opts.as_ptr());
+
+    unsafe {
+        let ctx = ((*mvm).new_context)(mvm);
+
+        // .typedef @i64 = int<64>
+        let b = ((*ctx).new_ir_builder)(ctx);
+        let i64_id = ((*b).gen_sym)(b, ptr::null());
+        ((*b).new_type_int)(b, i64_id, 64);
+        ((*b).load)(b);
+
+        // the client keeps an object (through a handle) across the collection
+        let obj = ((*ctx).new_fixed)(ctx, i64_id);
+        let iref = ((*ctx).get_iref)(ctx, obj);
+        let v = ((*ctx).handle_from_sint64)(ctx, 42, 64);
+        ((*ctx).store)(ctx, CMU_ORD_NOT_ATOMIC, iref, v);
+
+        let collections = ((*ctx).get_gc_stat)(ctx, CMU_GC_STAT_COLLECTIONS);
+        let major_collections = ((*ctx).get_gc_stat)(ctx, CMU_GC_STAT_MAJOR_COLLECTIONS);
+
+        // the test thread is not a Mu thread
+        ((*ctx).force_gc)(ctx);
+
+        assert_eq!(
+            ((*ctx).get_gc_stat)(ctx, CMU_GC_STAT_COLLECTIONS),
+            collections + 1
+        );
+        assert_eq!(
+            ((*ctx).get_gc_stat)(ctx, CMU_GC_STAT_MAJOR_COLLECTIONS),
+            major_collections + 1
+        );
+        assert!(((*ctx).get_gc_stat)(ctx, CMU_GC_STAT_HEAP_SIZE) > 0);
+
+        // the object is alive, and the handles still refer to it
+        let loaded = ((*ctx).load)(ctx, CMU_ORD_NOT_ATOMIC, iref);
+        assert_eq!(((*ctx).handle_to_sint64)(ctx, loaded), 42);
+        let iref_after_gc = ((*ctx).get_iref)(ctx, obj);
+        let loaded = ((*ctx).load)(ctx, CMU_ORD_NOT_ATOMIC, iref_after_gc);
+        assert_eq!(((*ctx).handle_to_sint64)(ctx, loaded), 42);
+
+        ((*ctx).close_context)(ctx);
+    }
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_new_hybrid_out_of_memory() {
+    let opts =
+        CString::new("init_mu --gc-immixspace-size=4194304 --gc-lospace-size=4194304").unwrap();
+    let mvm = mu_fastimpl_new_with_opts(opts.as_ptr());
+
+    unsafe {
+        let ctx = ((*mvm).new_context)(mvm);
+
+        // .typedef @i8 = int<8>
+        // .typedef @bytes = hybrid<@i8>
+        // .typedef @ref_bytes = ref<@bytes>
+        // .const @NULL_bytes <@ref_bytes> = NULL
+        let b = ((*ctx).new_ir_builder)(ctx);
+        let i8_id = ((*b).gen_sym)(b, ptr::null());
+        ((*b).new_type_int)(b, i8_id, 8);
+        let bytes_id = ((*b).gen_sym)(b, ptr::null());
+        ((*b).new_type_hybrid)(b, bytes_id, ptr::null_mut(), 0, i8_id);
+        let ref_bytes_id = ((*b).gen_sym)(b, ptr::null());
+        ((*b).new_type_ref)(b, ref_bytes_id, bytes_id);
+        let null_id = ((*b).gen_sym)(b, ptr::null());
+        ((*b).new_const_null)(b, null_id, ref_bytes_id);
+        ((*b).load)(b);
+        let null = ((*ctx).handle_from_const)(ctx, null_id);
+
+        // the object is larger than the large object space: we get null instead of a panic
+        let len = ((*ctx).handle_from_uint64)(ctx, 8 << 20, 64);
+        let obj = ((*ctx).new_hybrid)(ctx, bytes_id, len);
+        assert!(((*ctx).ref_eq)(ctx, obj, null) != 0);
+
+        // the heap still works after the failure
+        let len = ((*ctx).handle_from_uint64)(ctx, 1 << 20, 64);
+        let obj = ((*ctx).new_hybrid)(ctx, bytes_id, len);
+        assert!(((*ctx).ref_eq)(ctx, obj, null) == 0);
+
+        ((*ctx).close_context)(ctx);
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mu::ast::types::*;
+use mu::utils::Address;
+use mu::vm::handle::*;
+use mu::vm::*;
+
+fn handle(v: APIHandleValue) -> APIHandle {
+    APIHandle {
+        id: 0, // arbitrary
+        v: v,
+    }
+}
+
+fn addr(val: usize) -> Address {
+    unsafe { Address::from_usize(val) }
+}
+
+#[test]
+fn test_ref_eq() {
+    let vm = VM::new();
+
+    let a = handle(APIHandleValue::Ref(UINT64_TYPE.clone(), addr(0x1000)));
+    let b = handle(APIHandleValue::Ref(UINT64_TYPE.clone(), addr(0x1000)));
+    let c = handle(APIHandleValue::Ref(UINT64_TYPE.clone(), addr(0x2000)));
+    assert!(vm.handle_ref_eq(&a, &b));
+    assert!(!vm.handle_ref_eq(&a, &c));
+
+    // null references are equal regardless of their referent type
+    let null1 = handle(APIHandleValue::Ref(VOID_TYPE.clone(), addr(0)));
+    let null2 = handle(APIHandleValue::Ref(UINT64_TYPE.clone(), addr(0)));
+    assert!(vm.handle_ref_eq(&null1, &null2));
+}
+
+#[test]
+fn test_ref_eq_opaque() {
+    let vm = VM::new();
+
+    let f1 = handle(APIHandleValue::FuncRef(42));
+    let f2 = handle(APIHandleValue::FuncRef(42));
+    let f3 = handle(APIHandleValue::FuncRef(43));
+    assert!(vm.handle_ref_eq(&f1, &f2));
+    assert!(!vm.handle_ref_eq(&f1, &f3));
+
+    let s1 = handle(APIHandleValue::StackRef(addr(0x1000)));
+    let s2 = handle(APIHandleValue::StackRef(addr(0x2000)));
+    assert!(vm.handle_ref_eq(&s1, &s1.clone()));
+    assert!(!vm.handle_ref_eq(&s1, &s2));
+
+    let t1 = handle(APIHandleValue::ThreadRef(addr(0x1000)));
+    let t2 = handle(APIHandleValue::ThreadRef(addr(0x1000)));
+    assert!(vm.handle_ref_eq(&t1, &t2));
+}
+
+#[test]
+fn test_ref_ult() {
+    let vm = VM::new();
+
+    let lo = handle(APIHandleValue::IRef(UINT64_TYPE.clone(), addr(0x1000)));
+    let hi = handle(APIHandleValue::IRef(UINT64_TYPE.clone(), addr(0x1008)));
+    assert!(vm.handle_ref_ult(&lo, &hi));
+    assert!(!vm.handle_ref_ult(&hi, &lo));
+    assert!(!vm.handle_ref_ult(&lo, &lo.clone()));
+}
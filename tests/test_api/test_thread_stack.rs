@@ -0,0 +1,213 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libc;
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::linkutils::aot;
+use mu::runtime::stack_scan;
+use mu::runtime::thread::{MuStack, MuThread};
+use mu::utils::Address;
+use mu::vm::handle::*;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+fn stackref(stack: Box<MuStack>, vm: &VM) -> APIHandle {
+    stack_scan::register_stack(&stack, vm);
+    APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::StackRef(Address::from_mut_ptr(Box::into_raw(stack))),
+    }
+}
+
+/// compiles a function, and loads it into current process (with its symbol visible to the VM,
+/// so we can create stacks for it)
+fn compile_and_load(
+    vm: &VM,
+    fnc_name: &'static str,
+    libname: &'static str,
+) -> libloading::os::unix::Library {
+    let compiler = Compiler::new(CompilerPolicy::default(), vm);
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&vm.id_of(fnc_name)).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+        compiler.compile(&mut func_ver);
+    }
+    backend::emit_context(vm);
+
+    let dylib = aot::link_dylib(vec![Mu(fnc_name)], &linkutils::get_dylib_name(libname), vm);
+    libloading::os::unix::Library::open(Some(dylib.as_os_str()), libc::RTLD_NOW | libc::RTLD_GLOBAL)
+        .unwrap()
+}
+
+/// starts a thread on a new stack of thread_store, which stores 42 to cell and exits.
+/// Returns the stackref and threadref handles after the thread has exited
+fn run_thread_store(libname: &'static str, cell: &mut u64) -> (Arc<VM>, APIHandle, APIHandle) {
+    let vm = Arc::new(thread_store());
+    let _lib = compile_and_load(&vm, "thread_store", libname);
+
+    let func = vm.handle_from_func(vm.id_of("thread_store"));
+    let stack = vm.handle_new_stack(&func);
+    let p = vm.handle_from_uptr(vm.id_of("uptr_int64"), Address::from_mut_ptr(cell));
+    let v = vm.handle_from_uint64(42, 64);
+    let thread = vm.handle_new_thread_normal(&stack, None, vec![&p, &v], vm.clone());
+
+    vm.join_mu_threads();
+    (vm, *stack, *thread)
+}
+
+fn thread_store() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) uptr_int64 = mu_uptr(int64));
+
+    funcsig!    ((vm) sig = (uptr_int64, int64) -> ());
+    funcdecl!   ((vm) <sig> thread_store);
+    funcdef!    ((vm) <sig> thread_store VERSION thread_store_v1);
+
+    // blk_entry(p, v):
+    block!      ((vm, thread_store_v1) blk_entry);
+    ssa!        ((vm, thread_store_v1) <uptr_int64> p);
+    ssa!        ((vm, thread_store_v1) <int64> v);
+
+    // STORE p v
+    inst!       ((vm, thread_store_v1) blk_entry_store:
+        STORE p v (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // THREADEXIT
+    inst!       ((vm, thread_store_v1) blk_entry_threadexit:
+        THREADEXIT
+    );
+
+    define_block!   ((vm, thread_store_v1) blk_entry(p, v) {
+        blk_entry_store,
+        blk_entry_threadexit
+    });
+
+    define_func_ver!((vm) thread_store_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_new_thread_normal_and_join() {
+    let mut cell: u64 = 0;
+    run_thread_store("thread_store_join", &mut cell);
+
+    assert_eq!(cell, 42);
+}
+
+#[test]
+#[should_panic(expected = "has exited")]
+fn test_get_threadlocal_of_exited_thread() {
+    let mut cell: u64 = 0;
+    let (vm, _, thread) = run_thread_store("thread_store_exited", &mut cell);
+
+    vm.handle_get_threadlocal(&thread);
+}
+
+#[test]
+#[should_panic(expected = "has been killed")]
+fn test_kill_stack_of_exited_thread() {
+    let mut cell: u64 = 0;
+    let (vm, stack, _) = run_thread_store("thread_store_kill", &mut cell);
+
+    // the stack was released with the thread, so we should not free it again
+    vm.handle_kill_stack(&stack);
+}
+
+#[test]
+#[should_panic(expected = "has been killed")]
+fn test_new_thread_exceptional_on_killed_stack() {
+    let vm = Arc::new(VM::new());
+
+    let stack = Box::new(MuStack::new(vm.next_id(), unsafe { Address::zero() }, 0));
+    let handle = stackref(stack, &vm);
+    vm.handle_kill_stack(&handle);
+
+    let exception = APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::Ref(REF_VOID_TYPE.clone(), unsafe { Address::zero() }),
+    };
+    vm.handle_new_thread_exceptional(&handle, None, &exception, vm.clone());
+}
+
+#[test]
+fn test_kill_unbound_stack() {
+    let vm = VM::new();
+
+    // the stack never runs, so its entry does not matter
+    let stack = Box::new(MuStack::new(vm.next_id(), unsafe { Address::zero() }, 0));
+    let handle = stackref(stack, &vm);
+
+    assert!(vm.handle_ref_eq(&handle, &handle.clone()));
+    vm.handle_kill_stack(&handle);
+}
+
+#[test]
+fn test_join_without_threads() {
+    let vm = VM::new();
+
+    // returns immediately when no Mu thread was started
+    vm.join_mu_threads();
+}
+
+#[test]
+fn test_get_set_threadlocal() {
+    let vm = Arc::new(VM::new());
+
+    unsafe {
+        MuThread::current_thread_as_mu_thread(Address::from_usize(0x1000), vm.clone());
+    }
+
+    let thread = APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::ThreadRef(Address::from_ref(MuThread::current())),
+    };
+
+    let tl = vm.handle_get_threadlocal(&thread);
+    assert_eq!(tl.v.as_ref().1, unsafe { Address::from_usize(0x1000) });
+
+    let new_tl = APIHandle {
+        id: 0, // arbitrary
+        v: APIHandleValue::Ref(mu::ast::types::VOID_TYPE.clone(), unsafe {
+            Address::from_usize(0x2000)
+        }),
+    };
+    vm.handle_set_threadlocal(&thread, &new_tl);
+
+    assert_eq!(MuThread::current().user_tls, unsafe {
+        Address::from_usize(0x2000)
+    });
+    let tl = vm.handle_get_threadlocal(&thread);
+    assert_eq!(tl.v.as_ref().1, unsafe { Address::from_usize(0x2000) });
+
+    unsafe { MuThread::cleanup_current_mu_thread() };
+}
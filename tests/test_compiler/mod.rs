@@ -18,16 +18,25 @@ mod test_call;
 mod test_compiler;
 mod test_controlflow;
 mod test_convop;
+mod test_dce;
+mod test_escape_analysis;
 mod test_exception;
 mod test_floatingpoint;
+mod test_gc;
 mod test_global;
+mod test_gvn;
 mod test_inline;
+mod test_inst_sched;
 mod test_instsel;
 mod test_int;
 mod test_int128;
+mod test_licm;
 mod test_mem_inst;
 mod test_misc;
 mod test_opt;
+mod test_peephole;
 mod test_pre_instsel;
+mod test_profile;
 mod test_regalloc;
+mod test_sccp;
 mod test_thread;
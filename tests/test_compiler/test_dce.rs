@@ -0,0 +1,269 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::utils::LinkedHashMap;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+/// returns the block content
+fn block_content(func_ver: &MuFunctionVersion, block: MuID) -> &BlockContent {
+    let content = func_ver.content.as_ref().unwrap();
+    content.get_block(block).content.as_ref().unwrap()
+}
+
+#[test]
+fn test_dce_loop() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(dce_loop());
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::DCE::new())]), &vm);
+
+    let func_id = vm.id_of("dce_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+    // nothing branches to this block
+    assert!(!content.blocks.contains_key(&vm.id_of("blk_dead")));
+
+    // %d is only passed around the loop
+    let head = block_content(&func_ver, vm.id_of("blk_head"));
+    assert_eq!(head.args.len(), 2);
+    let body = block_content(&func_ver, vm.id_of("blk_body"));
+    assert_eq!(body.args.len(), 2);
+    assert_eq!(body.body.len(), 2);
+
+    // the branches no longer pass %d
+    let branch = body.body.last().unwrap().as_inst();
+    match branch.v {
+        Instruction_::Branch1(ref dest) => {
+            assert_eq!(dest.args.len(), 2);
+            assert_eq!(branch.ops.len(), 2);
+        }
+        _ => panic!("expect a BRANCH, found {}", branch),
+    }
+    let branch2 = head.body.last().unwrap().as_inst();
+    match branch2.v {
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => {
+            assert_eq!(true_dest.args.len(), 2);
+            assert_eq!(false_dest.args.len(), 1);
+            assert_eq!(branch2.ops.len(), 3);
+        }
+        _ => panic!("expect a BRANCH2, found {}", branch2),
+    }
+
+    let lib = linkutils::aot::compile_fnc("dce_loop", &dce_loop);
+    unsafe {
+        let dce_loop: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"dce_loop").unwrap();
+
+        let res = dce_loop(5);
+        println!("dce_loop(5) = {}", res);
+        assert_eq!(res, 5);
+    }
+}
+
+fn dce_loop() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_3 = Constant::Int(3));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> dce_loop);
+    funcdef!    ((vm) <sig> dce_loop VERSION dce_loop_v1);
+
+    // %entry(%n):
+    //   BRANCH %head(0, 1, %n)
+    block!      ((vm, dce_loop_v1) blk_entry);
+    ssa!        ((vm, dce_loop_v1) <int64> n);
+    consta!     ((vm, dce_loop_v1) int64_0_local = int64_0);
+    consta!     ((vm, dce_loop_v1) int64_1_local = int64_1);
+    consta!     ((vm, dce_loop_v1) int64_3_local = int64_3);
+
+    block!      ((vm, dce_loop_v1) blk_head);
+    inst!       ((vm, dce_loop_v1) blk_entry_branch:
+        BRANCH blk_head (int64_0_local, int64_1_local, n)
+    );
+
+    define_block!((vm, dce_loop_v1) blk_entry(n) {
+        blk_entry_branch
+    });
+
+    // %head(%i, %d, %hn):
+    //   %c = SLT %i %hn
+    //   BRANCH2 %c %body(%i, %d, %hn) %exit(%i)
+    ssa!        ((vm, dce_loop_v1) <int64> i);
+    ssa!        ((vm, dce_loop_v1) <int64> d);
+    ssa!        ((vm, dce_loop_v1) <int64> hn);
+    ssa!        ((vm, dce_loop_v1) <int1> c);
+    inst!       ((vm, dce_loop_v1) blk_head_cmp:
+        c = CMPOP (CmpOp::SLT) i hn
+    );
+
+    block!      ((vm, dce_loop_v1) blk_body);
+    block!      ((vm, dce_loop_v1) blk_exit);
+    inst!       ((vm, dce_loop_v1) blk_head_branch2:
+        BRANCH2 (c, i, d, hn)
+            IF (OP 0)
+            THEN blk_body (vec![1, 2, 3]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!((vm, dce_loop_v1) blk_head(i, d, hn) {
+        blk_head_cmp, blk_head_branch2
+    });
+
+    // %body(%bi, %bd, %bn):
+    //   %bd2 = MUL %bd 3
+    //   %bi2 = ADD %bi 1
+    //   BRANCH %head(%bi2, %bd2, %bn)
+    ssa!        ((vm, dce_loop_v1) <int64> bi);
+    ssa!        ((vm, dce_loop_v1) <int64> bd);
+    ssa!        ((vm, dce_loop_v1) <int64> bn);
+    ssa!        ((vm, dce_loop_v1) <int64> bd2);
+    ssa!        ((vm, dce_loop_v1) <int64> bi2);
+    inst!       ((vm, dce_loop_v1) blk_body_mul:
+        bd2 = BINOP (BinOp::Mul) bd int64_3_local
+    );
+    inst!       ((vm, dce_loop_v1) blk_body_add:
+        bi2 = BINOP (BinOp::Add) bi int64_1_local
+    );
+    inst!       ((vm, dce_loop_v1) blk_body_branch:
+        BRANCH blk_head (bi2, bd2, bn)
+    );
+
+    define_block!((vm, dce_loop_v1) blk_body(bi, bd, bn) {
+        blk_body_mul, blk_body_add, blk_body_branch
+    });
+
+    // %exit(%ex):
+    //   RET %ex
+    ssa!        ((vm, dce_loop_v1) <int64> ex);
+    inst!       ((vm, dce_loop_v1) blk_exit_ret:
+        RET (ex)
+    );
+
+    define_block!((vm, dce_loop_v1) blk_exit(ex) {
+        blk_exit_ret
+    });
+
+    // %dead():
+    //   BRANCH %exit(0)
+    block!      ((vm, dce_loop_v1) blk_dead);
+    inst!       ((vm, dce_loop_v1) blk_dead_branch:
+        BRANCH blk_exit (int64_0_local)
+    );
+
+    define_block!((vm, dce_loop_v1) blk_dead() {
+        blk_dead_branch
+    });
+
+    define_func_ver!((vm) dce_loop_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit, blk_dead
+    });
+
+    vm
+}
+
+#[test]
+fn test_dce_side_effect() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(dce_side_effect());
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::DCE::new())]), &vm);
+
+    let func_id = vm.id_of("dce_side_effect");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the unused NEW is kept, the unused ADD is removed
+    let entry = block_content(&func_ver, vm.id_of("blk_entry"));
+    assert_eq!(entry.body.len(), 2);
+    match entry.body[0].as_inst().v {
+        Instruction_::New(_) => {}
+        _ => panic!("expect a NEW, found {}", entry.body[0]),
+    }
+}
+
+fn dce_side_effect() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64     = mu_int(64));
+    typedef!    ((vm) ref_int64 = mu_ref(int64));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> dce_side_effect);
+    funcdef!    ((vm) <sig> dce_side_effect VERSION dce_side_effect_v1);
+
+    // %entry(%a):
+    //   %obj = NEW <int64>
+    //   %x = ADD %a %a
+    //   RET %a
+    block!      ((vm, dce_side_effect_v1) blk_entry);
+    ssa!        ((vm, dce_side_effect_v1) <int64> a);
+    ssa!        ((vm, dce_side_effect_v1) <ref_int64> obj);
+    ssa!        ((vm, dce_side_effect_v1) <int64> x);
+    inst!       ((vm, dce_side_effect_v1) blk_entry_new:
+        obj = NEW <int64>
+    );
+    inst!       ((vm, dce_side_effect_v1) blk_entry_add:
+        x = BINOP (BinOp::Add) a a
+    );
+    inst!       ((vm, dce_side_effect_v1) blk_entry_ret:
+        RET (a)
+    );
+
+    define_block!((vm, dce_side_effect_v1) blk_entry(a) {
+        blk_entry_new, blk_entry_add, blk_entry_ret
+    });
+
+    define_func_ver!((vm) dce_side_effect_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
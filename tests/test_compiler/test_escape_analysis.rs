@@ -0,0 +1,393 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+/// returns the instructions of the function
+fn all_insts(func_ver: &MuFunctionVersion) -> Vec<Instruction> {
+    let content = func_ver.content.as_ref().unwrap();
+    let mut ret = vec![];
+    for block in content.blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            ret.push(node.as_inst().clone());
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_ea_scalar_replacement() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(ea_box());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::EscapeAnalysis::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("ea_box");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the object is gone, its value is passed to the join as a parameter
+    for inst in all_insts(&func_ver) {
+        match inst.v {
+            Instruction_::New(_)
+            | Instruction_::GetIRef(_)
+            | Instruction_::Load { .. }
+            | Instruction_::Store { .. } => panic!("unexpected {}", inst),
+            _ => {}
+        }
+    }
+
+    let content = func_ver.content.as_ref().unwrap();
+    let blk_exit = content.get_block(vm.id_of("blk_exit"));
+    assert_eq!(blk_exit.content.as_ref().unwrap().args.len(), 1);
+
+    let lib = linkutils::aot::compile_fnc("ea_box", &ea_box);
+    unsafe {
+        let ea_box: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"ea_box").unwrap();
+
+        let res = ea_box(3, 4);
+        println!("ea_box(3, 4) = {}", res);
+        assert_eq!(res, 4);
+
+        let res = ea_box(5, 4);
+        println!("ea_box(5, 4) = {}", res);
+        assert_eq!(res, 5);
+    }
+}
+
+fn ea_box() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1       = mu_int(1));
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) ref_int64  = mu_ref(int64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+
+    funcsig!    ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> ea_box);
+    funcdef!    ((vm) <sig> ea_box VERSION ea_box_v1);
+
+    // %entry(%a, %b):
+    //   %obj = NEW <int64>
+    //   %obj_iref = GETIREF %obj
+    //   STORE %obj_iref %a
+    //   %c = SLT %a %b
+    //   BRANCH2 %c %true() %exit()
+    block!      ((vm, ea_box_v1) blk_entry);
+    ssa!        ((vm, ea_box_v1) <int64> a);
+    ssa!        ((vm, ea_box_v1) <int64> b);
+
+    ssa!        ((vm, ea_box_v1) <ref_int64> obj);
+    inst!       ((vm, ea_box_v1) blk_entry_new:
+        obj = NEW <int64>
+    );
+
+    ssa!        ((vm, ea_box_v1) <iref_int64> obj_iref);
+    inst!       ((vm, ea_box_v1) blk_entry_getiref:
+        obj_iref = GETIREF obj
+    );
+
+    inst!       ((vm, ea_box_v1) blk_entry_store:
+        STORE obj_iref a (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    ssa!        ((vm, ea_box_v1) <int1> c);
+    inst!       ((vm, ea_box_v1) blk_entry_cmp:
+        c = CMPOP (CmpOp::SLT) a b
+    );
+
+    block!      ((vm, ea_box_v1) blk_true);
+    block!      ((vm, ea_box_v1) blk_exit);
+    inst!       ((vm, ea_box_v1) blk_entry_branch2:
+        BRANCH2 (c)
+            IF (OP 0)
+            THEN blk_true (vec![]) WITH 0.5f32,
+            ELSE blk_exit (vec![])
+    );
+
+    define_block!((vm, ea_box_v1) blk_entry(a, b) {
+        blk_entry_new, blk_entry_getiref, blk_entry_store, blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %true():
+    //   %obj_iref2 = GETIREF %obj
+    //   STORE %obj_iref2 %b
+    //   BRANCH %exit()
+    ssa!        ((vm, ea_box_v1) <iref_int64> obj_iref2);
+    inst!       ((vm, ea_box_v1) blk_true_getiref:
+        obj_iref2 = GETIREF obj
+    );
+    inst!       ((vm, ea_box_v1) blk_true_store:
+        STORE obj_iref2 b (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, ea_box_v1) blk_true_branch:
+        BRANCH blk_exit ()
+    );
+
+    define_block!((vm, ea_box_v1) blk_true() {
+        blk_true_getiref, blk_true_store, blk_true_branch
+    });
+
+    // %exit():
+    //   %obj_iref3 = GETIREF %obj
+    //   %r = LOAD %obj_iref3
+    //   RET %r
+    ssa!        ((vm, ea_box_v1) <iref_int64> obj_iref3);
+    inst!       ((vm, ea_box_v1) blk_exit_getiref:
+        obj_iref3 = GETIREF obj
+    );
+    ssa!        ((vm, ea_box_v1) <int64> r);
+    inst!       ((vm, ea_box_v1) blk_exit_load:
+        r = LOAD obj_iref3 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, ea_box_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, ea_box_v1) blk_exit() {
+        blk_exit_getiref, blk_exit_load, blk_exit_ret
+    });
+
+    define_func_ver!((vm) ea_box_v1 (entry: blk_entry) {
+        blk_entry, blk_true, blk_exit
+    });
+
+    vm
+}
+
+#[test]
+fn test_ea_escape() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(ea_escape());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::EscapeAnalysis::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("ea_escape");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the object is returned, it is kept on the heap
+    let insts = all_insts(&func_ver);
+    assert_eq!(insts.len(), 4);
+    match insts[0].v {
+        Instruction_::New(_) => {}
+        _ => panic!("expect a NEW, found {}", insts[0]),
+    }
+}
+
+fn ea_escape() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) ref_int64  = mu_ref(int64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+
+    funcsig!    ((vm) sig = (int64) -> (ref_int64));
+    funcdecl!   ((vm) <sig> ea_escape);
+    funcdef!    ((vm) <sig> ea_escape VERSION ea_escape_v1);
+
+    // %entry(%a):
+    //   %obj = NEW <int64>
+    //   %obj_iref = GETIREF %obj
+    //   STORE %obj_iref %a
+    //   RET %obj
+    block!      ((vm, ea_escape_v1) blk_entry);
+    ssa!        ((vm, ea_escape_v1) <int64> a);
+
+    ssa!        ((vm, ea_escape_v1) <ref_int64> obj);
+    inst!       ((vm, ea_escape_v1) blk_entry_new:
+        obj = NEW <int64>
+    );
+
+    ssa!        ((vm, ea_escape_v1) <iref_int64> obj_iref);
+    inst!       ((vm, ea_escape_v1) blk_entry_getiref:
+        obj_iref = GETIREF obj
+    );
+
+    inst!       ((vm, ea_escape_v1) blk_entry_store:
+        STORE obj_iref a (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    inst!       ((vm, ea_escape_v1) blk_entry_ret:
+        RET (obj)
+    );
+
+    define_block!((vm, ea_escape_v1) blk_entry(a) {
+        blk_entry_new, blk_entry_getiref, blk_entry_store, blk_entry_ret
+    });
+
+    define_func_ver!((vm) ea_escape_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_ea_stack_allocation() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(ea_array());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::EscapeAnalysis::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("ea_array");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the array is indexed with a variable, it is allocated on the stack
+    let insts = all_insts(&func_ver);
+    match insts[0].v {
+        Instruction_::AllocA(_) => {}
+        _ => panic!("expect an ALLOCA, found {}", insts[0]),
+    }
+    for inst in insts.iter() {
+        match inst.v {
+            Instruction_::New(_) | Instruction_::GetIRef(_) => {
+                panic!("unexpected {}", inst)
+            }
+            _ => {}
+        }
+    }
+
+    let lib = linkutils::aot::compile_fnc("ea_array", &ea_array);
+    unsafe {
+        let ea_array: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"ea_array").unwrap();
+
+        let res = ea_array(0, 7);
+        println!("ea_array(0, 7) = {}", res);
+        assert_eq!(res, 7);
+
+        // the other elements are zero
+        let res = ea_array(2, 7);
+        println!("ea_array(2, 7) = {}", res);
+        assert_eq!(res, 0);
+    }
+}
+
+fn ea_array() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) array_t    = mu_array(int64, 4));
+    typedef!    ((vm) ref_array  = mu_ref(array_t));
+    typedef!    ((vm) iref_array = mu_iref(array_t));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+
+    funcsig!    ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> ea_array);
+    funcdef!    ((vm) <sig> ea_array VERSION ea_array_v1);
+
+    // %entry(%i, %v):
+    //   %arr = NEW <array_t>
+    //   %arr_iref = GETIREF %arr
+    //   %elem_i = GETELEMIREF %arr_iref %i
+    //   STORE %elem_i %v
+    //   %elem_0 = GETELEMIREF %arr_iref 0
+    //   %r = LOAD %elem_0
+    //   RET %r
+    block!      ((vm, ea_array_v1) blk_entry);
+    ssa!        ((vm, ea_array_v1) <int64> i);
+    ssa!        ((vm, ea_array_v1) <int64> v);
+
+    ssa!        ((vm, ea_array_v1) <ref_array> arr);
+    inst!       ((vm, ea_array_v1) blk_entry_new:
+        arr = NEW <array_t>
+    );
+
+    ssa!        ((vm, ea_array_v1) <iref_array> arr_iref);
+    inst!       ((vm, ea_array_v1) blk_entry_getiref:
+        arr_iref = GETIREF arr
+    );
+
+    ssa!        ((vm, ea_array_v1) <iref_int64> elem_i);
+    inst!       ((vm, ea_array_v1) blk_entry_getelemiref_i:
+        elem_i = GETELEMIREF arr_iref i (is_ptr: false)
+    );
+
+    inst!       ((vm, ea_array_v1) blk_entry_store:
+        STORE elem_i v (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    ssa!        ((vm, ea_array_v1) <iref_int64> elem_0);
+    consta!     ((vm, ea_array_v1) int64_0_local = int64_0);
+    inst!       ((vm, ea_array_v1) blk_entry_getelemiref_0:
+        elem_0 = GETELEMIREF arr_iref int64_0_local (is_ptr: false)
+    );
+
+    ssa!        ((vm, ea_array_v1) <int64> r);
+    inst!       ((vm, ea_array_v1) blk_entry_load:
+        r = LOAD elem_0 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    inst!       ((vm, ea_array_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!((vm, ea_array_v1) blk_entry(i, v) {
+        blk_entry_new, blk_entry_getiref, blk_entry_getelemiref_i, blk_entry_store,
+        blk_entry_getelemiref_0, blk_entry_load, blk_entry_ret
+    });
+
+    define_func_ver!((vm) ea_array_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -0,0 +1,950 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+extern crate log;
+extern crate mu;
+
+use self::mu::ast::inst::*;
+use self::mu::ast::ir::*;
+use self::mu::ast::op::*;
+use self::mu::ast::types::*;
+use self::mu::compiler::*;
+use self::mu::utils::LinkedHashMap;
+use self::mu::vm::*;
+
+use self::mu::linkutils;
+use self::mu::linkutils::aot;
+use std::sync::Arc;
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_gc_alloc_loop() {
+    build_and_run_test!(gc_alloc_loop, gc_alloc_loop_test1);
+}
+
+fn gc_alloc_loop() -> VM {
+    // a small heap, so that the loop triggers several collections
+    // (the garbage does not escape, keep it on the heap)
+    let vm = VM::new_with_opts("init_mu --gc-immixspace-size=4194304 --disable-escape-analysis");
+
+    typedef!    ((vm) int1     = mu_int(1));
+    typedef!    ((vm) int64    = mu_int(64));
+    typedef!    ((vm) array_t  = mu_array(int64, 15));
+    typedef!    ((vm) struct_t = mu_struct(int64, array_t));
+    typedef!    ((vm) ref_struct_t  = mu_ref(struct_t));
+    typedef!    ((vm) iref_struct_t = mu_iref(struct_t));
+    typedef!    ((vm) iref_int64    = mu_iref(int64));
+
+    constdef!   ((vm) <int64> int64_0  = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1  = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_42 = Constant::Int(42));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> gc_alloc_loop);
+    funcdef!    ((vm) <sig> gc_alloc_loop VERSION gc_alloc_loop_v1);
+
+    // blk_entry(n):
+    block!      ((vm, gc_alloc_loop_v1) blk_entry);
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> n);
+
+    // a = NEW <struct_t>
+    ssa!        ((vm, gc_alloc_loop_v1) <ref_struct_t> a);
+    inst!       ((vm, gc_alloc_loop_v1) blk_entry_new:
+        a = NEW <struct_t>
+    );
+
+    // a_iref = GETIREF a
+    ssa!        ((vm, gc_alloc_loop_v1) <iref_struct_t> a_iref);
+    inst!       ((vm, gc_alloc_loop_v1) blk_entry_getiref:
+        a_iref = GETIREF a
+    );
+
+    // a_field0 = GETFIELDIREF a_iref 0
+    ssa!        ((vm, gc_alloc_loop_v1) <iref_int64> a_field0);
+    inst!       ((vm, gc_alloc_loop_v1) blk_entry_getfieldiref:
+        a_field0 = GETFIELDIREF a_iref (is_ptr: false, index: 0)
+    );
+
+    // STORE a_field0 42
+    consta!     ((vm, gc_alloc_loop_v1) int64_42_local = int64_42);
+    inst!       ((vm, gc_alloc_loop_v1) blk_entry_store:
+        STORE a_field0 int64_42_local (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // BRANCH blk_head(a, n, 0)
+    block!      ((vm, gc_alloc_loop_v1) blk_head);
+    consta!     ((vm, gc_alloc_loop_v1) int64_0_local = int64_0);
+    inst!       ((vm, gc_alloc_loop_v1) blk_entry_branch:
+        BRANCH blk_head (a, n, int64_0_local)
+    );
+
+    define_block!   ((vm, gc_alloc_loop_v1) blk_entry(n) {
+        blk_entry_new,
+        blk_entry_getiref,
+        blk_entry_getfieldiref,
+        blk_entry_store,
+        blk_entry_branch
+    });
+
+    // blk_head(head_a, head_n, head_i):
+    ssa!        ((vm, gc_alloc_loop_v1) <ref_struct_t> head_a);
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> head_n);
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> head_i);
+
+    // cond = SLT head_i head_n
+    ssa!        ((vm, gc_alloc_loop_v1) <int1> cond);
+    inst!       ((vm, gc_alloc_loop_v1) blk_head_cmp:
+        cond = CMPOP (CmpOp::SLT) head_i head_n
+    );
+
+    // BRANCH2 cond blk_body(head_a, head_n, head_i) blk_exit(head_a)
+    block!      ((vm, gc_alloc_loop_v1) blk_body);
+    block!      ((vm, gc_alloc_loop_v1) blk_exit);
+    inst!       ((vm, gc_alloc_loop_v1) blk_head_branch2:
+        BRANCH2 (cond, head_a, head_n, head_i)
+            IF (OP 0)
+            THEN blk_body (vec![1, 2, 3]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!   ((vm, gc_alloc_loop_v1) blk_head(head_a, head_n, head_i) {
+        blk_head_cmp,
+        blk_head_branch2
+    });
+
+    // blk_body(body_a, body_n, body_i):
+    ssa!        ((vm, gc_alloc_loop_v1) <ref_struct_t> body_a);
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> body_n);
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> body_i);
+
+    // garbage = NEW <struct_t>
+    ssa!        ((vm, gc_alloc_loop_v1) <ref_struct_t> garbage);
+    inst!       ((vm, gc_alloc_loop_v1) blk_body_new:
+        garbage = NEW <struct_t>
+    );
+
+    // body_i2 = ADD body_i 1
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> body_i2);
+    consta!     ((vm, gc_alloc_loop_v1) int64_1_local = int64_1);
+    inst!       ((vm, gc_alloc_loop_v1) blk_body_add:
+        body_i2 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+
+    // BRANCH blk_head(body_a, body_n, body_i2)
+    inst!       ((vm, gc_alloc_loop_v1) blk_body_branch:
+        BRANCH blk_head (body_a, body_n, body_i2)
+    );
+
+    define_block!   ((vm, gc_alloc_loop_v1) blk_body(body_a, body_n, body_i) {
+        blk_body_new,
+        blk_body_add,
+        blk_body_branch
+    });
+
+    // blk_exit(exit_a):
+    ssa!        ((vm, gc_alloc_loop_v1) <ref_struct_t> exit_a);
+
+    // exit_iref = GETIREF exit_a
+    ssa!        ((vm, gc_alloc_loop_v1) <iref_struct_t> exit_iref);
+    inst!       ((vm, gc_alloc_loop_v1) blk_exit_getiref:
+        exit_iref = GETIREF exit_a
+    );
+
+    // exit_field0 = GETFIELDIREF exit_iref 0
+    ssa!        ((vm, gc_alloc_loop_v1) <iref_int64> exit_field0);
+    inst!       ((vm, gc_alloc_loop_v1) blk_exit_getfieldiref:
+        exit_field0 = GETFIELDIREF exit_iref (is_ptr: false, index: 0)
+    );
+
+    // res = LOAD exit_field0
+    ssa!        ((vm, gc_alloc_loop_v1) <int64> res);
+    inst!       ((vm, gc_alloc_loop_v1) blk_exit_load:
+        res = LOAD exit_field0 (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // RET res
+    inst!       ((vm, gc_alloc_loop_v1) blk_exit_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, gc_alloc_loop_v1) blk_exit(exit_a) {
+        blk_exit_getiref,
+        blk_exit_getfieldiref,
+        blk_exit_load,
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) gc_alloc_loop_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit
+    });
+
+    // 100000 iterations allocate about 12mb (the heap is 4mb), and the object
+    // allocated before the loop should survive the collections
+    emit_test! ((vm)
+        gc_alloc_loop, gc_alloc_loop_test1, gc_alloc_loop_test1_v1,
+        Int RET Int,
+        EQ,
+        sig,
+        int64(100000u64) RET int64(42u64),
+    );
+
+    vm
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_gc_write_barrier() {
+    build_and_run_test!(gc_write_barrier, gc_write_barrier_test1);
+}
+
+fn gc_write_barrier() -> VM {
+    // a small heap, so that the loop triggers several (minor) collections
+    let vm = VM::new_with_opts("init_mu --gc-immixspace-size=4194304");
+
+    typedef!    ((vm) int1     = mu_int(1));
+    typedef!    ((vm) int64    = mu_int(64));
+    typedef!    ((vm) array_t  = mu_array(int64, 15));
+    typedef!    ((vm) leaf_t   = mu_struct(int64, array_t));
+    typedef!    ((vm) ref_leaf_t  = mu_ref(leaf_t));
+    typedef!    ((vm) iref_leaf_t = mu_iref(leaf_t));
+    typedef!    ((vm) holder_t = mu_struct(ref_leaf_t, array_t));
+    typedef!    ((vm) ref_holder_t  = mu_ref(holder_t));
+    typedef!    ((vm) iref_holder_t = mu_iref(holder_t));
+    typedef!    ((vm) iref_ref_leaf_t = mu_iref(ref_leaf_t));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+
+    constdef!   ((vm) <int64> int64_0  = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1  = Constant::Int(1));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> gc_write_barrier);
+    funcdef!    ((vm) <sig> gc_write_barrier VERSION gc_write_barrier_v1);
+
+    // blk_entry(n):
+    block!      ((vm, gc_write_barrier_v1) blk_entry);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> n);
+
+    // holder = NEW <holder_t>
+    ssa!        ((vm, gc_write_barrier_v1) <ref_holder_t> holder);
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_new_holder:
+        holder = NEW <holder_t>
+    );
+
+    // first = NEW <leaf_t> (its field 0 is 0)
+    ssa!        ((vm, gc_write_barrier_v1) <ref_leaf_t> first);
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_new_leaf:
+        first = NEW <leaf_t>
+    );
+
+    // holder_iref = GETIREF holder
+    ssa!        ((vm, gc_write_barrier_v1) <iref_holder_t> holder_iref);
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_getiref:
+        holder_iref = GETIREF holder
+    );
+
+    // holder_field0 = GETFIELDIREF holder_iref 0
+    ssa!        ((vm, gc_write_barrier_v1) <iref_ref_leaf_t> holder_field0);
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_getfieldiref:
+        holder_field0 = GETFIELDIREF holder_iref (is_ptr: false, index: 0)
+    );
+
+    // STORE holder_field0 first
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_store:
+        STORE holder_field0 first (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // BRANCH blk_head(holder, n, 0, 0)
+    block!      ((vm, gc_write_barrier_v1) blk_head);
+    consta!     ((vm, gc_write_barrier_v1) int64_0_local = int64_0);
+    inst!       ((vm, gc_write_barrier_v1) blk_entry_branch:
+        BRANCH blk_head (holder, n, int64_0_local, int64_0_local)
+    );
+
+    define_block!   ((vm, gc_write_barrier_v1) blk_entry(n) {
+        blk_entry_new_holder,
+        blk_entry_new_leaf,
+        blk_entry_getiref,
+        blk_entry_getfieldiref,
+        blk_entry_store,
+        blk_entry_branch
+    });
+
+    // blk_head(head_holder, head_n, head_i, head_sum):
+    ssa!        ((vm, gc_write_barrier_v1) <ref_holder_t> head_holder);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> head_n);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> head_i);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> head_sum);
+
+    // cond = SLT head_i head_n
+    ssa!        ((vm, gc_write_barrier_v1) <int1> cond);
+    inst!       ((vm, gc_write_barrier_v1) blk_head_cmp:
+        cond = CMPOP (CmpOp::SLT) head_i head_n
+    );
+
+    // BRANCH2 cond blk_body(head_holder, head_n, head_i, head_sum) blk_exit(head_sum)
+    block!      ((vm, gc_write_barrier_v1) blk_body);
+    block!      ((vm, gc_write_barrier_v1) blk_exit);
+    inst!       ((vm, gc_write_barrier_v1) blk_head_branch2:
+        BRANCH2 (cond, head_holder, head_n, head_i, head_sum)
+            IF (OP 0)
+            THEN blk_body (vec![1, 2, 3, 4]) WITH 0.9f32,
+            ELSE blk_exit (vec![4])
+    );
+
+    define_block!   ((vm, gc_write_barrier_v1) blk_head(head_holder, head_n, head_i, head_sum) {
+        blk_head_cmp,
+        blk_head_branch2
+    });
+
+    // blk_body(body_holder, body_n, body_i, body_sum):
+    ssa!        ((vm, gc_write_barrier_v1) <ref_holder_t> body_holder);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> body_n);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> body_i);
+    ssa!        ((vm, gc_write_barrier_v1) <int64> body_sum);
+
+    // body_holder_iref = GETIREF body_holder
+    ssa!        ((vm, gc_write_barrier_v1) <iref_holder_t> body_holder_iref);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getiref_holder:
+        body_holder_iref = GETIREF body_holder
+    );
+
+    // body_slot = GETFIELDIREF body_holder_iref 0
+    ssa!        ((vm, gc_write_barrier_v1) <iref_ref_leaf_t> body_slot);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getfieldiref_holder:
+        body_slot = GETFIELDIREF body_holder_iref (is_ptr: false, index: 0)
+    );
+
+    // prev = LOAD body_slot (the leaf stored in last iteration, maybe before a GC)
+    ssa!        ((vm, gc_write_barrier_v1) <ref_leaf_t> prev);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_load_prev:
+        prev = LOAD body_slot (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // prev_iref = GETIREF prev
+    ssa!        ((vm, gc_write_barrier_v1) <iref_leaf_t> prev_iref);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getiref_prev:
+        prev_iref = GETIREF prev
+    );
+
+    // prev_field0 = GETFIELDIREF prev_iref 0
+    ssa!        ((vm, gc_write_barrier_v1) <iref_int64> prev_field0);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getfieldiref_prev:
+        prev_field0 = GETFIELDIREF prev_iref (is_ptr: false, index: 0)
+    );
+
+    // prev_val = LOAD prev_field0
+    ssa!        ((vm, gc_write_barrier_v1) <int64> prev_val);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_load_val:
+        prev_val = LOAD prev_field0 (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // body_sum2 = ADD body_sum prev_val
+    ssa!        ((vm, gc_write_barrier_v1) <int64> body_sum2);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_add_sum:
+        body_sum2 = BINOP (BinOp::Add) body_sum prev_val
+    );
+
+    // leaf = NEW <leaf_t> (may trigger a GC)
+    ssa!        ((vm, gc_write_barrier_v1) <ref_leaf_t> leaf);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_new:
+        leaf = NEW <leaf_t>
+    );
+
+    // leaf_iref = GETIREF leaf
+    ssa!        ((vm, gc_write_barrier_v1) <iref_leaf_t> leaf_iref);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getiref_leaf:
+        leaf_iref = GETIREF leaf
+    );
+
+    // leaf_field0 = GETFIELDIREF leaf_iref 0
+    ssa!        ((vm, gc_write_barrier_v1) <iref_int64> leaf_field0);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_getfieldiref_leaf:
+        leaf_field0 = GETFIELDIREF leaf_iref (is_ptr: false, index: 0)
+    );
+
+    // STORE leaf_field0 body_i
+    inst!       ((vm, gc_write_barrier_v1) blk_body_store_i:
+        STORE leaf_field0 body_i (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // STORE body_slot leaf (the holder is old after the first GC, and the leaf is young)
+    inst!       ((vm, gc_write_barrier_v1) blk_body_store_leaf:
+        STORE body_slot leaf (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // body_i2 = ADD body_i 1
+    ssa!        ((vm, gc_write_barrier_v1) <int64> body_i2);
+    consta!     ((vm, gc_write_barrier_v1) int64_1_local = int64_1);
+    inst!       ((vm, gc_write_barrier_v1) blk_body_add:
+        body_i2 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+
+    // BRANCH blk_head(body_holder, body_n, body_i2, body_sum2)
+    inst!       ((vm, gc_write_barrier_v1) blk_body_branch:
+        BRANCH blk_head (body_holder, body_n, body_i2, body_sum2)
+    );
+
+    define_block!   ((vm, gc_write_barrier_v1) blk_body(body_holder, body_n, body_i, body_sum) {
+        blk_body_getiref_holder,
+        blk_body_getfieldiref_holder,
+        blk_body_load_prev,
+        blk_body_getiref_prev,
+        blk_body_getfieldiref_prev,
+        blk_body_load_val,
+        blk_body_add_sum,
+        blk_body_new,
+        blk_body_getiref_leaf,
+        blk_body_getfieldiref_leaf,
+        blk_body_store_i,
+        blk_body_store_leaf,
+        blk_body_add,
+        blk_body_branch
+    });
+
+    // blk_exit(exit_sum):
+    ssa!        ((vm, gc_write_barrier_v1) <int64> exit_sum);
+
+    // RET exit_sum
+    inst!       ((vm, gc_write_barrier_v1) blk_exit_ret:
+        RET (exit_sum)
+    );
+
+    define_block!   ((vm, gc_write_barrier_v1) blk_exit(exit_sum) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) gc_write_barrier_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit
+    });
+
+    // every iteration reads the leaf that the last iteration stored to the holder. Leaves are
+    // only reachable from the (old) holder, the write barrier keeps them alive through minor
+    // collections. The sum is 0 + 0 + 1 + ... + (n - 2)
+    emit_test! ((vm)
+        gc_write_barrier, gc_write_barrier_test1, gc_write_barrier_test1_v1,
+        Int RET Int,
+        EQ,
+        sig,
+        int64(100000u64) RET int64(4999850001u64),
+    );
+
+    vm
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_gc_out_of_memory() {
+    build_and_run_test!(gc_oom AND gc_oom_alloc, gc_oom_test1, gc_out_of_memory);
+}
+
+fn gc_out_of_memory() -> VM {
+    // the large object space is too small for the allocation (the callee is not inlined,
+    // so that the exception is caught by the CALL)
+    let vm = VM::new_with_opts("init_mu --gc-lospace-size=16777216 --disable-inline");
+
+    typedef!    ((vm) int64    = mu_int(64));
+    typedef!    ((vm) hybrid_t = mu_hybrid()(int64));
+    typedef!    ((vm) ref_hybrid_t = mu_ref(hybrid_t));
+
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+
+    // gc_oom_alloc(n): returns NEWHYBRID <hybrid_t> n
+    funcsig!    ((vm) alloc_sig = (int64) -> (ref_hybrid_t));
+    funcdecl!   ((vm) <alloc_sig> gc_oom_alloc);
+    funcdef!    ((vm) <alloc_sig> gc_oom_alloc VERSION gc_oom_alloc_v1);
+
+    block!      ((vm, gc_oom_alloc_v1) blk_entry);
+    ssa!        ((vm, gc_oom_alloc_v1) <int64> len);
+
+    ssa!        ((vm, gc_oom_alloc_v1) <ref_hybrid_t> obj);
+    inst!       ((vm, gc_oom_alloc_v1) blk_entry_new:
+        obj = NEWHYBRID <hybrid_t> len
+    );
+
+    inst!       ((vm, gc_oom_alloc_v1) blk_entry_ret:
+        RET (obj)
+    );
+
+    define_block!   ((vm, gc_oom_alloc_v1) blk_entry(len) {
+        blk_entry_new,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) gc_oom_alloc_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    // gc_oom(n): returns 1 if gc_oom_alloc(n) throws, 0 otherwise
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> gc_oom);
+    funcdef!    ((vm) <sig> gc_oom VERSION gc_oom_v1);
+
+    typedef!    ((vm) funcref_alloc = mu_funcref(alloc_sig));
+    constdef!   ((vm) <funcref_alloc> const_funcref_alloc = Constant::FuncRef(gc_oom_alloc.clone()));
+
+    block!      ((vm, gc_oom_v1) blk_entry);
+    block!      ((vm, gc_oom_v1) blk_normal);
+    block!      ((vm, gc_oom_v1) blk_exn);
+    ssa!        ((vm, gc_oom_v1) <int64> n);
+
+    consta!     ((vm, gc_oom_v1) const_funcref_alloc_local = const_funcref_alloc);
+    ssa!        ((vm, gc_oom_v1) <ref_hybrid_t> res);
+    inst!       ((vm, gc_oom_v1) blk_entry_call:
+        res = CALL (const_funcref_alloc_local, n) FUNC(0) (vec![1]) CallConvention::Mu,
+            normal: blk_normal (vec![]),
+            exc   : blk_exn    (vec![])
+    );
+
+    define_block!   ((vm, gc_oom_v1) blk_entry(n) {
+        blk_entry_call
+    });
+
+    consta!     ((vm, gc_oom_v1) int64_0_local = int64_0);
+    inst!       ((vm, gc_oom_v1) blk_normal_ret:
+        RET (int64_0_local)
+    );
+
+    define_block!   ((vm, gc_oom_v1) blk_normal() {
+        blk_normal_ret
+    });
+
+    consta!     ((vm, gc_oom_v1) int64_1_local = int64_1);
+    inst!       ((vm, gc_oom_v1) blk_exn_ret:
+        RET (int64_1_local)
+    );
+
+    define_block!   ((vm, gc_oom_v1) blk_exn() {
+        blk_exn_ret
+    });
+
+    define_func_ver!((vm) gc_oom_v1 (entry: blk_entry) {
+        blk_entry, blk_normal, blk_exn
+    });
+
+    // 4M words (32mb) do not fit in the large object space (16mb), the allocation fails after
+    // a major collection, and the out-of-memory exception is thrown
+    emit_test! ((vm)
+        gc_oom, gc_oom_test1, gc_oom_test1_v1,
+        Int RET Int,
+        EQ,
+        sig,
+        int64(4194304u64) RET int64(1u64),
+    );
+
+    vm
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_gc_finalizer() {
+    build_and_run_test!(gc_finalizer AND gc_finalizer_drainer, gc_finalizer_test1, gc_finalizer_vm);
+}
+
+fn gc_finalizer_vm() -> VM {
+    // a small heap, so that the allocation loop triggers collections
+    let vm = VM::new_with_opts("init_mu --gc-immixspace-size=4194304 --disable-escape-analysis");
+
+    typedef!    ((vm) int1     = mu_int(1));
+    typedef!    ((vm) int64    = mu_int(64));
+    typedef!    ((vm) struct_t = mu_struct(int64, int64));
+    typedef!    ((vm) ref_struct_t  = mu_ref(struct_t));
+    typedef!    ((vm) iref_struct_t = mu_iref(struct_t));
+    typedef!    ((vm) iref_int64    = mu_iref(int64));
+    let void_t = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("void_t")),
+        MuType_::void(),
+    );
+    vm.set_name(void_t.as_entity());
+    typedef!    ((vm) ref_void_t = mu_ref(void_t));
+    let stackref_t = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("stackref_t")),
+        MuType_::StackRef,
+    );
+    vm.set_name(stackref_t.as_entity());
+    let threadref_t = vm.declare_type(
+        MuEntityHeader::named(vm.next_id(), Mu("threadref_t")),
+        MuType_::ThreadRef,
+    );
+    vm.set_name(threadref_t.as_entity());
+
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <ref_void_t> ref_void_null = Constant::NullRef);
+
+    // the number of finalised objects, and the sum of their fields
+    globaldef!  ((vm) <int64> fin_count);
+    globaldef!  ((vm) <int64> fin_sum);
+
+    // gc_finalizer_drainer(n): the finaliser thread. It takes n objects from the
+    // finalisation queue, adds their fields to fin_sum, and counts them in fin_count
+    funcsig!    ((vm) drainer_sig = (int64) -> ());
+    funcdecl!   ((vm) <drainer_sig> gc_finalizer_drainer);
+    funcdef!    ((vm) <drainer_sig> gc_finalizer_drainer VERSION gc_finalizer_drainer_v1);
+
+    // blk_entry(n):
+    block!      ((vm, gc_finalizer_drainer_v1) blk_entry);
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> n);
+
+    // BRANCH blk_head(n)
+    block!      ((vm, gc_finalizer_drainer_v1) blk_head);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_entry_branch:
+        BRANCH blk_head (n)
+    );
+
+    define_block!   ((vm, gc_finalizer_drainer_v1) blk_entry(n) {
+        blk_entry_branch
+    });
+
+    // blk_head(head_n):
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> head_n);
+
+    // obj = @uvm.gc.get_finalizable (null if nothing is queued for a while)
+    ssa!        ((vm, gc_finalizer_drainer_v1) <ref_void_t> obj);
+    let blk_head_get_finalizable = gc_finalizer_drainer_v1.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![obj.clone_value()]),
+        ops: vec![],
+        v: Instruction_::CommonInst_GetFinalizable,
+    });
+
+    // is_null = EQ obj NULL
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int1> is_null);
+    consta!     ((vm, gc_finalizer_drainer_v1) ref_void_null_local = ref_void_null);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_head_cmp:
+        is_null = CMPOP (CmpOp::EQ) obj ref_void_null_local
+    );
+
+    // BRANCH2 is_null blk_head(head_n) blk_found(head_n, obj)
+    block!      ((vm, gc_finalizer_drainer_v1) blk_found);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_head_branch2:
+        BRANCH2 (is_null, head_n, obj)
+            IF (OP 0)
+            THEN blk_head (vec![1]) WITH 0.9f32,
+            ELSE blk_found (vec![1, 2])
+    );
+
+    define_block!   ((vm, gc_finalizer_drainer_v1) blk_head(head_n) {
+        blk_head_get_finalizable,
+        blk_head_cmp,
+        blk_head_branch2
+    });
+
+    // blk_found(found_n, found_obj):
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> found_n);
+    ssa!        ((vm, gc_finalizer_drainer_v1) <ref_void_t> found_obj);
+
+    // found_ref = REFCAST found_obj
+    ssa!        ((vm, gc_finalizer_drainer_v1) <ref_struct_t> found_ref);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_refcast:
+        found_ref = CONVOP (ConvOp::REFCAST) <ref_void_t ref_struct_t> found_obj
+    );
+
+    // found_field0 = GETFIELDIREF (GETIREF found_ref) 0
+    ssa!        ((vm, gc_finalizer_drainer_v1) <iref_struct_t> found_iref);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_getiref:
+        found_iref = GETIREF found_ref
+    );
+    ssa!        ((vm, gc_finalizer_drainer_v1) <iref_int64> found_field0);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_getfieldiref:
+        found_field0 = GETFIELDIREF found_iref (is_ptr: false, index: 0)
+    );
+
+    // val = LOAD found_field0
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> val);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_load_val:
+        val = LOAD found_field0 (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // STORE fin_sum (ADD (LOAD fin_sum) val)
+    global!     ((vm, gc_finalizer_drainer_v1) found_fin_sum = fin_sum);
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> sum);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_load_sum:
+        sum = LOAD found_fin_sum (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> sum2);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_add_sum:
+        sum2 = BINOP (BinOp::Add) sum val
+    );
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_store_sum:
+        STORE found_fin_sum sum2 (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+
+    // count2 = ADD (LOAD fin_count) 1; STORE fin_count count2
+    global!     ((vm, gc_finalizer_drainer_v1) found_fin_count = fin_count);
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> count);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_load_count:
+        count = LOAD found_fin_count (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int64> count2);
+    consta!     ((vm, gc_finalizer_drainer_v1) int64_1_local = int64_1);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_add_count:
+        count2 = BINOP (BinOp::Add) count int64_1_local
+    );
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_store_count:
+        STORE found_fin_count count2 (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+
+    // done = EQ count2 found_n
+    ssa!        ((vm, gc_finalizer_drainer_v1) <int1> done);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_cmp:
+        done = CMPOP (CmpOp::EQ) count2 found_n
+    );
+
+    // BRANCH2 done blk_exit() blk_head(found_n)
+    block!      ((vm, gc_finalizer_drainer_v1) blk_exit);
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_found_branch2:
+        BRANCH2 (done, found_n)
+            IF (OP 0)
+            THEN blk_exit (vec![]) WITH 0.1f32,
+            ELSE blk_head (vec![1])
+    );
+
+    define_block!   ((vm, gc_finalizer_drainer_v1) blk_found(found_n, found_obj) {
+        blk_found_refcast,
+        blk_found_getiref,
+        blk_found_getfieldiref,
+        blk_found_load_val,
+        blk_found_load_sum,
+        blk_found_add_sum,
+        blk_found_store_sum,
+        blk_found_load_count,
+        blk_found_add_count,
+        blk_found_store_count,
+        blk_found_cmp,
+        blk_found_branch2
+    });
+
+    // blk_exit(): THREADEXIT
+    inst!       ((vm, gc_finalizer_drainer_v1) blk_exit_threadexit:
+        THREADEXIT
+    );
+
+    define_block!   ((vm, gc_finalizer_drainer_v1) blk_exit() {
+        blk_exit_threadexit
+    });
+
+    define_func_ver!((vm) gc_finalizer_drainer_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_found, blk_exit
+    });
+
+    // gc_finalizer(n): starts the finaliser thread, registers n objects (with fields 1..n)
+    // for finalisation and drops them, then allocates until all n are finalised.
+    // Returns the sum of the fields seen by the finaliser thread
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> gc_finalizer);
+    funcdef!    ((vm) <sig> gc_finalizer VERSION gc_finalizer_v1);
+
+    typedef!    ((vm) funcref_drainer = mu_funcref(drainer_sig));
+    constdef!   ((vm) <funcref_drainer> const_funcref_drainer =
+        Constant::FuncRef(gc_finalizer_drainer.clone()));
+
+    // blk_entry(n):
+    block!      ((vm, gc_finalizer_v1) blk_entry);
+    ssa!        ((vm, gc_finalizer_v1) <int64> n);
+
+    // stack = NEWSTACK gc_finalizer_drainer
+    ssa!        ((vm, gc_finalizer_v1) <stackref_t> stack);
+    consta!     ((vm, gc_finalizer_v1) const_funcref_drainer_local = const_funcref_drainer);
+    let blk_entry_newstack = gc_finalizer_v1.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![stack.clone_value()]),
+        ops: vec![const_funcref_drainer_local.clone()],
+        v: Instruction_::NewStack(0),
+    });
+
+    // thread = NEWTHREAD stack PASS_VALUES (n)
+    ssa!        ((vm, gc_finalizer_v1) <threadref_t> thread);
+    let blk_entry_newthread = gc_finalizer_v1.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![thread.clone_value()]),
+        ops: vec![stack.clone(), n.clone()],
+        v: Instruction_::NewThread {
+            stack: 0,
+            thread_local: None,
+            is_exception: false,
+            args: vec![1],
+        },
+    });
+
+    // BRANCH blk_reg_head(n, 0)
+    block!      ((vm, gc_finalizer_v1) blk_reg_head);
+    consta!     ((vm, gc_finalizer_v1) int64_0_local = int64_0);
+    inst!       ((vm, gc_finalizer_v1) blk_entry_branch:
+        BRANCH blk_reg_head (n, int64_0_local)
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_entry(n) {
+        blk_entry_newstack,
+        blk_entry_newthread,
+        blk_entry_branch
+    });
+
+    // blk_reg_head(head_n, head_i):
+    ssa!        ((vm, gc_finalizer_v1) <int64> head_n);
+    ssa!        ((vm, gc_finalizer_v1) <int64> head_i);
+
+    // cond = SLT head_i head_n
+    ssa!        ((vm, gc_finalizer_v1) <int1> cond);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_head_cmp:
+        cond = CMPOP (CmpOp::SLT) head_i head_n
+    );
+
+    // BRANCH2 cond blk_reg_body(head_n, head_i) blk_wait(head_n)
+    block!      ((vm, gc_finalizer_v1) blk_reg_body);
+    block!      ((vm, gc_finalizer_v1) blk_wait);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_head_branch2:
+        BRANCH2 (cond, head_n, head_i)
+            IF (OP 0)
+            THEN blk_reg_body (vec![1, 2]) WITH 0.9f32,
+            ELSE blk_wait (vec![1])
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_reg_head(head_n, head_i) {
+        blk_reg_head_cmp,
+        blk_reg_head_branch2
+    });
+
+    // blk_reg_body(body_n, body_i):
+    ssa!        ((vm, gc_finalizer_v1) <int64> body_n);
+    ssa!        ((vm, gc_finalizer_v1) <int64> body_i);
+
+    // obj = NEW <struct_t>
+    ssa!        ((vm, gc_finalizer_v1) <ref_struct_t> obj);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_new:
+        obj = NEW <struct_t>
+    );
+
+    // body_i2 = ADD body_i 1
+    ssa!        ((vm, gc_finalizer_v1) <int64> body_i2);
+    consta!     ((vm, gc_finalizer_v1) int64_1_local = int64_1);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_add:
+        body_i2 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+
+    // STORE (GETFIELDIREF (GETIREF obj) 0) body_i2
+    ssa!        ((vm, gc_finalizer_v1) <iref_struct_t> obj_iref);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_getiref:
+        obj_iref = GETIREF obj
+    );
+    ssa!        ((vm, gc_finalizer_v1) <iref_int64> obj_field0);
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_getfieldiref:
+        obj_field0 = GETFIELDIREF obj_iref (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_store:
+        STORE obj_field0 body_i2 (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // @uvm.gc.register_finalizer obj
+    let blk_reg_body_register = gc_finalizer_v1.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: vec![obj.clone()],
+        v: Instruction_::CommonInst_RegisterFinalizer(0),
+    });
+
+    // BRANCH blk_reg_head(body_n, body_i2)
+    inst!       ((vm, gc_finalizer_v1) blk_reg_body_branch:
+        BRANCH blk_reg_head (body_n, body_i2)
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_reg_body(body_n, body_i) {
+        blk_reg_body_new,
+        blk_reg_body_add,
+        blk_reg_body_getiref,
+        blk_reg_body_getfieldiref,
+        blk_reg_body_store,
+        blk_reg_body_register,
+        blk_reg_body_branch
+    });
+
+    // blk_wait(wait_n):
+    ssa!        ((vm, gc_finalizer_v1) <int64> wait_n);
+
+    // finalised = LOAD fin_count
+    global!     ((vm, gc_finalizer_v1) wait_fin_count = fin_count);
+    ssa!        ((vm, gc_finalizer_v1) <int64> finalised);
+    inst!       ((vm, gc_finalizer_v1) blk_wait_load:
+        finalised = LOAD wait_fin_count (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+
+    // all_finalised = EQ finalised wait_n
+    ssa!        ((vm, gc_finalizer_v1) <int1> all_finalised);
+    inst!       ((vm, gc_finalizer_v1) blk_wait_cmp:
+        all_finalised = CMPOP (CmpOp::EQ) finalised wait_n
+    );
+
+    // BRANCH2 all_finalised blk_exit() blk_alloc(wait_n)
+    block!      ((vm, gc_finalizer_v1) blk_alloc);
+    block!      ((vm, gc_finalizer_v1) blk_exit);
+    inst!       ((vm, gc_finalizer_v1) blk_wait_branch2:
+        BRANCH2 (all_finalised, wait_n)
+            IF (OP 0)
+            THEN blk_exit (vec![]) WITH 0.1f32,
+            ELSE blk_alloc (vec![1])
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_wait(wait_n) {
+        blk_wait_load,
+        blk_wait_cmp,
+        blk_wait_branch2
+    });
+
+    // blk_alloc(alloc_n): allocates garbage until a GC finds the registered objects
+    ssa!        ((vm, gc_finalizer_v1) <int64> alloc_n);
+    ssa!        ((vm, gc_finalizer_v1) <ref_struct_t> garbage);
+    inst!       ((vm, gc_finalizer_v1) blk_alloc_new:
+        garbage = NEW <struct_t>
+    );
+    inst!       ((vm, gc_finalizer_v1) blk_alloc_branch:
+        BRANCH blk_wait (alloc_n)
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_alloc(alloc_n) {
+        blk_alloc_new,
+        blk_alloc_branch
+    });
+
+    // blk_exit(): RET (LOAD fin_sum)
+    global!     ((vm, gc_finalizer_v1) exit_fin_sum = fin_sum);
+    ssa!        ((vm, gc_finalizer_v1) <int64> res);
+    inst!       ((vm, gc_finalizer_v1) blk_exit_load:
+        res = LOAD exit_fin_sum (is_ptr: false, order: MemoryOrder::SeqCst)
+    );
+    inst!       ((vm, gc_finalizer_v1) blk_exit_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, gc_finalizer_v1) blk_exit() {
+        blk_exit_load,
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) gc_finalizer_v1 (entry: blk_entry) {
+        blk_entry, blk_reg_head, blk_reg_body, blk_wait, blk_alloc, blk_exit
+    });
+
+    // the 10 objects are unreachable after registration. A collection resurrects and
+    // queues them, and the finaliser thread sees their fields (1 + 2 + ... + 10)
+    emit_test! ((vm)
+        gc_finalizer, gc_finalizer_test1, gc_finalizer_test1_v1,
+        Int RET Int,
+        EQ,
+        sig,
+        int64(10u64) RET int64(55u64),
+    );
+
+    vm
+}
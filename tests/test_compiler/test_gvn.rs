@@ -0,0 +1,480 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::utils::LinkedHashMap;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+/// returns the instructions of the block
+fn block_body(func_ver: &MuFunctionVersion, block: MuID) -> Vec<Instruction> {
+    let content = func_ver.content.as_ref().unwrap();
+    let body = &content.get_block(block).content.as_ref().unwrap().body;
+    body.iter().map(|node| node.as_inst().clone()).collect()
+}
+
+#[test]
+fn test_ir_domtree() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn_add());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::ControlFlowAnalysis::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("gvn_add");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let domtree = passes::DomTree::new(func_ver.content.as_ref().unwrap());
+    let entry = vm.id_of("blk_entry");
+    let blk_true = vm.id_of("blk_true");
+    let blk_false = vm.id_of("blk_false");
+    let blk_exit = vm.id_of("blk_exit");
+
+    assert_eq!(domtree.root(), entry);
+    assert_eq!(domtree.idom(entry), None);
+    assert_eq!(domtree.idom(blk_true), Some(entry));
+    assert_eq!(domtree.idom(blk_false), Some(entry));
+    // the exit is reached from both branches
+    assert_eq!(domtree.idom(blk_exit), Some(entry));
+    assert!(domtree.dominates(entry, blk_exit));
+    assert!(!domtree.dominates(blk_true, blk_exit));
+    assert_eq!(domtree.preorder()[0], entry);
+    assert_eq!(domtree.rpo().len(), 4);
+}
+
+#[test]
+fn test_gvn_add() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn_add());
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::GVN::new())]), &vm);
+
+    let func_id = vm.id_of("gvn_add");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let entry = block_body(&func_ver, vm.id_of("blk_entry"));
+    let x = entry[0].value.as_ref().unwrap()[0].id();
+
+    // %y = ADD %b %a is the same as %x
+    let blk_true = block_body(&func_ver, vm.id_of("blk_true"));
+    assert_eq!(blk_true.len(), 1);
+    match blk_true[0].v {
+        Instruction_::Branch1(ref dest) => {
+            let args = dest.get_arguments(&blk_true[0].ops);
+            assert_eq!(args[0].id(), x);
+        }
+        _ => panic!("expect a BRANCH, found {}", blk_true[0]),
+    }
+
+    // %z = SUB %a %b is not the same as %x
+    let blk_false = block_body(&func_ver, vm.id_of("blk_false"));
+    assert_eq!(blk_false.len(), 2);
+
+    let lib = linkutils::aot::compile_fnc("gvn_add", &gvn_add);
+    unsafe {
+        let gvn_add: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"gvn_add").unwrap();
+
+        let res = gvn_add(3, 4);
+        println!("gvn_add(3, 4) = {}", res);
+        assert_eq!(res, 7);
+
+        let res = gvn_add(20, 4);
+        println!("gvn_add(20, 4) = {}", res);
+        assert_eq!(res, 16);
+    }
+}
+
+fn gvn_add() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_10 = Constant::Int(10));
+
+    funcsig!    ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> gvn_add);
+    funcdef!    ((vm) <sig> gvn_add VERSION gvn_add_v1);
+
+    // %entry(%a, %b):
+    //   %x = ADD %a %b
+    //   %c = SLT %x 10
+    //   BRANCH2 %c %true() %false()
+    block!      ((vm, gvn_add_v1) blk_entry);
+    ssa!        ((vm, gvn_add_v1) <int64> a);
+    ssa!        ((vm, gvn_add_v1) <int64> b);
+    consta!     ((vm, gvn_add_v1) int64_10_local = int64_10);
+
+    ssa!        ((vm, gvn_add_v1) <int64> x);
+    inst!       ((vm, gvn_add_v1) blk_entry_add:
+        x = BINOP (BinOp::Add) a b
+    );
+
+    ssa!        ((vm, gvn_add_v1) <int1> c);
+    inst!       ((vm, gvn_add_v1) blk_entry_cmp:
+        c = CMPOP (CmpOp::SLT) x int64_10_local
+    );
+
+    block!      ((vm, gvn_add_v1) blk_true);
+    block!      ((vm, gvn_add_v1) blk_false);
+    inst!       ((vm, gvn_add_v1) blk_entry_branch2:
+        BRANCH2 (c)
+            IF (OP 0)
+            THEN blk_true (vec![]) WITH 0.5f32,
+            ELSE blk_false (vec![])
+    );
+
+    define_block!((vm, gvn_add_v1) blk_entry(a, b) {
+        blk_entry_add, blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %true():
+    //   %y = ADD %b %a
+    //   BRANCH %exit(%y)
+    block!      ((vm, gvn_add_v1) blk_exit);
+    ssa!        ((vm, gvn_add_v1) <int64> y);
+    inst!       ((vm, gvn_add_v1) blk_true_add:
+        y = BINOP (BinOp::Add) b a
+    );
+    inst!       ((vm, gvn_add_v1) blk_true_branch:
+        BRANCH blk_exit (y)
+    );
+
+    define_block!((vm, gvn_add_v1) blk_true() {
+        blk_true_add, blk_true_branch
+    });
+
+    // %false():
+    //   %z = SUB %a %b
+    //   BRANCH %exit(%z)
+    ssa!        ((vm, gvn_add_v1) <int64> z);
+    inst!       ((vm, gvn_add_v1) blk_false_sub:
+        z = BINOP (BinOp::Sub) a b
+    );
+    inst!       ((vm, gvn_add_v1) blk_false_branch:
+        BRANCH blk_exit (z)
+    );
+
+    define_block!((vm, gvn_add_v1) blk_false() {
+        blk_false_sub, blk_false_branch
+    });
+
+    // %exit(%r):
+    //   RET %r
+    ssa!        ((vm, gvn_add_v1) <int64> r);
+    inst!       ((vm, gvn_add_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, gvn_add_v1) blk_exit(r) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) gvn_add_v1 (entry: blk_entry) {
+        blk_entry, blk_true, blk_false, blk_exit
+    });
+
+    vm
+}
+
+#[test]
+fn test_gvn_load() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn_load());
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::GVN::new())]), &vm);
+
+    let func_id = vm.id_of("gvn_load");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the second LOAD is removed, the LOAD after the STORE is kept
+    let entry = block_body(&func_ver, vm.id_of("blk_entry"));
+    let loads = entry
+        .iter()
+        .filter(|inst| match inst.v {
+            Instruction_::Load { .. } => true,
+            _ => false,
+        })
+        .count();
+    assert_eq!(loads, 2);
+    assert_eq!(entry.len(), 5);
+}
+
+fn gvn_load() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+
+    funcsig!    ((vm) sig = (iref_int64) -> (int64));
+    funcdecl!   ((vm) <sig> gvn_load);
+    funcdef!    ((vm) <sig> gvn_load VERSION gvn_load_v1);
+
+    // %entry(%p):
+    //   %l1 = LOAD %p
+    //   %l2 = LOAD %p
+    //   %s = ADD %l1 %l2
+    //   STORE %p %s
+    //   %l3 = LOAD %p
+    //   RET %l3
+    block!      ((vm, gvn_load_v1) blk_entry);
+    ssa!        ((vm, gvn_load_v1) <iref_int64> p);
+    ssa!        ((vm, gvn_load_v1) <int64> l1);
+    ssa!        ((vm, gvn_load_v1) <int64> l2);
+    ssa!        ((vm, gvn_load_v1) <int64> s);
+    ssa!        ((vm, gvn_load_v1) <int64> l3);
+    inst!       ((vm, gvn_load_v1) blk_entry_load1:
+        l1 = LOAD p (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_load_v1) blk_entry_load2:
+        l2 = LOAD p (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_load_v1) blk_entry_add:
+        s = BINOP (BinOp::Add) l1 l2
+    );
+    inst!       ((vm, gvn_load_v1) blk_entry_store:
+        STORE p s (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_load_v1) blk_entry_load3:
+        l3 = LOAD p (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_load_v1) blk_entry_ret:
+        RET (l3)
+    );
+
+    define_block!((vm, gvn_load_v1) blk_entry(p) {
+        blk_entry_load1, blk_entry_load2, blk_entry_add, blk_entry_store, blk_entry_load3,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) gvn_load_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+/// counts the GETIREF and GETFIELDIREF instructions in the block
+fn count_derived_pointers(func_ver: &MuFunctionVersion, block: MuID) -> usize {
+    block_body(func_ver, block)
+        .iter()
+        .filter(|inst| match inst.v {
+            Instruction_::GetIRef(_) | Instruction_::GetFieldIRef { .. } => true,
+            _ => false,
+        })
+        .count()
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_gvn_derived_pointer_safepoint() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn_iref("init_mu"));
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::GVN::new())]), &vm);
+
+    let func_id = vm.id_of("gvn_iref");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the derived pointers are reused after the NEW (a GC safepoint), and in the
+    // dominated block, as the register allocator keeps %a alive as long as them
+    assert_eq!(count_derived_pointers(&func_ver, vm.id_of("blk_entry")), 2);
+    assert_eq!(count_derived_pointers(&func_ver, vm.id_of("blk_exit")), 0);
+}
+
+#[test]
+fn test_gvn_derived_pointer_no_gc() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn_iref("init_mu --gc-disable-collection"));
+    let compiler = Compiler::new(CompilerPolicy::new(vec![Box::new(passes::GVN::new())]), &vm);
+
+    let func_id = vm.id_of("gvn_iref");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // without collection, every derived pointer is the same as the first one
+    assert_eq!(count_derived_pointers(&func_ver, vm.id_of("blk_entry")), 2);
+    assert_eq!(count_derived_pointers(&func_ver, vm.id_of("blk_exit")), 0);
+}
+
+fn gvn_iref(opts: &str) -> VM {
+    let vm = VM::new_with_opts(opts);
+
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) struct_t   = mu_struct(int64, int64));
+    typedef!    ((vm) ref_struct_t  = mu_ref(struct_t));
+    typedef!    ((vm) iref_struct_t = mu_iref(struct_t));
+    typedef!    ((vm) iref_int64    = mu_iref(int64));
+
+    funcsig!    ((vm) sig = (ref_struct_t) -> (int64));
+    funcdecl!   ((vm) <sig> gvn_iref);
+    funcdef!    ((vm) <sig> gvn_iref VERSION gvn_iref_v1);
+
+    // %entry(%a):
+    //   %i1 = GETIREF %a
+    //   %f1 = GETFIELDIREF %i1 0
+    //   %x = LOAD %f1
+    //   %i2 = GETIREF %a
+    //   %f2 = GETFIELDIREF %i2 0
+    //   STORE %f2 %x
+    //   %g = NEW <struct_t>
+    //   %i3 = GETIREF %a
+    //   %f3 = GETFIELDIREF %i3 0
+    //   %y = LOAD %f3
+    //   BRANCH %exit(%y)
+    block!      ((vm, gvn_iref_v1) blk_entry);
+    ssa!        ((vm, gvn_iref_v1) <ref_struct_t> a);
+    ssa!        ((vm, gvn_iref_v1) <iref_struct_t> i1);
+    ssa!        ((vm, gvn_iref_v1) <iref_int64> f1);
+    ssa!        ((vm, gvn_iref_v1) <int64> x);
+    ssa!        ((vm, gvn_iref_v1) <iref_struct_t> i2);
+    ssa!        ((vm, gvn_iref_v1) <iref_int64> f2);
+    ssa!        ((vm, gvn_iref_v1) <ref_struct_t> g);
+    ssa!        ((vm, gvn_iref_v1) <iref_struct_t> i3);
+    ssa!        ((vm, gvn_iref_v1) <iref_int64> f3);
+    ssa!        ((vm, gvn_iref_v1) <int64> y);
+    inst!       ((vm, gvn_iref_v1) blk_entry_getiref1:
+        i1 = GETIREF a
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_getfieldiref1:
+        f1 = GETFIELDIREF i1 (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_load1:
+        x = LOAD f1 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_getiref2:
+        i2 = GETIREF a
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_getfieldiref2:
+        f2 = GETFIELDIREF i2 (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_store:
+        STORE f2 x (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_new:
+        g = NEW <struct_t>
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_getiref3:
+        i3 = GETIREF a
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_getfieldiref3:
+        f3 = GETFIELDIREF i3 (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_entry_load3:
+        y = LOAD f3 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    block!      ((vm, gvn_iref_v1) blk_exit);
+    inst!       ((vm, gvn_iref_v1) blk_entry_branch:
+        BRANCH blk_exit (y)
+    );
+
+    define_block!((vm, gvn_iref_v1) blk_entry(a) {
+        blk_entry_getiref1, blk_entry_getfieldiref1, blk_entry_load1, blk_entry_getiref2,
+        blk_entry_getfieldiref2, blk_entry_store, blk_entry_new, blk_entry_getiref3,
+        blk_entry_getfieldiref3, blk_entry_load3, blk_entry_branch
+    });
+
+    // %exit(%r):
+    //   %i4 = GETIREF %a
+    //   %f4 = GETFIELDIREF %i4 0
+    //   %z = LOAD %f4
+    //   %s = ADD %r %z
+    //   RET %s
+    ssa!        ((vm, gvn_iref_v1) <int64> r);
+    ssa!        ((vm, gvn_iref_v1) <iref_struct_t> i4);
+    ssa!        ((vm, gvn_iref_v1) <iref_int64> f4);
+    ssa!        ((vm, gvn_iref_v1) <int64> z);
+    ssa!        ((vm, gvn_iref_v1) <int64> s);
+    inst!       ((vm, gvn_iref_v1) blk_exit_getiref:
+        i4 = GETIREF a
+    );
+    inst!       ((vm, gvn_iref_v1) blk_exit_getfieldiref:
+        f4 = GETFIELDIREF i4 (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_exit_load:
+        z = LOAD f4 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, gvn_iref_v1) blk_exit_add:
+        s = BINOP (BinOp::Add) r z
+    );
+    inst!       ((vm, gvn_iref_v1) blk_exit_ret:
+        RET (s)
+    );
+
+    define_block!((vm, gvn_iref_v1) blk_exit(r) {
+        blk_exit_getiref, blk_exit_getfieldiref, blk_exit_load, blk_exit_add, blk_exit_ret
+    });
+
+    define_func_ver!((vm) gvn_iref_v1 (entry: blk_entry) {
+        blk_entry, blk_exit
+    });
+
+    vm
+}
@@ -18,10 +18,46 @@ use mu::ast::inst::*;
 use mu::ast::ir::*;
 use mu::ast::op::*;
 use mu::ast::types::*;
+use mu::compiler::*;
 use mu::linkutils;
 use mu::utils::LinkedHashMap;
 use mu::vm::*;
 
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// returns the instructions of the function
+fn all_insts(func_ver: &MuFunctionVersion) -> Vec<Instruction> {
+    let content = func_ver.content.as_ref().unwrap();
+    let mut ret = vec![];
+    for block in content.blocks.values() {
+        for inst in block.content.as_ref().unwrap().body.iter() {
+            ret.push(inst.as_inst().clone());
+        }
+    }
+    ret
+}
+
+/// checks that every SSA variable of the function is defined once
+fn assert_unique_definitions(func_ver: &MuFunctionVersion) {
+    let content = func_ver.content.as_ref().unwrap();
+    let mut defs = HashSet::new();
+    for block in content.blocks.values() {
+        let block_content = block.content.as_ref().unwrap();
+        let ref args = block_content.args;
+        for arg in args.iter().chain(block_content.exn_arg.iter()) {
+            assert!(defs.insert(arg.id()), "{} is defined more than once", arg);
+        }
+        for inst in block_content.body.iter() {
+            if let Some(ref values) = inst.as_inst().value {
+                for val in values.iter() {
+                    assert!(defs.insert(val.id()), "{} is defined more than once", val);
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_inline_add_simple() {
     let lib =
@@ -306,3 +342,321 @@ fn inline_add_with_extra_norm_args() -> VM {
 
     vm
 }
+
+#[test]
+fn test_inline_multi_level() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(inline_add_thrice());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::Inlining::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("add_thrice");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // add_twice is inlined, and then the calls to add in it
+    for inst in all_insts(&func_ver) {
+        match inst.v {
+            Instruction_::ExprCall { .. } | Instruction_::Call { .. } => {
+                panic!("{} is not inlined", inst)
+            }
+            _ => {}
+        }
+    }
+    assert_unique_definitions(&func_ver);
+
+    let lib = linkutils::aot::compile_fncs(
+        "add_thrice",
+        vec!["add_thrice", "add_twice", "add"],
+        &inline_add_thrice,
+    );
+
+    unsafe {
+        let add_thrice: libloading::Symbol<unsafe extern "C" fn(u64, u64, u64, u64) -> u64> =
+            lib.get(b"add_thrice").unwrap();
+
+        let res = add_thrice(1, 2, 3, 4);
+        println!("add_thrice(1, 2, 3, 4) = {}", res);
+        assert!(res == 10);
+    }
+}
+
+fn inline_add_thrice() -> VM {
+    let vm = inline_add_twice();
+
+    let int64 = vm.get_type(vm.id_of("int64"));
+    let add_twice_sig = vm.get_func_sig(vm.id_of("add_twice_sig"));
+    let funcref_add = vm.get_const(vm.id_of("funcref_add"));
+
+    typedef!    ((vm) funcref_to_add_twice_sig = mu_funcref(add_twice_sig));
+    let add_twice = MuEntityHeader::named(vm.id_of("add_twice"), Mu("add_twice"));
+    constdef!   ((vm) <funcref_to_add_twice_sig> funcref_add_twice
+        = Constant::FuncRef(add_twice));
+
+    funcsig!    ((vm) add_thrice_sig = (int64, int64, int64, int64) -> (int64));
+    funcdecl!   ((vm) <add_thrice_sig> add_thrice);
+    funcdef!    ((vm) <add_thrice_sig> add_thrice VERSION add_thrice_v1);
+
+    // %entry(%x, %y, %z, %w):
+    //   %r = EXPRCALL add_twice(%x, %y, %z)
+    //   %s = EXPRCALL add(%r, %w)
+    //   RET %s
+    block!      ((vm, add_thrice_v1) blk_entry);
+    ssa!        ((vm, add_thrice_v1) <int64> x);
+    ssa!        ((vm, add_thrice_v1) <int64> y);
+    ssa!        ((vm, add_thrice_v1) <int64> z);
+    ssa!        ((vm, add_thrice_v1) <int64> w);
+
+    consta!     ((vm, add_thrice_v1) funcref_add_twice_local = funcref_add_twice);
+    ssa!        ((vm, add_thrice_v1) <int64> r);
+    inst!       ((vm, add_thrice_v1) call:
+        r = EXPRCALL (CallConvention::Mu, is_abort: false) funcref_add_twice_local (x, y, z)
+    );
+
+    consta!     ((vm, add_thrice_v1) funcref_add_local = funcref_add);
+    ssa!        ((vm, add_thrice_v1) <int64> s);
+    inst!       ((vm, add_thrice_v1) call2:
+        s = EXPRCALL (CallConvention::Mu, is_abort: false) funcref_add_local (r, w)
+    );
+
+    inst!       ((vm, add_thrice_v1) ret:
+        RET (s)
+    );
+
+    define_block!   ((vm, add_thrice_v1) blk_entry(x, y, z, w) {call, call2, ret});
+
+    define_func_ver!((vm) add_thrice_v1 (entry: blk_entry) {blk_entry});
+
+    vm
+}
+
+#[test]
+fn test_inline_throw_to_exn_dest() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(inline_throw());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::Inlining::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("catch_negative");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the call is inlined, and THROW goes to the exceptional destination
+    for inst in all_insts(&func_ver) {
+        match inst.v {
+            Instruction_::Call { .. } => panic!("{} is not inlined", inst),
+            Instruction_::Throw(_) => panic!("{} is not rewritten", inst),
+            _ => {}
+        }
+    }
+
+    let content = func_ver.content.as_ref().unwrap();
+    let landing = content
+        .blocks
+        .values()
+        .find(|block| block.name().contains(":exn_landing"))
+        .unwrap();
+    let landing_content = landing.content.as_ref().unwrap();
+    assert!(landing_content.exn_arg.is_none());
+    assert_eq!(landing_content.args.len(), 1);
+
+    assert_unique_definitions(&func_ver);
+
+    let lib = linkutils::aot::compile_fncs(
+        "catch_negative",
+        vec!["catch_negative", "throw_if_negative"],
+        &inline_throw,
+    );
+
+    unsafe {
+        let catch_negative: libloading::Symbol<unsafe extern "C" fn(i64) -> i64> =
+            lib.get(b"catch_negative").unwrap();
+
+        let res = catch_negative(5);
+        println!("catch_negative(5) = {}", res);
+        assert!(res == 5);
+
+        let res = catch_negative(-5);
+        println!("catch_negative(-5) = {}", res);
+        assert!(res == 42);
+    }
+}
+
+fn inline_throw() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1       = mu_int(1));
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) ref_int64  = mu_ref(int64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+    constdef!   ((vm) <int64> int64_0  = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_42 = Constant::Int(42));
+
+    funcsig!    ((vm) throw_sig = (int64, ref_int64) -> (int64));
+    funcdecl!   ((vm) <throw_sig> throw_if_negative);
+    {
+        // throw_if_negative
+        funcdef!    ((vm) <throw_sig> throw_if_negative VERSION throw_if_negative_v1);
+
+        // %entry(%x, %e):
+        //   %c = SLT %x 0
+        //   BRANCH2 %c %throw() %ret()
+        block!      ((vm, throw_if_negative_v1) blk_entry);
+        ssa!        ((vm, throw_if_negative_v1) <int64> x);
+        ssa!        ((vm, throw_if_negative_v1) <ref_int64> e);
+        consta!     ((vm, throw_if_negative_v1) int64_0_local = int64_0);
+
+        ssa!        ((vm, throw_if_negative_v1) <int1> c);
+        inst!       ((vm, throw_if_negative_v1) blk_entry_cmp:
+            c = CMPOP (CmpOp::SLT) x int64_0_local
+        );
+
+        block!      ((vm, throw_if_negative_v1) blk_throw);
+        block!      ((vm, throw_if_negative_v1) blk_ret);
+        inst!       ((vm, throw_if_negative_v1) blk_entry_branch2:
+            BRANCH2 (c)
+                IF (OP 0)
+                THEN blk_throw (vec![]) WITH 0.1f32,
+                ELSE blk_ret (vec![])
+        );
+
+        define_block!((vm, throw_if_negative_v1) blk_entry(x, e) {
+            blk_entry_cmp, blk_entry_branch2
+        });
+
+        // %throw():
+        //   THROW %e
+        inst!       ((vm, throw_if_negative_v1) blk_throw_throw:
+            THROW e
+        );
+
+        define_block!((vm, throw_if_negative_v1) blk_throw() {
+            blk_throw_throw
+        });
+
+        // %ret():
+        //   RET %x
+        inst!       ((vm, throw_if_negative_v1) blk_ret_ret:
+            RET (x)
+        );
+
+        define_block!((vm, throw_if_negative_v1) blk_ret() {
+            blk_ret_ret
+        });
+
+        define_func_ver!((vm) throw_if_negative_v1 (entry: blk_entry) {
+            blk_entry, blk_throw, blk_ret
+        });
+    }
+
+    {
+        // catch_negative
+        typedef!    ((vm) funcref_to_throw_sig = mu_funcref(throw_sig));
+        constdef!   ((vm) <funcref_to_throw_sig> funcref_throw_if_negative
+            = Constant::FuncRef(throw_if_negative));
+
+        funcsig!    ((vm) catch_sig = (int64) -> (int64));
+        funcdecl!   ((vm) <catch_sig> catch_negative);
+        funcdef!    ((vm) <catch_sig> catch_negative VERSION catch_negative_v1);
+
+        // %entry(%x):
+        //   %e = NEW <@int64>
+        //   %e_iref = GETIREF %e
+        //   STORE %e_iref 42
+        //   %res = CALL throw_if_negative(%x, %e) normal: %norm(%res) exc: %exn()
+        block!      ((vm, catch_negative_v1) blk_entry);
+        ssa!        ((vm, catch_negative_v1) <int64> x);
+
+        ssa!        ((vm, catch_negative_v1) <ref_int64> e);
+        inst!       ((vm, catch_negative_v1) blk_entry_new:
+            e = NEW <int64>
+        );
+
+        ssa!        ((vm, catch_negative_v1) <iref_int64> e_iref);
+        inst!       ((vm, catch_negative_v1) blk_entry_getiref:
+            e_iref = GETIREF e
+        );
+
+        consta!     ((vm, catch_negative_v1) int64_42_local = int64_42);
+        inst!       ((vm, catch_negative_v1) blk_entry_store:
+            STORE e_iref int64_42_local (is_ptr: false, order: MemoryOrder::Relaxed)
+        );
+
+        block!      ((vm, catch_negative_v1) blk_norm);
+        block!      ((vm, catch_negative_v1) blk_exn);
+        consta!     ((vm, catch_negative_v1) funcref_local = funcref_throw_if_negative);
+        ssa!        ((vm, catch_negative_v1) <int64> res);
+        inst!       ((vm, catch_negative_v1) blk_entry_call:
+            //          0            , 1, 2, 3
+            res = CALL (funcref_local, x, e, res) FUNC(0) (vec![1, 2]) CallConvention::Mu,
+                  normal: blk_norm (vec![DestArg::Normal(3)]),
+                  exc: blk_exn (vec![])
+        );
+
+        define_block!((vm, catch_negative_v1) blk_entry(x) {
+            blk_entry_new, blk_entry_getiref, blk_entry_store, blk_entry_call
+        });
+
+        // %norm(%r):
+        //   RET %r
+        ssa!        ((vm, catch_negative_v1) <int64> r);
+        inst!       ((vm, catch_negative_v1) blk_norm_ret:
+            RET (r)
+        );
+
+        define_block!((vm, catch_negative_v1) blk_norm(r) {
+            blk_norm_ret
+        });
+
+        // %exn() [%exn_arg]:
+        //   %exn_iref = GETIREF %exn_arg
+        //   %v = LOAD %exn_iref
+        //   RET %v
+        ssa!        ((vm, catch_negative_v1) <ref_int64> exn_arg);
+        ssa!        ((vm, catch_negative_v1) <iref_int64> exn_iref);
+        inst!       ((vm, catch_negative_v1) blk_exn_getiref:
+            exn_iref = GETIREF exn_arg
+        );
+
+        ssa!        ((vm, catch_negative_v1) <int64> v);
+        inst!       ((vm, catch_negative_v1) blk_exn_load:
+            v = LOAD exn_iref (is_ptr: false, order: MemoryOrder::SeqCst)
+        );
+
+        inst!       ((vm, catch_negative_v1) blk_exn_ret:
+            RET (v)
+        );
+
+        define_block!((vm, catch_negative_v1) blk_exn() [exn_arg] {
+            blk_exn_getiref, blk_exn_load, blk_exn_ret
+        });
+
+        define_func_ver!((vm) catch_negative_v1 (entry: blk_entry) {
+            blk_entry, blk_norm, blk_exn
+        });
+    }
+
+    vm
+}
@@ -0,0 +1,132 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::linkutils;
+use mu::vm::*;
+
+#[test]
+fn test_inst_sched_mem_order() {
+    let lib = linkutils::aot::compile_fnc("inst_sched_mem_order", &inst_sched_mem_order);
+    unsafe {
+        let inst_sched_mem_order: libloading::Symbol<
+            unsafe extern "C" fn(*mut u64, u64, u64) -> u64,
+        > = lib.get(b"inst_sched_mem_order").unwrap();
+
+        let mut cell: u64 = 0;
+
+        let res = inst_sched_mem_order(&mut cell, 3, 4);
+        println!(
+            "inst_sched_mem_order(&cell, 3, 4) = {}, cell = {}",
+            res, cell
+        );
+        assert_eq!(res, 40);
+        assert_eq!(cell, 4);
+
+        let res = inst_sched_mem_order(&mut cell, 5, 2);
+        println!(
+            "inst_sched_mem_order(&cell, 5, 2) = {}, cell = {}",
+            res, cell
+        );
+        assert_eq!(res, 52);
+        assert_eq!(cell, 2);
+    }
+}
+
+/// stores and loads the same location between independent multiplications
+/// (the scheduler may move the multiplications, but not the memory accesses
+/// across each other)
+fn inst_sched_mem_order() -> VM {
+    let vm = VM::new_with_opts("init_mu --inst-sched=both");
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) uptr_int64 = mu_uptr(int64));
+
+    funcsig!    ((vm) sig = (uptr_int64, int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> inst_sched_mem_order);
+    funcdef!    ((vm) <sig> inst_sched_mem_order VERSION inst_sched_mem_order_v1);
+
+    // blk_entry(p, a, b):
+    block!      ((vm, inst_sched_mem_order_v1) blk_entry);
+    ssa!        ((vm, inst_sched_mem_order_v1) <uptr_int64> p);
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> a);
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> b);
+
+    // STORE p a
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_store_a:
+        STORE p a (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // x = LOAD p
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> x);
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_load_x:
+        x = LOAD p (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // m1 = MUL a b
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> m1);
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_mul_m1:
+        m1 = BINOP (BinOp::Mul) a b
+    );
+
+    // STORE p b
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_store_b:
+        STORE p b (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // y = LOAD p
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> y);
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_load_y:
+        y = LOAD p (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // m2 = MUL x m1
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> m2);
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_mul_m2:
+        m2 = BINOP (BinOp::Mul) x m1
+    );
+
+    // res = ADD m2 y
+    ssa!        ((vm, inst_sched_mem_order_v1) <int64> res);
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_add:
+        res = BINOP (BinOp::Add) m2 y
+    );
+
+    // RET res
+    inst!       ((vm, inst_sched_mem_order_v1) blk_entry_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, inst_sched_mem_order_v1) blk_entry(p, a, b) {
+        blk_entry_store_a,
+        blk_entry_load_x,
+        blk_entry_mul_m1,
+        blk_entry_store_b,
+        blk_entry_load_y,
+        blk_entry_mul_m2,
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) inst_sched_mem_order_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
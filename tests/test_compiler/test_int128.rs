@@ -76,6 +76,60 @@ pub fn add_u128() -> VM {
     vm
 }
 
+#[test]
+fn test_add_u128_linear_scan() {
+    let lib = linkutils::aot::compile_fnc("add_u128_linear_scan", &add_u128_linear_scan);
+
+    unsafe {
+        use std::u64;
+
+        let add_u128_linear_scan: libloading::Symbol<
+            unsafe extern "C" fn(u64, u64, u64, u64) -> (u64, u64),
+        > = lib.get(b"add_u128_linear_scan").unwrap();
+
+        let res = add_u128_linear_scan(1, 0, 1, 0);
+        println!("add_u128_linear_scan(1, 1) = {:?}", res);
+        assert!(res == (2, 0));
+
+        let res = add_u128_linear_scan(u64::MAX, 0, 1, 0);
+        println!("add_u128_linear_scan(u64::MAX, 1) = {:?}", res);
+        assert!(res == (0, 1));
+    }
+}
+
+/// the same as add_u128, but allocates registers with linear scan
+fn add_u128_linear_scan() -> VM {
+    let vm = VM::new_with_opts("init_mu --disable-inline --regalloc-linear-scan");
+
+    typedef!    ((vm) u128 = mu_int(128));
+
+    funcsig!    ((vm) sig = (u128, u128) -> (u128));
+    funcdecl!   ((vm) <sig> add_u128_linear_scan);
+    funcdef!    ((vm) <sig> add_u128_linear_scan VERSION add_u128_linear_scan_v1);
+
+    block!      ((vm, add_u128_linear_scan_v1) blk_entry);
+    ssa!        ((vm, add_u128_linear_scan_v1) <u128> a);
+    ssa!        ((vm, add_u128_linear_scan_v1) <u128> b);
+
+    // sum = Add %a %b
+    ssa!        ((vm, add_u128_linear_scan_v1) <u128> sum);
+    inst!       ((vm, add_u128_linear_scan_v1) blk_entry_add_u128:
+        sum = BINOP (BinOp::Add) a b
+    );
+
+    inst!       ((vm, add_u128_linear_scan_v1) blk_entry_ret:
+        RET (sum)
+    );
+
+    define_block!   ((vm, add_u128_linear_scan_v1) blk_entry(a, b) {
+        blk_entry_add_u128, blk_entry_ret
+    });
+
+    define_func_ver!((vm) add_u128_linear_scan_v1 (entry: blk_entry) {blk_entry});
+
+    vm
+}
+
 #[test]
 fn test_sub_u128() {
     let lib = linkutils::aot::compile_fnc("sub_u128", &sub_u128);
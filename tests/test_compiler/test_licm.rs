@@ -0,0 +1,496 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::linkutils::aot;
+use mu::utils::LinkedHashMap;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+/// does the block have a MUL?
+fn has_mul(block: &Block) -> bool {
+    block
+        .content
+        .as_ref()
+        .unwrap()
+        .body
+        .iter()
+        .any(|node| match node.as_inst().v {
+            Instruction_::BinOp(BinOp::Mul, _, _) => true,
+            _ => false,
+        })
+}
+
+#[test]
+fn test_ir_loop_analysis() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(licm_loop());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::ControlFlowAnalysis::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("licm_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+    let domtree = passes::DomTree::new(content);
+    let loop_analysis = passes::IRLoopAnalysis::new(content, &domtree);
+
+    let entry = vm.id_of("blk_entry");
+    let head = vm.id_of("blk_head");
+    let body = vm.id_of("blk_body");
+    let exit = vm.id_of("blk_exit");
+
+    assert_eq!(loop_analysis.loops().len(), 1);
+    let l = loop_analysis.get_loop(head).unwrap();
+    assert_eq!(l.backedges, vec![body]);
+    assert!(l.blocks.contains(&head));
+    assert!(l.blocks.contains(&body));
+    assert!(!l.blocks.contains(&exit));
+    // the entry block also branches to the exit, so it is not a preheader
+    assert_eq!(l.preheader, None);
+
+    assert_eq!(loop_analysis.loop_depth(entry), 0);
+    assert_eq!(loop_analysis.loop_depth(body), 1);
+    assert_eq!(loop_analysis.loop_depth(exit), 0);
+
+    let tree = loop_analysis.loop_nest_tree();
+    assert_eq!(*tree.root(), entry);
+    assert!(tree.get_children(&entry).contains(&head));
+}
+
+#[test]
+fn test_licm_loop() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(licm_loop());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::LICM::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("licm_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+
+    // %k = MUL %a %b is hoisted to the new preheader
+    assert!(!has_mul(content.get_block(vm.id_of("blk_body"))));
+    let preheaders: Vec<&Block> = content
+        .blocks
+        .values()
+        .filter(|block| block.name().ends_with("_preheader"))
+        .collect();
+    assert_eq!(preheaders.len(), 1);
+    assert!(has_mul(preheaders[0]));
+
+    let lib = linkutils::aot::compile_fnc("licm_loop", &licm_loop);
+    unsafe {
+        let licm_loop: libloading::Symbol<unsafe extern "C" fn(u64, u64, u64) -> u64> =
+            lib.get(b"licm_loop").unwrap();
+
+        let res = licm_loop(3, 4, 5);
+        println!("licm_loop(3, 4, 5) = {}", res);
+        assert_eq!(res, 60);
+
+        let res = licm_loop(3, 4, 0);
+        println!("licm_loop(3, 4, 0) = {}", res);
+        assert_eq!(res, 0);
+    }
+}
+
+/// does the block compute a derived pointer (GETIREF or GETFIELDIREF)?
+fn has_derived_pointer(block: &Block) -> bool {
+    block
+        .content
+        .as_ref()
+        .unwrap()
+        .body
+        .iter()
+        .any(|node| match node.as_inst().v {
+            Instruction_::GetIRef(_) | Instruction_::GetFieldIRef { .. } => true,
+            _ => false,
+        })
+}
+
+#[test]
+fn test_licm_gc_loop_hoists_derived_pointers() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(licm_gc_loop());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::LICM::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("licm_gc_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the loop has a safepoint, the invariant derived pointers are still hoisted
+    // (the register allocator keeps %a alive as long as them)
+    let content = func_ver.content.as_ref().unwrap();
+    assert!(!has_derived_pointer(
+        content.get_block(vm.id_of("blk_body"))
+    ));
+    let preheaders: Vec<&Block> = content
+        .blocks
+        .values()
+        .filter(|block| block.name().ends_with("_preheader"))
+        .collect();
+    assert_eq!(preheaders.len(), 1);
+    assert!(has_derived_pointer(preheaders[0]));
+}
+
+#[test]
+fn test_licm_no_gc_loop_hoists_derived_pointers() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(licm_gc_loop_with_opts("init_mu --gc-disable-collection"));
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::LICM::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("licm_gc_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // without collection, the invariant derived pointers are hoisted
+    let body = func_ver
+        .content
+        .as_ref()
+        .unwrap()
+        .get_block(vm.id_of("blk_body"));
+    assert!(!has_derived_pointer(body));
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_licm_gc_loop() {
+    build_and_run_test!(licm_gc_loop, licm_gc_loop_test1);
+}
+
+fn licm_gc_loop() -> VM {
+    // a small heap, so that the loop triggers several collections
+    // (the garbage does not escape, keep it on the heap)
+    licm_gc_loop_with_opts("init_mu --gc-immixspace-size=4194304 --disable-escape-analysis")
+}
+
+fn licm_gc_loop_with_opts(opts: &str) -> VM {
+    let vm = VM::new_with_opts(opts);
+
+    typedef!    ((vm) int1     = mu_int(1));
+    typedef!    ((vm) int64    = mu_int(64));
+    typedef!    ((vm) array_t  = mu_array(int64, 15));
+    typedef!    ((vm) struct_t = mu_struct(int64, array_t));
+    typedef!    ((vm) ref_struct_t  = mu_ref(struct_t));
+    typedef!    ((vm) iref_struct_t = mu_iref(struct_t));
+    typedef!    ((vm) iref_int64    = mu_iref(int64));
+
+    constdef!   ((vm) <int64> int64_0  = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1  = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_42 = Constant::Int(42));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> licm_gc_loop);
+    funcdef!    ((vm) <sig> licm_gc_loop VERSION licm_gc_loop_v1);
+
+    // %entry(%n):
+    //   %a = NEW <struct_t>
+    //   %a_iref = GETIREF %a
+    //   %a_field0 = GETFIELDIREF %a_iref 0
+    //   STORE %a_field0 42
+    //   %z = SGT %n 0
+    //   BRANCH2 %z %head(0, 0) %exit(0)
+    block!      ((vm, licm_gc_loop_v1) blk_entry);
+    ssa!        ((vm, licm_gc_loop_v1) <int64> n);
+    consta!     ((vm, licm_gc_loop_v1) int64_0_local = int64_0);
+    consta!     ((vm, licm_gc_loop_v1) int64_1_local = int64_1);
+    consta!     ((vm, licm_gc_loop_v1) int64_42_local = int64_42);
+
+    ssa!        ((vm, licm_gc_loop_v1) <ref_struct_t> a);
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_new:
+        a = NEW <struct_t>
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <iref_struct_t> a_iref);
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_getiref:
+        a_iref = GETIREF a
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <iref_int64> a_field0);
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_getfieldiref:
+        a_field0 = GETFIELDIREF a_iref (is_ptr: false, index: 0)
+    );
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_store:
+        STORE a_field0 int64_42_local (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    ssa!        ((vm, licm_gc_loop_v1) <int1> z);
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_cmp:
+        z = CMPOP (CmpOp::SGT) n int64_0_local
+    );
+
+    block!      ((vm, licm_gc_loop_v1) blk_head);
+    block!      ((vm, licm_gc_loop_v1) blk_exit);
+    inst!       ((vm, licm_gc_loop_v1) blk_entry_branch2:
+        BRANCH2 (z, int64_0_local)
+            IF (OP 0)
+            THEN blk_head (vec![1, 1]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!((vm, licm_gc_loop_v1) blk_entry(n) {
+        blk_entry_new, blk_entry_getiref, blk_entry_getfieldiref, blk_entry_store,
+        blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %head(%i, %s):
+    //   %c = SLT %i %n
+    //   BRANCH2 %c %body() %exit(%s)
+    ssa!        ((vm, licm_gc_loop_v1) <int64> i);
+    ssa!        ((vm, licm_gc_loop_v1) <int64> s);
+    ssa!        ((vm, licm_gc_loop_v1) <int1> c);
+    inst!       ((vm, licm_gc_loop_v1) blk_head_cmp:
+        c = CMPOP (CmpOp::SLT) i n
+    );
+
+    block!      ((vm, licm_gc_loop_v1) blk_body);
+    inst!       ((vm, licm_gc_loop_v1) blk_head_branch2:
+        BRANCH2 (c, s)
+            IF (OP 0)
+            THEN blk_body (vec![]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!((vm, licm_gc_loop_v1) blk_head(i, s) {
+        blk_head_cmp, blk_head_branch2
+    });
+
+    // %body():
+    //   %b_iref = GETIREF %a                     (loop-invariant)
+    //   %b_field0 = GETFIELDIREF %b_iref 0       (loop-invariant)
+    //   %garbage = NEW <struct_t>
+    //   %x = LOAD %b_field0
+    //   %s2 = ADD %s %x
+    //   %i2 = ADD %i 1
+    //   BRANCH %head(%i2, %s2)
+    ssa!        ((vm, licm_gc_loop_v1) <iref_struct_t> b_iref);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_getiref:
+        b_iref = GETIREF a
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <iref_int64> b_field0);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_getfieldiref:
+        b_field0 = GETFIELDIREF b_iref (is_ptr: false, index: 0)
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <ref_struct_t> garbage);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_new:
+        garbage = NEW <struct_t>
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <int64> x);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_load:
+        x = LOAD b_field0 (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <int64> s2);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_add_s:
+        s2 = BINOP (BinOp::Add) s x
+    );
+    ssa!        ((vm, licm_gc_loop_v1) <int64> i2);
+    inst!       ((vm, licm_gc_loop_v1) blk_body_add_i:
+        i2 = BINOP (BinOp::Add) i int64_1_local
+    );
+    inst!       ((vm, licm_gc_loop_v1) blk_body_branch:
+        BRANCH blk_head (i2, s2)
+    );
+
+    define_block!((vm, licm_gc_loop_v1) blk_body() {
+        blk_body_getiref, blk_body_getfieldiref, blk_body_new, blk_body_load,
+        blk_body_add_s, blk_body_add_i, blk_body_branch
+    });
+
+    // %exit(%r):
+    //   RET %r
+    ssa!        ((vm, licm_gc_loop_v1) <int64> r);
+    inst!       ((vm, licm_gc_loop_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, licm_gc_loop_v1) blk_exit(r) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) licm_gc_loop_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit
+    });
+
+    // 100000 iterations allocate about 12mb (the heap is 4mb), and the object
+    // allocated before the loop should survive the collections
+    emit_test! ((vm)
+        licm_gc_loop, licm_gc_loop_test1, licm_gc_loop_test1_v1,
+        Int RET Int,
+        EQ,
+        sig,
+        int64(100000u64) RET int64(4200000u64),
+    );
+
+    vm
+}
+
+fn licm_loop() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+
+    funcsig!    ((vm) sig = (int64, int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> licm_loop);
+    funcdef!    ((vm) <sig> licm_loop VERSION licm_loop_v1);
+
+    // %entry(%a, %b, %n):
+    //   %z = SGT %n 0
+    //   BRANCH2 %z %head(0, 0) %exit(0)
+    block!      ((vm, licm_loop_v1) blk_entry);
+    ssa!        ((vm, licm_loop_v1) <int64> a);
+    ssa!        ((vm, licm_loop_v1) <int64> b);
+    ssa!        ((vm, licm_loop_v1) <int64> n);
+    consta!     ((vm, licm_loop_v1) int64_0_local = int64_0);
+    consta!     ((vm, licm_loop_v1) int64_1_local = int64_1);
+
+    ssa!        ((vm, licm_loop_v1) <int1> z);
+    inst!       ((vm, licm_loop_v1) blk_entry_cmp:
+        z = CMPOP (CmpOp::SGT) n int64_0_local
+    );
+
+    block!      ((vm, licm_loop_v1) blk_head);
+    block!      ((vm, licm_loop_v1) blk_exit);
+    inst!       ((vm, licm_loop_v1) blk_entry_branch2:
+        BRANCH2 (z, int64_0_local)
+            IF (OP 0)
+            THEN blk_head (vec![1, 1]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!((vm, licm_loop_v1) blk_entry(a, b, n) {
+        blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %head(%i, %s):
+    //   %c = SLT %i %n
+    //   BRANCH2 %c %body() %exit(%s)
+    ssa!        ((vm, licm_loop_v1) <int64> i);
+    ssa!        ((vm, licm_loop_v1) <int64> s);
+    ssa!        ((vm, licm_loop_v1) <int1> c);
+    inst!       ((vm, licm_loop_v1) blk_head_cmp:
+        c = CMPOP (CmpOp::SLT) i n
+    );
+
+    block!      ((vm, licm_loop_v1) blk_body);
+    inst!       ((vm, licm_loop_v1) blk_head_branch2:
+        BRANCH2 (c, s)
+            IF (OP 0)
+            THEN blk_body (vec![]) WITH 0.9f32,
+            ELSE blk_exit (vec![1])
+    );
+
+    define_block!((vm, licm_loop_v1) blk_head(i, s) {
+        blk_head_cmp, blk_head_branch2
+    });
+
+    // %body():
+    //   %k = MUL %a %b
+    //   %s2 = ADD %s %k
+    //   %i2 = ADD %i 1
+    //   BRANCH %head(%i2, %s2)
+    ssa!        ((vm, licm_loop_v1) <int64> k);
+    ssa!        ((vm, licm_loop_v1) <int64> s2);
+    ssa!        ((vm, licm_loop_v1) <int64> i2);
+    inst!       ((vm, licm_loop_v1) blk_body_mul:
+        k = BINOP (BinOp::Mul) a b
+    );
+    inst!       ((vm, licm_loop_v1) blk_body_add_s:
+        s2 = BINOP (BinOp::Add) s k
+    );
+    inst!       ((vm, licm_loop_v1) blk_body_add_i:
+        i2 = BINOP (BinOp::Add) i int64_1_local
+    );
+    inst!       ((vm, licm_loop_v1) blk_body_branch:
+        BRANCH blk_head (i2, s2)
+    );
+
+    define_block!((vm, licm_loop_v1) blk_body() {
+        blk_body_mul, blk_body_add_s, blk_body_add_i, blk_body_branch
+    });
+
+    // %exit(%r):
+    //   RET %r
+    ssa!        ((vm, licm_loop_v1) <int64> r);
+    inst!       ((vm, licm_loop_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, licm_loop_v1) blk_exit(r) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) licm_loop_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit
+    });
+
+    vm
+}
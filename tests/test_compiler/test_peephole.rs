@@ -0,0 +1,278 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::linkutils;
+use mu::vm::*;
+
+#[test]
+fn test_peephole_mul_const() {
+    let lib = linkutils::aot::compile_fnc("peephole_mul_const", &peephole_mul_const);
+    unsafe {
+        let peephole_mul_const: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"peephole_mul_const").unwrap();
+
+        let res = peephole_mul_const(7);
+        println!("peephole_mul_const(7) = {}", res);
+        assert_eq!(res, 35);
+
+        let res = peephole_mul_const(0);
+        println!("peephole_mul_const(0) = {}", res);
+        assert_eq!(res, 0);
+    }
+}
+
+/// multiplies by a constant (which may become lea)
+fn peephole_mul_const() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_5 = Constant::Int(5));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> peephole_mul_const);
+    funcdef!    ((vm) <sig> peephole_mul_const VERSION peephole_mul_const_v1);
+
+    // blk_entry(a):
+    block!      ((vm, peephole_mul_const_v1) blk_entry);
+    ssa!        ((vm, peephole_mul_const_v1) <int64> a);
+
+    // res = MUL a 5
+    ssa!        ((vm, peephole_mul_const_v1) <int64> res);
+    consta!     ((vm, peephole_mul_const_v1) int64_5_local = int64_5);
+    inst!       ((vm, peephole_mul_const_v1) blk_entry_mul:
+        res = BINOP (BinOp::Mul) a int64_5_local
+    );
+
+    // RET res
+    inst!       ((vm, peephole_mul_const_v1) blk_entry_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, peephole_mul_const_v1) blk_entry(a) {
+        blk_entry_mul,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) peephole_mul_const_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_peephole_shift_add() {
+    let lib = linkutils::aot::compile_fnc("peephole_shift_add", &peephole_shift_add);
+    unsafe {
+        let peephole_shift_add: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"peephole_shift_add").unwrap();
+
+        let res = peephole_shift_add(1, 2);
+        println!("peephole_shift_add(1, 2) = {}", res);
+        assert_eq!(res, 17);
+
+        let res = peephole_shift_add(5, 0);
+        println!("peephole_shift_add(5, 0) = {}", res);
+        assert_eq!(res, 5);
+    }
+}
+
+/// adds a shifted value (which may become lea)
+fn peephole_shift_add() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_3 = Constant::Int(3));
+
+    funcsig!    ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> peephole_shift_add);
+    funcdef!    ((vm) <sig> peephole_shift_add VERSION peephole_shift_add_v1);
+
+    // blk_entry(a, b):
+    block!      ((vm, peephole_shift_add_v1) blk_entry);
+    ssa!        ((vm, peephole_shift_add_v1) <int64> a);
+    ssa!        ((vm, peephole_shift_add_v1) <int64> b);
+
+    // shifted = SHL b 3
+    ssa!        ((vm, peephole_shift_add_v1) <int64> shifted);
+    consta!     ((vm, peephole_shift_add_v1) int64_3_local = int64_3);
+    inst!       ((vm, peephole_shift_add_v1) blk_entry_shl:
+        shifted = BINOP (BinOp::Shl) b int64_3_local
+    );
+
+    // res = ADD a shifted
+    ssa!        ((vm, peephole_shift_add_v1) <int64> res);
+    inst!       ((vm, peephole_shift_add_v1) blk_entry_add:
+        res = BINOP (BinOp::Add) a shifted
+    );
+
+    // RET res
+    inst!       ((vm, peephole_shift_add_v1) blk_entry_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, peephole_shift_add_v1) blk_entry(a, b) {
+        blk_entry_shl,
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) peephole_shift_add_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_peephole_and_eq_zero() {
+    let lib = linkutils::aot::compile_fnc("peephole_and_eq_zero", &peephole_and_eq_zero);
+    unsafe {
+        let peephole_and_eq_zero: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"peephole_and_eq_zero").unwrap();
+
+        let res = peephole_and_eq_zero(6, 1);
+        println!("peephole_and_eq_zero(6, 1) = {}", res);
+        assert_eq!(res, 1);
+
+        let res = peephole_and_eq_zero(6, 2);
+        println!("peephole_and_eq_zero(6, 2) = {}", res);
+        assert_eq!(res, 0);
+    }
+}
+
+/// compares the result of an AND with zero (the AND already sets the flags)
+fn peephole_and_eq_zero() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) int1  = mu_int(1));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+
+    funcsig!    ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> peephole_and_eq_zero);
+    funcdef!    ((vm) <sig> peephole_and_eq_zero VERSION peephole_and_eq_zero_v1);
+
+    // blk_entry(a, b):
+    block!      ((vm, peephole_and_eq_zero_v1) blk_entry);
+    ssa!        ((vm, peephole_and_eq_zero_v1) <int64> a);
+    ssa!        ((vm, peephole_and_eq_zero_v1) <int64> b);
+
+    // masked = AND a b
+    ssa!        ((vm, peephole_and_eq_zero_v1) <int64> masked);
+    inst!       ((vm, peephole_and_eq_zero_v1) blk_entry_and:
+        masked = BINOP (BinOp::And) a b
+    );
+
+    // cond = EQ masked 0
+    ssa!        ((vm, peephole_and_eq_zero_v1) <int1> cond);
+    consta!     ((vm, peephole_and_eq_zero_v1) int64_0_local = int64_0);
+    consta!     ((vm, peephole_and_eq_zero_v1) int64_1_local = int64_1);
+    inst!       ((vm, peephole_and_eq_zero_v1) blk_entry_cmp:
+        cond = CMPOP (CmpOp::EQ) masked int64_0_local
+    );
+
+    // res = SELECT cond 1 0
+    ssa!        ((vm, peephole_and_eq_zero_v1) <int64> res);
+    inst!       ((vm, peephole_and_eq_zero_v1) blk_entry_select:
+        res = SELECT cond int64_1_local int64_0_local
+    );
+
+    // RET res
+    inst!       ((vm, peephole_and_eq_zero_v1) blk_entry_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, peephole_and_eq_zero_v1) blk_entry(a, b) {
+        blk_entry_and,
+        blk_entry_cmp,
+        blk_entry_select,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) peephole_and_eq_zero_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_peephole_load_add() {
+    let lib = linkutils::aot::compile_fnc("peephole_load_add", &peephole_load_add);
+    unsafe {
+        let peephole_load_add: libloading::Symbol<unsafe extern "C" fn(*mut u64, u64) -> u64> =
+            lib.get(b"peephole_load_add").unwrap();
+
+        let mut cell: u64 = 40;
+
+        let res = peephole_load_add(&mut cell, 2);
+        println!("peephole_load_add(&40, 2) = {}", res);
+        assert_eq!(res, 42);
+        assert_eq!(cell, 40);
+    }
+}
+
+/// adds a loaded value (the load may become a memory operand of the add)
+fn peephole_load_add() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) uptr_int64 = mu_uptr(int64));
+
+    funcsig!    ((vm) sig = (uptr_int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> peephole_load_add);
+    funcdef!    ((vm) <sig> peephole_load_add VERSION peephole_load_add_v1);
+
+    // blk_entry(p, a):
+    block!      ((vm, peephole_load_add_v1) blk_entry);
+    ssa!        ((vm, peephole_load_add_v1) <uptr_int64> p);
+    ssa!        ((vm, peephole_load_add_v1) <int64> a);
+
+    // x = LOAD p
+    ssa!        ((vm, peephole_load_add_v1) <int64> x);
+    inst!       ((vm, peephole_load_add_v1) blk_entry_load:
+        x = LOAD p (is_ptr: true, order: MemoryOrder::NotAtomic)
+    );
+
+    // res = ADD a x
+    ssa!        ((vm, peephole_load_add_v1) <int64> res);
+    inst!       ((vm, peephole_load_add_v1) blk_entry_add:
+        res = BINOP (BinOp::Add) a x
+    );
+
+    // RET res
+    inst!       ((vm, peephole_load_add_v1) blk_entry_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, peephole_load_add_v1) blk_entry(p, a) {
+        blk_entry_load,
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) peephole_load_add_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -0,0 +1,255 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::utils::LinkedHashMap;
+use mu::vm::profile;
+use mu::vm::*;
+
+use std::env;
+use std::sync::Arc;
+
+#[test]
+fn test_profile_instrument() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(profile_branch_instrumented());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::Profiling::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("profile_branch");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let fv = func_ver.name();
+    let entry = vm.id_of("blk_entry");
+    let blk_small = vm.id_of("blk_small");
+    let blk_big = vm.id_of("blk_big");
+    let blk_exit = vm.id_of("blk_exit");
+
+    let profile_counters = vm.profile_counters().read().unwrap();
+    assert_eq!(profile_counters.len(), 1);
+    let keys = profile_counters.values().next().unwrap();
+    assert_eq!(
+        *keys,
+        vec![
+            profile::entry_key(&fv),
+            profile::edge_key(&fv, entry, blk_small),
+            profile::edge_key(&fv, entry, blk_big),
+            profile::edge_key(&fv, blk_small, blk_exit),
+            profile::edge_key(&fv, blk_big, blk_exit),
+        ]
+    );
+
+    // the edges of BRANCH2 are split
+    let content = func_ver.content.as_ref().unwrap();
+    let edge_blocks = content
+        .blocks
+        .values()
+        .filter(|block| block.name().contains(":profile_edge"))
+        .count();
+    assert_eq!(edge_blocks, 2);
+
+    let lib = linkutils::aot::compile_fnc("profile_branch", &profile_branch_instrumented);
+    unsafe {
+        let profile_branch: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"profile_branch").unwrap();
+
+        assert_eq!(profile_branch(3), 4);
+        assert_eq!(profile_branch(4), 5);
+        assert_eq!(profile_branch(20), 19);
+
+        let symbol = mangle_name(Arc::new("profile_branch_v1:profile_counters".to_string()));
+        let counters: libloading::Symbol<*const [u64; 5]> = lib.get(symbol.as_bytes()).unwrap();
+        let counts = **counters;
+        println!("counts = {:?}", counts);
+        assert_eq!(counts, [3, 2, 1, 2, 1]);
+    }
+}
+
+#[test]
+fn test_profile_use() {
+    VM::start_logging_trace();
+
+    let path = env::temp_dir().join("test_profile_use.profile");
+    let path = path.to_str().unwrap().to_string();
+    let vm = Arc::new(profile_branch(&format!("init_mu --profile-use={}", path)));
+
+    // the small branch is always taken
+    {
+        let fv = "profile_branch_v1";
+        let entry = vm.id_of("blk_entry");
+        let blk_small = vm.id_of("blk_small");
+        let blk_big = vm.id_of("blk_big");
+        let blk_exit = vm.id_of("blk_exit");
+
+        let mut profile = profile::Profile::new();
+        profile.add(&profile::entry_key(fv), 10);
+        profile.add(&profile::edge_key(fv, entry, blk_small), 10);
+        profile.add(&profile::edge_key(fv, entry, blk_big), 0);
+        profile.add(&profile::edge_key(fv, blk_small, blk_exit), 10);
+        profile.add(&profile::edge_key(fv, blk_big, blk_exit), 0);
+        profile.save(&path).unwrap();
+    }
+
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::Profiling::new()),
+            Box::new(passes::ControlFlowAnalysis::new()),
+        ]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("profile_branch");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+    let entry = content.get_block(vm.id_of("blk_entry"));
+    let blk_small = content.get_block(vm.id_of("blk_small"));
+    let blk_big = content.get_block(vm.id_of("blk_big"));
+
+    let branch = entry.content.as_ref().unwrap().body.last().unwrap();
+    match branch.as_inst().v {
+        Instruction_::Branch2 { true_prob, .. } => assert_eq!(true_prob, 1.0f32),
+        _ => panic!("expect a BRANCH2"),
+    }
+    let edge = entry
+        .control_flow
+        .succs
+        .iter()
+        .find(|edge| edge.target == blk_small.id())
+        .unwrap();
+    assert_eq!(edge.probability, 1.0f32);
+
+    // the block that is never executed is laid out away from the hot trace
+    assert!(blk_big.trace_hint == TraceHint::SlowPath);
+    assert!(blk_small.trace_hint == TraceHint::None);
+}
+
+fn profile_branch_instrumented() -> VM {
+    profile_branch("init_mu --profile-instrument")
+}
+
+fn profile_branch(opts: &str) -> VM {
+    let vm = VM::new_with_opts(opts);
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_10 = Constant::Int(10));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> profile_branch);
+    funcdef!    ((vm) <sig> profile_branch VERSION profile_branch_v1);
+
+    // %entry(%x):
+    //   %c = SLT %x 10
+    //   BRANCH2 %c %small() %big()
+    block!      ((vm, profile_branch_v1) blk_entry);
+    ssa!        ((vm, profile_branch_v1) <int64> x);
+    consta!     ((vm, profile_branch_v1) int64_1_local = int64_1);
+    consta!     ((vm, profile_branch_v1) int64_10_local = int64_10);
+
+    ssa!        ((vm, profile_branch_v1) <int1> c);
+    inst!       ((vm, profile_branch_v1) blk_entry_cmp:
+        c = CMPOP (CmpOp::SLT) x int64_10_local
+    );
+
+    block!      ((vm, profile_branch_v1) blk_small);
+    block!      ((vm, profile_branch_v1) blk_big);
+    inst!       ((vm, profile_branch_v1) blk_entry_branch2:
+        BRANCH2 (c)
+            IF (OP 0)
+            THEN blk_small (vec![]) WITH 0.5f32,
+            ELSE blk_big (vec![])
+    );
+
+    define_block!((vm, profile_branch_v1) blk_entry(x) {
+        blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %small():
+    //   %y = ADD %x 1
+    //   BRANCH %exit(%y)
+    block!      ((vm, profile_branch_v1) blk_exit);
+    ssa!        ((vm, profile_branch_v1) <int64> y);
+    inst!       ((vm, profile_branch_v1) blk_small_add:
+        y = BINOP (BinOp::Add) x int64_1_local
+    );
+    inst!       ((vm, profile_branch_v1) blk_small_branch:
+        BRANCH blk_exit (y)
+    );
+
+    define_block!((vm, profile_branch_v1) blk_small() {
+        blk_small_add, blk_small_branch
+    });
+
+    // %big():
+    //   %z = SUB %x 1
+    //   BRANCH %exit(%z)
+    ssa!        ((vm, profile_branch_v1) <int64> z);
+    inst!       ((vm, profile_branch_v1) blk_big_sub:
+        z = BINOP (BinOp::Sub) x int64_1_local
+    );
+    inst!       ((vm, profile_branch_v1) blk_big_branch:
+        BRANCH blk_exit (z)
+    );
+
+    define_block!((vm, profile_branch_v1) blk_big() {
+        blk_big_sub, blk_big_branch
+    });
+
+    // %exit(%r):
+    //   RET %r
+    ssa!        ((vm, profile_branch_v1) <int64> r);
+    inst!       ((vm, profile_branch_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, profile_branch_v1) blk_exit(r) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) profile_branch_v1 (entry: blk_entry) {
+        blk_entry, blk_small, blk_big, blk_exit
+    });
+
+    vm
+}
@@ -47,6 +47,27 @@ fn get_number_of_moves(fv_id: MuID, vm: &VM) -> usize {
     n_mov_insts
 }
 
+/// returns the number of spill loads and spill stores in the compiled function
+fn get_number_of_spills(fv_id: MuID, vm: &VM) -> (usize, usize) {
+    let cfs = vm.compiled_funcs().read().unwrap();
+    let cf = cfs.get(&fv_id).unwrap().read().unwrap();
+
+    let mut n_loads = 0;
+    let mut n_stores = 0;
+
+    let mc = cf.mc();
+    for i in 0..mc.number_of_insts() {
+        if mc.is_spill_load(i).is_some() {
+            n_loads += 1;
+        }
+        if mc.is_spill_store(i).is_some() {
+            n_stores += 1;
+        }
+    }
+
+    (n_loads, n_stores)
+}
+
 #[test]
 #[allow(unused_variables)]
 fn test_spill1() {
@@ -795,7 +816,8 @@ fn test_coalesce_args() {
 }
 
 fn coalesce_args() -> VM {
-    let vm = VM::new();
+    // no yieldpoint in the prologue (which would introduce more moves)
+    let vm = VM::new_with_opts("init_mu --gc-disable-collection");
 
     typedef!    ((vm) int64 = mu_int(64));
 
@@ -2121,3 +2143,1473 @@ fn coalesce_unusable_reg() -> VM {
 
     vm
 }
+
+#[test]
+fn test_stack_map_ref_across_call() {
+    VM::start_logging_trace();
+
+    let vm = stack_map_ref_across_call();
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+    let func_id = vm.id_of("stack_map_ref_across_call");
+
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let cf_lock = compiled_funcs.get(&func_ver.id()).unwrap();
+        let cf = cf_lock.read().unwrap();
+
+        // we have one callsite, and a is live across it
+        assert_eq!(cf.frame.stack_maps.len(), 1);
+        for (callsite, stack_map) in cf.frame.stack_maps.iter() {
+            println!("stack map for {}: {}", callsite, stack_map);
+            assert_eq!(
+                stack_map.ref_slots.len() + stack_map.ref_callee_saved.len(),
+                1
+            );
+        }
+    }
+}
+
+fn stack_map_ref_across_call() -> VM {
+    // no yieldpoint in the prologue, so that we only have one callsite
+    let vm = VM::new_with_opts("init_mu --gc-disable-collection");
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) ref_int64 = mu_ref(int64));
+
+    // foo
+    funcsig!    ((vm) foo_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <foo_sig> foo);
+    funcdef!    ((vm) <foo_sig> foo VERSION foo_v1);
+
+    ssa!        ((vm, foo_v1) <int64> foo_x);
+    block!      ((vm, foo_v1) blk_entry);
+    inst!       ((vm, foo_v1) blk_entry_ret:
+        RET (foo_x)
+    );
+    define_block!   ((vm, foo_v1) blk_entry(foo_x) {
+        blk_entry_ret
+    });
+    define_func_ver!((vm) foo_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    // stack_map_ref_across_call
+    funcsig!    ((vm) sig = (ref_int64, int64) -> (ref_int64));
+    funcdecl!   ((vm) <sig> stack_map_ref_across_call);
+    funcdef!    ((vm) <sig> stack_map_ref_across_call VERSION stack_map_ref_across_call_v1);
+
+    typedef!    ((vm) type_funcref_foo = mu_funcref(foo_sig));
+    constdef!   ((vm) <type_funcref_foo> const_funcref_foo = Constant::FuncRef(foo.clone()));
+
+    // blk_entry(a, x):
+    ssa!        ((vm, stack_map_ref_across_call_v1) <ref_int64> a);
+    ssa!        ((vm, stack_map_ref_across_call_v1) <int64> x);
+    block!      ((vm, stack_map_ref_across_call_v1) blk_entry);
+
+    // res = EXPRCALL foo (x)
+    ssa!        ((vm, stack_map_ref_across_call_v1) <int64> res);
+    consta!     ((vm, stack_map_ref_across_call_v1) const_funcref_foo_local = const_funcref_foo);
+    inst!       ((vm, stack_map_ref_across_call_v1) blk_entry_call:
+        res = EXPRCALL (CallConvention::Mu, is_abort: false) const_funcref_foo_local (x)
+    );
+
+    // RET a
+    inst!       ((vm, stack_map_ref_across_call_v1) blk_entry_ret:
+        RET (a)
+    );
+
+    define_block!((vm, stack_map_ref_across_call_v1) blk_entry(a, x) {
+        blk_entry_call,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) stack_map_ref_across_call_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_stack_map_spilled_refs_across_call() {
+    VM::start_logging_trace();
+
+    let vm = stack_map_spilled_refs_across_call();
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+    let func_id = vm.id_of("stack_map_spilled_refs_across_call");
+
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let cf_lock = compiled_funcs.get(&func_ver.id()).unwrap();
+        let cf = cf_lock.read().unwrap();
+
+        // 7 references are live across the call to foo, but there are only 5 callee saved
+        // registers, so some of them are spilled (and the stack map records their slots)
+        let mut found = false;
+        for (callsite, stack_map) in cf.frame.stack_maps.iter() {
+            println!("stack map for {}: {}", callsite, stack_map);
+            if stack_map.ref_slots.len() + stack_map.ref_callee_saved.len() == 7 {
+                assert!(stack_map.ref_slots.len() >= 2);
+                found = true;
+            }
+        }
+        assert!(found);
+    }
+}
+
+fn stack_map_spilled_refs_across_call() -> VM {
+    // no yieldpoint in the prologue, so that we only have the callsites of foo and bar
+    let vm = VM::new_with_opts("init_mu --gc-disable-collection --disable-inline");
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) ref_int64 = mu_ref(int64));
+
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+
+    // foo
+    funcsig!    ((vm) foo_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <foo_sig> foo);
+    funcdef!    ((vm) <foo_sig> foo VERSION foo_v1);
+
+    ssa!        ((vm, foo_v1) <int64> foo_x);
+    block!      ((vm, foo_v1) blk_entry);
+    inst!       ((vm, foo_v1) blk_entry_ret:
+        RET (foo_x)
+    );
+    define_block!   ((vm, foo_v1) blk_entry(foo_x) {
+        blk_entry_ret
+    });
+    define_func_ver!((vm) foo_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    // bar
+    funcsig!    ((vm) bar_sig = (ref_int64, ref_int64, ref_int64, ref_int64, ref_int64,
+                                 ref_int64, ref_int64) -> (int64));
+    funcdecl!   ((vm) <bar_sig> bar);
+    funcdef!    ((vm) <bar_sig> bar VERSION bar_v1);
+
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a0);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a1);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a2);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a3);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a4);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a5);
+    ssa!        ((vm, bar_v1) <ref_int64> bar_a6);
+    block!      ((vm, bar_v1) blk_entry);
+    consta!     ((vm, bar_v1) int64_0_local = int64_0);
+    inst!       ((vm, bar_v1) blk_entry_ret:
+        RET (int64_0_local)
+    );
+    define_block!   ((vm, bar_v1) blk_entry(bar_a0, bar_a1, bar_a2, bar_a3, bar_a4, bar_a5,
+                                            bar_a6) {
+        blk_entry_ret
+    });
+    define_func_ver!((vm) bar_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    // stack_map_spilled_refs_across_call
+    funcsig!    ((vm) sig = (ref_int64, ref_int64, ref_int64, ref_int64, ref_int64, ref_int64,
+                             ref_int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> stack_map_spilled_refs_across_call);
+    funcdef!    ((vm) <sig> stack_map_spilled_refs_across_call
+                 VERSION stack_map_spilled_refs_across_call_v1);
+
+    typedef!    ((vm) type_funcref_foo = mu_funcref(foo_sig));
+    constdef!   ((vm) <type_funcref_foo> const_funcref_foo = Constant::FuncRef(foo.clone()));
+    typedef!    ((vm) type_funcref_bar = mu_funcref(bar_sig));
+    constdef!   ((vm) <type_funcref_bar> const_funcref_bar = Constant::FuncRef(bar.clone()));
+
+    // blk_entry(a0, ..., a6, x):
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a0);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a1);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a2);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a3);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a4);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a5);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <ref_int64> a6);
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <int64> x);
+    block!      ((vm, stack_map_spilled_refs_across_call_v1) blk_entry);
+
+    // EXPRCALL foo (x)
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <int64> res);
+    consta!     ((vm, stack_map_spilled_refs_across_call_v1) const_funcref_foo_local =
+                 const_funcref_foo);
+    inst!       ((vm, stack_map_spilled_refs_across_call_v1) blk_entry_call_foo:
+        res = EXPRCALL (CallConvention::Mu, is_abort: false) const_funcref_foo_local (x)
+    );
+
+    // res2 = EXPRCALL bar (a0, ..., a6)
+    ssa!        ((vm, stack_map_spilled_refs_across_call_v1) <int64> res2);
+    consta!     ((vm, stack_map_spilled_refs_across_call_v1) const_funcref_bar_local =
+                 const_funcref_bar);
+    inst!       ((vm, stack_map_spilled_refs_across_call_v1) blk_entry_call_bar:
+        res2 = EXPRCALL (CallConvention::Mu, is_abort: false) const_funcref_bar_local
+            (a0, a1, a2, a3, a4, a5, a6)
+    );
+
+    // RET res2
+    inst!       ((vm, stack_map_spilled_refs_across_call_v1) blk_entry_ret:
+        RET (res2)
+    );
+
+    define_block!((vm, stack_map_spilled_refs_across_call_v1) blk_entry(a0, a1, a2, a3, a4,
+                                                                        a5, a6, x) {
+        blk_entry_call_foo,
+        blk_entry_call_bar,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) stack_map_spilled_refs_across_call_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_linear_scan_spill() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(linear_scan_spill());
+
+    let policy = CompilerPolicy::with_register_allocation(
+        backend::reg_alloc::RegisterAllocation::new_with_algorithm(
+            backend::reg_alloc::RegAllocAlgorithm::LinearScan,
+        ),
+    );
+    let compiler = Compiler::new(policy, &vm);
+
+    let func_id = vm.id_of("linear_scan_spill");
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+    }
+
+    backend::emit_context(&vm);
+
+    let dylib = aot::link_dylib(
+        vec![Mu("linear_scan_spill")],
+        &linkutils::get_dylib_name("linear_scan_spill"),
+        &vm,
+    );
+
+    let lib = libloading::Library::new(dylib.as_os_str()).unwrap();
+    unsafe {
+        let linear_scan_spill: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            match lib.get(b"linear_scan_spill") {
+                Ok(symbol) => symbol,
+                Err(e) => panic!("cannot find symbol linear_scan_spill in dylib: {:?}", e),
+            };
+
+        let res = linear_scan_spill(1);
+        println!("linear_scan_spill(1) = {}", res);
+        assert!(res == 230);
+
+        let res = linear_scan_spill(3);
+        println!("linear_scan_spill(3) = {}", res);
+        assert!(res == 690);
+    }
+}
+
+/// keeps 20 values live at the same time (more than the usable GPRs on x86-64)
+fn linear_scan_spill() -> VM {
+    let vm = VM::new();
+
+    typedef!        ((vm) int64 = mu_int(64));
+
+    funcsig!        ((vm) linear_scan_spill_sig = (int64) -> (int64));
+    funcdecl!       ((vm) <linear_scan_spill_sig> linear_scan_spill);
+    funcdef!        ((vm) <linear_scan_spill_sig> linear_scan_spill VERSION linear_scan_spill_v1);
+
+    // %entry(%a):
+    block!          ((vm, linear_scan_spill_v1) blk_entry);
+    ssa!            ((vm, linear_scan_spill_v1) <int64> a);
+
+    // %t1 = ADD %a %a
+    // %t2 = ADD %t1 %a
+    // ...
+    // %t20 = ADD %t19 %a
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t1);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t1:
+        t1 = BINOP (BinOp::Add) a a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t2);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t2:
+        t2 = BINOP (BinOp::Add) t1 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t3);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t3:
+        t3 = BINOP (BinOp::Add) t2 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t4);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t4:
+        t4 = BINOP (BinOp::Add) t3 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t5);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t5:
+        t5 = BINOP (BinOp::Add) t4 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t6);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t6:
+        t6 = BINOP (BinOp::Add) t5 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t7);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t7:
+        t7 = BINOP (BinOp::Add) t6 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t8);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t8:
+        t8 = BINOP (BinOp::Add) t7 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t9);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t9:
+        t9 = BINOP (BinOp::Add) t8 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t10);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t10:
+        t10 = BINOP (BinOp::Add) t9 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t11);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t11:
+        t11 = BINOP (BinOp::Add) t10 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t12);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t12:
+        t12 = BINOP (BinOp::Add) t11 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t13);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t13:
+        t13 = BINOP (BinOp::Add) t12 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t14);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t14:
+        t14 = BINOP (BinOp::Add) t13 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t15);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t15:
+        t15 = BINOP (BinOp::Add) t14 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t16);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t16:
+        t16 = BINOP (BinOp::Add) t15 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t17);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t17:
+        t17 = BINOP (BinOp::Add) t16 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t18);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t18:
+        t18 = BINOP (BinOp::Add) t17 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t19);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t19:
+        t19 = BINOP (BinOp::Add) t18 a
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> t20);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_t20:
+        t20 = BINOP (BinOp::Add) t19 a
+    );
+
+    // %s1 = ADD %t1 %t2
+    // %s2 = ADD %s1 %t3
+    // ...
+    // %s19 = ADD %s18 %t20
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s1);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s1:
+        s1 = BINOP (BinOp::Add) t1 t2
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s2);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s2:
+        s2 = BINOP (BinOp::Add) s1 t3
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s3);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s3:
+        s3 = BINOP (BinOp::Add) s2 t4
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s4);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s4:
+        s4 = BINOP (BinOp::Add) s3 t5
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s5);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s5:
+        s5 = BINOP (BinOp::Add) s4 t6
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s6);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s6:
+        s6 = BINOP (BinOp::Add) s5 t7
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s7);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s7:
+        s7 = BINOP (BinOp::Add) s6 t8
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s8);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s8:
+        s8 = BINOP (BinOp::Add) s7 t9
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s9);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s9:
+        s9 = BINOP (BinOp::Add) s8 t10
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s10);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s10:
+        s10 = BINOP (BinOp::Add) s9 t11
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s11);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s11:
+        s11 = BINOP (BinOp::Add) s10 t12
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s12);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s12:
+        s12 = BINOP (BinOp::Add) s11 t13
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s13);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s13:
+        s13 = BINOP (BinOp::Add) s12 t14
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s14);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s14:
+        s14 = BINOP (BinOp::Add) s13 t15
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s15);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s15:
+        s15 = BINOP (BinOp::Add) s14 t16
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s16);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s16:
+        s16 = BINOP (BinOp::Add) s15 t17
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s17);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s17:
+        s17 = BINOP (BinOp::Add) s16 t18
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s18);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s18:
+        s18 = BINOP (BinOp::Add) s17 t19
+    );
+    ssa!            ((vm, linear_scan_spill_v1) <int64> s19);
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_add_s19:
+        s19 = BINOP (BinOp::Add) s18 t20
+    );
+
+    // RET %s19
+    inst!           ((vm, linear_scan_spill_v1) blk_entry_ret:
+        RET (s19)
+    );
+
+    define_block!   ((vm, linear_scan_spill_v1) blk_entry(a) {
+        blk_entry_add_t1,
+        blk_entry_add_t2,
+        blk_entry_add_t3,
+        blk_entry_add_t4,
+        blk_entry_add_t5,
+        blk_entry_add_t6,
+        blk_entry_add_t7,
+        blk_entry_add_t8,
+        blk_entry_add_t9,
+        blk_entry_add_t10,
+        blk_entry_add_t11,
+        blk_entry_add_t12,
+        blk_entry_add_t13,
+        blk_entry_add_t14,
+        blk_entry_add_t15,
+        blk_entry_add_t16,
+        blk_entry_add_t17,
+        blk_entry_add_t18,
+        blk_entry_add_t19,
+        blk_entry_add_t20,
+        blk_entry_add_s1,
+        blk_entry_add_s2,
+        blk_entry_add_s3,
+        blk_entry_add_s4,
+        blk_entry_add_s5,
+        blk_entry_add_s6,
+        blk_entry_add_s7,
+        blk_entry_add_s8,
+        blk_entry_add_s9,
+        blk_entry_add_s10,
+        blk_entry_add_s11,
+        blk_entry_add_s12,
+        blk_entry_add_s13,
+        blk_entry_add_s14,
+        blk_entry_add_s15,
+        blk_entry_add_s16,
+        blk_entry_add_s17,
+        blk_entry_add_s18,
+        blk_entry_add_s19,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) linear_scan_spill_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_linear_scan_loop() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(linear_scan_loop());
+
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+
+    let func_id = vm.id_of("linear_scan_loop");
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+    }
+
+    backend::emit_context(&vm);
+
+    let dylib = aot::link_dylib(
+        vec![Mu("linear_scan_loop")],
+        &linkutils::get_dylib_name("linear_scan_loop"),
+        &vm,
+    );
+
+    let lib = libloading::Library::new(dylib.as_os_str()).unwrap();
+    unsafe {
+        let linear_scan_loop: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            match lib.get(b"linear_scan_loop") {
+                Ok(symbol) => symbol,
+                Err(e) => panic!("cannot find symbol linear_scan_loop in dylib: {:?}", e),
+            };
+
+        let res = linear_scan_loop(10);
+        println!("linear_scan_loop(10) = {}", res);
+        assert!(res == 45);
+
+        let res = linear_scan_loop(0);
+        println!("linear_scan_loop(0) = {}", res);
+        assert!(res == 0);
+    }
+}
+
+/// sums 0..n in a loop (the allocator is chosen by the VM option)
+fn linear_scan_loop() -> VM {
+    let vm = VM::new_with_opts("init_mu --regalloc-linear-scan");
+
+    typedef!        ((vm) int64 = mu_int(64));
+    typedef!        ((vm) int1  = mu_int(1));
+    constdef!       ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!       ((vm) <int64> int64_1 = Constant::Int(1));
+
+    funcsig!        ((vm) linear_scan_loop_sig = (int64) -> (int64));
+    funcdecl!       ((vm) <linear_scan_loop_sig> linear_scan_loop);
+    funcdef!        ((vm) <linear_scan_loop_sig> linear_scan_loop VERSION linear_scan_loop_v1);
+
+    // %entry(%n):
+    block!          ((vm, linear_scan_loop_v1) blk_entry);
+    ssa!            ((vm, linear_scan_loop_v1) <int64> n);
+    consta!         ((vm, linear_scan_loop_v1) int64_0_local = int64_0);
+    consta!         ((vm, linear_scan_loop_v1) int64_1_local = int64_1);
+
+    // BRANCH %head(0, 0, %n)
+    block!          ((vm, linear_scan_loop_v1) blk_head);
+    inst!           ((vm, linear_scan_loop_v1) blk_entry_branch:
+        BRANCH blk_head (int64_0_local, int64_0_local, n)
+    );
+
+    define_block!   ((vm, linear_scan_loop_v1) blk_entry(n) {
+        blk_entry_branch
+    });
+
+    // %head(%i, %sum, %head_n):
+    ssa!            ((vm, linear_scan_loop_v1) <int64> i);
+    ssa!            ((vm, linear_scan_loop_v1) <int64> sum);
+    ssa!            ((vm, linear_scan_loop_v1) <int64> head_n);
+
+    // %cond = SLT %i %head_n
+    ssa!            ((vm, linear_scan_loop_v1) <int1> cond);
+    inst!           ((vm, linear_scan_loop_v1) blk_head_cmp:
+        cond = CMPOP (CmpOp::SLT) i head_n
+    );
+
+    // BRANCH2 %cond %body(%i, %sum, %head_n) %exit(%sum)
+    block!          ((vm, linear_scan_loop_v1) blk_body);
+    block!          ((vm, linear_scan_loop_v1) blk_exit);
+    inst!           ((vm, linear_scan_loop_v1) blk_head_branch2:
+        BRANCH2 (cond, i, sum, head_n)
+            IF (OP 0)
+            THEN blk_body (vec![1, 2, 3]) WITH 0.9f32,
+            ELSE blk_exit (vec![2])
+    );
+
+    define_block!   ((vm, linear_scan_loop_v1) blk_head(i, sum, head_n) {
+        blk_head_cmp,
+        blk_head_branch2
+    });
+
+    // %body(%body_i, %body_sum, %body_n):
+    ssa!            ((vm, linear_scan_loop_v1) <int64> body_i);
+    ssa!            ((vm, linear_scan_loop_v1) <int64> body_sum);
+    ssa!            ((vm, linear_scan_loop_v1) <int64> body_n);
+
+    // %sum2 = ADD %body_sum %body_i
+    ssa!            ((vm, linear_scan_loop_v1) <int64> sum2);
+    inst!           ((vm, linear_scan_loop_v1) blk_body_add_sum:
+        sum2 = BINOP (BinOp::Add) body_sum body_i
+    );
+
+    // %i2 = ADD %body_i 1
+    ssa!            ((vm, linear_scan_loop_v1) <int64> i2);
+    inst!           ((vm, linear_scan_loop_v1) blk_body_add_i:
+        i2 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+
+    // BRANCH %head(%i2, %sum2, %body_n)
+    inst!           ((vm, linear_scan_loop_v1) blk_body_branch:
+        BRANCH blk_head (i2, sum2, body_n)
+    );
+
+    define_block!   ((vm, linear_scan_loop_v1) blk_body(body_i, body_sum, body_n) {
+        blk_body_add_sum,
+        blk_body_add_i,
+        blk_body_branch
+    });
+
+    // %exit(%res):
+    ssa!            ((vm, linear_scan_loop_v1) <int64> res);
+
+    // RET %res
+    inst!           ((vm, linear_scan_loop_v1) blk_exit_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, linear_scan_loop_v1) blk_exit(res) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) linear_scan_loop_v1 (entry: blk_entry) {
+        blk_entry,
+        blk_head,
+        blk_body,
+        blk_exit
+    });
+
+    vm
+}
+
+#[test]
+fn test_linear_scan_split() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(linear_scan_split());
+
+    let policy = CompilerPolicy::with_register_allocation(
+        backend::reg_alloc::RegisterAllocation::new_with_algorithm(
+            backend::reg_alloc::RegAllocAlgorithm::LinearScan,
+        ),
+    );
+    let compiler = Compiler::new(policy, &vm);
+
+    let func_id = vm.id_of("linear_scan_split");
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+
+        // %x is spilled across the pressure region, and loaded once for its uses after it
+        // (not once for each use)
+        let (n_loads, n_stores) = get_number_of_spills(func_ver.id(), &vm);
+        println!("spill loads: {}, spill stores: {}", n_loads, n_stores);
+        assert!(n_loads <= n_stores);
+    }
+
+    backend::emit_context(&vm);
+
+    let dylib = aot::link_dylib(
+        vec![Mu("linear_scan_split")],
+        &linkutils::get_dylib_name("linear_scan_split"),
+        &vm,
+    );
+
+    let lib = libloading::Library::new(dylib.as_os_str()).unwrap();
+    unsafe {
+        let linear_scan_split: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            match lib.get(b"linear_scan_split") {
+                Ok(symbol) => symbol,
+                Err(e) => panic!("cannot find symbol linear_scan_split in dylib: {:?}", e),
+            };
+
+        let res = linear_scan_split(1);
+        println!("linear_scan_split(1) = {}", res);
+        assert!(res == 246);
+
+        let res = linear_scan_split(3);
+        println!("linear_scan_split(3) = {}", res);
+        assert!(res == 738);
+    }
+}
+
+/// defines %x before 20 values that are live at the same time, and uses it after them
+fn linear_scan_split() -> VM {
+    let vm = VM::new();
+
+    typedef!        ((vm) int64 = mu_int(64));
+
+    funcsig!        ((vm) linear_scan_split_sig = (int64) -> (int64));
+    funcdecl!       ((vm) <linear_scan_split_sig> linear_scan_split);
+    funcdef!        ((vm) <linear_scan_split_sig> linear_scan_split VERSION linear_scan_split_v1);
+
+    // %entry(%a):
+    block!          ((vm, linear_scan_split_v1) blk_entry);
+    ssa!            ((vm, linear_scan_split_v1) <int64> a);
+
+    // %x = ADD %a %a
+    ssa!            ((vm, linear_scan_split_v1) <int64> x);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_x:
+        x = BINOP (BinOp::Add) a a
+    );
+
+    // %t1 = ADD %a %a
+    // %t2 = ADD %t1 %a
+    // ...
+    // %t20 = ADD %t19 %a
+    ssa!            ((vm, linear_scan_split_v1) <int64> t1);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t1:
+        t1 = BINOP (BinOp::Add) a a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t2);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t2:
+        t2 = BINOP (BinOp::Add) t1 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t3);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t3:
+        t3 = BINOP (BinOp::Add) t2 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t4);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t4:
+        t4 = BINOP (BinOp::Add) t3 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t5);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t5:
+        t5 = BINOP (BinOp::Add) t4 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t6);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t6:
+        t6 = BINOP (BinOp::Add) t5 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t7);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t7:
+        t7 = BINOP (BinOp::Add) t6 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t8);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t8:
+        t8 = BINOP (BinOp::Add) t7 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t9);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t9:
+        t9 = BINOP (BinOp::Add) t8 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t10);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t10:
+        t10 = BINOP (BinOp::Add) t9 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t11);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t11:
+        t11 = BINOP (BinOp::Add) t10 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t12);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t12:
+        t12 = BINOP (BinOp::Add) t11 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t13);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t13:
+        t13 = BINOP (BinOp::Add) t12 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t14);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t14:
+        t14 = BINOP (BinOp::Add) t13 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t15);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t15:
+        t15 = BINOP (BinOp::Add) t14 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t16);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t16:
+        t16 = BINOP (BinOp::Add) t15 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t17);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t17:
+        t17 = BINOP (BinOp::Add) t16 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t18);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t18:
+        t18 = BINOP (BinOp::Add) t17 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t19);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t19:
+        t19 = BINOP (BinOp::Add) t18 a
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> t20);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_t20:
+        t20 = BINOP (BinOp::Add) t19 a
+    );
+
+    // %s1 = ADD %t1 %t2
+    // %s2 = ADD %s1 %t3
+    // ...
+    // %s19 = ADD %s18 %t20
+    ssa!            ((vm, linear_scan_split_v1) <int64> s1);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s1:
+        s1 = BINOP (BinOp::Add) t1 t2
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s2);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s2:
+        s2 = BINOP (BinOp::Add) s1 t3
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s3);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s3:
+        s3 = BINOP (BinOp::Add) s2 t4
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s4);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s4:
+        s4 = BINOP (BinOp::Add) s3 t5
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s5);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s5:
+        s5 = BINOP (BinOp::Add) s4 t6
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s6);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s6:
+        s6 = BINOP (BinOp::Add) s5 t7
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s7);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s7:
+        s7 = BINOP (BinOp::Add) s6 t8
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s8);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s8:
+        s8 = BINOP (BinOp::Add) s7 t9
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s9);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s9:
+        s9 = BINOP (BinOp::Add) s8 t10
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s10);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s10:
+        s10 = BINOP (BinOp::Add) s9 t11
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s11);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s11:
+        s11 = BINOP (BinOp::Add) s10 t12
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s12);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s12:
+        s12 = BINOP (BinOp::Add) s11 t13
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s13);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s13:
+        s13 = BINOP (BinOp::Add) s12 t14
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s14);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s14:
+        s14 = BINOP (BinOp::Add) s13 t15
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s15);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s15:
+        s15 = BINOP (BinOp::Add) s14 t16
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s16);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s16:
+        s16 = BINOP (BinOp::Add) s15 t17
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s17);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s17:
+        s17 = BINOP (BinOp::Add) s16 t18
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s18);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s18:
+        s18 = BINOP (BinOp::Add) s17 t19
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> s19);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_s19:
+        s19 = BINOP (BinOp::Add) s18 t20
+    );
+
+    // %r1 = ADD %s19 %x
+    // %r2 = ADD %r1 %x
+    // ...
+    // %r8 = ADD %r7 %x
+    ssa!            ((vm, linear_scan_split_v1) <int64> r1);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r1:
+        r1 = BINOP (BinOp::Add) s19 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r2);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r2:
+        r2 = BINOP (BinOp::Add) r1 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r3);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r3:
+        r3 = BINOP (BinOp::Add) r2 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r4);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r4:
+        r4 = BINOP (BinOp::Add) r3 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r5);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r5:
+        r5 = BINOP (BinOp::Add) r4 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r6);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r6:
+        r6 = BINOP (BinOp::Add) r5 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r7);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r7:
+        r7 = BINOP (BinOp::Add) r6 x
+    );
+    ssa!            ((vm, linear_scan_split_v1) <int64> r8);
+    inst!           ((vm, linear_scan_split_v1) blk_entry_add_r8:
+        r8 = BINOP (BinOp::Add) r7 x
+    );
+
+    // RET %r8
+    inst!           ((vm, linear_scan_split_v1) blk_entry_ret:
+        RET (r8)
+    );
+
+    define_block!   ((vm, linear_scan_split_v1) blk_entry(a) {
+        blk_entry_add_x,
+        blk_entry_add_t1,
+        blk_entry_add_t2,
+        blk_entry_add_t3,
+        blk_entry_add_t4,
+        blk_entry_add_t5,
+        blk_entry_add_t6,
+        blk_entry_add_t7,
+        blk_entry_add_t8,
+        blk_entry_add_t9,
+        blk_entry_add_t10,
+        blk_entry_add_t11,
+        blk_entry_add_t12,
+        blk_entry_add_t13,
+        blk_entry_add_t14,
+        blk_entry_add_t15,
+        blk_entry_add_t16,
+        blk_entry_add_t17,
+        blk_entry_add_t18,
+        blk_entry_add_t19,
+        blk_entry_add_t20,
+        blk_entry_add_s1,
+        blk_entry_add_s2,
+        blk_entry_add_s3,
+        blk_entry_add_s4,
+        blk_entry_add_s5,
+        blk_entry_add_s6,
+        blk_entry_add_s7,
+        blk_entry_add_s8,
+        blk_entry_add_s9,
+        blk_entry_add_s10,
+        blk_entry_add_s11,
+        blk_entry_add_s12,
+        blk_entry_add_s13,
+        blk_entry_add_s14,
+        blk_entry_add_s15,
+        blk_entry_add_s16,
+        blk_entry_add_s17,
+        blk_entry_add_s18,
+        blk_entry_add_s19,
+        blk_entry_add_r1,
+        blk_entry_add_r2,
+        blk_entry_add_r3,
+        blk_entry_add_r4,
+        blk_entry_add_r5,
+        blk_entry_add_r6,
+        blk_entry_add_r7,
+        blk_entry_add_r8,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) linear_scan_split_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_linear_scan_split_loop() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(linear_scan_split_loop());
+
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+
+    let func_id = vm.id_of("linear_scan_split_loop");
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+    }
+
+    backend::emit_context(&vm);
+
+    let dylib = aot::link_dylib(
+        vec![Mu("linear_scan_split_loop")],
+        &linkutils::get_dylib_name("linear_scan_split_loop"),
+        &vm,
+    );
+
+    let lib = libloading::Library::new(dylib.as_os_str()).unwrap();
+    unsafe {
+        let linear_scan_split_loop: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            match lib.get(b"linear_scan_split_loop") {
+                Ok(symbol) => symbol,
+                Err(e) => panic!(
+                    "cannot find symbol linear_scan_split_loop in dylib: {:?}",
+                    e
+                ),
+            };
+
+        let res = linear_scan_split_loop(10);
+        println!("linear_scan_split_loop(10) = {}", res);
+        assert!(res == 6280);
+
+        let res = linear_scan_split_loop(0);
+        println!("linear_scan_split_loop(0) = {}", res);
+        assert!(res == 0);
+    }
+}
+
+/// a loop whose body keeps 16 values live at the same time, so the loop variables are split
+/// (and moved at the edges of the loop)
+fn linear_scan_split_loop() -> VM {
+    let vm = VM::new_with_opts("init_mu --regalloc-linear-scan");
+
+    typedef!        ((vm) int64 = mu_int(64));
+    typedef!        ((vm) int1  = mu_int(1));
+    constdef!       ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!       ((vm) <int64> int64_1 = Constant::Int(1));
+
+    funcsig!        ((vm) linear_scan_split_loop_sig = (int64) -> (int64));
+    funcdecl!       ((vm) <linear_scan_split_loop_sig> linear_scan_split_loop);
+    funcdef!        ((vm) <linear_scan_split_loop_sig> linear_scan_split_loop
+                     VERSION linear_scan_split_loop_v1);
+
+    // %entry(%n):
+    block!          ((vm, linear_scan_split_loop_v1) blk_entry);
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> n);
+    consta!         ((vm, linear_scan_split_loop_v1) int64_0_local = int64_0);
+    consta!         ((vm, linear_scan_split_loop_v1) int64_1_local = int64_1);
+
+    // BRANCH %head(0, 0, %n)
+    block!          ((vm, linear_scan_split_loop_v1) blk_head);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_entry_branch:
+        BRANCH blk_head (int64_0_local, int64_0_local, n)
+    );
+
+    define_block!   ((vm, linear_scan_split_loop_v1) blk_entry(n) {
+        blk_entry_branch
+    });
+
+    // %head(%i, %sum, %head_n):
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> i);
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> sum);
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> head_n);
+
+    // %cond = SLT %i %head_n
+    ssa!            ((vm, linear_scan_split_loop_v1) <int1> cond);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_head_cmp:
+        cond = CMPOP (CmpOp::SLT) i head_n
+    );
+
+    // BRANCH2 %cond %body(%i, %sum, %head_n) %exit(%sum)
+    block!          ((vm, linear_scan_split_loop_v1) blk_body);
+    block!          ((vm, linear_scan_split_loop_v1) blk_exit);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_head_branch2:
+        BRANCH2 (cond, i, sum, head_n)
+            IF (OP 0)
+            THEN blk_body (vec![1, 2, 3]) WITH 0.9f32,
+            ELSE blk_exit (vec![2])
+    );
+
+    define_block!   ((vm, linear_scan_split_loop_v1) blk_head(i, sum, head_n) {
+        blk_head_cmp,
+        blk_head_branch2
+    });
+
+    // %body(%body_i, %body_sum, %body_n):
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> body_i);
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> body_sum);
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> body_n);
+
+    // %t1 = ADD %body_i 1
+    // %t2 = ADD %t1 %body_i
+    // ...
+    // %t16 = ADD %t15 %body_i
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t1);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t1:
+        t1 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t2);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t2:
+        t2 = BINOP (BinOp::Add) t1 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t3);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t3:
+        t3 = BINOP (BinOp::Add) t2 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t4);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t4:
+        t4 = BINOP (BinOp::Add) t3 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t5);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t5:
+        t5 = BINOP (BinOp::Add) t4 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t6);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t6:
+        t6 = BINOP (BinOp::Add) t5 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t7);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t7:
+        t7 = BINOP (BinOp::Add) t6 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t8);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t8:
+        t8 = BINOP (BinOp::Add) t7 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t9);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t9:
+        t9 = BINOP (BinOp::Add) t8 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t10);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t10:
+        t10 = BINOP (BinOp::Add) t9 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t11);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t11:
+        t11 = BINOP (BinOp::Add) t10 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t12);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t12:
+        t12 = BINOP (BinOp::Add) t11 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t13);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t13:
+        t13 = BINOP (BinOp::Add) t12 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t14);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t14:
+        t14 = BINOP (BinOp::Add) t13 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t15);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t15:
+        t15 = BINOP (BinOp::Add) t14 body_i
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> t16);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_t16:
+        t16 = BINOP (BinOp::Add) t15 body_i
+    );
+
+    // %s2 = ADD %t1 %t2
+    // %s3 = ADD %s2 %t3
+    // ...
+    // %s16 = ADD %s15 %t16
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s2);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s2:
+        s2 = BINOP (BinOp::Add) t1 t2
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s3);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s3:
+        s3 = BINOP (BinOp::Add) s2 t3
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s4);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s4:
+        s4 = BINOP (BinOp::Add) s3 t4
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s5);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s5:
+        s5 = BINOP (BinOp::Add) s4 t5
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s6);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s6:
+        s6 = BINOP (BinOp::Add) s5 t6
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s7);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s7:
+        s7 = BINOP (BinOp::Add) s6 t7
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s8);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s8:
+        s8 = BINOP (BinOp::Add) s7 t8
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s9);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s9:
+        s9 = BINOP (BinOp::Add) s8 t9
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s10);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s10:
+        s10 = BINOP (BinOp::Add) s9 t10
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s11);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s11:
+        s11 = BINOP (BinOp::Add) s10 t11
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s12);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s12:
+        s12 = BINOP (BinOp::Add) s11 t12
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s13);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s13:
+        s13 = BINOP (BinOp::Add) s12 t13
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s14);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s14:
+        s14 = BINOP (BinOp::Add) s13 t14
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s15);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s15:
+        s15 = BINOP (BinOp::Add) s14 t15
+    );
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> s16);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_s16:
+        s16 = BINOP (BinOp::Add) s15 t16
+    );
+
+    // %sum2 = ADD %body_sum %s16
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> sum2);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_sum:
+        sum2 = BINOP (BinOp::Add) body_sum s16
+    );
+
+    // %i2 = ADD %body_i 1
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> i2);
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_add_i:
+        i2 = BINOP (BinOp::Add) body_i int64_1_local
+    );
+
+    // BRANCH %head(%i2, %sum2, %body_n)
+    inst!           ((vm, linear_scan_split_loop_v1) blk_body_branch:
+        BRANCH blk_head (i2, sum2, body_n)
+    );
+
+    define_block!   ((vm, linear_scan_split_loop_v1) blk_body(body_i, body_sum, body_n) {
+        blk_body_add_t1,
+        blk_body_add_t2,
+        blk_body_add_t3,
+        blk_body_add_t4,
+        blk_body_add_t5,
+        blk_body_add_t6,
+        blk_body_add_t7,
+        blk_body_add_t8,
+        blk_body_add_t9,
+        blk_body_add_t10,
+        blk_body_add_t11,
+        blk_body_add_t12,
+        blk_body_add_t13,
+        blk_body_add_t14,
+        blk_body_add_t15,
+        blk_body_add_t16,
+        blk_body_add_s2,
+        blk_body_add_s3,
+        blk_body_add_s4,
+        blk_body_add_s5,
+        blk_body_add_s6,
+        blk_body_add_s7,
+        blk_body_add_s8,
+        blk_body_add_s9,
+        blk_body_add_s10,
+        blk_body_add_s11,
+        blk_body_add_s12,
+        blk_body_add_s13,
+        blk_body_add_s14,
+        blk_body_add_s15,
+        blk_body_add_s16,
+        blk_body_add_sum,
+        blk_body_add_i,
+        blk_body_branch
+    });
+
+    // %exit(%res):
+    ssa!            ((vm, linear_scan_split_loop_v1) <int64> res);
+
+    // RET %res
+    inst!           ((vm, linear_scan_split_loop_v1) blk_exit_ret:
+        RET (res)
+    );
+
+    define_block!   ((vm, linear_scan_split_loop_v1) blk_exit(res) {
+        blk_exit_ret
+    });
+
+    define_func_ver!((vm) linear_scan_split_loop_v1 (entry: blk_entry) {
+        blk_entry,
+        blk_head,
+        blk_body,
+        blk_exit
+    });
+
+    vm
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_stack_map_iref_base_across_call() {
+    VM::start_logging_trace();
+
+    let vm = stack_map_iref_base_across_call();
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+    let func_id = vm.id_of("stack_map_iref_base_across_call");
+
+    {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let cf_lock = compiled_funcs.get(&func_ver.id()).unwrap();
+        let cf = cf_lock.read().unwrap();
+
+        // a_iref is not recorded, but its base a is kept alive across every callsite
+        assert!(cf.frame.stack_maps.len() >= 1);
+        for (callsite, stack_map) in cf.frame.stack_maps.iter() {
+            println!("stack map for {}: {}", callsite, stack_map);
+            assert_eq!(
+                stack_map.ref_slots.len() + stack_map.ref_callee_saved.len(),
+                1
+            );
+        }
+    }
+}
+
+fn stack_map_iref_base_across_call() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    typedef!    ((vm) ref_int64 = mu_ref(int64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+
+    // foo
+    funcsig!    ((vm) foo_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <foo_sig> foo);
+    funcdef!    ((vm) <foo_sig> foo VERSION foo_v1);
+
+    ssa!        ((vm, foo_v1) <int64> foo_x);
+    block!      ((vm, foo_v1) blk_entry);
+    inst!       ((vm, foo_v1) blk_entry_ret:
+        RET (foo_x)
+    );
+    define_block!   ((vm, foo_v1) blk_entry(foo_x) {
+        blk_entry_ret
+    });
+    define_func_ver!((vm) foo_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    // stack_map_iref_base_across_call
+    funcsig!    ((vm) sig = (ref_int64, int64) -> (int64));
+    funcdecl!   ((vm) <sig> stack_map_iref_base_across_call);
+    funcdef!    ((vm) <sig> stack_map_iref_base_across_call
+                 VERSION stack_map_iref_base_across_call_v1);
+
+    typedef!    ((vm) type_funcref_foo = mu_funcref(foo_sig));
+    constdef!   ((vm) <type_funcref_foo> const_funcref_foo = Constant::FuncRef(foo.clone()));
+
+    // blk_entry(a, x):
+    ssa!        ((vm, stack_map_iref_base_across_call_v1) <ref_int64> a);
+    ssa!        ((vm, stack_map_iref_base_across_call_v1) <int64> x);
+    block!      ((vm, stack_map_iref_base_across_call_v1) blk_entry);
+
+    // a_iref = GETIREF a
+    ssa!        ((vm, stack_map_iref_base_across_call_v1) <iref_int64> a_iref);
+    inst!       ((vm, stack_map_iref_base_across_call_v1) blk_entry_getiref:
+        a_iref = GETIREF a
+    );
+
+    // res = EXPRCALL foo (x)
+    ssa!        ((vm, stack_map_iref_base_across_call_v1) <int64> res);
+    consta!     ((vm, stack_map_iref_base_across_call_v1) const_funcref_foo_local = const_funcref_foo);
+    inst!       ((vm, stack_map_iref_base_across_call_v1) blk_entry_call:
+        res = EXPRCALL (CallConvention::Mu, is_abort: false) const_funcref_foo_local (x)
+    );
+
+    // v = LOAD a_iref
+    ssa!        ((vm, stack_map_iref_base_across_call_v1) <int64> v);
+    inst!       ((vm, stack_map_iref_base_across_call_v1) blk_entry_load:
+        v = LOAD a_iref (is_ptr: false, order: MemoryOrder::Relaxed)
+    );
+
+    // RET v
+    inst!       ((vm, stack_map_iref_base_across_call_v1) blk_entry_ret:
+        RET (v)
+    );
+
+    define_block!((vm, stack_map_iref_base_across_call_v1) blk_entry(a, x) {
+        blk_entry_getiref,
+        blk_entry_call,
+        blk_entry_load,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) stack_map_iref_base_across_call_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
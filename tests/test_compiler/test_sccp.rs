@@ -0,0 +1,964 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate libloading;
+
+use mu::ast::inst::*;
+use mu::ast::ir::*;
+use mu::ast::op::*;
+use mu::ast::ptr::*;
+use mu::ast::types::*;
+use mu::compiler::*;
+use mu::linkutils;
+use mu::utils::LinkedHashMap;
+use mu::vm::*;
+
+use std::sync::Arc;
+
+/// returns the instructions of the function
+fn all_insts(func_ver: &MuFunctionVersion) -> Vec<Instruction> {
+    let content = func_ver.content.as_ref().unwrap();
+    let mut ret = vec![];
+    for block in content.blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            ret.push(node.as_inst().clone());
+        }
+    }
+    ret
+}
+
+/// returns the last instruction of the block
+fn last_inst(func_ver: &MuFunctionVersion, block: MuID) -> Instruction {
+    let content = func_ver.content.as_ref().unwrap();
+    let body = &content.get_block(block).content.as_ref().unwrap().body;
+    body.last().unwrap().as_inst().clone()
+}
+
+/// returns the operands of the RET at the end of the block
+fn ret_values(func_ver: &MuFunctionVersion, block: MuID) -> Vec<P<TreeNode>> {
+    let ret = last_inst(func_ver, block);
+    match ret.v {
+        Instruction_::Return(ref vals) => vals.iter().map(|i| ret.ops[*i].clone()).collect(),
+        _ => panic!("expect a RET, found {}", ret),
+    }
+}
+
+#[test]
+fn test_sccp_branch() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_branch());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_branch");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+    // the false branch is never taken
+    assert!(!content.blocks.contains_key(&vm.id_of("blk_false")));
+
+    // the entry block only branches to the true branch with the folded value
+    let entry = content.get_entry_block().content.as_ref().unwrap();
+    assert_eq!(entry.body.len(), 1);
+    let branch = last_inst(&func_ver, vm.id_of("blk_entry"));
+    match branch.v {
+        Instruction_::Branch1(ref dest) => {
+            assert_eq!(dest.target.id(), vm.id_of("blk_true"));
+            let args = dest.get_arguments(&branch.ops);
+            assert_eq!(args[0].extract_int_const(), Some(7));
+        }
+        _ => panic!("expect a BRANCH, found {}", branch),
+    }
+
+    let lib = linkutils::aot::compile_fnc("sccp_branch", &sccp_branch);
+    unsafe {
+        let sccp_branch: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"sccp_branch").unwrap();
+
+        let res = sccp_branch(1);
+        println!("sccp_branch(1) = {}", res);
+        assert_eq!(res, 8);
+    }
+}
+
+fn sccp_branch() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_3  = Constant::Int(3));
+    constdef!   ((vm) <int64> int64_4  = Constant::Int(4));
+    constdef!   ((vm) <int64> int64_10 = Constant::Int(10));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> sccp_branch);
+    funcdef!    ((vm) <sig> sccp_branch VERSION sccp_branch_v1);
+
+    // %entry(%a):
+    block!      ((vm, sccp_branch_v1) blk_entry);
+    ssa!        ((vm, sccp_branch_v1) <int64> a);
+    consta!     ((vm, sccp_branch_v1) int64_3_local = int64_3);
+    consta!     ((vm, sccp_branch_v1) int64_4_local = int64_4);
+    consta!     ((vm, sccp_branch_v1) int64_10_local = int64_10);
+
+    // %x = ADD 3 4
+    ssa!        ((vm, sccp_branch_v1) <int64> x);
+    inst!       ((vm, sccp_branch_v1) blk_entry_add:
+        x = BINOP (BinOp::Add) int64_3_local int64_4_local
+    );
+
+    // %c = SLT %x 10
+    ssa!        ((vm, sccp_branch_v1) <int1> c);
+    inst!       ((vm, sccp_branch_v1) blk_entry_cmp:
+        c = CMPOP (CmpOp::SLT) x int64_10_local
+    );
+
+    // BRANCH2 %c %blk_true(%x) %blk_false(%a)
+    block!      ((vm, sccp_branch_v1) blk_true);
+    block!      ((vm, sccp_branch_v1) blk_false);
+    inst!       ((vm, sccp_branch_v1) blk_entry_branch2:
+        BRANCH2 (c, x, a)
+            IF (OP 0)
+            THEN blk_true (vec![1]) WITH 0.5f32,
+            ELSE blk_false (vec![2])
+    );
+
+    define_block!((vm, sccp_branch_v1) blk_entry(a) {
+        blk_entry_add, blk_entry_cmp, blk_entry_branch2
+    });
+
+    // %blk_true(%t):
+    //   %r = ADD %t %a
+    //   RET %r
+    ssa!        ((vm, sccp_branch_v1) <int64> t);
+    ssa!        ((vm, sccp_branch_v1) <int64> r);
+    inst!       ((vm, sccp_branch_v1) blk_true_add:
+        r = BINOP (BinOp::Add) t a
+    );
+    inst!       ((vm, sccp_branch_v1) blk_true_ret:
+        RET (r)
+    );
+
+    define_block!((vm, sccp_branch_v1) blk_true(t) {
+        blk_true_add, blk_true_ret
+    });
+
+    // %blk_false(%f):
+    //   RET %f
+    ssa!        ((vm, sccp_branch_v1) <int64> f);
+    inst!       ((vm, sccp_branch_v1) blk_false_ret:
+        RET (f)
+    );
+
+    define_block!((vm, sccp_branch_v1) blk_false(f) {
+        blk_false_ret
+    });
+
+    define_func_ver!((vm) sccp_branch_v1 (entry: blk_entry) {
+        blk_entry, blk_true, blk_false
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_loop() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_loop());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // %x is 5 on every edge into the loop, so the exit returns a constant
+    let ret = last_inst(&func_ver, vm.id_of("blk_exit"));
+    match ret.v {
+        Instruction_::Return(ref vals) => {
+            assert_eq!(ret.ops[vals[0]].as_value().extract_int_const(), Some(10));
+        }
+        _ => panic!("expect a RET, found {}", ret),
+    }
+
+    // the loop condition is not known
+    match last_inst(&func_ver, vm.id_of("blk_head")).v {
+        Instruction_::Branch2 { .. } => {}
+        _ => panic!("expect the loop condition to remain"),
+    }
+
+    let lib = linkutils::aot::compile_fnc("sccp_loop", &sccp_loop);
+    unsafe {
+        let sccp_loop: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"sccp_loop").unwrap();
+
+        let res = sccp_loop(3);
+        println!("sccp_loop(3) = {}", res);
+        assert_eq!(res, 10);
+    }
+}
+
+fn sccp_loop() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_2 = Constant::Int(2));
+    constdef!   ((vm) <int64> int64_5 = Constant::Int(5));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> sccp_loop);
+    funcdef!    ((vm) <sig> sccp_loop VERSION sccp_loop_v1);
+
+    // %entry(%n):
+    //   BRANCH %head(5, %n)
+    block!      ((vm, sccp_loop_v1) blk_entry);
+    ssa!        ((vm, sccp_loop_v1) <int64> n);
+    consta!     ((vm, sccp_loop_v1) int64_0_local = int64_0);
+    consta!     ((vm, sccp_loop_v1) int64_1_local = int64_1);
+    consta!     ((vm, sccp_loop_v1) int64_2_local = int64_2);
+    consta!     ((vm, sccp_loop_v1) int64_5_local = int64_5);
+
+    block!      ((vm, sccp_loop_v1) blk_head);
+    inst!       ((vm, sccp_loop_v1) blk_entry_branch:
+        BRANCH blk_head (int64_5_local, n)
+    );
+
+    define_block!((vm, sccp_loop_v1) blk_entry(n) {
+        blk_entry_branch
+    });
+
+    // %head(%x, %i):
+    //   %c = EQ %i 0
+    //   BRANCH2 %c %exit(%x) %body(%x, %i)
+    ssa!        ((vm, sccp_loop_v1) <int64> x);
+    ssa!        ((vm, sccp_loop_v1) <int64> i);
+    ssa!        ((vm, sccp_loop_v1) <int1> c);
+    inst!       ((vm, sccp_loop_v1) blk_head_cmp:
+        c = CMPOP (CmpOp::EQ) i int64_0_local
+    );
+
+    block!      ((vm, sccp_loop_v1) blk_exit);
+    block!      ((vm, sccp_loop_v1) blk_body);
+    inst!       ((vm, sccp_loop_v1) blk_head_branch2:
+        BRANCH2 (c, x, i)
+            IF (OP 0)
+            THEN blk_exit (vec![1]) WITH 0.1f32,
+            ELSE blk_body (vec![1, 2])
+    );
+
+    define_block!((vm, sccp_loop_v1) blk_head(x, i) {
+        blk_head_cmp, blk_head_branch2
+    });
+
+    // %body(%bx, %bi):
+    //   %bi2 = SUB %bi 1
+    //   BRANCH %head(%bx, %bi2)
+    ssa!        ((vm, sccp_loop_v1) <int64> bx);
+    ssa!        ((vm, sccp_loop_v1) <int64> bi);
+    ssa!        ((vm, sccp_loop_v1) <int64> bi2);
+    inst!       ((vm, sccp_loop_v1) blk_body_sub:
+        bi2 = BINOP (BinOp::Sub) bi int64_1_local
+    );
+    inst!       ((vm, sccp_loop_v1) blk_body_branch:
+        BRANCH blk_head (bx, bi2)
+    );
+
+    define_block!((vm, sccp_loop_v1) blk_body(bx, bi) {
+        blk_body_sub, blk_body_branch
+    });
+
+    // %exit(%ex):
+    //   %r = MUL %ex 2
+    //   RET %r
+    ssa!        ((vm, sccp_loop_v1) <int64> ex);
+    ssa!        ((vm, sccp_loop_v1) <int64> r);
+    inst!       ((vm, sccp_loop_v1) blk_exit_mul:
+        r = BINOP (BinOp::Mul) ex int64_2_local
+    );
+    inst!       ((vm, sccp_loop_v1) blk_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, sccp_loop_v1) blk_exit(ex) {
+        blk_exit_mul, blk_exit_ret
+    });
+
+    define_func_ver!((vm) sccp_loop_v1 (entry: blk_entry) {
+        blk_entry, blk_head, blk_body, blk_exit
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_int128_switch() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_int128_switch());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_int128_switch");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let content = func_ver.content.as_ref().unwrap();
+    // (2^64 - 1 + 1) >> 64 is 1, so the SWITCH always takes %blk_one
+    assert!(content.blocks.contains_key(&vm.id_of("blk_one")));
+    assert!(!content.blocks.contains_key(&vm.id_of("blk_default")));
+    match last_inst(&func_ver, vm.id_of("blk_entry")).v {
+        Instruction_::Branch1(_) => {}
+        _ => panic!("expect the SWITCH to be a BRANCH"),
+    }
+
+    // division by zero is left to the machine
+    let body = &content
+        .get_block(vm.id_of("blk_one"))
+        .content
+        .as_ref()
+        .unwrap()
+        .body;
+    assert!(body.iter().any(|node| match node.as_inst().v {
+        Instruction_::BinOp(BinOp::Udiv, _, _) => true,
+        _ => false,
+    }));
+}
+
+fn sccp_int128_switch() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64  = mu_int(64));
+    typedef!    ((vm) int128 = mu_int(128));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int128> int128_max64 = Constant::IntEx(vec![::std::u64::MAX, 0]));
+    constdef!   ((vm) <int128> int128_1 = Constant::IntEx(vec![1, 0]));
+    constdef!   ((vm) <int128> int128_64 = Constant::IntEx(vec![64, 0]));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> sccp_int128_switch);
+    funcdef!    ((vm) <sig> sccp_int128_switch VERSION sccp_int128_switch_v1);
+
+    // %entry(%a):
+    block!      ((vm, sccp_int128_switch_v1) blk_entry);
+    ssa!        ((vm, sccp_int128_switch_v1) <int64> a);
+    consta!     ((vm, sccp_int128_switch_v1) int64_0_local = int64_0);
+    consta!     ((vm, sccp_int128_switch_v1) int64_1_local = int64_1);
+    consta!     ((vm, sccp_int128_switch_v1) int128_max64_local = int128_max64);
+    consta!     ((vm, sccp_int128_switch_v1) int128_1_local = int128_1);
+    consta!     ((vm, sccp_int128_switch_v1) int128_64_local = int128_64);
+
+    // %x = ADD (2^64 - 1) 1
+    ssa!        ((vm, sccp_int128_switch_v1) <int128> x);
+    inst!       ((vm, sccp_int128_switch_v1) blk_entry_add:
+        x = BINOP (BinOp::Add) int128_max64_local int128_1_local
+    );
+    // %y = LSHR %x 64
+    ssa!        ((vm, sccp_int128_switch_v1) <int128> y);
+    inst!       ((vm, sccp_int128_switch_v1) blk_entry_lshr:
+        y = BINOP (BinOp::Lshr) x int128_64_local
+    );
+    // %z = TRUNC <int128 int64> %y
+    ssa!        ((vm, sccp_int128_switch_v1) <int64> z);
+    inst!       ((vm, sccp_int128_switch_v1) blk_entry_trunc:
+        z = CONVOP (ConvOp::TRUNC) <int128 int64> y
+    );
+
+    // SWITCH %z %blk_default (0 -> %blk_default, 1 -> %blk_one)
+    block!      ((vm, sccp_int128_switch_v1) blk_one);
+    block!      ((vm, sccp_int128_switch_v1) blk_default);
+    let blk_entry_switch = sccp_int128_switch_v1.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: vec![z.clone(), int64_0_local.clone(), int64_1_local.clone()],
+        v: Instruction_::Switch {
+            cond: 0,
+            default: Destination {
+                target: blk_default.hdr.clone(),
+                args: vec![],
+            },
+            branches: vec![
+                (
+                    1,
+                    Destination {
+                        target: blk_default.hdr.clone(),
+                        args: vec![],
+                    },
+                ),
+                (
+                    2,
+                    Destination {
+                        target: blk_one.hdr.clone(),
+                        args: vec![],
+                    },
+                ),
+            ],
+        },
+    });
+
+    define_block!((vm, sccp_int128_switch_v1) blk_entry(a) {
+        blk_entry_add, blk_entry_lshr, blk_entry_trunc, blk_entry_switch
+    });
+
+    // %blk_one():
+    //   %d = UDIV 1 0
+    //   RET %d
+    ssa!        ((vm, sccp_int128_switch_v1) <int64> d);
+    inst!       ((vm, sccp_int128_switch_v1) blk_one_div:
+        d = BINOP (BinOp::Udiv) int64_1_local int64_0_local
+    );
+    inst!       ((vm, sccp_int128_switch_v1) blk_one_ret:
+        RET (d)
+    );
+
+    define_block!((vm, sccp_int128_switch_v1) blk_one() {
+        blk_one_div, blk_one_ret
+    });
+
+    // %blk_default():
+    //   RET 0
+    inst!       ((vm, sccp_int128_switch_v1) blk_default_ret:
+        RET (int64_0_local)
+    );
+
+    define_block!((vm, sccp_int128_switch_v1) blk_default() {
+        blk_default_ret
+    });
+
+    define_func_ver!((vm) sccp_int128_switch_v1 (entry: blk_entry) {
+        blk_entry, blk_one, blk_default
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_fp() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_fp());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_fp");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // (1.5 + 2.25) * 2 - 0.5 is 7
+    let vals = ret_values(&func_ver, vm.id_of("blk_entry"));
+    match vals[0].as_value().v {
+        Value_::Constant(Constant::Double(v)) => assert_eq!(v, 7f64),
+        _ => panic!("expect a double constant, found {}", vals[0]),
+    }
+    assert_eq!(all_insts(&func_ver).len(), 1);
+
+    let lib = linkutils::aot::compile_fnc("sccp_fp", &sccp_fp);
+    unsafe {
+        let sccp_fp: libloading::Symbol<unsafe extern "C" fn() -> f64> =
+            lib.get(b"sccp_fp").unwrap();
+
+        let res = sccp_fp();
+        println!("sccp_fp() = {}", res);
+        assert_eq!(res, 7f64);
+    }
+}
+
+fn sccp_fp() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) double = mu_double);
+    constdef!   ((vm) <double> double_0_5  = Constant::Double(0.5f64));
+    constdef!   ((vm) <double> double_1_5  = Constant::Double(1.5f64));
+    constdef!   ((vm) <double> double_2    = Constant::Double(2f64));
+    constdef!   ((vm) <double> double_2_25 = Constant::Double(2.25f64));
+
+    funcsig!    ((vm) sig = () -> (double));
+    funcdecl!   ((vm) <sig> sccp_fp);
+    funcdef!    ((vm) <sig> sccp_fp VERSION sccp_fp_v1);
+
+    // %entry():
+    block!      ((vm, sccp_fp_v1) blk_entry);
+    consta!     ((vm, sccp_fp_v1) double_0_5_local = double_0_5);
+    consta!     ((vm, sccp_fp_v1) double_1_5_local = double_1_5);
+    consta!     ((vm, sccp_fp_v1) double_2_local = double_2);
+    consta!     ((vm, sccp_fp_v1) double_2_25_local = double_2_25);
+
+    // %x = FADD 1.5 2.25
+    ssa!        ((vm, sccp_fp_v1) <double> x);
+    inst!       ((vm, sccp_fp_v1) blk_entry_fadd:
+        x = BINOP (BinOp::FAdd) double_1_5_local double_2_25_local
+    );
+
+    // %y = FMUL %x 2
+    ssa!        ((vm, sccp_fp_v1) <double> y);
+    inst!       ((vm, sccp_fp_v1) blk_entry_fmul:
+        y = BINOP (BinOp::FMul) x double_2_local
+    );
+
+    // %r = FSUB %y 0.5
+    ssa!        ((vm, sccp_fp_v1) <double> r);
+    inst!       ((vm, sccp_fp_v1) blk_entry_fsub:
+        r = BINOP (BinOp::FSub) y double_0_5_local
+    );
+
+    // RET %r
+    inst!       ((vm, sccp_fp_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!((vm, sccp_fp_v1) blk_entry() {
+        blk_entry_fadd, blk_entry_fmul, blk_entry_fsub, blk_entry_ret
+    });
+
+    define_func_ver!((vm) sccp_fp_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_fcmp() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_fcmp());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_fcmp");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // every comparison is folded (NaN is unordered with anything, even itself)
+    let vals = ret_values(&func_ver, vm.id_of("blk_entry"));
+    let results: Vec<u64> = vals
+        .iter()
+        .map(|val| val.as_value().extract_int_const().unwrap())
+        .collect();
+    assert_eq!(results, vec![0, 1, 1, 0, 1, 0, 1]);
+    assert_eq!(all_insts(&func_ver).len(), 1);
+}
+
+fn sccp_fcmp() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1   = mu_int(1));
+    typedef!    ((vm) float  = mu_float);
+    typedef!    ((vm) double = mu_double);
+    constdef!   ((vm) <double> double_0 = Constant::Double(0f64));
+    constdef!   ((vm) <double> double_1 = Constant::Double(1f64));
+    constdef!   ((vm) <float> float_1   = Constant::Float(1f32));
+    constdef!   ((vm) <float> float_2   = Constant::Float(2f32));
+
+    funcsig!    ((vm) sig = () -> (int1, int1, int1, int1, int1, int1, int1));
+    funcdecl!   ((vm) <sig> sccp_fcmp);
+    funcdef!    ((vm) <sig> sccp_fcmp VERSION sccp_fcmp_v1);
+
+    // %entry():
+    block!      ((vm, sccp_fcmp_v1) blk_entry);
+    consta!     ((vm, sccp_fcmp_v1) double_0_local = double_0);
+    consta!     ((vm, sccp_fcmp_v1) double_1_local = double_1);
+    consta!     ((vm, sccp_fcmp_v1) float_1_local = float_1);
+    consta!     ((vm, sccp_fcmp_v1) float_2_local = float_2);
+
+    // %nan = FDIV 0 0
+    ssa!        ((vm, sccp_fcmp_v1) <double> nan);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_fdiv:
+        nan = BINOP (BinOp::FDiv) double_0_local double_0_local
+    );
+
+    // %oeq = FOEQ %nan %nan (false)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> oeq);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_oeq:
+        oeq = CMPOP (CmpOp::FOEQ) nan nan
+    );
+
+    // %une = FUNE %nan %nan (true)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> une);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_une:
+        une = CMPOP (CmpOp::FUNE) nan nan
+    );
+
+    // %uno = FUNO %nan 1 (true)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> uno);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_uno:
+        uno = CMPOP (CmpOp::FUNO) nan double_1_local
+    );
+
+    // %ord = FORD %nan 1 (false)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> ord);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_ord:
+        ord = CMPOP (CmpOp::FORD) nan double_1_local
+    );
+
+    // %ult = FULT %nan 1 (true)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> ult);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_ult:
+        ult = CMPOP (CmpOp::FULT) nan double_1_local
+    );
+
+    // %olt = FOLT %nan 1 (false)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> olt);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_olt:
+        olt = CMPOP (CmpOp::FOLT) nan double_1_local
+    );
+
+    // %one = FONE <float> 1 2 (true)
+    ssa!        ((vm, sccp_fcmp_v1) <int1> one);
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_one:
+        one = CMPOP (CmpOp::FONE) float_1_local float_2_local
+    );
+
+    // RET (%oeq, %une, %uno, %ord, %ult, %olt, %one)
+    inst!       ((vm, sccp_fcmp_v1) blk_entry_ret:
+        RET (oeq, une, uno, ord, ult, olt, one)
+    );
+
+    define_block!((vm, sccp_fcmp_v1) blk_entry() {
+        blk_entry_fdiv, blk_entry_oeq, blk_entry_une, blk_entry_uno, blk_entry_ord,
+        blk_entry_ult, blk_entry_olt, blk_entry_one, blk_entry_ret
+    });
+
+    define_func_ver!((vm) sccp_fcmp_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_select() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_select());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_select");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the condition is always true, so the SELECT is %a
+    let vals = ret_values(&func_ver, vm.id_of("blk_entry"));
+    assert_eq!(vals[0].extract_ssa_id(), Some(vm.id_of("a")));
+    assert_eq!(all_insts(&func_ver).len(), 1);
+
+    let lib = linkutils::aot::compile_fnc("sccp_select", &sccp_select);
+    unsafe {
+        let sccp_select: libloading::Symbol<unsafe extern "C" fn(u64) -> u64> =
+            lib.get(b"sccp_select").unwrap();
+
+        let res = sccp_select(9);
+        println!("sccp_select(9) = {}", res);
+        assert_eq!(res, 9);
+    }
+}
+
+fn sccp_select() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_3 = Constant::Int(3));
+    constdef!   ((vm) <int64> int64_5 = Constant::Int(5));
+    constdef!   ((vm) <int64> int64_7 = Constant::Int(7));
+
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> sccp_select);
+    funcdef!    ((vm) <sig> sccp_select VERSION sccp_select_v1);
+
+    // %entry(%a):
+    block!      ((vm, sccp_select_v1) blk_entry);
+    ssa!        ((vm, sccp_select_v1) <int64> a);
+    consta!     ((vm, sccp_select_v1) int64_3_local = int64_3);
+    consta!     ((vm, sccp_select_v1) int64_5_local = int64_5);
+    consta!     ((vm, sccp_select_v1) int64_7_local = int64_7);
+
+    // %c = SGT 5 3
+    ssa!        ((vm, sccp_select_v1) <int1> c);
+    inst!       ((vm, sccp_select_v1) blk_entry_cmp:
+        c = CMPOP (CmpOp::SGT) int64_5_local int64_3_local
+    );
+
+    // %r = SELECT %c %a 7
+    ssa!        ((vm, sccp_select_v1) <int64> r);
+    inst!       ((vm, sccp_select_v1) blk_entry_select:
+        r = SELECT c a int64_7_local
+    );
+
+    // RET %r
+    inst!       ((vm, sccp_select_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!((vm, sccp_select_v1) blk_entry(a) {
+        blk_entry_cmp, blk_entry_select, blk_entry_ret
+    });
+
+    define_func_ver!((vm) sccp_select_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_sdiv_unfolded() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_sdiv());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_sdiv");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // division by zero and MIN / -1 are left to the machine (%z is folded to 0)
+    let insts = all_insts(&func_ver);
+    assert_eq!(insts.len(), 3);
+    let sdiv = &insts[0];
+    match sdiv.v {
+        Instruction_::BinOp(BinOp::Sdiv, _, op2) => {
+            assert_eq!(sdiv.ops[op2].as_value().extract_int_const(), Some(0));
+        }
+        _ => panic!("expect a SDIV, found {}", sdiv),
+    }
+    match insts[1].v {
+        Instruction_::BinOp(BinOp::Srem, _, _) => {}
+        _ => panic!("expect a SREM, found {}", insts[1]),
+    }
+}
+
+fn sccp_sdiv() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_3    = Constant::Int(3));
+    constdef!   ((vm) <int64> int64_7    = Constant::Int(7));
+    constdef!   ((vm) <int64> int64_min  = Constant::Int(::std::i64::MIN as u64));
+    constdef!   ((vm) <int64> int64_neg1 = Constant::Int(-1i64 as u64));
+
+    funcsig!    ((vm) sig = () -> (int64, int64));
+    funcdecl!   ((vm) <sig> sccp_sdiv);
+    funcdef!    ((vm) <sig> sccp_sdiv VERSION sccp_sdiv_v1);
+
+    // %entry():
+    block!      ((vm, sccp_sdiv_v1) blk_entry);
+    consta!     ((vm, sccp_sdiv_v1) int64_3_local = int64_3);
+    consta!     ((vm, sccp_sdiv_v1) int64_7_local = int64_7);
+    consta!     ((vm, sccp_sdiv_v1) int64_min_local = int64_min);
+    consta!     ((vm, sccp_sdiv_v1) int64_neg1_local = int64_neg1);
+
+    // %z = SUB 3 3
+    ssa!        ((vm, sccp_sdiv_v1) <int64> z);
+    inst!       ((vm, sccp_sdiv_v1) blk_entry_sub:
+        z = BINOP (BinOp::Sub) int64_3_local int64_3_local
+    );
+
+    // %q = SDIV 7 %z
+    ssa!        ((vm, sccp_sdiv_v1) <int64> q);
+    inst!       ((vm, sccp_sdiv_v1) blk_entry_sdiv:
+        q = BINOP (BinOp::Sdiv) int64_7_local z
+    );
+
+    // %m = SREM MIN -1
+    ssa!        ((vm, sccp_sdiv_v1) <int64> m);
+    inst!       ((vm, sccp_sdiv_v1) blk_entry_srem:
+        m = BINOP (BinOp::Srem) int64_min_local int64_neg1_local
+    );
+
+    // RET (%q, %m)
+    inst!       ((vm, sccp_sdiv_v1) blk_entry_ret:
+        RET (q, m)
+    );
+
+    define_block!((vm, sccp_sdiv_v1) blk_entry() {
+        blk_entry_sub, blk_entry_sdiv, blk_entry_srem, blk_entry_ret
+    });
+
+    define_func_ver!((vm) sccp_sdiv_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_sccp_shift_modulo_width() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp_shift());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm,
+    );
+
+    let func_id = vm.id_of("sccp_shift");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the shift amounts are taken modulo the width
+    let vals = ret_values(&func_ver, vm.id_of("blk_entry"));
+    let results: Vec<u64> = vals
+        .iter()
+        .map(|val| val.as_value().extract_int_const().unwrap())
+        .collect();
+    assert_eq!(results, vec![2, 0x40000000, 0xc0]);
+    assert_eq!(all_insts(&func_ver).len(), 1);
+}
+
+fn sccp_shift() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int8  = mu_int(8));
+    typedef!    ((vm) int32 = mu_int(32));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_1     = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_65    = Constant::Int(65));
+    constdef!   ((vm) <int32> int32_min   = Constant::Int(0x80000000));
+    constdef!   ((vm) <int32> int32_33    = Constant::Int(33));
+    constdef!   ((vm) <int8>  int8_min    = Constant::Int(0x80));
+    constdef!   ((vm) <int8>  int8_9      = Constant::Int(9));
+
+    funcsig!    ((vm) sig = () -> (int64, int32, int8));
+    funcdecl!   ((vm) <sig> sccp_shift);
+    funcdef!    ((vm) <sig> sccp_shift VERSION sccp_shift_v1);
+
+    // %entry():
+    block!      ((vm, sccp_shift_v1) blk_entry);
+    consta!     ((vm, sccp_shift_v1) int64_1_local = int64_1);
+    consta!     ((vm, sccp_shift_v1) int64_65_local = int64_65);
+    consta!     ((vm, sccp_shift_v1) int32_min_local = int32_min);
+    consta!     ((vm, sccp_shift_v1) int32_33_local = int32_33);
+    consta!     ((vm, sccp_shift_v1) int8_min_local = int8_min);
+    consta!     ((vm, sccp_shift_v1) int8_9_local = int8_9);
+
+    // %a = SHL <int<64>> 1 65
+    ssa!        ((vm, sccp_shift_v1) <int64> a);
+    inst!       ((vm, sccp_shift_v1) blk_entry_shl:
+        a = BINOP (BinOp::Shl) int64_1_local int64_65_local
+    );
+
+    // %b = LSHR <int<32>> 0x80000000 33
+    ssa!        ((vm, sccp_shift_v1) <int32> b);
+    inst!       ((vm, sccp_shift_v1) blk_entry_lshr:
+        b = BINOP (BinOp::Lshr) int32_min_local int32_33_local
+    );
+
+    // %c = ASHR <int<8>> 0x80 9
+    ssa!        ((vm, sccp_shift_v1) <int8> c);
+    inst!       ((vm, sccp_shift_v1) blk_entry_ashr:
+        c = BINOP (BinOp::Ashr) int8_min_local int8_9_local
+    );
+
+    // RET (%a, %b, %c)
+    inst!       ((vm, sccp_shift_v1) blk_entry_ret:
+        RET (a, b, c)
+    );
+
+    define_block!((vm, sccp_shift_v1) blk_entry() {
+        blk_entry_shl, blk_entry_lshr, blk_entry_ashr, blk_entry_ret
+    });
+
+    define_func_ver!((vm) sccp_shift_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}